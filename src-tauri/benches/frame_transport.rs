@@ -0,0 +1,183 @@
+//! Cost of getting a frame update to the viewer as a JSON event with Base64
+//! pixels versus a binary channel message, through `FrameChannel::send` on a
+//! mock Tauri app.
+//!
+//! Run with `cargo bench --bench frame_transport`. For each update and
+//! transport it reports:
+//!
+//! - send: time on the session thread inside `FrameChannel::send`
+//!   (serialization plus Tauri's dispatch)
+//! - receive: time on a separate viewer thread turning the delivered message
+//!   back into pixels (JSON parse and Base64 decode, or the binary header walk)
+//! - latency: from the start of `send` until the viewer thread has the pixels
+//!
+//! send + receive is the CPU cost of one update. Frames go one at a time, as
+//! with flow control at one frame in flight. The mock runtime has no webview,
+//! so the browser's own decode is not included; each viewer logs that in the
+//! webview console every 100 frames (toggle `binaryFrames` in the settings
+//! store to compare).
+
+use open_term_lib::frame::{DirtyRect, FrameChannel, FrameUpdate};
+use std::hint::black_box;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::ipc::{Channel, InvokeResponseBody};
+use tauri::test::{mock_app, MockRuntime};
+use tauri::{AppHandle, Listener};
+
+const ITERATIONS: u32 = 50;
+const EVENT: &str = "bench-frame";
+
+/// What the session thread hands the viewer thread
+enum Delivered {
+    Event(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Default)]
+struct Timings {
+    send: Duration,
+    receive: Duration,
+    latency: Duration,
+}
+
+fn main() {
+    let cases = [
+        ("full frame 1920x1080", FrameUpdate::full(1920, 1080, pixels(1920, 1080))),
+        (
+            "12 dirty rects 256x128",
+            FrameUpdate::partial(
+                (0..12)
+                    .map(|i| DirtyRect::new(i * 128, 64, 256, 128, pixels(256, 128)))
+                    .collect(),
+            ),
+        ),
+        (
+            "cursor-sized rect 32x32",
+            FrameUpdate::partial(vec![DirtyRect::new(400, 300, 32, 32, pixels(32, 32))]),
+        ),
+    ];
+
+    let app = mock_app();
+    let handle = app.handle().clone();
+
+    println!(
+        "{:<26} {:<7} {:>10} {:>10} {:>10}",
+        "", "", "send", "receive", "latency"
+    );
+    for (name, update) in &cases {
+        for binary in [false, true] {
+            let t = measure(&handle, update, binary);
+            println!(
+                "{:<26} {:<7} {:>8.2}ms {:>8.2}ms {:>8.2}ms",
+                name,
+                if binary { "binary" } else { "event" },
+                millis(t.send),
+                millis(t.receive),
+                millis(t.latency)
+            );
+        }
+    }
+}
+
+/// Average timings for sending `update` over one transport
+fn measure(handle: &AppHandle<MockRuntime>, update: &FrameUpdate, binary: bool) -> Timings {
+    let (deliver, delivered) = mpsc::channel::<Delivered>();
+    let (ack, acked) = mpsc::channel::<(Instant, Duration)>();
+
+    let frames = FrameChannel::default();
+    let listener = if binary {
+        frames.attach(Channel::new(move |body| {
+            if let InvokeResponseBody::Raw(bytes) = body {
+                let _ = deliver.send(Delivered::Binary(bytes));
+            }
+            Ok(())
+        }));
+        None
+    } else {
+        Some(handle.listen_any(EVENT, move |event| {
+            let _ = deliver.send(Delivered::Event(event.payload().to_owned()));
+        }))
+    };
+
+    let viewer = thread::spawn(move || {
+        for message in delivered {
+            let start = Instant::now();
+            let bytes = match message {
+                Delivered::Event(json) => decode_event(&json),
+                Delivered::Binary(bytes) => decode_binary(&bytes),
+            };
+            black_box(bytes);
+            let _ = ack.send((Instant::now(), start.elapsed()));
+        }
+    });
+
+    let mut t = Timings::default();
+    // One warm-up round, then the measured ones
+    for round in 0..=ITERATIONS {
+        let start = Instant::now();
+        frames.send(handle, EVENT, update).unwrap();
+        let sent = start.elapsed();
+        let (done, receive) = acked.recv().unwrap();
+        if round > 0 {
+            t.send += sent;
+            t.receive += receive;
+            t.latency += done - start;
+        }
+    }
+
+    // Dropping the sender side ends the viewer thread
+    drop(frames);
+    if let Some(id) = listener {
+        handle.unlisten(id);
+    }
+    viewer.join().unwrap();
+
+    Timings {
+        send: t.send / ITERATIONS,
+        receive: t.receive / ITERATIONS,
+        latency: t.latency / ITERATIONS,
+    }
+}
+
+/// What the viewer does with an event: parse the JSON and decode the pixels
+fn decode_event(json: &str) -> usize {
+    match serde_json::from_str::<FrameUpdate>(json).unwrap() {
+        FrameUpdate::Full { data, .. } => data.len(),
+        FrameUpdate::Partial { rects, .. } => rects.iter().map(|r| r.data.len()).sum(),
+    }
+}
+
+/// What the viewer does with a binary message: walk the rects and copy out
+/// their pixels (see `FrameUpdate::encode_binary` for the layout)
+fn decode_binary(bytes: &[u8]) -> usize {
+    let count = u16::from_le_bytes([bytes[2], bytes[3]]) as usize;
+    let mut offset = 12;
+    let mut total = 0;
+    for _ in 0..count {
+        let field = |i: usize| u16::from_le_bytes([bytes[offset + i * 2], bytes[offset + i * 2 + 1]]) as usize;
+        let len = field(2) * field(3) * 4;
+        offset += 8;
+        total += black_box(bytes[offset..offset + len].to_vec()).len();
+        offset += len;
+    }
+    total
+}
+
+/// Noisy RGBA pixels, so nothing is cheaper than it would be for a real desktop
+fn pixels(width: usize, height: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..width * height * 4)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
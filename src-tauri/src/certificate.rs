@@ -0,0 +1,117 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use x509_cert::der::Decode;
+
+/// How the server's TLS certificate is checked
+#[derive(Debug, Clone, Default)]
+pub struct CertificatePolicy {
+    /// Fingerprint the user trusted for this host. When set, exactly that
+    /// certificate is accepted instead of validating against the system roots.
+    pub pinned: Option<String>,
+    /// Accept any certificate. Only set when a profile explicitly asks for it.
+    pub accept_any: bool,
+}
+
+/// Details of the certificate a server presented
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerCertificate {
+    pub host: String,
+    pub port: u16,
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: Option<DateTime<Utc>>,
+    pub not_after: Option<DateTime<Utc>>,
+    /// SHA-256 of the DER certificate, as colon-separated hex
+    pub fingerprint: String,
+}
+
+impl ServerCertificate {
+    pub fn from_der(host: &str, port: u16, der: &[u8]) -> Result<Self, String> {
+        let cert = x509_cert::Certificate::from_der(der)
+            .map_err(|e| format!("Failed to parse certificate: {}", e))?;
+        let tbs = &cert.tbs_certificate;
+        let to_datetime = |time: x509_cert::time::Time| {
+            DateTime::from_timestamp(time.to_unix_duration().as_secs() as i64, 0)
+        };
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            subject: tbs.subject.to_string(),
+            issuer: tbs.issuer.to_string(),
+            not_before: to_datetime(tbs.validity.not_before),
+            not_after: to_datetime(tbs.validity.not_after),
+            fingerprint: fingerprint(der),
+        })
+    }
+
+    /// Whether this is the certificate with `fingerprint`, however it's formatted
+    pub fn matches(&self, fingerprint: &str) -> bool {
+        normalize_fingerprint(&self.fingerprint) == normalize_fingerprint(fingerprint)
+    }
+}
+
+/// The server's certificate couldn't be verified and isn't pinned. The
+/// frontend shows the details and calls `rdp_trust_certificate` or
+/// `vnc_trust_certificate` to accept it.
+#[derive(Debug, Clone, Serialize)]
+pub struct UntrustedCertificate {
+    pub certificate: ServerCertificate,
+    /// Why it was rejected
+    pub reason: String,
+}
+
+impl fmt::Display for UntrustedCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Untrusted certificate for {}:{} ({}): {}",
+            self.certificate.host, self.certificate.port, self.certificate.fingerprint, self.reason
+        )
+    }
+}
+
+impl std::error::Error for UntrustedCertificate {}
+
+/// Error from connecting an RDP or VNC session. Serialized as a plain string,
+/// except for an untrusted certificate which carries its details.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum ConnectError {
+    UntrustedCertificate(Box<UntrustedCertificate>),
+    Other(String),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::UntrustedCertificate(e) => e.fmt(f),
+            ConnectError::Other(e) => f.write_str(e),
+        }
+    }
+}
+
+impl From<String> for ConnectError {
+    fn from(e: String) -> Self {
+        ConnectError::Other(e)
+    }
+}
+
+/// SHA-256 fingerprint of a DER certificate, e.g. "AB:CD:..."
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(char::is_ascii_hexdigit)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
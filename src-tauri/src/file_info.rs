@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Coarse kind of a file, from its name, for icons and sorting by type
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FileCategory {
+    Directory,
+    Archive,
+    Image,
+    Text,
+    Code,
+    #[default]
+    Binary,
+}
+
+const ARCHIVE_EXTENSIONS: &[&str] = &[
+    "zip", "tar", "gz", "tgz", "bz2", "tbz2", "xz", "txz", "zst", "lz", "lzma", "7z", "rar", "cab", "deb", "rpm",
+    "jar", "war", "apk", "dmg", "iso",
+];
+
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "bmp", "svg", "webp", "ico", "tif", "tiff", "heic", "avif", "psd",
+];
+
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "markdown", "rst", "log", "csv", "tsv", "json", "yaml", "yml", "toml", "ini", "cfg", "conf", "xml",
+    "env", "properties", "lock",
+];
+
+const CODE_EXTENSIONS: &[&str] = &[
+    "rs", "c", "h", "cc", "cpp", "hpp", "cs", "go", "java", "kt", "kts", "scala", "swift", "m", "py", "rb", "php", "pl",
+    "lua", "r", "js", "mjs", "cjs", "jsx", "ts", "tsx", "vue", "svelte", "html", "htm", "css", "scss", "sass", "less",
+    "sh", "bash", "zsh", "fish", "ps1", "bat", "cmd", "sql", "dart", "ex", "exs", "erl", "hs", "ml", "clj", "zig",
+    "nim", "tf", "gradle", "cmake", "mk",
+];
+
+/// Extensionless names that are still source or config
+const CODE_NAMES: &[&str] = &["makefile", "dockerfile", "containerfile", "jenkinsfile", "vagrantfile", "rakefile"];
+const TEXT_NAMES: &[&str] = &["readme", "license", "licence", "changelog", "authors", "notice", "copying"];
+
+impl FileCategory {
+    /// Category of an entry called `name`. Unknown extensions, and files
+    /// without one, are binary.
+    pub fn of(name: &str, is_dir: bool) -> Self {
+        if is_dir {
+            return FileCategory::Directory;
+        }
+        let lower = name.to_lowercase();
+        // Dotfiles (.bashrc) have no extension
+        let extension = Path::new(&lower).extension().and_then(|ext| ext.to_str());
+
+        match extension {
+            Some(ext) if ARCHIVE_EXTENSIONS.contains(&ext) => FileCategory::Archive,
+            Some(ext) if IMAGE_EXTENSIONS.contains(&ext) => FileCategory::Image,
+            Some(ext) if TEXT_EXTENSIONS.contains(&ext) => FileCategory::Text,
+            Some(ext) if CODE_EXTENSIONS.contains(&ext) => FileCategory::Code,
+            Some(_) => FileCategory::Binary,
+            None if CODE_NAMES.contains(&lower.as_str()) => FileCategory::Code,
+            None if TEXT_NAMES.contains(&lower.as_str()) => FileCategory::Text,
+            // .bashrc, .gitignore and the like are config
+            None if lower.starts_with('.') => FileCategory::Text,
+            None => FileCategory::Binary,
+        }
+    }
+}
+
+/// Displayed size of an entry: `bytes` in 1024-based units, e.g. "512 B",
+/// "4.2 MB". Directory sizes mean nothing to the user, so those are empty.
+pub fn human_size(bytes: u64, is_dir: bool) -> String {
+    if is_dir {
+        return String::new();
+    }
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
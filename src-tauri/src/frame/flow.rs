@@ -0,0 +1,122 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Frames a viewer may have queued before updates are held back
+pub const DEFAULT_MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+/// Acks missing this long are taken as lost (e.g. the viewer reloaded), so
+/// a session never stalls waiting for them
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Span the effective frame rate is measured over
+const FPS_WINDOW: Duration = Duration::from_secs(2);
+
+/// Backpressure between a frame reader and its viewer. The viewer acks each
+/// frame once painted; while too many are unacknowledged the reader keeps
+/// merging changes and sends one update with the latest pixels when the
+/// viewer catches up, instead of queueing stale ones. Cloned handles share
+/// state, so it survives the reader being restarted.
+#[derive(Clone)]
+pub struct FrameFlow(Arc<Mutex<FlowState>>);
+
+struct FlowState {
+    /// 0 disables backpressure
+    max_in_flight: usize,
+    in_flight: usize,
+    /// Last ack, or when the oldest unacknowledged frame was sent
+    last_ack: Instant,
+    sent: u64,
+    skipped: u64,
+    /// Send times within `FPS_WINDOW`
+    recent: VecDeque<Instant>,
+}
+
+/// Reply of the frame stats commands
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameFlowStats {
+    pub frames_sent: u64,
+    /// Updates folded into a later frame because the viewer was behind
+    pub frames_skipped: u64,
+    pub frames_in_flight: usize,
+    pub max_in_flight: usize,
+    /// Frames sent per second, over the last couple of seconds
+    pub fps: f64,
+}
+
+impl FrameFlow {
+    pub fn new(max_in_flight: usize) -> Self {
+        Self(Arc::new(Mutex::new(FlowState {
+            max_in_flight,
+            in_flight: 0,
+            last_ack: Instant::now(),
+            sent: 0,
+            skipped: 0,
+            recent: VecDeque::new(),
+        })))
+    }
+
+    pub fn set_max_in_flight(&self, max_in_flight: usize) {
+        self.0.lock().max_in_flight = max_in_flight;
+    }
+
+    /// Whether the viewer can take another frame
+    pub fn ready(&self) -> bool {
+        let mut state = self.0.lock();
+        if state.max_in_flight == 0 || state.in_flight < state.max_in_flight {
+            return true;
+        }
+        if state.last_ack.elapsed() >= ACK_TIMEOUT {
+            state.in_flight = 0;
+            return true;
+        }
+        false
+    }
+
+    pub fn sent(&self) {
+        let mut state = self.0.lock();
+        let now = Instant::now();
+        if state.in_flight == 0 {
+            state.last_ack = now;
+        }
+        state.in_flight += 1;
+        state.sent += 1;
+        state.recent.push_back(now);
+        while state.recent.front().is_some_and(|sent| now - *sent > FPS_WINDOW) {
+            state.recent.pop_front();
+        }
+    }
+
+    /// An update arrived while the viewer was behind
+    pub fn skipped(&self) {
+        self.0.lock().skipped += 1;
+    }
+
+    /// The viewer painted a frame
+    pub fn ack(&self) {
+        let mut state = self.0.lock();
+        state.in_flight = state.in_flight.saturating_sub(1);
+        state.last_ack = Instant::now();
+    }
+
+    pub fn stats(&self) -> FrameFlowStats {
+        let state = self.0.lock();
+        let now = Instant::now();
+        let frames = state.recent.iter().filter(|sent| now - **sent <= FPS_WINDOW).count();
+        FrameFlowStats {
+            frames_sent: state.sent,
+            frames_skipped: state.skipped,
+            frames_in_flight: state.in_flight,
+            max_in_flight: state.max_in_flight,
+            fps: frames as f64 / FPS_WINDOW.as_secs_f64(),
+        }
+    }
+}
+
+impl Default for FrameFlow {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAMES_IN_FLIGHT)
+    }
+}
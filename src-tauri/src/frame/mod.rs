@@ -0,0 +1,294 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod flow;
+mod screenshot;
+mod transport;
+
+pub use flow::{FrameFlow, FrameFlowStats, DEFAULT_MAX_FRAMES_IN_FLIGHT};
+pub use screenshot::{save_screenshot, ScreenshotFormat, ScreenshotInfo};
+pub use transport::FrameChannel;
+
+/// A dirty rectangle update - only the changed region
+/// Pixels are Base64-encoded when sent as a JSON event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirtyRect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    #[serde(with = "base64_pixels")]
+    pub data: Vec<u8>, // RGBA pixels
+}
+
+impl DirtyRect {
+    pub fn new(x: u16, y: u16, width: u16, height: u16, rgba_data: Vec<u8>) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            data: rgba_data,
+        }
+    }
+
+    /// Build a dirty rect by copying a region out of a full RGBA framebuffer
+    pub fn from_framebuffer(region: &Region, frame: &[u8], frame_width: u16) -> Self {
+        let data = extract_region(region, frame, frame_width);
+        Self::new(region.x, region.y, region.width, region.height, data)
+    }
+}
+
+/// Frame update type - either full frame or dirty rectangles
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum FrameUpdate {
+    /// Full frame update (used for initial frame)
+    Full {
+        width: u16,
+        height: u16,
+        #[serde(with = "base64_pixels")]
+        data: Vec<u8>,
+        sent_at: f64,
+    },
+    /// Partial update with dirty rectangles
+    Partial { rects: Vec<DirtyRect>, sent_at: f64 },
+}
+
+/// `kind` byte of a binary frame message
+const BINARY_FULL: u8 = 0;
+const BINARY_PARTIAL: u8 = 1;
+
+impl FrameUpdate {
+    pub fn full(width: u16, height: u16, rgba_data: Vec<u8>) -> Self {
+        Self::Full {
+            width,
+            height,
+            data: rgba_data,
+            sent_at: now_ms(),
+        }
+    }
+
+    pub fn partial(rects: Vec<DirtyRect>) -> Self {
+        Self::Partial {
+            rects,
+            sent_at: now_ms(),
+        }
+    }
+
+    /// Encode for a binary frame channel. All numbers are little-endian:
+    ///
+    /// ```text
+    /// u8  kind          0 = full frame, 1 = dirty rectangles
+    /// u8  reserved
+    /// u16 rect count    1 for a full frame
+    /// f64 sent_at       milliseconds since the Unix epoch
+    /// per rect:
+    ///   u16 x, y, width, height
+    ///   width * height * 4 bytes of RGBA pixels
+    /// ```
+    ///
+    /// A full frame is a single rectangle at 0,0 covering the desktop.
+    pub fn encode_binary(&self) -> Vec<u8> {
+        let (kind, sent_at, count, pixels) = match self {
+            Self::Full { data, sent_at, .. } => (BINARY_FULL, *sent_at, 1, data.len()),
+            Self::Partial { rects, sent_at } => (
+                BINARY_PARTIAL,
+                *sent_at,
+                rects.len(),
+                rects.iter().map(|r| r.data.len()).sum(),
+            ),
+        };
+
+        let mut out = Vec::with_capacity(12 + count * 8 + pixels);
+        out.push(kind);
+        out.push(0);
+        out.extend_from_slice(&(count as u16).to_le_bytes());
+        out.extend_from_slice(&sent_at.to_le_bytes());
+        match self {
+            Self::Full {
+                width,
+                height,
+                data,
+                ..
+            } => push_rect(&mut out, 0, 0, *width, *height, data),
+            Self::Partial { rects, .. } => {
+                for r in rects {
+                    push_rect(&mut out, r.x, r.y, r.width, r.height, &r.data);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn push_rect(out: &mut Vec<u8>, x: u16, y: u16, width: u16, height: u16, data: &[u8]) {
+    for n in [x, y, width, height] {
+        out.extend_from_slice(&n.to_le_bytes());
+    }
+    out.extend_from_slice(data);
+}
+
+/// Send time stamped on frame updates, so the viewer can measure latency
+fn now_ms() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64() * 1000.0)
+        .unwrap_or_default()
+}
+
+/// Pixels travel as Base64 strings in JSON events
+mod base64_pixels {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        BASE64.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A changed area of the framebuffer, tracked before its pixels are extracted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Region {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> Self {
+        Self { x, y, width, height }
+    }
+
+    fn right(&self) -> u32 {
+        self.x as u32 + self.width as u32
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y as u32 + self.height as u32
+    }
+
+    /// Whether two regions overlap or share an edge
+    pub fn touches(&self, other: &Region) -> bool {
+        !(self.right() < other.x as u32
+            || other.right() < self.x as u32
+            || self.bottom() < other.y as u32
+            || other.bottom() < self.y as u32)
+    }
+
+    /// Smallest region covering both
+    pub fn union(&self, other: &Region) -> Region {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+        Region {
+            x,
+            y,
+            width: (right - x as u32) as u16,
+            height: (bottom - y as u32) as u16,
+        }
+    }
+
+    /// Clamp the region to a framebuffer of the given size
+    pub fn clamp(&self, width: u16, height: u16) -> Option<Region> {
+        if self.x >= width || self.y >= height {
+            return None;
+        }
+        let w = self.width.min(width - self.x);
+        let h = self.height.min(height - self.y);
+        if w == 0 || h == 0 {
+            return None;
+        }
+        Some(Region::new(self.x, self.y, w, h))
+    }
+}
+
+/// Merge overlapping or adjacent regions until no two of them touch
+pub fn coalesce_regions(mut regions: Vec<Region>) -> Vec<Region> {
+    let mut merged = true;
+    while merged && regions.len() > 1 {
+        merged = false;
+        let mut result: Vec<Region> = Vec::with_capacity(regions.len());
+        for region in regions {
+            if let Some(existing) = result.iter_mut().find(|r| r.touches(&region)) {
+                *existing = existing.union(&region);
+                merged = true;
+            } else {
+                result.push(region);
+            }
+        }
+        regions = result;
+    }
+    regions
+}
+
+/// Copy a region's RGBA pixels out of a full framebuffer
+pub fn extract_region(region: &Region, frame: &[u8], frame_width: u16) -> Vec<u8> {
+    let full_width = frame_width as usize;
+    let x = region.x as usize;
+    let w = region.width as usize;
+
+    let mut data = Vec::with_capacity(w * region.height as usize * 4);
+    for row in region.y as usize..region.bottom() as usize {
+        let start = (row * full_width + x) * 4;
+        let end = start + w * 4;
+        if end <= frame.len() {
+            data.extend_from_slice(&frame[start..end]);
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The dirty rects a session emits for `regions`, as the RDP client
+    /// builds them
+    fn dirty_rects(regions: Vec<Region>, frame: &[u8], width: u16, height: u16) -> Vec<DirtyRect> {
+        coalesce_regions(regions)
+            .iter()
+            .filter_map(|r| r.clamp(width, height))
+            .map(|r| DirtyRect::from_framebuffer(&r, frame, width))
+            .collect()
+    }
+
+    #[test]
+    fn dirty_rect_data_matches_its_size() {
+        let (width, height) = (97u16, 61u16);
+        // Each pixel's bytes encode its position, to check the right ones are copied
+        let frame: Vec<u8> = (0..height)
+            .flat_map(|y| (0..width).flat_map(move |x| [x as u8, y as u8, (x >> 8) as u8, 255]))
+            .collect();
+
+        // Overlapping, adjacent, disjoint and partly off-screen regions
+        let mut seed = 7u32;
+        let mut next = |limit: u16| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            ((seed >> 16) % limit as u32) as u16
+        };
+        for _ in 0..200 {
+            let count = 1 + next(8) as usize;
+            let regions = (0..count)
+                .map(|_| Region::new(next(width + 10), next(height + 10), 1 + next(40), 1 + next(40)))
+                .collect();
+            for rect in dirty_rects(regions, &frame, width, height) {
+                assert_eq!(rect.data.len(), rect.width as usize * rect.height as usize * 4, "{:?}", rect);
+                assert!(rect.x + rect.width <= width && rect.y + rect.height <= height);
+                let last = rect.data.len() - 4;
+                assert_eq!(&rect.data[..2], &[rect.x as u8, rect.y as u8]);
+                assert_eq!(
+                    &rect.data[last..last + 2],
+                    &[(rect.x + rect.width - 1) as u8, (rect.y + rect.height - 1) as u8]
+                );
+            }
+        }
+    }
+}
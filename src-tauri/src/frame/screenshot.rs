@@ -0,0 +1,48 @@
+use image::{ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreenshotFormat {
+    #[default]
+    Png,
+    Jpeg,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScreenshotInfo {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub file_size: u64,
+}
+
+/// Encode an RGBA frame copy to `path`. Runs outside any framebuffer lock.
+pub fn save_screenshot(
+    rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+    path: &str,
+    format: ScreenshotFormat,
+) -> Result<ScreenshotInfo, String> {
+    let image = RgbaImage::from_raw(width, height, rgba)
+        .ok_or_else(|| "Framebuffer size does not match its dimensions".to_string())?;
+
+    match format {
+        ScreenshotFormat::Png => image.save_with_format(path, ImageFormat::Png),
+        // JPEG has no alpha channel
+        ScreenshotFormat::Jpeg => image::DynamicImage::ImageRgba8(image)
+            .to_rgb8()
+            .save_with_format(path, ImageFormat::Jpeg),
+    }
+    .map_err(|e| format!("Failed to save screenshot: {}", e))?;
+
+    let file_size = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+
+    Ok(ScreenshotInfo {
+        path: path.to_string(),
+        width,
+        height,
+        file_size,
+    })
+}
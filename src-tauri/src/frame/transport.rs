@@ -0,0 +1,48 @@
+use super::FrameUpdate;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tauri::ipc::{Channel, Response};
+use tauri::{AppHandle, Emitter, Runtime};
+
+/// Where a session's frame updates go. Updates are sent as JSON events with
+/// Base64 pixels until the viewer attaches a channel, then as raw binary
+/// messages (see `FrameUpdate::encode_binary`), which skips the Base64 and
+/// JSON work on both ends. Viewers with the binary frames setting off
+/// detach it to get events again. Cloned handles share the attached channel, so it
+/// survives the frame reader being restarted.
+#[derive(Clone, Default)]
+pub struct FrameChannel(Arc<Mutex<Option<Channel<Response>>>>);
+
+impl FrameChannel {
+    /// Send later updates over `channel`, replacing any earlier one
+    pub fn attach(&self, channel: Channel<Response>) {
+        *self.0.lock() = Some(channel);
+    }
+
+    /// Go back to sending updates as events
+    pub fn detach(&self) {
+        *self.0.lock() = None;
+    }
+
+    /// Send over the attached channel, or as `event` when there is none. A
+    /// channel that fails (its viewer went away) is dropped and the update
+    /// goes out as an event instead.
+    pub fn send<R: Runtime>(
+        &self,
+        app_handle: &AppHandle<R>,
+        event: &str,
+        update: &FrameUpdate,
+    ) -> tauri::Result<()> {
+        let channel = self.0.lock().clone();
+        if let Some(channel) = channel {
+            match channel.send(Response::new(update.encode_binary())) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    eprintln!("Frame channel closed, falling back to events: {}", e);
+                    *self.0.lock() = None;
+                }
+            }
+        }
+        app_handle.emit(event, update)
+    }
+}
@@ -2,9 +2,14 @@ use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use suppaftp::FtpStream;
 use thiserror::Error;
 
+use crate::file_info::{human_size, FileCategory};
+use crate::storage::UsageTracker;
+use super::FtpKeepalive;
+
 #[derive(Error, Debug)]
 pub enum FtpBrowserError {
     #[error("FTP error: {0}")]
@@ -39,11 +44,20 @@ pub struct FileEntry {
     pub size: u64,
     pub modified: Option<i64>,
     pub permissions: Option<u32>,
+    /// Size for display; empty for directories
+    #[serde(default)]
+    pub size_human: String,
+    #[serde(default)]
+    pub category: FileCategory,
 }
 
 pub struct FtpBrowser {
     stream: Arc<Mutex<FtpStream>>,
     current_path: Mutex<PathBuf>,
+    /// Stops with the browser
+    _keepalive: Option<FtpKeepalive>,
+    /// Opened from a saved profile; timed until disconnected
+    usage: Mutex<Option<UsageTracker>>,
 }
 
 // Safety: FtpStream is wrapped in Mutex for thread-safe access
@@ -52,9 +66,31 @@ unsafe impl Send for FtpBrowser {}
 
 impl FtpBrowser {
     pub fn new(stream: Arc<Mutex<FtpStream>>) -> Self {
+        // Start where the server put us after login
+        let current_path = stream.lock().pwd().unwrap_or_else(|_| "/".to_string());
         Self {
             stream,
-            current_path: Mutex::new(PathBuf::from("/")),
+            current_path: Mutex::new(PathBuf::from(current_path)),
+            _keepalive: None,
+            usage: Mutex::new(None),
+        }
+    }
+
+    /// Keep the control connection alive with NOOPs every `interval`
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self._keepalive = Some(FtpKeepalive::start(self.stream.clone(), interval));
+        self
+    }
+
+    /// Count the session's time toward its profile's usage until
+    /// `finish_usage`
+    pub fn track_usage(&self, tracker: UsageTracker) {
+        *self.usage.lock() = Some(tracker);
+    }
+
+    pub fn finish_usage(&self) {
+        if let Some(usage) = self.usage.lock().take() {
+            usage.finish();
         }
     }
 
@@ -70,6 +106,29 @@ impl FtpBrowser {
         *self.current_path.lock() = PathBuf::from(path);
     }
 
+    /// `path` made absolute against the current directory
+    fn resolve(&self, path: &str) -> String {
+        if path.starts_with('/') {
+            return path.to_string();
+        }
+        let current = self.current_path();
+        match path {
+            "" | "." => current,
+            _ => format!("{}/{}", current.trim_end_matches('/'), path),
+        }
+    }
+
+    /// Change the current directory, which relative paths are resolved
+    /// against. Returns the new directory as reported by the server.
+    pub fn chdir(&self, path: &str) -> Result<String, FtpBrowserError> {
+        let target = self.resolve(path);
+        let mut stream = self.stream.lock();
+        stream.cwd(&target).map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
+        let new_path = stream.pwd().map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
+        self.set_path(&new_path);
+        Ok(new_path)
+    }
+
     pub fn pwd(&self) -> Result<String, FtpBrowserError> {
         let mut stream = self.stream.lock();
         let path = stream.pwd().map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
@@ -83,10 +142,12 @@ impl FtpBrowser {
     }
 
     pub fn list_dir(&self, path: &str) -> Result<Vec<FileEntry>, FtpBrowserError> {
+        let target = self.resolve(path);
+        let current = self.current_path();
         let mut stream = self.stream.lock();
 
         // Change to the target directory
-        stream.cwd(path).map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
+        stream.cwd(&target).map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
 
         // Get current path after cwd
         let current_path_str = stream.pwd().map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
@@ -94,6 +155,11 @@ impl FtpBrowser {
         // Get detailed list
         let list = stream.list(None).map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
 
+        // Listing doesn't change the current directory
+        if current_path_str != current {
+            let _ = stream.cwd(&current);
+        }
+
         let mut files: Vec<FileEntry> = list
             .into_iter()
             .filter_map(|line| self.parse_list_line(&line, &current_path_str))
@@ -176,7 +242,10 @@ impl FtpBrowser {
             format!("{}/{}", parent_path, name)
         };
 
+        let is_dir = file_type == FileType::Directory;
         Some(FileEntry {
+            size_human: human_size(size, is_dir),
+            category: FileCategory::of(&name, is_dir),
             name,
             path,
             file_type,
@@ -225,7 +294,10 @@ impl FtpBrowser {
             format!("{}/{}", parent_path, name)
         };
 
+        let is_dir = file_type == FileType::Directory;
         Some(FileEntry {
+            size_human: human_size(size, is_dir),
+            category: FileCategory::of(&name, is_dir),
             name,
             path,
             file_type,
@@ -280,30 +352,48 @@ impl FtpBrowser {
     }
 
     pub fn mkdir(&self, path: &str) -> Result<(), FtpBrowserError> {
+        let path = &self.resolve(path);
         let mut stream = self.stream.lock();
         stream.mkdir(path).map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
         Ok(())
     }
 
+    /// Create an empty file. Fails if it exists unless `overwrite` is set.
+    pub fn touch(&self, path: &str, overwrite: bool) -> Result<(), FtpBrowserError> {
+        let path = &self.resolve(path);
+        let mut stream = self.stream.lock();
+        if !overwrite && stream.size(path).is_ok() {
+            return Err(FtpBrowserError::Ftp(format!("File already exists: {}", path)));
+        }
+        stream
+            .put_file(path, &mut std::io::empty())
+            .map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
+        Ok(())
+    }
+
     pub fn rmdir(&self, path: &str) -> Result<(), FtpBrowserError> {
+        let path = &self.resolve(path);
         let mut stream = self.stream.lock();
         stream.rmdir(path).map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
         Ok(())
     }
 
     pub fn delete(&self, path: &str) -> Result<(), FtpBrowserError> {
+        let path = &self.resolve(path);
         let mut stream = self.stream.lock();
         stream.rm(path).map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
         Ok(())
     }
 
     pub fn rename(&self, from: &str, to: &str) -> Result<(), FtpBrowserError> {
+        let (from, to) = (&self.resolve(from), &self.resolve(to));
         let mut stream = self.stream.lock();
         stream.rename(from, to).map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
         Ok(())
     }
 
     pub fn size(&self, path: &str) -> Result<u64, FtpBrowserError> {
+        let path = &self.resolve(path);
         let mut stream = self.stream.lock();
         let size = stream.size(path).map_err(|e| FtpBrowserError::Ftp(e.to_string()))?;
         Ok(size as u64)
@@ -1,7 +1,8 @@
+use crate::proxy::{self, ProxyConfig};
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use suppaftp::FtpStream;
+use suppaftp::{FtpStream, Mode};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -39,10 +40,29 @@ unsafe impl Sync for FtpClient {}
 unsafe impl Send for FtpClient {}
 
 impl FtpClient {
-    pub fn connect(host: &str, port: u16, auth: &FtpAuthMethod) -> Result<Self, FtpError> {
-        let addr = format!("{}:{}", host, port);
-        let mut stream = FtpStream::connect(&addr)
+    /// Connect and log in. Through a `proxy`, passive data connections are
+    /// tunnelled as well.
+    pub fn connect(
+        host: &str,
+        port: u16,
+        auth: &FtpAuthMethod,
+        proxy: Option<&ProxyConfig>,
+    ) -> Result<Self, FtpError> {
+        let tcp = proxy::connect(host, port, proxy)
             .map_err(|e| FtpError::Connection(e.to_string()))?;
+        // PASV can only describe IPv4 data addresses
+        let ipv6 = proxy.is_none() && tcp.peer_addr().is_ok_and(|addr| addr.is_ipv6());
+        let mut stream = FtpStream::connect_with_stream(tcp)
+            .map_err(|e| FtpError::Connection(e.to_string()))?;
+        if ipv6 {
+            stream.set_mode(Mode::ExtendedPassive);
+        }
+        if let Some(proxy) = proxy.cloned() {
+            stream = stream.passive_stream_builder(move |addr| {
+                proxy::connect(&addr.ip().to_string(), addr.port(), Some(&proxy))
+                    .map_err(suppaftp::FtpError::ConnectionError)
+            });
+        }
 
         // Authenticate
         match auth {
@@ -0,0 +1,49 @@
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use suppaftp::FtpStream;
+
+/// Default interval between NOOPs on an idle control connection
+pub const DEFAULT_KEEPALIVE_SECS: u64 = 60;
+
+/// Sends NOOP on the control connection while it is idle so servers don't
+/// drop it. Stops when dropped.
+pub struct FtpKeepalive {
+    stop: Arc<AtomicBool>,
+}
+
+impl FtpKeepalive {
+    pub fn start(stream: Arc<Mutex<FtpStream>>, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = stop.clone();
+
+        thread::spawn(move || {
+            let mut last_noop = Instant::now();
+            while !stop_flag.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(500));
+                if last_noop.elapsed() < interval {
+                    continue;
+                }
+
+                // Commands and transfers hold the lock for their whole duration,
+                // so a busy stream means there's no idle time to fill
+                if let Some(mut stream) = stream.try_lock() {
+                    if let Err(e) = stream.noop() {
+                        eprintln!("FTP keepalive failed: {}", e);
+                    }
+                }
+                last_noop = Instant::now();
+            }
+        });
+
+        Self { stop }
+    }
+}
+
+impl Drop for FtpKeepalive {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
@@ -1,7 +1,9 @@
 pub mod browser;
 pub mod client;
+pub mod keepalive;
 pub mod transfer;
 
 pub use browser::{FileEntry, FileType, FtpBrowser};
 pub use client::{FtpAuthMethod, FtpClient, FtpError};
-pub use transfer::{FtpTransfer, TransferProgress, TransferStatus};
+pub use keepalive::FtpKeepalive;
+pub use transfer::{FtpTransfer, FtpTransferMode, TransferProgress, TransferStatus};
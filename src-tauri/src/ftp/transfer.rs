@@ -4,6 +4,7 @@ use std::fs::File;
 use std::io::{Read, Write, Cursor};
 use std::path::Path;
 use std::sync::Arc;
+use suppaftp::types::{FileType, FormatControl};
 use suppaftp::FtpStream;
 use thiserror::Error;
 use uuid::Uuid;
@@ -17,6 +18,8 @@ pub enum FtpTransferError {
     Io(#[from] std::io::Error),
     #[error("Transfer cancelled")]
     Cancelled,
+    #[error("Verification failed: {0}")]
+    Verify(String),
 }
 
 impl From<suppaftp::FtpError> for FtpTransferError {
@@ -67,9 +70,23 @@ impl TransferProgress {
     }
 }
 
+/// FTP representation type for a single-file transfer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FtpTransferMode {
+    /// Bytes as they are
+    #[default]
+    Binary,
+    /// Text, with line endings translated between the local and the
+    /// server's convention
+    Ascii,
+}
+
 pub struct FtpTransfer {
     stream: Arc<Mutex<FtpStream>>,
     cancelled: Arc<Mutex<bool>>,
+    /// Create missing remote directories above an upload's destination
+    create_parents: bool,
+    mode: FtpTransferMode,
 }
 
 // Safety: FtpStream is wrapped in Mutex for thread-safe access
@@ -81,6 +98,55 @@ impl FtpTransfer {
         Self {
             stream,
             cancelled: Arc::new(Mutex::new(false)),
+            create_parents: false,
+            mode: FtpTransferMode::Binary,
+        }
+    }
+
+    /// Transfer single files in `mode`. Folder uploads are always binary,
+    /// since they mix text and other files.
+    pub fn with_mode(mut self, mode: FtpTransferMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Run `f` with the session in this transfer's mode, going back to
+    /// binary (the session's normal type) afterwards
+    fn in_mode<T>(
+        &self,
+        stream: &mut FtpStream,
+        f: impl FnOnce(&mut FtpStream) -> Result<T, FtpTransferError>,
+    ) -> Result<T, FtpTransferError> {
+        if self.mode == FtpTransferMode::Binary {
+            return f(stream);
+        }
+        stream.transfer_type(FileType::Ascii(FormatControl::Default))?;
+        let result = f(stream);
+        let restored = stream.transfer_type(FileType::Binary);
+        let value = result?;
+        restored?;
+        Ok(value)
+    }
+
+    /// Create any missing directories above the remote destination before
+    /// uploading, instead of failing with "No such file"
+    pub fn with_create_parents(mut self, create: bool) -> Self {
+        self.create_parents = create;
+        self
+    }
+
+    /// Create `dir` and each of its ancestors. FTP servers answer 550 both
+    /// for existing directories and real failures, so errors are ignored
+    /// and a truly missing directory shows up when the upload fails.
+    fn create_remote_dirs(&self, dir: &str) {
+        let mut stream = self.stream.lock();
+        let mut current = String::new();
+        for component in dir.split('/').filter(|c| !c.is_empty()) {
+            if !current.is_empty() || dir.starts_with('/') {
+                current.push('/');
+            }
+            current.push_str(component);
+            let _ = stream.mkdir(&current);
         }
     }
 
@@ -88,6 +154,25 @@ impl FtpTransfer {
         *self.cancelled.lock() = true;
     }
 
+    /// Flag shared with this transfer; setting it cancels the transfer
+    pub fn cancel_flag(&self) -> Arc<Mutex<bool>> {
+        self.cancelled.clone()
+    }
+
+    /// Check that `remote_path` has the local file's size after an upload.
+    /// Only meaningful for binary transfers; FTP has no portable checksum.
+    pub fn verify_upload(&self, local_path: &str, remote_path: &str) -> Result<(), FtpTransferError> {
+        let local_size = std::fs::metadata(local_path)?.len();
+        let remote_size = self.stream.lock().size(remote_path)? as u64;
+        if remote_size != local_size {
+            return Err(FtpTransferError::Verify(format!(
+                "remote size {} differs from local size {}",
+                remote_size, local_size
+            )));
+        }
+        Ok(())
+    }
+
     pub fn download<F>(
         &self,
         remote_path: &str,
@@ -104,8 +189,10 @@ impl FtpTransfer {
             .map_err(|e| FtpTransferError::Ftp(e.to_string()))? as u64;
 
         // Download file to a buffer using retr_as_buffer
-        let data = stream.retr_as_buffer(remote_path)
-            .map_err(|e| FtpTransferError::Ftp(e.to_string()))?;
+        let data = self.in_mode(&mut stream, |stream| {
+            stream.retr_as_buffer(remote_path)
+                .map_err(|e| FtpTransferError::Ftp(e.to_string()))
+        })?;
 
         drop(stream); // Release the lock before writing to local file
 
@@ -116,12 +203,20 @@ impl FtpTransfer {
 
         // Write to local file with progress updates
         let mut local_file = File::create(local_path)?;
-        let bytes = data.into_inner();
+        let mut bytes = data.into_inner();
+        if self.mode == FtpTransferMode::Ascii {
+            bytes = from_netascii(&bytes);
+        }
+        // Line ending translation changes the size
+        let total_size = if self.mode == FtpTransferMode::Ascii { bytes.len() as u64 } else { total_size };
         let chunk_size = 32768usize; // 32KB chunks for progress updates
         let mut transferred: u64 = 0;
 
         for chunk in bytes.chunks(chunk_size) {
             if *self.cancelled.lock() {
+                // Don't leave a truncated file behind
+                drop(local_file);
+                let _ = std::fs::remove_file(local_path);
                 return Err(FtpTransferError::Cancelled);
             }
 
@@ -179,6 +274,16 @@ impl FtpTransfer {
             progress_callback(transferred / 2, total_size); // Show 0-50% during read
         }
 
+        if self.create_parents {
+            if let Some((parent, _)) = remote_path.rsplit_once('/') {
+                self.create_remote_dirs(parent);
+            }
+        }
+
+        if self.mode == FtpTransferMode::Ascii {
+            buffer = to_netascii(&buffer);
+        }
+
         // Upload using put_file
         let mut stream = self.stream.lock();
         let mut cursor = Cursor::new(&buffer);
@@ -186,8 +291,10 @@ impl FtpTransfer {
         // Report 50% before upload starts
         progress_callback(total_size / 2, total_size);
 
-        stream.put_file(remote_path, &mut cursor)
-            .map_err(|e| FtpTransferError::Ftp(e.to_string()))?;
+        self.in_mode(&mut stream, |stream| {
+            stream.put_file(remote_path, &mut cursor)
+                .map_err(|e| FtpTransferError::Ftp(e.to_string()))
+        })?;
 
         // Report completion
         progress_callback(total_size, total_size);
@@ -195,7 +302,8 @@ impl FtpTransfer {
         Ok(())
     }
 
-    /// Upload a folder recursively
+    /// Upload a folder recursively. Progress names each file by its path
+    /// within the folder.
     pub fn upload_folder<F>(
         &self,
         local_path: &str,
@@ -228,6 +336,9 @@ impl FtpTransfer {
         let remote_root = remote_base.join(&folder_name);
         let remote_root_str = remote_root.to_string_lossy().to_string();
 
+        if self.create_parents {
+            self.create_remote_dirs(remote_path);
+        }
         {
             let mut stream = self.stream.lock();
             let _ = stream.mkdir(&remote_root_str);
@@ -249,11 +360,8 @@ impl FtpTransfer {
                 let mut stream = self.stream.lock();
                 let _ = stream.mkdir(&remote_entry_str);
             } else if entry.file_type().is_file() {
-                // Upload file
-                let file_name = entry_path
-                    .file_name()
-                    .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or_default();
+                // Upload file, reporting its path within the folder
+                let file_name = relative_path.to_string_lossy().to_string();
 
                 progress_callback(transferred, total_size, &file_name);
 
@@ -281,3 +389,33 @@ impl FtpTransfer {
         Ok(())
     }
 }
+
+/// Text as sent over an ASCII-mode connection: CRLF line endings
+fn to_netascii(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 32);
+    let mut previous = 0u8;
+    for &byte in data {
+        if byte == b'\n' && previous != b'\r' {
+            out.push(b'\r');
+        }
+        out.push(byte);
+        previous = byte;
+    }
+    out
+}
+
+/// Text received over an ASCII-mode connection, with local line endings.
+/// suppaftp passes data through as is, so CRLF is translated here.
+fn from_netascii(data: &[u8]) -> Vec<u8> {
+    if cfg!(windows) {
+        return data.to_vec();
+    }
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &byte) in data.iter().enumerate() {
+        if byte == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        out.push(byte);
+    }
+    out
+}
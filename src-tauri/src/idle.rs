@@ -0,0 +1,77 @@
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+
+/// How often the watcher looks at the session's last activity
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When a session last saw input or output. Clones share the timestamp.
+#[derive(Clone)]
+pub struct Activity(Arc<Mutex<Instant>>);
+
+impl Activity {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    pub fn touch(&self) {
+        *self.0.lock() = Instant::now();
+    }
+
+    pub fn idle_for(&self) -> Duration {
+        self.0.lock().elapsed()
+    }
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Closes a session once it has been idle for its timeout, then sends
+/// `session-idle-timeout-{id}` with the timeout in seconds. `close` returns
+/// false if the session had already gone. Stops when dropped.
+pub struct IdleWatch {
+    stop: Arc<AtomicBool>,
+}
+
+impl IdleWatch {
+    pub fn start(
+        session_id: &str,
+        activity: Activity,
+        timeout: Duration,
+        app_handle: AppHandle,
+        close: impl FnOnce() -> bool + Send + 'static,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = stop.clone();
+        let event = format!("session-idle-timeout-{}", session_id);
+
+        thread::spawn(move || {
+            while !stop_flag.load(Ordering::SeqCst) {
+                thread::sleep(CHECK_INTERVAL.min(timeout));
+                if activity.idle_for() < timeout || stop_flag.load(Ordering::SeqCst) {
+                    continue;
+                }
+
+                if close() {
+                    eprintln!("Closed session after {}s idle: {}", timeout.as_secs(), event);
+                    let _ = app_handle.emit(&event, timeout.as_secs());
+                }
+                return;
+            }
+        });
+
+        Self { stop }
+    }
+}
+
+impl Drop for IdleWatch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
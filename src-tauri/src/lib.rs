@@ -1,24 +1,39 @@
+pub mod frame;
+mod certificate;
+mod file_info;
 mod ftp;
+mod idle;
 mod local;
+mod progress;
+mod proxy;
 mod rdp;
 mod sftp;
 mod ssh;
 mod state;
 mod storage;
 mod terminal;
+mod util;
 mod vnc;
 
+use certificate::ConnectError;
 use ftp::{FtpAuthMethod, FtpBrowser, FtpClient};
 use parking_lot::Mutex;
+use progress::{ThrottledProgress, TransferGroup};
+use proxy::ProxyConfig;
 use rdp::RdpManager;
 use sftp::{FileEntry, SftpBrowser, TransferProgress, TransferStatus};
-use ssh::AuthMethod;
+use ssh::{AuthMethod, SshConnectStage};
 use state::AppState;
 use std::collections::HashMap;
 use std::sync::Arc;
-use storage::{ConnectionProfile, ConnectionStorage, ConnectionType, KeychainManager, StoredAuthMethod};
-use tauri::{AppHandle, Emitter, State};
-use terminal::session::SessionInfo;
+use storage::{
+    CertificatePins, ConnectionChanges, ConnectionFilter, ConnectionProfile, ConnectionStorage, ConnectionType, ForeignSession,
+    ConnectionStats, ImportSummary, JournalTracker, KeychainBackendStatus, KeychainManager, ProfileMetadata,
+    ProfileSecrets, RdpLicenses, ResumableTransfer, StorageEncryptionStatus, StoredAuthMethod, TerminalSettings, TransferJournal,
+    UsageLog, UsageTracker,
+};
+use tauri::{AppHandle, Emitter, Manager, State};
+use terminal::session::{SessionInfo, SessionStats};
 use vnc::VncManager;
 
 // SFTP sessions stored separately with their own ID
@@ -37,14 +52,27 @@ type RdpManagerState = Arc<RdpManager>;
 async fn create_terminal(
     app_handle: AppHandle,
     state: State<'_, Arc<AppState>>,
+    term: Option<String>,
 ) -> Result<SessionInfo, String> {
-    let info = state.terminal_manager.create_local_session()?;
+    let info = state.terminal_manager.create_local_session(term.as_deref())?;
     state
         .terminal_manager
         .start_output_reader(&info.id, app_handle)?;
     Ok(info)
 }
 
+/// Sends each stage of an SSH connect as `ssh-connect-progress-{connect_id}`,
+/// if the caller gave an ID to listen on
+fn connect_progress(app_handle: &AppHandle, connect_id: Option<String>) -> Option<impl Fn(SshConnectStage)> {
+    let app_handle = app_handle.clone();
+    connect_id.map(move |connect_id| {
+        let event = format!("ssh-connect-progress-{}", connect_id);
+        move |stage: SshConnectStage| {
+            let _ = app_handle.emit(&event, stage);
+        }
+    })
+}
+
 #[tauri::command]
 async fn create_ssh_terminal(
     app_handle: AppHandle,
@@ -53,10 +81,20 @@ async fn create_ssh_terminal(
     port: u16,
     username: String,
     auth: AuthMethod,
+    term: Option<String>,
+    proxy: Option<ProxyConfig>,
+    connect_id: Option<String>,
 ) -> Result<SessionInfo, String> {
-    let info = state
-        .terminal_manager
-        .create_ssh_session(&host, port, &username, &auth)?;
+    let progress = connect_progress(&app_handle, connect_id);
+    let info = state.terminal_manager.create_ssh_session(
+        &host,
+        port,
+        &username,
+        &auth,
+        term.as_deref(),
+        proxy.as_ref(),
+        progress.as_ref().map(|progress| progress as &dyn Fn(SshConnectStage)),
+    )?;
     state
         .terminal_manager
         .start_output_reader(&info.id, app_handle)?;
@@ -72,6 +110,85 @@ async fn write_terminal(
     state.terminal_manager.write_to_session(&session_id, &data)
 }
 
+/// Like `write_terminal`, but takes text and UTF-8 encodes it here
+#[tauri::command]
+async fn write_terminal_text(
+    state: State<'_, Arc<AppState>>,
+    session_id: String,
+    text: String,
+) -> Result<usize, String> {
+    state
+        .terminal_manager
+        .write_to_session(&session_id, text.as_bytes())
+}
+
+/// Set `key` in a session's interactive shell by typing an assignment
+/// into it: `export` for SSH and Unix shells, `$env:` for PowerShell on
+/// Windows. Best effort; only that shell and what it starts afterwards
+/// see the change. The line starts with a space, which keeps it out of
+/// history in shells set to ignore such lines.
+#[tauri::command]
+async fn set_session_env(
+    state: State<'_, Arc<AppState>>,
+    session_id: String,
+    key: String,
+    value: String,
+) -> Result<(), String> {
+    if !util::is_env_name(&key) {
+        return Err(format!("Invalid variable name: {}", key));
+    }
+    if value.contains(['\0', '\n', '\r']) {
+        return Err("The value can't contain line breaks or NUL".to_string());
+    }
+    let info = state
+        .terminal_manager
+        .get_session_info(&session_id)
+        .ok_or_else(|| format!("Session not found: {}", session_id))?;
+    let line = if cfg!(windows) && matches!(info.session_type, terminal::session::SessionType::Local) {
+        format!(" $env:{} = {}\r", key, util::powershell_quote(&value))
+    } else {
+        format!(" export {}={}\n", key, util::shell_quote(&value))
+    };
+    state
+        .terminal_manager
+        .write_to_session(&session_id, line.as_bytes())
+        .map(|_| ())
+}
+
+/// Environment of an SSH session's user, from running `env` on the server.
+/// That is a fresh shell, so variables set in the interactive one (e.g.
+/// with `set_session_env`) don't show up.
+#[tauri::command]
+async fn get_session_env(
+    state: State<'_, Arc<AppState>>,
+    session_id: String,
+) -> Result<std::collections::BTreeMap<String, String>, String> {
+    let client = state
+        .terminal_manager
+        .get_ssh_client(&session_id)
+        .ok_or_else(|| "Only SSH sessions can be queried".to_string())?;
+    let output = client.exec("env").map_err(|e| e.to_string())?;
+
+    let mut env = std::collections::BTreeMap::new();
+    let mut last: Option<String> = None;
+    for line in output.lines() {
+        match line.split_once('=').filter(|(name, _)| util::is_env_name(name)) {
+            Some((name, value)) => {
+                env.insert(name.to_string(), value.to_string());
+                last = Some(name.to_string());
+            }
+            // Continuation of a value with line breaks
+            None => {
+                if let Some(value) = last.as_ref().and_then(|name| env.get_mut(name)) {
+                    value.push('\n');
+                    value.push_str(line);
+                }
+            }
+        }
+    }
+    Ok(env)
+}
+
 #[tauri::command]
 async fn resize_terminal(
     state: State<'_, Arc<AppState>>,
@@ -84,6 +201,26 @@ async fn resize_terminal(
         .resize_session(&session_id, cols, rows)
 }
 
+#[tauri::command]
+async fn get_terminal_output_config(
+    state: State<'_, Arc<AppState>>,
+) -> Result<terminal::OutputConfig, String> {
+    Ok(state.terminal_manager.output_config())
+}
+
+/// Tune output batching for terminals opened afterwards
+#[tauri::command]
+async fn set_terminal_output_config(
+    state: State<'_, Arc<AppState>>,
+    config: terminal::OutputConfig,
+) -> Result<(), String> {
+    if config.read_buffer_size == 0 || config.max_batch_bytes == 0 {
+        return Err("Buffer sizes must be greater than zero".to_string());
+    }
+    state.terminal_manager.set_output_config(config);
+    Ok(())
+}
+
 #[tauri::command]
 async fn close_terminal(
     state: State<'_, Arc<AppState>>,
@@ -97,22 +234,324 @@ async fn list_terminals(state: State<'_, Arc<AppState>>) -> Result<Vec<SessionIn
     Ok(state.terminal_manager.list_sessions())
 }
 
+#[tauri::command]
+async fn ssh_get_banner(
+    state: State<'_, Arc<AppState>>,
+    session_id: String,
+) -> Result<ssh::SshBanner, String> {
+    state
+        .terminal_manager
+        .get_ssh_banner(&session_id)
+        .ok_or_else(|| "SSH session not found or not an SSH session".to_string())
+}
+
+/// Which authentication methods the server accepts for `username`, so the
+/// login form can show only the relevant fields. Doesn't authenticate.
+#[tauri::command]
+async fn ssh_probe_auth(
+    host: String,
+    port: u16,
+    username: String,
+    proxy: Option<ProxyConfig>,
+) -> Result<ssh::SshAuthMethods, String> {
+    ssh::SshClient::probe_auth(&host, port, &username, proxy.as_ref()).map_err(|e| e.to_string())
+}
+
 // ============ Connection Storage Commands ============
 
 #[tauri::command]
-async fn list_connections() -> Result<Vec<ConnectionProfile>, String> {
-    let storage = ConnectionStorage::new().map_err(|e| e.to_string())?;
-    storage.list().map_err(|e| e.to_string())
+async fn list_connections(
+    state: State<'_, Arc<AppState>>,
+    group: Option<String>,
+    tag: Option<String>,
+    connection_type: Option<String>,
+    search: Option<String>,
+) -> Result<Vec<ConnectionProfile>, String> {
+    let storage = &state.connections;
+    let filter = ConnectionFilter {
+        group,
+        tag,
+        connection_type,
+        search,
+    };
+    storage.list(&filter).map_err(|e| e.to_string())
+}
+
+/// Hosts from an OpenSSH config (by default `~/.ssh/config`) as SSH
+/// profiles, for the user to confirm before `save_imported_connections`.
+/// Nothing is saved. Wildcard patterns and hosts already saved with the
+/// same host, user and port are left out. Hosts with an IdentityFile use
+/// that key, others the SSH agent.
+#[tauri::command]
+async fn import_ssh_config(
+    state: State<'_, Arc<AppState>>,
+    path: Option<String>,
+) -> Result<Vec<ConnectionProfile>, String> {
+    let path = match path.filter(|p| !p.is_empty()) {
+        Some(path) => std::path::PathBuf::from(path),
+        None => ssh::config::default_config_path().ok_or("No home directory")?,
+    };
+    let hosts = ssh::config::read_ssh_config(&path)?;
+    let saved = state
+        .connections
+        .list(&ConnectionFilter::default())
+        .map_err(|e| e.to_string())?;
+    Ok(ssh::config::new_profiles(hosts, &saved))
+}
+
+/// Save the profiles the user kept from `import_ssh_config`, skipping any
+/// that match a saved SSH profile. Returns those saved.
+#[tauri::command]
+async fn save_imported_connections(
+    state: State<'_, Arc<AppState>>,
+    profiles: Vec<ConnectionProfile>,
+) -> Result<Vec<ConnectionProfile>, String> {
+    let storage = &state.connections;
+    let mut seen: std::collections::HashSet<_> = storage
+        .list(&ConnectionFilter::default())
+        .map_err(|e| e.to_string())?
+        .iter()
+        .filter_map(ConnectionProfile::ssh_target)
+        .collect();
+
+    let mut saved = Vec::new();
+    for profile in profiles {
+        let Some(target) = profile.ssh_target() else {
+            continue;
+        };
+        if seen.insert(target) {
+            storage
+                .save_connection(profile.clone())
+                .map_err(|e| e.to_string())?;
+            saved.push(profile);
+        }
+    }
+    Ok(saved)
+}
+
+/// PuTTY's saved SSH sessions as new profiles, for the user to confirm
+/// before `save_foreign_sessions`. Nothing is saved. Sessions matching a
+/// saved profile are left out; outside Windows there are none.
+#[tauri::command]
+async fn import_putty_sessions(state: State<'_, Arc<AppState>>) -> Result<Vec<ForeignSession>, String> {
+    new_foreign_sessions(&state.connections, storage::foreign::read_putty_sessions()?)
+}
+
+/// The FTP and SFTP sites in FileZilla's Site Manager, by default its own
+/// `sitemanager.xml`, as new profiles. Like `import_putty_sessions`,
+/// nothing is saved; the preview only says whether a site has a saved
+/// password.
+#[tauri::command]
+async fn import_filezilla_sites(
+    state: State<'_, Arc<AppState>>,
+    path: Option<String>,
+) -> Result<Vec<ForeignSession>, String> {
+    match filezilla_path(path) {
+        Some(path) => new_foreign_sessions(&state.connections, storage::foreign::read_filezilla_sites(&path)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// `path`, or FileZilla's own `sitemanager.xml` when none is given
+fn filezilla_path(path: Option<String>) -> Option<std::path::PathBuf> {
+    match path.filter(|p| !p.is_empty()) {
+        Some(path) => Some(std::path::PathBuf::from(path)),
+        None => storage::foreign::default_filezilla_path(),
+    }
+}
+
+/// Drop sessions for a login already saved, or earlier in the list
+fn new_foreign_sessions(
+    storage: &ConnectionStorage,
+    sessions: Vec<ForeignSession>,
+) -> Result<Vec<ForeignSession>, String> {
+    let mut seen: std::collections::HashSet<_> = storage
+        .list(&ConnectionFilter::default())
+        .map_err(|e| e.to_string())?
+        .iter()
+        .filter_map(ConnectionProfile::login_target)
+        .collect();
+    Ok(sessions
+        .into_iter()
+        .filter(|session| session.profile.login_target().is_some_and(|target| seen.insert(target)))
+        .collect())
+}
+
+/// Save the sessions the user kept from `import_putty_sessions` or
+/// `import_filezilla_sites`. Saved passwords are read again from the
+/// FileZilla file at `filezilla_path` (the same `path` the preview used)
+/// and put in the keychain. Sessions for a login already saved are skipped.
+#[tauri::command]
+async fn save_foreign_sessions(
+    state: State<'_, Arc<AppState>>,
+    sessions: Vec<ForeignSession>,
+    filezilla_path: Option<String>,
+) -> Result<ImportSummary, String> {
+    let storage = &state.connections;
+    let mut passwords = HashMap::new();
+    if sessions.iter().any(|session| session.has_password) {
+        if let Some(path) = self::filezilla_path(filezilla_path) {
+            for site in storage::foreign::read_filezilla_sites(&path)? {
+                if let (Some(target), Some(password)) = (site.profile.login_target(), site.password) {
+                    passwords.entry(target).or_insert(password);
+                }
+            }
+        }
+    }
+    let mut seen: std::collections::HashSet<_> = storage
+        .list(&ConnectionFilter::default())
+        .map_err(|e| e.to_string())?
+        .iter()
+        .filter_map(ConnectionProfile::login_target)
+        .collect();
+
+    let mut summary = ImportSummary {
+        imported: Vec::new(),
+        renamed_ids: 0,
+        secret_errors: Vec::new(),
+    };
+    for session in sessions {
+        if !session.profile.login_target().is_some_and(|target| seen.insert(target)) {
+            continue;
+        }
+        storage
+            .save_connection(session.profile.clone())
+            .map_err(|e| e.to_string())?;
+        if session.has_password {
+            let password = session.profile.login_target().and_then(|target| passwords.get(&target));
+            let result = match password {
+                Some(password) => {
+                    KeychainManager::store_password(&session.profile.id, password).map_err(|e| e.to_string())
+                }
+                None => Err("the saved password is no longer there".to_string()),
+            };
+            if let Err(e) = result {
+                summary.secret_errors.push(format!("{}: {}", session.profile.name, e));
+            }
+        }
+        summary.imported.push(session.profile);
+    }
+    Ok(summary)
+}
+
+/// Write every connection profile to `path`, to move them to another
+/// machine. `include_secrets` adds the saved passwords and requires a
+/// `passphrase`, which encrypts the file. Returns the number exported.
+#[tauri::command]
+async fn export_connections(
+    state: State<'_, Arc<AppState>>,
+    path: String,
+    include_secrets: bool,
+    passphrase: Option<String>,
+) -> Result<usize, String> {
+    let storage = &state.connections;
+    storage::portable::export_connections(
+        storage,
+        std::path::Path::new(&path),
+        include_secrets,
+        passphrase.as_deref(),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Add the profiles from an `export_connections` file, restoring any saved
+/// passwords. Profiles whose id already exists are imported under a new one.
+#[tauri::command]
+async fn import_connections(
+    state: State<'_, Arc<AppState>>,
+    path: String,
+    passphrase: Option<String>,
+) -> Result<ImportSummary, String> {
+    let storage = &state.connections;
+    storage::portable::import_connections(storage, std::path::Path::new(&path), passphrase.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+/// Whether connections.json is encrypted and unlocked. While it's
+/// encrypted and locked, other storage commands fail until `unlock_storage`.
+#[tauri::command]
+async fn storage_encryption_status(state: State<'_, Arc<AppState>>) -> Result<StorageEncryptionStatus, String> {
+    let storage = &state.connections;
+    storage.encryption_status().map_err(|e| e.to_string())
+}
+
+/// Encrypt the connection profiles, and passwords saved from now on, under
+/// `master_password`, for systems without a usable keychain
+#[tauri::command]
+async fn enable_storage_encryption(state: State<'_, Arc<AppState>>, master_password: String) -> Result<(), String> {
+    let storage = &state.connections;
+    storage.enable_encryption(&master_password).map_err(|e| e.to_string())
+}
+
+/// Unlock encrypted storage for this run. A wrong password leaves it
+/// locked and the file untouched.
+#[tauri::command]
+async fn unlock_storage(state: State<'_, Arc<AppState>>, master_password: String) -> Result<(), String> {
+    let storage = &state.connections;
+    storage.unlock(&master_password).map_err(|e| e.to_string())
+}
+
+/// Decrypt the connection profiles back to plain JSON and move the
+/// passwords to the keychain. Fails, still encrypted, if the keychain
+/// won't take them all.
+#[tauri::command]
+async fn disable_storage_encryption(
+    state: State<'_, Arc<AppState>>,
+    master_password: String,
+) -> Result<(), String> {
+    let storage = &state.connections;
+    storage.disable_encryption(&master_password).map_err(|e| e.to_string())
+}
+
+/// Replace a connection's tags
+#[tauri::command]
+async fn set_connection_tags(state: State<'_, Arc<AppState>>, id: String, tags: Vec<String>) -> Result<(), String> {
+    let storage = &state.connections;
+    storage.set_tags(&id, tags).map_err(|e| e.to_string())
+}
+
+/// Put a connection in a group; an empty or missing `group` ungroups it
+#[tauri::command]
+async fn set_connection_group(
+    state: State<'_, Arc<AppState>>,
+    id: String,
+    group: Option<String>,
+) -> Result<(), String> {
+    let storage = &state.connections;
+    let group = group.map(|g| g.trim().to_string()).filter(|g| !g.is_empty());
+    storage.set_group(&id, group).map_err(|e| e.to_string())
+}
+
+/// Mark or unmark a connection as a favorite; favorites are listed first
+#[tauri::command]
+async fn set_connection_favorite(state: State<'_, Arc<AppState>>, id: String, favorite: bool) -> Result<(), String> {
+    let storage = &state.connections;
+    storage.set_favorite(&id, favorite).map_err(|e| e.to_string())
+}
+
+/// Set the order favorites are listed in, e.g. after a drag and drop
+#[tauri::command]
+async fn reorder_connections(state: State<'_, Arc<AppState>>, ordered_ids: Vec<String>) -> Result<(), String> {
+    let storage = &state.connections;
+    storage.reorder(&ordered_ids).map_err(|e| e.to_string())
+}
+
+/// Names of the groups connections are in, sorted
+#[tauri::command]
+async fn list_connection_groups(state: State<'_, Arc<AppState>>) -> Result<Vec<String>, String> {
+    let storage = &state.connections;
+    storage.groups().map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn get_connection(id: String) -> Result<ConnectionProfile, String> {
-    let storage = ConnectionStorage::new().map_err(|e| e.to_string())?;
+async fn get_connection(state: State<'_, Arc<AppState>>, id: String) -> Result<ConnectionProfile, String> {
+    let storage = &state.connections;
     storage.get(&id).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 async fn save_connection(
+    state: State<'_, Arc<AppState>>,
     name: String,
     host: String,
     port: u16,
@@ -120,18 +559,23 @@ async fn save_connection(
     auth_type: String,
     private_key_path: Option<String>,
     password: Option<String>,
+    certificate_path: Option<String>,
+    passphrase: Option<String>,
+    save_passphrase: Option<bool>,
 ) -> Result<ConnectionProfile, String> {
-    let storage = ConnectionStorage::new().map_err(|e| e.to_string())?;
+    let storage = &state.connections;
 
     let auth_method = match auth_type.as_str() {
         "password" => StoredAuthMethod::Password,
         "publickey" => StoredAuthMethod::PublicKey {
             private_key_path: private_key_path.unwrap_or_default(),
+            certificate_path: certificate_path.filter(|p| !p.is_empty()),
         },
         "agent" => StoredAuthMethod::Agent,
         _ => return Err("Invalid auth type".to_string()),
     };
 
+    let uses_key = matches!(auth_method, StoredAuthMethod::PublicKey { .. });
     let profile = ConnectionProfile::new_ssh(name, host, port, username, auth_method);
 
     // Store password in keychain if provided
@@ -141,6 +585,12 @@ async fn save_connection(
                 .map_err(|e| format!("Failed to store password: {}", e))?;
         }
     }
+    if uses_key && save_passphrase.unwrap_or(false) {
+        if let Some(passphrase) = passphrase.filter(|p| !p.is_empty()) {
+            KeychainManager::store_passphrase(&profile.id, &passphrase)
+                .map_err(|e| format!("Failed to store passphrase: {}", e))?;
+        }
+    }
 
     storage
         .save_connection(profile.clone())
@@ -151,6 +601,7 @@ async fn save_connection(
 
 #[tauri::command]
 async fn save_ftp_connection(
+    state: State<'_, Arc<AppState>>,
     name: String,
     host: String,
     port: u16,
@@ -158,7 +609,7 @@ async fn save_ftp_connection(
     password: Option<String>,
     anonymous: bool,
 ) -> Result<ConnectionProfile, String> {
-    let storage = ConnectionStorage::new().map_err(|e| e.to_string())?;
+    let storage = &state.connections;
 
     let profile = ConnectionProfile::new_ftp(name, host, port, username, anonymous);
 
@@ -181,14 +632,29 @@ async fn save_ftp_connection(
 
 #[tauri::command]
 async fn save_vnc_connection(
+    state: State<'_, Arc<AppState>>,
     name: String,
     host: String,
     port: u16,
     password: Option<String>,
+    encoding: Option<vnc::VncEncoding>,
+    quality: Option<u8>,
+    color_depth: Option<vnc::VncColorDepth>,
+    auth_type: Option<vnc::VncAuthType>,
+    username: Option<String>,
 ) -> Result<ConnectionProfile, String> {
-    let storage = ConnectionStorage::new().map_err(|e| e.to_string())?;
+    let storage = &state.connections;
 
-    let profile = ConnectionProfile::new_vnc(name, host, port);
+    let profile = ConnectionProfile::new_vnc(
+        name,
+        host,
+        port,
+        encoding.unwrap_or_default(),
+        quality,
+        color_depth.unwrap_or_default(),
+        auth_type.unwrap_or_default(),
+        username.filter(|u| !u.is_empty()),
+    );
 
     // Store password in keychain if provided
     if let Some(pwd) = password {
@@ -207,24 +673,56 @@ async fn save_vnc_connection(
 
 #[tauri::command]
 async fn save_rdp_connection(
+    state: State<'_, Arc<AppState>>,
     name: String,
     host: String,
     port: u16,
     username: String,
     password: Option<String>,
     domain: Option<String>,
+    save_password: Option<bool>,
+    quality: Option<rdp::RdpQuality>,
+    keyboard: Option<rdp::RdpKeyboard>,
+    accept_invalid_certs: Option<bool>,
+    gateway: Option<rdp::RdpGateway>,
+    auto_reconnect: Option<bool>,
+    codec: Option<rdp::RdpCodec>,
+    shared_folder: Option<String>,
+    shared_folder_read_only: Option<bool>,
+    custom_performance_flags: Option<rdp::RdpPerformanceFlags>,
 ) -> Result<ConnectionProfile, String> {
-    let storage = ConnectionStorage::new().map_err(|e| e.to_string())?;
+    let storage = &state.connections;
 
-    let profile = ConnectionProfile::new_rdp(name, host, port, username, domain);
+    let save_password = save_password.unwrap_or(true);
+    let gateway_password = gateway.as_ref().and_then(|g| g.password.clone());
+    let profile = ConnectionProfile::new_rdp(
+        name,
+        host,
+        port,
+        username,
+        domain,
+        save_password,
+        quality.unwrap_or_default(),
+        keyboard.unwrap_or_else(rdp::RdpKeyboard::system),
+        accept_invalid_certs.unwrap_or(false),
+        gateway,
+        auto_reconnect.unwrap_or(true),
+        codec.unwrap_or_default(),
+        rdp_shared_folder(shared_folder, shared_folder_read_only),
+        custom_performance_flags,
+    );
 
-    // Store password in keychain if provided
-    if let Some(pwd) = password {
+    // Store password in keychain if provided, unless the profile is prompt-only
+    if let Some(pwd) = password.filter(|_| save_password) {
         if !pwd.is_empty() {
             KeychainManager::store_password(&profile.id, &pwd)
                 .map_err(|e| format!("Failed to store password: {}", e))?;
         }
     }
+    if let Some(pwd) = gateway_password.filter(|p| !p.is_empty()) {
+        KeychainManager::store_password(&KeychainManager::gateway_entry(&profile.id), &pwd)
+            .map_err(|e| format!("Failed to store gateway password: {}", e))?;
+    }
 
     storage
         .save_connection(profile.clone())
@@ -233,88 +731,174 @@ async fn save_rdp_connection(
     Ok(profile)
 }
 
+/// Change a saved connection. Unset arguments keep what's stored, and
+/// saved passwords are only touched when the change affects them.
 #[tauri::command]
 async fn update_connection(
+    state: State<'_, Arc<AppState>>,
     id: String,
-    name: String,
+    name: Option<String>,
     connection_type: String,
-    host: String,
-    port: u16,
+    host: Option<String>,
+    port: Option<u16>,
     username: Option<String>,
     auth_type: Option<String>,
     private_key_path: Option<String>,
     password: Option<String>,
     anonymous: Option<bool>,
     domain: Option<String>,
+    encoding: Option<vnc::VncEncoding>,
+    quality: Option<u8>,
+    color_depth: Option<vnc::VncColorDepth>,
+    certificate_path: Option<String>,
+    save_password: Option<bool>,
+    rdp_quality: Option<rdp::RdpQuality>,
+    rdp_keyboard: Option<rdp::RdpKeyboard>,
+    rdp_accept_invalid_certs: Option<bool>,
+    rdp_gateway: Option<rdp::RdpGateway>,
+    rdp_auto_reconnect: Option<bool>,
+    rdp_codec: Option<rdp::RdpCodec>,
+    rdp_shared_folder: Option<String>,
+    rdp_shared_folder_read_only: Option<bool>,
+    rdp_custom_performance_flags: Option<rdp::RdpPerformanceFlags>,
+    passphrase: Option<String>,
+    save_passphrase: Option<bool>,
+    notes: Option<String>,
+    environment: Option<String>,
+    owner: Option<String>,
 ) -> Result<ConnectionProfile, String> {
-    let storage = ConnectionStorage::new().map_err(|e| e.to_string())?;
+    let storage = &state.connections;
+    let secrets = ProfileSecrets {
+        password,
+        gateway_password: rdp_gateway.as_ref().and_then(|g| g.password.clone()),
+        passphrase,
+        save_passphrase,
+    };
+    let changes = ConnectionChanges {
+        host,
+        port,
+        username,
+        auth_type,
+        private_key_path,
+        certificate_path,
+        anonymous,
+        domain,
+        encoding,
+        quality,
+        color_depth,
+        save_password,
+        rdp_quality,
+        keyboard: rdp_keyboard,
+        accept_invalid_certs: rdp_accept_invalid_certs,
+        gateway: rdp_gateway,
+        auto_reconnect: rdp_auto_reconnect,
+        codec: rdp_codec,
+        shared_folder: rdp_shared_folder,
+        shared_folder_read_only: rdp_shared_folder_read_only,
+        custom_performance_flags: rdp_custom_performance_flags,
+    };
 
-    // Get existing profile to preserve created_at and last_used
-    let existing = storage.get(&id).map_err(|e| e.to_string())?;
+    let (before, profile) = storage
+        .update(&id, &connection_type, name, changes, ProfileMetadata { notes, environment, owner })
+        .map_err(|e| e.to_string())?;
+    secrets
+        .apply(&storage::keychain::Keychain, &before, &profile)
+        .map_err(|e| format!("Failed to store password: {}", e))?;
 
-    let conn_type = match connection_type.as_str() {
-        "ssh" => {
-            let auth_method = match auth_type.as_deref().unwrap_or("password") {
-                "password" => StoredAuthMethod::Password,
-                "publickey" => StoredAuthMethod::PublicKey {
-                    private_key_path: private_key_path.unwrap_or_default(),
-                },
-                "agent" => StoredAuthMethod::Agent,
-                _ => return Err("Invalid auth type".to_string()),
-            };
-            ConnectionType::Ssh {
-                host,
-                port,
-                username: username.unwrap_or_default(),
-                auth_method,
-            }
-        }
-        "ftp" => ConnectionType::Ftp {
-            host,
-            port,
-            username,
-            anonymous: anonymous.unwrap_or(false),
-        },
-        "vnc" => ConnectionType::Vnc { host, port },
-        "rdp" => ConnectionType::Rdp {
-            host,
-            port,
-            username: username.unwrap_or_default(),
-            domain,
-        },
-        _ => return Err("Invalid connection type".to_string()),
-    };
+    Ok(profile)
+}
 
-    let profile = ConnectionProfile {
-        id: id.clone(),
-        name,
-        connection_type: conn_type,
-        created_at: existing.created_at,
-        last_used: existing.last_used,
-    };
+/// Folder downloads from this connection default to, if one is set
+#[tauri::command]
+async fn get_connection_download_dir(state: State<'_, Arc<AppState>>, id: String) -> Result<Option<String>, String> {
+    let storage = &state.connections;
+    let profile = storage.get(&id).map_err(|e| e.to_string())?;
+    Ok(profile.default_download_dir)
+}
 
-    // Update password in keychain
-    let _ = KeychainManager::delete_password(&id);
-    if let Some(pwd) = password {
-        if !pwd.is_empty() {
-            KeychainManager::store_password(&id, &pwd)
-                .map_err(|e| format!("Failed to store password: {}", e))?;
-        }
-    }
+/// Set the folder downloads from this connection default to; an empty or
+/// missing `dir` clears it
+#[tauri::command]
+async fn set_connection_download_dir(
+    state: State<'_, Arc<AppState>>,
+    id: String,
+    dir: Option<String>,
+) -> Result<(), String> {
+    let storage = &state.connections;
+    storage
+        .set_default_download_dir(&id, dir.filter(|d| !d.is_empty()))
+        .map_err(|e| e.to_string())
+}
+
+/// Set the folders SFTP/FTP sessions from this connection start in, remote
+/// and local; empty or missing clears them. Both must be absolute.
+#[tauri::command]
+async fn set_connection_default_paths(
+    state: State<'_, Arc<AppState>>,
+    id: String,
+    remote_path: Option<String>,
+    local_path: Option<String>,
+) -> Result<(), String> {
+    let nonempty = |path: Option<String>| path.map(|p| p.trim().to_string()).filter(|p| !p.is_empty());
+    state
+        .connections
+        .set_default_paths(&id, nonempty(remote_path), nonempty(local_path))
+        .map_err(|e| e.to_string())
+}
+
+/// Close sessions opened from this connection after `secs` without
+/// activity; zero or missing turns it off
+#[tauri::command]
+async fn set_connection_idle_timeout(
+    state: State<'_, Arc<AppState>>,
+    id: String,
+    secs: Option<u64>,
+) -> Result<(), String> {
+    let storage = &state.connections;
+    storage
+        .set_idle_timeout(&id, secs.filter(|&s| s > 0))
+        .map_err(|e| e.to_string())
+}
+
+/// The connection's terminal settings, if it has any
+#[tauri::command]
+async fn get_connection_terminal_settings(
+    state: State<'_, Arc<AppState>>,
+    id: String,
+) -> Result<Option<TerminalSettings>, String> {
+    let storage = &state.connections;
+    let profile = storage.get(&id).map_err(|e| e.to_string())?;
+    Ok(profile.terminal_settings)
+}
 
+/// Replace the connection's terminal settings; `None` or all fields unset
+/// goes back to the app defaults. Returns what was stored.
+#[tauri::command]
+async fn set_connection_terminal_settings(
+    state: State<'_, Arc<AppState>>,
+    id: String,
+    settings: Option<TerminalSettings>,
+) -> Result<Option<TerminalSettings>, String> {
+    let settings = match settings {
+        Some(settings) => settings.normalized()?,
+        None => None,
+    };
+    let storage = &state.connections;
     storage
-        .save_connection(profile.clone())
+        .set_terminal_settings(&id, settings.clone())
         .map_err(|e| e.to_string())?;
-
-    Ok(profile)
+    Ok(settings)
 }
 
 #[tauri::command]
-async fn delete_connection(id: String) -> Result<(), String> {
-    let storage = ConnectionStorage::new().map_err(|e| e.to_string())?;
+async fn delete_connection(state: State<'_, Arc<AppState>>, id: String) -> Result<(), String> {
+    let storage = &state.connections;
 
-    // Try to delete password from keychain (ignore errors if not found)
+    // Try to delete passwords from keychain (ignore errors if not found)
     let _ = KeychainManager::delete_password(&id);
+    let _ = KeychainManager::delete_password(&KeychainManager::gateway_entry(&id));
+    let _ = KeychainManager::delete_passphrase(&id);
+    let _ = UsageLog::new().and_then(|log| log.remove(&id));
 
     storage.delete(&id).map_err(|e| e.to_string())
 }
@@ -326,9 +910,37 @@ async fn connect_saved(
     connection_id: String,
     password: Option<String>,
     passphrase: Option<String>,
+    term: Option<String>,
+    proxy: Option<ProxyConfig>,
+    connect_id: Option<String>,
 ) -> Result<SessionInfo, String> {
-    let storage = ConnectionStorage::new().map_err(|e| e.to_string())?;
-    let profile = storage.get(&connection_id).map_err(|e| e.to_string())?;
+    let profile = state.connections.get(&connection_id).map_err(|e| e.to_string())?;
+    let progress = connect_progress(&app_handle, connect_id);
+    open_saved_ssh(
+        &app_handle,
+        &state,
+        &profile,
+        password,
+        passphrase,
+        term.as_deref(),
+        proxy.as_ref(),
+        progress.as_ref().map(|progress| progress as &dyn Fn(SshConnectStage)),
+    )
+}
+
+/// Open a terminal for the saved SSH `profile`. Passwords not given come
+/// from the keychain.
+fn open_saved_ssh(
+    app_handle: &AppHandle,
+    state: &AppState,
+    profile: &ConnectionProfile,
+    password: Option<String>,
+    passphrase: Option<String>,
+    term: Option<&str>,
+    proxy: Option<&ProxyConfig>,
+    progress: Option<&dyn Fn(SshConnectStage)>,
+) -> Result<SessionInfo, String> {
+    let connection_id = &profile.id;
 
     // Extract SSH connection details
     let (host, port, username) = match &profile.connection_type {
@@ -346,25 +958,449 @@ async fn connect_saved(
         }
     };
 
-    // Try to get password from keychain if not provided
-    let pwd = password.or_else(|| KeychainManager::get_password(&connection_id).ok());
+    // Try to get password and key passphrase from keychain if not provided
+    let pwd = password.or_else(|| KeychainManager::get_password(connection_id).ok());
+    let passphrase = passphrase.or_else(|| KeychainManager::get_passphrase(connection_id).ok());
 
     let auth = profile.to_auth_method(pwd, passphrase);
 
-    let info = state
+    let mut info = state
+        .terminal_manager
+        .create_ssh_session(&host, port, &username, &auth, term, proxy, progress)
+        .inspect_err(|e| storage::usage::record_failure(connection_id, e))?;
+    info.terminal_settings = profile.terminal_settings.clone();
+    state
         .terminal_manager
-        .create_ssh_session(&host, port, &username, &auth)?;
+        .track_usage(&info.id, UsageTracker::start(connection_id))?;
 
+    if let Some(secs) = profile.idle_timeout_secs {
+        state
+            .terminal_manager
+            .set_idle_timeout(&info.id, std::time::Duration::from_secs(secs), app_handle.clone())?;
+    }
     state
         .terminal_manager
-        .start_output_reader(&info.id, app_handle)?;
+        .start_output_reader(&info.id, app_handle.clone())?;
 
     // Update last used timestamp
-    let _ = storage.update_last_used(&connection_id);
+    let _ = state.connections.update_last_used(connection_id);
 
     Ok(info)
 }
 
+/// Connect counts, connected time and the last failure of a saved
+/// connection; zeroes if it was never connected
+#[tauri::command]
+async fn get_connection_stats(id: String) -> Result<ConnectionStats, String> {
+    let log = UsageLog::new().map_err(|e| e.to_string())?;
+    log.stats(&id).map_err(|e| e.to_string())
+}
+
+/// Stats of the `limit` connections most recently connected to, or tried
+#[tauri::command]
+async fn get_recent_connections(limit: Option<usize>) -> Result<Vec<ConnectionStats>, String> {
+    let log = UsageLog::new().map_err(|e| e.to_string())?;
+    log.recent(limit.unwrap_or(10)).map_err(|e| e.to_string())
+}
+
+/// Options for `connect_group`
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+struct GroupConnectOptions {
+    term: Option<String>,
+    proxy: Option<ProxyConfig>,
+    /// Connections attempted at once; defaults to `GROUP_CONNECT_PARALLELISM`
+    max_parallel: Option<usize>,
+}
+
+const GROUP_CONNECT_PARALLELISM: usize = 4;
+
+/// One profile's outcome in `connect_group`
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum GroupConnect {
+    Connected {
+        connection_id: String,
+        name: String,
+        session: Box<SessionInfo>,
+    },
+    Failed {
+        connection_id: String,
+        name: String,
+        error: String,
+    },
+}
+
+/// Open a terminal for every SSH profile in the group `group_or_tag`, or
+/// tagged with it, several at a time. A host that fails doesn't stop the
+/// others; results are in connection list order.
+#[tauri::command]
+async fn connect_group(
+    app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
+    group_or_tag: String,
+    options: Option<GroupConnectOptions>,
+) -> Result<Vec<GroupConnect>, String> {
+    let options = options.unwrap_or_default();
+    let filter = ConnectionFilter {
+        connection_type: Some("ssh".to_string()),
+        ..Default::default()
+    };
+    let profiles: Vec<ConnectionProfile> = state
+        .connections
+        .list(&filter)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|profile| {
+            profile.group.as_deref().is_some_and(|group| group.eq_ignore_ascii_case(&group_or_tag))
+                || profile.tags.iter().any(|tag| tag.eq_ignore_ascii_case(&group_or_tag))
+        })
+        .collect();
+    if profiles.is_empty() {
+        return Err(format!("No SSH connections in group or tag \"{}\"", group_or_tag));
+    }
+
+    Ok(open_saved_profiles(&app_handle, &state, &profiles, &options, &|_| {}))
+}
+
+/// Open a terminal for each SSH profile, several at a time, calling
+/// `on_result` as each one connects or fails. Results are in `profiles`
+/// order.
+fn open_saved_profiles(
+    app_handle: &AppHandle,
+    state: &AppState,
+    profiles: &[ConnectionProfile],
+    options: &GroupConnectOptions,
+    on_result: &(dyn Fn(&GroupConnect) + Sync),
+) -> Vec<GroupConnect> {
+    if profiles.is_empty() {
+        return Vec::new();
+    }
+    let workers = options
+        .max_parallel
+        .unwrap_or(GROUP_CONNECT_PARALLELISM)
+        .clamp(1, profiles.len());
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let mut results: Vec<(usize, GroupConnect)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        let Some(profile) = profiles.get(index) else {
+                            break;
+                        };
+                        let connection_id = profile.id.clone();
+                        let name = profile.name.clone();
+                        let outcome = match open_saved_ssh(
+                            app_handle,
+                            state,
+                            profile,
+                            None,
+                            None,
+                            options.term.as_deref(),
+                            options.proxy.as_ref(),
+                            None,
+                        ) {
+                            Ok(session) => GroupConnect::Connected {
+                                connection_id,
+                                name,
+                                session: Box::new(session),
+                            },
+                            Err(error) => GroupConnect::Failed {
+                                connection_id,
+                                name,
+                                error,
+                            },
+                        };
+                        on_result(&outcome);
+                        done.push((index, outcome));
+                    }
+                    done
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+/// Set once `run_startup_connections` has run, so a reloaded frontend
+/// doesn't open the sessions again
+static STARTUP_CONNECTIONS_RAN: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Sent as `startup-connections-complete` when startup connections finish
+#[derive(Debug, Clone, serde::Serialize)]
+struct StartupConnectionsSummary {
+    connected: usize,
+    /// Only `GroupConnect::Failed` entries
+    failed: Vec<GroupConnect>,
+}
+
+/// Open every SSH profile marked `auto_connect`, with keychain credentials,
+/// unless that's turned off. Each result is sent as `startup-connection`
+/// as it arrives and a summary as `startup-connections-complete`. Only the
+/// first successful call per app run does anything; while storage is
+/// locked it fails and can be retried after unlocking.
+#[tauri::command]
+async fn run_startup_connections(
+    app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Vec<GroupConnect>, String> {
+    let storage = &state.connections;
+    let profiles: Vec<ConnectionProfile> = if storage.auto_connect_enabled().map_err(|e| e.to_string())? {
+        let filter = ConnectionFilter {
+            connection_type: Some("ssh".to_string()),
+            ..Default::default()
+        };
+        storage
+            .list(&filter)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .filter(|profile| profile.auto_connect)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    if STARTUP_CONNECTIONS_RAN.swap(true, std::sync::atomic::Ordering::SeqCst) {
+        return Ok(Vec::new());
+    }
+
+    let emitter = app_handle.clone();
+    let results = open_saved_profiles(&app_handle, &state, &profiles, &Default::default(), &move |result| {
+        let _ = emitter.emit("startup-connection", result);
+    });
+    let summary = StartupConnectionsSummary {
+        connected: results
+            .iter()
+            .filter(|result| matches!(result, GroupConnect::Connected { .. }))
+            .count(),
+        failed: results
+            .iter()
+            .filter(|result| matches!(result, GroupConnect::Failed { .. }))
+            .cloned()
+            .collect(),
+    };
+    let _ = app_handle.emit("startup-connections-complete", summary);
+    Ok(results)
+}
+
+/// Open the connection when the app starts. SSH profiles only.
+#[tauri::command]
+async fn set_connection_auto_connect(
+    state: State<'_, Arc<AppState>>,
+    id: String,
+    auto_connect: bool,
+) -> Result<(), String> {
+    state.connections.set_auto_connect(&id, auto_connect).map_err(|e| e.to_string())
+}
+
+/// Whether `run_startup_connections` opens anything
+#[tauri::command]
+async fn get_auto_connect_enabled(state: State<'_, Arc<AppState>>) -> Result<bool, String> {
+    state.connections.auto_connect_enabled().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_auto_connect_enabled(state: State<'_, Arc<AppState>>, enabled: bool) -> Result<(), String> {
+    state.connections.set_auto_connect_enabled(enabled).map_err(|e| e.to_string())
+}
+
+/// Error returned when a saved profile has no stored password and none was given
+const PASSWORD_REQUIRED: &str = "Password required";
+
+/// Count a failed connect to a saved profile. A certificate waiting for the
+/// user's decision isn't a failure.
+fn record_connect_failure(connection_id: &str, error: &ConnectError) {
+    if let ConnectError::Other(e) = error {
+        storage::usage::record_failure(connection_id, e);
+    }
+}
+
+#[tauri::command]
+async fn connect_saved_vnc(
+    state: State<'_, Arc<AppState>>,
+    app_handle: AppHandle,
+    vnc_manager: State<'_, VncManagerState>,
+    connection_id: String,
+    password: Option<String>,
+) -> Result<(String, u16, u16), ConnectError> {
+    let storage = &state.connections;
+    let profile = storage.get(&connection_id).map_err(|e| e.to_string())?;
+
+    let idle_timeout_secs = profile.idle_timeout_secs;
+    let ConnectionType::Vnc {
+        host,
+        port,
+        encoding,
+        quality,
+        color_depth,
+        auth_type,
+        username,
+    } = profile.connection_type
+    else {
+        return Err("Not a VNC connection profile".to_string().into());
+    };
+
+    let password = password.or_else(|| KeychainManager::get_password(&connection_id).ok());
+    if password.is_none() && auth_type.requires_password() {
+        return Err(PASSWORD_REQUIRED.to_string().into());
+    }
+
+    let session_id = uuid::Uuid::new_v4().to_string();
+    let credentials = vnc::VncCredentials {
+        auth_type,
+        username,
+        password,
+    };
+    let (width, height) = vnc_manager.create_session(
+        session_id.clone(),
+        &host,
+        port,
+        &credentials,
+        &certificate_policy(&host, port, false)?,
+        encoding,
+        quality.unwrap_or(vnc::VNC_DEFAULT_QUALITY),
+        color_depth,
+        None,
+    )
+    .inspect_err(|e| record_connect_failure(&connection_id, e))?;
+    vnc_manager.track_usage(&session_id, UsageTracker::start(&connection_id));
+    if let Some(secs) = idle_timeout_secs {
+        vnc_manager.set_idle_timeout(&session_id, std::time::Duration::from_secs(secs), app_handle.clone())?;
+    }
+    vnc_manager.start_frame_reader(&session_id, app_handle)?;
+
+    let _ = storage.update_last_used(&connection_id);
+
+    Ok((session_id, width, height))
+}
+
+/// Result of `connect_saved_rdp`
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum SavedRdpConnect {
+    Connected {
+        session_id: String,
+        width: u16,
+        height: u16,
+    },
+    /// Prompt-only profile and no password given; ask the user and call again
+    NeedsPassword {
+        connection_id: String,
+        host: String,
+        username: String,
+    },
+}
+
+#[tauri::command]
+async fn connect_saved_rdp(
+    state: State<'_, Arc<AppState>>,
+    app_handle: AppHandle,
+    rdp_manager: State<'_, RdpManagerState>,
+    connection_id: String,
+    password: Option<String>,
+    width: u16,
+    height: u16,
+    quality: Option<rdp::RdpQuality>,
+    keyboard: Option<rdp::RdpKeyboard>,
+    proxy: Option<ProxyConfig>,
+    software_cursor: Option<bool>,
+) -> Result<SavedRdpConnect, ConnectError> {
+    let storage = &state.connections;
+    let profile = storage.get(&connection_id).map_err(|e| e.to_string())?;
+
+    let idle_timeout_secs = profile.idle_timeout_secs;
+    let ConnectionType::Rdp {
+        host,
+        port,
+        username,
+        domain,
+        save_password,
+        quality: saved_quality,
+        keyboard: saved_keyboard,
+        accept_invalid_certs,
+        gateway,
+        auto_reconnect,
+        codec,
+        shared_folder,
+        custom_performance_flags,
+    } = profile.connection_type
+    else {
+        return Err("Not an RDP connection profile".to_string().into());
+    };
+
+    // Prompt-only profiles never look in the keychain
+    let password = match password {
+        Some(password) => password,
+        None if !save_password => {
+            return Ok(SavedRdpConnect::NeedsPassword {
+                connection_id,
+                host,
+                username,
+            });
+        }
+        None => KeychainManager::get_password(&connection_id)
+            .map_err(|_| PASSWORD_REQUIRED.to_string())?,
+    };
+    // Without a saved gateway password the RDP credentials are used
+    let gateway = gateway.map(|gateway| rdp::RdpGateway {
+        password: KeychainManager::get_password(&KeychainManager::gateway_entry(&connection_id)).ok(),
+        ..gateway
+    });
+
+    let session_id = uuid::Uuid::new_v4().to_string();
+    let certificates = certificate_policy(&host, port, accept_invalid_certs)?;
+    let (width, height) = rdp_manager.create_session(
+        session_id.clone(),
+        rdp::RdpConnectParams {
+            host,
+            port,
+            username,
+            password,
+            domain,
+            width,
+            height,
+            quality: quality.unwrap_or(saved_quality),
+            custom_performance_flags,
+            codec,
+            keyboard: keyboard.unwrap_or(saved_keyboard),
+            proxy,
+            pointer_software_rendering: software_cursor.unwrap_or(false),
+            certificates,
+            gateway,
+            connection_id: Some(connection_id.clone()),
+            // Profiles don't store smart cards; see rdp_connect
+            smart_card: None,
+            shared_folder,
+            timeout: rdp::DEFAULT_CONNECT_TIMEOUT,
+            max_frames_in_flight: frame::DEFAULT_MAX_FRAMES_IN_FLIGHT,
+        },
+        auto_reconnect,
+        None,
+        None,
+    )
+    .inspect_err(|e| record_connect_failure(&connection_id, e))?;
+    rdp_manager.track_usage(&session_id, UsageTracker::start(&connection_id));
+    if let Some(secs) = idle_timeout_secs {
+        rdp_manager.set_idle_timeout(&session_id, std::time::Duration::from_secs(secs), app_handle.clone())?;
+    }
+    rdp_manager.start_frame_reader(&session_id, app_handle)?;
+
+    let _ = storage.update_last_used(&connection_id);
+
+    Ok(SavedRdpConnect::Connected {
+        session_id,
+        width,
+        height,
+    })
+}
+
 // ============ SFTP Commands ============
 
 #[tauri::command]
@@ -372,30 +1408,82 @@ async fn sftp_open(
     state: State<'_, Arc<AppState>>,
     sftp_sessions: State<'_, SftpSessions>,
     session_id: String,
-) -> Result<String, String> {
+    share_connection: Option<bool>,
+    connection_id: Option<String>,
+) -> Result<FileBrowserOpened, String> {
     // Get the SSH connection info from the terminal session
     let conn_info = state
         .terminal_manager
         .get_ssh_connection_info(&session_id)
         .ok_or_else(|| "SSH session not found or not an SSH session".to_string())?;
 
-    // Create a NEW SSH connection specifically for SFTP to avoid mutex contention
-    // with the terminal's session (which is used by the output reader thread)
-    let sftp_client = ssh::SshClient::connect(
-        &conn_info.host,
-        conn_info.port,
-        &conn_info.username,
-        &conn_info.auth,
-    )
-    .map_err(|e| format!("Failed to create SFTP connection: {}", e))?;
+    // Open the sftp subsystem on the terminal's own connection, so hosts
+    // with one-time passwords don't need a second login. The terminal's
+    // channel and SFTP take turns on the session lock, which also guards
+    // the session's blocking mode.
+    let shared = share_connection
+        .unwrap_or(true)
+        .then(|| state.terminal_manager.get_ssh_client(&session_id))
+        .flatten()
+        .and_then(|client| match client.open_sftp() {
+            Ok(sftp_session) => Some(sftp_session),
+            Err(e) => {
+                eprintln!("SFTP: Can't share the terminal's connection ({}), opening a new one", e);
+                None
+            }
+        });
 
-    let sftp_session = sftp_client.open_sftp().map_err(|e| e.to_string())?;
-    let browser = SftpBrowser::new(sftp_session.sftp(), sftp_session.session());
+    let sftp_session = match shared {
+        Some(sftp_session) => sftp_session,
+        None => {
+            // A separate connection, so transfers never wait on the terminal
+            let sftp_client = ssh::SshClient::connect(
+                &conn_info.host,
+                conn_info.port,
+                &conn_info.username,
+                &conn_info.auth,
+                conn_info.proxy.as_ref(),
+                None,
+            )
+            .map_err(|e| format!("Failed to create SFTP connection: {}", e))?;
+            sftp_client.open_sftp().map_err(|e| e.to_string())?
+        }
+    };
+    let browser = SftpBrowser::new(sftp_session.sftp(), sftp_session.session())
+        .with_server(format!("{}@{}:{}", conn_info.username, conn_info.host, conn_info.port))
+        .with_counters(state.terminal_manager.session_counters(&session_id))
+        .with_home_dir();
+
+    let profile = connection_id.and_then(|id| state.connections.get(&id).ok());
+    let (remote_path, default_local_path) = profile
+        .map(|profile| (profile.default_remote_path, profile.default_local_path))
+        .unwrap_or_default();
+    if let Some(path) = remote_path {
+        if let Err(e) = browser.chdir(&path) {
+            eprintln!("SFTP: Can't open default folder {} ({}), staying in home", path, e);
+        }
+    }
 
-    let sftp_id = uuid::Uuid::new_v4().to_string();
-    sftp_sessions.lock().insert(sftp_id.clone(), browser);
+    let opened = FileBrowserOpened {
+        browser_id: uuid::Uuid::new_v4().to_string(),
+        current_path: browser.current_path(),
+        default_local_path,
+    };
+    sftp_sessions.lock().insert(opened.browser_id.clone(), browser);
 
-    Ok(sftp_id)
+    Ok(opened)
+}
+
+/// Result of `sftp_open` and `connect_saved_ftp`
+#[derive(Debug, Clone, serde::Serialize)]
+struct FileBrowserOpened {
+    /// `sftp_id` or `ftp_id` for the other commands
+    browser_id: String,
+    /// The profile's default remote folder, or home if it has none or
+    /// it's gone
+    current_path: String,
+    /// Where the local pane should open, if the profile says
+    default_local_path: Option<String>,
 }
 
 #[tauri::command]
@@ -409,13 +1497,92 @@ async fn sftp_list_dir(
     sftp_sessions: State<'_, SftpSessions>,
     sftp_id: String,
     path: String,
+    follow_links: Option<bool>,
+    filter: Option<String>,
+    filter_dirs: Option<bool>,
 ) -> Result<Vec<FileEntry>, String> {
+    let filter = util::NameFilter::new(filter.as_deref(), !filter_dirs.unwrap_or(false))?;
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+
+    let mut entries = browser
+        .list_dir(&path, follow_links.unwrap_or(false))
+        .map_err(|e| e.to_string())?;
+    if let Some(filter) = filter {
+        entries.retain(|entry| filter.keeps(&entry.name, entry.file_type == sftp::browser::FileType::Directory));
+    }
+    Ok(entries)
+}
+
+/// List a directory in batches for huge directories. Entries arrive via
+/// `sftp-listing-{sftp_id}`, followed by `sftp-listing-complete-{sftp_id}` or
+/// `sftp-listing-error-{sftp_id}`. Returns the listing ID carried by each event.
+#[tauri::command]
+async fn sftp_list_dir_stream(
+    app_handle: AppHandle,
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    path: String,
+    follow_links: Option<bool>,
+) -> Result<String, String> {
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+
+    // Own handle on the session so the sessions map isn't held while listing
+    let lister = SftpBrowser::new(browser.sftp.clone(), browser.session.clone());
+    let path = browser.resolve(&path);
+    let listing_id = uuid::Uuid::new_v4().to_string();
+    let id = listing_id.clone();
+
+    std::thread::spawn(move || {
+        let follow_links = follow_links.unwrap_or(false);
+        let result = lister.list_dir_batched(&path, sftp::LISTING_BATCH_SIZE, follow_links, |entries| {
+            let _ = app_handle.emit(
+                &format!("sftp-listing-{}", sftp_id),
+                sftp::ListingBatch {
+                    listing_id: id.clone(),
+                    path: path.clone(),
+                    entries,
+                },
+            );
+        });
+
+        match result {
+            Ok(total) => {
+                let _ = app_handle.emit(
+                    &format!("sftp-listing-complete-{}", sftp_id),
+                    sftp::ListingComplete {
+                        listing_id: id,
+                        path,
+                        total,
+                    },
+                );
+            }
+            Err(e) => {
+                let _ = app_handle.emit(
+                    &format!("sftp-listing-error-{}", sftp_id),
+                    (id, e.to_string()),
+                );
+            }
+        }
+    });
+
+    Ok(listing_id)
+}
+
+/// The user's remote home directory, as an absolute path
+#[tauri::command]
+async fn sftp_home_dir(sftp_sessions: State<'_, SftpSessions>, sftp_id: String) -> Result<String, String> {
     let sessions = sftp_sessions.lock();
     let browser = sessions
         .get(&sftp_id)
         .ok_or_else(|| "SFTP session not found".to_string())?;
 
-    browser.list_dir(&path).map_err(|e| e.to_string())
+    browser.home_dir().map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -431,6 +1598,21 @@ async fn sftp_get_current_path(
     Ok(browser.current_path())
 }
 
+/// Change the directory relative paths are resolved against; returns it
+#[tauri::command]
+async fn sftp_chdir(
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    path: String,
+) -> Result<String, String> {
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+
+    browser.chdir(&path).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn sftp_realpath(
     sftp_sessions: State<'_, SftpSessions>,
@@ -459,6 +1641,164 @@ async fn sftp_mkdir(
     browser.mkdir(&path).map_err(|e| e.to_string())
 }
 
+/// Create an empty file (e.g. the editor's "new file")
+#[tauri::command]
+async fn sftp_touch(
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    path: String,
+    overwrite: Option<bool>,
+) -> Result<(), String> {
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+
+    browser
+        .touch(&path, overwrite.unwrap_or(false))
+        .map_err(|e| e.to_string())
+}
+
+/// Change the permission bits of a single file or folder
+#[tauri::command]
+async fn sftp_chmod(
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    path: String,
+    mode: u32,
+) -> Result<(), String> {
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+
+    browser.chmod(&path, mode).map_err(|e| e.to_string())
+}
+
+/// Make a file executable (or not) for its owner, group and others,
+/// leaving its other permissions alone. Returns the new mode.
+#[tauri::command]
+async fn sftp_set_executable(
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    path: String,
+    executable: bool,
+) -> Result<u32, String> {
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+
+    browser.set_executable(&path, executable).map_err(|e| e.to_string())
+}
+
+/// Apply `dir_mode` to a folder and every folder below it and `file_mode` to
+/// the files. Runs in the background: progress arrives via
+/// `sftp-chmod-progress-{sftp_id}`, then `sftp-chmod-complete-{sftp_id}`
+/// (listing entries that couldn't be changed) or `sftp-chmod-error-{sftp_id}`.
+/// Returns the ID carried by each event.
+#[tauri::command]
+async fn sftp_chmod_recursive(
+    app_handle: AppHandle,
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    path: String,
+    file_mode: u32,
+    dir_mode: u32,
+) -> Result<String, String> {
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+
+    // Own handle on the session so the sessions map isn't held during the walk
+    let walker = SftpBrowser::new(browser.sftp.clone(), browser.session.clone());
+    let path = browser.resolve(&path);
+    let chmod_id = uuid::Uuid::new_v4().to_string();
+    let id = chmod_id.clone();
+
+    std::thread::spawn(move || {
+        let mut last_emit: Option<std::time::Instant> = None;
+        let result = walker.chmod_recursive(&path, file_mode, dir_mode, |summary| {
+            if last_emit.is_some_and(|last| last.elapsed() < progress::PROGRESS_INTERVAL) {
+                return;
+            }
+            last_emit = Some(std::time::Instant::now());
+            let _ = app_handle.emit(
+                &format!("sftp-chmod-progress-{}", sftp_id),
+                sftp::ChmodProgress {
+                    chmod_id: id.clone(),
+                    changed: summary.changed,
+                    failed: summary.failed.len(),
+                },
+            );
+        });
+
+        match result {
+            Ok(summary) => {
+                let _ = app_handle.emit(
+                    &format!("sftp-chmod-complete-{}", sftp_id),
+                    sftp::ChmodComplete {
+                        chmod_id: id,
+                        path,
+                        changed: summary.changed,
+                        failed: summary.failed,
+                    },
+                );
+            }
+            Err(e) => {
+                let _ = app_handle.emit(
+                    &format!("sftp-chmod-error-{}", sftp_id),
+                    (id, e.to_string()),
+                );
+            }
+        }
+    });
+
+    Ok(chmod_id)
+}
+
+/// Copy a file or folder to "name (copy)" next to it; returns the new path
+#[tauri::command]
+async fn sftp_duplicate(
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    path: String,
+) -> Result<String, String> {
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+
+    browser.duplicate(&path).map_err(|e| e.to_string())
+}
+
+/// Names of the files in the local templates folder
+#[tauri::command]
+async fn sftp_list_templates() -> Result<Vec<String>, String> {
+    sftp::templates::list_templates().map_err(|e| e.to_string())
+}
+
+/// Create `path` on the server from a local template. Fails if it exists.
+#[tauri::command]
+async fn sftp_new_from_template(
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    template: String,
+    path: String,
+) -> Result<(), String> {
+    let local_path = sftp::templates::template_path(&template).map_err(|e| e.to_string())?;
+
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+
+    browser
+        .create_from_file(&local_path, &path)
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn sftp_delete(
     sftp_sessions: State<'_, SftpSessions>,
@@ -478,35 +1818,99 @@ async fn sftp_delete(
     }
 }
 
+/// Delete several files and folders in one go, folders with their
+/// contents. Runs in the background: each item's result arrives as
+/// `sftp-delete-item-{sftp_id}` when it finishes, and a failure doesn't stop
+/// the rest; `sftp-delete-complete-{sftp_id}` then lists what was and wasn't
+/// deleted. Returns the ID carried by each event.
+#[tauri::command]
+async fn sftp_delete_many(
+    app_handle: AppHandle,
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    items: Vec<(String, bool)>,
+) -> Result<String, String> {
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+
+    // Own handle on the session so the sessions map isn't held meanwhile
+    let deleter = SftpBrowser::new(browser.sftp.clone(), browser.session.clone());
+    let items: Vec<(String, bool)> = items
+        .into_iter()
+        .map(|(path, is_dir)| (browser.resolve(&path), is_dir))
+        .collect();
+    let delete_id = uuid::Uuid::new_v4().to_string();
+    let id = delete_id.clone();
+
+    std::thread::spawn(move || {
+        let event = format!("sftp-delete-item-{}", sftp_id);
+        let summary = deleter.delete_many(&items, |path, error| {
+            let _ = app_handle.emit(
+                &event,
+                sftp::DeleteResult {
+                    delete_id: id.clone(),
+                    path: path.to_string(),
+                    error: error.map(str::to_string),
+                },
+            );
+        });
+        let _ = app_handle.emit(
+            &format!("sftp-delete-complete-{}", sftp_id),
+            sftp::DeleteComplete {
+                delete_id: id,
+                deleted: summary.deleted,
+                failed: summary.failed,
+            },
+        );
+    });
+
+    Ok(delete_id)
+}
+
 #[tauri::command]
 async fn sftp_rename(
     sftp_sessions: State<'_, SftpSessions>,
     sftp_id: String,
     old_path: String,
     new_path: String,
+    overwrite: Option<bool>,
 ) -> Result<(), String> {
     let sessions = sftp_sessions.lock();
     let browser = sessions
         .get(&sftp_id)
         .ok_or_else(|| "SFTP session not found".to_string())?;
 
-    browser
-        .rename(&old_path, &new_path)
-        .map_err(|e| e.to_string())
+    // With overwrite set this is a move-over-existing; otherwise an existing
+    // destination makes the rename fail
+    if overwrite.unwrap_or(false) {
+        browser
+            .rename_overwrite(&old_path, &new_path)
+            .map_err(|e| e.to_string())
+    } else {
+        browser
+            .rename(&old_path, &new_path)
+            .map_err(|e| e.to_string())
+    }
 }
 
 #[tauri::command]
 async fn sftp_download(
     app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
     sftp_sessions: State<'_, SftpSessions>,
     sftp_id: String,
     remote_path: String,
     local_path: String,
+    resume: Option<bool>,
+    preserve_metadata: Option<bool>,
 ) -> Result<TransferProgress, String> {
     let sessions = sftp_sessions.lock();
     let browser = sessions
         .get(&sftp_id)
         .ok_or_else(|| "SFTP session not found".to_string())?;
+    let remote_path = browser.resolve(&remote_path);
 
     let stat = browser.stat(&remote_path).map_err(|e| e.to_string())?;
     let filename = stat.name.clone();
@@ -515,23 +1919,113 @@ async fn sftp_download(
         filename,
         local_path.clone(),
         remote_path.clone(),
-        false,
-        stat.size,
+        false,
+        stat.size,
+    );
+
+    let preserve_metadata = preserve_metadata.unwrap_or(false);
+    let transfer = sftp::transfer::FileTransfer::new(browser.sftp.clone(), browser.session.clone())
+        .with_preserve_metadata(preserve_metadata)
+        .with_counters(browser.counters());
+    let transfer_id = progress.id.clone();
+    let transfers = state.transfers.clone();
+    transfers.register(&transfer_id, &sftp_id, transfer.cancel_flag());
+    let app = app_handle.clone();
+
+    progress.status = TransferStatus::InProgress;
+
+    let resume = resume.unwrap_or(false);
+    let mut journal = JournalTracker::start(resumable_transfer(&progress, browser, preserve_metadata));
+
+    std::thread::spawn(move || {
+        let mut throttle = ThrottledProgress::new(|transferred, total| {
+            let _ = app.emit(
+                &format!("transfer-progress-{}", transfer_id),
+                (transferred, total),
+            );
+        });
+        let result = transfer.download(&remote_path, &local_path, resume, |transferred, total| {
+            journal.update(transferred, total);
+            throttle.update(transferred, total)
+        });
+        throttle.finish();
+        journal.finish(result.is_ok());
+
+        match result {
+            Ok(_) => {
+                let _ = app.emit(&format!("transfer-complete-{}", transfer_id), true);
+            }
+            Err(e) => {
+                let _ = app.emit(&format!("transfer-error-{}", transfer_id), e.to_string());
+            }
+        }
+
+        transfers.finish(&transfer_id);
+    });
+
+    Ok(progress)
+}
+
+#[tauri::command]
+async fn sftp_upload(
+    app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    local_path: String,
+    remote_path: String,
+    preserve_metadata: Option<bool>,
+    create_parents: Option<bool>,
+    resume: Option<bool>,
+) -> Result<TransferProgress, String> {
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+    let remote_path = browser.resolve(&remote_path);
+
+    let metadata = std::fs::metadata(&local_path).map_err(|e| e.to_string())?;
+    let filename = std::path::Path::new(&local_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let mut progress = TransferProgress::new(
+        filename,
+        local_path.clone(),
+        remote_path.clone(),
+        true,
+        metadata.len(),
     );
 
-    let transfer = sftp::transfer::FileTransfer::new(browser.sftp.clone(), browser.session.clone());
+    let preserve_metadata = preserve_metadata.unwrap_or(false);
+    let transfer = sftp::transfer::FileTransfer::new(browser.sftp.clone(), browser.session.clone())
+        .with_preserve_metadata(preserve_metadata)
+        .with_counters(browser.counters())
+        .with_create_parents(create_parents.unwrap_or(false));
     let transfer_id = progress.id.clone();
+    let transfers = state.transfers.clone();
+    transfers.register(&transfer_id, &sftp_id, transfer.cancel_flag());
     let app = app_handle.clone();
 
     progress.status = TransferStatus::InProgress;
 
+    let resume = resume.unwrap_or(false);
+    let mut journal = JournalTracker::start(resumable_transfer(&progress, browser, preserve_metadata));
+
     std::thread::spawn(move || {
-        let result = transfer.download(&remote_path, &local_path, |transferred, total| {
+        let mut throttle = ThrottledProgress::new(|transferred, total| {
             let _ = app.emit(
                 &format!("transfer-progress-{}", transfer_id),
                 (transferred, total),
             );
         });
+        let result = transfer.upload(&local_path, &remote_path, resume, |transferred, total| {
+            journal.update(transferred, total);
+            throttle.update(transferred, total)
+        });
+        throttle.finish();
+        journal.finish(result.is_ok());
 
         match result {
             Ok(_) => {
@@ -541,25 +2035,36 @@ async fn sftp_download(
                 let _ = app.emit(&format!("transfer-error-{}", transfer_id), e.to_string());
             }
         }
+
+        transfers.finish(&transfer_id);
     });
 
     Ok(progress)
 }
 
+/// Upload a local file and delete it once the remote copy is verified.
+/// The local file is kept if the upload fails, is cancelled or doesn't
+/// verify.
 #[tauri::command]
-async fn sftp_upload(
+async fn sftp_move_from_local(
     app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
     sftp_sessions: State<'_, SftpSessions>,
     sftp_id: String,
     local_path: String,
     remote_path: String,
+    create_parents: Option<bool>,
 ) -> Result<TransferProgress, String> {
     let sessions = sftp_sessions.lock();
     let browser = sessions
         .get(&sftp_id)
         .ok_or_else(|| "SFTP session not found".to_string())?;
+    let remote_path = browser.resolve(&remote_path);
 
     let metadata = std::fs::metadata(&local_path).map_err(|e| e.to_string())?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a file", local_path));
+    }
     let filename = std::path::Path::new(&local_path)
         .file_name()
         .map(|n| n.to_string_lossy().to_string())
@@ -573,58 +2078,189 @@ async fn sftp_upload(
         metadata.len(),
     );
 
-    let transfer = sftp::transfer::FileTransfer::new(browser.sftp.clone(), browser.session.clone());
+    let transfer = sftp::transfer::FileTransfer::new(browser.sftp.clone(), browser.session.clone())
+        .with_counters(browser.counters())
+        .with_create_parents(create_parents.unwrap_or(false));
     let transfer_id = progress.id.clone();
+    let transfers = state.transfers.clone();
+    let cancelled = transfer.cancel_flag();
+    transfers.register(&transfer_id, &sftp_id, cancelled.clone());
     let app = app_handle.clone();
 
     progress.status = TransferStatus::InProgress;
 
     std::thread::spawn(move || {
-        let result = transfer.upload(&local_path, &remote_path, |transferred, total| {
+        let mut throttle = ThrottledProgress::new(|transferred, total| {
             let _ = app.emit(
                 &format!("transfer-progress-{}", transfer_id),
                 (transferred, total),
             );
         });
-
+        let result = transfer.upload(&local_path, &remote_path, false, |transferred, total| {
+            throttle.update(transferred, total)
+        });
+        throttle.finish();
+
+        let result = result
+            .and_then(|_| transfer.verify_upload(&local_path, &remote_path))
+            .and_then(|_| {
+                if *cancelled.lock() {
+                    Err(sftp::transfer::TransferError::Cancelled)
+                } else {
+                    Ok(())
+                }
+            });
         match result {
-            Ok(_) => {
-                let _ = app.emit(&format!("transfer-complete-{}", transfer_id), true);
-            }
+            Ok(_) => match std::fs::remove_file(&local_path) {
+                Ok(_) => {
+                    let _ = app.emit(&format!("transfer-complete-{}", transfer_id), true);
+                }
+                Err(e) => {
+                    let _ = app.emit(
+                        &format!("transfer-error-{}", transfer_id),
+                        format!("Uploaded, but the local file couldn't be deleted: {}", e),
+                    );
+                }
+            },
             Err(e) => {
-                let _ = app.emit(&format!("transfer-error-{}", transfer_id), e.to_string());
+                let _ = app.emit(
+                    &format!("transfer-error-{}", transfer_id),
+                    format!("{}; the local file was kept", e),
+                );
             }
         }
+
+        transfers.finish(&transfer_id);
     });
 
     Ok(progress)
 }
 
+/// Journal entry for a single-file SFTP transfer about to start
+fn resumable_transfer(
+    progress: &TransferProgress,
+    browser: &SftpBrowser,
+    preserve_metadata: bool,
+) -> ResumableTransfer {
+    ResumableTransfer {
+        id: progress.id.clone(),
+        is_upload: progress.is_upload,
+        local_path: progress.local_path.clone(),
+        remote_path: progress.remote_path.clone(),
+        server: browser.server().map(|server| server.to_string()),
+        total_bytes: progress.total_bytes,
+        transferred_bytes: 0,
+        preserve_metadata,
+        updated_at: chrono::Utc::now(),
+    }
+}
+
+/// SFTP downloads and uploads that stopped before finishing, e.g. because
+/// the app was closed or crashed, most recent first. Running transfers are
+/// left out.
+#[tauri::command]
+async fn list_resumable_transfers(state: State<'_, Arc<AppState>>) -> Result<Vec<ResumableTransfer>, String> {
+    let journal = TransferJournal::new().map_err(|e| e.to_string())?;
+    let transfers = journal.list().map_err(|e| e.to_string())?;
+    Ok(transfers
+        .into_iter()
+        .filter(|transfer| !state.transfers.is_active(&transfer.id))
+        .collect())
+}
+
+/// Continue a transfer from `list_resumable_transfers` on `sftp_id`, which
+/// must be connected to the same server. Downloads continue from their
+/// `.part` file and uploads from the remote file's length, so nothing
+/// written after the last journal update is sent again. The transfer gets
+/// a new id and journal entry.
+#[tauri::command]
+async fn resume_transfer(
+    app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    transfer_id: String,
+) -> Result<TransferProgress, String> {
+    if state.transfers.is_active(&transfer_id) {
+        return Err("The transfer is still running".to_string());
+    }
+    let journal = TransferJournal::new().map_err(|e| e.to_string())?;
+    let entry = journal.get(&transfer_id).map_err(|e| e.to_string())?;
+
+    // Appending to a same-named file on another host would corrupt it
+    let server = sftp_sessions
+        .lock()
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?
+        .server()
+        .map(str::to_string);
+    let Some(expected) = &entry.server else {
+        return Err("The transfer didn't record its server, so it can't be resumed safely".to_string());
+    };
+    if server.as_ref() != Some(expected) {
+        return Err(format!("The transfer was to {}; connect to that server to resume it", expected));
+    }
+
+    let progress = if entry.is_upload {
+        sftp_upload(
+            app_handle,
+            state,
+            sftp_sessions,
+            sftp_id,
+            entry.local_path,
+            entry.remote_path,
+            Some(entry.preserve_metadata),
+            None,
+            Some(true),
+        )
+        .await?
+    } else {
+        sftp_download(
+            app_handle,
+            state,
+            sftp_sessions,
+            sftp_id,
+            entry.remote_path,
+            entry.local_path,
+            Some(true),
+            Some(entry.preserve_metadata),
+        )
+        .await?
+    };
+    journal.remove(&transfer_id).map_err(|e| e.to_string())?;
+    Ok(progress)
+}
+
+/// Forget an interrupted transfer. A download's `.part` file is deleted.
+#[tauri::command]
+async fn discard_resumable_transfer(transfer_id: String) -> Result<(), String> {
+    let journal = TransferJournal::new().map_err(|e| e.to_string())?;
+    let entry = journal.get(&transfer_id).map_err(|e| e.to_string())?;
+    if !entry.is_upload {
+        let _ = std::fs::remove_file(sftp::transfer::part_path(&entry.local_path));
+    }
+    journal.remove(&transfer_id).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn sftp_upload_folder(
     app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
     sftp_sessions: State<'_, SftpSessions>,
     sftp_id: String,
     local_path: String,
     remote_path: String,
+    preserve_metadata: Option<bool>,
+    create_parents: Option<bool>,
 ) -> Result<TransferProgress, String> {
     let sessions = sftp_sessions.lock();
     let browser = sessions
         .get(&sftp_id)
         .ok_or_else(|| "SFTP session not found".to_string())?;
+    let remote_path = browser.resolve(&remote_path);
 
     // Calculate folder size for progress
-    let mut total_size: u64 = 0;
-    for entry in walkdir::WalkDir::new(&local_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        if entry.file_type().is_file() {
-            if let Ok(metadata) = entry.metadata() {
-                total_size += metadata.len();
-            }
-        }
-    }
+    let total_size = sftp::transfer::local_tree_size(&local_path);
 
     let folder_name = std::path::Path::new(&local_path)
         .file_name()
@@ -639,19 +2275,114 @@ async fn sftp_upload_folder(
         total_size,
     );
 
-    let transfer = sftp::transfer::FileTransfer::new(browser.sftp.clone(), browser.session.clone());
+    let transfer = sftp::transfer::FileTransfer::new(browser.sftp.clone(), browser.session.clone())
+        .with_preserve_metadata(preserve_metadata.unwrap_or(false))
+        .with_counters(browser.counters())
+        .with_create_parents(create_parents.unwrap_or(false));
+    let transfer_id = progress.id.clone();
+    let transfers = state.transfers.clone();
+    transfers.register(&transfer_id, &sftp_id, transfer.cancel_flag());
+    let app = app_handle.clone();
+
+    progress.status = TransferStatus::InProgress;
+    let total_files = sftp::transfer::local_file_count(&local_path);
+
+    std::thread::spawn(move || {
+        let group = std::cell::RefCell::new(TransferGroup::new(transfer_id.clone(), total_files, total_size));
+        let mut throttle = ThrottledProgress::new(|transferred, total| {
+            let _ = app.emit(
+                &format!("transfer-progress-{}", transfer_id),
+                (transferred, total),
+            );
+            let _ = app.emit(&format!("transfer-group-{}", transfer_id), &*group.borrow());
+        });
+        let result = transfer.upload_folder(&local_path, &remote_path, |transferred, total, filename| {
+            group.borrow_mut().update(transferred, filename);
+            throttle.update(transferred, total)
+        });
+        throttle.finish();
+
+        match result {
+            Ok(_) => {
+                group.borrow_mut().finish();
+                let _ = app.emit(&format!("transfer-group-{}", transfer_id), &*group.borrow());
+                let _ = app.emit(&format!("transfer-complete-{}", transfer_id), true);
+            }
+            Err(e) => {
+                let _ = app.emit(&format!("transfer-error-{}", transfer_id), e.to_string());
+            }
+        }
+
+        transfers.finish(&transfer_id);
+    });
+
+    Ok(progress)
+}
+
+/// Upload several dropped files and folders into `remote_dir` as one transfer
+#[tauri::command]
+async fn sftp_upload_paths(
+    app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    local_paths: Vec<String>,
+    remote_dir: String,
+    preserve_metadata: Option<bool>,
+) -> Result<TransferProgress, String> {
+    if local_paths.is_empty() {
+        return Err("No paths to upload".to_string());
+    }
+
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+    let remote_dir = browser.resolve(&remote_dir);
+
+    let total_size: u64 = local_paths
+        .iter()
+        .map(|p| sftp::transfer::local_tree_size(p))
+        .sum();
+
+    let filename = if local_paths.len() == 1 {
+        std::path::Path::new(&local_paths[0])
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| local_paths[0].clone())
+    } else {
+        format!("{} items", local_paths.len())
+    };
+
+    let mut progress = TransferProgress::new(
+        filename,
+        local_paths.join(", "),
+        remote_dir.clone(),
+        true,
+        total_size,
+    );
+
+    let transfer = sftp::transfer::FileTransfer::new(browser.sftp.clone(), browser.session.clone())
+        .with_preserve_metadata(preserve_metadata.unwrap_or(false))
+        .with_counters(browser.counters());
     let transfer_id = progress.id.clone();
+    let transfers = state.transfers.clone();
+    transfers.register(&transfer_id, &sftp_id, transfer.cancel_flag());
     let app = app_handle.clone();
 
     progress.status = TransferStatus::InProgress;
 
     std::thread::spawn(move || {
-        let result = transfer.upload_folder(&local_path, &remote_path, |transferred, total, _filename| {
+        let mut throttle = ThrottledProgress::new(|transferred, total| {
             let _ = app.emit(
                 &format!("transfer-progress-{}", transfer_id),
                 (transferred, total),
             );
         });
+        let result = transfer.upload_paths(&local_paths, &remote_dir, |transferred, total, _filename| {
+            throttle.update(transferred, total)
+        });
+        throttle.finish();
 
         match result {
             Ok(_) => {
@@ -661,6 +2392,8 @@ async fn sftp_upload_folder(
                 let _ = app.emit(&format!("transfer-error-{}", transfer_id), e.to_string());
             }
         }
+
+        transfers.finish(&transfer_id);
     });
 
     Ok(progress)
@@ -675,6 +2408,9 @@ async fn ftp_connect(
     port: u16,
     username: Option<String>,
     password: Option<String>,
+    keepalive: Option<bool>,
+    keepalive_interval_secs: Option<u64>,
+    proxy: Option<ProxyConfig>,
 ) -> Result<String, String> {
     let auth = match (username, password) {
         (Some(user), Some(pwd)) => FtpAuthMethod::Password {
@@ -683,25 +2419,103 @@ async fn ftp_connect(
         },
         _ => FtpAuthMethod::Anonymous,
     };
-
-    let client = FtpClient::connect(&host, port, &auth)
-        .map_err(|e| format!("FTP connection failed: {}", e))?;
-
-    let browser = FtpBrowser::new(client.stream());
+    let browser = open_ftp(&host, port, &auth, keepalive, keepalive_interval_secs, proxy.as_ref())?;
 
     let ftp_id = uuid::Uuid::new_v4().to_string();
     ftp_sessions.lock().insert(ftp_id.clone(), browser);
 
+    Ok(ftp_id)
+}
+
+/// Connect to an FTP server for browsing, with keepalive on unless turned off
+fn open_ftp(
+    host: &str,
+    port: u16,
+    auth: &FtpAuthMethod,
+    keepalive: Option<bool>,
+    keepalive_interval_secs: Option<u64>,
+    proxy: Option<&ProxyConfig>,
+) -> Result<FtpBrowser, String> {
+    let client = FtpClient::connect(host, port, auth, proxy).map_err(|e| format!("FTP connection failed: {}", e))?;
+
+    let mut browser = FtpBrowser::new(client.stream());
+    if keepalive.unwrap_or(true) {
+        let interval = keepalive_interval_secs
+            .unwrap_or(ftp::keepalive::DEFAULT_KEEPALIVE_SECS)
+            .max(1);
+        browser = browser.with_keepalive(std::time::Duration::from_secs(interval));
+    }
+
     // Don't drop client - we need to keep the connection alive
     std::mem::forget(client);
 
-    Ok(ftp_id)
+    Ok(browser)
+}
+
+/// Connect to a saved FTP profile, with the password from the keychain
+/// unless given, and start in its default remote folder if it has one
+#[tauri::command]
+async fn connect_saved_ftp(
+    state: State<'_, Arc<AppState>>,
+    ftp_sessions: State<'_, FtpSessions>,
+    connection_id: String,
+    password: Option<String>,
+    keepalive: Option<bool>,
+    keepalive_interval_secs: Option<u64>,
+    proxy: Option<ProxyConfig>,
+) -> Result<FileBrowserOpened, String> {
+    let profile = state.connections.get(&connection_id).map_err(|e| e.to_string())?;
+    let ConnectionType::Ftp {
+        host,
+        port,
+        username,
+        anonymous,
+    } = &profile.connection_type
+    else {
+        return Err("Not an FTP connection profile".to_string());
+    };
+
+    let auth = match username {
+        Some(username) if !anonymous => {
+            let password = password
+                .or_else(|| KeychainManager::get_password(&connection_id).ok())
+                .ok_or_else(|| PASSWORD_REQUIRED.to_string())?;
+            FtpAuthMethod::Password {
+                username: username.clone(),
+                password,
+            }
+        }
+        _ => FtpAuthMethod::Anonymous,
+    };
+    let browser = open_ftp(host, *port, &auth, keepalive, keepalive_interval_secs, proxy.as_ref())
+        .inspect_err(|e| storage::usage::record_failure(&connection_id, e))?;
+    browser.track_usage(UsageTracker::start(&connection_id));
+
+    let home = browser.current_path();
+    let current_path = match &profile.default_remote_path {
+        Some(path) => browser.chdir(path).unwrap_or_else(|e| {
+            eprintln!("FTP: Can't open default folder {} ({}), staying in {}", path, e, home);
+            home
+        }),
+        None => home,
+    };
+    let _ = state.connections.update_last_used(&connection_id);
+
+    let opened = FileBrowserOpened {
+        browser_id: uuid::Uuid::new_v4().to_string(),
+        current_path,
+        default_local_path: profile.default_local_path.clone(),
+    };
+    ftp_sessions.lock().insert(opened.browser_id.clone(), browser);
+
+    Ok(opened)
 }
 
 #[tauri::command]
 async fn ftp_disconnect(ftp_sessions: State<'_, FtpSessions>, ftp_id: String) -> Result<(), String> {
     let mut sessions = ftp_sessions.lock();
     if let Some(browser) = sessions.remove(&ftp_id) {
+        browser.finish_usage();
         // Try to quit gracefully
         let stream = browser.stream();
         let mut stream_guard = stream.lock();
@@ -715,13 +2529,20 @@ async fn ftp_list_dir(
     ftp_sessions: State<'_, FtpSessions>,
     ftp_id: String,
     path: String,
+    filter: Option<String>,
+    filter_dirs: Option<bool>,
 ) -> Result<Vec<ftp::FileEntry>, String> {
+    let filter = util::NameFilter::new(filter.as_deref(), !filter_dirs.unwrap_or(false))?;
     let sessions = ftp_sessions.lock();
     let browser = sessions
         .get(&ftp_id)
         .ok_or_else(|| "FTP session not found".to_string())?;
 
-    browser.list_dir(&path).map_err(|e| e.to_string())
+    let mut entries = browser.list_dir(&path).map_err(|e| e.to_string())?;
+    if let Some(filter) = filter {
+        entries.retain(|entry| filter.keeps(&entry.name, entry.file_type == ftp::FileType::Directory));
+    }
+    Ok(entries)
 }
 
 #[tauri::command]
@@ -734,6 +2555,21 @@ async fn ftp_pwd(ftp_sessions: State<'_, FtpSessions>, ftp_id: String) -> Result
     browser.pwd().map_err(|e| e.to_string())
 }
 
+/// Change the directory relative paths are resolved against; returns it
+#[tauri::command]
+async fn ftp_chdir(
+    ftp_sessions: State<'_, FtpSessions>,
+    ftp_id: String,
+    path: String,
+) -> Result<String, String> {
+    let sessions = ftp_sessions.lock();
+    let browser = sessions
+        .get(&ftp_id)
+        .ok_or_else(|| "FTP session not found".to_string())?;
+
+    browser.chdir(&path).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn ftp_mkdir(
     ftp_sessions: State<'_, FtpSessions>,
@@ -748,6 +2584,24 @@ async fn ftp_mkdir(
     browser.mkdir(&path).map_err(|e| e.to_string())
 }
 
+/// Create an empty file (e.g. the editor's "new file")
+#[tauri::command]
+async fn ftp_touch(
+    ftp_sessions: State<'_, FtpSessions>,
+    ftp_id: String,
+    path: String,
+    overwrite: Option<bool>,
+) -> Result<(), String> {
+    let sessions = ftp_sessions.lock();
+    let browser = sessions
+        .get(&ftp_id)
+        .ok_or_else(|| "FTP session not found".to_string())?;
+
+    browser
+        .touch(&path, overwrite.unwrap_or(false))
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn ftp_delete(
     ftp_sessions: State<'_, FtpSessions>,
@@ -785,10 +2639,12 @@ async fn ftp_rename(
 #[tauri::command]
 async fn ftp_download(
     app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
     ftp_sessions: State<'_, FtpSessions>,
     ftp_id: String,
     remote_path: String,
     local_path: String,
+    mode: Option<ftp::FtpTransferMode>,
 ) -> Result<ftp::TransferProgress, String> {
     let sessions = ftp_sessions.lock();
     let browser = sessions
@@ -809,19 +2665,25 @@ async fn ftp_download(
         size,
     );
 
-    let transfer = ftp::FtpTransfer::new(browser.stream());
+    let transfer = ftp::FtpTransfer::new(browser.stream()).with_mode(mode.unwrap_or_default());
     let transfer_id = progress.id.clone();
+    let transfers = state.transfers.clone();
+    transfers.register(&transfer_id, &ftp_id, transfer.cancel_flag());
     let app = app_handle.clone();
 
     progress.status = ftp::TransferStatus::InProgress;
 
     std::thread::spawn(move || {
-        let result = transfer.download(&remote_path, &local_path, |transferred, total| {
+        let mut throttle = ThrottledProgress::new(|transferred, total| {
             let _ = app.emit(
                 &format!("ftp-transfer-progress-{}", transfer_id),
                 (transferred, total),
             );
         });
+        let result = transfer.download(&remote_path, &local_path, |transferred, total| {
+            throttle.update(transferred, total)
+        });
+        throttle.finish();
 
         match result {
             Ok(_) => {
@@ -831,6 +2693,8 @@ async fn ftp_download(
                 let _ = app.emit(&format!("ftp-transfer-error-{}", transfer_id), e.to_string());
             }
         }
+
+        transfers.finish(&transfer_id);
     });
 
     Ok(progress)
@@ -839,10 +2703,13 @@ async fn ftp_download(
 #[tauri::command]
 async fn ftp_upload(
     app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
     ftp_sessions: State<'_, FtpSessions>,
     ftp_id: String,
     local_path: String,
     remote_path: String,
+    create_parents: Option<bool>,
+    mode: Option<ftp::FtpTransferMode>,
 ) -> Result<ftp::TransferProgress, String> {
     let sessions = ftp_sessions.lock();
     let browser = sessions
@@ -863,19 +2730,27 @@ async fn ftp_upload(
         metadata.len(),
     );
 
-    let transfer = ftp::FtpTransfer::new(browser.stream());
+    let transfer = ftp::FtpTransfer::new(browser.stream())
+        .with_create_parents(create_parents.unwrap_or(false))
+        .with_mode(mode.unwrap_or_default());
     let transfer_id = progress.id.clone();
+    let transfers = state.transfers.clone();
+    transfers.register(&transfer_id, &ftp_id, transfer.cancel_flag());
     let app = app_handle.clone();
 
     progress.status = ftp::TransferStatus::InProgress;
 
     std::thread::spawn(move || {
-        let result = transfer.upload(&local_path, &remote_path, |transferred, total| {
+        let mut throttle = ThrottledProgress::new(|transferred, total| {
             let _ = app.emit(
                 &format!("ftp-transfer-progress-{}", transfer_id),
                 (transferred, total),
             );
         });
+        let result = transfer.upload(&local_path, &remote_path, |transferred, total| {
+            throttle.update(transferred, total)
+        });
+        throttle.finish();
 
         match result {
             Ok(_) => {
@@ -885,36 +2760,124 @@ async fn ftp_upload(
                 let _ = app.emit(&format!("ftp-transfer-error-{}", transfer_id), e.to_string());
             }
         }
+
+        transfers.finish(&transfer_id);
     });
 
     Ok(progress)
 }
 
+/// Upload a local file in binary mode and delete it once the remote size
+/// matches. The local file is kept if the upload fails, is cancelled or
+/// doesn't verify.
 #[tauri::command]
-async fn ftp_upload_folder(
+async fn ftp_move_from_local(
     app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
     ftp_sessions: State<'_, FtpSessions>,
     ftp_id: String,
     local_path: String,
     remote_path: String,
+    create_parents: Option<bool>,
 ) -> Result<ftp::TransferProgress, String> {
     let sessions = ftp_sessions.lock();
     let browser = sessions
         .get(&ftp_id)
         .ok_or_else(|| "FTP session not found".to_string())?;
 
-    // Calculate folder size for progress
-    let mut total_size: u64 = 0;
-    for entry in walkdir::WalkDir::new(&local_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        if entry.file_type().is_file() {
-            if let Ok(metadata) = entry.metadata() {
-                total_size += metadata.len();
+    let metadata = std::fs::metadata(&local_path).map_err(|e| e.to_string())?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a file", local_path));
+    }
+    let filename = std::path::Path::new(&local_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let mut progress = ftp::TransferProgress::new(
+        filename,
+        local_path.clone(),
+        remote_path.clone(),
+        true,
+        metadata.len(),
+    );
+
+    // ASCII mode rewrites line endings, so sizes couldn't be compared
+    let transfer = ftp::FtpTransfer::new(browser.stream())
+        .with_create_parents(create_parents.unwrap_or(false))
+        .with_mode(ftp::FtpTransferMode::Binary);
+    let transfer_id = progress.id.clone();
+    let transfers = state.transfers.clone();
+    let cancelled = transfer.cancel_flag();
+    transfers.register(&transfer_id, &ftp_id, cancelled.clone());
+    let app = app_handle.clone();
+
+    progress.status = ftp::TransferStatus::InProgress;
+
+    std::thread::spawn(move || {
+        let mut throttle = ThrottledProgress::new(|transferred, total| {
+            let _ = app.emit(
+                &format!("ftp-transfer-progress-{}", transfer_id),
+                (transferred, total),
+            );
+        });
+        let result = transfer.upload(&local_path, &remote_path, |transferred, total| {
+            throttle.update(transferred, total)
+        });
+        throttle.finish();
+
+        let result = result
+            .and_then(|_| transfer.verify_upload(&local_path, &remote_path))
+            .and_then(|_| {
+                if *cancelled.lock() {
+                    Err(ftp::transfer::FtpTransferError::Cancelled)
+                } else {
+                    Ok(())
+                }
+            });
+        match result {
+            Ok(_) => match std::fs::remove_file(&local_path) {
+                Ok(_) => {
+                    let _ = app.emit(&format!("ftp-transfer-complete-{}", transfer_id), true);
+                }
+                Err(e) => {
+                    let _ = app.emit(
+                        &format!("ftp-transfer-error-{}", transfer_id),
+                        format!("Uploaded, but the local file couldn't be deleted: {}", e),
+                    );
+                }
+            },
+            Err(e) => {
+                let _ = app.emit(
+                    &format!("ftp-transfer-error-{}", transfer_id),
+                    format!("{}; the local file was kept", e),
+                );
             }
         }
-    }
+
+        transfers.finish(&transfer_id);
+    });
+
+    Ok(progress)
+}
+
+#[tauri::command]
+async fn ftp_upload_folder(
+    app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
+    ftp_sessions: State<'_, FtpSessions>,
+    ftp_id: String,
+    local_path: String,
+    remote_path: String,
+    create_parents: Option<bool>,
+) -> Result<ftp::TransferProgress, String> {
+    let sessions = ftp_sessions.lock();
+    let browser = sessions
+        .get(&ftp_id)
+        .ok_or_else(|| "FTP session not found".to_string())?;
+
+    // Calculate folder size for progress
+    let total_size = sftp::transfer::local_tree_size(&local_path);
 
     let folder_name = std::path::Path::new(&local_path)
         .file_name()
@@ -929,28 +2892,43 @@ async fn ftp_upload_folder(
         total_size,
     );
 
-    let transfer = ftp::FtpTransfer::new(browser.stream());
+    let transfer = ftp::FtpTransfer::new(browser.stream())
+        .with_create_parents(create_parents.unwrap_or(false));
     let transfer_id = progress.id.clone();
+    let transfers = state.transfers.clone();
+    transfers.register(&transfer_id, &ftp_id, transfer.cancel_flag());
     let app = app_handle.clone();
 
     progress.status = ftp::TransferStatus::InProgress;
+    let total_files = sftp::transfer::local_file_count(&local_path);
 
     std::thread::spawn(move || {
-        let result = transfer.upload_folder(&local_path, &remote_path, |transferred, total, _filename| {
+        let group = std::cell::RefCell::new(TransferGroup::new(transfer_id.clone(), total_files, total_size));
+        let mut throttle = ThrottledProgress::new(|transferred, total| {
             let _ = app.emit(
                 &format!("ftp-transfer-progress-{}", transfer_id),
                 (transferred, total),
             );
+            let _ = app.emit(&format!("ftp-transfer-group-{}", transfer_id), &*group.borrow());
         });
+        let result = transfer.upload_folder(&local_path, &remote_path, |transferred, total, filename| {
+            group.borrow_mut().update(transferred, filename);
+            throttle.update(transferred, total)
+        });
+        throttle.finish();
 
         match result {
             Ok(_) => {
+                group.borrow_mut().finish();
+                let _ = app.emit(&format!("ftp-transfer-group-{}", transfer_id), &*group.borrow());
                 let _ = app.emit(&format!("ftp-transfer-complete-{}", transfer_id), true);
             }
             Err(e) => {
                 let _ = app.emit(&format!("ftp-transfer-error-{}", transfer_id), e.to_string());
             }
         }
+
+        transfers.finish(&transfer_id);
     });
 
     Ok(progress)
@@ -978,6 +2956,7 @@ async fn sftp_read_file(
     let browser = sessions
         .get(&sftp_id)
         .ok_or_else(|| "SFTP session not found".to_string())?;
+    let remote_path = browser.resolve(&remote_path);
 
     // Set blocking mode for the operation (session is normally non-blocking)
     let session = browser.session.lock();
@@ -997,22 +2976,80 @@ async fn sftp_read_file(
         .map_err(|e| format!("Failed to read remote file: {}", e));
 
     session.set_blocking(false);
-    result?;
-
-    Ok(contents)
+    result?;
+
+    Ok(contents)
+}
+
+/// Open a remote file for editing, detecting its encoding and line endings.
+/// Binary files come back without content.
+#[tauri::command]
+async fn sftp_edit_open(
+    sftp_sessions: State<'_, SftpSessions>,
+    sftp_id: String,
+    remote_path: String,
+) -> Result<sftp::encoding::EditorFile, String> {
+    let sessions = sftp_sessions.lock();
+    let browser = sessions
+        .get(&sftp_id)
+        .ok_or_else(|| "SFTP session not found".to_string())?;
+    let remote_path = browser.resolve(&remote_path);
+
+    // Set blocking mode for the operation (session is normally non-blocking)
+    let session = browser.session.lock();
+    session.set_blocking(true);
+
+    let sftp = browser.sftp.lock();
+    let mut file = sftp
+        .open(std::path::Path::new(&remote_path))
+        .map_err(|e| {
+            session.set_blocking(false);
+            format!("Failed to open remote file: {}", e)
+        })?;
+
+    // Only the start is needed to tell a binary file apart
+    use std::io::Read;
+    let mut bytes = Vec::new();
+    let result = (&mut file)
+        .take(sftp::encoding::SNIFF_LEN as u64)
+        .read_to_end(&mut bytes)
+        .and_then(|_| {
+            let mut format = sftp::encoding::detect(&bytes);
+            if format.binary {
+                return Ok(sftp::encoding::EditorFile { content: None, format });
+            }
+            file.read_to_end(&mut bytes)?;
+            let content = sftp::encoding::decode(&bytes, &mut format);
+            Ok(sftp::encoding::EditorFile {
+                content: Some(content),
+                format,
+            })
+        })
+        .map_err(|e| format!("Failed to read remote file: {}", e));
+
+    session.set_blocking(false);
+    result
 }
 
+/// Write a remote file. With `format` (from `sftp_edit_open`) the text is
+/// saved in the file's original encoding and line endings.
 #[tauri::command]
 async fn sftp_write_file(
     sftp_sessions: State<'_, SftpSessions>,
     sftp_id: String,
     remote_path: String,
     content: String,
+    format: Option<sftp::encoding::FileFormat>,
 ) -> Result<(), String> {
     let sessions = sftp_sessions.lock();
     let browser = sessions
         .get(&sftp_id)
         .ok_or_else(|| "SFTP session not found".to_string())?;
+    let remote_path = browser.resolve(&remote_path);
+    let bytes = match &format {
+        Some(format) => sftp::encoding::encode(&content, format)?,
+        None => content.into_bytes(),
+    };
 
     // Set blocking mode for the operation (session is normally non-blocking)
     let session = browser.session.lock();
@@ -1027,7 +3064,7 @@ async fn sftp_write_file(
         })?;
 
     use std::io::Write;
-    let result = file.write_all(content.as_bytes())
+    let result = file.write_all(&bytes)
         .map_err(|e| format!("Failed to write remote file: {}", e));
 
     session.set_blocking(false);
@@ -1081,8 +3118,17 @@ async fn ftp_write_file(
 // ============ Local File System Commands ============
 
 #[tauri::command]
-async fn local_list_dir(path: String) -> Result<Vec<local::browser::FileEntry>, String> {
-    local::browser::list_directory(&path).map_err(|e| e.to_string())
+async fn local_list_dir(
+    path: String,
+    filter: Option<String>,
+    filter_dirs: Option<bool>,
+) -> Result<Vec<local::browser::FileEntry>, String> {
+    let filter = util::NameFilter::new(filter.as_deref(), !filter_dirs.unwrap_or(false))?;
+    let mut entries = local::browser::list_directory(&path).map_err(|e| e.to_string())?;
+    if let Some(filter) = filter {
+        entries.retain(|entry| filter.keeps(&entry.name, entry.file_type == local::browser::FileType::Directory));
+    }
+    Ok(entries)
 }
 
 #[tauri::command]
@@ -1102,6 +3148,17 @@ async fn local_get_downloads_dir() -> Result<String, String> {
     local::browser::get_downloads_dir().map_err(|e| e.to_string())
 }
 
+/// `entry`'s path ready to paste into a shell: quoted for a POSIX shell for
+/// remote entries, for this system's shell when `local` is set
+#[tauri::command]
+async fn shell_quoted_path(entry: FileEntry, local: Option<bool>) -> Result<String, String> {
+    if local.unwrap_or(false) {
+        Ok(util::local_shell_path(&entry.path))
+    } else {
+        Ok(util::shell_path(&entry.path))
+    }
+}
+
 // ============ Keychain Commands ============
 
 #[tauri::command]
@@ -1109,6 +3166,19 @@ async fn has_stored_password(connection_id: String) -> Result<bool, String> {
     Ok(KeychainManager::has_password(&connection_id))
 }
 
+/// Where passwords are saved: the OS keychain, or a file when there is
+/// none or the user prefers it
+#[tauri::command]
+async fn keychain_backend_status() -> Result<KeychainBackendStatus, String> {
+    KeychainManager::backend_status().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_store_secrets_in_file(enabled: bool) -> Result<KeychainBackendStatus, String> {
+    KeychainManager::set_store_secrets_in_file(enabled).map_err(|e| e.to_string())?;
+    KeychainManager::backend_status().map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn keychain_get_password(connection_id: String) -> Result<Option<String>, String> {
     KeychainManager::get_password(&connection_id)
@@ -1121,17 +3191,39 @@ async fn keychain_get_password(connection_id: String) -> Result<Option<String>,
 #[tauri::command]
 async fn vnc_connect(
     app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
     vnc_manager: State<'_, VncManagerState>,
     host: String,
     port: u16,
     password: Option<String>,
-) -> Result<(String, u16, u16), String> {
+    encoding: Option<vnc::VncEncoding>,
+    quality: Option<u8>,
+    color_depth: Option<vnc::VncColorDepth>,
+    username: Option<String>,
+    auth_type: Option<vnc::VncAuthType>,
+    via_ssh: Option<vnc::SshTunnelConfig>,
+) -> Result<(String, u16, u16), ConnectError> {
+    let via_ssh = match via_ssh {
+        Some(config) => Some(connect_ssh_tunnel(&state.connections, config)?),
+        None => None,
+    };
+
     let session_id = uuid::Uuid::new_v4().to_string();
+    let credentials = vnc::VncCredentials {
+        auth_type: auth_type.unwrap_or_default(),
+        username,
+        password,
+    };
     let (width, height) = vnc_manager.create_session(
         session_id.clone(),
         &host,
         port,
-        password.as_deref(),
+        &credentials,
+        &certificate_policy(&host, port, false)?,
+        encoding.unwrap_or_default(),
+        quality.unwrap_or(vnc::VNC_DEFAULT_QUALITY),
+        color_depth.unwrap_or_default(),
+        via_ssh,
     )?;
 
     vnc_manager.start_frame_reader(&session_id, app_handle)?;
@@ -1139,6 +3231,104 @@ async fn vnc_connect(
     Ok((session_id, width, height))
 }
 
+/// Trust the VeNCrypt certificate with `fingerprint` for `host:port` on
+/// later connects, after the user has reviewed an untrusted-certificate error
+#[tauri::command]
+async fn vnc_trust_certificate(host: String, port: u16, fingerprint: String) -> Result<(), String> {
+    let pins = CertificatePins::new().map_err(|e| e.to_string())?;
+    pins.trust(&host, port, &fingerprint).map_err(|e| e.to_string())
+}
+
+/// Wait for VNC servers to connect to us. Returns the listener id and the bound port;
+/// each incoming connection is reported on `vnc-incoming-{listener_id}`.
+///
+/// Listens on every interface unless `bind_address` names one, and any
+/// host that reaches it is sent the password's response. `auth_type`
+/// limits which authentication peers may use; ARD and MS-Logon are refused.
+#[tauri::command]
+async fn vnc_listen(
+    app_handle: AppHandle,
+    vnc_manager: State<'_, VncManagerState>,
+    port: u16,
+    bind_address: Option<String>,
+    auth_type: Option<vnc::VncAuthType>,
+    password: Option<String>,
+    encoding: Option<vnc::VncEncoding>,
+    quality: Option<u8>,
+    color_depth: Option<vnc::VncColorDepth>,
+) -> Result<(String, u16), String> {
+    let listener_id = uuid::Uuid::new_v4().to_string();
+    let bind_address = match bind_address.as_deref().map(str::trim).filter(|a| !a.is_empty()) {
+        Some(address) => util::unbracket_host(address)
+            .parse()
+            .map_err(|_| format!("Invalid listen address: {}", address))?,
+        None => std::net::Ipv4Addr::UNSPECIFIED.into(),
+    };
+    let credentials = vnc::VncCredentials {
+        auth_type: auth_type.unwrap_or_default(),
+        password,
+        ..Default::default()
+    };
+    let port = vnc_manager.start_listener(
+        listener_id.clone(),
+        bind_address,
+        port,
+        credentials,
+        encoding.unwrap_or_default(),
+        quality.unwrap_or(vnc::VNC_DEFAULT_QUALITY),
+        color_depth.unwrap_or_default(),
+        app_handle,
+    )?;
+
+    Ok((listener_id, port))
+}
+
+#[tauri::command]
+async fn vnc_stop_listen(
+    vnc_manager: State<'_, VncManagerState>,
+    listener_id: String,
+) -> Result<(), String> {
+    vnc_manager.stop_listener(&listener_id)
+}
+
+/// Open the SSH leg of a tunnelled VNC connection
+fn connect_ssh_tunnel(storage: &ConnectionStorage, config: vnc::SshTunnelConfig) -> Result<ssh::SshClient, String> {
+    let (host, port, username, auth) = match config.connection_id {
+        Some(connection_id) => {
+            let profile = storage.get(&connection_id).map_err(|e| e.to_string())?;
+            let (host, port, username) = match &profile.connection_type {
+                storage::connections::ConnectionType::Ssh { host, port, username, .. } => {
+                    (host.clone(), *port, username.clone())
+                }
+                _ => return Err("SSH tunnel profile is not an SSH connection".to_string()),
+            };
+            let pwd = config
+                .password
+                .or_else(|| KeychainManager::get_password(&connection_id).ok());
+            let passphrase = config
+                .passphrase
+                .or_else(|| KeychainManager::get_passphrase(&connection_id).ok());
+            let auth = profile.to_auth_method(pwd, passphrase);
+            (host, port, username, auth)
+        }
+        None => {
+            let host = config
+                .host
+                .ok_or_else(|| "SSH tunnel requires a host or saved connection".to_string())?;
+            let username = config
+                .username
+                .ok_or_else(|| "SSH tunnel requires a username".to_string())?;
+            let auth = config
+                .auth
+                .ok_or_else(|| "SSH tunnel requires an authentication method".to_string())?;
+            (host, config.port.unwrap_or(22), username, auth)
+        }
+    };
+
+    ssh::SshClient::connect(&host, port, &username, &auth, config.proxy.as_ref(), None)
+        .map_err(|e| format!("SSH tunnel connection failed: {}", e))
+}
+
 #[tauri::command]
 async fn vnc_send_input(
     vnc_manager: State<'_, VncManagerState>,
@@ -1148,6 +3338,29 @@ async fn vnc_send_input(
     vnc_manager.send_input(&session_id, event)
 }
 
+/// Type a string into the session as key events
+#[tauri::command]
+async fn vnc_send_text(
+    vnc_manager: State<'_, VncManagerState>,
+    session_id: String,
+    text: String,
+) -> Result<(), String> {
+    vnc_manager.send_text(&session_id, &text)
+}
+
+/// Change compression quality (0-9, higher is better) on a live session
+#[tauri::command]
+async fn vnc_set_quality(
+    vnc_manager: State<'_, VncManagerState>,
+    session_id: String,
+    level: u8,
+) -> Result<(), String> {
+    if level > vnc::VNC_MAX_QUALITY {
+        return Err(format!("Quality level must be 0-{}", vnc::VNC_MAX_QUALITY));
+    }
+    vnc_manager.set_quality(&session_id, level)
+}
+
 #[tauri::command]
 async fn vnc_disconnect(
     vnc_manager: State<'_, VncManagerState>,
@@ -1164,8 +3377,57 @@ async fn vnc_get_dimensions(
     vnc_manager.get_dimensions(&session_id)
 }
 
+/// Receive the session's frames as binary messages on `channel` instead of
+/// `vnc-frame-{session_id}` events
+#[tauri::command]
+async fn vnc_attach_frame_channel(
+    vnc_manager: State<'_, VncManagerState>,
+    session_id: String,
+    channel: tauri::ipc::Channel<tauri::ipc::Response>,
+) -> Result<(), String> {
+    vnc_manager.attach_frame_channel(&session_id, channel)
+}
+
+/// Go back to `vnc-frame-{session_id}` events, for viewers with binary
+/// frames turned off
+#[tauri::command]
+async fn vnc_detach_frame_channel(
+    vnc_manager: State<'_, VncManagerState>,
+    session_id: String,
+) -> Result<(), String> {
+    vnc_manager.detach_frame_channel(&session_id)
+}
+
+// ============ Remote Desktop Screenshots ============
+
+/// Save the current VNC or RDP screen to `path` as PNG (default) or JPEG
+#[tauri::command]
+async fn capture_remote_screenshot(
+    vnc_manager: State<'_, VncManagerState>,
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+    path: String,
+    format: Option<frame::ScreenshotFormat>,
+) -> Result<frame::ScreenshotInfo, String> {
+    let format = format.unwrap_or_default();
+    if vnc_manager.has_session(&session_id) {
+        vnc_manager.capture_screenshot(&session_id, &path, format)
+    } else {
+        rdp_manager.capture_screenshot(&session_id, &path, format)
+    }
+}
+
 // ============ RDP Commands ============
 
+/// Connect to an RDP server. With `smart_card`, NLA logs on with the card
+/// and `password` is ignored. Saved profiles can't hold a smart card, so
+/// this is the only way to use one.
+///
+/// Returns the session id at once and connects in the background, sending
+/// `rdp-connected-{id}` or `rdp-connect-failed-{id}`. Pass a new
+/// `session_id` to listen for those before invoking; one already in use is
+/// refused. `timeout_ms` bounds the whole connect
+/// (default 30s).
 #[tauri::command]
 async fn rdp_connect(
     app_handle: AppHandle,
@@ -1178,26 +3440,137 @@ async fn rdp_connect(
     width: u16,
     height: u16,
     quality: Option<rdp::RdpQuality>,
+    keyboard: Option<rdp::RdpKeyboard>,
+    proxy: Option<ProxyConfig>,
+    software_cursor: Option<bool>,
+    accept_invalid_certs: Option<bool>,
+    gateway: Option<rdp::RdpGateway>,
+    auto_reconnect: Option<bool>,
+    codec: Option<rdp::RdpCodec>,
+    smart_card: Option<rdp::RdpSmartCard>,
+    timeout_ms: Option<u64>,
+    session_id: Option<String>,
+    max_frames_in_flight: Option<usize>,
+    shared_folder: Option<String>,
+    shared_folder_read_only: Option<bool>,
+    custom_performance_flags: Option<rdp::RdpPerformanceFlags>,
 ) -> Result<String, String> {
-    let session_id = uuid::Uuid::new_v4().to_string();
-    let quality = quality.unwrap_or(rdp::RdpQuality::High);  // Default to high quality
-    rdp_manager.create_session(
+    let session_id = session_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let quality = quality.unwrap_or_default();
+    let certificates = certificate_policy(&host, port, accept_invalid_certs.unwrap_or(false))?;
+    let timeout = timeout_ms.map_or(rdp::DEFAULT_CONNECT_TIMEOUT, std::time::Duration::from_millis);
+    rdp_manager.inner().clone().connect_in_background(
         session_id.clone(),
-        &host,
-        port,
-        &username,
-        &password,
-        domain.as_deref(),
-        width,
-        height,
-        quality,
+        rdp::RdpConnectParams {
+            host,
+            port,
+            username,
+            password,
+            domain,
+            width,
+            height,
+            quality,
+            custom_performance_flags,
+            codec: codec.unwrap_or_default(),
+            keyboard: keyboard.unwrap_or_else(rdp::RdpKeyboard::system),
+            proxy,
+            pointer_software_rendering: software_cursor.unwrap_or(false),
+            certificates,
+            gateway,
+            connection_id: None,
+            smart_card,
+            shared_folder: rdp_shared_folder(shared_folder, shared_folder_read_only),
+            timeout,
+            max_frames_in_flight: max_frames_in_flight.unwrap_or(frame::DEFAULT_MAX_FRAMES_IN_FLIGHT),
+        },
+        auto_reconnect.unwrap_or(true),
+        app_handle,
     )?;
 
-    rdp_manager.start_frame_reader(&session_id, app_handle)?;
-
     Ok(session_id)
 }
 
+/// Abort a connect started by `rdp_connect`; it then fails with
+/// `rdp-connect-failed-{id}`
+#[tauri::command]
+async fn rdp_cancel_connect(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+) -> Result<(), String> {
+    rdp_manager.cancel_connect(&session_id);
+    Ok(())
+}
+
+/// Connect a dropped session again after `rdp-connection-lost-{id}`, for
+/// profiles that ask before reconnecting. Without a `password`, sessions
+/// from a saved profile that keeps its password use the one in the
+/// keychain, so a changed password is picked up; others reuse the one they
+/// logged on with.
+#[tauri::command]
+async fn rdp_reconnect(
+    app_handle: AppHandle,
+    state: State<'_, Arc<AppState>>,
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+    password: Option<String>,
+) -> Result<(), String> {
+    let password = password.or_else(|| {
+        let connection_id = rdp_manager.connection_id(&session_id)?;
+        let profile = state.connections.get(&connection_id).ok()?;
+        match profile.connection_type {
+            ConnectionType::Rdp {
+                save_password: true,
+                ..
+            } => KeychainManager::get_password(&connection_id).ok(),
+            _ => None,
+        }
+    });
+    rdp_manager.reconnect(&session_id, password, app_handle)
+}
+
+/// Folder to share from the `shared_folder` command arguments; an empty
+/// path shares nothing
+fn rdp_shared_folder(path: Option<String>, read_only: Option<bool>) -> Option<rdp::RdpSharedFolder> {
+    path.filter(|path| !path.trim().is_empty()).map(|path| rdp::RdpSharedFolder {
+        path,
+        read_only: read_only.unwrap_or(false),
+    })
+}
+
+/// Certificate checks for connecting to `host:port`: its pinned certificate
+/// if the user trusted one, otherwise normal validation
+fn certificate_policy(host: &str, port: u16, accept_invalid_certs: bool) -> Result<certificate::CertificatePolicy, String> {
+    let pinned = CertificatePins::new()
+        .and_then(|pins| pins.get(host, port))
+        .map_err(|e| e.to_string())?;
+    Ok(certificate::CertificatePolicy {
+        pinned,
+        accept_any: accept_invalid_certs,
+    })
+}
+
+/// Trust the certificate with `fingerprint` for `host:port` on later
+/// connects, after the user has reviewed an untrusted-certificate error
+#[tauri::command]
+async fn rdp_trust_certificate(host: String, port: u16, fingerprint: String) -> Result<(), String> {
+    let pins = CertificatePins::new().map_err(|e| e.to_string())?;
+    pins.trust(&host, port, &fingerprint).map_err(|e| e.to_string())
+}
+
+/// Forget every cached RDP client license, so the next connect to each
+/// server negotiates a new one
+#[tauri::command]
+async fn clear_rdp_license_cache() -> Result<(), String> {
+    let licenses = RdpLicenses::new().map_err(|e| e.to_string())?;
+    licenses.clear().map_err(|e| e.to_string())
+}
+
+/// Keyboard layouts selectable for RDP connections
+#[tauri::command]
+async fn rdp_keyboard_layouts() -> Result<rdp::KeyboardLayouts, String> {
+    Ok(rdp::keyboard_layouts())
+}
+
 #[tauri::command]
 async fn rdp_send_input(
     rdp_manager: State<'_, RdpManagerState>,
@@ -1207,6 +3580,48 @@ async fn rdp_send_input(
     rdp_manager.send_input(&session_id, event)
 }
 
+/// Send a key combination the local OS would intercept, e.g. Ctrl+Alt+Del
+/// or the Windows key
+#[tauri::command]
+async fn rdp_send_special_key(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+    combo: rdp::RdpSpecialKey,
+) -> Result<(), String> {
+    rdp_manager.send_special_key(&session_id, combo)
+}
+
+/// Send characters as Unicode keyboard events, bypassing the keyboard layout
+#[tauri::command]
+async fn rdp_send_unicode(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+    text: String,
+) -> Result<(), String> {
+    rdp_manager.send_unicode(&session_id, &text)
+}
+
+/// Type a string into the session as Unicode key events
+#[tauri::command]
+async fn rdp_send_text(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+    text: String,
+) -> Result<(), String> {
+    rdp_manager.send_text(&session_id, &text)
+}
+
+/// Copy text to the session's clipboard. Text copied on the remote side
+/// arrives as `rdp-clipboard-{session_id}` events.
+#[tauri::command]
+async fn rdp_send_clipboard(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+    text: String,
+) -> Result<(), String> {
+    rdp_manager.send_clipboard(&session_id, text)
+}
+
 #[tauri::command]
 async fn rdp_disconnect(
     rdp_manager: State<'_, RdpManagerState>,
@@ -1215,6 +3630,51 @@ async fn rdp_disconnect(
     rdp_manager.close_session(&session_id)
 }
 
+/// Change the remote desktop size to fit the window. Fails if the server
+/// doesn't support dynamic resolution.
+#[tauri::command]
+async fn rdp_resize(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+    width: u16,
+    height: u16,
+) -> Result<(), String> {
+    rdp_manager.resize(&session_id, width, height)
+}
+
+/// Switch a live session's quality preset, e.g. after moving to a slower
+/// network. Frame pacing changes at once; color depth and codecs follow on
+/// the next reconnect. Also sent as `rdp-quality-{session_id}`.
+#[tauri::command]
+async fn rdp_set_quality(
+    app_handle: AppHandle,
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+    quality: rdp::RdpQuality,
+) -> Result<rdp::RdpQualityInfo, String> {
+    rdp_manager.set_quality(&session_id, quality, &app_handle)
+}
+
+/// Report whether the viewer is visible; hidden viewers may stop display
+/// updates depending on the quality preset
+#[tauri::command]
+async fn rdp_set_visible(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+    visible: bool,
+) -> Result<(), String> {
+    rdp_manager.set_visible(&session_id, visible)
+}
+
+/// Current remote cursor shape; changes arrive as `rdp-pointer-{session_id}`
+#[tauri::command]
+async fn rdp_get_pointer(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+) -> Result<Option<rdp::RdpPointer>, String> {
+    rdp_manager.pointer_shape(&session_id)
+}
+
 #[tauri::command]
 async fn rdp_get_dimensions(
     rdp_manager: State<'_, RdpManagerState>,
@@ -1223,6 +3683,176 @@ async fn rdp_get_dimensions(
     rdp_manager.get_dimensions(&session_id)
 }
 
+/// Receive the session's frames as binary messages on `channel` instead of
+/// `rdp-frame-{session_id}` events
+#[tauri::command]
+async fn rdp_attach_frame_channel(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+    channel: tauri::ipc::Channel<tauri::ipc::Response>,
+) -> Result<(), String> {
+    rdp_manager.attach_frame_channel(&session_id, channel)
+}
+
+/// Go back to `rdp-frame-{session_id}` events, for viewers with binary
+/// frames turned off
+#[tauri::command]
+async fn rdp_detach_frame_channel(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+) -> Result<(), String> {
+    rdp_manager.detach_frame_channel(&session_id)
+}
+
+/// The viewer finished painting a frame; see `rdp_set_max_frames_in_flight`
+#[tauri::command]
+async fn rdp_frame_ack(rdp_manager: State<'_, RdpManagerState>, session_id: String) -> Result<(), String> {
+    rdp_manager.frame_ack(&session_id)
+}
+
+/// How many frames the viewer may leave unacknowledged before updates are
+/// held back and merged into one; 0 turns backpressure off
+#[tauri::command]
+async fn rdp_set_max_frames_in_flight(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+    max_frames_in_flight: usize,
+) -> Result<(), String> {
+    rdp_manager.set_max_frames_in_flight(&session_id, max_frames_in_flight)
+}
+
+/// Frames sent and skipped for a session, and its effective frame rate
+#[tauri::command]
+async fn rdp_frame_stats(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+) -> Result<frame::FrameFlowStats, String> {
+    rdp_manager.frame_stats(&session_id)
+}
+
+/// Bandwidth and decode CPU of a session's display updates since it connected
+#[tauri::command]
+async fn rdp_codec_stats(
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+) -> Result<rdp::RdpCodecStats, String> {
+    rdp_manager.codec_stats(&session_id)
+}
+
+// ============ Session Status ============
+
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum SessionKind {
+    Terminal,
+    Sftp,
+    Ftp,
+    Vnc,
+    Rdp,
+}
+
+/// Result of `session_status`
+#[derive(Debug, Clone, serde::Serialize)]
+struct SessionStatus {
+    /// `None` if no session has this ID (never existed or already cleaned up)
+    kind: Option<SessionKind>,
+    connected: bool,
+    /// SSH terminals only: the server closed the channel
+    channel_eof: Option<bool>,
+    /// Number of SFTP/FTP transfers running on this session
+    active_transfers: usize,
+}
+
+/// Bytes sent and received by a terminal session, plus the files moved by
+/// SFTP browsers opened from it
+#[tauri::command]
+async fn session_stats(
+    state: State<'_, Arc<AppState>>,
+    session_id: String,
+) -> Result<SessionStats, String> {
+    state
+        .terminal_manager
+        .session_stats(&session_id)
+        .ok_or_else(|| format!("Session not found: {}", session_id))
+}
+
+/// Whether a session is still usable, without touching the connection
+#[tauri::command]
+async fn session_status(
+    state: State<'_, Arc<AppState>>,
+    sftp_sessions: State<'_, SftpSessions>,
+    ftp_sessions: State<'_, FtpSessions>,
+    vnc_manager: State<'_, VncManagerState>,
+    rdp_manager: State<'_, RdpManagerState>,
+    session_id: String,
+) -> Result<SessionStatus, String> {
+    let mut status = SessionStatus {
+        kind: None,
+        connected: false,
+        channel_eof: None,
+        active_transfers: state.transfers.active_for(&session_id),
+    };
+
+    if let Some((alive, channel_eof)) = state.terminal_manager.session_health(&session_id) {
+        status.kind = Some(SessionKind::Terminal);
+        status.connected = alive;
+        status.channel_eof = channel_eof;
+    } else if sftp_sessions.lock().contains_key(&session_id) {
+        status.kind = Some(SessionKind::Sftp);
+        status.connected = true;
+    } else if ftp_sessions.lock().contains_key(&session_id) {
+        status.kind = Some(SessionKind::Ftp);
+        status.connected = true;
+    } else if let Some(connected) = vnc_manager.is_connected(&session_id) {
+        status.kind = Some(SessionKind::Vnc);
+        status.connected = connected;
+    } else if let Some(connected) = rdp_manager.is_connected(&session_id) {
+        status.kind = Some(SessionKind::Rdp);
+        status.connected = connected;
+    }
+
+    Ok(status)
+}
+
+// ============ Shutdown ============
+
+/// How long to let running transfers notice cancellation before closing their sessions
+const SHUTDOWN_TRANSFER_GRACE: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Cancel all transfers and close every session
+fn close_everything(app_handle: &AppHandle) {
+    let state = app_handle.state::<Arc<AppState>>();
+
+    // Transfers first: they hold the SFTP/FTP session locks
+    state.transfers.cancel_all();
+    if !state.transfers.wait_idle(SHUTDOWN_TRANSFER_GRACE) {
+        eprintln!("Shutdown: some transfers did not stop in time");
+    }
+
+    state.terminal_manager.close_all();
+
+    app_handle.state::<SftpSessions>().lock().clear();
+
+    let ftp_sessions: Vec<_> = app_handle.state::<FtpSessions>().lock().drain().collect();
+    for (_, browser) in ftp_sessions {
+        // Skip the goodbye if a stuck transfer still holds the stream
+        let stream = browser.stream();
+        let guard = stream.try_lock();
+        if let Some(mut stream_guard) = guard {
+            let _ = stream_guard.quit();
+        }
+    }
+
+    app_handle.state::<VncManagerState>().close_all();
+    app_handle.state::<RdpManagerState>().close_all();
+}
+
+#[tauri::command]
+async fn shutdown_all(app_handle: AppHandle) -> Result<(), String> {
+    close_everything(&app_handle);
+    Ok(())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
@@ -1233,16 +3863,50 @@ pub fn run() {
         .manage(FtpSessions::default())
         .manage(VncManagerState::default())
         .manage(RdpManagerState::default())
+        .setup(|app| {
+            let app_handle = app.handle().clone();
+            let change_handle = app_handle.clone();
+            storage::connections::on_recovery(move |recovery| {
+                let _ = app_handle.emit("connections-recovered", recovery);
+            });
+            storage::connections::on_change(move || {
+                let _ = change_handle.emit("connections-changed", ());
+            });
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             // Terminal
             create_terminal,
             create_ssh_terminal,
             write_terminal,
+            write_terminal_text,
+            set_session_env,
+            get_session_env,
             resize_terminal,
             close_terminal,
+            get_terminal_output_config,
+            set_terminal_output_config,
             list_terminals,
+            ssh_get_banner,
+            ssh_probe_auth,
             // Connections
             list_connections,
+            set_connection_tags,
+            set_connection_group,
+            set_connection_favorite,
+            reorder_connections,
+            list_connection_groups,
+            import_ssh_config,
+            save_imported_connections,
+            import_putty_sessions,
+            import_filezilla_sites,
+            save_foreign_sessions,
+            export_connections,
+            import_connections,
+            storage_encryption_status,
+            enable_storage_encryption,
+            unlock_storage,
+            disable_storage_encryption,
             get_connection,
             save_connection,
             save_ftp_connection,
@@ -1250,36 +3914,74 @@ pub fn run() {
             save_rdp_connection,
             update_connection,
             delete_connection,
+            get_connection_download_dir,
+            set_connection_download_dir,
+            set_connection_default_paths,
+            set_connection_idle_timeout,
+            get_connection_terminal_settings,
+            set_connection_terminal_settings,
             connect_saved,
+            run_startup_connections,
+            set_connection_auto_connect,
+            get_auto_connect_enabled,
+            set_auto_connect_enabled,
+            get_connection_stats,
+            get_recent_connections,
+            connect_group,
+            connect_saved_vnc,
+            connect_saved_rdp,
             has_stored_password,
             keychain_get_password,
+            keychain_backend_status,
+            set_store_secrets_in_file,
             // SFTP
             sftp_open,
             sftp_close,
             sftp_list_dir,
+            sftp_list_dir_stream,
             sftp_get_current_path,
+            sftp_home_dir,
+            sftp_chdir,
             sftp_realpath,
             sftp_mkdir,
+            sftp_touch,
+            sftp_chmod,
+            sftp_set_executable,
+            sftp_chmod_recursive,
+            sftp_duplicate,
+            sftp_list_templates,
+            sftp_new_from_template,
             sftp_delete,
+            sftp_delete_many,
             sftp_rename,
             sftp_download,
             sftp_upload,
+            sftp_move_from_local,
+            list_resumable_transfers,
+            resume_transfer,
+            discard_resumable_transfer,
             sftp_upload_folder,
+            sftp_upload_paths,
             // FTP
             ftp_connect,
+            connect_saved_ftp,
             ftp_disconnect,
             ftp_list_dir,
             ftp_pwd,
+            ftp_chdir,
             ftp_mkdir,
+            ftp_touch,
             ftp_delete,
             ftp_rename,
             ftp_download,
             ftp_upload,
+            ftp_move_from_local,
             ftp_upload_folder,
             // File Editor
             read_local_file,
             write_local_file,
             sftp_read_file,
+            sftp_edit_open,
             sftp_write_file,
             ftp_read_file,
             ftp_write_file,
@@ -1288,17 +3990,55 @@ pub fn run() {
             check_is_directory,
             // VNC
             vnc_connect,
+            vnc_trust_certificate,
             vnc_send_input,
+            vnc_send_text,
+            vnc_set_quality,
+            vnc_listen,
+            capture_remote_screenshot,
+            vnc_stop_listen,
             vnc_disconnect,
             vnc_get_dimensions,
+            vnc_attach_frame_channel,
+            vnc_detach_frame_channel,
             // RDP
             rdp_connect,
+            rdp_reconnect,
+            rdp_trust_certificate,
+            clear_rdp_license_cache,
+            rdp_keyboard_layouts,
             rdp_send_input,
+            rdp_send_special_key,
+            rdp_send_unicode,
+            rdp_send_text,
+            rdp_send_clipboard,
+            rdp_cancel_connect,
             rdp_disconnect,
+            rdp_resize,
+            rdp_set_quality,
+            rdp_set_visible,
+            rdp_get_pointer,
             rdp_get_dimensions,
+            rdp_attach_frame_channel,
+            rdp_detach_frame_channel,
+            rdp_frame_ack,
+            rdp_set_max_frames_in_flight,
+            rdp_frame_stats,
+            rdp_codec_stats,
             local_get_home_dir,
             local_get_downloads_dir,
+            shell_quoted_path,
+            // Session status
+            session_status,
+            session_stats,
+            // Shutdown
+            shutdown_all,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app_handle, event| {
+            if let tauri::RunEvent::Exit = event {
+                close_everything(app_handle);
+            }
+        });
 }
@@ -3,6 +3,8 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+use crate::file_info::{human_size, FileCategory};
+
 #[derive(Error, Debug)]
 pub enum LocalBrowserError {
     #[error("IO error: {0}")]
@@ -27,6 +29,11 @@ pub struct FileEntry {
     pub size: u64,
     pub modified: Option<i64>,
     pub permissions: Option<u32>,
+    /// Size for display; empty for directories
+    #[serde(default)]
+    pub size_human: String,
+    #[serde(default)]
+    pub category: FileCategory,
 }
 
 pub fn list_directory(path: &str) -> Result<Vec<FileEntry>, LocalBrowserError> {
@@ -86,7 +93,10 @@ pub fn list_directory(path: &str) -> Result<Vec<FileEntry>, LocalBrowserError> {
         #[cfg(not(unix))]
         let permissions = None;
 
+        let is_dir = file_type == FileType::Directory;
         files.push(FileEntry {
+            size_human: human_size(size, is_dir),
+            category: FileCategory::of(&name, is_dir),
             name,
             path: full_path,
             file_type,
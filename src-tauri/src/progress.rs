@@ -0,0 +1,92 @@
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Minimum time between transfer progress events
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Rate-limits transfer progress reports. Transfers report every chunk,
+/// which for a fast link means thousands of IPC events a second; this
+/// passes on at most one per `PROGRESS_INTERVAL`, plus completion.
+pub struct ThrottledProgress<F: FnMut(u64, u64)> {
+    emit: F,
+    last_emit: Option<Instant>,
+    /// Latest report held back by the throttle
+    pending: Option<(u64, u64)>,
+}
+
+impl<F: FnMut(u64, u64)> ThrottledProgress<F> {
+    pub fn new(emit: F) -> Self {
+        Self {
+            emit,
+            last_emit: None,
+            pending: None,
+        }
+    }
+
+    pub fn update(&mut self, transferred: u64, total: u64) {
+        let due = self
+            .last_emit
+            .is_none_or(|last| last.elapsed() >= PROGRESS_INTERVAL);
+        if due || transferred >= total {
+            self.pending = None;
+            self.last_emit = Some(Instant::now());
+            (self.emit)(transferred, total);
+        } else {
+            self.pending = Some((transferred, total));
+        }
+    }
+
+    /// Send the last held-back report so the final byte count isn't lost
+    pub fn finish(mut self) {
+        if let Some((transferred, total)) = self.pending.take() {
+            (self.emit)(transferred, total);
+        }
+    }
+}
+
+/// Payload of `transfer-group-{group_id}` (`ftp-transfer-group-{group_id}`
+/// for FTP): where a multi-file transfer stands, for "47/312 files"
+/// displays. Sent at the same rate as the transfer's progress events.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferGroup {
+    /// The transfer's id
+    pub group_id: String,
+    pub total_files: u64,
+    pub completed_files: u64,
+    /// File being transferred, relative to the folder
+    pub current_file: Option<String>,
+    pub transferred_bytes: u64,
+    pub total_bytes: u64,
+}
+
+impl TransferGroup {
+    pub fn new(group_id: String, total_files: u64, total_bytes: u64) -> Self {
+        Self {
+            group_id,
+            total_files,
+            completed_files: 0,
+            current_file: None,
+            transferred_bytes: 0,
+            total_bytes,
+        }
+    }
+
+    /// Record progress on `current_file`. Files go one at a time, so moving
+    /// on to another file means the previous one is done.
+    pub fn update(&mut self, transferred: u64, current_file: &str) {
+        if self.current_file.as_deref() != Some(current_file) {
+            if self.current_file.is_some() {
+                self.completed_files = (self.completed_files + 1).min(self.total_files);
+            }
+            self.current_file = Some(current_file.to_string());
+        }
+        self.transferred_bytes = transferred;
+    }
+
+    /// Mark every file done
+    pub fn finish(&mut self) {
+        self.completed_files = self.total_files;
+        self.current_file = None;
+        self.transferred_bytes = self.total_bytes;
+    }
+}
@@ -0,0 +1,176 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use crate::util::{host_port, unbracket_host};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// Longest proxy response header we accept before giving up
+const MAX_RESPONSE_HEADER: usize = 8 * 1024;
+
+/// Head start each address gets before the next one is tried alongside it
+/// (RFC 8305)
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// HTTP proxy that outgoing connections are tunnelled through with CONNECT,
+/// for networks that only allow web traffic out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    pub host: String,
+    pub port: u16,
+    /// Basic auth credentials, if the proxy requires them
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Open a TCP connection to `host:port`, directly or through `proxy`.
+/// `host` may be an IPv6 literal, bracketed or not.
+pub fn connect(host: &str, port: u16, proxy: Option<&ProxyConfig>) -> io::Result<TcpStream> {
+    open(host, port, proxy, None)
+}
+
+/// Like `connect`, but each TCP connection attempt and the proxy's answer
+/// may take at most `timeout`
+pub fn connect_timeout(
+    host: &str,
+    port: u16,
+    proxy: Option<&ProxyConfig>,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    open(host, port, proxy, Some(timeout))
+}
+
+fn open(host: &str, port: u16, proxy: Option<&ProxyConfig>, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    match proxy {
+        Some(proxy) => connect_via_proxy(host, port, proxy, timeout),
+        None => tcp_connect(host, port, timeout),
+    }
+}
+
+/// Connect to whichever address `host` resolves to answers first. Attempts
+/// alternate between IPv6 and IPv4 and start `ATTEMPT_DELAY` apart, or
+/// as soon as the previous one fails (happy eyeballs).
+fn tcp_connect(host: &str, port: u16, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let host = unbracket_host(host);
+    let mut addrs = interleave_families((host, port).to_socket_addrs()?.collect()).into_iter();
+    let no_addresses = || io::Error::new(io::ErrorKind::NotFound, format!("No addresses found for {}", host));
+
+    let attempt = move |addr: SocketAddr| match timeout {
+        Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+        None => TcpStream::connect(addr),
+    };
+    if addrs.len() <= 1 {
+        return attempt(addrs.next().ok_or_else(no_addresses)?);
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let mut pending = 0;
+    let mut last_error = None;
+    loop {
+        if let Some(addr) = addrs.next() {
+            let sender = sender.clone();
+            // Losing attempts finish on their own and drop their stream
+            thread::spawn(move || {
+                let _ = sender.send(attempt(addr));
+            });
+            pending += 1;
+        } else if pending == 0 {
+            return Err(last_error.unwrap_or_else(no_addresses));
+        }
+
+        let result = if !addrs.as_slice().is_empty() {
+            receiver.recv_timeout(ATTEMPT_DELAY)
+        } else {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+        match result {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => {
+                pending -= 1;
+                last_error = Some(e);
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// `addrs` reordered to alternate between address families, starting with
+/// the family the resolver listed first
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first_ipv6) = addrs.first().map(SocketAddr::is_ipv6) else {
+        return addrs;
+    };
+    let mut interleaved = Vec::with_capacity(addrs.len());
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|addr| addr.is_ipv6() == first_ipv6);
+    preferred.reverse();
+    other.reverse();
+    while let Some(addr) = preferred.pop() {
+        interleaved.push(addr);
+        interleaved.extend(other.pop());
+    }
+    interleaved.extend(other.into_iter().rev());
+    interleaved
+}
+
+fn connect_via_proxy(host: &str, port: u16, proxy: &ProxyConfig, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let mut stream = tcp_connect(&proxy.host, proxy.port, timeout).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to connect to proxy {}: {}", host_port(&proxy.host, proxy.port), e),
+        )
+    })?;
+
+    // IPv6 literals need brackets in the authority
+    let target = host_port(host, port);
+
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+    if let Some(username) = &proxy.username {
+        let credentials = format!("{}:{}", username, proxy.password.as_deref().unwrap_or(""));
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            BASE64.encode(credentials)
+        ));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+
+    stream.set_read_timeout(timeout)?;
+    let header = read_response_header(&mut stream)?;
+    stream.set_read_timeout(None)?;
+    let status_line = header.lines().next().unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    if !status.starts_with('2') {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("Proxy refused tunnel to {}: {}", target, status_line.trim()),
+        ));
+    }
+
+    Ok(stream)
+}
+
+/// Read an HTTP response up to the blank line. Reads a byte at a time so
+/// nothing past the header (the start of the tunnelled stream) is consumed.
+pub(crate) fn read_response_header<S: Read>(stream: &mut S) -> io::Result<String> {
+    let mut header = Vec::new();
+    let mut byte = [0u8; 1];
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_RESPONSE_HEADER {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Response header too long",
+            ));
+        }
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed while reading the response",
+            ));
+        }
+        header.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&header).into_owned())
+}
@@ -1,17 +1,216 @@
 use ironrdp_blocking::Framed;
-use ironrdp_connector::{ClientConnector, Credentials, DesktopSize, ServerName};
+use ironrdp_connector::connection_activation::{ConnectionActivationSequence, ConnectionActivationState};
+use ironrdp_connector::{ClientConnector, Credentials, DesktopSize, Sequence, ServerName};
+use ironrdp_core::WriteBuf;
+use ironrdp_displaycontrol::client::DisplayControlClient;
+use ironrdp_displaycontrol::pdu::MonitorLayoutEntry;
+use ironrdp_dvc::DrdynvcClient;
 use ironrdp_graphics::image_processing::PixelFormat;
-use ironrdp_pdu::rdp::capability_sets::{MajorPlatformType, BitmapCodecs, Codec, CodecProperty, RemoteFxContainer, RfxClientCapsContainer, RfxCaps, RfxCapset, RfxICap, RfxICapFlags, EntropyBits, CaptureFlags, NsCodec};
+use ironrdp_pdu::geometry::{InclusiveRectangle, Rectangle};
+use ironrdp_pdu::rdp::headers::ShareDataPdu;
+use ironrdp_pdu::rdp::suppress_output::SuppressOutputPdu;
+use ironrdp_pdu::rdp::server_license::ServerLicenseError;
+use ironrdp_pdu::rdp::capability_sets::{MajorPlatformType, BitmapCodecs, Codec, CodecProperty, RemoteFxContainer, RfxClientCapsContainer, RfxCaps, RfxCapset, RfxICap, RfxICapFlags, EntropyBits, CaptureFlags};
+use ironrdp_session::fast_path::ProcessorBuilder;
 use ironrdp_session::image::DecodedImage;
 use ironrdp_session::{ActiveStage, ActiveStageOutput};
+use ironrdp_svc::SvcProcessorMessages;
 use parking_lot::Mutex;
-use std::net::TcpStream;
+use serde::Serialize;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use crate::certificate::{CertificatePolicy, ServerCertificate, UntrustedCertificate};
+use super::clipboard::ClipboardChannel;
+use super::drive::{DriveChannel, RdpSharedFolder, SoundChannel};
+use super::gateway::{GatewayError, GatewayStream, RdpGateway};
+use super::license::RdpLicenseCache;
+use super::pointer::RdpPointer;
+use crate::frame::Region;
+use crate::proxy::ProxyConfig;
+
+const CONNECT_CANCELLED: &str = "Connection cancelled";
+
+/// First byte of every slow-path RDP PDU
+const TPKT_VERSION: u8 = 3;
+
+/// How long the server may take to finish a Deactivation-Reactivation sequence
+const REACTIVATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Client-assigned codecID for RemoteFX in the Bitmap Codecs capability set
+/// (MS-RDPBCGR 2.2.7.2.10.1.1). The server tags surface bits with it, and
+/// ironrdp's surface decoder only takes 3 as RemoteFX.
+const CLIENT_CODEC_ID_REMOTEFX: u8 = 3;
+
+/// Most events one FastPath input PDU can carry; the count is a single byte
+const MAX_FASTPATH_INPUT_EVENTS: usize = 255;
+
+const RESIZE_NOT_SUPPORTED: &str = "Dynamic resize is not supported by this server";
+
+/// Where `RdpClient::connect` has got to, sent as
+/// `rdp-connect-progress-{session_id}`
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RdpConnectStage {
+    /// Opening the TCP connection or gateway tunnel
+    Connecting,
+    TlsHandshake,
+    /// CredSSP/NLA
+    Authenticating,
+    /// Licensing and the capability exchange that settles the desktop
+    NegotiatingDisplay,
+}
+
+/// Reply of `rdp_codec_stats`: what the server's output costs on this
+/// connection, to compare RemoteFX with plain bitmaps on a given link
+#[derive(Debug, Clone, Serialize)]
+pub struct RdpCodecStats {
+    pub codec: super::RdpCodec,
+    /// Since the connection was made
+    pub seconds: f64,
+    /// Server PDUs, headers included
+    pub bytes_received: u64,
+    /// Average over `seconds`
+    pub kbps: f64,
+    /// Spent decoding server output
+    pub decode_ms: f64,
+    /// Share of one core spent decoding, 0-100
+    pub decode_cpu_percent: f64,
+}
+
+/// Running totals behind `RdpCodecStats`
+struct DecodeStats {
+    since: Instant,
+    bytes: u64,
+    decoding: Duration,
+}
+
+/// Bounds how long `RdpClient::connect` may take, lets it be cancelled
+/// between phases and reports each phase as it starts
+pub struct ConnectControl<'a> {
+    deadline: Instant,
+    cancel: Option<&'a AtomicBool>,
+    progress: Option<&'a dyn Fn(RdpConnectStage)>,
+}
+
+impl<'a> ConnectControl<'a> {
+    pub fn new(
+        timeout: Duration,
+        cancel: Option<&'a AtomicBool>,
+        progress: Option<&'a dyn Fn(RdpConnectStage)>,
+    ) -> Self {
+        Self {
+            deadline: Instant::now() + timeout,
+            cancel,
+            progress,
+        }
+    }
+
+    /// Time left for `phase`, or why it shouldn't start
+    fn remaining(&self, phase: &str) -> Result<Duration, String> {
+        if self.cancel.is_some_and(|cancel| cancel.load(Ordering::SeqCst)) {
+            return Err(CONNECT_CANCELLED.to_string());
+        }
+        self.deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| format!("Timed out before {}", phase))
+    }
+
+    fn stage(&self, stage: RdpConnectStage) {
+        if let Some(progress) = self.progress {
+            progress(stage);
+        }
+    }
+}
+
+/// TLS stream used for `connect_finalize`, which runs CredSSP and the rest
+/// of the connection sequence in one go. CredSSP messages are DER and start
+/// with 0x30; the first client write starting with the TPKT version byte
+/// marks the move on to licensing and capabilities.
+struct StageWatch<'a> {
+    stream: native_tls::TlsStream<RdpTransport>,
+    control: &'a ConnectControl<'a>,
+    reported: bool,
+    /// The last write was partial, so the next continues a message
+    mid_message: bool,
+}
+
+impl Read for StageWatch<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for StageWatch<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.reported && !self.mid_message && buf.first() == Some(&TPKT_VERSION) {
+            self.reported = true;
+            self.control.stage(RdpConnectStage::NegotiatingDisplay);
+        }
+        let written = self.stream.write(buf)?;
+        self.mid_message = written < buf.len();
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
 
 /// Stream wrapper type after TLS upgrade
-type TlsFramed = Framed<native_tls::TlsStream<TcpStream>>;
+type TlsFramed = Framed<native_tls::TlsStream<RdpTransport>>;
+
+/// Connection to the RDP server, direct or tunnelled through an RD Gateway
+#[derive(Debug)]
+enum RdpTransport {
+    Direct(TcpStream),
+    Gateway(Box<GatewayStream>),
+}
+
+impl RdpTransport {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            RdpTransport::Direct(stream) => stream.set_read_timeout(timeout),
+            RdpTransport::Gateway(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            RdpTransport::Direct(stream) => stream.local_addr(),
+            RdpTransport::Gateway(stream) => stream.local_addr(),
+        }
+    }
+}
+
+impl Read for RdpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            RdpTransport::Direct(stream) => stream.read(buf),
+            RdpTransport::Gateway(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for RdpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            RdpTransport::Direct(stream) => stream.write(buf),
+            RdpTransport::Gateway(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RdpTransport::Direct(stream) => stream.flush(),
+            RdpTransport::Gateway(stream) => stream.flush(),
+        }
+    }
+}
 
 /// A working RDP client using IronRDP
 pub struct RdpClient {
@@ -25,9 +224,37 @@ pub struct RdpClient {
     connection_info: super::RdpConnectionInfo,
     /// Connection state
     connected: Arc<AtomicBool>,
-    /// Desktop dimensions
-    width: u16,
-    height: u16,
+    /// Desktop dimensions; change when the server reactivates the session
+    size: Mutex<(u16, u16)>,
+    /// New size not yet reported to the frontend
+    pending_resize: Mutex<Option<(u16, u16)>>,
+    /// Cursor changes not yet reported to the frontend
+    pending_pointer: Mutex<Vec<RdpPointer>>,
+    /// Current cursor shape (bitmap, hidden or default)
+    pointer_shape: Mutex<Option<RdpPointer>>,
+    /// Areas redrawn by input, e.g. a software-rendered cursor moving
+    input_regions: Mutex<Vec<Region>>,
+    /// Codec advertised to the server (RemoteFX or plain bitmaps)
+    codec: super::RdpCodec,
+    /// Color depth negotiated at connect
+    color_depth: u32,
+    /// Preset for the settings that can change live; starts as the one
+    /// connected with
+    quality: Mutex<super::RdpQuality>,
+    /// The viewer is minimized or in a background tab
+    hidden: AtomicBool,
+    /// The server was asked to stop sending display updates
+    output_suppressed: AtomicBool,
+    /// Processing server output failed while using RemoteFX
+    codec_failed: AtomicBool,
+    /// Bandwidth and decode time of server output
+    decode_stats: Mutex<DecodeStats>,
+    /// Collapses mouse moves before they hit the network
+    input_batcher: Mutex<super::InputBatcher>,
+    /// A thread is waiting to send what the batcher holds back
+    input_flush_scheduled: AtomicBool,
+    /// Why the server ended the session, once it has
+    disconnect: Mutex<Option<super::RdpDisconnect>>,
 }
 
 // Safety: All internal types are wrapped in synchronization primitives
@@ -44,67 +271,46 @@ impl RdpClient {
         width: u16,
         height: u16,
         quality: super::RdpQuality,
+        custom_performance_flags: Option<super::RdpPerformanceFlags>,
+        codec: super::RdpCodec,
+        keyboard: super::RdpKeyboard,
+        proxy: Option<&ProxyConfig>,
+        pointer_software_rendering: bool,
+        certificates: &CertificatePolicy,
+        gateway: Option<&RdpGateway>,
+        smart_card: Option<&super::RdpSmartCard>,
+        shared_folder: Option<&RdpSharedFolder>,
+        license_cache: Arc<RdpLicenseCache>,
+        control: &ConnectControl,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let addr = format!("{}:{}", host, port);
-        eprintln!("RDP: Connecting to {} as {}...", addr, username);
-
-        // Create TCP connection
-        let tcp_stream = TcpStream::connect(&addr)
-            .map_err(|e| format!("Failed to connect to {}: {}", addr, e))?;
+        let gateway = gateway
+            .filter(|gateway| !gateway.bypasses(host))
+            .map(|gateway| gateway.with_default_credentials(username, password, domain));
+        match &gateway {
+            Some(gateway) => eprintln!(
+                "RDP: Connecting to {}:{} as {} via gateway {}:{}...",
+                host, port, username, gateway.host, gateway.port
+            ),
+            None => eprintln!("RDP: Connecting to {}:{} as {}...", host, port, username),
+        }
 
-        // Use blocking mode during connection handshake (no timeout)
-        tcp_stream
-            .set_read_timeout(None)
-            .map_err(|e| format!("Failed to set read timeout: {}", e))?;
-
-        let client_addr = tcp_stream
-            .local_addr()
-            .map_err(|e| format!("Failed to get local address: {}", e))?;
-
-        // Performance flags based on quality preset
-        use ironrdp_pdu::rdp::client_info::PerformanceFlags;
-        let perf_flags = match quality {
-            super::RdpQuality::Ultra => {
-                // Ultra quality - all visual features enabled
-                PerformanceFlags::ENABLE_FONT_SMOOTHING 
-                    | PerformanceFlags::ENABLE_DESKTOP_COMPOSITION
-            },
-            super::RdpQuality::High => {
-                // High quality - minimal performance flags
-                PerformanceFlags::ENABLE_FONT_SMOOTHING 
-                    | PerformanceFlags::ENABLE_DESKTOP_COMPOSITION
-            },
-            super::RdpQuality::Balanced => {
-                // Balanced - some optimizations but keep visual quality
-                PerformanceFlags::DISABLE_WALLPAPER
-                    | PerformanceFlags::DISABLE_FULLWINDOWDRAG
-                    | PerformanceFlags::ENABLE_FONT_SMOOTHING
-                    | PerformanceFlags::ENABLE_DESKTOP_COMPOSITION
-            },
-            super::RdpQuality::Performance => {
-                // Performance focused - aggressive optimizations
-                PerformanceFlags::DISABLE_WALLPAPER
-                    | PerformanceFlags::DISABLE_FULLWINDOWDRAG
-                    | PerformanceFlags::DISABLE_MENUANIMATIONS
-                    | PerformanceFlags::DISABLE_THEMING
-                    | PerformanceFlags::ENABLE_FONT_SMOOTHING
-            },
-            super::RdpQuality::LowBandwidth => {
-                // Maximum compression for low bandwidth
-                PerformanceFlags::DISABLE_WALLPAPER
-                    | PerformanceFlags::DISABLE_FULLWINDOWDRAG
-                    | PerformanceFlags::DISABLE_MENUANIMATIONS
-                    | PerformanceFlags::DISABLE_THEMING
-                    | PerformanceFlags::DISABLE_CURSORSETTINGS
-            },
-        };
+        let perf_flags = custom_performance_flags
+            .map(|flags| flags.performance_flags())
+            .unwrap_or_else(|| quality.performance_flags());
+        let codec = codec.resolve(quality);
+        let color_depth = quality.color_depth_with(codec);
 
         // Build connector config with optimized settings
-        let config = ironrdp_connector::Config {
-            credentials: Credentials::UsernamePassword {
+        let credentials = match smart_card {
+            Some(card) => card.credentials()?,
+            None => Credentials::UsernamePassword {
                 username: username.to_string(),
                 password: password.to_string(),
             },
+        };
+
+        let config = ironrdp_connector::Config {
+            credentials,
             domain: domain.map(|s| s.to_string()),
             desktop_size: DesktopSize { width, height },
             desktop_scale_factor: 100,
@@ -112,84 +318,82 @@ impl RdpClient {
             enable_credssp: true,
             client_build: 0,
             client_name: "OpenTerm".to_string(),
-            keyboard_type: ironrdp_pdu::gcc::KeyboardType::IbmEnhanced,
+            keyboard_type: keyboard.keyboard_type.to_gcc(),
             keyboard_subtype: 0,
-            keyboard_functional_keys_count: 12,
-            keyboard_layout: 0x409, // US English
+            keyboard_functional_keys_count: keyboard.functional_keys_count,
+            keyboard_layout: keyboard.layout,
             ime_file_name: String::new(),
             bitmap: Some(ironrdp_connector::BitmapConfig {
-                lossy_compression: match quality {
-                    super::RdpQuality::Ultra => false,        // Lossless for ultra quality
-                    super::RdpQuality::High => false,         // Lossless for high quality
-                    super::RdpQuality::Balanced => false,     // Lossless for balanced (NSCodec)
-                    super::RdpQuality::Performance => true,   // Allow lossy for performance
-                    super::RdpQuality::LowBandwidth => true,  // Lossy for bandwidth
-                },
-                color_depth: match quality {
-                    super::RdpQuality::Ultra => 32,        // Full 32-bit color
-                    super::RdpQuality::High => 32,         // Full 32-bit color
-                    super::RdpQuality::Balanced => 24,     // Good 24-bit color
-                    super::RdpQuality::Performance => 16,  // Fast 16-bit color
-                    super::RdpQuality::LowBandwidth => 8,  // Low bandwidth 8-bit
-                },
-                codecs: Self::get_advanced_codecs(quality), // Use advanced codec configuration
+                lossy_compression: quality.lossy_compression(),
+                color_depth,
+                codecs: Self::get_advanced_codecs(codec),
             }),
             dig_product_id: String::new(),
             client_dir: String::new(),
             platform: MajorPlatformType::WINDOWS,
-            hardware_id: None,
+            hardware_id: RdpLicenseCache::hardware_id(),
             request_data: None,
             autologon: true,
             enable_audio_playback: false,
             performance_flags: perf_flags,
-            license_cache: None,
+            license_cache: Some(license_cache),
             timezone_info: ironrdp_pdu::rdp::client_info::TimezoneInfo::default(),
             enable_server_pointer: true,
-            pointer_software_rendering: false,
+            // Draw the cursor into the frame instead of reporting it
+            pointer_software_rendering,
         };
 
-        // Create connector
-        let mut connector = ClientConnector::new(config, client_addr);
-
-        // Create framed transport
-        let mut framed = Framed::new(tcp_stream);
-
-        eprintln!("RDP: Starting connection sequence (before TLS)...");
-
-        // Begin connection (before TLS upgrade)
-        let should_upgrade = ironrdp_blocking::connect_begin(&mut framed, &mut connector)
-            .map_err(|e| format!("Connection begin failed: {:?}", e))?;
-
-        // Get the underlying stream and upgrade to TLS
-        eprintln!("RDP: Upgrading to TLS...");
-        let initial_stream = framed.into_inner_no_leftover();
-
-        // Create TLS connector
-        let tls_connector = native_tls::TlsConnector::builder()
-            .danger_accept_invalid_certs(true) // Accept self-signed certs (common for RDP)
-            .danger_accept_invalid_hostnames(true)
-            .build()
-            .map_err(|e| format!("Failed to create TLS connector: {}", e))?;
-
-        let tls_stream = tls_connector
-            .connect(host, initial_stream)
-            .map_err(|e| format!("TLS handshake failed: {}", e))?;
+        // A pinned certificate is compared by fingerprint instead of validated
+        let verify = !certificates.accept_any && certificates.pinned.is_none();
+        let (mut connector, should_upgrade, tls_stream) =
+            match Self::begin_tls(host, port, proxy, gateway.as_ref(), config.clone(), shared_folder, verify, control) {
+                Ok(begun) => begun,
+                Err(TlsError::Handshake(reason)) if verify => {
+                    // Reconnect without validation just to show the user the certificate
+                    let (_, _, tls_stream) = Self::begin_tls(host, port, proxy, gateway.as_ref(), config, shared_folder, false, control)
+                        .map_err(|_| format!("TLS handshake failed: {}", reason))?;
+                    let certificate = Self::server_certificate(&tls_stream, host, port)?;
+                    return Err(Box::new(UntrustedCertificate { certificate, reason }));
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+        if let (Some(pinned), false) = (&certificates.pinned, certificates.accept_any) {
+            let certificate = Self::server_certificate(&tls_stream, host, port)?;
+            if !certificate.matches(pinned) {
+                return Err(Box::new(UntrustedCertificate {
+                    certificate,
+                    reason: "The certificate has changed since it was trusted".to_string(),
+                }));
+            }
+        }
 
-        // Keep blocking mode for CredSSP/NLA handshake
+        // Keep blocking mode for CredSSP/NLA handshake, bounded by what's left
+        // of the deadline
+        tls_stream
+            .get_ref()
+            .set_read_timeout(Some(control.remaining("CredSSP/NLA")?))
+            .map_err(|e| format!("Failed to set read timeout: {}", e))?;
 
         // Get server public key from TLS certificate
         let server_public_key = Self::extract_server_public_key(&tls_stream)?;
 
-        let mut tls_framed = Framed::new(tls_stream);
+        let mut tls_framed = Framed::new(StageWatch {
+            stream: tls_stream,
+            control,
+            reported: false,
+            mid_message: false,
+        });
 
         // Mark as upgraded
         let upgraded = ironrdp_blocking::mark_as_upgraded(should_upgrade, &mut connector);
 
         eprintln!("RDP: Finalizing connection (CredSSP/NLA)...");
+        control.stage(RdpConnectStage::Authenticating);
 
         // Create a no-op network client for CredSSP (we don't do Kerberos)
         let mut network_client = NoopNetworkClient;
-        let server_name = ServerName::new(host.to_string());
+        let server_name = ServerName::new(crate::util::unbracket_host(host).to_string());
 
         // Finalize connection
         let connection_result = ironrdp_blocking::connect_finalize(
@@ -201,7 +405,7 @@ impl RdpClient {
             server_public_key,
             None, // No Kerberos config
         )
-        .map_err(|e| format!("Connection finalize failed: {:?}", e))?;
+        .map_err(|e| ConnectionFinalizeError::new(&e, tls_framed.get_inner().0.reported))?;
 
         let desktop_size = connection_result.desktop_size;
         eprintln!(
@@ -212,7 +416,7 @@ impl RdpClient {
         // NOW switch to short read timeout for responsive input handling
         // This is safe because the connection handshake is complete
         // We need to extract the stream, set timeout, and re-wrap it
-        let tls_stream = tls_framed.into_inner_no_leftover();
+        let tls_stream = tls_framed.into_inner_no_leftover().stream;
         if let Err(e) = tls_stream.get_ref().set_read_timeout(Some(Duration::from_millis(50))) {
             eprintln!("RDP: Warning - failed to set read timeout: {}", e);
         }
@@ -235,14 +439,124 @@ impl RdpClient {
                 domain: domain.map(|s| s.to_string()),
             },
             connected: Arc::new(AtomicBool::new(true)),
-            width: desktop_size.width,
-            height: desktop_size.height,
+            size: Mutex::new((desktop_size.width, desktop_size.height)),
+            pending_resize: Mutex::new(None),
+            pending_pointer: Mutex::new(Vec::new()),
+            pointer_shape: Mutex::new(None),
+            input_regions: Mutex::new(Vec::new()),
+            codec,
+            color_depth,
+            quality: Mutex::new(quality),
+            hidden: AtomicBool::new(false),
+            output_suppressed: AtomicBool::new(false),
+            codec_failed: AtomicBool::new(false),
+            decode_stats: Mutex::new(DecodeStats {
+                since: Instant::now(),
+                bytes: 0,
+                decoding: Duration::ZERO,
+            }),
+            input_batcher: Mutex::new(super::InputBatcher::new()),
+            input_flush_scheduled: AtomicBool::new(false),
+            disconnect: Mutex::new(None),
         })
     }
 
+    /// Connect (through `gateway` if given), run the connection sequence up
+    /// to the security upgrade and do the TLS handshake, validating the
+    /// server certificate if `verify`. `shared_folder` is offered to the
+    /// server as a redirected drive.
+    fn begin_tls(
+        host: &str,
+        port: u16,
+        proxy: Option<&ProxyConfig>,
+        gateway: Option<&RdpGateway>,
+        config: ironrdp_connector::Config,
+        shared_folder: Option<&RdpSharedFolder>,
+        verify: bool,
+        control: &ConnectControl,
+    ) -> Result<(ClientConnector, ironrdp_blocking::ShouldUpgrade, native_tls::TlsStream<RdpTransport>), TlsError> {
+        let addr = crate::util::host_port(host, port);
+
+        // Create TCP connection, or the gateway tunnel
+        let timeout = control.remaining("connecting")?;
+        control.stage(RdpConnectStage::Connecting);
+        let transport = match gateway {
+            Some(gateway) => RdpTransport::Gateway(Box::new(GatewayStream::connect(gateway, host, port, proxy, timeout)?)),
+            None => RdpTransport::Direct(
+                crate::proxy::connect_timeout(host, port, proxy, timeout)
+                    .map_err(|e| format!("Failed to connect to {}: {}", addr, e))?,
+            ),
+        };
+
+        // Use blocking mode during connection handshake, up to the deadline
+        transport
+            .set_read_timeout(Some(control.remaining("the connection sequence")?))
+            .map_err(|e| format!("Failed to set read timeout: {}", e))?;
+
+        let client_addr = transport
+            .local_addr()
+            .map_err(|e| format!("Failed to get local address: {}", e))?;
+
+        // Create connector, with the clipboard channel for copy/paste and the
+        // Display Control channel for resizing the desktop
+        let display_control = DisplayControlClient::new(|_| Ok(Vec::new()));
+        let mut connector = ClientConnector::new(config, client_addr)
+            .with_static_channel(ClipboardChannel::new())
+            .with_static_channel(DrdynvcClient::new().with_dynamic_channel(display_control));
+        if let Some(folder) = shared_folder {
+            connector = connector
+                .with_static_channel(DriveChannel::new(folder)?)
+                .with_static_channel(SoundChannel);
+        }
+
+        // Create framed transport
+        let mut framed = Framed::new(transport);
+
+        eprintln!("RDP: Starting connection sequence (before TLS)...");
+
+        // Begin connection (before TLS upgrade)
+        let should_upgrade = ironrdp_blocking::connect_begin(&mut framed, &mut connector)
+            .map_err(|e| format!("Connection begin failed: {:?}", e))?;
+
+        // Get the underlying stream and upgrade to TLS
+        eprintln!("RDP: Upgrading to TLS...");
+        let initial_stream = framed.into_inner_no_leftover();
+        initial_stream
+            .set_read_timeout(Some(control.remaining("the TLS handshake")?))
+            .map_err(|e| format!("Failed to set read timeout: {}", e))?;
+
+        control.stage(RdpConnectStage::TlsHandshake);
+        let tls_connector = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(!verify)
+            .danger_accept_invalid_hostnames(!verify)
+            .build()
+            .map_err(|e| format!("Failed to create TLS connector: {}", e))?;
+
+        let tls_stream = tls_connector.connect(crate::util::unbracket_host(host), initial_stream).map_err(|e| match e {
+            native_tls::HandshakeError::Failure(e) => TlsError::Handshake(e.to_string()),
+            e => TlsError::Other(format!("TLS handshake failed: {}", e)),
+        })?;
+
+        Ok((connector, should_upgrade, tls_stream))
+    }
+
+    fn server_certificate(
+        tls_stream: &native_tls::TlsStream<RdpTransport>,
+        host: &str,
+        port: u16,
+    ) -> Result<ServerCertificate, String> {
+        let der = tls_stream
+            .peer_certificate()
+            .map_err(|e| format!("Failed to get peer certificate: {}", e))?
+            .ok_or_else(|| "No peer certificate available".to_string())?
+            .to_der()
+            .map_err(|e| format!("Failed to get DER certificate: {}", e))?;
+        ServerCertificate::from_der(host, port, &der)
+    }
+
     /// Extract server's public key from TLS certificate
     fn extract_server_public_key(
-        tls_stream: &native_tls::TlsStream<TcpStream>,
+        tls_stream: &native_tls::TlsStream<RdpTransport>,
     ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
         use x509_cert::der::Decode;
         
@@ -273,8 +587,8 @@ impl RdpClient {
     }
 
     /// Process incoming RDP events and update the framebuffer
-    /// Returns dirty rectangles if there were updates
-    pub fn process_events(&self) -> Result<Option<Vec<super::DirtyRect>>, String> {
+    /// Returns the changed regions if there were updates
+    pub fn process_events(&self) -> Result<Option<Vec<Region>>, String> {
         if !self.is_connected() {
             return Ok(None);
         }
@@ -292,7 +606,8 @@ impl RdpClient {
                 if e.kind() == std::io::ErrorKind::WouldBlock
                     || e.kind() == std::io::ErrorKind::TimedOut
                 {
-                    return Ok(None);
+                    let regions = std::mem::take(&mut *self.input_regions.lock());
+                    return Ok(if regions.is_empty() { None } else { Some(regions) });
                 }
                 // Connection closed
                 if e.kind() == std::io::ErrorKind::UnexpectedEof
@@ -309,14 +624,22 @@ impl RdpClient {
         let outputs = {
             let mut active_stage = self.active_stage.lock();
             let mut image = self.image.lock();
-            active_stage
-                .process(&mut *image, action, &payload)
-                .map_err(|e| format!("Failed to process PDU: {:?}", e))?
+            let started = Instant::now();
+            let outputs = active_stage.process(&mut image, action, &payload);
+            let mut stats = self.decode_stats.lock();
+            stats.bytes += payload.len() as u64;
+            stats.decoding += started.elapsed();
+            drop(stats);
+            outputs.map_err(|e| {
+                if self.codec == super::RdpCodec::RemoteFx {
+                    self.codec_failed.store(true, Ordering::SeqCst);
+                }
+                format!("Failed to process PDU: {:?}", e)
+            })?
         };
 
-        let mut frame_updated = false;
         let mut responses: Vec<Vec<u8>> = Vec::new();
-        let mut dirty_rects: Vec<super::DirtyRect> = Vec::new();
+        let mut dirty_regions = std::mem::take(&mut *self.input_regions.lock());
 
         for output in outputs {
             match output {
@@ -325,55 +648,43 @@ impl RdpClient {
                     responses.push(frame);
                 }
                 ActiveStageOutput::GraphicsUpdate(region) => {
-                    // Graphics were updated - capture the dirty region
-                    frame_updated = true;
-                    
-                    // Extract region data from image buffer
-                    let image = self.image.lock();
-                    let full_data = image.data();
-                    let full_width = self.width as usize;
-                    
-                    let x = region.left as usize;
-                    let y = region.top as usize;
-                    let w = (region.right - region.left) as usize;
-                    let h = (region.bottom - region.top) as usize;
-                    
-                    // Extract just the dirty region pixels
-                    let mut rect_data = Vec::with_capacity(w * h * 4);
-                    for row in y..(y + h) {
-                        let start = (row * full_width + x) * 4;
-                        let end = start + w * 4;
-                        if end <= full_data.len() {
-                            rect_data.extend_from_slice(&full_data[start..end]);
-                        }
-                    }
-                    
-                    // Use Base64-encoded DirtyRect
-                    dirty_rects.push(super::DirtyRect::new(
-                        region.left as u16,
-                        region.top as u16,
-                        w as u16,
-                        h as u16,
-                        &rect_data,
+                    // Pixels are copied out when the update is emitted
+                    dirty_regions.push(Region::new(
+                        region.left,
+                        region.top,
+                        region.width(),
+                        region.height(),
                     ));
                 }
-                ActiveStageOutput::PointerDefault | ActiveStageOutput::PointerHidden => {
-                    // Pointer updates
+                ActiveStageOutput::PointerDefault => {
+                    self.push_pointer(RdpPointer::Default);
                 }
-                ActiveStageOutput::PointerPosition { .. } => {
-                    // Pointer position update
+                ActiveStageOutput::PointerHidden => {
+                    self.push_pointer(RdpPointer::Hidden);
                 }
-                ActiveStageOutput::PointerBitmap(_) => {
-                    // Custom cursor bitmap
+                ActiveStageOutput::PointerPosition { x, y } => {
+                    self.push_pointer(RdpPointer::Position { x, y });
+                }
+                ActiveStageOutput::PointerBitmap(pointer) => {
+                    self.push_pointer(RdpPointer::from(&*pointer));
                 }
                 ActiveStageOutput::Terminate(reason) => {
                     eprintln!("RDP: Session terminated: {:?}", reason);
+                    *self.disconnect.lock() = Some(super::RdpDisconnect::from_reason(&reason));
                     self.connected.store(false, Ordering::SeqCst);
                     return Ok(None);
                 }
-                ActiveStageOutput::DeactivateAll(_reactivation) => {
-                    eprintln!("RDP: Deactivation requested");
-                    // Could handle reactivation here
+                ActiveStageOutput::DeactivateAll(activation) => {
+                    eprintln!("RDP: Deactivation requested, reactivating");
+                    // Answer what came before, then rerun the capability exchange
+                    let mut framed = self.framed.lock();
+                    for frame in responses.drain(..) {
+                        framed
+                            .write_all(&frame)
+                            .map_err(|e| format!("Failed to write response: {}", e))?;
+                    }
+                    drop(framed);
+                    self.reactivate(activation)?;
                 }
             }
         }
@@ -388,153 +699,330 @@ impl RdpClient {
             }
         }
 
-        // Return dirty rectangles if there were updates
-        Ok(if frame_updated && !dirty_rects.is_empty() {
-            Some(dirty_rects)
-        } else {
+        Ok(if dirty_regions.is_empty() {
             None
+        } else {
+            Some(dirty_regions)
         })
     }
 
-    /// Send mouse movement event
-    pub fn send_mouse_move(&self, x: u16, y: u16) -> Result<(), String> {
-        if !self.is_connected() {
-            return Err("Not connected".to_string());
+    /// Why the server ended the session, if it did
+    pub fn take_disconnect(&self) -> Option<super::RdpDisconnect> {
+        self.disconnect.lock().take()
+    }
+
+    fn push_pointer(&self, pointer: RdpPointer) {
+        let mut pending = self.pending_pointer.lock();
+        if let RdpPointer::Position { .. } = pointer {
+            // Only the latest position matters
+            if let Some(RdpPointer::Position { .. }) = pending.last() {
+                pending.pop();
+            }
+        } else {
+            *self.pointer_shape.lock() = Some(pointer.clone());
         }
+        pending.push(pointer);
+    }
 
-        use ironrdp_pdu::input::fast_path::FastPathInputEvent;
-        use ironrdp_pdu::input::mouse::{MousePdu, PointerFlags};
+    /// Cursor changes since the last call, in order
+    pub fn take_pointer_events(&self) -> Vec<RdpPointer> {
+        std::mem::take(&mut *self.pending_pointer.lock())
+    }
 
-        let event = FastPathInputEvent::MouseEvent(MousePdu {
-            flags: PointerFlags::MOVE,
-            number_of_wheel_rotation_units: 0,
-            x_position: x,
-            y_position: y,
-        });
+    /// Current cursor shape, if the server has set one
+    pub fn pointer_shape(&self) -> Option<RdpPointer> {
+        self.pointer_shape.lock().clone()
+    }
 
-        self.send_fastpath_input(vec![event])
+    /// Run the Deactivation-Reactivation sequence, which servers start e.g.
+    /// after a resize, and switch to the desktop size it settles on
+    fn reactivate(&self, mut activation: Box<ConnectionActivationSequence>) -> Result<(), String> {
+        let deadline = Instant::now() + REACTIVATION_TIMEOUT;
+        let mut buf = WriteBuf::new();
+        let mut framed = self.framed.lock();
+
+        let (io_channel_id, user_channel_id, desktop_size, enable_server_pointer, pointer_software_rendering) = loop {
+            buf.clear();
+            let written = match activation.next_pdu_hint() {
+                Some(hint) => {
+                    let pdu = match framed.read_by_hint(hint) {
+                        Ok(pdu) => pdu,
+                        Err(e)
+                            if (e.kind() == std::io::ErrorKind::WouldBlock
+                                || e.kind() == std::io::ErrorKind::TimedOut)
+                                && Instant::now() < deadline =>
+                        {
+                            continue;
+                        }
+                        Err(e) => {
+                            self.connected.store(false, Ordering::SeqCst);
+                            return Err(format!("Reactivation failed: {}", e));
+                        }
+                    };
+                    activation.step(&pdu, &mut buf)
+                }
+                None => activation.step_no_input(&mut buf),
+            }
+            .map_err(|e| format!("Reactivation failed: {:?}", e))?;
+
+            if let Some(len) = written.size() {
+                framed
+                    .write_all(&buf[..len])
+                    .map_err(|e| format!("Failed to write response: {}", e))?;
+            }
+
+            if let ConnectionActivationState::Finalized {
+                io_channel_id,
+                user_channel_id,
+                desktop_size,
+                enable_server_pointer,
+                pointer_software_rendering,
+            } = activation.connection_activation_state()
+            {
+                break (
+                    io_channel_id,
+                    user_channel_id,
+                    desktop_size,
+                    enable_server_pointer,
+                    pointer_software_rendering,
+                );
+            }
+        };
+        drop(framed);
+
+        let mut active_stage = self.active_stage.lock();
+        active_stage.set_fastpath_processor(
+            ProcessorBuilder {
+                io_channel_id,
+                user_channel_id,
+                enable_server_pointer,
+                pointer_software_rendering,
+            }
+            .build(),
+        );
+        active_stage.set_enable_server_pointer(enable_server_pointer);
+
+        let (width, height) = (desktop_size.width, desktop_size.height);
+        *self.image.lock() = DecodedImage::new(PixelFormat::RgbA32, width, height);
+        *self.size.lock() = (width, height);
+        *self.pending_resize.lock() = Some((width, height));
+        eprintln!("RDP: Reactivated at {}x{}", width, height);
+        Ok(())
     }
 
-    /// Send mouse button event
-    pub fn send_mouse_button(&self, button: u8, down: bool, x: u16, y: u16) -> Result<(), String> {
+    /// Ask the server to change the desktop size over the Display Control
+    /// channel. The new size applies once the server reactivates the session.
+    pub fn request_resize(&self, width: u16, height: u16) -> Result<(), String> {
         if !self.is_connected() {
             return Err("Not connected".to_string());
         }
 
-        use ironrdp_pdu::input::fast_path::FastPathInputEvent;
-        use ironrdp_pdu::input::mouse::{MousePdu, PointerFlags};
-
-        let mut flags = if down {
-            PointerFlags::DOWN
-        } else {
-            PointerFlags::empty()
-        };
-
-        // Map button to flags
-        flags |= match button {
-            1 => PointerFlags::LEFT_BUTTON,
-            2 => PointerFlags::RIGHT_BUTTON,
-            3 => PointerFlags::MIDDLE_BUTTON_OR_WHEEL,
-            _ => PointerFlags::LEFT_BUTTON,
+        let (width, height) = MonitorLayoutEntry::adjust_display_size(width as u32, height as u32);
+        let frame = {
+            let mut active_stage = self.active_stage.lock();
+            // The server opens the channel only if it supports dynamic resolution
+            let ready = active_stage
+                .get_dvc::<DisplayControlClient>()
+                .and_then(|dvc| dvc.channel_processor_downcast_ref::<DisplayControlClient>())
+                .is_some_and(|display_control| display_control.ready());
+            if !ready {
+                return Err(RESIZE_NOT_SUPPORTED.to_string());
+            }
+            active_stage
+                .encode_resize(width, height, None, None)
+                .ok_or_else(|| RESIZE_NOT_SUPPORTED.to_string())?
+                .map_err(|e| format!("Failed to encode resize: {:?}", e))?
         };
 
-        let event = FastPathInputEvent::MouseEvent(MousePdu {
-            flags,
-            number_of_wheel_rotation_units: 0,
-            x_position: x,
-            y_position: y,
-        });
+        self.framed
+            .lock()
+            .write_all(&frame)
+            .map_err(|e| format!("Failed to send resize: {}", e))
+    }
 
-        self.send_fastpath_input(vec![event])
+    /// New desktop size since the last call, if the server changed it
+    pub fn take_resize(&self) -> Option<(u16, u16)> {
+        self.pending_resize.lock().take()
     }
 
-    /// Send mouse wheel event
-    pub fn send_mouse_wheel(&self, delta: i16, x: u16, y: u16) -> Result<(), String> {
+    /// Queue an input event, sending whatever the batcher releases. Clicks,
+    /// keys and text go out at once together with anything queued before
+    /// them; mouse moves are collapsed to the latest position and sent at
+    /// most every batch interval.
+    pub fn queue_input(self: &Arc<Self>, event: super::InputEvent) -> Result<(), String> {
         if !self.is_connected() {
             return Err("Not connected".to_string());
         }
 
-        use ironrdp_pdu::input::fast_path::FastPathInputEvent;
-        use ironrdp_pdu::input::mouse::{MousePdu, PointerFlags};
+        let events = {
+            let mut batcher = self.input_batcher.lock();
+            if batcher.add_event(event) {
+                batcher.flush()
+            } else {
+                if let Some(wait) = batcher.time_until_flush() {
+                    self.schedule_input_flush(wait);
+                }
+                Vec::new()
+            }
+        };
+        self.send_input_events(events)
+    }
 
-        let mut flags = PointerFlags::VERTICAL_WHEEL;
-        if delta < 0 {
-            flags |= PointerFlags::WHEEL_NEGATIVE;
+    /// Send what the batcher holds back once its window has passed, even if
+    /// no further input comes to push it out
+    fn schedule_input_flush(self: &Arc<Self>, mut wait: Duration) {
+        if self.input_flush_scheduled.swap(true, Ordering::SeqCst) {
+            return;
         }
-
-        let event = FastPathInputEvent::MouseEvent(MousePdu {
-            flags,
-            number_of_wheel_rotation_units: delta,
-            x_position: x,
-            y_position: y,
+        let client = Arc::downgrade(self);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(wait);
+            let Some(client) = client.upgrade() else {
+                return;
+            };
+            let (events, next) = {
+                let mut batcher = client.input_batcher.lock();
+                let events = batcher.maybe_flush();
+                let next = batcher.time_until_flush().filter(|_| client.is_connected());
+                if next.is_none() {
+                    client.input_flush_scheduled.store(false, Ordering::SeqCst);
+                }
+                (events, next)
+            };
+            if client.is_connected() {
+                if let Err(e) = client.send_input_events(events) {
+                    eprintln!("RDP: Failed to flush input: {}", e);
+                }
+            }
+            match next {
+                Some(next) => wait = next,
+                None => return,
+            }
         });
+    }
 
-        self.send_fastpath_input(vec![event])
+    fn send_input_events(&self, events: Vec<super::InputEvent>) -> Result<(), String> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let events: Vec<_> = events.iter().flat_map(fastpath_input_events).collect();
+        self.send_fastpath_input(&events)
     }
 
-    /// Send keyboard event
-    pub fn send_keyboard(&self, scancode: u16, down: bool) -> Result<(), String> {
+    /// Type `text` independent of the server's keyboard layout; see
+    /// `text_key_events`
+    pub fn send_unicode(&self, text: &str) -> Result<(), String> {
         if !self.is_connected() {
             return Err("Not connected".to_string());
         }
-
-        use ironrdp_pdu::input::fast_path::{FastPathInputEvent, KeyboardFlags};
-
-        let mut flags = KeyboardFlags::empty();
-        if !down {
-            flags |= KeyboardFlags::RELEASE;
+        if text.is_empty() {
+            return Ok(());
         }
 
-        // Handle extended keys (scancodes > 0x7F typically need extended flag)
-        let scancode = if scancode > 0x7F {
-            flags |= KeyboardFlags::EXTENDED;
-            scancode as u8
-        } else {
-            scancode as u8
-        };
-
-        let event = FastPathInputEvent::KeyboardEvent(flags, scancode);
+        self.send_fastpath_input(&text_key_events(text))
+    }
 
-        self.send_fastpath_input(vec![event])
+    /// Send `events` in as many FastPath input PDUs as they need
+    fn send_fastpath_input(&self, events: &[ironrdp_pdu::input::fast_path::FastPathInputEvent]) -> Result<(), String> {
+        for pdu in events.chunks(MAX_FASTPATH_INPUT_EVENTS) {
+            self.send_fastpath_pdu(pdu)?;
+        }
+        Ok(())
     }
 
-    fn send_fastpath_input(
-        &self,
-        events: Vec<ironrdp_pdu::input::fast_path::FastPathInputEvent>,
-    ) -> Result<(), String> {
+    fn send_fastpath_pdu(&self, events: &[ironrdp_pdu::input::fast_path::FastPathInputEvent]) -> Result<(), String> {
         // Process input events
         let outputs = {
             let mut active_stage = self.active_stage.lock();
             let mut image = self.image.lock();
             active_stage
-                .process_fastpath_input(&mut *image, &events)
+                .process_fastpath_input(&mut image, events)
                 .map_err(|e| format!("Failed to process input: {:?}", e))?
         };
 
         // Send responses (separate lock scope)
         let mut framed = self.framed.lock();
         for output in outputs {
-            if let ActiveStageOutput::ResponseFrame(frame) = output {
-                framed
-                    .write_all(&frame)
-                    .map_err(|e| format!("Failed to send input: {}", e))?;
+            match output {
+                ActiveStageOutput::ResponseFrame(frame) => {
+                    framed
+                        .write_all(&frame)
+                        .map_err(|e| format!("Failed to send input: {}", e))?;
+                }
+                // Software-rendered cursor moved; picked up by process_events
+                ActiveStageOutput::GraphicsUpdate(region) => {
+                    self.input_regions.lock().push(Region::new(
+                        region.left,
+                        region.top,
+                        region.width(),
+                        region.height(),
+                    ));
+                }
+                _ => {}
             }
         }
 
         Ok(())
     }
 
+    /// Put `text` on the remote clipboard
+    pub fn set_clipboard(&self, text: String) -> Result<(), String> {
+        if !self.is_connected() {
+            return Err("Not connected".to_string());
+        }
+
+        let frame = {
+            let mut active_stage = self.active_stage.lock();
+            let messages = active_stage
+                .get_svc_processor_mut::<ClipboardChannel>()
+                .ok_or_else(|| "Clipboard channel not available".to_string())?
+                .set_local_text(text);
+            if messages.is_empty() {
+                // Announced once the server's clipboard is ready
+                return Ok(());
+            }
+            active_stage
+                .process_svc_processor_messages(SvcProcessorMessages::<ClipboardChannel>::new(messages))
+                .map_err(|e| format!("Failed to encode clipboard data: {:?}", e))?
+        };
+
+        self.framed
+            .lock()
+            .write_all(&frame)
+            .map_err(|e| format!("Failed to send clipboard data: {}", e))
+    }
+
+    /// Text copied on the remote side since the last call
+    pub fn take_clipboard(&self) -> Option<String> {
+        self.active_stage
+            .lock()
+            .get_svc_processor_mut::<ClipboardChannel>()?
+            .take_remote_text()
+    }
+
+    /// Copy the current pixels of `regions` out of the framebuffer. Regions
+    /// are clamped to the desktop, so each rect's data matches its size.
+    pub fn dirty_rects(&self, regions: &[Region]) -> Vec<super::DirtyRect> {
+        let image = self.image.lock();
+        let (width, height) = (image.width(), image.height());
+        regions
+            .iter()
+            .filter_map(|r| r.clamp(width, height))
+            .map(|r| super::DirtyRect::from_framebuffer(&r, image.data(), width))
+            .collect()
+    }
+
     pub fn get_frame(&self) -> Vec<u8> {
         let image = self.image.lock();
         image.data().to_vec()
     }
 
     pub fn width(&self) -> u16 {
-        self.width
+        self.size.lock().0
     }
 
     pub fn height(&self) -> u16 {
-        self.height
+        self.size.lock().1
     }
 
     pub fn is_connected(&self) -> bool {
@@ -549,11 +1037,284 @@ impl RdpClient {
         &self.connection_info
     }
 
-    /// Configure codecs with conservative settings for stability
-    fn get_advanced_codecs(quality: super::RdpQuality) -> ironrdp_pdu::rdp::capability_sets::BitmapCodecs {
-        // Use default codecs for now to ensure compatibility
-        // Advanced codec configuration can cause issues with some servers
-        ironrdp_pdu::rdp::capability_sets::BitmapCodecs::default()
+    /// Codec in use on this connection
+    pub fn codec(&self) -> super::RdpCodec {
+        self.codec
+    }
+
+    /// Bandwidth and decode time of the server's output so far
+    pub fn codec_stats(&self) -> RdpCodecStats {
+        let stats = self.decode_stats.lock();
+        let seconds = stats.since.elapsed().as_secs_f64();
+        let decode_ms = stats.decoding.as_secs_f64() * 1000.0;
+        RdpCodecStats {
+            codec: self.codec,
+            seconds,
+            bytes_received: stats.bytes,
+            kbps: if seconds > 0.0 { stats.bytes as f64 * 8.0 / 1000.0 / seconds } else { 0.0 },
+            decode_ms,
+            decode_cpu_percent: if seconds > 0.0 { decode_ms / 10.0 / seconds } else { 0.0 },
+        }
+    }
+
+    /// Color depth in use on this connection
+    pub fn color_depth(&self) -> u32 {
+        self.color_depth
+    }
+
+    /// Preset for frame pacing and output suppression
+    pub fn quality(&self) -> super::RdpQuality {
+        *self.quality.lock()
+    }
+
+    /// Switch the live settings to `quality`. Color depth and codecs stay as
+    /// negotiated until the next connection.
+    pub fn set_quality(&self, quality: super::RdpQuality) -> Result<(), String> {
+        *self.quality.lock() = quality;
+        self.update_output()
+    }
+
+    /// Record whether the viewer can be seen; a hidden viewer stops display
+    /// updates if the quality preset says so
+    pub fn set_visible(&self, visible: bool) -> Result<(), String> {
+        self.hidden.store(!visible, Ordering::SeqCst);
+        self.update_output()
+    }
+
+    /// Send a Suppress Output PDU if whether updates should flow has changed.
+    /// Allowing them again makes the server redraw the whole desktop.
+    fn update_output(&self) -> Result<(), String> {
+        // A reconnect starts with updates allowed
+        if !self.is_connected() {
+            return Ok(());
+        }
+
+        let suppress = self.hidden.load(Ordering::SeqCst) && self.quality().suppress_when_hidden();
+        if self.output_suppressed.swap(suppress, Ordering::SeqCst) == suppress {
+            return Ok(());
+        }
+
+        let (width, height) = *self.size.lock();
+        let pdu = SuppressOutputPdu {
+            desktop_rect: (!suppress).then(|| InclusiveRectangle {
+                left: 0,
+                top: 0,
+                right: width.saturating_sub(1),
+                bottom: height.saturating_sub(1),
+            }),
+        };
+        let mut buf = WriteBuf::new();
+        let len = self
+            .active_stage
+            .lock()
+            .encode_static(&mut buf, ShareDataPdu::SuppressOutput(pdu))
+            .map_err(|e| format!("Failed to encode suppress output: {:?}", e))?;
+
+        self.framed
+            .lock()
+            .write_all(&buf[..len])
+            .map_err(|e| format!("Failed to send suppress output: {}", e))
+    }
+
+    /// Whether the session broke while decoding RemoteFX, so a reconnect
+    /// should fall back to plain bitmaps
+    pub fn codec_failed(&self) -> bool {
+        self.codec_failed.load(Ordering::SeqCst)
+    }
+
+    /// Bitmap codecs capability set for `codec`
+    fn get_advanced_codecs(codec: super::RdpCodec) -> BitmapCodecs {
+        match codec {
+            super::RdpCodec::RemoteFx => BitmapCodecs(vec![Codec {
+                id: CLIENT_CODEC_ID_REMOTEFX,
+                property: CodecProperty::RemoteFx(RemoteFxContainer::ClientContainer(RfxClientCapsContainer {
+                    capture_flags: CaptureFlags::empty(),
+                    // RLGR3 is what Windows clients advertise; IronRDP decodes both
+                    caps_data: RfxCaps(RfxCapset(vec![RfxICap {
+                        flags: RfxICapFlags::empty(),
+                        entropy_bits: EntropyBits::Rlgr3,
+                    }])),
+                })),
+            }]),
+            _ => BitmapCodecs::default(),
+        }
+    }
+}
+
+/// FastPath events for one input event
+fn fastpath_input_events(event: &super::InputEvent) -> Vec<ironrdp_pdu::input::fast_path::FastPathInputEvent> {
+    use super::InputEvent;
+    use ironrdp_pdu::input::fast_path::{FastPathInputEvent, KeyboardFlags};
+    use ironrdp_pdu::input::mouse::{MousePdu, PointerFlags};
+
+    let mouse = |flags, number_of_wheel_rotation_units, x_position, y_position| {
+        FastPathInputEvent::MouseEvent(MousePdu {
+            flags,
+            number_of_wheel_rotation_units,
+            x_position,
+            y_position,
+        })
+    };
+
+    match *event {
+        InputEvent::MouseMove { x, y } => vec![mouse(PointerFlags::MOVE, 0, x, y)],
+        InputEvent::MouseButton { button, down, x, y } => {
+            let mut flags = if down {
+                PointerFlags::DOWN
+            } else {
+                PointerFlags::empty()
+            };
+
+            // Map button to flags
+            flags |= match button {
+                super::input::MOUSE_BUTTON_LEFT => PointerFlags::LEFT_BUTTON,
+                super::input::MOUSE_BUTTON_RIGHT => PointerFlags::RIGHT_BUTTON,
+                super::input::MOUSE_BUTTON_MIDDLE => PointerFlags::MIDDLE_BUTTON_OR_WHEEL,
+                _ => PointerFlags::LEFT_BUTTON,
+            };
+            vec![mouse(flags, 0, x, y)]
+        }
+        InputEvent::MouseWheel { delta, x, y } => {
+            let mut flags = PointerFlags::VERTICAL_WHEEL;
+            if delta < 0 {
+                flags |= PointerFlags::WHEEL_NEGATIVE;
+            }
+            vec![mouse(flags, delta, x, y)]
+        }
+        InputEvent::Keyboard { scancode, down } => {
+            let mut flags = KeyboardFlags::empty();
+            if !down {
+                flags |= KeyboardFlags::RELEASE;
+            }
+            // Handle extended keys (scancodes > 0x7F typically need extended flag)
+            if scancode > 0x7F {
+                flags |= KeyboardFlags::EXTENDED;
+            }
+            vec![FastPathInputEvent::KeyboardEvent(flags, scancode as u8)]
+        }
+        InputEvent::Text { ref text } => text_key_events(text),
+    }
+}
+
+/// Key events that type `text`: line breaks (CRLF counting as one) and tabs
+/// as the Enter and Tab keys, everything else as a Unicode press/release
+/// pair per UTF-16 unit
+fn text_key_events(text: &str) -> Vec<ironrdp_pdu::input::fast_path::FastPathInputEvent> {
+    use ironrdp_pdu::input::fast_path::{FastPathInputEvent, KeyboardFlags};
+
+    let mut events = Vec::with_capacity(text.len() * 2);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let scancode = match c {
+            '\r' => {
+                chars.next_if_eq(&'\n');
+                Some(0x1C)
+            }
+            '\n' => Some(0x1C),
+            '\t' => Some(0x0F),
+            _ => None,
+        };
+        match scancode {
+            Some(code) => events.extend([
+                FastPathInputEvent::KeyboardEvent(KeyboardFlags::empty(), code),
+                FastPathInputEvent::KeyboardEvent(KeyboardFlags::RELEASE, code),
+            ]),
+            None => {
+                let mut units = [0u16; 2];
+                for &unit in c.encode_utf16(&mut units).iter() {
+                    events.extend([
+                        FastPathInputEvent::UnicodeKeyboardEvent(KeyboardFlags::empty(), unit),
+                        FastPathInputEvent::UnicodeKeyboardEvent(KeyboardFlags::RELEASE, unit),
+                    ]);
+                }
+            }
+        }
+    }
+    events
+}
+
+/// Phase of the connection sequence after the security upgrade that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum FinalizeFailure {
+    /// CredSSP/NLA: the server refused the credentials, or the connection
+    /// broke before it accepted them
+    Authentication,
+    /// The server turned down the license offered or broke off licensing
+    License,
+    /// Capability exchange or connection finalization; may be retried with
+    /// fewer codecs
+    Capabilities,
+}
+
+/// The connection sequence failed after the security upgrade
+#[derive(Debug)]
+pub(super) struct ConnectionFinalizeError {
+    failure: FinalizeFailure,
+    message: String,
+}
+
+impl ConnectionFinalizeError {
+    /// `past_credssp`: the client had moved on to licensing and capabilities
+    fn new(error: &ironrdp_connector::ConnectorError, past_credssp: bool) -> Self {
+        use ironrdp_connector::ConnectorErrorKind;
+        use std::error::Error as _;
+
+        let failure = if !past_credssp
+            || matches!(error.kind(), ConnectorErrorKind::Credssp(_) | ConnectorErrorKind::AccessDenied)
+        {
+            FinalizeFailure::Authentication
+        } else if error.source().is_some_and(|source| source.is::<ServerLicenseError>()) {
+            FinalizeFailure::License
+        } else {
+            FinalizeFailure::Capabilities
+        };
+        Self {
+            failure,
+            message: format!("{:?}", error),
+        }
+    }
+
+    pub(super) fn failure(&self) -> FinalizeFailure {
+        self.failure
+    }
+}
+
+impl std::fmt::Display for ConnectionFinalizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Connection finalize failed: {}", self.message)
+    }
+}
+
+impl std::error::Error for ConnectionFinalizeError {}
+
+/// Failure while setting up TLS
+enum TlsError {
+    /// The TLS handshake was rejected, e.g. the certificate didn't validate
+    Handshake(String),
+    /// The RD Gateway refused the credentials or couldn't reach the server
+    Gateway(GatewayError),
+    Other(String),
+}
+
+impl From<GatewayError> for TlsError {
+    fn from(e: GatewayError) -> Self {
+        TlsError::Gateway(e)
+    }
+}
+
+impl From<String> for TlsError {
+    fn from(e: String) -> Self {
+        TlsError::Other(e)
+    }
+}
+
+impl From<TlsError> for Box<dyn std::error::Error + Send + Sync> {
+    fn from(e: TlsError) -> Self {
+        match e {
+            TlsError::Handshake(e) => format!("TLS handshake failed: {}", e).into(),
+            TlsError::Gateway(e) => Box::new(e),
+            TlsError::Other(e) => e.into(),
+        }
     }
 }
 
@@ -573,3 +1334,52 @@ impl ironrdp_connector::sspi::network_client::NetworkClient for NoopNetworkClien
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ironrdp_pdu::input::fast_path::{FastPathInputEvent, KeyboardFlags};
+
+    #[test]
+    fn text_maps_line_breaks_and_tabs_to_keys() {
+        let events = text_key_events("a\n\t😀");
+        assert!(matches!(
+            events[..],
+            [
+                FastPathInputEvent::UnicodeKeyboardEvent(_, 0x61),
+                FastPathInputEvent::UnicodeKeyboardEvent(KeyboardFlags::RELEASE, 0x61),
+                FastPathInputEvent::KeyboardEvent(_, 0x1C),
+                FastPathInputEvent::KeyboardEvent(KeyboardFlags::RELEASE, 0x1C),
+                FastPathInputEvent::KeyboardEvent(_, 0x0F),
+                FastPathInputEvent::KeyboardEvent(KeyboardFlags::RELEASE, 0x0F),
+                // A surrogate pair
+                FastPathInputEvent::UnicodeKeyboardEvent(_, 0xD83D),
+                FastPathInputEvent::UnicodeKeyboardEvent(_, 0xD83D),
+                FastPathInputEvent::UnicodeKeyboardEvent(_, 0xDE00),
+                FastPathInputEvent::UnicodeKeyboardEvent(_, 0xDE00),
+            ]
+        ));
+    }
+
+    #[test]
+    fn crlf_is_one_enter_press() {
+        let enters = |text: &str| {
+            text_key_events(text)
+                .iter()
+                .filter(|event| matches!(event, FastPathInputEvent::KeyboardEvent(flags, 0x1C) if flags.is_empty()))
+                .count()
+        };
+        assert_eq!(enters("a\r\nb"), 1);
+        assert_eq!(enters("a\nb\rc"), 2);
+        assert_eq!(enters("\r\r\n\n"), 3);
+        assert_eq!(text_key_events("a\r\nb").len(), 6);
+    }
+
+    #[test]
+    fn long_text_spans_several_pdus() {
+        let events = text_key_events(&"x".repeat(300));
+        let pdus: Vec<_> = events.chunks(MAX_FASTPATH_INPUT_EVENTS).collect();
+        assert_eq!(pdus.len(), 3);
+        assert!(pdus.iter().all(|pdu| pdu.len() <= MAX_FASTPATH_INPUT_EVENTS));
+    }
+}
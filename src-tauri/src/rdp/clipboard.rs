@@ -0,0 +1,171 @@
+//! Clipboard redirection over the CLIPRDR static virtual channel (MS-RDPECLIP).
+//! Only Unicode text is exchanged.
+
+use ironrdp_pdu::gcc::ChannelName;
+use ironrdp_pdu::PduResult;
+use ironrdp_svc::{ChannelFlags, SvcClientProcessor, SvcMessage, SvcProcessor};
+
+const CHANNEL_NAME: ChannelName = ChannelName::from_static(b"cliprdr\0");
+
+// Clipboard PDU types
+const CB_MONITOR_READY: u16 = 0x0001;
+const CB_FORMAT_LIST: u16 = 0x0002;
+const CB_FORMAT_LIST_RESPONSE: u16 = 0x0003;
+const CB_FORMAT_DATA_REQUEST: u16 = 0x0004;
+const CB_FORMAT_DATA_RESPONSE: u16 = 0x0005;
+const CB_CLIP_CAPS: u16 = 0x0007;
+
+const CB_RESPONSE_OK: u16 = 0x0001;
+const CB_RESPONSE_FAIL: u16 = 0x0002;
+
+const CB_CAPSTYPE_GENERAL: u16 = 0x0001;
+const CB_CAPS_VERSION_2: u32 = 0x0002;
+
+const CF_UNICODETEXT: u32 = 13;
+
+/// msgType + msgFlags + dataLen
+const HEADER_LEN: usize = 8;
+/// Format ID followed by a 32-byte name; we never advertise long format
+/// names, so the server uses this layout too
+const SHORT_FORMAT_LEN: usize = 36;
+
+#[derive(Debug, Default)]
+pub struct ClipboardChannel {
+    /// Set once the server has sent Monitor Ready
+    ready: bool,
+    /// Text we offer to the server
+    local_text: Option<String>,
+    /// Text copied on the server, not yet picked up
+    remote_text: Option<String>,
+}
+
+ironrdp_svc::impl_as_any!(ClipboardChannel);
+
+impl ClipboardChannel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Offer `text` to the server. Returns the messages announcing it, which
+    /// are empty until the channel is ready (it's announced then instead).
+    pub fn set_local_text(&mut self, text: String) -> Vec<SvcMessage> {
+        self.local_text = Some(text);
+        if self.ready {
+            vec![self.format_list()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Text copied on the server since the last call
+    pub fn take_remote_text(&mut self) -> Option<String> {
+        self.remote_text.take()
+    }
+
+    fn format_list(&self) -> SvcMessage {
+        let mut body = Vec::new();
+        if self.local_text.is_some() {
+            body.extend_from_slice(&CF_UNICODETEXT.to_le_bytes());
+            body.extend_from_slice(&[0u8; SHORT_FORMAT_LEN - 4]);
+        }
+        clipboard_pdu(CB_FORMAT_LIST, 0, &body)
+    }
+
+    fn capabilities() -> SvcMessage {
+        let mut body = Vec::with_capacity(16);
+        body.extend_from_slice(&1u16.to_le_bytes()); // cCapabilitiesSets
+        body.extend_from_slice(&0u16.to_le_bytes()); // pad
+        body.extend_from_slice(&CB_CAPSTYPE_GENERAL.to_le_bytes());
+        body.extend_from_slice(&12u16.to_le_bytes()); // lengthCapability
+        body.extend_from_slice(&CB_CAPS_VERSION_2.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes()); // generalFlags
+        clipboard_pdu(CB_CLIP_CAPS, 0, &body)
+    }
+
+    fn on_format_list(&self, body: &[u8]) -> Vec<SvcMessage> {
+        let has_text = body
+            .chunks_exact(SHORT_FORMAT_LEN)
+            .any(|entry| u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) == CF_UNICODETEXT);
+
+        let mut messages = vec![clipboard_pdu(CB_FORMAT_LIST_RESPONSE, CB_RESPONSE_OK, &[])];
+        if has_text {
+            messages.push(clipboard_pdu(
+                CB_FORMAT_DATA_REQUEST,
+                0,
+                &CF_UNICODETEXT.to_le_bytes(),
+            ));
+        }
+        messages
+    }
+
+    fn on_data_request(&self, body: &[u8]) -> SvcMessage {
+        let requested = body
+            .get(..4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+        match (&self.local_text, requested) {
+            (Some(text), Some(CF_UNICODETEXT)) => {
+                let mut data: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+                data.extend_from_slice(&[0, 0]);
+                clipboard_pdu(CB_FORMAT_DATA_RESPONSE, CB_RESPONSE_OK, &data)
+            }
+            _ => clipboard_pdu(CB_FORMAT_DATA_RESPONSE, CB_RESPONSE_FAIL, &[]),
+        }
+    }
+
+    fn on_data_response(&mut self, flags: u16, body: &[u8]) {
+        if flags & CB_RESPONSE_OK == 0 {
+            return;
+        }
+        let units: Vec<u16> = body
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .take_while(|&u| u != 0)
+            .collect();
+        self.remote_text = Some(String::from_utf16_lossy(&units));
+    }
+}
+
+impl SvcProcessor for ClipboardChannel {
+    fn channel_name(&self) -> ChannelName {
+        CHANNEL_NAME
+    }
+
+    fn process(&mut self, payload: &[u8]) -> PduResult<Vec<SvcMessage>> {
+        if payload.len() < HEADER_LEN {
+            eprintln!("RDP: Ignoring short clipboard PDU ({} bytes)", payload.len());
+            return Ok(Vec::new());
+        }
+        let msg_type = u16::from_le_bytes([payload[0], payload[1]]);
+        let msg_flags = u16::from_le_bytes([payload[2], payload[3]]);
+        let data_len = u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]]) as usize;
+        let body = &payload[HEADER_LEN..payload.len().min(HEADER_LEN + data_len)];
+
+        let messages = match msg_type {
+            CB_MONITOR_READY => {
+                self.ready = true;
+                vec![Self::capabilities(), self.format_list()]
+            }
+            CB_FORMAT_LIST => self.on_format_list(body),
+            CB_FORMAT_DATA_REQUEST => vec![self.on_data_request(body)],
+            CB_FORMAT_DATA_RESPONSE => {
+                self.on_data_response(msg_flags, body);
+                Vec::new()
+            }
+            // Capabilities, format list responses, locks, ...
+            _ => Vec::new(),
+        };
+        Ok(messages)
+    }
+}
+
+impl SvcClientProcessor for ClipboardChannel {}
+
+fn clipboard_pdu(msg_type: u16, msg_flags: u16, body: &[u8]) -> SvcMessage {
+    let mut pdu = Vec::with_capacity(HEADER_LEN + body.len());
+    pdu.extend_from_slice(&msg_type.to_le_bytes());
+    pdu.extend_from_slice(&msg_flags.to_le_bytes());
+    pdu.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    pdu.extend_from_slice(body);
+    SvcMessage::from(pdu).with_flags(ChannelFlags::SHOW_PROTOCOL)
+}
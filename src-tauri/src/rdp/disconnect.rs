@@ -0,0 +1,150 @@
+//! Why a session ended. IronRDP hands over the server's Set Error Info
+//! code only as its description, so the code is found again by comparing
+//! with the descriptions IronRDP gives the codes the UI cares about.
+
+use ironrdp_pdu::rdp::server_error_info::{
+    ErrorInfo, ProtocolIndependentCode as Code, ProtocolIndependentLicensingCode as LicensingCode,
+};
+use ironrdp_session::GracefulDisconnectReason;
+use serde::Serialize;
+use std::fmt;
+
+/// Reason code for `rdp-disconnected-{session_id}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RdpDisconnectCode {
+    /// The user logged off inside the session
+    Logoff,
+    /// An administrator logged the session off
+    AdminLogoff,
+    /// An administrator disconnected the session
+    AdminDisconnect,
+    /// The server's idle session limit was reached
+    IdleTimeout,
+    /// The server's active session limit was reached
+    SessionTimeLimit,
+    /// Someone else connected to the same session
+    ReplacedByOtherConnection,
+    /// Remote Desktop licensing failed
+    Licensing,
+    /// The server refused the user
+    AccessDenied,
+    /// The server closed the session without saying why
+    ServerDisconnect,
+    /// The connection dropped and couldn't be restored
+    ConnectionLost,
+    Other,
+}
+
+/// Payload of `rdp-disconnected-{session_id}`
+#[derive(Debug, Clone, Serialize)]
+pub struct RdpDisconnect {
+    pub code: RdpDisconnectCode,
+    /// Short explanation for the user
+    pub message: String,
+    /// What the server or socket reported, if it adds anything
+    pub detail: Option<String>,
+}
+
+impl RdpDisconnect {
+    pub fn from_reason(reason: &GracefulDisconnectReason) -> Self {
+        let description = match reason {
+            GracefulDisconnectReason::Other(description) => description,
+            _ => return Self::new(RdpDisconnectCode::ServerDisconnect, None),
+        };
+
+        let code = error_info_codes()
+            .find(|(info, _)| info.description() == *description)
+            .map_or(RdpDisconnectCode::Other, |(_, code)| code);
+        Self::new(code, Some(description.clone()))
+    }
+
+    pub fn connection_lost(error: &str) -> Self {
+        Self::new(RdpDisconnectCode::ConnectionLost, Some(error.to_string()))
+    }
+
+    fn new(code: RdpDisconnectCode, detail: Option<String>) -> Self {
+        let message = match code {
+            RdpDisconnectCode::Logoff => "You logged off the remote session",
+            RdpDisconnectCode::AdminLogoff => "An administrator logged you off",
+            RdpDisconnectCode::AdminDisconnect => "An administrator disconnected the session",
+            RdpDisconnectCode::IdleTimeout => "The session was idle for too long",
+            RdpDisconnectCode::SessionTimeLimit => "The session reached its time limit",
+            RdpDisconnectCode::ReplacedByOtherConnection => "Another connection took over the session",
+            RdpDisconnectCode::Licensing => "Remote Desktop licensing failed",
+            RdpDisconnectCode::AccessDenied => "The server denied access",
+            RdpDisconnectCode::ServerDisconnect => "The server ended the session",
+            RdpDisconnectCode::ConnectionLost => "The connection was lost",
+            RdpDisconnectCode::Other => "The session ended",
+        };
+        Self {
+            code,
+            message: message.to_string(),
+            detail,
+        }
+    }
+}
+
+/// The Set Error Info codes reported as something other than `Other`
+fn error_info_codes() -> impl Iterator<Item = (ErrorInfo, RdpDisconnectCode)> {
+    let session = [
+        (Code::LogoffByUser, RdpDisconnectCode::Logoff),
+        (Code::RpcInitiatedLogoff, RdpDisconnectCode::AdminLogoff),
+        (Code::RpcInitiatedDisconnect, RdpDisconnectCode::AdminDisconnect),
+        (Code::RpcInitiatedDisconnectByuser, RdpDisconnectCode::AdminDisconnect),
+        (Code::IdleTimeout, RdpDisconnectCode::IdleTimeout),
+        (Code::LogonTimeout, RdpDisconnectCode::SessionTimeLimit),
+        (Code::DisconnectedByOtherconnection, RdpDisconnectCode::ReplacedByOtherConnection),
+        (Code::ServerDeniedConnection, RdpDisconnectCode::AccessDenied),
+        (Code::ServerInsufficientPrivileges, RdpDisconnectCode::AccessDenied),
+    ]
+    .map(|(code, reason)| (ErrorInfo::ProtocolIndependentCode(code), reason));
+    let licensing = [
+        LicensingCode::Internal,
+        LicensingCode::NoLicenseServer,
+        LicensingCode::NoLicense,
+        LicensingCode::BadClientMsg,
+        LicensingCode::HwidDoesntMatchLicense,
+        LicensingCode::BadClientLicense,
+        LicensingCode::CantFinishProtocol,
+        LicensingCode::ClientEndedProtocol,
+        LicensingCode::BadClientEncryption,
+        LicensingCode::CantUpgradeLicense,
+        LicensingCode::NoRemoteConnections,
+    ]
+    .map(|code| (ErrorInfo::ProtocolIndependentLicensingCode(code), RdpDisconnectCode::Licensing));
+    session.into_iter().chain(licensing)
+}
+
+impl fmt::Display for RdpDisconnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.code, &self.detail) {
+            (RdpDisconnectCode::Other | RdpDisconnectCode::ConnectionLost, Some(detail)) => {
+                write!(f, "{} ({})", self.message, detail)
+            }
+            _ => f.write_str(&self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_info_descriptions_map_back_to_codes() {
+        for (info, code) in error_info_codes() {
+            let reason = GracefulDisconnectReason::Other(info.description());
+            assert_eq!(RdpDisconnect::from_reason(&reason).code, code, "{:?}", info);
+        }
+
+        let other = GracefulDisconnectReason::Other(
+            ErrorInfo::ProtocolIndependentCode(Code::ServerDwmCrash).description(),
+        );
+        assert_eq!(RdpDisconnect::from_reason(&other).code, RdpDisconnectCode::Other);
+        assert_eq!(
+            RdpDisconnect::from_reason(&GracefulDisconnectReason::ServerInitiated).code,
+            RdpDisconnectCode::ServerDisconnect
+        );
+    }
+}
@@ -0,0 +1,1028 @@
+//! Drive redirection over the RDPDR static virtual channel (MS-RDPEFS).
+//! One local folder is announced as a file system device and the server's
+//! I/O requests are served from it with `std::fs`. Every path the server
+//! names is checked to stay inside that folder, symlinks included.
+
+use ironrdp_pdu::gcc::ChannelName;
+use ironrdp_pdu::PduResult;
+use ironrdp_svc::{SvcClientProcessor, SvcMessage, SvcProcessor};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const CHANNEL_NAME: ChannelName = ChannelName::from_static(b"rdpdr\0\0\0");
+const SOUND_CHANNEL_NAME: ChannelName = ChannelName::from_static(b"rdpsnd\0\0");
+
+const RDPDR_CTYP_CORE: u16 = 0x4472;
+
+// Core packet IDs
+const PAKID_CORE_SERVER_ANNOUNCE: u16 = 0x496E;
+const PAKID_CORE_CLIENTID_CONFIRM: u16 = 0x4343;
+const PAKID_CORE_CLIENT_NAME: u16 = 0x434E;
+const PAKID_CORE_DEVICELIST_ANNOUNCE: u16 = 0x4441;
+const PAKID_CORE_DEVICE_REPLY: u16 = 0x6472;
+const PAKID_CORE_DEVICE_IOREQUEST: u16 = 0x4952;
+const PAKID_CORE_DEVICE_IOCOMPLETION: u16 = 0x4943;
+const PAKID_CORE_SERVER_CAPABILITY: u16 = 0x5350;
+const PAKID_CORE_CLIENT_CAPABILITY: u16 = 0x4350;
+const PAKID_CORE_USER_LOGGEDON: u16 = 0x554C;
+
+const RDPDR_VERSION_MINOR: u16 = 0x000C;
+
+const CAP_GENERAL_TYPE: u16 = 0x0001;
+const CAP_DRIVE_TYPE: u16 = 0x0004;
+const GENERAL_CAPABILITY_VERSION_02: u32 = 0x0002;
+const DRIVE_CAPABILITY_VERSION_02: u32 = 0x0002;
+/// RDPDR_DEVICE_REMOVE_PDUS | RDPDR_CLIENT_DISPLAY_NAME_PDU | RDPDR_USER_LOGGEDON_PDU
+const EXTENDED_PDUS: u32 = 0x0007;
+
+const RDPDR_DTYP_FILESYSTEM: u32 = 0x0008;
+const DRIVE_DEVICE_ID: u32 = 1;
+
+// IRP major and minor functions
+const IRP_MJ_CREATE: u32 = 0x00;
+const IRP_MJ_CLOSE: u32 = 0x02;
+const IRP_MJ_READ: u32 = 0x03;
+const IRP_MJ_WRITE: u32 = 0x04;
+const IRP_MJ_QUERY_INFORMATION: u32 = 0x05;
+const IRP_MJ_SET_INFORMATION: u32 = 0x06;
+const IRP_MJ_QUERY_VOLUME_INFORMATION: u32 = 0x0A;
+const IRP_MJ_DIRECTORY_CONTROL: u32 = 0x0C;
+const IRP_MJ_DEVICE_CONTROL: u32 = 0x0E;
+const IRP_MJ_LOCK_CONTROL: u32 = 0x11;
+const IRP_MN_QUERY_DIRECTORY: u32 = 0x01;
+const IRP_MN_NOTIFY_CHANGE_DIRECTORY: u32 = 0x02;
+
+// NTSTATUS values
+const STATUS_SUCCESS: u32 = 0x0000_0000;
+const STATUS_NO_MORE_FILES: u32 = 0x8000_0006;
+const STATUS_UNSUCCESSFUL: u32 = 0xC000_0001;
+const STATUS_INVALID_HANDLE: u32 = 0xC000_0008;
+const STATUS_INVALID_PARAMETER: u32 = 0xC000_000D;
+const STATUS_ACCESS_DENIED: u32 = 0xC000_0022;
+const STATUS_OBJECT_NAME_INVALID: u32 = 0xC000_0033;
+const STATUS_OBJECT_NAME_NOT_FOUND: u32 = 0xC000_0034;
+const STATUS_OBJECT_NAME_COLLISION: u32 = 0xC000_0035;
+const STATUS_OBJECT_PATH_NOT_FOUND: u32 = 0xC000_003A;
+const STATUS_MEDIA_WRITE_PROTECTED: u32 = 0xC000_00A2;
+const STATUS_FILE_IS_A_DIRECTORY: u32 = 0xC000_00BA;
+const STATUS_NOT_SUPPORTED: u32 = 0xC000_00BB;
+const STATUS_DIRECTORY_NOT_EMPTY: u32 = 0xC000_0101;
+const STATUS_NOT_A_DIRECTORY: u32 = 0xC000_0103;
+
+// CreateDisposition
+const FILE_SUPERSEDE: u32 = 0;
+const FILE_OPEN: u32 = 1;
+const FILE_CREATE: u32 = 2;
+const FILE_OPEN_IF: u32 = 3;
+const FILE_OVERWRITE: u32 = 4;
+const FILE_OVERWRITE_IF: u32 = 5;
+
+// CreateOptions
+const FILE_DIRECTORY_FILE: u32 = 0x0000_0001;
+const FILE_NON_DIRECTORY_FILE: u32 = 0x0000_0040;
+const FILE_DELETE_ON_CLOSE: u32 = 0x0000_1000;
+
+// Create response Information
+const FILE_SUPERSEDED: u8 = 0;
+const FILE_OPENED: u8 = 1;
+const FILE_CREATED: u8 = 2;
+const FILE_OVERWRITTEN: u8 = 3;
+
+/// DesiredAccess bits that change the file or its metadata: FILE_WRITE_DATA,
+/// FILE_APPEND_DATA, FILE_WRITE_EA, FILE_WRITE_ATTRIBUTES, DELETE,
+/// GENERIC_ALL and GENERIC_WRITE
+const WRITE_ACCESS: u32 = 0x0000_0002 | 0x0000_0004 | 0x0000_0010 | 0x0000_0100 | 0x0001_0000 | 0x1000_0000 | 0x4000_0000;
+
+// File information classes
+const FILE_DIRECTORY_INFORMATION: u32 = 1;
+const FILE_FULL_DIRECTORY_INFORMATION: u32 = 2;
+const FILE_BOTH_DIRECTORY_INFORMATION: u32 = 3;
+const FILE_BASIC_INFORMATION: u32 = 4;
+const FILE_STANDARD_INFORMATION: u32 = 5;
+const FILE_RENAME_INFORMATION: u32 = 10;
+const FILE_NAMES_INFORMATION: u32 = 12;
+const FILE_DISPOSITION_INFORMATION: u32 = 13;
+const FILE_ALLOCATION_INFORMATION: u32 = 19;
+const FILE_END_OF_FILE_INFORMATION: u32 = 20;
+const FILE_ATTRIBUTE_TAG_INFORMATION: u32 = 35;
+
+// File system information classes
+const FILE_FS_VOLUME_INFORMATION: u32 = 1;
+const FILE_FS_SIZE_INFORMATION: u32 = 3;
+const FILE_FS_DEVICE_INFORMATION: u32 = 4;
+const FILE_FS_ATTRIBUTE_INFORMATION: u32 = 5;
+const FILE_FS_FULL_SIZE_INFORMATION: u32 = 7;
+
+// File attributes
+const FILE_ATTRIBUTE_READONLY: u32 = 0x0001;
+const FILE_ATTRIBUTE_HIDDEN: u32 = 0x0002;
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x0010;
+const FILE_ATTRIBUTE_ARCHIVE: u32 = 0x0020;
+
+// File system attributes
+const FILE_CASE_PRESERVED_NAMES: u32 = 0x0000_0002;
+const FILE_UNICODE_ON_DISK: u32 = 0x0000_0004;
+const FILE_READ_ONLY_VOLUME: u32 = 0x0008_0000;
+
+const FILE_DEVICE_DISK: u32 = 0x0000_0007;
+const FILE_READ_ONLY_DEVICE: u32 = 0x0000_0002;
+
+const SECTOR_SIZE: u32 = 512;
+const SECTORS_PER_UNIT: u32 = 8;
+/// std can't tell how much space is free, so a roomy volume is reported
+/// and a full disk shows up as a failed write
+const REPORTED_UNITS: u64 = (1 << 40) / (SECTOR_SIZE * SECTORS_PER_UNIT) as u64;
+
+/// Largest read served at once
+const MAX_READ: u32 = 1 << 20;
+
+/// Seconds from 1601-01-01 (FILETIME epoch) to the Unix epoch
+const FILETIME_UNIX_OFFSET: u64 = 11_644_473_600;
+
+/// Local folder shared with the remote session as a drive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RdpSharedFolder {
+    pub path: String,
+    /// Let the server read the folder but not change anything in it
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+#[derive(Debug)]
+struct OpenFile {
+    path: PathBuf,
+    /// None for directories
+    file: Option<File>,
+    delete_on_close: bool,
+    /// Names still to be returned by a directory query
+    listing: Option<VecDeque<String>>,
+}
+
+#[derive(Debug)]
+pub struct DriveChannel {
+    /// Canonical path of the shared folder
+    root: PathBuf,
+    read_only: bool,
+    /// Shown in the remote session as "<name> on <client>"
+    name: String,
+    files: HashMap<u32, OpenFile>,
+    next_file_id: u32,
+}
+
+ironrdp_svc::impl_as_any!(DriveChannel);
+
+impl DriveChannel {
+    pub fn new(folder: &RdpSharedFolder) -> Result<Self, String> {
+        let root = fs::canonicalize(&folder.path)
+            .map_err(|e| format!("Shared folder {}: {}", folder.path, e))?;
+        if !root.is_dir() {
+            return Err(format!("Shared folder {} is not a folder", folder.path));
+        }
+        let name = root
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "OpenTerm".to_string());
+        Ok(Self {
+            root,
+            read_only: folder.read_only,
+            name,
+            files: HashMap::new(),
+            next_file_id: 1,
+        })
+    }
+
+    fn announce_reply(minor: u16, client_id: u32) -> SvcMessage {
+        let mut body = Vec::with_capacity(8);
+        body.extend_from_slice(&1u16.to_le_bytes()); // VersionMajor
+        body.extend_from_slice(&minor.min(RDPDR_VERSION_MINOR).to_le_bytes());
+        body.extend_from_slice(&client_id.to_le_bytes());
+        rdpdr_pdu(PAKID_CORE_CLIENTID_CONFIRM, &body)
+    }
+
+    fn client_name() -> SvcMessage {
+        let name = utf16_nul("OpenTerm");
+        let mut body = Vec::with_capacity(12 + name.len());
+        body.extend_from_slice(&1u32.to_le_bytes()); // UnicodeFlag
+        body.extend_from_slice(&0u32.to_le_bytes()); // CodePage
+        body.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        body.extend_from_slice(&name);
+        rdpdr_pdu(PAKID_CORE_CLIENT_NAME, &body)
+    }
+
+    fn capabilities() -> SvcMessage {
+        let mut body = Vec::with_capacity(56);
+        body.extend_from_slice(&2u16.to_le_bytes()); // numCapabilities
+        body.extend_from_slice(&0u16.to_le_bytes()); // Padding
+
+        body.extend_from_slice(&CAP_GENERAL_TYPE.to_le_bytes());
+        body.extend_from_slice(&44u16.to_le_bytes());
+        body.extend_from_slice(&GENERAL_CAPABILITY_VERSION_02.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes()); // osType
+        body.extend_from_slice(&0u32.to_le_bytes()); // osVersion
+        body.extend_from_slice(&1u16.to_le_bytes()); // protocolMajorVersion
+        body.extend_from_slice(&RDPDR_VERSION_MINOR.to_le_bytes());
+        body.extend_from_slice(&0xFFFFu32.to_le_bytes()); // ioCode1: every IRP
+        body.extend_from_slice(&0u32.to_le_bytes()); // ioCode2
+        body.extend_from_slice(&EXTENDED_PDUS.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes()); // extraFlags1
+        body.extend_from_slice(&0u32.to_le_bytes()); // extraFlags2
+        body.extend_from_slice(&0u32.to_le_bytes()); // SpecialTypeDeviceCap
+
+        body.extend_from_slice(&CAP_DRIVE_TYPE.to_le_bytes());
+        body.extend_from_slice(&8u16.to_le_bytes());
+        body.extend_from_slice(&DRIVE_CAPABILITY_VERSION_02.to_le_bytes());
+        rdpdr_pdu(PAKID_CORE_CLIENT_CAPABILITY, &body)
+    }
+
+    fn device_list(&self) -> SvcMessage {
+        // Preferred DOS name: up to 7 ASCII characters, NUL-padded
+        let mut dos_name = [0u8; 8];
+        let ascii = self.name.bytes().filter(|b| b.is_ascii_alphanumeric()).take(7);
+        for (dst, src) in dos_name.iter_mut().zip(ascii) {
+            *dst = src.to_ascii_uppercase();
+        }
+        if dos_name[0] == 0 {
+            dos_name[..5].copy_from_slice(b"SHARE");
+        }
+        let mut display: Vec<u8> = self
+            .name
+            .chars()
+            .map(|c| if c.is_ascii() && !c.is_ascii_control() { c as u8 } else { b'_' })
+            .collect();
+        display.push(0);
+
+        let mut body = Vec::with_capacity(24 + display.len());
+        body.extend_from_slice(&1u32.to_le_bytes()); // DeviceCount
+        body.extend_from_slice(&RDPDR_DTYP_FILESYSTEM.to_le_bytes());
+        body.extend_from_slice(&DRIVE_DEVICE_ID.to_le_bytes());
+        body.extend_from_slice(&dos_name);
+        body.extend_from_slice(&(display.len() as u32).to_le_bytes());
+        body.extend_from_slice(&display);
+        rdpdr_pdu(PAKID_CORE_DEVICELIST_ANNOUNCE, &body)
+    }
+
+    /// Handle a Device I/O Request. None if it stays pending, which is how
+    /// change notifications are left.
+    fn on_io_request(&mut self, data: &[u8]) -> Option<SvcMessage> {
+        let mut request = Reader::new(data);
+        let header = (|| {
+            Ok::<_, u32>((request.u32()?, request.u32()?, request.u32()?, request.u32()?, request.u32()?))
+        })();
+        let Ok((device_id, file_id, completion_id, major, minor)) = header else {
+            eprintln!("RDP: Ignoring short drive I/O request ({} bytes)", data.len());
+            return None;
+        };
+        let params = request.rest();
+
+        if major == IRP_MJ_DIRECTORY_CONTROL && minor == IRP_MN_NOTIFY_CHANGE_DIRECTORY {
+            return None;
+        }
+
+        let result = if device_id != DRIVE_DEVICE_ID {
+            Err(STATUS_INVALID_HANDLE)
+        } else {
+            match major {
+                IRP_MJ_CREATE => self.create(params),
+                IRP_MJ_CLOSE => self.close(file_id),
+                IRP_MJ_READ => self.read(file_id, params),
+                IRP_MJ_WRITE => self.write(file_id, params),
+                IRP_MJ_QUERY_INFORMATION => self.query_information(file_id, params),
+                IRP_MJ_SET_INFORMATION => self.set_information(file_id, params),
+                IRP_MJ_QUERY_VOLUME_INFORMATION => self.query_volume(params),
+                IRP_MJ_DIRECTORY_CONTROL if minor == IRP_MN_QUERY_DIRECTORY => {
+                    self.query_directory(file_id, params)
+                }
+                // No IOCTLs are implemented; an empty output is what a
+                // plain disk answers to most of them
+                IRP_MJ_DEVICE_CONTROL => Ok(0u32.to_le_bytes().to_vec()),
+                IRP_MJ_LOCK_CONTROL => Ok(vec![0; 5]),
+                _ => Err(STATUS_NOT_SUPPORTED),
+            }
+        };
+
+        let (status, body) = match result {
+            Ok(body) => (STATUS_SUCCESS, body),
+            // The fixed part of each response is still expected on failure
+            Err(status) => {
+                let len = match major {
+                    IRP_MJ_READ | IRP_MJ_DEVICE_CONTROL | IRP_MJ_QUERY_INFORMATION | IRP_MJ_QUERY_VOLUME_INFORMATION => 4,
+                    _ => 5,
+                };
+                (status, vec![0; len])
+            }
+        };
+
+        let mut reply = Vec::with_capacity(12 + body.len());
+        reply.extend_from_slice(&DRIVE_DEVICE_ID.to_le_bytes());
+        reply.extend_from_slice(&completion_id.to_le_bytes());
+        reply.extend_from_slice(&status.to_le_bytes());
+        reply.extend_from_slice(&body);
+        Some(rdpdr_pdu(PAKID_CORE_DEVICE_IOCOMPLETION, &reply))
+    }
+
+    /// The local path for a path on the shared drive. Rejects `..` and
+    /// anything else that could name a file outside the root, including
+    /// through a symlink. Symlinks are followed only when their target
+    /// exists inside the root, so a dangling one can't be created through.
+    fn resolve(&self, path: &str) -> Result<PathBuf, u32> {
+        let mut resolved = self.root.clone();
+        let mut exists = true;
+        for part in path.split(['\\', '/']).filter(|part| !part.is_empty()) {
+            if part == "." || part == ".." || part.contains([':', '\0']) {
+                return Err(STATUS_OBJECT_NAME_INVALID);
+            }
+            resolved.push(part);
+            // Below a missing component there's nothing to follow
+            if !exists {
+                continue;
+            }
+            match fs::symlink_metadata(&resolved) {
+                Ok(meta) if meta.file_type().is_symlink() => match resolved.canonicalize() {
+                    Ok(real) if real.starts_with(&self.root) => {}
+                    _ => return Err(STATUS_ACCESS_DENIED),
+                },
+                Ok(_) => {}
+                Err(_) => exists = false,
+            }
+        }
+        Ok(resolved)
+    }
+
+    fn writable(&self) -> Result<(), u32> {
+        if self.read_only {
+            Err(STATUS_MEDIA_WRITE_PROTECTED)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn open_file(&mut self, file_id: u32) -> Result<&mut OpenFile, u32> {
+        self.files.get_mut(&file_id).ok_or(STATUS_INVALID_HANDLE)
+    }
+
+    fn create(&mut self, params: &[u8]) -> Result<Vec<u8>, u32> {
+        let mut r = Reader::new(params);
+        let desired_access = r.u32()?;
+        let _allocation_size = r.u64()?;
+        let _file_attributes = r.u32()?;
+        let _shared_access = r.u32()?;
+        let disposition = r.u32()?;
+        let options = r.u32()?;
+        let path_len = r.u32()?;
+        let path = self.resolve(&utf16_string(r.bytes(path_len as usize)?))?;
+
+        let changes = desired_access & WRITE_ACCESS != 0
+            || options & FILE_DELETE_ON_CLOSE != 0
+            || matches!(disposition, FILE_SUPERSEDE | FILE_CREATE | FILE_OVERWRITE | FILE_OVERWRITE_IF);
+        let existing = fs::metadata(&path).ok();
+        if self.read_only && (changes || (existing.is_none() && disposition == FILE_OPEN_IF)) {
+            return Err(STATUS_MEDIA_WRITE_PROTECTED);
+        }
+
+        let missing = || {
+            if path.parent().is_some_and(Path::is_dir) {
+                STATUS_OBJECT_NAME_NOT_FOUND
+            } else {
+                STATUS_OBJECT_PATH_NOT_FOUND
+            }
+        };
+        let (file, information) = match existing {
+            Some(_) if disposition == FILE_CREATE => return Err(STATUS_OBJECT_NAME_COLLISION),
+            None if matches!(disposition, FILE_OPEN | FILE_OVERWRITE) => return Err(missing()),
+            Some(meta) if meta.is_dir() => {
+                if options & FILE_NON_DIRECTORY_FILE != 0 {
+                    return Err(STATUS_FILE_IS_A_DIRECTORY);
+                }
+                (None, FILE_OPENED)
+            }
+            Some(_) if options & FILE_DIRECTORY_FILE != 0 => return Err(STATUS_NOT_A_DIRECTORY),
+            None if options & FILE_DIRECTORY_FILE != 0 => {
+                fs::create_dir(&path).map_err(|e| io_status(&e))?;
+                (None, FILE_CREATED)
+            }
+            existing => {
+                let truncate = matches!(disposition, FILE_SUPERSEDE | FILE_OVERWRITE | FILE_OVERWRITE_IF);
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(changes)
+                    .create(changes && existing.is_none())
+                    .truncate(truncate && existing.is_some())
+                    .open(&path)
+                    .map_err(|e| io_status(&e))?;
+                let information = match (existing, disposition) {
+                    (None, _) => FILE_CREATED,
+                    (Some(_), FILE_SUPERSEDE) => FILE_SUPERSEDED,
+                    (Some(_), FILE_OVERWRITE | FILE_OVERWRITE_IF) => FILE_OVERWRITTEN,
+                    (Some(_), _) => FILE_OPENED,
+                };
+                (Some(file), information)
+            }
+        };
+
+        let file_id = self.next_file_id;
+        self.next_file_id = self.next_file_id.wrapping_add(1).max(1);
+        self.files.insert(
+            file_id,
+            OpenFile {
+                path,
+                file,
+                delete_on_close: options & FILE_DELETE_ON_CLOSE != 0,
+                listing: None,
+            },
+        );
+
+        let mut body = file_id.to_le_bytes().to_vec();
+        body.push(information);
+        Ok(body)
+    }
+
+    fn close(&mut self, file_id: u32) -> Result<Vec<u8>, u32> {
+        let open = self.files.remove(&file_id).ok_or(STATUS_INVALID_HANDLE)?;
+        if open.delete_on_close {
+            let is_file = open.file.is_some();
+            drop(open.file);
+            let removed = if is_file {
+                fs::remove_file(&open.path)
+            } else {
+                fs::remove_dir(&open.path)
+            };
+            removed.map_err(|e| io_status(&e))?;
+        }
+        Ok(vec![0; 5])
+    }
+
+    fn read(&mut self, file_id: u32, params: &[u8]) -> Result<Vec<u8>, u32> {
+        let mut r = Reader::new(params);
+        let length = r.u32()?.min(MAX_READ);
+        let offset = r.u64()?;
+        let file = self.open_file(file_id)?.file.as_mut().ok_or(STATUS_FILE_IS_A_DIRECTORY)?;
+
+        let mut data = Vec::with_capacity(length as usize);
+        file.seek(SeekFrom::Start(offset)).map_err(|e| io_status(&e))?;
+        file.take(length as u64)
+            .read_to_end(&mut data)
+            .map_err(|e| io_status(&e))?;
+
+        let mut body = (data.len() as u32).to_le_bytes().to_vec();
+        body.extend_from_slice(&data);
+        Ok(body)
+    }
+
+    fn write(&mut self, file_id: u32, params: &[u8]) -> Result<Vec<u8>, u32> {
+        self.writable()?;
+        let mut r = Reader::new(params);
+        let length = r.u32()?;
+        let offset = r.u64()?;
+        r.bytes(20)?; // Padding
+        let data = r.bytes(length as usize)?;
+        let file = self.open_file(file_id)?.file.as_mut().ok_or(STATUS_FILE_IS_A_DIRECTORY)?;
+
+        file.seek(SeekFrom::Start(offset)).map_err(|e| io_status(&e))?;
+        file.write_all(data).map_err(|e| io_status(&e))?;
+
+        let mut body = length.to_le_bytes().to_vec();
+        body.push(0); // Padding
+        Ok(body)
+    }
+
+    fn query_information(&mut self, file_id: u32, params: &[u8]) -> Result<Vec<u8>, u32> {
+        let class = Reader::new(params).u32()?;
+        let read_only = self.read_only;
+        let open = self.open_file(file_id)?;
+        let meta = fs::metadata(&open.path).map_err(|e| io_status(&e))?;
+        let attributes = file_attributes(&open.path, &meta, read_only);
+
+        let mut info = Vec::new();
+        match class {
+            FILE_BASIC_INFORMATION => {
+                push_times(&mut info, &meta);
+                info.extend_from_slice(&attributes.to_le_bytes());
+            }
+            FILE_STANDARD_INFORMATION => {
+                info.extend_from_slice(&allocation_size(&meta).to_le_bytes());
+                info.extend_from_slice(&file_size(&meta).to_le_bytes());
+                info.extend_from_slice(&1u32.to_le_bytes()); // NumberOfLinks
+                info.push(open.delete_on_close as u8);
+                info.push(meta.is_dir() as u8);
+            }
+            FILE_ATTRIBUTE_TAG_INFORMATION => {
+                info.extend_from_slice(&attributes.to_le_bytes());
+                info.extend_from_slice(&0u32.to_le_bytes()); // ReparseTag
+            }
+            _ => return Err(STATUS_NOT_SUPPORTED),
+        }
+        Ok(length_prefixed(&info))
+    }
+
+    fn set_information(&mut self, file_id: u32, params: &[u8]) -> Result<Vec<u8>, u32> {
+        self.writable()?;
+        let mut r = Reader::new(params);
+        let class = r.u32()?;
+        let length = r.u32()?;
+        r.bytes(24)?; // Padding
+        let buffer = r.rest();
+
+        match class {
+            FILE_BASIC_INFORMATION => {
+                let mut info = Reader::new(buffer);
+                let _creation = info.u64()?;
+                let _last_access = info.u64()?;
+                let last_write = info.u64()?;
+                if let (Some(file), Some(time)) = (self.open_file(file_id)?.file.as_ref(), from_filetime(last_write)) {
+                    file.set_modified(time).map_err(|e| io_status(&e))?;
+                }
+            }
+            FILE_END_OF_FILE_INFORMATION => {
+                let size = Reader::new(buffer).u64()?;
+                let file = self.open_file(file_id)?.file.as_ref().ok_or(STATUS_FILE_IS_A_DIRECTORY)?;
+                file.set_len(size).map_err(|e| io_status(&e))?;
+            }
+            // Only a size hint; the end of file is set separately
+            FILE_ALLOCATION_INFORMATION => {}
+            FILE_DISPOSITION_INFORMATION => {
+                let delete = length == 0 || buffer.first().is_some_and(|&b| b != 0);
+                let open = self.open_file(file_id)?;
+                if delete && open.file.is_none() {
+                    let mut entries = fs::read_dir(&open.path).map_err(|e| io_status(&e))?;
+                    if entries.next().is_some() {
+                        return Err(STATUS_DIRECTORY_NOT_EMPTY);
+                    }
+                }
+                open.delete_on_close = delete;
+            }
+            FILE_RENAME_INFORMATION => {
+                let mut info = Reader::new(buffer);
+                let replace = info.u8()? != 0;
+                let _root_directory = info.u8()?;
+                let name_len = info.u32()?;
+                let target = self.resolve(&utf16_string(info.bytes(name_len as usize)?))?;
+                if !replace && fs::symlink_metadata(&target).is_ok() {
+                    return Err(STATUS_OBJECT_NAME_COLLISION);
+                }
+                let read_only = self.read_only;
+                let open = self.open_file(file_id)?;
+                // Windows can't rename a file that is still open
+                let reopen = open.file.take().is_some();
+                fs::rename(&open.path, &target).map_err(|e| io_status(&e))?;
+                open.path = target;
+                if reopen {
+                    open.file = OpenOptions::new().read(true).write(!read_only).open(&open.path).ok();
+                }
+            }
+            _ => return Err(STATUS_NOT_SUPPORTED),
+        }
+
+        let mut body = length.to_le_bytes().to_vec();
+        body.push(0); // Padding
+        Ok(body)
+    }
+
+    fn query_volume(&self, params: &[u8]) -> Result<Vec<u8>, u32> {
+        let class = Reader::new(params).u32()?;
+        let mut info = Vec::new();
+        match class {
+            FILE_FS_VOLUME_INFORMATION => {
+                let label = utf16(&self.name);
+                info.extend_from_slice(&0u64.to_le_bytes()); // VolumeCreationTime
+                info.extend_from_slice(&0x4F54_524Du32.to_le_bytes()); // VolumeSerialNumber
+                info.extend_from_slice(&(label.len() as u32).to_le_bytes());
+                info.push(0); // SupportsObjects
+                info.push(0); // Reserved
+                info.extend_from_slice(&label);
+            }
+            FILE_FS_SIZE_INFORMATION => {
+                info.extend_from_slice(&REPORTED_UNITS.to_le_bytes()); // TotalAllocationUnits
+                info.extend_from_slice(&REPORTED_UNITS.to_le_bytes()); // AvailableAllocationUnits
+                info.extend_from_slice(&SECTORS_PER_UNIT.to_le_bytes());
+                info.extend_from_slice(&SECTOR_SIZE.to_le_bytes());
+            }
+            FILE_FS_FULL_SIZE_INFORMATION => {
+                info.extend_from_slice(&REPORTED_UNITS.to_le_bytes()); // TotalAllocationUnits
+                info.extend_from_slice(&REPORTED_UNITS.to_le_bytes()); // CallerAvailableAllocationUnits
+                info.extend_from_slice(&REPORTED_UNITS.to_le_bytes()); // ActualAvailableAllocationUnits
+                info.extend_from_slice(&SECTORS_PER_UNIT.to_le_bytes());
+                info.extend_from_slice(&SECTOR_SIZE.to_le_bytes());
+            }
+            FILE_FS_ATTRIBUTE_INFORMATION => {
+                let mut attributes = FILE_CASE_PRESERVED_NAMES | FILE_UNICODE_ON_DISK;
+                if self.read_only {
+                    attributes |= FILE_READ_ONLY_VOLUME;
+                }
+                let fs_name = utf16("NTFS");
+                info.extend_from_slice(&attributes.to_le_bytes());
+                info.extend_from_slice(&255u32.to_le_bytes()); // MaximumComponentNameLength
+                info.extend_from_slice(&(fs_name.len() as u32).to_le_bytes());
+                info.extend_from_slice(&fs_name);
+            }
+            FILE_FS_DEVICE_INFORMATION => {
+                let characteristics = if self.read_only { FILE_READ_ONLY_DEVICE } else { 0 };
+                info.extend_from_slice(&FILE_DEVICE_DISK.to_le_bytes());
+                info.extend_from_slice(&characteristics.to_le_bytes());
+            }
+            _ => return Err(STATUS_NOT_SUPPORTED),
+        }
+        Ok(length_prefixed(&info))
+    }
+
+    /// One directory entry per request, as FreeRDP and mstsc do
+    fn query_directory(&mut self, file_id: u32, params: &[u8]) -> Result<Vec<u8>, u32> {
+        let mut r = Reader::new(params);
+        let class = r.u32()?;
+        let initial = r.u8()? != 0;
+        let path_len = r.u32()?;
+        r.bytes(23)?; // Padding
+        let path = utf16_string(r.bytes(path_len as usize)?);
+        let read_only = self.read_only;
+        let open = self.open_file(file_id)?;
+
+        if initial || open.listing.is_none() {
+            // The path ends with the pattern to match, usually "*"
+            let pattern = path.rsplit('\\').next().filter(|p| !p.is_empty()).unwrap_or("*");
+            let mut names: Vec<String> = fs::read_dir(&open.path)
+                .map_err(|e| io_status(&e))?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| wildcard_match(pattern, name))
+                .collect();
+            names.sort();
+            open.listing = Some(names.into());
+        }
+
+        let Some(name) = open.listing.as_mut().and_then(VecDeque::pop_front) else {
+            return Err(STATUS_NO_MORE_FILES);
+        };
+        let entry_path = open.path.join(&name);
+        let meta = fs::metadata(&entry_path)
+            .or_else(|_| fs::symlink_metadata(&entry_path))
+            .map_err(|e| io_status(&e))?;
+        let file_name = utf16(&name);
+
+        let mut info = Vec::new();
+        info.extend_from_slice(&0u32.to_le_bytes()); // NextEntryOffset
+        info.extend_from_slice(&0u32.to_le_bytes()); // FileIndex
+        match class {
+            FILE_DIRECTORY_INFORMATION | FILE_FULL_DIRECTORY_INFORMATION | FILE_BOTH_DIRECTORY_INFORMATION => {
+                push_times(&mut info, &meta);
+                info.extend_from_slice(&file_size(&meta).to_le_bytes());
+                info.extend_from_slice(&allocation_size(&meta).to_le_bytes());
+                info.extend_from_slice(&file_attributes(&entry_path, &meta, read_only).to_le_bytes());
+                info.extend_from_slice(&(file_name.len() as u32).to_le_bytes());
+                if class != FILE_DIRECTORY_INFORMATION {
+                    info.extend_from_slice(&0u32.to_le_bytes()); // EaSize
+                }
+                if class == FILE_BOTH_DIRECTORY_INFORMATION {
+                    info.push(0); // ShortNameLength
+                    info.push(0); // Reserved
+                    info.extend_from_slice(&[0u8; 24]); // ShortName
+                }
+            }
+            FILE_NAMES_INFORMATION => {
+                info.extend_from_slice(&(file_name.len() as u32).to_le_bytes());
+            }
+            _ => return Err(STATUS_NOT_SUPPORTED),
+        }
+        info.extend_from_slice(&file_name);
+        Ok(length_prefixed(&info))
+    }
+}
+
+impl SvcProcessor for DriveChannel {
+    fn channel_name(&self) -> ChannelName {
+        CHANNEL_NAME
+    }
+
+    fn process(&mut self, payload: &[u8]) -> PduResult<Vec<SvcMessage>> {
+        if payload.len() < 4 {
+            eprintln!("RDP: Ignoring short drive PDU ({} bytes)", payload.len());
+            return Ok(Vec::new());
+        }
+        let component = u16::from_le_bytes([payload[0], payload[1]]);
+        let packet_id = u16::from_le_bytes([payload[2], payload[3]]);
+        let body = &payload[4..];
+        if component != RDPDR_CTYP_CORE {
+            return Ok(Vec::new());
+        }
+
+        let messages = match packet_id {
+            PAKID_CORE_SERVER_ANNOUNCE => {
+                let mut r = Reader::new(body);
+                match (r.u16(), r.u16(), r.u32()) {
+                    (Ok(_major), Ok(minor), Ok(client_id)) => {
+                        vec![Self::announce_reply(minor, client_id), Self::client_name()]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            PAKID_CORE_SERVER_CAPABILITY => vec![Self::capabilities()],
+            // Drives are announced once the user has logged on
+            PAKID_CORE_USER_LOGGEDON => {
+                eprintln!("RDP: Sharing {} as drive {}", self.root.display(), self.name);
+                vec![self.device_list()]
+            }
+            PAKID_CORE_DEVICE_REPLY => {
+                let mut r = Reader::new(body);
+                if let (Ok(_device_id), Ok(result)) = (r.u32(), r.u32()) {
+                    if result != STATUS_SUCCESS {
+                        eprintln!("RDP: Server refused the shared drive (status {:#010x})", result);
+                    }
+                }
+                Vec::new()
+            }
+            PAKID_CORE_DEVICE_IOREQUEST => self.on_io_request(body).into_iter().collect(),
+            // Client ID confirm, device removal, ...
+            _ => Vec::new(),
+        };
+        Ok(messages)
+    }
+}
+
+impl SvcClientProcessor for DriveChannel {}
+
+/// Windows only starts drive redirection when the client also has a sound
+/// channel. This one never answers the server's audio formats, so no audio
+/// is sent.
+#[derive(Debug, Default)]
+pub struct SoundChannel;
+
+ironrdp_svc::impl_as_any!(SoundChannel);
+
+impl SvcProcessor for SoundChannel {
+    fn channel_name(&self) -> ChannelName {
+        SOUND_CHANNEL_NAME
+    }
+
+    fn process(&mut self, _payload: &[u8]) -> PduResult<Vec<SvcMessage>> {
+        Ok(Vec::new())
+    }
+}
+
+impl SvcClientProcessor for SoundChannel {}
+
+fn rdpdr_pdu(packet_id: u16, body: &[u8]) -> SvcMessage {
+    let mut pdu = Vec::with_capacity(4 + body.len());
+    pdu.extend_from_slice(&RDPDR_CTYP_CORE.to_le_bytes());
+    pdu.extend_from_slice(&packet_id.to_le_bytes());
+    pdu.extend_from_slice(body);
+    SvcMessage::from(pdu)
+}
+
+/// Little-endian reader over a request; running short is an invalid
+/// parameter
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], u32> {
+        if self.data.len() < len {
+            return Err(STATUS_INVALID_PARAMETER);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, u32> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, u32> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, u32> {
+        let b = self.bytes(8)?;
+        Ok(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+}
+
+fn length_prefixed(info: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(4 + info.len());
+    body.extend_from_slice(&(info.len() as u32).to_le_bytes());
+    body.extend_from_slice(info);
+    body
+}
+
+fn utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+fn utf16_nul(text: &str) -> Vec<u8> {
+    let mut bytes = utf16(text);
+    bytes.extend_from_slice(&[0, 0]);
+    bytes
+}
+
+/// A UTF-16LE string, up to its NUL terminator if it has one
+fn utf16_string(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .take_while(|&u| u != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn io_status(e: &io::Error) -> u32 {
+    match e.kind() {
+        io::ErrorKind::NotFound => STATUS_OBJECT_NAME_NOT_FOUND,
+        io::ErrorKind::PermissionDenied => STATUS_ACCESS_DENIED,
+        io::ErrorKind::AlreadyExists => STATUS_OBJECT_NAME_COLLISION,
+        io::ErrorKind::DirectoryNotEmpty => STATUS_DIRECTORY_NOT_EMPTY,
+        io::ErrorKind::NotADirectory => STATUS_NOT_A_DIRECTORY,
+        io::ErrorKind::IsADirectory => STATUS_FILE_IS_A_DIRECTORY,
+        io::ErrorKind::ReadOnlyFilesystem => STATUS_MEDIA_WRITE_PROTECTED,
+        _ => STATUS_UNSUCCESSFUL,
+    }
+}
+
+/// 100ns intervals since 1601, 0 if unknown
+fn filetime(time: io::Result<SystemTime>) -> u64 {
+    time.ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since| (since.as_secs() + FILETIME_UNIX_OFFSET) * 10_000_000 + since.subsec_nanos() as u64 / 100)
+        .unwrap_or(0)
+}
+
+/// None for 0 and -1, which mean "don't change"
+fn from_filetime(filetime: u64) -> Option<SystemTime> {
+    if filetime == 0 || filetime == u64::MAX {
+        return None;
+    }
+    let secs = (filetime / 10_000_000).checked_sub(FILETIME_UNIX_OFFSET)?;
+    Some(UNIX_EPOCH + Duration::new(secs, (filetime % 10_000_000) as u32 * 100))
+}
+
+/// Creation, last access, last write and change times
+fn push_times(out: &mut Vec<u8>, meta: &Metadata) {
+    let modified = filetime(meta.modified());
+    let created = match filetime(meta.created()) {
+        0 => modified,
+        created => created,
+    };
+    for time in [created, filetime(meta.accessed()), modified, modified] {
+        out.extend_from_slice(&time.to_le_bytes());
+    }
+}
+
+fn file_size(meta: &Metadata) -> u64 {
+    if meta.is_dir() {
+        0
+    } else {
+        meta.len()
+    }
+}
+
+fn allocation_size(meta: &Metadata) -> u64 {
+    let unit = (SECTOR_SIZE * SECTORS_PER_UNIT) as u64;
+    file_size(meta).div_ceil(unit) * unit
+}
+
+fn file_attributes(path: &Path, meta: &Metadata, read_only: bool) -> u32 {
+    let mut attributes = if meta.is_dir() {
+        FILE_ATTRIBUTE_DIRECTORY
+    } else {
+        FILE_ATTRIBUTE_ARCHIVE
+    };
+    if read_only || meta.permissions().readonly() {
+        attributes |= FILE_ATTRIBUTE_READONLY;
+    }
+    if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+        attributes |= FILE_ATTRIBUTE_HIDDEN;
+    }
+    attributes
+}
+
+/// Case-insensitive match against a pattern with `*` and `?`
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    if pattern == "*" || pattern == "*.*" {
+        return true;
+    }
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn share(read_only: bool) -> (TempDir, DriveChannel) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("root")).unwrap();
+        fs::create_dir(dir.path().join("outside")).unwrap();
+        fs::write(dir.path().join("root").join("inside.txt"), "in").unwrap();
+        let channel = DriveChannel::new(&RdpSharedFolder {
+            path: dir.path().join("root").to_string_lossy().to_string(),
+            read_only,
+        })
+        .unwrap();
+        (dir, channel)
+    }
+
+    /// Device Create Request parameters for `path`
+    fn create_request(path: &str, desired_access: u32, disposition: u32) -> Vec<u8> {
+        let name: Vec<u8> = path.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect();
+        let mut params = desired_access.to_le_bytes().to_vec();
+        params.extend_from_slice(&0u64.to_le_bytes()); // AllocationSize
+        params.extend_from_slice(&0u32.to_le_bytes()); // FileAttributes
+        params.extend_from_slice(&0u32.to_le_bytes()); // SharedAccess
+        params.extend_from_slice(&disposition.to_le_bytes());
+        params.extend_from_slice(&0u32.to_le_bytes()); // CreateOptions
+        params.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        params.extend_from_slice(&name);
+        params
+    }
+
+    #[test]
+    fn resolve_stays_inside_the_root() {
+        let (_dir, channel) = share(false);
+        assert_eq!(channel.resolve("\\inside.txt"), Ok(channel.root.join("inside.txt")));
+        // Absolute paths are relative to the share
+        assert_eq!(channel.resolve("\\a\\b/c"), Ok(channel.root.join("a").join("b").join("c")));
+        assert_eq!(channel.resolve(""), Ok(channel.root.clone()));
+
+        for path in ["\\..\\outside", "\\a\\..\\..\\x", "\\.\\inside.txt", "C:\\Windows", "\\x:stream", "\\a\0b"] {
+            assert_eq!(channel.resolve(path), Err(STATUS_OBJECT_NAME_INVALID), "{:?}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_follows_only_symlinks_that_stay_inside() {
+        use std::os::unix::fs::symlink;
+
+        let (dir, channel) = share(false);
+        let root = dir.path().join("root");
+        symlink(root.join("inside.txt"), root.join("link-in")).unwrap();
+        symlink(dir.path().join("outside"), root.join("link-out")).unwrap();
+        symlink(dir.path().join("outside").join("new.txt"), root.join("dangling")).unwrap();
+
+        assert!(channel.resolve("\\link-in").is_ok());
+        assert_eq!(channel.resolve("\\link-out"), Err(STATUS_ACCESS_DENIED));
+        assert_eq!(channel.resolve("\\link-out\\x.txt"), Err(STATUS_ACCESS_DENIED));
+        assert_eq!(channel.resolve("\\dangling"), Err(STATUS_ACCESS_DENIED));
+
+        // Creating through the dangling link writes nothing outside
+        let mut channel = channel;
+        let request = create_request("\\dangling", 0x0000_0002, FILE_OPEN_IF);
+        assert_eq!(channel.create(&request), Err(STATUS_ACCESS_DENIED));
+        assert!(!dir.path().join("outside").join("new.txt").exists());
+    }
+
+    #[test]
+    fn read_only_share_refuses_changes() {
+        let (dir, mut channel) = share(true);
+        let read = create_request("\\inside.txt", 0x0000_0001, FILE_OPEN);
+        assert!(channel.create(&read).is_ok());
+
+        for (path, access, disposition) in [
+            ("\\inside.txt", 0x0000_0002, FILE_OPEN),
+            ("\\inside.txt", 0x0000_0001, FILE_OVERWRITE_IF),
+            ("\\new.txt", 0x0000_0001, FILE_OPEN_IF),
+            ("\\new.txt", 0x0000_0001, FILE_CREATE),
+        ] {
+            let request = create_request(path, access, disposition);
+            assert_eq!(channel.create(&request), Err(STATUS_MEDIA_WRITE_PROTECTED), "{}", path);
+        }
+        assert_eq!(fs::read_to_string(dir.path().join("root").join("inside.txt")).unwrap(), "in");
+        assert!(!dir.path().join("root").join("new.txt").exists());
+    }
+}
@@ -0,0 +1,605 @@
+//! Remote Desktop Gateway client (MS-TSGU). Uses the WebSocket transport
+//! with Basic authentication: the gateway tunnel is set up over HTTPS and the
+//! RDP connection then runs inside it as if it were a TCP stream. Gateways
+//! that only accept NTLM or Negotiate are refused.
+
+use crate::proxy::{self, ProxyConfig};
+use crate::util::{host_port, unbracket_host};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+use thiserror::Error;
+
+pub const DEFAULT_GATEWAY_PORT: u16 = 443;
+
+// Gateway packet types
+const PKT_TYPE_HANDSHAKE_REQUEST: u16 = 0x1;
+const PKT_TYPE_HANDSHAKE_RESPONSE: u16 = 0x2;
+const PKT_TYPE_TUNNEL_CREATE: u16 = 0x4;
+const PKT_TYPE_TUNNEL_RESPONSE: u16 = 0x5;
+const PKT_TYPE_TUNNEL_AUTH: u16 = 0x6;
+const PKT_TYPE_TUNNEL_AUTH_RESPONSE: u16 = 0x7;
+const PKT_TYPE_CHANNEL_CREATE: u16 = 0x8;
+const PKT_TYPE_CHANNEL_RESPONSE: u16 = 0x9;
+const PKT_TYPE_DATA: u16 = 0xA;
+const PKT_TYPE_CLOSE_CHANNEL: u16 = 0x10;
+const PKT_TYPE_CLOSE_CHANNEL_RESPONSE: u16 = 0x11;
+
+const HTTP_CAPABILITY_IDLE_TIMEOUT: u32 = 0x2;
+const PROTOCOL_RDP: u16 = 3;
+
+// Gateway error codes worth explaining
+const E_PROXY_INTERNALERROR: u32 = 0x800759D8;
+const E_PROXY_RAP_ACCESSDENIED: u32 = 0x800759DA;
+const E_PROXY_NAP_ACCESSDENIED: u32 = 0x800759DB;
+const E_PROXY_TS_CONNECTFAILED: u32 = 0x800759DD;
+
+/// packetType + reserved + packetLength
+const PACKET_HEADER_LEN: usize = 8;
+/// Largest RDP payload sent in one data packet
+const MAX_DATA_LEN: usize = 16 * 1024;
+
+// WebSocket opcodes
+const WS_CONTINUATION: u8 = 0x0;
+const WS_BINARY: u8 = 0x2;
+const WS_CLOSE: u8 = 0x8;
+const WS_PING: u8 = 0x9;
+const WS_PONG: u8 = 0xA;
+
+/// RD Gateway to tunnel an RDP connection through
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RdpGateway {
+    pub host: String,
+    #[serde(default = "default_gateway_port")]
+    pub port: u16,
+    /// Gateway credentials; the RDP credentials are used when unset
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Never written to the profile; saved profiles keep it in the keychain
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// Connect directly to hosts on the local network
+    #[serde(default)]
+    pub bypass_local: bool,
+}
+
+fn default_gateway_port() -> u16 {
+    DEFAULT_GATEWAY_PORT
+}
+
+impl RdpGateway {
+    /// Fill in missing gateway credentials from the RDP ones, as the gateway
+    /// usually belongs to the same domain as the target
+    pub fn with_default_credentials(&self, username: &str, password: &str, domain: Option<&str>) -> Self {
+        let own_username = self.username.as_deref().filter(|u| !u.is_empty());
+        let domain = match own_username {
+            Some(_) => self.domain.clone(),
+            None => self.domain.clone().or_else(|| domain.map(str::to_string)),
+        };
+        Self {
+            username: Some(own_username.unwrap_or(username).to_string()),
+            password: Some(self.password.clone().unwrap_or_else(|| password.to_string())),
+            domain,
+            ..self.clone()
+        }
+    }
+
+    /// Whether `host` is reached directly instead of through the gateway:
+    /// private, loopback and link-local addresses, single-label names and
+    /// `.local` names, when `bypass_local` is set
+    pub fn bypasses(&self, host: &str) -> bool {
+        if !self.bypass_local {
+            return false;
+        }
+        match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+            Ok(IpAddr::V6(ip)) => {
+                let first = ip.segments()[0];
+                // Unique local (fc00::/7) and link-local (fe80::/10)
+                ip.is_loopback() || (first & 0xFE00) == 0xFC00 || (first & 0xFFC0) == 0xFE80
+            }
+            Err(_) => !host.contains('.') || host.to_ascii_lowercase().ends_with(".local"),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum GatewayError {
+    #[error("RD Gateway authentication failed: {0}")]
+    Authentication(String),
+    #[error("RD Gateway could not connect to {target}: {reason}")]
+    Target { target: String, reason: String },
+    #[error("RD Gateway error: {0}")]
+    Protocol(String),
+    #[error("RD Gateway connection failed: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// RDP stream tunnelled through an RD Gateway
+#[derive(Debug)]
+pub struct GatewayStream {
+    tls: native_tls::TlsStream<TcpStream>,
+    /// Bytes read off the connection that don't form a whole WebSocket frame yet
+    frames: Vec<u8>,
+    /// WebSocket payload that doesn't form a whole gateway packet yet
+    packets: Vec<u8>,
+    /// RDP bytes ready to be read
+    data: VecDeque<u8>,
+    closed: bool,
+}
+
+impl GatewayStream {
+    /// Open a tunnel through `gateway` to `target_host:target_port`, waiting
+    /// at most `timeout` for each step
+    pub fn connect(
+        gateway: &RdpGateway,
+        target_host: &str,
+        target_port: u16,
+        proxy: Option<&ProxyConfig>,
+        timeout: Duration,
+    ) -> Result<Self, GatewayError> {
+        let tcp = proxy::connect_timeout(&gateway.host, gateway.port, proxy, timeout)?;
+        tcp.set_read_timeout(Some(timeout))?;
+        let tls = native_tls::TlsConnector::new()
+            .map_err(|e| GatewayError::Protocol(format!("TLS setup failed: {}", e)))?
+            .connect(&gateway.host, tcp)
+            .map_err(|e| GatewayError::Protocol(format!("TLS handshake with gateway failed: {}", e)))?;
+
+        let mut stream = Self {
+            tls,
+            frames: Vec::new(),
+            packets: Vec::new(),
+            data: VecDeque::new(),
+            closed: false,
+        };
+
+        stream.upgrade(
+            &gateway.host,
+            gateway.domain.as_deref(),
+            gateway.username.as_deref().unwrap_or_default(),
+            gateway.password.as_deref().unwrap_or_default(),
+        )?;
+        stream.open_channel(target_host, target_port)?;
+        Ok(stream)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tls.get_ref().local_addr()
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tls.get_ref().set_read_timeout(timeout)
+    }
+
+    /// Switch the HTTPS connection to a WebSocket, authenticating on the way
+    fn upgrade(&mut self, host: &str, domain: Option<&str>, username: &str, password: &str) -> Result<(), GatewayError> {
+        let key: [u8; 16] = rand::random();
+        let request = format!(
+            "GET /remoteDesktopGateway/ HTTP/1.1\r\n\
+             Host: {host}\r\n\
+             Connection: Upgrade\r\n\
+             Upgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Key: {}\r\n\
+             Authorization: {}\r\n\
+             RDG-Connection-Id: {{{}}}\r\n\
+             \r\n",
+            BASE64.encode(key),
+            basic_authorization(domain, username, password),
+            uuid::Uuid::new_v4(),
+        );
+        self.tls.write_all(request.as_bytes())?;
+
+        let header = proxy::read_response_header(&mut self.tls)?;
+        check_upgrade(&header, &gateway_user(domain, username))
+    }
+
+    /// Handshake, create and authorize the tunnel, then open a channel to the target
+    fn open_channel(&mut self, target_host: &str, target_port: u16) -> Result<(), GatewayError> {
+        // Version 1.0, no extended authentication
+        self.send_packet(PKT_TYPE_HANDSHAKE_REQUEST, &[1, 0, 0, 0, 0, 0])?;
+        let body = self.expect_packet(PKT_TYPE_HANDSHAKE_RESPONSE)?;
+        check_status(&body, 0, "handshake").map_err(GatewayError::Protocol)?;
+
+        let mut body = Vec::with_capacity(8);
+        body.extend_from_slice(&HTTP_CAPABILITY_IDLE_TIMEOUT.to_le_bytes());
+        body.extend_from_slice(&[0, 0, 0, 0]); // fieldsPresent, reserved
+        self.send_packet(PKT_TYPE_TUNNEL_CREATE, &body)?;
+        let body = self.expect_packet(PKT_TYPE_TUNNEL_RESPONSE)?;
+        // serverVersion comes before the status code
+        check_status(&body, 2, "tunnel creation").map_err(GatewayError::Authentication)?;
+
+        let client_name = utf16_with_nul("OpenTerm");
+        let mut body = Vec::with_capacity(4 + client_name.len());
+        body.extend_from_slice(&0u16.to_le_bytes()); // fieldsPresent
+        body.extend_from_slice(&(client_name.len() as u16).to_le_bytes());
+        body.extend_from_slice(&client_name);
+        self.send_packet(PKT_TYPE_TUNNEL_AUTH, &body)?;
+        let body = self.expect_packet(PKT_TYPE_TUNNEL_AUTH_RESPONSE)?;
+        check_status(&body, 0, "tunnel authorization").map_err(GatewayError::Authentication)?;
+
+        let resource = utf16_with_nul(unbracket_host(target_host));
+        let mut body = Vec::with_capacity(8 + resource.len());
+        body.push(1); // numResources
+        body.push(0); // numAltResources
+        body.extend_from_slice(&target_port.to_le_bytes());
+        body.extend_from_slice(&PROTOCOL_RDP.to_le_bytes());
+        body.extend_from_slice(&(resource.len() as u16).to_le_bytes());
+        body.extend_from_slice(&resource);
+        self.send_packet(PKT_TYPE_CHANNEL_CREATE, &body)?;
+        let body = self.expect_packet(PKT_TYPE_CHANNEL_RESPONSE)?;
+        let target = host_port(target_host, target_port);
+        match read_u32(&body, 0) {
+            Some(0) => Ok(()),
+            Some(E_PROXY_RAP_ACCESSDENIED) => Err(GatewayError::Authentication(format!(
+                "not allowed to connect to {}",
+                target
+            ))),
+            Some(code) => Err(GatewayError::Target {
+                target,
+                reason: describe_error(code),
+            }),
+            None => Err(GatewayError::Protocol("truncated channel response".to_string())),
+        }
+    }
+
+    /// Wait for the next gateway packet, which must be `packet_type`
+    fn expect_packet(&mut self, packet_type: u16) -> Result<Vec<u8>, GatewayError> {
+        loop {
+            match self.next_packet()? {
+                Some((ty, body)) if ty == packet_type => return Ok(body),
+                Some((PKT_TYPE_CLOSE_CHANNEL, body)) => {
+                    return Err(GatewayError::Protocol(format!(
+                        "gateway closed the tunnel ({})",
+                        read_u32(&body, 0).map(describe_error).unwrap_or_default()
+                    )))
+                }
+                // Keepalives and other notifications
+                Some(_) => continue,
+                None => return Err(GatewayError::Protocol("gateway closed the connection".to_string())),
+            }
+        }
+    }
+
+    /// Next whole gateway packet as (type, body), `None` once the WebSocket is
+    /// closed. Safe to call again after a read timeout; partial data is kept.
+    fn next_packet(&mut self) -> io::Result<Option<(u16, Vec<u8>)>> {
+        loop {
+            if self.packets.len() >= PACKET_HEADER_LEN {
+                let packet_type = u16::from_le_bytes([self.packets[0], self.packets[1]]);
+                let len = read_u32(&self.packets, 4).unwrap_or_default() as usize;
+                if len < PACKET_HEADER_LEN {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid gateway packet length"));
+                }
+                if self.packets.len() >= len {
+                    let body = self.packets[PACKET_HEADER_LEN..len].to_vec();
+                    self.packets.drain(..len);
+                    return Ok(Some((packet_type, body)));
+                }
+            }
+            if self.closed || !self.next_frame()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Read one WebSocket frame, appending its payload to `packets`. Returns
+    /// false once the WebSocket is closed.
+    fn next_frame(&mut self) -> io::Result<bool> {
+        loop {
+            if let Some((opcode, payload, frame_len)) = parse_frame(&self.frames) {
+                self.frames.drain(..frame_len);
+                match opcode {
+                    WS_BINARY | WS_CONTINUATION => self.packets.extend_from_slice(&payload),
+                    WS_PING => self.send_frame(WS_PONG, &payload)?,
+                    WS_CLOSE => {
+                        self.closed = true;
+                        return Ok(false);
+                    }
+                    _ => {}
+                }
+                return Ok(true);
+            }
+
+            let mut buf = [0u8; 16 * 1024];
+            let n = self.tls.read(&mut buf)?;
+            if n == 0 {
+                self.closed = true;
+                return Ok(false);
+            }
+            self.frames.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    fn send_packet(&mut self, packet_type: u16, body: &[u8]) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(PACKET_HEADER_LEN + body.len());
+        packet.extend_from_slice(&packet_type.to_le_bytes());
+        packet.extend_from_slice(&0u16.to_le_bytes());
+        packet.extend_from_slice(&((PACKET_HEADER_LEN + body.len()) as u32).to_le_bytes());
+        packet.extend_from_slice(body);
+        self.send_frame(WS_BINARY, &packet)
+    }
+
+    /// Send a single masked WebSocket frame, as clients must
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(14 + payload.len());
+        frame.push(0x80 | opcode);
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        let mask: [u8; 4] = rand::random();
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        self.tls.write_all(&frame)
+    }
+}
+
+impl Read for GatewayStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.data.is_empty() {
+            match self.next_packet()? {
+                Some((PKT_TYPE_DATA, body)) => {
+                    let len = body.get(..2).map_or(0, |b| u16::from_le_bytes([b[0], b[1]]) as usize);
+                    self.data.extend(body.iter().skip(2).take(len));
+                }
+                Some((PKT_TYPE_CLOSE_CHANNEL, _)) => {
+                    let _ = self.send_packet(PKT_TYPE_CLOSE_CHANNEL_RESPONSE, &0u32.to_le_bytes());
+                    self.closed = true;
+                    return Ok(0);
+                }
+                // Keepalives and other notifications
+                Some(_) => continue,
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.data.len());
+        for (dst, src) in buf.iter_mut().zip(self.data.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for GatewayStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for chunk in buf.chunks(MAX_DATA_LEN) {
+            let mut body = Vec::with_capacity(2 + chunk.len());
+            body.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+            body.extend_from_slice(chunk);
+            self.send_packet(PKT_TYPE_DATA, &body)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tls.flush()
+    }
+}
+
+/// User name as the gateway expects it, `DOMAIN\user` when a domain is set
+fn gateway_user(domain: Option<&str>, username: &str) -> String {
+    match domain.filter(|d| !d.is_empty()) {
+        Some(domain) => format!("{}\\{}", domain, username),
+        None => username.to_string(),
+    }
+}
+
+/// `Authorization` header value for the WebSocket upgrade
+fn basic_authorization(domain: Option<&str>, username: &str, password: &str) -> String {
+    format!("Basic {}", BASE64.encode(format!("{}:{}", gateway_user(domain, username), password)))
+}
+
+/// Whether the gateway's response to the upgrade request switched to a
+/// WebSocket. Only Basic authentication is supported; a gateway that asks
+/// for NTLM or Negotiate (Windows authentication) is refused with an error
+/// saying so.
+fn check_upgrade(header: &str, user: &str) -> Result<(), GatewayError> {
+    let status_line = header.lines().next().unwrap_or_default().trim();
+    match status_line.split_whitespace().nth(1).unwrap_or_default() {
+        "101" => Ok(()),
+        "401" => {
+            let schemes = auth_schemes(header);
+            if schemes.is_empty() || schemes.iter().any(|s| s.eq_ignore_ascii_case("Basic")) {
+                Err(GatewayError::Authentication(format!(
+                    "the gateway rejected the credentials for {}",
+                    user
+                )))
+            } else {
+                Err(GatewayError::Authentication(format!(
+                    "the gateway only accepts {} authentication, which isn't supported; \
+                     enable Basic authentication on the gateway to connect through it",
+                    schemes.join(" or ")
+                )))
+            }
+        }
+        "403" => Err(GatewayError::Authentication(format!("access denied ({})", status_line))),
+        _ => Err(GatewayError::Protocol(format!(
+            "unexpected response to WebSocket upgrade: {}",
+            status_line
+        ))),
+    }
+}
+
+/// Schemes of the challenges in `WWW-Authenticate`, e.g. ["Negotiate",
+/// "NTLM"]. A field can hold several challenges separated by commas, as can
+/// a challenge's parameters; those are the parts with an `=`.
+fn auth_schemes(header: &str) -> Vec<String> {
+    header
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("WWW-Authenticate"))
+        .flat_map(|(_, value)| split_unquoted(value, ','))
+        .filter_map(|part| part.split_whitespace().next())
+        .filter(|scheme| !scheme.contains('='))
+        .map(str::to_string)
+        .collect()
+}
+
+/// `value` split at each `separator` outside double quotes
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// Parse a complete WebSocket frame at the start of `buf` into
+/// (opcode, unmasked payload, frame length)
+fn parse_frame(buf: &[u8]) -> Option<(u8, Vec<u8>, usize)> {
+    let opcode = buf.first()? & 0x0F;
+    let second = *buf.get(1)?;
+    let masked = second & 0x80 != 0;
+
+    let (len, mut offset) = match second & 0x7F {
+        126 => (u16::from_be_bytes(buf.get(2..4)?.try_into().ok()?) as usize, 4),
+        127 => (u64::from_be_bytes(buf.get(2..10)?.try_into().ok()?) as usize, 10),
+        len => (len as usize, 2),
+    };
+    let mask = if masked {
+        let mask: [u8; 4] = buf.get(offset..offset + 4)?.try_into().ok()?;
+        offset += 4;
+        Some(mask)
+    } else {
+        None
+    };
+
+    let payload = buf.get(offset..offset.checked_add(len)?)?;
+    let payload = match mask {
+        Some(mask) => payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]).collect(),
+        None => payload.to_vec(),
+    };
+    Some((opcode, payload, offset + len))
+}
+
+/// Error unless the status code at `offset` in a response body is zero
+fn check_status(body: &[u8], offset: usize, step: &str) -> Result<(), String> {
+    match read_u32(body, offset) {
+        Some(0) => Ok(()),
+        Some(code) => Err(format!("{} refused: {}", step, describe_error(code))),
+        None => Err(format!("truncated {} response", step)),
+    }
+}
+
+fn describe_error(code: u32) -> String {
+    let description = match code {
+        E_PROXY_INTERNALERROR => "internal gateway error",
+        E_PROXY_RAP_ACCESSDENIED => "resource authorization policy denied access",
+        E_PROXY_NAP_ACCESSDENIED => "connection authorization policy denied access",
+        E_PROXY_TS_CONNECTFAILED => "the remote computer could not be reached",
+        _ => return format!("error 0x{:08X}", code),
+    };
+    format!("{} (0x{:08X})", description, code)
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(buf.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn utf16_with_nul(s: &str) -> Vec<u8> {
+    s.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basic_authorization_header() {
+        assert_eq!(
+            basic_authorization(None, "alice", "pa:ss"),
+            format!("Basic {}", BASE64.encode("alice:pa:ss"))
+        );
+        assert_eq!(
+            basic_authorization(Some("CORP"), "alice", "secret"),
+            format!("Basic {}", BASE64.encode("CORP\\alice:secret"))
+        );
+        assert_eq!(basic_authorization(Some(""), "alice", ""), format!("Basic {}", BASE64.encode("alice:")));
+    }
+
+    #[test]
+    fn upgrade_responses() {
+        let response = |status: &str, fields: &str| format!("HTTP/1.1 {}\r\n{}\r\n", status, fields);
+
+        assert!(check_upgrade(
+            &response("101 Switching Protocols", "Upgrade: websocket\r\nConnection: Upgrade\r\n"),
+            "alice"
+        )
+        .is_ok());
+
+        let rejected = [
+            response("401 Unauthorized", ""),
+            response("401 Unauthorized", "WWW-Authenticate: Basic realm=\"gw\", charset=\"UTF-8\"\r\n"),
+            response(
+                "401 Unauthorized",
+                "WWW-Authenticate: Negotiate\r\nwww-authenticate: NTLM\r\nWWW-Authenticate: Basic realm=\"gw\"\r\n",
+            ),
+        ];
+        for header in &rejected {
+            match check_upgrade(header, "CORP\\alice") {
+                Err(GatewayError::Authentication(message)) => {
+                    assert!(message.contains("rejected the credentials for CORP\\alice"), "{}", message)
+                }
+                other => panic!("expected rejected credentials, got {:?}", other),
+            }
+        }
+
+        for fields in [
+            "WWW-Authenticate: Negotiate\r\nWWW-Authenticate: NTLM\r\n",
+            "WWW-Authenticate: Negotiate, NTLM\r\n",
+        ] {
+            match check_upgrade(&response("401 Unauthorized", fields), "alice") {
+                Err(GatewayError::Authentication(message)) => {
+                    assert!(message.contains("only accepts Negotiate or NTLM"), "{}", message)
+                }
+                other => panic!("expected unsupported authentication, got {:?}", other),
+            }
+        }
+
+        assert!(matches!(
+            check_upgrade(&response("403 Forbidden", ""), "alice"),
+            Err(GatewayError::Authentication(_))
+        ));
+        assert!(matches!(
+            check_upgrade(&response("200 OK", ""), "alice"),
+            Err(GatewayError::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn auth_schemes_of_challenges() {
+        let header = "HTTP/1.1 401 Unauthorized\r\n\
+                      WWW-Authenticate: NTLM TlRMTVNTUAACAAAA==\r\n\
+                      WWW-Authenticate: Basic realm=\"a, b\", charset=\"UTF-8\"\r\n\
+                      Content-Length: 0\r\n\r\n";
+        assert_eq!(auth_schemes(header), ["NTLM", "Basic"]);
+    }
+}
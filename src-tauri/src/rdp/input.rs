@@ -12,6 +12,64 @@ pub enum InputEvent {
     MouseWheel { delta: i16, x: u16, y: u16 },
     #[serde(rename = "keyboard")]
     Keyboard { scancode: u16, down: bool },
+    /// Characters typed as Unicode key events (IME, non-US layouts)
+    #[serde(rename = "text")]
+    Text { text: String },
+}
+
+/// Key combinations the local OS would otherwise intercept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RdpSpecialKey {
+    /// Secure attention sequence: the logon, lock and Task Manager screen
+    CtrlAltDel,
+    CtrlShiftEsc,
+    /// Opens the Start menu
+    Windows,
+    AltTab,
+    AltShiftTab,
+    AltF4,
+    PrintScreen,
+    /// Screenshot of the active window
+    AltPrintScreen,
+    /// Locks the remote session
+    WinL,
+    WinR,
+    WinD,
+    WinE,
+}
+
+impl RdpSpecialKey {
+    /// Scancodes to press in order, then release in reverse; `0xE0xx` are
+    /// extended keys
+    fn scancodes(self) -> &'static [u16] {
+        const CTRL: u16 = 0x1D;
+        const SHIFT: u16 = 0x2A;
+        const ALT: u16 = 0x38;
+        const WIN: u16 = 0xE05B;
+        const PRINT_SCREEN: u16 = 0xE037;
+        match self {
+            RdpSpecialKey::CtrlAltDel => &[CTRL, ALT, 0xE053],
+            RdpSpecialKey::CtrlShiftEsc => &[CTRL, SHIFT, 0x01],
+            RdpSpecialKey::Windows => &[WIN],
+            RdpSpecialKey::AltTab => &[ALT, 0x0F],
+            RdpSpecialKey::AltShiftTab => &[ALT, SHIFT, 0x0F],
+            RdpSpecialKey::AltF4 => &[ALT, 0x3E],
+            RdpSpecialKey::PrintScreen => &[PRINT_SCREEN],
+            RdpSpecialKey::AltPrintScreen => &[ALT, PRINT_SCREEN],
+            RdpSpecialKey::WinL => &[WIN, 0x26],
+            RdpSpecialKey::WinR => &[WIN, 0x13],
+            RdpSpecialKey::WinD => &[WIN, 0x20],
+            RdpSpecialKey::WinE => &[WIN, 0x12],
+        }
+    }
+
+    /// Key presses and releases that type the combination
+    pub fn key_events(self) -> Vec<InputEvent> {
+        let scancodes = self.scancodes();
+        let presses = scancodes.iter().map(|&scancode| InputEvent::Keyboard { scancode, down: true });
+        let releases = scancodes.iter().rev().map(|&scancode| InputEvent::Keyboard { scancode, down: false });
+        presses.chain(releases).collect()
+    }
 }
 
 // Mouse button constants
@@ -25,7 +83,6 @@ pub struct InputBatcher {
     last_flush: Instant,
     max_batch_time: Duration,
     max_batch_size: usize,
-    last_mouse_pos: Option<(u16, u16)>,
 }
 
 impl InputBatcher {
@@ -35,22 +92,15 @@ impl InputBatcher {
             last_flush: Instant::now(),
             max_batch_time: Duration::from_millis(16), // ~60 FPS max input rate
             max_batch_size: 10,
-            last_mouse_pos: None,
         }
     }
 
     /// Add an event to the batch, returns true if should flush immediately
     pub fn add_event(&mut self, event: InputEvent) -> bool {
         // For mouse moves, only keep the latest position to reduce spam
-        match &event {
-            InputEvent::MouseMove { x, y } => {
-                // Remove any previous mouse move events in the batch
-                self.pending_events.retain(|e| !matches!(e, InputEvent::MouseMove { .. }));
-                self.last_mouse_pos = Some((*x, *y));
-            }
-            _ => {
-                // For other events, always add them
-            }
+        if let InputEvent::MouseMove { .. } = event {
+            // Remove any previous mouse move events in the batch
+            self.pending_events.retain(|e| !matches!(e, InputEvent::MouseMove { .. }));
         }
 
         self.pending_events.push(event);
@@ -73,7 +123,7 @@ impl InputBatcher {
     fn has_critical_events(&self) -> bool {
         self.pending_events.iter().any(|event| matches!(
             event,
-            InputEvent::MouseButton { .. } | InputEvent::Keyboard { .. }
+            InputEvent::MouseButton { .. } | InputEvent::Keyboard { .. } | InputEvent::Text { .. }
         ))
     }
 
@@ -83,6 +133,14 @@ impl InputBatcher {
         std::mem::take(&mut self.pending_events)
     }
 
+    /// How long until the held-back events are due, if there are any
+    pub fn time_until_flush(&self) -> Option<Duration> {
+        if self.pending_events.is_empty() {
+            return None;
+        }
+        Some(self.max_batch_time.saturating_sub(self.last_flush.elapsed()))
+    }
+
     /// Force flush if enough time has passed
     pub fn maybe_flush(&mut self) -> Vec<InputEvent> {
         if self.should_flush() {
@@ -98,3 +156,44 @@ impl Default for InputBatcher {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesces_mouse_moves() {
+        let mut batcher = InputBatcher::new();
+        let mut pdus = Vec::new();
+        for i in 0..100 {
+            if batcher.add_event(InputEvent::MouseMove { x: i, y: i }) {
+                pdus.push(batcher.flush());
+            }
+        }
+        // The rest goes out when the flush timer fires
+        assert!(batcher.time_until_flush().is_some());
+        pdus.push(batcher.flush());
+
+        assert!(pdus.len() <= 3, "{} PDUs for 100 moves", pdus.len());
+        assert!(pdus.iter().all(|pdu| pdu.len() == 1));
+        assert!(matches!(pdus.last().unwrap()[..], [InputEvent::MouseMove { x: 99, y: 99 }]));
+    }
+
+    #[test]
+    fn clicks_are_never_delayed() {
+        let mut batcher = InputBatcher::new();
+        assert!(!batcher.add_event(InputEvent::MouseMove { x: 10, y: 20 }));
+        assert!(batcher.add_event(InputEvent::MouseButton { button: MOUSE_BUTTON_LEFT, down: true, x: 10, y: 20 }));
+        let pdu = batcher.flush();
+        assert!(matches!(
+            pdu[..],
+            [InputEvent::MouseMove { x: 10, y: 20 }, InputEvent::MouseButton { down: true, .. }]
+        ));
+
+        // Right after a flush, still inside the batch window
+        assert!(batcher.add_event(InputEvent::MouseButton { button: MOUSE_BUTTON_LEFT, down: false, x: 10, y: 20 }));
+        assert!(batcher.add_event(InputEvent::Keyboard { scancode: 0x1C, down: true }));
+        assert_eq!(batcher.flush().len(), 2);
+        assert_eq!(batcher.time_until_flush(), None);
+    }
+}
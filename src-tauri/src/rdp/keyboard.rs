@@ -0,0 +1,283 @@
+use ironrdp_pdu::gcc::KeyboardType;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// US English, used when the system layout can't be determined
+pub const DEFAULT_KEYBOARD_LAYOUT: u32 = 0x409;
+
+/// A keyboard layout offered in the connection dialog
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct KeyboardLayout {
+    /// Locale identifier, e.g. "de-DE"
+    pub locale: &'static str,
+    pub label: &'static str,
+    /// Keyboard layout ID (KLID)
+    pub id: u32,
+}
+
+const fn layout(locale: &'static str, label: &'static str, id: u32) -> KeyboardLayout {
+    KeyboardLayout { locale, label, id }
+}
+
+/// Layouts by locale. The first entry for a language is used when only the
+/// language is known.
+pub const KEYBOARD_LAYOUTS: &[KeyboardLayout] = &[
+    layout("en-US", "English (US)", 0x409),
+    layout("en-GB", "English (UK)", 0x809),
+    layout("en-IE", "Irish", 0x1809),
+    layout("de-DE", "German", 0x407),
+    layout("de-CH", "German (Switzerland)", 0x807),
+    layout("de-AT", "German (Austria)", 0x407),
+    layout("fr-FR", "French", 0x40C),
+    layout("fr-BE", "French (Belgium)", 0x80C),
+    layout("fr-CA", "French (Canada)", 0x1009),
+    layout("fr-CH", "French (Switzerland)", 0x100C),
+    layout("es-ES", "Spanish", 0x40A),
+    layout("es-MX", "Spanish (Latin America)", 0x80A),
+    layout("it-IT", "Italian", 0x410),
+    layout("pt-PT", "Portuguese", 0x816),
+    layout("pt-BR", "Portuguese (Brazil)", 0x416),
+    layout("nl-NL", "Dutch", 0x413),
+    layout("nl-BE", "Belgian (Period)", 0x813),
+    layout("da-DK", "Danish", 0x406),
+    layout("nb-NO", "Norwegian", 0x414),
+    layout("sv-SE", "Swedish", 0x41D),
+    layout("fi-FI", "Finnish", 0x40B),
+    layout("is-IS", "Icelandic", 0x40F),
+    layout("pl-PL", "Polish (Programmers)", 0x415),
+    layout("cs-CZ", "Czech", 0x405),
+    layout("sk-SK", "Slovak", 0x41B),
+    layout("hu-HU", "Hungarian", 0x40E),
+    layout("ro-RO", "Romanian", 0x418),
+    layout("tr-TR", "Turkish Q", 0x41F),
+    layout("el-GR", "Greek", 0x408),
+    layout("ru-RU", "Russian", 0x419),
+    layout("uk-UA", "Ukrainian", 0x422),
+    layout("he-IL", "Hebrew", 0x40D),
+    layout("ar-SA", "Arabic", 0x401),
+    layout("ja-JP", "Japanese", 0x411),
+    layout("ko-KR", "Korean", 0x412),
+    layout("zh-CN", "Chinese (Simplified)", 0x804),
+    layout("zh-TW", "Chinese (Traditional)", 0x404),
+    layout("vi-VN", "Vietnamese", 0x42A),
+];
+
+/// Short names accepted besides locales (mostly country codes)
+const LAYOUT_ALIASES: &[(&str, u32)] = &[
+    ("us", 0x409),
+    ("gb", 0x809),
+    ("uk", 0x809),
+    ("ch", 0x807),
+    ("be", 0x80C),
+    ("ca", 0x1009),
+    ("br", 0x416),
+    ("no", 0x414),
+    ("cz", 0x405),
+    ("zh", 0x804),
+];
+
+/// Look up a layout ID by locale ("de-DE", "fr_CA.UTF-8"), language ("ja")
+/// or short name ("uk", "ch"), case-insensitively
+pub fn keyboard_layout_id(name: &str) -> Option<u32> {
+    // Drop encoding and modifier ("de_DE.UTF-8@euro") and normalise separators
+    let locale = name.split(['.', '@']).next().unwrap_or_default().replace('_', "-");
+    let language = locale.split('-').next().unwrap_or_default();
+
+    KEYBOARD_LAYOUTS
+        .iter()
+        .find(|l| l.locale.eq_ignore_ascii_case(&locale))
+        .map(|l| l.id)
+        .or_else(|| {
+            LAYOUT_ALIASES
+                .iter()
+                .find(|(alias, _)| alias.eq_ignore_ascii_case(&locale))
+                .map(|&(_, id)| id)
+        })
+        .or_else(|| {
+            KEYBOARD_LAYOUTS
+                .iter()
+                .find(|l| {
+                    l.locale
+                        .split('-')
+                        .next()
+                        .is_some_and(|lang| lang.eq_ignore_ascii_case(language))
+                })
+                .map(|l| l.id)
+        })
+}
+
+/// Layout of the local keyboard, so the remote session types what the
+/// user's keys say. Falls back to US English.
+pub fn system_keyboard_layout() -> u32 {
+    detect_keyboard_layout().unwrap_or(DEFAULT_KEYBOARD_LAYOUT)
+}
+
+#[cfg(windows)]
+fn detect_keyboard_layout() -> Option<u32> {
+    #[link(name = "user32")]
+    extern "system" {
+        fn GetKeyboardLayout(thread_id: u32) -> isize;
+    }
+
+    // The low word of the HKL is the layout's language ID
+    let hkl = unsafe { GetKeyboardLayout(0) };
+    match (hkl as usize & 0xFFFF) as u32 {
+        0 => None,
+        id => Some(id),
+    }
+}
+
+#[cfg(not(windows))]
+fn detect_keyboard_layout() -> Option<u32> {
+    // The locale is the best hint available without a display server query
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .filter(|value| value != "C" && value != "POSIX")
+        .and_then(|value| keyboard_layout_id(&value))
+}
+
+/// Layouts for the connection dialog, with the local one to preselect
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyboardLayouts {
+    pub system_layout: u32,
+    pub layouts: &'static [KeyboardLayout],
+}
+
+pub fn keyboard_layouts() -> KeyboardLayouts {
+    KeyboardLayouts {
+        system_layout: system_keyboard_layout(),
+        layouts: KEYBOARD_LAYOUTS,
+    }
+}
+
+/// Physical keyboard reported to the server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RdpKeyboardType {
+    IbmPcXt,
+    OlivettiIco,
+    IbmPcAt,
+    /// 101/102-key keyboard
+    #[default]
+    IbmEnhanced,
+    Nokia1050,
+    Nokia9140,
+    Japanese,
+}
+
+impl RdpKeyboardType {
+    pub fn to_gcc(self) -> KeyboardType {
+        match self {
+            RdpKeyboardType::IbmPcXt => KeyboardType::IbmPcXt,
+            RdpKeyboardType::OlivettiIco => KeyboardType::OlivettiIco,
+            RdpKeyboardType::IbmPcAt => KeyboardType::IbmPcAt,
+            RdpKeyboardType::IbmEnhanced => KeyboardType::IbmEnhanced,
+            RdpKeyboardType::Nokia1050 => KeyboardType::Nokia1050,
+            RdpKeyboardType::Nokia9140 => KeyboardType::Nokia9140,
+            RdpKeyboardType::Japanese => KeyboardType::Japanese,
+        }
+    }
+}
+
+/// Keyboard settings sent to the server while connecting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RdpKeyboard {
+    /// Layout ID; also accepts a locale or name known to `keyboard_layout_id`.
+    /// Defaults to US English; see `RdpKeyboard::system` for the local one.
+    #[serde(deserialize_with = "deserialize_layout")]
+    pub layout: u32,
+    pub keyboard_type: RdpKeyboardType,
+    pub functional_keys_count: u32,
+}
+
+impl RdpKeyboard {
+    /// Default settings with the local keyboard's layout, for connects and
+    /// profiles that don't name one
+    pub fn system() -> Self {
+        Self {
+            layout: system_keyboard_layout(),
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for RdpKeyboard {
+    fn default() -> Self {
+        Self {
+            layout: DEFAULT_KEYBOARD_LAYOUT,
+            keyboard_type: RdpKeyboardType::default(),
+            functional_keys_count: 12,
+        }
+    }
+}
+
+fn deserialize_layout<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Layout {
+        Id(u32),
+        Name(String),
+    }
+
+    match Layout::deserialize(deserializer)? {
+        Layout::Id(id) => Ok(id),
+        Layout::Name(name) => keyboard_layout_id(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown keyboard layout: {}", name))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_layouts_by_locale_language_and_alias() {
+        let cases = [
+            ("en_US.UTF-8", 0x409),
+            ("en-GB", 0x809),
+            ("uk", 0x809),
+            ("de_DE.UTF-8@euro", 0x407),
+            ("de-CH", 0x807),
+            ("ch", 0x807),
+            ("fr_FR", 0x40C),
+            ("fr-CA", 0x1009),
+            ("es", 0x40A),
+            ("it_IT.utf8", 0x410),
+            ("pt-BR", 0x416),
+            ("nb_NO", 0x414),
+            ("SV-se", 0x41D),
+            ("pl", 0x415),
+            ("ru_RU.KOI8-R", 0x419),
+            ("ja_JP.eucJP", 0x411),
+            ("ko", 0x412),
+            ("zh_TW", 0x404),
+            ("zh", 0x804),
+        ];
+        for (name, id) in cases {
+            assert_eq!(keyboard_layout_id(name), Some(id), "{}", name);
+        }
+        assert_eq!(keyboard_layout_id("xx-YY"), None);
+        assert_eq!(keyboard_layout_id(""), None);
+    }
+
+    #[test]
+    fn every_listed_layout_resolves_to_itself() {
+        for layout in KEYBOARD_LAYOUTS {
+            assert_eq!(keyboard_layout_id(layout.locale), Some(layout.id), "{}", layout.locale);
+        }
+    }
+
+    #[test]
+    fn default_layout_is_fixed() {
+        assert_eq!(RdpKeyboard::default().layout, DEFAULT_KEYBOARD_LAYOUT);
+        let keyboard: RdpKeyboard = serde_json::from_str(r#"{"keyboard_type": "Japanese"}"#).unwrap();
+        assert_eq!(keyboard.layout, DEFAULT_KEYBOARD_LAYOUT);
+        let keyboard: RdpKeyboard = serde_json::from_str(r#"{"layout": "de-DE"}"#).unwrap();
+        assert_eq!(keyboard.layout, 0x407);
+        assert!(serde_json::from_str::<RdpKeyboard>(r#"{"layout": "klingon"}"#).is_err());
+    }
+}
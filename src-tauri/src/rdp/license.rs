@@ -0,0 +1,84 @@
+//! Client license cache. A licensed RDS farm issues a CAL on the first
+//! connect and accepts it back on later ones; without a cache every
+//! connect negotiates a new one.
+
+use crate::storage::{RdpLicenses, StoredLicense};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use ironrdp_connector::{ConnectorResult, LicenseCache};
+use ironrdp_pdu::rdp::server_license::LicenseInformation;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Licenses for one server, kept in the config directory. Storage
+/// failures are logged rather than failing the connect, which then just
+/// negotiates a new license.
+#[derive(Debug)]
+pub struct RdpLicenseCache {
+    server: String,
+    /// A cached license was offered to the server this connect
+    offered: AtomicBool,
+}
+
+impl RdpLicenseCache {
+    pub fn new(server: &str) -> Self {
+        Self {
+            server: server.to_string(),
+            offered: AtomicBool::new(false),
+        }
+    }
+
+    /// Hardware ID the cached licenses are bound to
+    pub fn hardware_id() -> Option<[u32; 4]> {
+        RdpLicenses::new()
+            .and_then(|licenses| licenses.hardware_id())
+            .map_err(|e| eprintln!("RDP: Failed to load license hardware ID: {}", e))
+            .ok()
+    }
+
+    pub fn offered(&self) -> bool {
+        self.offered.load(Ordering::SeqCst)
+    }
+
+    /// Drop this server's licenses, e.g. after it rejected one, so the next
+    /// connect negotiates afresh
+    pub fn forget(&self) {
+        self.offered.store(false, Ordering::SeqCst);
+        if let Err(e) = RdpLicenses::new().and_then(|licenses| licenses.forget(&self.server)) {
+            eprintln!("RDP: Failed to clear cached licenses for {}: {}", self.server, e);
+        }
+    }
+}
+
+impl LicenseCache for RdpLicenseCache {
+    fn get_license(&self, info: LicenseInformation) -> ConnectorResult<Option<Vec<u8>>> {
+        let license = RdpLicenses::new().and_then(|licenses| {
+            licenses.get(&self.server, info.version, &info.scope, &info.company_name, &info.product_id)
+        });
+        match license {
+            Ok(Some(license)) => {
+                eprintln!("RDP: Offering cached license for {} ({})", self.server, info.scope);
+                self.offered.store(true, Ordering::SeqCst);
+                Ok(Some(license))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => {
+                eprintln!("RDP: Failed to read license cache: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn store_license(&self, info: LicenseInformation) -> ConnectorResult<()> {
+        let license = StoredLicense {
+            version: info.version,
+            scope: info.scope,
+            company_name: info.company_name,
+            product_id: info.product_id,
+            license: BASE64.encode(&info.license_info),
+        };
+        match RdpLicenses::new().and_then(|licenses| licenses.store(&self.server, license)) {
+            Ok(()) => eprintln!("RDP: Cached license for {}", self.server),
+            Err(e) => eprintln!("RDP: Failed to cache license for {}: {}", self.server, e),
+        }
+        Ok(())
+    }
+}
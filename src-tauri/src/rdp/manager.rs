@@ -1,163 +1,587 @@
-use super::{InputEvent, RdpClient};
+use crate::certificate::ConnectError;
+use super::reconnect::{reconnect_delay, RdpConnectParams, RdpReconnecting, RECONNECT_ATTEMPTS};
+use super::{
+    FrameUpdate, InputEvent, RdpClient, RdpCodec, RdpCodecStats, RdpConnectStage, RdpDisconnect, RdpQuality,
+    RdpSpecialKey,
+};
+use crate::frame::{
+    coalesce_regions, save_screenshot, FrameChannel, FrameFlow, FrameFlowStats, Region, ScreenshotFormat,
+    ScreenshotInfo,
+};
+use crate::idle::{Activity, IdleWatch};
+use crate::storage::UsageTracker;
 use parking_lot::Mutex;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
+use tauri::ipc::{Channel, Response};
 use tauri::{AppHandle, Emitter};
 
+/// Pause between characters typed by `send_text`
+const TEXT_KEY_DELAY: Duration = Duration::from_millis(5);
+
+/// Payload of `rdp-resized-{session_id}` when the desktop size changes
+#[derive(Debug, Clone, Serialize)]
+pub struct RdpResize {
+    pub width: u16,
+    pub height: u16,
+}
+
+impl From<(u16, u16)> for RdpResize {
+    fn from((width, height): (u16, u16)) -> Self {
+        Self { width, height }
+    }
+}
+
+/// Payload of `rdp-quality-{session_id}`, sent when the quality preset
+/// changes and after each reconnect
+#[derive(Debug, Clone, Serialize)]
+pub struct RdpQualityInfo {
+    /// Preset in effect for frame pacing and output suppression, and used
+    /// for the next connection
+    pub quality: RdpQuality,
+    /// What the current connection negotiated
+    pub color_depth: u32,
+    pub codec: RdpCodec,
+    /// Color depth or codec differ from what `quality` asks for until the
+    /// session reconnects
+    pub pending_reconnect: bool,
+}
+
+impl RdpQualityInfo {
+    fn new(client: &RdpClient, params: &RdpConnectParams) -> Self {
+        let codec = params.codec.resolve(params.quality);
+        Self {
+            quality: client.quality(),
+            color_depth: client.color_depth(),
+            codec: client.codec(),
+            pending_reconnect: client.codec() != codec
+                || client.color_depth() != params.quality.color_depth_with(codec),
+        }
+    }
+}
+
 pub struct RdpManager {
     sessions: Arc<Mutex<HashMap<String, Arc<RdpClient>>>>,
+    /// How each session was connected, for reconnecting after it drops
+    reconnects: Arc<Mutex<HashMap<String, Reconnect>>>,
+    /// Binary frame channels attached by viewers, kept across reconnects
+    frame_channels: Arc<Mutex<HashMap<String, FrameChannel>>>,
+    /// Frame acks and stats of each viewer, kept across reconnects
+    frame_flows: Arc<Mutex<HashMap<String, FrameFlow>>>,
+    /// Cancel flags of connects still in progress
+    connecting: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+    /// Last input and idle watcher of sessions with an idle timeout. Screen
+    /// updates don't count as activity; a clock on the desktop would keep
+    /// the session alive forever.
+    idle: Arc<Mutex<HashMap<String, (Activity, IdleWatch)>>>,
+    /// Sessions the server ended, and why, until the viewer closes them
+    ended: Arc<Mutex<HashMap<String, RdpDisconnect>>>,
+    /// Sessions opened from a saved profile, timed until closed
+    usage: Mutex<HashMap<String, UsageTracker>>,
+}
+
+#[derive(Clone)]
+struct Reconnect {
+    params: RdpConnectParams,
+    /// Reconnect without asking; otherwise `rdp-connection-lost-{id}` is
+    /// sent and the frontend calls `rdp_reconnect`
+    automatic: bool,
 }
 
 impl RdpManager {
     pub fn new() -> Self {
         Self {
             sessions: Arc::new(Mutex::new(HashMap::new())),
+            reconnects: Arc::new(Mutex::new(HashMap::new())),
+            frame_channels: Arc::new(Mutex::new(HashMap::new())),
+            frame_flows: Arc::new(Mutex::new(HashMap::new())),
+            connecting: Arc::new(Mutex::new(HashMap::new())),
+            idle: Arc::new(Mutex::new(HashMap::new())),
+            usage: Mutex::new(HashMap::new()),
+            ended: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
     pub fn create_session(
         &self,
         session_id: String,
-        host: &str,
-        port: u16,
-        username: &str,
-        password: &str,
-        domain: Option<&str>,
-        width: u16,
-        height: u16,
-        quality: super::RdpQuality,
-    ) -> Result<(u16, u16), String> {
-        let client = RdpClient::connect(host, port, username, password, domain, width, height, quality)
-            .map_err(|e| format!("RDP connection failed: {}", e))?;
+        mut params: RdpConnectParams,
+        auto_reconnect: bool,
+        cancel: Option<&AtomicBool>,
+        progress: Option<&dyn Fn(RdpConnectStage)>,
+    ) -> Result<(u16, u16), ConnectError> {
+        let client = params.connect(cancel, progress)?;
 
         let w = client.width();
         let h = client.height();
 
         let client = Arc::new(client);
-        self.sessions.lock().insert(session_id, client);
+        {
+            // Checked under the lock so `close_session` can't slip in between
+            let mut sessions = self.sessions.lock();
+            if cancel.is_some_and(|cancel| cancel.load(Ordering::SeqCst)) {
+                client.disconnect();
+                return Err(ConnectError::Other("Connection cancelled".to_string()));
+            }
+            sessions.insert(session_id.clone(), client);
+        }
+        self.frame_channels
+            .lock()
+            .insert(session_id.clone(), FrameChannel::default());
+        self.frame_flows
+            .lock()
+            .insert(session_id.clone(), FrameFlow::new(params.max_frames_in_flight));
+        self.reconnects.lock().insert(
+            session_id,
+            Reconnect {
+                params,
+                automatic: auto_reconnect,
+            },
+        );
 
         Ok((w, h))
     }
 
+    /// Connect on a background thread and start the frame reader, then send
+    /// `rdp-connected-{id}` with the desktop size or `rdp-connect-failed-{id}`
+    /// with the error. Each phase is announced as `rdp-connect-progress-{id}`.
+    /// `cancel_connect` or `close_session` stops it between connection
+    /// phases. Fails if a session or connect with `session_id` exists.
+    pub fn connect_in_background(
+        self: Arc<Self>,
+        session_id: String,
+        params: RdpConnectParams,
+        auto_reconnect: bool,
+        app_handle: AppHandle,
+    ) -> Result<(), String> {
+        let cancel = Arc::new(AtomicBool::new(false));
+        {
+            let mut connecting = self.connecting.lock();
+            if connecting.contains_key(&session_id)
+                || self.sessions.lock().contains_key(&session_id)
+                || self.reconnects.lock().contains_key(&session_id)
+            {
+                return Err(format!("RDP session {} already exists", session_id));
+            }
+            connecting.insert(session_id.clone(), cancel.clone());
+        }
+
+        thread::spawn(move || {
+            let progress_event = format!("rdp-connect-progress-{}", session_id);
+            let progress = |stage: RdpConnectStage| {
+                let _ = app_handle.emit(&progress_event, stage);
+            };
+            let result = self
+                .create_session(
+                    session_id.clone(),
+                    params,
+                    auto_reconnect,
+                    Some(&cancel),
+                    Some(&progress),
+                )
+                .and_then(|size| {
+                    self.start_frame_reader(&session_id, app_handle.clone())?;
+                    Ok(size)
+                });
+            self.connecting.lock().remove(&session_id);
+
+            match result {
+                Ok(size) => {
+                    let _ = app_handle.emit(&format!("rdp-connected-{}", session_id), RdpResize::from(size));
+                }
+                Err(e) => {
+                    eprintln!("RDP: Connect for session {} failed: {}", session_id, e);
+                    let _ = app_handle.emit(&format!("rdp-connect-failed-{}", session_id), e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Abort a connect started by `connect_in_background`. Does nothing if it
+    /// already finished.
+    pub fn cancel_connect(&self, session_id: &str) {
+        if let Some(cancel) = self.connecting.lock().get(session_id) {
+            cancel.store(true, Ordering::SeqCst);
+        }
+    }
+
     pub fn start_frame_reader(&self, session_id: &str, app_handle: AppHandle) -> Result<(), String> {
-        let sessions = self.sessions.lock();
-        let client = sessions
+        let mut client = self
+            .sessions
+            .lock()
             .get(session_id)
             .ok_or_else(|| "RDP session not found".to_string())?
             .clone();
 
+        let frames = self
+            .frame_channels
+            .lock()
+            .entry(session_id.to_string())
+            .or_default()
+            .clone();
+        let flow = self
+            .frame_flows
+            .lock()
+            .entry(session_id.to_string())
+            .or_default()
+            .clone();
         let session_id = session_id.to_string();
-        let width = client.width();
-        let height = client.height();
+        let sessions = self.sessions.clone();
+        let reconnects = self.reconnects.clone();
+        let frame_channels = self.frame_channels.clone();
+        let frame_flows = self.frame_flows.clone();
+        let idle = self.idle.clone();
+        let ended = self.ended.clone();
 
         thread::spawn(move || {
-            let mut frame_count = 0;
-            let mut pending_rects: Vec<super::DirtyRect> = Vec::new();
-            let mut last_frame_time = std::time::Instant::now();
-            let mut last_input_time = std::time::Instant::now();
-            let mut activity_detected = false;
-            
-            // More conservative frame rate: reduce from 75 FPS to reasonable levels
-            let get_frame_interval = |has_activity: bool, has_changes: bool| {
-                if has_activity || has_changes {
-                    Duration::from_millis(50) // 20 FPS during activity (was 30 FPS)
-                } else {
-                    Duration::from_millis(200) // 5 FPS when static (was 10 FPS)
+            let disconnect = loop {
+                let e = match read_frames(&client, &session_id, &frames, &flow, &app_handle) {
+                    Ok(()) => break client.take_disconnect(),
+                    Err(e) => e,
+                };
+                let automatic = match reconnects.lock().get(&session_id) {
+                    // A RemoteFX failure isn't a lost connection: switch to
+                    // bitmaps right away instead of asking
+                    Some(reconnect) => reconnect.automatic || client.codec_failed(),
+                    // Closed by the user
+                    None => break None,
+                };
+                if !automatic {
+                    let _ = app_handle.emit(&format!("rdp-connection-lost-{}", session_id), e);
+                    break None;
                 }
-            };
-            
-            eprintln!("RDP: Starting frame reader for session {} with adaptive frame rate", session_id);
-            
-            while client.is_connected() {
-                // Check for recent input activity (within last 2 seconds)
-                activity_detected = last_input_time.elapsed() < Duration::from_secs(2);
-                
-                // Process RDP events - collect dirty rectangles
-                match client.process_events() {
-                    Ok(Some(mut rects)) => {
-                        // Accumulate dirty rectangles and coalesce overlapping ones
-                        pending_rects.append(&mut rects);
-                        pending_rects = Self::coalesce_dirty_rects(pending_rects);
-                        activity_detected = true; // Visual changes indicate activity
-                    }
-                    Ok(None) => {
-                        // No update from server - send initial full frame if needed
-                        if frame_count == 0 {
-                            let frame_data = client.get_frame();
-                            let update = super::FrameUpdate::full(width, height, &frame_data);
-                            let event_name = format!("rdp-frame-{}", session_id);
-                            if let Err(e) = app_handle.emit(&event_name, &update) {
-                                eprintln!("RDP: Failed to emit initial frame: {}", e);
-                            }
-                            frame_count = 1;
-                            last_frame_time = std::time::Instant::now();
-                        }
-                    }
+                match reconnect(&sessions, &reconnects, &session_id, &client, e, &frames, &app_handle) {
+                    Ok(new_client) => client = new_client,
                     Err(e) => {
-                        eprintln!("RDP: Read error: {}", e);
-                        let _ = app_handle.emit(&format!("rdp-error-{}", session_id), e);
-                        break;
-                    }
-                }
-                
-                // Send accumulated dirty rectangles based on adaptive timing
-                let has_changes = !pending_rects.is_empty();
-                let frame_interval = get_frame_interval(activity_detected, has_changes);
-                
-                if has_changes && last_frame_time.elapsed() >= frame_interval {
-                    let update = super::FrameUpdate::Partial {
-                        rects: std::mem::take(&mut pending_rects),
-                    };
-                    let event_name = format!("rdp-frame-{}", session_id);
-                    if let Err(e) = app_handle.emit(&event_name, &update) {
-                        eprintln!("RDP: Failed to emit frame update: {}", e);
-                        break;
-                    }
-                    frame_count += 1;
-                    last_frame_time = std::time::Instant::now();
-                    
-                    if frame_count % 100 == 0 {
-                        eprintln!("RDP: Sent {} frame updates for session {}", frame_count, session_id);
+                        let _ = app_handle.emit(&format!("rdp-error-{}", session_id), &e);
+                        break Some(RdpDisconnect::connection_lost(&e));
                     }
                 }
+            };
 
-                // Minimal sleep - read timeout handles pacing
-                thread::sleep(Duration::from_millis(1));
+            // Drop the ended session, unless the user closed it meanwhile
+            if let Some(disconnect) = disconnect {
+                let mut sessions = sessions.lock();
+                if sessions.get(&session_id).is_some_and(|current| Arc::ptr_eq(current, &client)) {
+                    sessions.remove(&session_id);
+                    drop(sessions);
+                    reconnects.lock().remove(&session_id);
+                    frame_channels.lock().remove(&session_id);
+                    frame_flows.lock().remove(&session_id);
+                    idle.lock().remove(&session_id);
+                    eprintln!("RDP: Session {} ended: {}", session_id, disconnect);
+                    ended.lock().insert(session_id.clone(), disconnect.clone());
+                    let _ = app_handle.emit(&format!("rdp-disconnected-{}", session_id), disconnect);
+                }
             }
-            
+
             eprintln!("RDP: Frame reader stopped for session {}", session_id);
         });
 
         Ok(())
     }
 
+    /// Send the session's frames over `channel` as binary messages instead of
+    /// `rdp-frame-{session_id}` events
+    pub fn attach_frame_channel(&self, session_id: &str, channel: Channel<Response>) -> Result<(), String> {
+        if !self.sessions.lock().contains_key(session_id) {
+            return Err("RDP session not found".to_string());
+        }
+        self.frame_channels
+            .lock()
+            .entry(session_id.to_string())
+            .or_default()
+            .attach(channel);
+        Ok(())
+    }
+
+    /// Send the session's frames as `rdp-frame-{session_id}` events again
+    pub fn detach_frame_channel(&self, session_id: &str) -> Result<(), String> {
+        if !self.sessions.lock().contains_key(session_id) {
+            return Err("RDP session not found".to_string());
+        }
+        if let Some(frames) = self.frame_channels.lock().get(session_id) {
+            frames.detach();
+        }
+        Ok(())
+    }
+
+    fn frame_flow(&self, session_id: &str) -> Result<FrameFlow, String> {
+        self.frame_flows
+            .lock()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| "RDP session not found".to_string())
+    }
+
+    /// The viewer painted a frame, so another may be sent
+    pub fn frame_ack(&self, session_id: &str) -> Result<(), String> {
+        self.frame_flow(session_id)?.ack();
+        Ok(())
+    }
+
+    /// Change how many frames may await an ack, for this connection and
+    /// any reconnect
+    pub fn set_max_frames_in_flight(&self, session_id: &str, max: usize) -> Result<(), String> {
+        self.frame_flow(session_id)?.set_max_in_flight(max);
+        if let Some(reconnect) = self.reconnects.lock().get_mut(session_id) {
+            reconnect.params.max_frames_in_flight = max;
+        }
+        Ok(())
+    }
+
+    pub fn frame_stats(&self, session_id: &str) -> Result<FrameFlowStats, String> {
+        Ok(self.frame_flow(session_id)?.stats())
+    }
+
+    pub fn codec_stats(&self, session_id: &str) -> Result<RdpCodecStats, String> {
+        Ok(self.client(session_id)?.codec_stats())
+    }
+
+    /// Saved profile a session was opened from, if any
+    pub fn connection_id(&self, session_id: &str) -> Option<String> {
+        self.reconnects
+            .lock()
+            .get(session_id)
+            .and_then(|reconnect| reconnect.params.connection_id.clone())
+    }
+
+    /// Connect a dropped session again under the same id and restart its
+    /// frame reader, after the user confirmed `rdp-connection-lost-{id}`.
+    /// A `password` replaces the one the session logged on with, for this
+    /// and later reconnects.
+    pub fn reconnect(&self, session_id: &str, password: Option<String>, app_handle: AppHandle) -> Result<(), String> {
+        let client = self
+            .sessions
+            .lock()
+            .get(session_id)
+            .ok_or_else(|| "RDP session not found".to_string())?
+            .clone();
+        if client.is_connected() {
+            return Err("RDP session is still connected".to_string());
+        }
+        if let Some(password) = password {
+            if let Some(reconnect) = self.reconnects.lock().get_mut(session_id) {
+                reconnect.params.password = password;
+            }
+        }
+
+        let frames = self
+            .frame_channels
+            .lock()
+            .get(session_id)
+            .cloned()
+            .unwrap_or_default();
+        reconnect(
+            &self.sessions,
+            &self.reconnects,
+            session_id,
+            &client,
+            "Reconnect requested".to_string(),
+            &frames,
+            &app_handle,
+        )?;
+        self.start_frame_reader(session_id, app_handle)
+    }
+
+    /// Switch a live session to `quality`. Frame pacing and output
+    /// suppression change at once; color depth and codecs are negotiated at
+    /// connect, so they follow on the next reconnect. The result is also sent
+    /// as `rdp-quality-{session_id}`.
+    pub fn set_quality(
+        &self,
+        session_id: &str,
+        quality: RdpQuality,
+        app_handle: &AppHandle,
+    ) -> Result<RdpQualityInfo, String> {
+        let client = self
+            .sessions
+            .lock()
+            .get(session_id)
+            .ok_or_else(|| "RDP session not found".to_string())?
+            .clone();
+
+        // Recorded first, so an automatic reconnect uses it
+        let params = {
+            let mut reconnects = self.reconnects.lock();
+            let reconnect = reconnects
+                .get_mut(session_id)
+                .ok_or_else(|| "RDP session not found".to_string())?;
+            reconnect.params.quality = quality;
+            reconnect.params.clone()
+        };
+        client.set_quality(quality)?;
+
+        let info = RdpQualityInfo::new(&client, &params);
+
+        let _ = app_handle.emit(&format!("rdp-quality-{}", session_id), &info);
+        Ok(info)
+    }
+
+    /// Tell the session whether its viewer can be seen, so display updates
+    /// can be suppressed while it's minimized or in a background tab
+    pub fn set_visible(&self, session_id: &str, visible: bool) -> Result<(), String> {
+        let client = self
+            .sessions
+            .lock()
+            .get(session_id)
+            .ok_or_else(|| "RDP session not found".to_string())?
+            .clone();
+        client.set_visible(visible)
+    }
+
+    /// The session's client, or why there is none
+    fn client(&self, session_id: &str) -> Result<Arc<RdpClient>, String> {
+        if let Some(client) = self.sessions.lock().get(session_id) {
+            return Ok(client.clone());
+        }
+        Err(match self.ended.lock().get(session_id) {
+            Some(disconnect) => format!("Session ended: {}", disconnect),
+            None => "RDP session not found".to_string(),
+        })
+    }
+
+    /// Count the session's time toward its profile's usage once it closes
+    pub fn track_usage(&self, session_id: &str, tracker: UsageTracker) {
+        self.usage.lock().insert(session_id.to_string(), tracker);
+    }
+
+    /// Close the session once no input has been sent for `timeout`
+    pub fn set_idle_timeout(
+        self: &Arc<Self>,
+        session_id: &str,
+        timeout: Duration,
+        app_handle: AppHandle,
+    ) -> Result<(), String> {
+        if !self.sessions.lock().contains_key(session_id) {
+            return Err("RDP session not found".to_string());
+        }
+
+        let activity = Activity::new();
+        let manager = Arc::downgrade(self);
+        let id = session_id.to_string();
+        let watch = IdleWatch::start(session_id, activity.clone(), timeout, app_handle, move || {
+            let Some(manager) = manager.upgrade() else {
+                return false;
+            };
+            let open = manager.sessions.lock().contains_key(&id);
+            let _ = manager.close_session(&id);
+            open
+        });
+        self.idle.lock().insert(session_id.to_string(), (activity, watch));
+        Ok(())
+    }
+
+    fn touch(&self, session_id: &str) {
+        if let Some((activity, _)) = self.idle.lock().get(session_id) {
+            activity.touch();
+        }
+    }
+
+    /// Queue an input event; mouse moves are batched, everything else is
+    /// sent right away
     pub fn send_input(&self, session_id: &str, event: InputEvent) -> Result<(), String> {
+        self.touch(session_id);
+        self.client(session_id)?.queue_input(event)
+    }
+
+    /// Type a key combination the local OS would intercept, such as
+    /// Ctrl+Alt+Del. The server honours the scancodes from an RDP client.
+    pub fn send_special_key(&self, session_id: &str, key: RdpSpecialKey) -> Result<(), String> {
+        self.touch(session_id);
+        let client = self.client(session_id)?;
+        for event in key.key_events() {
+            client.queue_input(event)?;
+        }
+        Ok(())
+    }
+
+    /// Send `text` as Unicode keyboard events at once
+    pub fn send_unicode(&self, session_id: &str, text: &str) -> Result<(), String> {
+        self.touch(session_id);
+        self.client(session_id)?.send_unicode(text)
+    }
+
+    /// Type `text` like `send_unicode`, pausing between characters for
+    /// applications that drop keys arriving too fast
+    pub fn send_text(&self, session_id: &str, text: &str) -> Result<(), String> {
+        self.touch(session_id);
+        let client = self.client(session_id)?;
+
+        let mut buf = [0u8; 4];
+        for c in text.chars() {
+            client.send_unicode(c.encode_utf8(&mut buf))?;
+            thread::sleep(TEXT_KEY_DELAY);
+        }
+        Ok(())
+    }
+
+    /// Ask the server to resize the desktop; the new size arrives as
+    /// `rdp-resized-{session_id}` followed by a full frame
+    pub fn resize(&self, session_id: &str, width: u16, height: u16) -> Result<(), String> {
+        let client = self
+            .sessions
+            .lock()
+            .get(session_id)
+            .ok_or_else(|| "RDP session not found".to_string())?
+            .clone();
+        client.request_resize(width, height)
+    }
+
+    /// Current cursor shape, for a viewer that missed the last `rdp-pointer` event
+    pub fn pointer_shape(&self, session_id: &str) -> Result<Option<super::RdpPointer>, String> {
         let sessions = self.sessions.lock();
         let client = sessions
             .get(session_id)
             .ok_or_else(|| "RDP session not found".to_string())?;
+        Ok(client.pointer_shape())
+    }
 
-        match event {
-            InputEvent::MouseMove { x, y } => {
-                client.send_mouse_move(x, y)?;
-            }
-            InputEvent::MouseButton { button, down, x, y } => {
-                client.send_mouse_button(button, down, x, y)?;
-            }
-            InputEvent::MouseWheel { delta, x, y } => {
-                client.send_mouse_wheel(delta, x, y)?;
-            }
-            InputEvent::Keyboard { scancode, down } => {
-                client.send_keyboard(scancode, down)?;
-            }
-        }
+    /// Put `text` on the session's clipboard
+    pub fn send_clipboard(&self, session_id: &str, text: String) -> Result<(), String> {
+        let client = self
+            .sessions
+            .lock()
+            .get(session_id)
+            .ok_or_else(|| "RDP session not found".to_string())?
+            .clone();
+        client.set_clipboard(text)
+    }
 
-        Ok(())
+    /// Save the current screen to `path`
+    pub fn capture_screenshot(
+        &self,
+        session_id: &str,
+        path: &str,
+        format: ScreenshotFormat,
+    ) -> Result<ScreenshotInfo, String> {
+        let sessions = self.sessions.lock();
+        let client = sessions
+            .get(session_id)
+            .ok_or_else(|| "RDP session not found".to_string())?
+            .clone();
+        // get_frame copies under a short lock; encoding happens after
+        let (width, height, rgba) = (client.width(), client.height(), client.get_frame());
+        drop(sessions);
+
+        save_screenshot(rgba, width as u32, height as u32, path, format)
     }
 
     pub fn close_session(&self, session_id: &str) -> Result<(), String> {
+        self.ended.lock().remove(session_id);
+        self.idle.lock().remove(session_id);
+        if let Some(usage) = self.usage.lock().remove(session_id) {
+            usage.finish();
+        }
+        self.cancel_connect(session_id);
+        self.reconnects.lock().remove(session_id);
+        self.frame_channels.lock().remove(session_id);
+        self.frame_flows.lock().remove(session_id);
         let mut sessions = self.sessions.lock();
         if let Some(client) = sessions.remove(session_id) {
             client.disconnect();
@@ -165,71 +589,228 @@ impl RdpManager {
         Ok(())
     }
 
+    /// Disconnect every session, e.g. on application exit
+    pub fn close_all(&self) {
+        self.ended.lock().clear();
+        self.idle.lock().clear();
+        for (_, usage) in self.usage.lock().drain() {
+            usage.finish();
+        }
+        for cancel in self.connecting.lock().values() {
+            cancel.store(true, Ordering::SeqCst);
+        }
+        self.reconnects.lock().clear();
+        self.frame_channels.lock().clear();
+        self.frame_flows.lock().clear();
+        let sessions: Vec<_> = self.sessions.lock().drain().collect();
+        for (_, client) in sessions {
+            client.disconnect();
+        }
+    }
+
+    /// `None` if the session doesn't exist
+    pub fn is_connected(&self, session_id: &str) -> Option<bool> {
+        self.sessions.lock().get(session_id).map(|c| c.is_connected())
+    }
+
     pub fn get_dimensions(&self, session_id: &str) -> Result<(u16, u16), String> {
-        let sessions = self.sessions.lock();
-        let client = sessions
-            .get(session_id)
-            .ok_or_else(|| "RDP session not found".to_string())?;
+        let client = self.client(session_id)?;
         Ok((client.width(), client.height()))
     }
+}
 
-    /// Coalesce overlapping dirty rectangles to reduce IPC overhead
-    fn coalesce_dirty_rects(mut rects: Vec<super::DirtyRect>) -> Vec<super::DirtyRect> {
-        if rects.len() <= 1 {
-            return rects;
+/// Process events and send frames to the frontend until the session ends.
+/// While the viewer is behind on acks, changes keep merging into one
+/// update that goes out once it catches up. An error means the connection
+/// was lost rather than closed.
+fn read_frames(
+    client: &RdpClient,
+    session_id: &str,
+    frames: &FrameChannel,
+    flow: &FrameFlow,
+    app_handle: &AppHandle,
+) -> Result<(), String> {
+    let event_name = format!("rdp-frame-{}", session_id);
+    let mut frame_count = 0;
+    let mut pending_regions: Vec<Region> = Vec::new();
+    let mut last_frame_time = std::time::Instant::now();
+    let mut last_input_time = std::time::Instant::now();
+    let mut activity_detected = false;
+    
+    eprintln!("RDP: Starting frame reader for session {} with adaptive frame rate", session_id);
+    
+    while client.is_connected() {
+        // Check for recent input activity (within last 2 seconds)
+        activity_detected = last_input_time.elapsed() < Duration::from_secs(2);
+        
+        // Process RDP events - collect dirty regions
+        match client.process_events() {
+            Ok(Some(mut regions)) => {
+                if !pending_regions.is_empty() && !flow.ready() {
+                    flow.skipped();
+                }
+                // Accumulate dirty regions and coalesce overlapping ones
+                pending_regions.append(&mut regions);
+                pending_regions = coalesce_regions(pending_regions);
+                activity_detected = true; // Visual changes indicate activity
+            }
+            Ok(None) => {
+                // No update from server - send initial full frame if needed
+                if frame_count == 0 {
+                    let update =
+                        FrameUpdate::full(client.width(), client.height(), client.get_frame());
+                    match frames.send(app_handle, &event_name, &update) {
+                        Ok(()) => flow.sent(),
+                        Err(e) => eprintln!("RDP: Failed to emit initial frame: {}", e),
+                    }
+                    frame_count = 1;
+                    last_frame_time = std::time::Instant::now();
+                }
+            }
+            Err(e) => {
+                eprintln!("RDP: Read error: {}", e);
+                return Err(e);
+            }
         }
 
-        // Sort by position for better coalescing
-        rects.sort_by(|a, b| a.y.cmp(&b.y).then(a.x.cmp(&b.x)));
+        // The desktop was resized: pending regions refer to the old image
+        if let Some((width, height)) = client.take_resize() {
+            pending_regions.clear();
+            let _ = app_handle.emit(
+                &format!("rdp-resized-{}", session_id),
+                RdpResize::from((width, height)),
+            );
+            let update = FrameUpdate::full(width, height, client.get_frame());
+            match frames.send(app_handle, &event_name, &update) {
+                Ok(()) => flow.sent(),
+                Err(e) => eprintln!("RDP: Failed to emit resized frame: {}", e),
+            }
+            last_frame_time = std::time::Instant::now();
+        }
 
-        let mut result = Vec::new();
-        let mut iter = rects.into_iter();
-        let mut current = iter.next().unwrap();
+        for pointer in client.take_pointer_events() {
+            let _ = app_handle.emit(&format!("rdp-pointer-{}", session_id), pointer);
+        }
 
-        for rect in iter {
-            // Check if rectangles are adjacent or overlapping
-            if Self::can_merge_rects(&current, &rect) {
-                current = Self::merge_rects(current, rect);
-            } else {
-                result.push(current);
-                current = rect;
+        if let Some(text) = client.take_clipboard() {
+            let _ = app_handle.emit(&format!("rdp-clipboard-{}", session_id), text);
+        }
+        
+        // Send accumulated dirty regions based on adaptive timing
+        let has_changes = !pending_regions.is_empty();
+        // Read each time, so a quality change applies without reconnecting
+        let frame_interval = client.quality().frame_interval(activity_detected || has_changes);
+        
+        if has_changes && last_frame_time.elapsed() >= frame_interval && flow.ready() {
+            // Pixels are read now, so merged regions carry current data
+            let update =
+                FrameUpdate::partial(client.dirty_rects(&std::mem::take(&mut pending_regions)));
+            if let Err(e) = frames.send(app_handle, &event_name, &update) {
+                eprintln!("RDP: Failed to emit frame update: {}", e);
+                return Ok(());
+            }
+            flow.sent();
+            frame_count += 1;
+            last_frame_time = std::time::Instant::now();
+            
+            if frame_count % 100 == 0 {
+                eprintln!("RDP: Sent {} frame updates for session {}", frame_count, session_id);
             }
         }
-        result.push(current);
 
-        result
+        // Minimal sleep - read timeout handles pacing
+        thread::sleep(Duration::from_millis(1));
     }
 
-    /// Check if two dirty rectangles can be merged (adjacent or overlapping)
-    fn can_merge_rects(a: &super::DirtyRect, b: &super::DirtyRect) -> bool {
-        let a_right = a.x + a.width;
-        let a_bottom = a.y + a.height;
-        let b_right = b.x + b.width;
-        let b_bottom = b.y + b.height;
+    Ok(())
+}
 
-        // Check for overlap or adjacency
-        !(a_right < b.x || b_right < a.x || a_bottom < b.y || b_bottom < a.y)
+/// Connect `session_id` again with its original parameters, retrying with
+/// backoff, and swap the new client in for `old`. IronRDP doesn't support
+/// auto-reconnect cookies, so each attempt is a full logon; the server
+/// reattaches the user's disconnected session as usual.
+fn reconnect(
+    sessions: &Mutex<HashMap<String, Arc<RdpClient>>>,
+    reconnects: &Mutex<HashMap<String, Reconnect>>,
+    session_id: &str,
+    old: &Arc<RdpClient>,
+    mut reason: String,
+    frames: &FrameChannel,
+    app_handle: &AppHandle,
+) -> Result<Arc<RdpClient>, String> {
+    old.disconnect();
+    let mut params = reconnects
+        .lock()
+        .get(session_id)
+        .map(|reconnect| reconnect.params.clone())
+        .ok_or_else(|| "RDP session not found".to_string())?;
+    // Keep the size the session was resized to
+    params.width = old.width();
+    params.height = old.height();
+    if old.codec_failed() {
+        eprintln!("RDP: RemoteFX failed for session {}, falling back to bitmaps", session_id);
+        params.codec = RdpCodec::Bitmap;
     }
 
-    /// Merge two dirty rectangles into a single rectangle
-    fn merge_rects(a: super::DirtyRect, b: super::DirtyRect) -> super::DirtyRect {
-        let min_x = a.x.min(b.x);
-        let min_y = a.y.min(b.y);
-        let max_x = (a.x + a.width).max(b.x + b.width);
-        let max_y = (a.y + a.height).max(b.y + b.height);
+    for attempt in 0..RECONNECT_ATTEMPTS {
+        let _ = app_handle.emit(
+            &format!("rdp-reconnecting-{}", session_id),
+            RdpReconnecting {
+                attempt: attempt + 1,
+                max_attempts: RECONNECT_ATTEMPTS,
+                reason: reason.clone(),
+            },
+        );
+        thread::sleep(reconnect_delay(attempt));
 
-        // For merged rectangles, we need to reconstruct the pixel data
-        // For simplicity, we'll use the data from the larger rectangle
-        let data = if a.data.len() >= b.data.len() { a.data } else { b.data };
+        let client = match params.connect(None, None) {
+            Ok(client) => Arc::new(client),
+            Err(e) => {
+                eprintln!("RDP: Reconnect attempt {} failed: {}", attempt + 1, e);
+                reason = e.to_string();
+                continue;
+            }
+        };
 
-        super::DirtyRect {
-            x: min_x,
-            y: min_y,
-            width: max_x - min_x,
-            height: max_y - min_y,
-            data,
+        // The tab may have been closed while connecting
+        {
+            let mut sessions = sessions.lock();
+            match sessions.get(session_id) {
+                Some(current) if Arc::ptr_eq(current, old) => {
+                    sessions.insert(session_id.to_string(), client.clone());
+                    // Later reconnects keep any codec fallback
+                    if let Some(reconnect) = reconnects.lock().get_mut(session_id) {
+                        reconnect.params.codec = params.codec;
+                        // The quality may have changed while connecting
+                        if reconnect.params.quality != params.quality {
+                            let _ = client.set_quality(reconnect.params.quality);
+                        }
+                        params = reconnect.params.clone();
+                    }
+                }
+                _ => {
+                    client.disconnect();
+                    return Err("RDP session was closed".to_string());
+                }
+            }
         }
+
+        eprintln!("RDP: Session {} reconnected", session_id);
+        let (width, height) = (client.width(), client.height());
+        let _ = app_handle.emit(
+            &format!("rdp-reconnected-{}", session_id),
+            RdpResize::from((width, height)),
+        );
+        let _ = app_handle.emit(
+            &format!("rdp-quality-{}", session_id),
+            RdpQualityInfo::new(&client, &params),
+        );
+        let update = FrameUpdate::full(width, height, client.get_frame());
+        let _ = frames.send(app_handle, &format!("rdp-frame-{}", session_id), &update);
+        return Ok(client);
     }
+
+    Err(format!("Reconnect failed after {} attempts: {}", RECONNECT_ATTEMPTS, reason))
 }
 
 impl Default for RdpManager {
@@ -1,15 +1,33 @@
 mod client;
+mod clipboard;
+mod disconnect;
+mod drive;
 mod framebuffer;
+mod gateway;
 mod input;
+mod keyboard;
+mod license;
 mod manager;
+mod pointer;
+mod reconnect;
+mod smartcard;
 
-pub use client::RdpClient;
+pub use client::{RdpClient, RdpCodecStats, RdpConnectStage};
+pub use disconnect::RdpDisconnect;
+pub use drive::RdpSharedFolder;
 pub use framebuffer::FrameBuffer;
-pub use input::InputEvent;
-pub use manager::RdpManager;
+pub use gateway::RdpGateway;
+pub use input::{InputBatcher, InputEvent, RdpSpecialKey};
+pub use keyboard::{keyboard_layouts, KeyboardLayouts, RdpKeyboard};
+pub use manager::{RdpManager, RdpQualityInfo};
+pub use pointer::RdpPointer;
+pub use reconnect::{RdpConnectParams, DEFAULT_CONNECT_TIMEOUT};
+pub use smartcard::RdpSmartCard;
+pub use crate::frame::{DirtyRect, FrameUpdate};
 
-use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use ironrdp_pdu::rdp::client_info::PerformanceFlags;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RdpConnectionInfo {
@@ -19,13 +37,14 @@ pub struct RdpConnectionInfo {
     pub domain: Option<String>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RdpQuality {
     /// Ultra quality - 32-bit, lossless, RemoteFX + NSCodec
     Ultra,
     /// High quality - 32-bit, minimal loss, RemoteFX
     High,
     /// Balanced quality - 24-bit, NSCodec + RFX
+    #[default]
     Balanced,
     /// Performance focused - 16-bit, aggressive compression
     Performance,
@@ -33,55 +52,190 @@ pub enum RdpQuality {
     LowBandwidth,
 }
 
-impl Default for RdpQuality {
-    fn default() -> Self {
-        RdpQuality::High  // Default to high quality
+impl RdpQuality {
+    /// Visual features to turn off (or on) for this preset
+    pub fn performance_flags(&self) -> PerformanceFlags {
+        match self {
+            // Ultra/High - all visual features enabled
+            RdpQuality::Ultra | RdpQuality::High => {
+                PerformanceFlags::ENABLE_FONT_SMOOTHING
+                    | PerformanceFlags::ENABLE_DESKTOP_COMPOSITION
+            }
+            // Balanced - some optimizations but keep visual quality
+            RdpQuality::Balanced => {
+                PerformanceFlags::DISABLE_WALLPAPER
+                    | PerformanceFlags::DISABLE_FULLWINDOWDRAG
+                    | PerformanceFlags::ENABLE_FONT_SMOOTHING
+                    | PerformanceFlags::ENABLE_DESKTOP_COMPOSITION
+            }
+            // Performance focused - aggressive optimizations
+            RdpQuality::Performance => {
+                PerformanceFlags::DISABLE_WALLPAPER
+                    | PerformanceFlags::DISABLE_FULLWINDOWDRAG
+                    | PerformanceFlags::DISABLE_MENUANIMATIONS
+                    | PerformanceFlags::DISABLE_THEMING
+                    | PerformanceFlags::ENABLE_FONT_SMOOTHING
+            }
+            // Maximum compression for low bandwidth
+            RdpQuality::LowBandwidth => {
+                PerformanceFlags::DISABLE_WALLPAPER
+                    | PerformanceFlags::DISABLE_FULLWINDOWDRAG
+                    | PerformanceFlags::DISABLE_MENUANIMATIONS
+                    | PerformanceFlags::DISABLE_THEMING
+                    | PerformanceFlags::DISABLE_CURSORSETTINGS
+            }
+        }
+    }
+
+    pub fn color_depth(&self) -> u32 {
+        match self {
+            RdpQuality::Ultra | RdpQuality::High => 32,
+            RdpQuality::Balanced => 24,
+            RdpQuality::Performance => 16,
+            RdpQuality::LowBandwidth => 8,
+        }
+    }
+
+    /// Color depth requested with `codec`; RemoteFX only runs at 32-bit color
+    pub fn color_depth_with(&self, codec: RdpCodec) -> u32 {
+        match codec {
+            RdpCodec::RemoteFx => 32,
+            _ => self.color_depth(),
+        }
+    }
+
+    /// Whether the server may use lossy bitmap compression
+    pub fn lossy_compression(&self) -> bool {
+        matches!(self, RdpQuality::Performance | RdpQuality::LowBandwidth)
+    }
+
+    /// Minimum time between frame updates sent to the viewer, while the
+    /// screen is changing or the user is typing (`active`) and while idle.
+    /// Applies live, unlike the settings negotiated at connect.
+    pub fn frame_interval(&self, active: bool) -> Duration {
+        let ms = match (self, active) {
+            (RdpQuality::Ultra | RdpQuality::High, true) => 33,
+            (RdpQuality::Balanced, true) => 50,
+            (RdpQuality::Performance, true) => 66,
+            (RdpQuality::LowBandwidth, true) => 100,
+            (RdpQuality::LowBandwidth, false) => 500,
+            (_, false) => 200,
+        };
+        Duration::from_millis(ms)
+    }
+
+    /// Whether to ask the server to stop sending display updates while the
+    /// viewer is hidden. Higher presets keep updates flowing so the desktop
+    /// is current the moment it's shown again.
+    pub fn suppress_when_hidden(&self) -> bool {
+        matches!(self, RdpQuality::Balanced | RdpQuality::Performance | RdpQuality::LowBandwidth)
+    }
+
+    /// Codec the preset asks for when the profile doesn't override it
+    pub fn codec(&self) -> RdpCodec {
+        match self {
+            RdpQuality::High | RdpQuality::Balanced => RdpCodec::RemoteFx,
+            // NSCodec would suit Ultra, but IronRDP can't decode it; plain
+            // bitmaps at 32-bit are lossless too
+            RdpQuality::Ultra | RdpQuality::Performance | RdpQuality::LowBandwidth => RdpCodec::Bitmap,
+        }
     }
 }
 
-/// A dirty rectangle update - only the changed region
-/// Uses Base64 encoding for reliable binary transfer
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DirtyRect {
-    pub x: u16,
-    pub y: u16,
-    pub width: u16,
-    pub height: u16,
-    pub data: String, // Base64-encoded RGBA pixels
+/// Visual features to keep, overriding the quality preset's choice
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RdpPerformanceFlags {
+    pub wallpaper: bool,
+    pub themes: bool,
+    /// Menu and window animations
+    pub animations: bool,
+    pub font_smoothing: bool,
+    /// Transparency and other composited effects
+    pub desktop_composition: bool,
+    /// Window contents shown while dragging
+    pub full_window_drag: bool,
 }
 
-impl DirtyRect {
-    pub fn new(x: u16, y: u16, width: u16, height: u16, rgba_data: &[u8]) -> Self {
-        Self {
-            x,
-            y,
-            width,
-            height,
-            data: BASE64.encode(rgba_data),
-        }
+impl RdpPerformanceFlags {
+    pub fn performance_flags(&self) -> PerformanceFlags {
+        let mut flags = PerformanceFlags::empty();
+        flags.set(PerformanceFlags::DISABLE_WALLPAPER, !self.wallpaper);
+        flags.set(PerformanceFlags::DISABLE_THEMING, !self.themes);
+        flags.set(PerformanceFlags::DISABLE_MENUANIMATIONS, !self.animations);
+        flags.set(PerformanceFlags::ENABLE_FONT_SMOOTHING, self.font_smoothing);
+        flags.set(PerformanceFlags::ENABLE_DESKTOP_COMPOSITION, self.desktop_composition);
+        flags.set(PerformanceFlags::DISABLE_FULLWINDOWDRAG, !self.full_window_drag);
+        flags
     }
 }
 
-/// Frame update type - either full frame or dirty rectangles
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-pub enum FrameUpdate {
-    /// Full frame update (used for initial frame)
-    Full { 
-        width: u16, 
-        height: u16, 
-        data: String 
-    }, // Base64-encoded
-    /// Partial update with dirty rectangles
-    Partial { rects: Vec<DirtyRect> },
+/// Bitmap codec advertised to the server.
+///
+/// RemoteFX sends wavelet-compressed 64x64 tiles: much less bandwidth than
+/// bitmaps for photos, video and gradients, at the cost of more CPU to
+/// decode and slight blurring of fine detail. It always runs at 32-bit
+/// color. Plain bitmaps (interleaved RLE/planar) are cheap to decode and
+/// exact, and compress well for flat UI and text but poorly for anything
+/// photographic. `rdp_codec_stats` reports the bandwidth and decode CPU a
+/// session actually uses, so the two can be compared on a given server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RdpCodec {
+    /// Whatever the quality preset uses
+    #[default]
+    Auto,
+    RemoteFx,
+    /// For servers that misbehave with RemoteFX
+    Bitmap,
+}
+
+impl RdpCodec {
+    /// The codec actually used with `quality`
+    pub fn resolve(self, quality: RdpQuality) -> RdpCodec {
+        match self {
+            RdpCodec::Auto => quality.codec(),
+            codec => codec,
+        }
+    }
 }
 
-impl FrameUpdate {
-    pub fn full(width: u16, height: u16, rgba_data: &[u8]) -> Self {
-        Self::Full {
-            width,
-            height,
-            data: BASE64.encode(rgba_data),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_presets_map_to_flags_depth_and_codec() {
+        let visuals = PerformanceFlags::ENABLE_FONT_SMOOTHING | PerformanceFlags::ENABLE_DESKTOP_COMPOSITION;
+        let no_drag = PerformanceFlags::DISABLE_WALLPAPER | PerformanceFlags::DISABLE_FULLWINDOWDRAG;
+        let no_chrome = no_drag | PerformanceFlags::DISABLE_MENUANIMATIONS | PerformanceFlags::DISABLE_THEMING;
+        let cases = [
+            (RdpQuality::Ultra, visuals, 32, RdpCodec::Bitmap, false),
+            (RdpQuality::High, visuals, 32, RdpCodec::RemoteFx, false),
+            (RdpQuality::Balanced, no_drag | visuals, 24, RdpCodec::RemoteFx, false),
+            (
+                RdpQuality::Performance,
+                no_chrome | PerformanceFlags::ENABLE_FONT_SMOOTHING,
+                16,
+                RdpCodec::Bitmap,
+                true,
+            ),
+            (
+                RdpQuality::LowBandwidth,
+                no_chrome | PerformanceFlags::DISABLE_CURSORSETTINGS,
+                8,
+                RdpCodec::Bitmap,
+                true,
+            ),
+        ];
+        for (quality, flags, depth, codec, lossy) in cases {
+            assert_eq!(quality.performance_flags(), flags, "{:?}", quality);
+            assert_eq!(quality.codec(), codec, "{:?}", quality);
+            assert_eq!(RdpCodec::Auto.resolve(quality), codec, "{:?}", quality);
+            assert_eq!(quality.color_depth(), depth, "{:?}", quality);
+            assert_eq!(quality.color_depth_with(codec), if codec == RdpCodec::RemoteFx { 32 } else { depth });
+            assert_eq!(quality.lossy_compression(), lossy, "{:?}", quality);
+            // A profile's codec override wins over the preset
+            assert_eq!(RdpCodec::Bitmap.resolve(quality), RdpCodec::Bitmap);
+            assert_eq!(RdpCodec::RemoteFx.resolve(quality), RdpCodec::RemoteFx);
         }
     }
 }
@@ -0,0 +1,36 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use ironrdp_graphics::pointer::DecodedPointer;
+use serde::Serialize;
+
+/// Remote cursor change, emitted as `rdp-pointer-{session_id}` so the
+/// frontend can draw the cursor as an overlay. Not sent when the cursor is
+/// software-rendered into the frame.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RdpPointer {
+    /// New cursor shape
+    Bitmap {
+        width: u16,
+        height: u16,
+        hotspot_x: u16,
+        hotspot_y: u16,
+        data: String, // Base64-encoded RGBA pixels
+    },
+    /// Server moved the cursor
+    Position { x: u16, y: u16 },
+    Hidden,
+    /// Use the local system cursor
+    Default,
+}
+
+impl From<&DecodedPointer> for RdpPointer {
+    fn from(pointer: &DecodedPointer) -> Self {
+        RdpPointer::Bitmap {
+            width: pointer.width,
+            height: pointer.height,
+            hotspot_x: pointer.hotspot_x,
+            hotspot_y: pointer.hotspot_y,
+            data: BASE64.encode(&pointer.bitmap_data),
+        }
+    }
+}
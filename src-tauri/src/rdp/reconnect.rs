@@ -0,0 +1,148 @@
+use crate::certificate::{CertificatePolicy, ConnectError, UntrustedCertificate};
+use super::client::{ConnectControl, ConnectionFinalizeError, FinalizeFailure, RdpConnectStage};
+use super::license::RdpLicenseCache;
+use super::{
+    RdpClient, RdpCodec, RdpGateway, RdpKeyboard, RdpPerformanceFlags, RdpQuality, RdpSharedFolder, RdpSmartCard,
+};
+use crate::proxy::ProxyConfig;
+use serde::Serialize;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a connection attempt may take when the caller doesn't say
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Connection attempts after a session drops before giving up
+pub const RECONNECT_ATTEMPTS: u32 = 6;
+
+/// Longest wait between reconnect attempts
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Everything needed to connect a session again after it drops
+#[derive(Debug, Clone)]
+pub struct RdpConnectParams {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub domain: Option<String>,
+    pub width: u16,
+    pub height: u16,
+    pub quality: RdpQuality,
+    /// Visual features to keep instead of the preset's
+    pub custom_performance_flags: Option<RdpPerformanceFlags>,
+    pub codec: RdpCodec,
+    pub keyboard: RdpKeyboard,
+    pub proxy: Option<ProxyConfig>,
+    pub pointer_software_rendering: bool,
+    pub certificates: CertificatePolicy,
+    pub gateway: Option<RdpGateway>,
+    /// Saved profile the session was opened from, to look its password up
+    /// again on a manual reconnect
+    pub connection_id: Option<String>,
+    /// Log on with a smart card instead of `password`
+    pub smart_card: Option<RdpSmartCard>,
+    /// Local folder to redirect as a drive
+    pub shared_folder: Option<RdpSharedFolder>,
+    /// Limit for each connection attempt, RemoteFX fallback included
+    pub timeout: Duration,
+    /// Unacknowledged frames the viewer may have before updates are held
+    /// back and merged; 0 sends every update
+    pub max_frames_in_flight: usize,
+}
+
+impl RdpConnectParams {
+    /// Connect, falling back to plain bitmaps if the server breaks off the
+    /// capability exchange when offered RemoteFX; authentication and
+    /// licensing failures are never retried that way. Setting `cancel` aborts
+    /// the attempt at the next phase; `progress` hears of each phase.
+    pub fn connect(
+        &mut self,
+        cancel: Option<&AtomicBool>,
+        progress: Option<&dyn Fn(RdpConnectStage)>,
+    ) -> Result<RdpClient, ConnectError> {
+        let control = ConnectControl::new(self.timeout, cancel, progress);
+        match self.try_connect(&control) {
+            Err(e)
+                if self.codec.resolve(self.quality) == RdpCodec::RemoteFx
+                    && e.downcast_ref::<ConnectionFinalizeError>()
+                        .is_some_and(|e| e.failure() == FinalizeFailure::Capabilities) =>
+            {
+                eprintln!("RDP: {}; retrying without RemoteFX", e);
+                self.codec = RdpCodec::Bitmap;
+                self.try_connect(&control)
+            }
+            result => result,
+        }
+        .map_err(|e| match e.downcast::<UntrustedCertificate>() {
+            Ok(untrusted) => ConnectError::UntrustedCertificate(untrusted),
+            Err(e) => ConnectError::Other(format!("RDP connection failed: {}", e)),
+        })
+    }
+
+    /// Connect, negotiating a new license if the server turns down the one
+    /// cached for it, e.g. because it expired
+    fn try_connect(&self, control: &ConnectControl) -> Result<RdpClient, Box<dyn std::error::Error + Send + Sync>> {
+        let licenses = Arc::new(RdpLicenseCache::new(&self.host));
+        match self.connect_with(control, &licenses) {
+            Err(e)
+                if licenses.offered()
+                    && e.downcast_ref::<ConnectionFinalizeError>()
+                        .is_some_and(|e| e.failure() == FinalizeFailure::License) =>
+            {
+                eprintln!("RDP: {}; retrying without the cached license", e);
+                licenses.forget();
+                self.connect_with(control, &licenses)
+            }
+            result => result,
+        }
+    }
+
+    fn connect_with(
+        &self,
+        control: &ConnectControl,
+        licenses: &Arc<RdpLicenseCache>,
+    ) -> Result<RdpClient, Box<dyn std::error::Error + Send + Sync>> {
+        RdpClient::connect(
+            &self.host,
+            self.port,
+            &self.username,
+            &self.password,
+            self.domain.as_deref(),
+            self.width,
+            self.height,
+            self.quality,
+            self.custom_performance_flags,
+            self.codec,
+            self.keyboard,
+            self.proxy.as_ref(),
+            self.pointer_software_rendering,
+            &self.certificates,
+            self.gateway.as_ref(),
+            self.smart_card.as_ref(),
+            self.shared_folder.as_ref(),
+            licenses.clone(),
+            control,
+        )
+    }
+}
+
+/// Payload of `rdp-reconnecting-{session_id}`, sent before each attempt
+#[derive(Debug, Clone, Serialize)]
+pub struct RdpReconnecting {
+    /// 1-based
+    pub attempt: u32,
+    pub max_attempts: u32,
+    /// Why the connection was lost, or why the last attempt failed
+    pub reason: String,
+}
+
+/// Wait before reconnect attempt `attempt` (0-based): none for the first,
+/// then doubling from a second up to `MAX_RECONNECT_DELAY`
+pub fn reconnect_delay(attempt: u32) -> Duration {
+    match attempt {
+        0 => Duration::ZERO,
+        n => Duration::from_secs(1 << (n - 1).min(5)).min(MAX_RECONNECT_DELAY),
+    }
+}
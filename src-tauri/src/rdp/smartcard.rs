@@ -0,0 +1,74 @@
+//! Smart card logon for NLA. IronRDP's CredSSP emulates the card: it signs
+//! with the certificate and private key exported from it, and sends the PIN
+//! and reader details the server expects from a real card.
+//!
+//! Only `rdp_connect` takes a card; saved profiles don't store one, so
+//! `connect_saved_rdp` always logs on with a password.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use ironrdp_connector::{Credentials, SmartCardIdentity};
+use serde::Deserialize;
+use std::fs;
+
+/// CSP Windows uses for PIV and most other smart cards
+const DEFAULT_CSP_NAME: &str = "Microsoft Base Smart Card Crypto Provider";
+
+/// Smart card to log on with instead of a password
+#[derive(Debug, Clone, Deserialize)]
+pub struct RdpSmartCard {
+    #[serde(default)]
+    pub pin: String,
+    /// Name of the reader the card is in, as the server should see it
+    pub reader_name: String,
+    /// Key container on the card holding the logon key
+    #[serde(default)]
+    pub container_name: String,
+    #[serde(default = "default_csp_name")]
+    pub csp_name: String,
+    /// Logon certificate, DER or PEM
+    pub certificate_path: String,
+    /// RSA private key for the certificate (PKCS#1), DER or PEM
+    pub private_key_path: String,
+}
+
+fn default_csp_name() -> String {
+    DEFAULT_CSP_NAME.to_string()
+}
+
+impl RdpSmartCard {
+    /// CredSSP credentials for this card. The username comes from the
+    /// certificate, so none is given here.
+    pub fn credentials(&self) -> Result<Credentials, String> {
+        let certificate = read_der(&self.certificate_path, "certificate")?;
+        let private_key = read_der(&self.private_key_path, "private key")?;
+        Ok(Credentials::SmartCard {
+            pin: self.pin.clone(),
+            config: Some(SmartCardIdentity {
+                certificate,
+                reader_name: self.reader_name.clone(),
+                container_name: self.container_name.clone(),
+                csp_name: self.csp_name.clone(),
+                private_key,
+            }),
+        })
+    }
+}
+
+/// Read a DER file, or the first block of a PEM file
+fn read_der(path: &str, what: &str) -> Result<Vec<u8>, String> {
+    let data = fs::read(path).map_err(|e| format!("Failed to read smart card {} {}: {}", what, path, e))?;
+    let Some(pem) = std::str::from_utf8(&data).ok().filter(|s| s.contains("-----BEGIN")) else {
+        return Ok(data);
+    };
+
+    let body: String = pem
+        .lines()
+        .skip_while(|line| !line.starts_with("-----BEGIN"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----END"))
+        .map(str::trim)
+        .collect();
+    BASE64
+        .decode(body)
+        .map_err(|e| format!("Invalid PEM in smart card {} {}: {}", what, path, e))
+}
@@ -1,10 +1,15 @@
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
-use ssh2::{Session, Sftp};
+use ssh2::{ErrorCode, FileStat, OpenFlags, OpenType, RenameFlags, Session, Sftp};
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use thiserror::Error;
 
+use crate::file_info::{human_size, FileCategory};
+use crate::terminal::session::SessionCounters;
+use crate::util::shell_quote;
+
 #[derive(Error, Debug)]
 pub enum SftpError {
     #[error("SFTP error: {0}")]
@@ -37,12 +42,358 @@ pub struct FileEntry {
     pub size: u64,
     pub modified: Option<i64>,
     pub permissions: Option<u32>,
+    /// Size for display; empty for directories
+    #[serde(default)]
+    pub size_human: String,
+    #[serde(default)]
+    pub category: FileCategory,
+    /// What a symlink points to, if it was followed and isn't broken
+    #[serde(default)]
+    pub target_type: Option<FileType>,
+}
+
+/// libssh2's "no more files" result from readdir
+const LIBSSH2_ERROR_FILE: i32 = -16;
+
+/// Number of entries per `sftp-listing-{id}` event
+pub const LISTING_BATCH_SIZE: usize = 500;
+
+/// One batch of a streamed directory listing
+#[derive(Debug, Clone, Serialize)]
+pub struct ListingBatch {
+    pub listing_id: String,
+    pub path: String,
+    pub entries: Vec<FileEntry>,
+}
+
+/// Final event of a streamed directory listing
+#[derive(Debug, Clone, Serialize)]
+pub struct ListingComplete {
+    pub listing_id: String,
+    pub path: String,
+    pub total: usize,
+}
+
+/// An entry a recursive chmod couldn't change
+#[derive(Debug, Clone, Serialize)]
+pub struct ChmodFailure {
+    pub path: String,
+    pub error: String,
+}
+
+/// Outcome of a recursive chmod
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChmodSummary {
+    pub changed: usize,
+    pub failed: Vec<ChmodFailure>,
+}
+
+/// Progress event of a recursive chmod
+#[derive(Debug, Clone, Serialize)]
+pub struct ChmodProgress {
+    pub chmod_id: String,
+    pub changed: usize,
+    pub failed: usize,
+}
+
+/// Final event of a recursive chmod
+#[derive(Debug, Clone, Serialize)]
+pub struct ChmodComplete {
+    pub chmod_id: String,
+    pub path: String,
+    pub changed: usize,
+    pub failed: Vec<ChmodFailure>,
+}
+
+/// One item of a bulk delete, sent as it finishes
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteResult {
+    pub delete_id: String,
+    pub path: String,
+    /// Why it wasn't deleted
+    pub error: Option<String>,
+}
+
+/// An item a bulk delete couldn't remove
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteFailure {
+    pub path: String,
+    pub error: String,
+}
+
+/// Outcome of a bulk delete
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeleteSummary {
+    pub deleted: Vec<String>,
+    pub failed: Vec<DeleteFailure>,
+}
+
+/// Final event of a bulk delete
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteComplete {
+    pub delete_id: String,
+    pub deleted: Vec<String>,
+    pub failed: Vec<DeleteFailure>,
+}
+
+/// Delete a folder and everything in it. Symlinks are removed, not followed.
+fn remove_tree(sftp: &Sftp, dir: &Path) -> Result<(), ssh2::Error> {
+    for (entry, stat) in sftp.readdir(dir)? {
+        match entry.file_name() {
+            Some(name) if name != "." && name != ".." => {}
+            _ => continue,
+        }
+        if stat.is_dir() {
+            remove_tree(sftp, &entry)?;
+        } else {
+            sftp.unlink(&entry)?;
+        }
+    }
+    sftp.rmdir(dir)
+}
+
+/// SFTP status codes a server answers a rename onto an existing file with
+const SSH_FX_FAILURE: i32 = 4;
+const SSH_FX_FILE_ALREADY_EXISTS: i32 = 11;
+
+/// The SFTP calls `rename_replacing` makes
+trait RenameOps {
+    fn move_to(&self, from: &Path, to: &Path) -> Result<(), ssh2::Error>;
+    /// Whether `path` is a directory, without following symlinks; an error
+    /// when there's nothing there
+    fn is_dir(&self, path: &Path) -> Result<bool, ssh2::Error>;
+    fn remove(&self, path: &Path) -> Result<(), ssh2::Error>;
+}
+
+impl RenameOps for Sftp {
+    fn move_to(&self, from: &Path, to: &Path) -> Result<(), ssh2::Error> {
+        let flags = RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE;
+        self.rename(from, to, Some(flags))
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool, ssh2::Error> {
+        self.lstat(path).map(|stat| stat.is_dir())
+    }
+
+    fn remove(&self, path: &Path) -> Result<(), ssh2::Error> {
+        self.unlink(path)
+    }
+}
+
+/// Rename `old` to `new`, replacing a file already at `new`. Only when the
+/// rename failed because `new` exists (the source is there, the destination
+/// is a file or symlink and the server gave a generic failure) is the
+/// destination moved aside and the rename retried; if that fails too the
+/// destination is put back. Any other failure leaves both untouched.
+fn rename_replacing(fs: &impl RenameOps, old: &Path, new: &Path) -> Result<(), ssh2::Error> {
+    let e = match fs.move_to(old, new) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    let destination_in_the_way = matches!(e.code(), ErrorCode::SFTP(SSH_FX_FAILURE | SSH_FX_FILE_ALREADY_EXISTS))
+        && fs.is_dir(old).is_ok()
+        && fs.is_dir(new).is_ok_and(|is_dir| !is_dir);
+    if !destination_in_the_way {
+        return Err(e);
+    }
+
+    let name = new.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let tag = uuid::Uuid::new_v4().simple().to_string();
+    let aside = new.with_file_name(format!(".{}.{}.replaced", name, &tag[..8]));
+    fs.move_to(new, &aside)?;
+    match fs.move_to(old, new) {
+        Ok(()) => {
+            if let Err(e) = fs.remove(&aside) {
+                eprintln!("SFTP: couldn't remove replaced file {}: {}", aside.display(), e);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            if let Err(restore) = fs.move_to(&aside, new) {
+                eprintln!(
+                    "SFTP: couldn't move {} back to {}: {}",
+                    aside.display(),
+                    new.display(),
+                    restore
+                );
+            }
+            Err(e)
+        }
+    }
+}
+
+/// `path` made absolute against `current`
+fn resolve_in(current: &Path, path: &str) -> String {
+    if path.starts_with('/') {
+        return path.to_string();
+    }
+    let current = current.to_string_lossy();
+    match path {
+        "" | "." => current.to_string(),
+        _ => format!("{}/{}", current.trim_end_matches('/'), path),
+    }
+}
+
+/// The SFTP calls `chdir_in` makes
+trait ChdirOps {
+    fn realpath(&self, path: &Path) -> Result<PathBuf, ssh2::Error>;
+    /// Whether `path` is a directory, following symlinks
+    fn is_dir_followed(&self, path: &Path) -> Result<bool, ssh2::Error>;
+}
+
+impl ChdirOps for Sftp {
+    fn realpath(&self, path: &Path) -> Result<PathBuf, ssh2::Error> {
+        Sftp::realpath(self, path)
+    }
+
+    fn is_dir_followed(&self, path: &Path) -> Result<bool, ssh2::Error> {
+        self.stat(path).map(|stat| stat.is_dir())
+    }
+}
+
+/// Move `current` to `path`, resolved against it and canonicalised by the
+/// server, if that is a directory
+fn chdir_in(fs: &impl ChdirOps, current: &Mutex<PathBuf>, path: &str) -> Result<String, SftpError> {
+    let path_str = resolve_in(&current.lock(), path);
+    let real = fs.realpath(Path::new(&path_str))?;
+    if !fs.is_dir_followed(&real)? {
+        return Err(SftpError::Path(format!("Not a directory: {}", path_str)));
+    }
+    let real_str = real.to_string_lossy().to_string();
+    *current.lock() = real;
+    Ok(real_str)
+}
+
+/// Set only the permission bits of `path`
+fn set_mode(sftp: &Sftp, path: &Path, mode: u32) -> Result<(), ssh2::Error> {
+    sftp.setstat(
+        path,
+        FileStat {
+            size: None,
+            uid: None,
+            gid: None,
+            perm: Some(mode & 0o7777),
+            atime: None,
+            mtime: None,
+        },
+    )
+}
+
+fn file_type(stat: &FileStat) -> FileType {
+    if stat.is_dir() {
+        FileType::Directory
+    } else if stat.file_type().is_symlink() {
+        FileType::Symlink
+    } else if stat.is_file() {
+        FileType::File
+    } else {
+        FileType::Other
+    }
+}
+
+fn file_entry(entry_path: &Path, stat: &FileStat) -> Option<FileEntry> {
+    let name = entry_path.file_name()?.to_string_lossy().to_string();
+
+    // Skip . and ..
+    if name == "." || name == ".." {
+        return None;
+    }
+
+    let file_type = file_type(stat);
+    let size = stat.size.unwrap_or(0);
+    let is_dir = file_type == FileType::Directory;
+    Some(FileEntry {
+        size_human: human_size(size, is_dir),
+        category: FileCategory::of(&name, is_dir),
+        name,
+        path: entry_path.to_string_lossy().to_string(),
+        file_type,
+        size,
+        modified: stat.mtime.map(|t| t as i64),
+        permissions: stat.perm,
+        target_type: None,
+    })
+}
+
+/// Fill in `target_type` for symlinks by following them (one `stat` each);
+/// links to directories are then categorized as directories. Broken links
+/// keep `None`.
+fn resolve_links(sftp: &Sftp, entries: &mut [FileEntry]) {
+    for entry in entries
+        .iter_mut()
+        .filter(|e| e.file_type == FileType::Symlink)
+    {
+        entry.target_type = sftp.stat(Path::new(&entry.path)).ok().map(|s| file_type(&s));
+        if entry.target_type == Some(FileType::Directory) {
+            entry.size_human = String::new();
+            entry.category = FileCategory::Directory;
+        }
+    }
+}
+
+/// First free "name (copy)", "name (copy 2)", ... next to `path`. Files keep
+/// their extension after the suffix.
+fn copy_destination(sftp: &Sftp, path: &Path, is_dir: bool) -> Result<PathBuf, SftpError> {
+    let parent = path.parent().unwrap_or(Path::new("/"));
+    let name = path
+        .file_name()
+        .ok_or_else(|| SftpError::Path(format!("Cannot duplicate {}", path.display())))?
+        .to_string_lossy();
+    let (stem, ext) = match (is_dir, path.file_stem(), path.extension()) {
+        (false, Some(stem), Some(ext)) => (stem.to_string_lossy(), format!(".{}", ext.to_string_lossy())),
+        _ => (name, String::new()),
+    };
+
+    for n in 1..=1000 {
+        let suffix = if n == 1 { " (copy)".to_string() } else { format!(" (copy {})", n) };
+        let candidate = parent.join(format!("{}{}{}", stem, suffix, ext));
+        if sftp.lstat(&candidate).is_err() {
+            return Ok(candidate);
+        }
+    }
+    Err(SftpError::Path(format!("No free name to duplicate {}", path.display())))
+}
+
+/// Copy a file, symlink or directory tree through SFTP
+fn copy_tree(sftp: &Sftp, src: &Path, dest: &Path) -> Result<(), SftpError> {
+    let stat = sftp.lstat(src)?;
+    let mode = stat.perm.map(|p| (p & 0o7777) as i32);
+
+    if stat.file_type().is_symlink() {
+        let target = sftp.readlink(src)?;
+        sftp.symlink(&target, dest)?;
+    } else if stat.is_dir() {
+        sftp.mkdir(dest, mode.unwrap_or(0o755))?;
+        for (entry, _) in sftp.readdir(src)? {
+            let Some(name) = entry.file_name() else { continue };
+            if name == "." || name == ".." {
+                continue;
+            }
+            copy_tree(sftp, &entry, &dest.join(name))?;
+        }
+    } else {
+        let mut reader = sftp.open(src)?;
+        let mut writer = sftp.open_mode(
+            dest,
+            OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE,
+            mode.unwrap_or(0o644),
+            OpenType::File,
+        )?;
+        std::io::copy(&mut reader, &mut writer)?;
+    }
+    Ok(())
 }
 
 pub struct SftpBrowser {
     pub sftp: Arc<Mutex<Sftp>>,
     pub session: Arc<Mutex<Session>>,
     current_path: Mutex<PathBuf>,
+    /// Login directory, once looked up
+    home_dir: Mutex<Option<String>>,
+    /// `user@host:port` of the connection, recorded with journaled transfers
+    server: Option<String>,
+    /// Counters of the terminal session the browser was opened from
+    counters: Option<SessionCounters>,
 }
 
 // Safety: Sftp is wrapped in Mutex for thread-safe access
@@ -55,7 +406,69 @@ impl SftpBrowser {
             sftp,
             session,
             current_path: Mutex::new(PathBuf::from("/")),
+            home_dir: Mutex::new(None),
+            server: None,
+            counters: None,
+        }
+    }
+
+    pub fn with_server(mut self, server: String) -> Self {
+        self.server = Some(server);
+        self
+    }
+
+    pub fn server(&self) -> Option<&str> {
+        self.server.as_deref()
+    }
+
+    /// Count transfers towards a terminal session's totals
+    pub fn with_counters(mut self, counters: Option<SessionCounters>) -> Self {
+        self.counters = counters;
+        self
+    }
+
+    pub fn counters(&self) -> Option<SessionCounters> {
+        self.counters.clone()
+    }
+
+    /// Start in the login directory, where the server resolves relative
+    /// paths, rather than at "/"
+    pub fn with_home_dir(self) -> Self {
+        if let Ok(home) = self.home_dir() {
+            *self.current_path.lock() = PathBuf::from(home);
         }
+        self
+    }
+
+    /// The user's remote home. SFTP has no working directory, so the
+    /// server resolves "." to the login directory whatever the browser's
+    /// current path; servers that won't are asked for `$HOME` instead.
+    pub fn home_dir(&self) -> Result<String, SftpError> {
+        if let Some(home) = self.home_dir.lock().clone() {
+            return Ok(home);
+        }
+        let home = match self.with_blocking(|sftp| sftp.realpath(Path::new("."))) {
+            Ok(home) => home.to_string_lossy().to_string(),
+            Err(e) => self.shell_home().ok_or(e)?,
+        };
+        *self.home_dir.lock() = Some(home.clone());
+        Ok(home)
+    }
+
+    /// `$HOME` from running `echo` on the server, if it allows commands
+    fn shell_home(&self) -> Option<String> {
+        let session = self.session.lock();
+        session.set_blocking(true);
+        let home = (|| {
+            let mut channel = session.channel_session().ok()?;
+            channel.exec("echo \"$HOME\"").ok()?;
+            let mut output = String::new();
+            channel.read_to_string(&mut output).ok()?;
+            let _ = channel.wait_close();
+            Some(output.trim().to_string()).filter(|home| home.starts_with('/'))
+        })();
+        session.set_blocking(false);
+        home
     }
 
     /// Execute an SFTP operation with blocking mode enabled
@@ -83,42 +496,33 @@ impl SftpBrowser {
         *self.current_path.lock() = PathBuf::from(path);
     }
 
-    pub fn list_dir(&self, path: &str) -> Result<Vec<FileEntry>, SftpError> {
-        let path_str = path.to_string();
+    /// `path` made absolute against the current directory. Commands taking
+    /// a remote path resolve it first, so they act on what the user sees.
+    pub fn resolve(&self, path: &str) -> String {
+        resolve_in(&self.current_path.lock(), path)
+    }
+
+    /// Change the current directory, which relative paths are resolved
+    /// against. Returns the new directory, canonicalised by the server.
+    pub fn chdir(&self, path: &str) -> Result<String, SftpError> {
+        self.with_blocking(|sftp| chdir_in(sftp, &self.current_path, path))
+    }
+
+    /// List `path`. With `follow_links`, symlinks are also followed to report
+    /// what they point to, at the cost of one round trip per link.
+    pub fn list_dir(&self, path: &str, follow_links: bool) -> Result<Vec<FileEntry>, SftpError> {
+        let path_str = self.resolve(path);
         self.with_blocking(|sftp| {
             let path = Path::new(&path_str);
             let entries = sftp.readdir(path)?;
 
             let mut files: Vec<FileEntry> = entries
                 .into_iter()
-                .filter_map(|(entry_path, stat)| {
-                    let name = entry_path.file_name()?.to_string_lossy().to_string();
-
-                    // Skip . and ..
-                    if name == "." || name == ".." {
-                        return None;
-                    }
-
-                    let file_type = if stat.is_dir() {
-                        FileType::Directory
-                    } else if stat.file_type().is_symlink() {
-                        FileType::Symlink
-                    } else if stat.is_file() {
-                        FileType::File
-                    } else {
-                        FileType::Other
-                    };
-
-                    Some(FileEntry {
-                        name,
-                        path: entry_path.to_string_lossy().to_string(),
-                        file_type,
-                        size: stat.size.unwrap_or(0),
-                        modified: stat.mtime.map(|t| t as i64),
-                        permissions: stat.perm,
-                    })
-                })
+                .filter_map(|(entry_path, stat)| file_entry(&entry_path, &stat))
                 .collect();
+            if follow_links {
+                resolve_links(sftp, &mut files);
+            }
 
             // Sort: directories first, then by name
             files.sort_by(|a, b| {
@@ -134,8 +538,70 @@ impl SftpBrowser {
         })
     }
 
+    /// List `path` without collecting it first, handing entries to `on_batch`
+    /// in groups of up to `batch_size` as the server returns them. Entries are
+    /// in server order, not sorted. The session lock is released between
+    /// batches so other operations can run. Returns the number of entries.
+    /// `follow_links` is as for `list_dir`.
+    pub fn list_dir_batched<F>(
+        &self,
+        path: &str,
+        batch_size: usize,
+        follow_links: bool,
+        mut on_batch: F,
+    ) -> Result<usize, SftpError>
+    where
+        F: FnMut(Vec<FileEntry>),
+    {
+        let dir_path = PathBuf::from(self.resolve(path));
+        let mut dir = self.with_blocking(|sftp| sftp.opendir(&dir_path))?;
+        let mut total = 0;
+
+        let result = loop {
+            let read = self.with_blocking(|sftp| {
+                let mut batch = Vec::with_capacity(batch_size);
+                let mut done = false;
+                while batch.len() < batch_size {
+                    match dir.readdir() {
+                        Ok((name, stat)) => {
+                            if let Some(entry) = file_entry(&dir_path.join(&name), &stat) {
+                                batch.push(entry);
+                            }
+                        }
+                        Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_FILE) => {
+                            done = true;
+                            break;
+                        }
+                        Err(e) => return Err(SftpError::from(e)),
+                    }
+                }
+                if follow_links {
+                    resolve_links(sftp, &mut batch);
+                }
+                Ok((batch, done))
+            });
+            let (batch, done) = match read {
+                Ok(read) => read,
+                Err(e) => break Err(e),
+            };
+
+            total += batch.len();
+            if !batch.is_empty() {
+                on_batch(batch);
+            }
+            if done {
+                break Ok(total);
+            }
+        };
+
+        // Dropping the handle closes it on the server, which like any other
+        // request needs the session locked and blocking
+        self.with_blocking(|_| drop(dir));
+        result
+    }
+
     pub fn stat(&self, path: &str) -> Result<FileEntry, SftpError> {
-        let path_str = path.to_string();
+        let path_str = self.resolve(path);
         self.with_blocking(|sftp| {
             let path_buf = Path::new(&path_str);
             let stat = sftp.stat(path_buf)?;
@@ -145,37 +611,143 @@ impl SftpBrowser {
                 .map(|n| n.to_string_lossy().to_string())
                 .unwrap_or_else(|| "/".to_string());
 
-            let file_type = if stat.is_dir() {
-                FileType::Directory
-            } else if stat.file_type().is_symlink() {
-                FileType::Symlink
-            } else if stat.is_file() {
-                FileType::File
-            } else {
-                FileType::Other
-            };
-
+            let file_type = file_type(&stat);
+            let size = stat.size.unwrap_or(0);
+            let is_dir = file_type == FileType::Directory;
             Ok(FileEntry {
+                size_human: human_size(size, is_dir),
+                category: FileCategory::of(&name, is_dir),
                 name,
                 path: path_str.clone(),
                 file_type,
-                size: stat.size.unwrap_or(0),
+                size,
                 modified: stat.mtime.map(|t| t as i64),
                 permissions: stat.perm,
+                target_type: None,
             })
         })
     }
 
     pub fn mkdir(&self, path: &str) -> Result<(), SftpError> {
-        let path_str = path.to_string();
+        let path_str = self.resolve(path);
         self.with_blocking(|sftp| {
             sftp.mkdir(Path::new(&path_str), 0o755)?;
             Ok(())
         })
     }
 
+    /// Create an empty file. Fails if it exists unless `overwrite` is set,
+    /// in which case an existing file is truncated.
+    pub fn touch(&self, path: &str, overwrite: bool) -> Result<(), SftpError> {
+        let path_str = self.resolve(path);
+        self.with_blocking(|sftp| {
+            let target = Path::new(&path_str);
+            if !overwrite && sftp.lstat(target).is_ok() {
+                return Err(SftpError::Path(format!("File already exists: {}", path_str)));
+            }
+            let flags = if overwrite {
+                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE
+            } else {
+                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE
+            };
+            let file = sftp.open_mode(target, flags, 0o644, OpenType::File)?;
+            drop(file);
+            Ok(())
+        })
+    }
+
+    /// Change the permission bits of `path`
+    pub fn chmod(&self, path: &str, mode: u32) -> Result<(), SftpError> {
+        let path_str = self.resolve(path);
+        self.with_blocking(|sftp| {
+            set_mode(sftp, Path::new(&path_str), mode)?;
+            Ok(())
+        })
+    }
+
+    /// Add or remove the owner, group and other execute bits of a file,
+    /// keeping the rest of its mode. Returns the new permission bits.
+    pub fn set_executable(&self, path: &str, executable: bool) -> Result<u32, SftpError> {
+        let path_str = self.resolve(path);
+        self.with_blocking(|sftp| {
+            let path = Path::new(&path_str);
+            let stat = sftp.stat(path)?;
+            if stat.is_dir() {
+                return Err(SftpError::Path(format!("Not a file: {}", path_str)));
+            }
+            let mode = stat.perm.unwrap_or(0o644) & 0o7777;
+            let mode = if executable { mode | 0o111 } else { mode & !0o111 };
+            set_mode(sftp, path, mode)?;
+            Ok(mode)
+        })
+    }
+
+    /// Apply `dir_mode` to `path` and every directory below it and
+    /// `file_mode` to everything else, like `chmod -R` with the usual 755/644
+    /// split. Symlinks are left alone. Entries that can't be changed or read
+    /// are recorded in the summary instead of stopping the walk; only a
+    /// missing `path` is an error. The session lock is released between
+    /// directories, and `on_progress` gets the running summary after each.
+    pub fn chmod_recursive<F>(
+        &self,
+        path: &str,
+        file_mode: u32,
+        dir_mode: u32,
+        mut on_progress: F,
+    ) -> Result<ChmodSummary, SftpError>
+    where
+        F: FnMut(&ChmodSummary),
+    {
+        let root = PathBuf::from(self.resolve(path));
+        let mut summary = ChmodSummary::default();
+
+        let stat = self.with_blocking(|sftp| sftp.lstat(&root))?;
+        if !stat.is_dir() {
+            if !stat.file_type().is_symlink() {
+                self.with_blocking(|sftp| set_mode(sftp, &root, file_mode))?;
+                summary.changed += 1;
+            }
+            return Ok(summary);
+        }
+
+        let mut pending = vec![root];
+        while let Some(dir) = pending.pop() {
+            self.with_blocking(|sftp| {
+                let mut record = |path: &Path, result: Result<(), ssh2::Error>| match result {
+                    Ok(()) => summary.changed += 1,
+                    Err(e) => summary.failed.push(ChmodFailure {
+                        path: path.to_string_lossy().to_string(),
+                        error: e.to_string(),
+                    }),
+                };
+
+                // Change the directory first, so a mode that grants read
+                // access also lets us list it
+                record(&dir, set_mode(sftp, &dir, dir_mode));
+                let entries = match sftp.readdir(&dir) {
+                    Ok(entries) => entries,
+                    Err(e) => return record(&dir, Err(e)),
+                };
+
+                for (entry, stat) in entries {
+                    match entry.file_name() {
+                        Some(name) if name != "." && name != ".." => {}
+                        _ => continue,
+                    }
+                    if stat.is_dir() {
+                        pending.push(entry);
+                    } else if !stat.file_type().is_symlink() {
+                        record(&entry, set_mode(sftp, &entry, file_mode));
+                    }
+                }
+            });
+            on_progress(&summary);
+        }
+        Ok(summary)
+    }
+
     pub fn rmdir(&self, path: &str) -> Result<(), SftpError> {
-        let path_str = path.to_string();
+        let path_str = self.resolve(path);
         self.with_blocking(|sftp| {
             sftp.rmdir(Path::new(&path_str))?;
             Ok(())
@@ -183,16 +755,47 @@ impl SftpBrowser {
     }
 
     pub fn delete(&self, path: &str) -> Result<(), SftpError> {
-        let path_str = path.to_string();
+        let path_str = self.resolve(path);
         self.with_blocking(|sftp| {
             sftp.unlink(Path::new(&path_str))?;
             Ok(())
         })
     }
 
+    /// Delete each absolute `(path, is_dir)` in turn, folders with their
+    /// contents. The session is held for one item at a time, so other
+    /// operations get through between them. A failure is recorded and the
+    /// rest are still deleted; `on_item` gets each path and error as it
+    /// happens.
+    pub fn delete_many<F>(&self, items: &[(String, bool)], mut on_item: F) -> DeleteSummary
+    where
+        F: FnMut(&str, Option<&str>),
+    {
+        let mut summary = DeleteSummary::default();
+        for (path, is_dir) in items {
+            let removed = self.with_blocking(|sftp| {
+                if *is_dir {
+                    remove_tree(sftp, Path::new(path))
+                } else {
+                    sftp.unlink(Path::new(path))
+                }
+            });
+            let error = removed.err().map(|e| e.to_string());
+            on_item(path, error.as_deref());
+            match error {
+                None => summary.deleted.push(path.clone()),
+                Some(error) => summary.failed.push(DeleteFailure {
+                    path: path.clone(),
+                    error,
+                }),
+            }
+        }
+        summary
+    }
+
     pub fn rename(&self, old_path: &str, new_path: &str) -> Result<(), SftpError> {
-        let old = old_path.to_string();
-        let new = new_path.to_string();
+        let old = self.resolve(old_path);
+        let new = self.resolve(new_path);
         self.with_blocking(|sftp| {
             sftp.rename(
                 Path::new(&old),
@@ -203,11 +806,228 @@ impl SftpBrowser {
         })
     }
 
+    /// Rename or move a file, replacing the destination if it already exists.
+    /// Servers speaking SFTP v3 ignore the flags and refuse to overwrite, so
+    /// there the destination is moved aside for the rename (see
+    /// `rename_replacing`).
+    pub fn rename_overwrite(&self, old_path: &str, new_path: &str) -> Result<(), SftpError> {
+        let old = self.resolve(old_path);
+        let new = self.resolve(new_path);
+        self.with_blocking(|sftp| rename_replacing(sftp, Path::new(&old), Path::new(&new)))?;
+        Ok(())
+    }
+
+    /// Copy `path` (file or folder) to "name (copy)" in the same directory and
+    /// return the new path. Runs `cp` on the server when possible, otherwise
+    /// streams the copy through SFTP.
+    pub fn duplicate(&self, path: &str) -> Result<String, SftpError> {
+        let src_str = self.resolve(path);
+        let src = PathBuf::from(&src_str);
+        let dest = self.with_blocking(|sftp| {
+            let is_dir = sftp.stat(&src)?.is_dir();
+            copy_destination(sftp, &src, is_dir)
+        })?;
+        let dest_str = dest.to_string_lossy().to_string();
+
+        if let Err(e) = self.exec_copy(&src_str, &dest_str) {
+            // Don't mix a fallback copy into whatever cp left behind
+            if self.with_blocking(|sftp| sftp.lstat(&dest).is_ok()) {
+                return Err(e);
+            }
+            eprintln!("SFTP: server-side copy unavailable ({}), copying over SFTP", e);
+            self.with_blocking(|sftp| copy_tree(sftp, &src, &dest))?;
+        }
+        Ok(dest_str)
+    }
+
+    /// Run `cp` on the server over an exec channel
+    fn exec_copy(&self, src: &str, dest: &str) -> Result<(), SftpError> {
+        let session = self.session.lock();
+        session.set_blocking(true);
+
+        let result = (|| -> Result<(), SftpError> {
+            let mut channel = session.channel_session()?;
+            channel.exec(&format!("cp -RPp -- {} {}", shell_quote(src), shell_quote(dest)))?;
+            let mut stderr = String::new();
+            let _ = channel.stderr().read_to_string(&mut stderr);
+            channel.wait_close()?;
+            match channel.exit_status()? {
+                0 => Ok(()),
+                code => Err(SftpError::Sftp(format!("cp exited with {}: {}", code, stderr.trim()))),
+            }
+        })();
+
+        session.set_blocking(false);
+        result
+    }
+
+    /// Upload a local file to `path`, failing if `path` already exists
+    pub fn create_from_file(&self, local_path: &Path, path: &str) -> Result<(), SftpError> {
+        let mut reader = std::fs::File::open(local_path)?;
+        let path_str = self.resolve(path);
+        self.with_blocking(|sftp| {
+            let target = Path::new(&path_str);
+            if sftp.lstat(target).is_ok() {
+                return Err(SftpError::Path(format!("File already exists: {}", path_str)));
+            }
+            let mut writer = sftp.open_mode(
+                target,
+                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE,
+                0o644,
+                OpenType::File,
+            )?;
+            std::io::copy(&mut reader, &mut writer)?;
+            Ok(())
+        })
+    }
+
     pub fn realpath(&self, path: &str) -> Result<String, SftpError> {
-        let path_str = path.to_string();
+        let path_str = self.resolve(path);
         self.with_blocking(|sftp| {
             let real = sftp.realpath(Path::new(&path_str))?;
             Ok(real.to_string_lossy().to_string())
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    const SSH_FX_NO_SUCH_FILE: i32 = 2;
+    const SSH_FX_PERMISSION_DENIED: i32 = 3;
+
+    /// An SFTP v3 server: paths map to their contents, directories are
+    /// `None`, and renames never overwrite
+    #[derive(Default)]
+    struct FakeServer {
+        entries: RefCell<HashMap<PathBuf, Option<&'static str>>>,
+        /// Renames of these sources fail with the given status
+        failing: HashMap<PathBuf, i32>,
+    }
+
+    impl FakeServer {
+        fn with(entries: &[(&str, Option<&'static str>)]) -> Self {
+            Self {
+                entries: RefCell::new(entries.iter().map(|(p, c)| (PathBuf::from(p), *c)).collect()),
+                failing: HashMap::new(),
+            }
+        }
+
+        fn get(&self, path: &str) -> Option<Option<&'static str>> {
+            self.entries.borrow().get(Path::new(path)).copied()
+        }
+    }
+
+    fn status(code: i32) -> ssh2::Error {
+        ssh2::Error::new(ErrorCode::SFTP(code), "fake")
+    }
+
+    impl RenameOps for FakeServer {
+        fn move_to(&self, from: &Path, to: &Path) -> Result<(), ssh2::Error> {
+            if let Some(code) = self.failing.get(from) {
+                return Err(status(*code));
+            }
+            let mut entries = self.entries.borrow_mut();
+            if !entries.contains_key(from) {
+                return Err(status(SSH_FX_NO_SUCH_FILE));
+            }
+            if entries.contains_key(to) {
+                return Err(status(SSH_FX_FAILURE));
+            }
+            let contents = entries.remove(from).unwrap();
+            entries.insert(to.to_path_buf(), contents);
+            Ok(())
+        }
+
+        fn is_dir(&self, path: &Path) -> Result<bool, ssh2::Error> {
+            match self.entries.borrow().get(path) {
+                Some(contents) => Ok(contents.is_none()),
+                None => Err(status(SSH_FX_NO_SUCH_FILE)),
+            }
+        }
+
+        fn remove(&self, path: &Path) -> Result<(), ssh2::Error> {
+            self.entries.borrow_mut().remove(path);
+            Ok(())
+        }
+    }
+
+    impl ChdirOps for FakeServer {
+        fn realpath(&self, path: &Path) -> Result<PathBuf, ssh2::Error> {
+            match self.entries.borrow().contains_key(path) {
+                true => Ok(path.to_path_buf()),
+                false => Err(status(SSH_FX_NO_SUCH_FILE)),
+            }
+        }
+
+        fn is_dir_followed(&self, path: &Path) -> Result<bool, ssh2::Error> {
+            RenameOps::is_dir(self, path)
+        }
+    }
+
+    #[test]
+    fn relative_paths_after_chdir_are_in_the_new_directory() {
+        let server = FakeServer::with(&[
+            ("/home/me/report.csv", Some("home")),
+            ("/srv", None),
+            ("/srv/app", None),
+            ("/srv/app/report.csv", Some("app")),
+        ]);
+        let current = Mutex::new(PathBuf::from("/home/me"));
+
+        assert_eq!(chdir_in(&server, &current, "/srv").unwrap(), "/srv");
+        assert_eq!(chdir_in(&server, &current, "app").unwrap(), "/srv/app");
+        assert_eq!(server.get(&resolve_in(&current.lock(), "report.csv")), Some(Some("app")));
+        assert!(chdir_in(&server, &current, "report.csv").is_err());
+        assert_eq!(*current.lock(), PathBuf::from("/srv/app"));
+    }
+
+    fn rename(server: &FakeServer) -> Result<(), ssh2::Error> {
+        rename_replacing(server, Path::new("/d/src.txt"), Path::new("/d/dest.txt"))
+    }
+
+    #[test]
+    fn replaces_an_existing_file() {
+        let server = FakeServer::with(&[("/d/src.txt", Some("src")), ("/d/dest.txt", Some("dest"))]);
+        rename(&server).unwrap();
+        assert_eq!(server.get("/d/dest.txt"), Some(Some("src")));
+        assert_eq!(server.entries.borrow().len(), 1, "nothing left beside it");
+    }
+
+    #[test]
+    fn missing_source_leaves_destination() {
+        let server = FakeServer::with(&[("/d/dest.txt", Some("dest"))]);
+        assert!(rename(&server).is_err());
+        assert_eq!(server.get("/d/dest.txt"), Some(Some("dest")));
+    }
+
+    #[test]
+    fn other_failures_leave_destination() {
+        let mut server = FakeServer::with(&[("/d/src.txt", Some("src")), ("/d/dest.txt", Some("dest"))]);
+        server.failing.insert(PathBuf::from("/d/src.txt"), SSH_FX_PERMISSION_DENIED);
+        let err = rename(&server).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::SFTP(SSH_FX_PERMISSION_DENIED));
+        assert_eq!(server.get("/d/dest.txt"), Some(Some("dest")));
+        assert_eq!(server.get("/d/src.txt"), Some(Some("src")));
+    }
+
+    #[test]
+    fn failed_retry_restores_destination() {
+        let mut server = FakeServer::with(&[("/d/src.txt", Some("src")), ("/d/dest.txt", Some("dest"))]);
+        server.failing.insert(PathBuf::from("/d/src.txt"), SSH_FX_FAILURE);
+        assert!(rename(&server).is_err());
+        assert_eq!(server.get("/d/dest.txt"), Some(Some("dest")));
+        assert_eq!(server.get("/d/src.txt"), Some(Some("src")));
+        assert_eq!(server.entries.borrow().len(), 2);
+    }
+
+    #[test]
+    fn directory_destination_is_not_replaced() {
+        let server = FakeServer::with(&[("/d/src.txt", Some("src")), ("/d/dest.txt", None)]);
+        assert!(rename(&server).is_err());
+        assert_eq!(server.get("/d/dest.txt"), Some(None));
+    }
+}
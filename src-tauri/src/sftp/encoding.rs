@@ -0,0 +1,176 @@
+//! Text encoding and line ending detection for the remote file editor, so
+//! files are saved back the way they were found.
+
+use serde::{Deserialize, Serialize};
+
+/// How much of a file is inspected to detect its format
+pub const SNIFF_LEN: usize = 8 * 1024;
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Anything that isn't valid UTF-8 but doesn't look binary
+    Latin1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+/// Detected format of a remote file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileFormat {
+    pub encoding: TextEncoding,
+    /// Whether the file starts with a byte order mark
+    pub bom: bool,
+    pub line_ending: LineEnding,
+    /// Not text; the editor shouldn't open it
+    pub binary: bool,
+}
+
+/// Result of `sftp_edit_open`
+#[derive(Debug, Clone, Serialize)]
+pub struct EditorFile {
+    /// Contents with LF line endings; `None` for binary files
+    pub content: Option<String>,
+    pub format: FileFormat,
+}
+
+/// Detect the format from the start of a file
+pub fn detect(sample: &[u8]) -> FileFormat {
+    let (encoding, bom) = if sample.starts_with(UTF8_BOM) {
+        (TextEncoding::Utf8, true)
+    } else if sample.starts_with(UTF16LE_BOM) {
+        (TextEncoding::Utf16Le, true)
+    } else if sample.starts_with(UTF16BE_BOM) {
+        (TextEncoding::Utf16Be, true)
+    } else if looks_binary(sample) {
+        return FileFormat {
+            encoding: TextEncoding::Utf8,
+            bom: false,
+            line_ending: LineEnding::Lf,
+            binary: true,
+        };
+    } else if is_utf8(sample) {
+        (TextEncoding::Utf8, false)
+    } else {
+        (TextEncoding::Latin1, false)
+    };
+
+    let mut format = FileFormat {
+        encoding,
+        bom,
+        line_ending: LineEnding::Lf,
+        binary: false,
+    };
+    format.line_ending = line_ending(&decode_raw(sample, &format));
+    format
+}
+
+/// Decode a whole file to text with LF line endings. A UTF-8 guess made from
+/// the sample becomes Latin-1 if invalid bytes turn up later in the file.
+pub fn decode(bytes: &[u8], format: &mut FileFormat) -> String {
+    if format.encoding == TextEncoding::Utf8 && !is_utf8(bytes) {
+        format.encoding = TextEncoding::Latin1;
+        format.bom = false;
+    }
+    decode_raw(bytes, format).replace("\r\n", "\n")
+}
+
+/// Encode editor text for saving, with the file's original encoding, byte
+/// order mark and line endings
+pub fn encode(text: &str, format: &FileFormat) -> Result<Vec<u8>, String> {
+    if format.binary {
+        return Err("Binary files can't be saved from the editor".to_string());
+    }
+
+    let text = text.replace("\r\n", "\n");
+    let text = match format.line_ending {
+        LineEnding::Lf => text,
+        LineEnding::Crlf => text.replace('\n', "\r\n"),
+    };
+
+    let mut bytes = Vec::with_capacity(text.len() + 3);
+    match format.encoding {
+        TextEncoding::Utf8 => {
+            if format.bom {
+                bytes.extend_from_slice(UTF8_BOM);
+            }
+            bytes.extend_from_slice(text.as_bytes());
+        }
+        TextEncoding::Utf16Le => {
+            bytes.extend_from_slice(UTF16LE_BOM);
+            bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        }
+        TextEncoding::Utf16Be => {
+            bytes.extend_from_slice(UTF16BE_BOM);
+            bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+        }
+        TextEncoding::Latin1 => {
+            for c in text.chars() {
+                let byte = u8::try_from(u32::from(c))
+                    .map_err(|_| format!("'{}' can't be saved in Latin-1", c))?;
+                bytes.push(byte);
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decode without touching line endings, replacing anything invalid
+fn decode_raw(bytes: &[u8], format: &FileFormat) -> String {
+    let utf16 = |to_u16: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes[2..]
+            .chunks_exact(2)
+            .map(|pair| to_u16([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    };
+
+    match format.encoding {
+        TextEncoding::Utf8 if format.bom => String::from_utf8_lossy(&bytes[UTF8_BOM.len()..]).into_owned(),
+        TextEncoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        TextEncoding::Utf16Le => utf16(u16::from_le_bytes),
+        TextEncoding::Utf16Be => utf16(u16::from_be_bytes),
+        TextEncoding::Latin1 => bytes.iter().map(|&b| char::from(b)).collect(),
+    }
+}
+
+/// Valid UTF-8, allowing a character cut off at the end of a sample
+fn is_utf8(bytes: &[u8]) -> bool {
+    match std::str::from_utf8(bytes) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// NUL bytes, or lots of control characters that don't occur in text
+fn looks_binary(sample: &[u8]) -> bool {
+    if sample.contains(&0) {
+        return true;
+    }
+    let control = sample
+        .iter()
+        .filter(|&&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0C | 0x1B))
+        .count();
+    control * 10 > sample.len()
+}
+
+/// CRLF if most lines end that way
+fn line_ending(text: &str) -> LineEnding {
+    let lines = text.matches('\n').count();
+    let crlf = text.matches("\r\n").count();
+    if crlf > 0 && crlf * 2 >= lines {
+        LineEnding::Crlf
+    } else {
+        LineEnding::Lf
+    }
+}
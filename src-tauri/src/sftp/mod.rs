@@ -1,5 +1,10 @@
 pub mod browser;
+pub mod encoding;
+pub mod templates;
 pub mod transfer;
 
-pub use browser::{FileEntry, SftpBrowser};
+pub use browser::{
+    ChmodComplete, ChmodProgress, DeleteComplete, DeleteResult, FileEntry, ListingBatch, ListingComplete, SftpBrowser,
+    LISTING_BATCH_SIZE,
+};
 pub use transfer::{TransferProgress, TransferStatus};
@@ -0,0 +1,38 @@
+use super::browser::SftpError;
+use std::fs;
+use std::path::PathBuf;
+
+/// Local folder of boilerplate files offered by "new from template"
+pub fn templates_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("openterm")
+        .join("templates")
+}
+
+/// Names of the files in the templates folder, sorted. Creates the folder so
+/// users have somewhere to drop templates.
+pub fn list_templates() -> Result<Vec<String>, SftpError> {
+    let dir = templates_dir();
+    fs::create_dir_all(&dir)?;
+
+    let mut names: Vec<String> = fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Local path of the template called `name`
+pub fn template_path(name: &str) -> Result<PathBuf, SftpError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(SftpError::Path(format!("Invalid template name: {}", name)));
+    }
+    let path = templates_dir().join(name);
+    if !path.is_file() {
+        return Err(SftpError::Path(format!("Template not found: {}", name)));
+    }
+    Ok(path)
+}
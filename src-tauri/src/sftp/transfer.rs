@@ -1,14 +1,19 @@
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
-use ssh2::{Session, Sftp};
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
+use sha2::{Digest, Sha256};
+use ssh2::{FileStat, OpenFlags, OpenType, Session, Sftp};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 use uuid::Uuid;
 use walkdir::WalkDir;
 
+use crate::terminal::session::SessionCounters;
+use crate::util::shell_quote;
+
 #[derive(Error, Debug)]
 pub enum TransferError {
     #[error("SFTP error: {0}")]
@@ -17,6 +22,8 @@ pub enum TransferError {
     Io(#[from] std::io::Error),
     #[error("Transfer cancelled")]
     Cancelled,
+    #[error("Verification failed: {0}")]
+    Verify(String),
 }
 
 impl From<ssh2::Error> for TransferError {
@@ -74,10 +81,70 @@ impl TransferProgress {
     }
 }
 
+/// Total size in bytes of a local file, or of all files under a folder
+pub fn local_tree_size(local_path: &str) -> u64 {
+    WalkDir::new(local_path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Number of files under a local folder, or 1 for a file
+pub fn local_file_count(local_path: &str) -> u64 {
+    WalkDir::new(local_path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .count() as u64
+}
+
+/// Path of the in-progress file used while downloading to `local_path`
+pub fn part_path(local_path: &str) -> String {
+    format!("{}.part", local_path)
+}
+
+/// Remote attributes carrying a local file's mode and timestamps
+fn local_attributes(meta: &std::fs::Metadata) -> FileStat {
+    let secs = |time: std::io::Result<SystemTime>| {
+        time.ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+    };
+    let mtime = secs(meta.modified());
+
+    #[cfg(unix)]
+    let perm = {
+        use std::os::unix::fs::PermissionsExt;
+        Some(meta.permissions().mode() & 0o7777)
+    };
+
+    #[cfg(not(unix))]
+    let perm = None;
+
+    FileStat {
+        size: None,
+        uid: None,
+        gid: None,
+        perm,
+        // SFTP sets both times together
+        atime: secs(meta.accessed()).or(mtime),
+        mtime,
+    }
+}
+
 pub struct FileTransfer {
     sftp: Arc<Mutex<Sftp>>,
     session: Arc<Mutex<Session>>,
     cancelled: Arc<Mutex<bool>>,
+    /// Carry permissions and timestamps over to the copy
+    preserve_metadata: bool,
+    /// Create missing remote directories above an upload's destination
+    create_parents: bool,
+    /// Totals of the terminal session the transfer counts towards
+    counters: Option<SessionCounters>,
 }
 
 // Safety: Sftp and Session are wrapped in Mutex for thread-safe access
@@ -90,6 +157,63 @@ impl FileTransfer {
             sftp,
             session,
             cancelled: Arc::new(Mutex::new(false)),
+            preserve_metadata: false,
+            create_parents: false,
+            counters: None,
+        }
+    }
+
+    /// Copy mode and mtime/atime of uploaded files to the server, and mtime/atime
+    /// of downloaded files to the local copy
+    pub fn with_preserve_metadata(mut self, preserve: bool) -> Self {
+        self.preserve_metadata = preserve;
+        self
+    }
+
+    pub fn with_counters(mut self, counters: Option<SessionCounters>) -> Self {
+        self.counters = counters;
+        self
+    }
+
+    /// Create any missing directories above the remote destination before
+    /// uploading, instead of failing with "No such file"
+    pub fn with_create_parents(mut self, create: bool) -> Self {
+        self.create_parents = create;
+        self
+    }
+
+    /// Create `dir` and any missing ancestors on the server
+    fn create_remote_dirs(&self, dir: &Path) -> Result<(), TransferError> {
+        self.with_blocking(|sftp| {
+            if dir.as_os_str().is_empty() || sftp.stat(dir).is_ok() {
+                return Ok(());
+            }
+            let mut current = PathBuf::new();
+            for component in dir.components() {
+                current.push(component);
+                if let Err(e) = sftp.mkdir(&current, 0o755) {
+                    // Fine if it already exists, e.g. an ancestor we can't write to
+                    if !sftp.stat(&current).map(|stat| stat.is_dir()).unwrap_or(false) {
+                        return Err(TransferError::Sftp(format!(
+                            "Failed to create {}: {}",
+                            current.display(),
+                            e
+                        )));
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Apply local mode and timestamps to an uploaded file. Some servers refuse
+    /// this; the upload itself still counts as done.
+    fn copy_attributes(&self, sftp: &Sftp, remote: &Path, meta: &std::fs::Metadata) {
+        if !self.preserve_metadata {
+            return;
+        }
+        if let Err(e) = sftp.setstat(remote, local_attributes(meta)) {
+            eprintln!("SFTP: Failed to preserve attributes of {}: {}", remote.display(), e);
         }
     }
 
@@ -97,6 +221,11 @@ impl FileTransfer {
         *self.cancelled.lock() = true;
     }
 
+    /// Flag shared with this transfer; setting it cancels the transfer
+    pub fn cancel_flag(&self) -> Arc<Mutex<bool>> {
+        self.cancelled.clone()
+    }
+
     /// Execute an SFTP operation with blocking mode enabled
     fn with_blocking<T, F>(&self, f: F) -> T
     where
@@ -114,49 +243,79 @@ impl FileTransfer {
         result
     }
 
+    /// Read or write one chunk of an open remote file in blocking mode. The
+    /// session is locked per chunk rather than per transfer, so a terminal
+    /// sharing it keeps getting output while files move.
+    fn blocking_chunk<T>(&self, f: impl FnOnce() -> T) -> T {
+        let session = self.session.lock();
+        session.set_blocking(true);
+        let result = f();
+        session.set_blocking(false);
+        result
+    }
+
+    /// Download a remote file via a `.part` file next to `local_path`.
+    ///
+    /// When `resume` is set and a `.part` file from an interrupted download exists,
+    /// the transfer continues from its current length instead of starting over.
+    /// The `.part` file is renamed to `local_path` once the download completes.
     pub fn download<F>(
         &self,
         remote_path: &str,
         local_path: &str,
+        resume: bool,
         mut progress_callback: F,
     ) -> Result<(), TransferError>
     where
         F: FnMut(u64, u64),
     {
-        // Set blocking mode for the entire transfer operation
-        let session = self.session.lock();
-        session.set_blocking(true);
-
-        let sftp = self.sftp.lock();
         let remote = Path::new(remote_path);
 
-        // Get file size
-        let stat = sftp.stat(remote)?;
+        // Get file size and open the remote file
+        let (stat, mut remote_file) = self.with_blocking(|sftp| -> Result<_, ssh2::Error> {
+            Ok((sftp.stat(remote)?, sftp.open(remote)?))
+        })?;
         let total_size = stat.size.unwrap_or(0);
 
-        // Open remote file
-        let mut remote_file = sftp.open(remote)?;
-
-        // Create local file
-        let mut local_file = File::create(local_path)?;
+        // Pick up where a previous attempt left off, if its partial file is usable
+        let part_path = part_path(local_path);
+        let existing = std::fs::metadata(&part_path).map(|m| m.len()).unwrap_or(0);
+        let offset = if resume && existing > 0 && existing <= total_size {
+            existing
+        } else {
+            0
+        };
+
+        let mut local_file = if offset > 0 {
+            remote_file.seek(SeekFrom::Start(offset))?;
+            OpenOptions::new().append(true).open(&part_path)?
+        } else {
+            File::create(&part_path)?
+        };
 
         let mut buffer = vec![0u8; 256 * 1024]; // 256KB buffer
-        let mut transferred: u64 = 0;
-        let mut last_progress: u64 = 0;
+        let mut transferred: u64 = offset;
+        let mut last_progress: u64 = offset;
+
+        if offset > 0 {
+            progress_callback(transferred, total_size);
+        }
 
         loop {
             if *self.cancelled.lock() {
-                session.set_blocking(false);
                 return Err(TransferError::Cancelled);
             }
 
-            let bytes_read = remote_file.read(&mut buffer)?;
+            let bytes_read = self.blocking_chunk(|| remote_file.read(&mut buffer))?;
             if bytes_read == 0 {
                 break;
             }
 
             local_file.write_all(&buffer[..bytes_read])?;
             transferred += bytes_read as u64;
+            if let Some(counters) = &self.counters {
+                counters.record_downloaded(bytes_read);
+            }
 
             // Throttle progress updates to every 512KB
             if transferred - last_progress >= 512 * 1024 || transferred == total_size {
@@ -166,24 +325,37 @@ impl FileTransfer {
         }
 
         local_file.flush()?;
-        session.set_blocking(false);
+        drop(local_file);
+        self.blocking_chunk(|| drop(remote_file));
+
+        std::fs::rename(&part_path, local_path)?;
+
+        if self.preserve_metadata {
+            if let Some(mtime) = stat.mtime {
+                let modified = UNIX_EPOCH + Duration::from_secs(mtime);
+                let accessed = stat.atime.map_or(modified, |t| UNIX_EPOCH + Duration::from_secs(t));
+                let times = std::fs::FileTimes::new()
+                    .set_modified(modified)
+                    .set_accessed(accessed);
+                OpenOptions::new().write(true).open(local_path)?.set_times(times)?;
+            }
+        }
         Ok(())
     }
 
+    /// Upload a local file. With `resume`, an existing remote file no longer
+    /// than the local one is taken as an interrupted upload and continued
+    /// from its length.
     pub fn upload<F>(
         &self,
         local_path: &str,
         remote_path: &str,
+        resume: bool,
         mut progress_callback: F,
     ) -> Result<(), TransferError>
     where
         F: FnMut(u64, u64),
     {
-        // Set blocking mode for the entire transfer operation
-        let session = self.session.lock();
-        session.set_blocking(true);
-
-        let sftp = self.sftp.lock();
         let remote = Path::new(remote_path);
 
         // Get local file size
@@ -193,16 +365,41 @@ impl FileTransfer {
         // Open local file
         let mut local_file = File::open(local_path)?;
 
-        // Create remote file
-        let mut remote_file = sftp.create(remote)?;
+        if self.create_parents {
+            if let Some(parent) = remote.parent() {
+                self.create_remote_dirs(parent)?;
+            }
+        }
+
+        let offset = if resume {
+            self.with_blocking(|sftp| sftp.stat(remote).ok())
+                .and_then(|stat| stat.size)
+                .filter(|&size| size > 0 && size <= total_size)
+                .unwrap_or(0)
+        } else {
+            0
+        };
+
+        let mut remote_file = if offset > 0 {
+            local_file.seek(SeekFrom::Start(offset))?;
+            let mut remote_file =
+                self.with_blocking(|sftp| sftp.open_mode(remote, OpenFlags::WRITE, 0o644, OpenType::File))?;
+            remote_file.seek(SeekFrom::Start(offset))?;
+            remote_file
+        } else {
+            self.with_blocking(|sftp| sftp.create(remote))?
+        };
 
         let mut buffer = vec![0u8; 256 * 1024]; // 256KB buffer
-        let mut transferred: u64 = 0;
-        let mut last_progress: u64 = 0;
+        let mut transferred: u64 = offset;
+        let mut last_progress: u64 = offset;
+
+        if offset > 0 {
+            progress_callback(transferred, total_size);
+        }
 
         loop {
             if *self.cancelled.lock() {
-                session.set_blocking(false);
                 return Err(TransferError::Cancelled);
             }
 
@@ -211,8 +408,11 @@ impl FileTransfer {
                 break;
             }
 
-            remote_file.write_all(&buffer[..bytes_read])?;
+            self.blocking_chunk(|| remote_file.write_all(&buffer[..bytes_read]))?;
             transferred += bytes_read as u64;
+            if let Some(counters) = &self.counters {
+                counters.record_uploaded(bytes_read);
+            }
 
             // Throttle progress updates to every 512KB
             if transferred - last_progress >= 512 * 1024 || transferred == total_size {
@@ -221,12 +421,61 @@ impl FileTransfer {
             }
         }
 
-        remote_file.flush()?;
-        session.set_blocking(false);
+        self.blocking_chunk(|| {
+            remote_file.flush()?;
+            drop(remote_file);
+            Ok::<_, std::io::Error>(())
+        })?;
+        self.with_blocking(|sftp| self.copy_attributes(sftp, remote, &local_file_meta));
+        Ok(())
+    }
+
+    /// Check that `remote_path` matches the local file after an upload:
+    /// same size, and same SHA-256 when the server can run `sha256sum`
+    pub fn verify_upload(&self, local_path: &str, remote_path: &str) -> Result<(), TransferError> {
+        let local_size = std::fs::metadata(local_path)?.len();
+        let remote_size = self.with_blocking(|sftp| sftp.stat(Path::new(remote_path)))?.size;
+        if remote_size != Some(local_size) {
+            return Err(TransferError::Verify(format!(
+                "remote size {} differs from local size {}",
+                remote_size.map_or_else(|| "unknown".to_string(), |size| size.to_string()),
+                local_size
+            )));
+        }
+        if let Some(remote_hash) = self.remote_sha256(remote_path) {
+            if remote_hash != local_sha256(local_path)? {
+                return Err(TransferError::Verify("checksums differ".to_string()));
+            }
+        }
         Ok(())
     }
 
-    /// Upload a folder recursively
+    /// SHA-256 of a remote file as lowercase hex, or None when the server
+    /// has no shell or `sha256sum`
+    fn remote_sha256(&self, remote_path: &str) -> Option<String> {
+        let session = self.session.lock();
+        session.set_blocking(true);
+        let hash = (|| {
+            let mut channel = session.channel_session().ok()?;
+            channel.exec(&format!("sha256sum -- {}", shell_quote(remote_path))).ok()?;
+            let mut output = String::new();
+            channel.read_to_string(&mut output).ok()?;
+            channel.wait_close().ok()?;
+            if channel.exit_status().ok()? != 0 {
+                return None;
+            }
+            output
+                .split_whitespace()
+                .next()
+                .filter(|hash| hash.len() == 64)
+                .map(str::to_ascii_lowercase)
+        })();
+        session.set_blocking(false);
+        hash
+    }
+
+    /// Upload a folder recursively. Progress names each file by its path
+    /// within the folder.
     pub fn upload_folder<F>(
         &self,
         local_path: &str,
@@ -240,14 +489,7 @@ impl FileTransfer {
         let remote_base = Path::new(remote_path);
 
         // Calculate total size first
-        let mut total_size: u64 = 0;
-        for entry in WalkDir::new(local_path).into_iter().filter_map(|e| e.ok()) {
-            if entry.file_type().is_file() {
-                if let Ok(metadata) = entry.metadata() {
-                    total_size += metadata.len();
-                }
-            }
-        }
+        let total_size = local_tree_size(local_path);
 
         let mut transferred: u64 = 0;
 
@@ -258,19 +500,14 @@ impl FileTransfer {
             .unwrap_or_else(|| "upload".to_string());
         let remote_root = remote_base.join(&folder_name);
 
-        // Set blocking mode for the entire folder upload
-        let session = self.session.lock();
-        session.set_blocking(true);
-
-        {
-            let sftp = self.sftp.lock();
-            let _ = sftp.mkdir(&remote_root, 0o755);
+        if self.create_parents {
+            self.create_remote_dirs(remote_base)?;
         }
+        let _ = self.with_blocking(|sftp| sftp.mkdir(&remote_root, 0o755));
 
         // Walk through local directory
         for entry in WalkDir::new(local_path).into_iter().filter_map(|e| e.ok()) {
             if *self.cancelled.lock() {
-                session.set_blocking(false);
                 return Err(TransferError::Cancelled);
             }
 
@@ -280,26 +517,20 @@ impl FileTransfer {
 
             if entry.file_type().is_dir() {
                 // Create directory on remote
-                let sftp = self.sftp.lock();
-                let _ = sftp.mkdir(&remote_entry_path, 0o755);
+                let _ = self.with_blocking(|sftp| sftp.mkdir(&remote_entry_path, 0o755));
             } else if entry.file_type().is_file() {
-                // Upload file
-                let file_name = entry_path
-                    .file_name()
-                    .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or_default();
+                // Upload file, reporting its path within the folder
+                let file_name = relative_path.to_string_lossy().to_string();
 
                 progress_callback(transferred, total_size, &file_name);
 
-                let sftp = self.sftp.lock();
                 let mut local_file = File::open(entry_path)?;
-                let mut remote_file = sftp.create(&remote_entry_path)?;
+                let mut remote_file = self.with_blocking(|sftp| sftp.create(&remote_entry_path))?;
 
                 let mut buffer = vec![0u8; 256 * 1024];
                 let mut last_progress = transferred;
                 loop {
                     if *self.cancelled.lock() {
-                        session.set_blocking(false);
                         return Err(TransferError::Cancelled);
                     }
 
@@ -308,8 +539,11 @@ impl FileTransfer {
                         break;
                     }
 
-                    remote_file.write_all(&buffer[..bytes_read])?;
+                    self.blocking_chunk(|| remote_file.write_all(&buffer[..bytes_read]))?;
                     transferred += bytes_read as u64;
+                    if let Some(counters) = &self.counters {
+                        counters.record_uploaded(bytes_read);
+                    }
 
                     if transferred - last_progress >= 512 * 1024 {
                         progress_callback(transferred, total_size, &file_name);
@@ -317,11 +551,79 @@ impl FileTransfer {
                     }
                 }
 
-                remote_file.flush()?;
+                self.blocking_chunk(|| {
+                    remote_file.flush()?;
+                    drop(remote_file);
+                    Ok::<_, std::io::Error>(())
+                })?;
+                if let Ok(meta) = entry.metadata() {
+                    self.with_blocking(|sftp| self.copy_attributes(sftp, &remote_entry_path, &meta));
+                }
             }
         }
 
-        session.set_blocking(false);
         Ok(())
     }
+
+    /// Upload a mix of files and folders into `remote_dir`, keeping each
+    /// top-level name. Progress covers the combined size of all paths.
+    pub fn upload_paths<F>(
+        &self,
+        local_paths: &[String],
+        remote_dir: &str,
+        mut progress_callback: F,
+    ) -> Result<(), TransferError>
+    where
+        F: FnMut(u64, u64, &str), // (transferred, total, current_file)
+    {
+        let sizes: Vec<u64> = local_paths.iter().map(|p| local_tree_size(p)).collect();
+        let total_size: u64 = sizes.iter().sum();
+        let mut completed: u64 = 0;
+
+        for (local_path, size) in local_paths.iter().zip(sizes) {
+            if *self.cancelled.lock() {
+                return Err(TransferError::Cancelled);
+            }
+
+            let local = Path::new(local_path);
+            let name = local
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .ok_or_else(|| TransferError::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid path: {}", local_path),
+                )))?;
+
+            if local.is_dir() {
+                self.upload_folder(local_path, remote_dir, |transferred, _total, file| {
+                    progress_callback(completed + transferred, total_size, file);
+                })?;
+            } else {
+                let remote_path = Path::new(remote_dir).join(&name);
+                self.upload(local_path, &remote_path.to_string_lossy(), false, |transferred, _total| {
+                    progress_callback(completed + transferred, total_size, &name);
+                })?;
+            }
+
+            completed += size;
+            progress_callback(completed, total_size, &name);
+        }
+
+        Ok(())
+    }
+}
+
+/// SHA-256 of a local file as lowercase hex
+fn local_sha256(path: &str) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 256 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
 }
@@ -1,11 +1,17 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum AuthMethod {
     Password { password: String },
-    PublicKey { private_key_path: String, passphrase: Option<String> },
+    PublicKey {
+        private_key_path: String,
+        passphrase: Option<String>,
+        /// OpenSSH certificate (`*-cert.pub`); found next to the key when not set
+        #[serde(default)]
+        certificate_path: Option<String>,
+    },
     Agent,
 }
 
@@ -18,6 +24,7 @@ impl AuthMethod {
         Self::PublicKey {
             private_key_path: private_key_path.into(),
             passphrase,
+            certificate_path: None,
         }
     }
 
@@ -40,6 +47,14 @@ pub fn get_default_key_paths() -> Vec<PathBuf> {
     paths
 }
 
+/// Certificate OpenSSH would pick up for a private key (`id_rsa` -> `id_rsa-cert.pub`)
+pub fn find_certificate(private_key_path: &Path) -> Option<PathBuf> {
+    let mut name = private_key_path.file_name()?.to_os_string();
+    name.push("-cert.pub");
+    let cert = private_key_path.with_file_name(name);
+    cert.exists().then_some(cert)
+}
+
 pub fn find_default_key() -> Option<PathBuf> {
     get_default_key_paths()
         .into_iter()
@@ -1,12 +1,26 @@
-use super::auth::AuthMethod;
-use parking_lot::Mutex;
-use ssh2::{Channel, Session, Sftp};
-use std::io::{Read, Write};
-use std::net::TcpStream;
-use std::path::Path;
+use super::auth::{find_certificate, AuthMethod};
+use super::disconnect::{self, SshDisconnect};
+use crate::proxy::{self, ProxyConfig};
+use crate::util::{host_port, unbracket_host};
+use parking_lot::{Mutex, MutexGuard};
+use serde::{Deserialize, Serialize};
+use ssh2::{Channel, ErrorCode, Session, Sftp};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 
+/// How long the TCP connection (and proxy answer) may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// How long the server may take to send its banner and finish the key
+/// exchange once the TCP connection is up
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(20);
+
+// libssh2 error codes for a blocking call that ran past the session timeout
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+const LIBSSH2_ERROR_SOCKET_TIMEOUT: i32 = -30;
+
 #[derive(Error, Debug)]
 pub enum SshError {
     #[error("Connection failed: {0}")]
@@ -21,6 +35,39 @@ pub enum SshError {
     Io(#[from] std::io::Error),
     #[error("SSH2 error: {0}")]
     Ssh2(#[from] ssh2::Error),
+    #[error("Disconnected by server: {0}")]
+    Disconnected(SshDisconnect),
+    #[error("Timed out: {0}")]
+    Timeout(String),
+}
+
+/// Where `SshClient::connect` has got to, sent as
+/// `ssh-connect-progress-{connect_id}`
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SshConnectStage {
+    TcpConnected,
+    /// Banner and key exchange
+    SshHandshake,
+    Authenticating,
+}
+
+/// Banners presented by the server while connecting
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SshBanner {
+    /// Server identification string sent during the handshake (e.g. "SSH-2.0-OpenSSH_9.6")
+    pub server_id: Option<String>,
+    /// Pre-authentication banner message (legal/ops notices), if the server sent one
+    pub message: Option<String>,
+}
+
+/// Authentication methods a server offers, from `SshClient::probe_auth`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SshAuthMethods {
+    /// As named by the server, e.g. "publickey", "password", "keyboard-interactive"
+    pub methods: Vec<String>,
+    /// The server let the user in without authenticating
+    pub none_accepted: bool,
 }
 
 pub struct SshClient {
@@ -28,28 +75,138 @@ pub struct SshClient {
     host: String,
     port: u16,
     username: String,
+    banner: SshBanner,
 }
 
 // Safety: Session is wrapped in Mutex for thread-safe access
 unsafe impl Sync for SshClient {}
 unsafe impl Send for SshClient {}
 
+impl Drop for SshClient {
+    // A disconnect nobody picked up would otherwise stay in the map
+    fn drop(&mut self) {
+        disconnect::take(&self.session.lock());
+    }
+}
+
+/// Run the banner and key exchange, giving up after `HANDSHAKE_TIMEOUT` on
+/// a server that accepted the connection but doesn't negotiate
+fn handshake(session: &mut Session, addr: &str) -> Result<(), SshError> {
+    session.set_timeout(HANDSHAKE_TIMEOUT.as_millis() as u32);
+    let result = session.handshake();
+    session.set_timeout(0);
+    result.map_err(|e| match e.code() {
+        ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT | LIBSSH2_ERROR_SOCKET_TIMEOUT) => SshError::Timeout(format!(
+            "SSH handshake with {} took longer than {}s",
+            addr,
+            HANDSHAKE_TIMEOUT.as_secs()
+        )),
+        _ => e.into(),
+    })
+}
+
 impl SshClient {
+    /// Connect and authenticate, reporting each stage to `progress`
     pub fn connect(
         host: &str,
         port: u16,
         username: &str,
         auth: &AuthMethod,
+        proxy: Option<&ProxyConfig>,
+        progress: Option<&dyn Fn(SshConnectStage)>,
     ) -> Result<Self, SshError> {
-        let addr = format!("{}:{}", host, port);
-        let tcp = TcpStream::connect(&addr)
-            .map_err(|e| SshError::Connection(format!("Failed to connect to {}: {}", addr, e)))?;
+        let report = |stage| {
+            if let Some(progress) = progress {
+                progress(stage);
+            }
+        };
+        let addr = host_port(host, port);
+        let mut session = Self::open_session(host, port, proxy)?;
+        report(SshConnectStage::TcpConnected);
+
+        // Prefer the server's own explanation when it hung up on us
+        let banner = Self::handshake_and_authenticate(&mut session, &addr, username, auth, &report)
+            .map_err(|e| disconnect::take(&session).map_or(e, SshError::Disconnected))?;
+
+        // Keep session in blocking mode initially - we'll switch channels to non-blocking after setup
+        Ok(Self {
+            session: Arc::new(Mutex::new(session)),
+            host: host.to_string(),
+            port,
+            username: username.to_string(),
+            banner,
+        })
+    }
+
+    /// Ask the server which authentication methods it accepts for
+    /// `username`, without authenticating, then disconnect
+    pub fn probe_auth(
+        host: &str,
+        port: u16,
+        username: &str,
+        proxy: Option<&ProxyConfig>,
+    ) -> Result<SshAuthMethods, SshError> {
+        let addr = host_port(host, port);
+        let mut session = Self::open_session(host, port, proxy)?;
+
+        let result = handshake(&mut session, &addr).and_then(|_| {
+            match session.auth_methods(username) {
+                Ok(methods) => Ok(SshAuthMethods {
+                    methods: methods
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|m| !m.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                    none_accepted: false,
+                }),
+                // The "none" request that lists the methods succeeded
+                Err(_) if session.authenticated() => Ok(SshAuthMethods {
+                    methods: Vec::new(),
+                    none_accepted: true,
+                }),
+                Err(e) => Err(e.into()),
+            }
+        });
+        let result = result.map_err(|e| disconnect::take(&session).map_or(e, SshError::Disconnected));
+
+        let _ = session.disconnect(None, "Authentication probe", None);
+        disconnect::take(&session);
+        result
+    }
+
+    /// A session on a new TCP connection to `host`, not yet handshaken
+    fn open_session(host: &str, port: u16, proxy: Option<&ProxyConfig>) -> Result<Session, SshError> {
+        let addr = host_port(host, port);
+        let tcp = proxy::connect_timeout(host, port, proxy, CONNECT_TIMEOUT).map_err(|e| match e.kind() {
+            io::ErrorKind::TimedOut => SshError::Timeout(format!(
+                "No connection to {} within {}s",
+                addr,
+                CONNECT_TIMEOUT.as_secs()
+            )),
+            _ => SshError::Connection(format!("Failed to connect to {}: {}", addr, e)),
+        })?;
 
         tcp.set_nonblocking(false)?;
 
         let mut session = Session::new()?;
+        disconnect::watch(&session);
         session.set_tcp_stream(tcp);
-        session.handshake()?;
+        Ok(session)
+    }
+
+    fn handshake_and_authenticate(
+        session: &mut Session,
+        addr: &str,
+        username: &str,
+        auth: &AuthMethod,
+        report: &dyn Fn(SshConnectStage),
+    ) -> Result<SshBanner, SshError> {
+        report(SshConnectStage::SshHandshake);
+        handshake(session, addr)?;
+        report(SshConnectStage::Authenticating);
+
+        let server_id = session.banner().map(|b| b.trim_end().to_string());
 
         // Authenticate
         match auth {
@@ -61,12 +218,18 @@ impl SshClient {
             AuthMethod::PublicKey {
                 private_key_path,
                 passphrase,
+                certificate_path,
             } => {
                 let key_path = Path::new(private_key_path);
+                // A signed certificate is offered in place of the public key
+                let cert_path = match certificate_path {
+                    Some(path) => Some(PathBuf::from(path)),
+                    None => find_certificate(key_path),
+                };
                 session
                     .userauth_pubkey_file(
                         username,
-                        None,
+                        cert_path.as_deref(),
                         key_path,
                         passphrase.as_deref(),
                     )
@@ -99,23 +262,28 @@ impl SshClient {
             return Err(SshError::Authentication("Authentication failed".to_string()));
         }
 
-        // Keep session in blocking mode initially - we'll switch channels to non-blocking after setup
-        Ok(Self {
-            session: Arc::new(Mutex::new(session)),
-            host: host.to_string(),
-            port,
-            username: username.to_string(),
-        })
+        // The userauth banner only becomes available after an authentication attempt
+        let message = session
+            .userauth_banner()
+            .ok()
+            .flatten()
+            .map(|b| b.to_string())
+            .filter(|b| !b.trim().is_empty());
+
+        Ok(SshBanner { server_id, message })
     }
 
-    pub fn open_channel(&self) -> Result<SshChannel, SshError> {
+    /// Open an interactive shell on a pty with the given `TERM`
+    pub fn open_channel(&self, term: &str) -> Result<SshChannel, SshError> {
         let session = self.session.lock();
 
         // Ensure blocking mode for channel setup
         session.set_blocking(true);
 
         let mut channel = session.channel_session()?;
-        channel.request_pty("xterm-256color", None, Some((80, 24, 0, 0)))?;
+        // Advertise truecolor; servers whose AcceptEnv doesn't allow it refuse, which is fine
+        let _ = channel.setenv("COLORTERM", "truecolor");
+        channel.request_pty(term, None, Some((80, 24, 0, 0)))?;
         channel.shell()?;
 
         // Switch to non-blocking mode for I/O operations
@@ -123,6 +291,7 @@ impl SshClient {
 
         Ok(SshChannel {
             channel: Arc::new(Mutex::new(channel)),
+            session: self.session.clone(),
         })
     }
 
@@ -143,6 +312,52 @@ impl SshClient {
         })
     }
 
+    /// Open a direct-tcpip channel to `host:port` as seen from the server (like `ssh -L`).
+    /// The tunnel starts in blocking mode.
+    pub fn open_direct_tcpip(&self, host: &str, port: u16) -> Result<SshTunnel, SshError> {
+        let session = self.session.lock();
+        session.set_blocking(true);
+
+        let channel = session
+            .channel_direct_tcpip(unbracket_host(host), port, None)
+            .map_err(|e| SshError::Channel(format!("Failed to open tunnel to {}: {}", host_port(host, port), e)))?;
+
+        Ok(SshTunnel {
+            channel,
+            session: self.session.clone(),
+        })
+    }
+
+    /// Run `command` on a channel of its own and return its output. Fails
+    /// if it exits non-zero. The session is held until it finishes, so
+    /// keep it quick.
+    pub fn exec(&self, command: &str) -> Result<String, SshError> {
+        let session = self.session.lock();
+        session.set_blocking(true);
+
+        let result = (|| -> Result<String, SshError> {
+            let mut channel = session.channel_session()?;
+            channel.exec(command)?;
+            let mut output = String::new();
+            channel.read_to_string(&mut output)?;
+            let mut stderr = String::new();
+            let _ = channel.stderr().read_to_string(&mut stderr);
+            channel.wait_close()?;
+            match channel.exit_status()? {
+                0 => Ok(output),
+                code => Err(SshError::Channel(format!(
+                    "{} exited with {}: {}",
+                    command,
+                    code,
+                    stderr.trim()
+                ))),
+            }
+        })();
+
+        session.set_blocking(false);
+        result
+    }
+
     pub fn host(&self) -> &str {
         &self.host
     }
@@ -154,10 +369,32 @@ impl SshClient {
     pub fn username(&self) -> &str {
         &self.username
     }
+
+    pub fn banner(&self) -> &SshBanner {
+        &self.banner
+    }
+
+    /// Reason the server gave for closing the connection, once it has
+    pub fn take_disconnect(&self) -> Option<SshDisconnect> {
+        disconnect::take(&self.session.lock())
+    }
+
+    /// Lock the session for a non-blocking read on one of its channels.
+    /// SFTP sharing the session switches it to blocking mode for each
+    /// operation; holding the lock waits those out instead of blocking.
+    pub fn lock_nonblocking(&self) -> MutexGuard<'_, Session> {
+        let session = self.session.lock();
+        session.set_blocking(false);
+        session
+    }
 }
 
+/// An interactive shell channel. Every operation holds the session lock,
+/// as `SshClient::lock_nonblocking` does for reads, so SFTP sharing the
+/// session never runs alongside it or leaves it in blocking mode.
 pub struct SshChannel {
     channel: Arc<Mutex<Channel>>,
+    session: Arc<Mutex<Session>>,
 }
 
 // Safety: Channel is wrapped in Mutex for thread-safe access
@@ -165,15 +402,22 @@ unsafe impl Sync for SshChannel {}
 unsafe impl Send for SshChannel {}
 
 impl SshChannel {
-    pub fn write(&self, data: &[u8]) -> Result<usize, SshError> {
-        let mut channel = self.channel.lock();
+    /// Lock the session in non-blocking mode, then the channel
+    fn lock(&self) -> (MutexGuard<'_, Session>, MutexGuard<'_, Channel>) {
+        let session = self.session.lock();
+        session.set_blocking(false);
+        (session, self.channel.lock())
+    }
 
-        // Handle non-blocking write with retry
+    pub fn write(&self, data: &[u8]) -> Result<usize, SshError> {
+        // Handle non-blocking write with retry. The locks are let go
+        // between tries so reads and SFTP aren't held up.
         let mut total_written = 0;
         let mut remaining = data;
 
         while !remaining.is_empty() {
-            match channel.write(remaining) {
+            let result = self.lock().1.write(remaining);
+            match result {
                 Ok(0) => break,
                 Ok(n) => {
                     total_written += n;
@@ -190,7 +434,8 @@ impl SshChannel {
 
         // Flush with retry for non-blocking mode
         loop {
-            match channel.flush() {
+            let result = self.lock().1.flush();
+            match result {
                 Ok(_) => break,
                 Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                     std::thread::sleep(std::time::Duration::from_millis(1));
@@ -204,28 +449,88 @@ impl SshChannel {
     }
 
     pub fn read(&self, buf: &mut [u8]) -> Result<usize, SshError> {
-        let mut channel = self.channel.lock();
+        let (_session, mut channel) = self.lock();
         channel.read(buf).map_err(SshError::from)
     }
 
     pub fn resize(&self, cols: u32, rows: u32) -> Result<(), SshError> {
-        let mut channel = self.channel.lock();
+        let (_session, mut channel) = self.lock();
         channel.request_pty_size(cols, rows, None, None)?;
         Ok(())
     }
 
     pub fn close(&self) -> Result<(), SshError> {
-        let mut channel = self.channel.lock();
+        let (_session, mut channel) = self.lock();
         channel.send_eof()?;
         channel.wait_close()?;
         Ok(())
     }
 
+    /// Whether the server has closed its side of the channel
+    pub fn eof(&self) -> bool {
+        self.lock().1.eof()
+    }
+
+    /// Exit status the server reported for the remote command, available
+    /// once the channel is at EOF
+    pub fn exit_status(&self) -> Option<i32> {
+        let (_session, channel) = self.lock();
+        if channel.eof() {
+            channel.exit_status().ok()
+        } else {
+            None
+        }
+    }
+
+    /// The channel for `SessionReader`, which locks the session through
+    /// `SshClient::lock_nonblocking` before each read
     pub fn get_reader(&self) -> Arc<Mutex<Channel>> {
         self.channel.clone()
     }
 }
 
+/// A forwarded TCP connection over SSH. Keeps the session alive while open.
+pub struct SshTunnel {
+    channel: Channel,
+    session: Arc<Mutex<Session>>,
+}
+
+// Safety: the tunnel is owned and driven by a single thread at a time
+unsafe impl Send for SshTunnel {}
+
+impl SshTunnel {
+    pub fn set_blocking(&self, blocking: bool) {
+        self.session.lock().set_blocking(blocking);
+    }
+
+    pub fn eof(&self) -> bool {
+        self.channel.eof()
+    }
+}
+
+impl Read for SshTunnel {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.channel.read(buf)
+    }
+}
+
+impl Write for SshTunnel {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.channel.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.channel.flush()
+    }
+}
+
+impl Drop for SshTunnel {
+    fn drop(&mut self) {
+        self.set_blocking(true);
+        let _ = self.channel.close();
+    }
+}
+
 pub struct SftpSession {
     sftp: Arc<Mutex<Sftp>>,
     session: Arc<Mutex<Session>>,
@@ -0,0 +1,416 @@
+//! OpenSSH client config (`~/.ssh/config`) reading, for importing hosts.
+//! Only what a connection profile needs is looked at. As in ssh, the first
+//! value found for an option wins, across every Host block matching a host.
+
+use crate::storage::connections::{ConnectionProfile, StoredAuthMethod};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Include depth at which includes stop being followed
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// A concrete host from the config, with its effective settings
+#[derive(Debug, Clone)]
+pub struct SshConfigHost {
+    /// Name after `Host`
+    pub alias: String,
+    /// `HostName`, or the alias
+    pub host: String,
+    pub port: u16,
+    /// `User`, or the local user name
+    pub user: String,
+    pub identity_file: Option<String>,
+    pub certificate_file: Option<String>,
+}
+
+/// A `Host` block (or the options before the first one, as `*`)
+struct Block {
+    patterns: Vec<String>,
+    /// Lowercased keyword and its first argument, in file order
+    options: Vec<(String, String)>,
+}
+
+impl Block {
+    fn matches(&self, host: &str) -> bool {
+        let mut matched = false;
+        for pattern in &self.patterns {
+            match pattern.strip_prefix('!') {
+                Some(negated) if wildcard_match(negated, host) => return false,
+                Some(_) => {}
+                None => matched |= wildcard_match(pattern, host),
+            }
+        }
+        matched
+    }
+}
+
+/// `~/.ssh/config`
+pub fn default_config_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ssh").join("config"))
+}
+
+/// Hosts named in the config at `path`, in the order they appear. Wildcard
+/// patterns only contribute settings; `Match` blocks are skipped.
+pub fn read_ssh_config(path: &Path) -> Result<Vec<SshConfigHost>, String> {
+    let mut blocks = vec![Block {
+        patterns: vec!["*".to_string()],
+        options: Vec::new(),
+    }];
+    parse_file(path, &mut blocks, 0)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+    let mut aliases: Vec<String> = Vec::new();
+    for block in &blocks[1..] {
+        for pattern in &block.patterns {
+            let concrete = !pattern.contains(['*', '?', '!']);
+            if concrete && !aliases.iter().any(|alias| alias.eq_ignore_ascii_case(pattern)) {
+                aliases.push(pattern.clone());
+            }
+        }
+    }
+
+    let local_user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default();
+    let hosts = aliases
+        .into_iter()
+        .map(|alias| {
+            let mut options: HashMap<&str, &str> = HashMap::new();
+            for block in blocks.iter().filter(|block| block.matches(&alias)) {
+                for (keyword, value) in &block.options {
+                    options.entry(keyword.as_str()).or_insert(value.as_str());
+                }
+            }
+            SshConfigHost {
+                host: options
+                    .get("hostname")
+                    .map(|host| host.replace("%h", &alias))
+                    .unwrap_or_else(|| alias.clone()),
+                port: options.get("port").and_then(|port| port.parse().ok()).unwrap_or(22),
+                user: options
+                    .get("user")
+                    .map(|user| user.to_string())
+                    .unwrap_or_else(|| local_user.clone()),
+                identity_file: options
+                    .get("identityfile")
+                    .filter(|file| !file.eq_ignore_ascii_case("none"))
+                    .map(|file| expand_path(file, &local_user)),
+                certificate_file: options
+                    .get("certificatefile")
+                    .filter(|file| !file.eq_ignore_ascii_case("none"))
+                    .map(|file| expand_path(file, &local_user)),
+                alias,
+            }
+        })
+        .collect();
+    Ok(hosts)
+}
+
+/// `hosts` as SSH profiles, leaving out those with the host, user and port
+/// of a profile in `saved` or of one earlier in the list. Hosts with an
+/// IdentityFile use that key, others the SSH agent.
+pub fn new_profiles(hosts: Vec<SshConfigHost>, saved: &[ConnectionProfile]) -> Vec<ConnectionProfile> {
+    let mut seen: HashSet<_> = saved.iter().filter_map(ConnectionProfile::ssh_target).collect();
+    hosts
+        .into_iter()
+        .map(|host| {
+            let auth_method = match host.identity_file {
+                Some(private_key_path) => StoredAuthMethod::PublicKey {
+                    private_key_path,
+                    certificate_path: host.certificate_file,
+                },
+                None => StoredAuthMethod::Agent,
+            };
+            ConnectionProfile::new_ssh(host.alias, host.host, host.port, host.user, auth_method)
+        })
+        .filter(|profile| profile.ssh_target().is_some_and(|target| seen.insert(target)))
+        .collect()
+}
+
+fn parse_file(path: &Path, blocks: &mut Vec<Block>, depth: usize) -> std::io::Result<()> {
+    let content = fs::read_to_string(path)?;
+    // Options after a Match line belong to it until the next Host
+    let mut in_match = false;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (keyword, args) = split_line(line);
+        let keyword = keyword.to_lowercase();
+
+        match keyword.as_str() {
+            "host" => {
+                in_match = false;
+                blocks.push(Block {
+                    patterns: args,
+                    options: Vec::new(),
+                });
+            }
+            "match" => in_match = true,
+            "include" if !in_match && depth < MAX_INCLUDE_DEPTH => {
+                for pattern in &args {
+                    for included in include_paths(pattern) {
+                        // A missing or unreadable include is skipped, as ssh does
+                        let _ = parse_file(&included, blocks, depth + 1);
+                    }
+                }
+            }
+            _ if in_match => {}
+            _ => {
+                if let (Some(value), Some(block)) = (args.into_iter().next(), blocks.last_mut()) {
+                    block.options.push((keyword, value));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Keyword and arguments of a config line. Keywords may be followed by `=`;
+/// arguments may be double-quoted.
+fn split_line(line: &str) -> (&str, Vec<String>) {
+    let end = line.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(line.len());
+    let (keyword, rest) = line.split_at(end);
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in rest.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    (keyword, args)
+}
+
+/// Files an `Include` argument names. Relative paths are under `~/.ssh`;
+/// a wildcard is allowed in the file name.
+fn include_paths(pattern: &str) -> Vec<PathBuf> {
+    let expanded = PathBuf::from(expand_path(pattern, ""));
+    let path = if expanded.is_absolute() {
+        expanded
+    } else {
+        match dirs::home_dir() {
+            Some(home) => home.join(".ssh").join(expanded),
+            None => return Vec::new(),
+        }
+    };
+
+    let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        return vec![path];
+    }
+    let Some(dir) = path.parent() else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| wildcard_match(&name, &entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path())
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Expand a leading `~` and the `%d` (home) and `%u` (local user) tokens
+fn expand_path(path: &str, local_user: &str) -> String {
+    let home = dirs::home_dir()
+        .map(|home| home.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let path = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home, rest),
+        _ => path.to_string(),
+    };
+    path.replace("%d", &home).replace("%u", local_user)
+}
+
+/// Case-insensitive match against an ssh pattern with `*` and `?`
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::connections::ConnectionType;
+
+    /// Config at `dir/config` with `Include dir/conf.d/*.conf` first
+    fn write_config(dir: &Path) -> PathBuf {
+        let conf_d = dir.join("conf.d");
+        fs::create_dir(&conf_d).unwrap();
+        fs::write(conf_d.join("a.conf"), "Host included\n    HostName inc.example.com\n").unwrap();
+        fs::write(conf_d.join("b.conf"), "Host second-include\n").unwrap();
+        fs::write(conf_d.join("c.txt"), "Host not-included\n").unwrap();
+
+        let config = format!(
+            r#"Include "{}/*.conf"
+# Options under Match don't apply to any Host
+Match host second-include
+    User matched
+    Port 1
+Host web web-alias
+    HostName %h.example.com
+    Port 2222
+Host *.internal !bastion.internal
+    User ops
+    IdentityFile ~/.ssh/internal_%u
+Host db.internal bastion.internal
+    Port=2200
+    User dbadmin
+    IdentityFile "%d/keys/my key"
+Host nokey
+    IdentityFile none
+    CertificateFile none
+Host *
+    User fallback
+    Port 2022
+"#,
+            conf_d.display()
+        );
+        let path = dir.join("config");
+        fs::write(&path, config).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_effective_host_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let hosts = read_ssh_config(&write_config(dir.path())).unwrap();
+        let home = dirs::home_dir().unwrap().to_string_lossy().to_string();
+        let local_user = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_default();
+        let internal_key = format!("{}/.ssh/internal_{}", home, local_user);
+        let db_key = format!("{}/keys/my key", home);
+
+        #[rustfmt::skip]
+        let expected: &[(&str, &str, u16, &str, Option<&str>)] = &[
+            ("included", "inc.example.com", 2022, "fallback", None),
+            ("second-include", "second-include", 2022, "fallback", None),
+            ("web", "web.example.com", 2222, "fallback", None),
+            ("web-alias", "web-alias.example.com", 2222, "fallback", None),
+            ("db.internal", "db.internal", 2200, "ops", Some(&internal_key)),
+            ("bastion.internal", "bastion.internal", 2200, "dbadmin", Some(&db_key)),
+            ("nokey", "nokey", 2022, "fallback", None),
+        ];
+        assert_eq!(hosts.len(), expected.len());
+        for (host, &(alias, hostname, port, user, identity_file)) in hosts.iter().zip(expected) {
+            assert_eq!(host.alias, alias);
+            assert_eq!(host.host, hostname, "{}", alias);
+            assert_eq!(host.port, port, "{}", alias);
+            assert_eq!(host.user, user, "{}", alias);
+            assert_eq!(host.identity_file.as_deref(), identity_file, "{}", alias);
+            assert_eq!(host.certificate_file, None, "{}", alias);
+        }
+    }
+
+    #[test]
+    fn splits_config_lines() {
+        #[rustfmt::skip]
+        let cases: &[(&str, &str, &[&str])] = &[
+            ("Port 22", "Port", &["22"]),
+            ("Port=22", "Port", &["22"]),
+            ("Port = 22", "Port", &["22"]),
+            ("Host a  b\tc", "Host", &["a", "b", "c"]),
+            (r#"IdentityFile "~/my key""#, "IdentityFile", &["~/my key"]),
+            (r#"Include "a b"/c d"#, "Include", &["a b/c", "d"]),
+            ("Compression", "Compression", &[]),
+        ];
+        for &(line, keyword, args) in cases {
+            assert_eq!(split_line(line), (keyword, args.iter().map(|a| a.to_string()).collect()), "{}", line);
+        }
+    }
+
+    #[test]
+    fn matches_host_patterns() {
+        #[rustfmt::skip]
+        let cases: &[(&[&str], &str, bool)] = &[
+            (&["*"], "anything", true),
+            (&["web?"], "web1", true),
+            (&["web?"], "web10", false),
+            (&["*.EXAMPLE.com"], "db.example.COM", true),
+            (&["*.example.com", "!db.example.com"], "db.example.com", false),
+            (&["!db.example.com", "*.example.com"], "web.example.com", true),
+            (&["!db.example.com"], "web.example.com", false),
+        ];
+        for &(patterns, host, matches) in cases {
+            let block = Block {
+                patterns: patterns.iter().map(|p| p.to_string()).collect(),
+                options: Vec::new(),
+            };
+            assert_eq!(block.matches(host), matches, "{:?} against {}", patterns, host);
+        }
+    }
+
+    #[test]
+    fn leaves_out_hosts_already_saved() {
+        let host = |alias: &str, host: &str, user: &str| SshConfigHost {
+            alias: alias.to_string(),
+            host: host.to_string(),
+            port: 22,
+            user: user.to_string(),
+            identity_file: None,
+            certificate_file: None,
+        };
+        let saved = [ConnectionProfile::new_ssh(
+            "Web".to_string(),
+            "web.example.com".to_string(),
+            22,
+            "deploy".to_string(),
+            StoredAuthMethod::Password,
+        )];
+        let hosts = vec![
+            host("web", "WEB.example.com", "deploy"),
+            host("web-root", "web.example.com", "root"),
+            host("db", "db.example.com", "admin"),
+            host("db-again", "db.example.com", "admin"),
+        ];
+
+        let profiles = new_profiles(hosts, &saved);
+        let names: Vec<_> = profiles.iter().map(|profile| profile.name.as_str()).collect();
+        assert_eq!(names, ["web-root", "db"]);
+        assert!(matches!(
+            profiles[0].connection_type,
+            ConnectionType::Ssh {
+                auth_method: StoredAuthMethod::Agent,
+                ..
+            }
+        ));
+    }
+}
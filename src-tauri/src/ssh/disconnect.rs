@@ -0,0 +1,113 @@
+//! Capture of SSH_MSG_DISCONNECT. libssh2 only reports "socket disconnect"
+//! when the server hangs up; the reason and message the server sent are
+//! delivered to a session callback, which ssh2 doesn't expose.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use ssh2::Session;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void};
+use std::fmt;
+use std::sync::OnceLock;
+
+const LIBSSH2_CALLBACK_DISCONNECT: c_int = 2;
+
+extern "C" {
+    fn libssh2_session_callback_set(session: *mut c_void, cbtype: c_int, callback: *mut c_void) -> *mut c_void;
+}
+
+/// Why the server closed the connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SshDisconnect {
+    /// SSH_DISCONNECT_* reason code (RFC 4253 section 11.1)
+    pub reason: u32,
+    /// Message sent by the server, may be empty
+    pub message: String,
+}
+
+impl SshDisconnect {
+    pub fn reason_text(&self) -> &'static str {
+        match self.reason {
+            1 => "host not allowed to connect",
+            2 => "protocol error",
+            3 => "key exchange failed",
+            5 => "MAC error",
+            6 => "compression error",
+            7 => "service not available",
+            8 => "protocol version not supported",
+            9 => "host key not verifiable",
+            10 => "connection lost",
+            11 => "closed by application",
+            12 => "too many connections",
+            13 => "authentication cancelled by user",
+            14 => "no more authentication methods available",
+            15 => "illegal user name",
+            _ => "unknown reason",
+        }
+    }
+}
+
+impl fmt::Display for SshDisconnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = self.message.trim();
+        if message.is_empty() {
+            write!(f, "{}", self.reason_text())
+        } else {
+            write!(f, "{} ({})", message, self.reason_text())
+        }
+    }
+}
+
+/// Disconnects received but not yet picked up, by libssh2 session pointer.
+/// `SshClient` takes its entry when dropped.
+fn received() -> &'static Mutex<HashMap<usize, SshDisconnect>> {
+    static RECEIVED: OnceLock<Mutex<HashMap<usize, SshDisconnect>>> = OnceLock::new();
+    RECEIVED.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn session_key(session: &Session) -> usize {
+    &*session.raw() as *const _ as usize
+}
+
+unsafe extern "C" fn on_disconnect(
+    session: *mut c_void,
+    reason: c_int,
+    message: *const c_char,
+    message_len: c_int,
+    _language: *const c_char,
+    _language_len: c_int,
+    _abstract: *mut *mut c_void,
+) {
+    let message = if message.is_null() || message_len <= 0 {
+        String::new()
+    } else {
+        let bytes = std::slice::from_raw_parts(message as *const u8, message_len as usize);
+        String::from_utf8_lossy(bytes).into_owned()
+    };
+    received().lock().insert(
+        session as usize,
+        SshDisconnect {
+            reason: reason as u32,
+            message,
+        },
+    );
+}
+
+/// Record disconnect messages for `session`
+pub fn watch(session: &Session) {
+    let key = session_key(session);
+    // A previous session may have lived at the same address
+    received().lock().remove(&key);
+
+    type DisconnectFn =
+        unsafe extern "C" fn(*mut c_void, c_int, *const c_char, c_int, *const c_char, c_int, *mut *mut c_void);
+    let callback: DisconnectFn = on_disconnect;
+    unsafe {
+        libssh2_session_callback_set(key as *mut c_void, LIBSSH2_CALLBACK_DISCONNECT, callback as *mut c_void);
+    }
+}
+
+/// The disconnect the server sent on `session`, if any
+pub fn take(session: &Session) -> Option<SshDisconnect> {
+    received().lock().remove(&session_key(session))
+}
@@ -1,5 +1,7 @@
 pub mod auth;
 pub mod client;
+pub mod config;
+pub mod disconnect;
 
-pub use client::SshClient;
+pub use client::{SshAuthMethods, SshBanner, SshClient, SshConnectStage, SshTunnel};
 pub use auth::AuthMethod;
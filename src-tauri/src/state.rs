@@ -1,14 +1,23 @@
+use crate::storage::ConnectionStorage;
 use crate::terminal::TerminalManager;
+use parking_lot::Mutex;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 pub struct AppState {
     pub terminal_manager: Arc<TerminalManager>,
+    pub transfers: Arc<TransferRegistry>,
+    /// Saved connections; reads are served from memory until the file changes
+    pub connections: Arc<ConnectionStorage>,
 }
 
 impl AppState {
     pub fn new() -> Self {
         Self {
             terminal_manager: Arc::new(TerminalManager::new()),
+            transfers: Arc::new(TransferRegistry::default()),
+            connections: Arc::new(ConnectionStorage::default()),
         }
     }
 }
@@ -18,3 +27,64 @@ impl Default for AppState {
         Self::new()
     }
 }
+
+struct ActiveTransfer {
+    /// SFTP/FTP session the transfer runs on
+    session_id: String,
+    cancel_flag: Arc<Mutex<bool>>,
+}
+
+/// The SFTP/FTP transfers currently running, by transfer ID
+#[derive(Default)]
+pub struct TransferRegistry {
+    active: Mutex<HashMap<String, ActiveTransfer>>,
+}
+
+impl TransferRegistry {
+    pub fn register(&self, transfer_id: &str, session_id: &str, cancel_flag: Arc<Mutex<bool>>) {
+        self.active.lock().insert(
+            transfer_id.to_string(),
+            ActiveTransfer {
+                session_id: session_id.to_string(),
+                cancel_flag,
+            },
+        );
+    }
+
+    /// Called by the transfer thread once it has stopped, whatever the outcome
+    pub fn finish(&self, transfer_id: &str) {
+        self.active.lock().remove(transfer_id);
+    }
+
+    pub fn cancel_all(&self) {
+        for transfer in self.active.lock().values() {
+            *transfer.cancel_flag.lock() = true;
+        }
+    }
+
+    pub fn is_active(&self, transfer_id: &str) -> bool {
+        self.active.lock().contains_key(transfer_id)
+    }
+
+    /// Number of transfers running on `session_id`
+    pub fn active_for(&self, session_id: &str) -> usize {
+        self.active
+            .lock()
+            .values()
+            .filter(|t| t.session_id == session_id)
+            .count()
+    }
+
+    /// Wait for running transfers to stop. Returns false if some are still
+    /// running after `timeout`.
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !self.active.lock().is_empty() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        true
+    }
+}
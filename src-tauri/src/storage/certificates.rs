@@ -0,0 +1,64 @@
+use super::connections::StorageError;
+use crate::util::host_port;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct CertificatesFile {
+    /// SHA-256 fingerprint trusted for each `host:port`. Keys without a
+    /// port are pins from older versions, which are no longer used.
+    pins: HashMap<String, String>,
+}
+
+/// Server certificates the user chose to trust (RDP), by host and port
+pub struct CertificatePins {
+    file_path: PathBuf,
+}
+
+impl CertificatePins {
+    pub fn new() -> Result<Self, StorageError> {
+        let config_dir = dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("openterm");
+
+        fs::create_dir_all(&config_dir)?;
+
+        Ok(Self {
+            file_path: config_dir.join("trusted_certificates.json"),
+        })
+    }
+
+    fn load(&self) -> Result<CertificatesFile, StorageError> {
+        if !self.file_path.exists() {
+            return Ok(CertificatesFile::default());
+        }
+        let content = fs::read_to_string(&self.file_path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn save(&self, data: &CertificatesFile) -> Result<(), StorageError> {
+        let json = serde_json::to_string_pretty(data)?;
+        fs::write(&self.file_path, json)?;
+        Ok(())
+    }
+
+    /// Fingerprint pinned for `host:port`, if any
+    pub fn get(&self, host: &str, port: u16) -> Result<Option<String>, StorageError> {
+        Ok(self.load()?.pins.get(&pin_key(host, port)).cloned())
+    }
+
+    /// Trust the certificate with `fingerprint` for `host:port`, replacing
+    /// any previous pin
+    pub fn trust(&self, host: &str, port: u16, fingerprint: &str) -> Result<(), StorageError> {
+        let mut data = self.load()?;
+        data.pins.insert(pin_key(host, port), fingerprint.to_string());
+        self.save(&data)
+    }
+}
+
+/// Servers on different ports of one host have certificates of their own
+fn pin_key(host: &str, port: u16) -> String {
+    host_port(&host.to_lowercase(), port)
+}
@@ -1,9 +1,18 @@
+use crate::rdp::{RdpCodec, RdpGateway, RdpKeyboard, RdpPerformanceFlags, RdpQuality, RdpSharedFolder};
 use crate::ssh::AuthMethod;
+use crate::vnc::{VncAuthType, VncColorDepth, VncEncoding};
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Deserializer, Serialize};
+use parking_lot::{Mutex, ReentrantMutex};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use super::crypto::{DerivedKey, Sealed};
+use super::keychain::{KeychainError, KeychainManager, SecretStore};
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -15,13 +24,98 @@ pub enum StorageError {
     Json(#[from] serde_json::Error),
     #[error("Connection not found: {0}")]
     NotFound(String),
+    #[error("Cannot change a {from} connection to {to}")]
+    TypeChange { from: &'static str, to: &'static str },
+    #[error("{0}")]
+    Export(String),
+    #[error("Wrong passphrase, or the file has been altered")]
+    WrongPassphrase,
+    #[error("Connection storage is locked; enter the master password")]
+    Locked,
+    #[error("{0}")]
+    Crypto(String),
+    #[error("{0}")]
+    Invalid(String),
+    #[error(
+        "connections.json is from a newer version of the app (format {found}, this one reads up to {supported}); \
+         it was left unchanged"
+    )]
+    NewerVersion { found: u32, supported: u32 },
+}
+
+/// Look and behavior of a connection's terminal tab, e.g. a red scheme and
+/// larger font for production hosts. Unset fields use the app settings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerminalSettings {
+    /// Name of one of the frontend's color schemes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_scheme: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font_family: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font_size: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor_style: Option<TerminalCursorStyle>,
+    /// Lines kept above the screen
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scrollback: Option<u32>,
+    /// Typed into the shell once the session opens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup_command: Option<String>,
+    /// CSS color of the tab, e.g. "#c62828"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tab_color: Option<String>,
+}
+
+impl TerminalSettings {
+    /// Largest scrollback accepted, in lines
+    pub const MAX_SCROLLBACK: u32 = 1_000_000;
+
+    /// Blank strings unset, sizes checked. `None` when nothing is set.
+    pub fn normalized(self) -> Result<Option<Self>, String> {
+        let text = |value: Option<String>| {
+            value
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        if self.font_size.is_some_and(|size| !(4.0..=96.0).contains(&size)) {
+            return Err("Font size must be between 4 and 96".to_string());
+        }
+        if self.scrollback.is_some_and(|lines| lines > Self::MAX_SCROLLBACK) {
+            return Err(format!("Scrollback can be at most {} lines", Self::MAX_SCROLLBACK));
+        }
+        let settings = Self {
+            color_scheme: text(self.color_scheme),
+            font_family: text(self.font_family),
+            font_size: self.font_size,
+            cursor_style: self.cursor_style,
+            scrollback: self.scrollback,
+            // Leading spaces may matter to the shell (e.g. keeping it out of history)
+            startup_command: self.startup_command.filter(|command| !command.trim().is_empty()),
+            tab_color: text(self.tab_color),
+        };
+        Ok((settings != Self::default()).then_some(settings))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TerminalCursorStyle {
+    Block,
+    Underline,
+    Bar,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "auth_type")]
 pub enum StoredAuthMethod {
     Password,
-    PublicKey { private_key_path: String },
+    PublicKey {
+        private_key_path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        certificate_path: Option<String>,
+    },
     Agent,
 }
 
@@ -46,6 +140,16 @@ pub enum ConnectionType {
     Vnc {
         host: String,
         port: u16,
+        #[serde(default)]
+        encoding: VncEncoding,
+        #[serde(default)]
+        quality: Option<u8>,
+        #[serde(default)]
+        color_depth: VncColorDepth,
+        #[serde(default)]
+        auth_type: VncAuthType,
+        #[serde(default)]
+        username: Option<String>,
     },
     #[serde(rename = "rdp")]
     Rdp {
@@ -53,23 +157,86 @@ pub enum ConnectionType {
         port: u16,
         username: String,
         domain: Option<String>,
+        /// When false the password is never stored and must be entered on every connect
+        #[serde(default = "default_true")]
+        save_password: bool,
+        #[serde(default)]
+        quality: RdpQuality,
+        /// Profiles from before keyboard settings use the local layout
+        #[serde(default = "RdpKeyboard::system")]
+        keyboard: RdpKeyboard,
+        /// Skip certificate validation for this server. Off unless the user
+        /// explicitly turns it on.
+        #[serde(default)]
+        accept_invalid_certs: bool,
+        /// RD Gateway to connect through; its password is kept in the keychain
+        #[serde(default)]
+        gateway: Option<RdpGateway>,
+        /// Reconnect dropped sessions without asking first
+        #[serde(default = "default_true")]
+        auto_reconnect: bool,
+        /// Overrides the quality preset's codec, for servers that misbehave
+        #[serde(default)]
+        codec: RdpCodec,
+        /// Local folder shared with the session as a drive
+        #[serde(default)]
+        shared_folder: Option<RdpSharedFolder>,
+        /// Visual features to keep, overriding the quality preset's choice
+        #[serde(default)]
+        custom_performance_flags: Option<RdpPerformanceFlags>,
     },
 }
 
-// Old format for backward compatibility
-#[derive(Debug, Clone, Deserialize)]
-struct OldConnectionProfile {
-    id: String,
-    name: String,
-    host: String,
-    port: u16,
-    username: String,
-    auth_method: StoredAuthMethod,
-    created_at: DateTime<Utc>,
-    last_used: Option<DateTime<Utc>>,
+impl ConnectionType {
+    /// Protocol name, as stored in `connection_type`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ConnectionType::Ssh { .. } => "ssh",
+            ConnectionType::Ftp { .. } => "ftp",
+            ConnectionType::Vnc { .. } => "vnc",
+            ConnectionType::Rdp { .. } => "rdp",
+        }
+    }
+
+    /// Whether a password is kept for this connection; prompt-only RDP
+    /// profiles and ones that don't log in with a password keep none
+    fn saves_password(&self) -> bool {
+        !matches!(
+            self,
+            ConnectionType::Rdp {
+                save_password: false,
+                ..
+            } | ConnectionType::Ssh {
+                auth_method: StoredAuthMethod::PublicKey { .. } | StoredAuthMethod::Agent,
+                ..
+            } | ConnectionType::Ftp { anonymous: true, .. }
+                | ConnectionType::Vnc {
+                    auth_type: VncAuthType::None,
+                    ..
+                }
+        )
+    }
+
+    fn has_gateway(&self) -> bool {
+        matches!(self, ConnectionType::Rdp { gateway: Some(_), .. })
+    }
+
+    fn uses_key(&self) -> bool {
+        matches!(
+            self,
+            ConnectionType::Ssh {
+                auth_method: StoredAuthMethod::PublicKey { .. },
+                ..
+            }
+        )
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectionProfile {
     pub id: String,
     pub name: String,
@@ -77,55 +244,47 @@ pub struct ConnectionProfile {
     pub connection_type: ConnectionType,
     pub created_at: DateTime<Utc>,
     pub last_used: Option<DateTime<Utc>>,
-}
-
-impl<'de> Deserialize<'de> for ConnectionProfile {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        #[derive(Deserialize)]
-        #[serde(untagged)]
-        enum ProfileFormat {
-            New(NewFormat),
-            Old(OldConnectionProfile),
-        }
-
-        #[derive(Deserialize)]
-        struct NewFormat {
-            id: String,
-            name: String,
-            #[serde(flatten)]
-            connection_type: ConnectionType,
-            created_at: DateTime<Utc>,
-            last_used: Option<DateTime<Utc>>,
-        }
-
-        match ProfileFormat::deserialize(deserializer)? {
-            ProfileFormat::New(new) => Ok(ConnectionProfile {
-                id: new.id,
-                name: new.name,
-                connection_type: new.connection_type,
-                created_at: new.created_at,
-                last_used: new.last_used,
-            }),
-            ProfileFormat::Old(old) => {
-                // Convert old format to new format (assume SSH)
-                Ok(ConnectionProfile {
-                    id: old.id,
-                    name: old.name,
-                    connection_type: ConnectionType::Ssh {
-                        host: old.host,
-                        port: old.port,
-                        username: old.username,
-                        auth_method: old.auth_method,
-                    },
-                    created_at: old.created_at,
-                    last_used: old.last_used,
-                })
-            }
-        }
-    }
+    /// Where SFTP/FTP downloads from this connection go unless the user
+    /// picks another folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_download_dir: Option<String>,
+    /// Absolute remote folder SFTP/FTP browsers open in, instead of home
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_remote_path: Option<String>,
+    /// Absolute local folder the local pane opens in next to the browser
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_local_path: Option<String>,
+    /// Close SSH, RDP and VNC sessions opened from this profile after this
+    /// long without activity. Off when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_secs: Option<u64>,
+    /// Free-form labels for filtering, e.g. "prod" or "db"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Folder the profile is listed under; ungrouped profiles come last
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Listed before everything else
+    #[serde(default)]
+    pub favorite: bool,
+    /// Position among the favorites, set by `reorder`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_order: Option<u32>,
+    /// Opened when the app starts, unless that's turned off globally
+    #[serde(default)]
+    pub auto_connect: bool,
+    /// Terminal look and behavior for SSH sessions from this profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terminal_settings: Option<TerminalSettings>,
+    /// Free text, e.g. rack location or change-ticket links
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// Deployment stage, e.g. "prod", "staging" or "dev"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    /// Person or team responsible for the host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
 }
 
 impl ConnectionProfile {
@@ -147,6 +306,19 @@ impl ConnectionProfile {
             },
             created_at: Utc::now(),
             last_used: None,
+            default_download_dir: None,
+            default_remote_path: None,
+            default_local_path: None,
+            idle_timeout_secs: None,
+            tags: Vec::new(),
+            group: None,
+            favorite: false,
+            sort_order: None,
+            auto_connect: false,
+            terminal_settings: None,
+            notes: None,
+            environment: None,
+            owner: None,
         }
     }
 
@@ -168,6 +340,19 @@ impl ConnectionProfile {
             },
             created_at: Utc::now(),
             last_used: None,
+            default_download_dir: None,
+            default_remote_path: None,
+            default_local_path: None,
+            idle_timeout_secs: None,
+            tags: Vec::new(),
+            group: None,
+            favorite: false,
+            sort_order: None,
+            auto_connect: false,
+            terminal_settings: None,
+            notes: None,
+            environment: None,
+            owner: None,
         }
     }
 
@@ -175,6 +360,11 @@ impl ConnectionProfile {
         name: String,
         host: String,
         port: u16,
+        encoding: VncEncoding,
+        quality: Option<u8>,
+        color_depth: VncColorDepth,
+        auth_type: VncAuthType,
+        username: Option<String>,
     ) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
@@ -182,9 +372,27 @@ impl ConnectionProfile {
             connection_type: ConnectionType::Vnc {
                 host,
                 port,
+                encoding,
+                quality,
+                color_depth,
+                auth_type,
+                username,
             },
             created_at: Utc::now(),
             last_used: None,
+            default_download_dir: None,
+            default_remote_path: None,
+            default_local_path: None,
+            idle_timeout_secs: None,
+            tags: Vec::new(),
+            group: None,
+            favorite: false,
+            sort_order: None,
+            auto_connect: false,
+            terminal_settings: None,
+            notes: None,
+            environment: None,
+            owner: None,
         }
     }
 
@@ -194,6 +402,15 @@ impl ConnectionProfile {
         port: u16,
         username: String,
         domain: Option<String>,
+        save_password: bool,
+        quality: RdpQuality,
+        keyboard: RdpKeyboard,
+        accept_invalid_certs: bool,
+        gateway: Option<RdpGateway>,
+        auto_reconnect: bool,
+        codec: RdpCodec,
+        shared_folder: Option<RdpSharedFolder>,
+        custom_performance_flags: Option<RdpPerformanceFlags>,
     ) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
@@ -203,9 +420,62 @@ impl ConnectionProfile {
                 port,
                 username,
                 domain,
+                save_password,
+                quality,
+                keyboard,
+                accept_invalid_certs,
+                gateway,
+                auto_reconnect,
+                codec,
+                shared_folder,
+                custom_performance_flags,
             },
             created_at: Utc::now(),
             last_used: None,
+            default_download_dir: None,
+            default_remote_path: None,
+            default_local_path: None,
+            idle_timeout_secs: None,
+            tags: Vec::new(),
+            group: None,
+            favorite: false,
+            sort_order: None,
+            auto_connect: false,
+            terminal_settings: None,
+            notes: None,
+            environment: None,
+            owner: None,
+        }
+    }
+
+    /// Lowercased host, user and port of an SSH profile, which identify
+    /// the same login across profiles
+    pub fn ssh_target(&self) -> Option<(String, String, u16)> {
+        match &self.connection_type {
+            ConnectionType::Ssh {
+                host,
+                port,
+                username,
+                ..
+            } => Some((host.to_lowercase(), username.clone(), *port)),
+            _ => None,
+        }
+    }
+
+    /// Kind, lowercased host, user and port of an SSH or FTP profile, which
+    /// identify the same login across profiles
+    pub fn login_target(&self) -> Option<(&'static str, String, String, u16)> {
+        match &self.connection_type {
+            ConnectionType::Ftp {
+                host,
+                port,
+                username,
+                anonymous,
+            } => {
+                let user = if *anonymous { None } else { username.clone() };
+                Some(("ftp", host.to_lowercase(), user.unwrap_or_default(), *port))
+            }
+            _ => self.ssh_target().map(|(host, user, port)| ("ssh", host, user, port)),
         }
     }
 
@@ -217,10 +487,14 @@ impl ConnectionProfile {
                         password: password.unwrap_or_default(),
                     }
                 }
-                StoredAuthMethod::PublicKey { private_key_path } => {
+                StoredAuthMethod::PublicKey {
+                    private_key_path,
+                    certificate_path,
+                } => {
                     AuthMethod::PublicKey {
                         private_key_path: private_key_path.clone(),
                         passphrase,
+                        certificate_path: certificate_path.clone(),
                     }
                 }
                 StoredAuthMethod::Agent => AuthMethod::Agent,
@@ -241,9 +515,520 @@ impl ConnectionProfile {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct ConnectionsFile {
+    /// Format version, see `SCHEMA_VERSION`
+    version: u32,
     connections: HashMap<String, ConnectionProfile>,
+    /// Passwords by keychain entry name, kept here instead of the keychain
+    /// while the file is encrypted
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    secrets: HashMap<String, String>,
+    /// Skip every profile's `auto_connect`
+    #[serde(default)]
+    auto_connect_disabled: bool,
+}
+
+impl Default for ConnectionsFile {
+    fn default() -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            connections: HashMap::new(),
+            secrets: HashMap::new(),
+            auto_connect_disabled: false,
+        }
+    }
+}
+
+/// Format of connections.json this build writes. A change that older
+/// builds would misread bumps it and adds a step to `MIGRATIONS`.
+const SCHEMA_VERSION: u32 = 1;
+
+/// Upgrades connections.json by one schema version
+type Migration = fn(&mut Value) -> Result<(), StorageError>;
+
+/// `MIGRATIONS[n]` upgrades a file from version n to n + 1
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [migrate_v0_to_v1];
+
+/// Version 0 (no `version` field) predates FTP, VNC and RDP: some profiles
+/// are flat SSH ones without a `connection_type`
+fn migrate_v0_to_v1(data: &mut Value) -> Result<(), StorageError> {
+    let Some(connections) = data.get_mut("connections").and_then(Value::as_object_mut) else {
+        return Ok(());
+    };
+    for profile in connections.values_mut() {
+        let profile = profile
+            .as_object_mut()
+            .ok_or_else(|| StorageError::Invalid("A saved connection is not an object".to_string()))?;
+        profile
+            .entry("connection_type")
+            .or_insert_with(|| Value::from("ssh"));
+    }
+    Ok(())
+}
+
+/// Bring the decoded contents of connections.json up to `SCHEMA_VERSION`.
+/// Files from newer builds are refused rather than misread.
+fn migrate(mut data: Value) -> Result<ConnectionsFile, StorageError> {
+    let version = match data.get("version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| StorageError::Invalid(format!("Invalid connections.json version {}", version)))?,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(StorageError::NewerVersion {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    for step in &MIGRATIONS[version as usize..] {
+        step(&mut data)?;
+    }
+    data.as_object_mut()
+        .ok_or_else(|| StorageError::Invalid("connections.json is not an object".to_string()))?
+        .insert("version".to_string(), Value::from(SCHEMA_VERSION));
+    Ok(serde_json::from_value(data)?)
+}
+
+/// connections.json as stored: plain, or sealed under the master password
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredFile {
+    Encrypted { encrypted: Sealed },
+    Plain(Value),
+}
+
+#[derive(Serialize)]
+struct EncryptedFile<'a> {
+    encrypted: &'a Sealed,
+}
+
+/// Authenticated along with the encrypted connections
+const ENCRYPTED_AAD: &[u8] = b"openterm-storage";
+
+/// Whether connections.json is encrypted, and if so whether it was unlocked
+/// in this run
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageEncryptionStatus {
+    pub enabled: bool,
+    pub unlocked: bool,
+}
+
+/// Notes and ownership fields given to `update`. Unset fields keep what's
+/// stored; empty ones clear it.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileMetadata {
+    pub notes: Option<String>,
+    pub environment: Option<String>,
+    pub owner: Option<String>,
+}
+
+impl ProfileMetadata {
+    /// Longest `notes` accepted, in bytes
+    pub const MAX_NOTES_LEN: usize = 16 * 1024;
+
+    fn apply(self, profile: &mut ConnectionProfile) -> Result<(), StorageError> {
+        if self.notes.as_ref().is_some_and(|notes| notes.len() > Self::MAX_NOTES_LEN) {
+            return Err(StorageError::Invalid(format!(
+                "Notes can be at most {} KB",
+                Self::MAX_NOTES_LEN / 1024
+            )));
+        }
+        let set = |field: &mut Option<String>, value: Option<String>, trim: bool| {
+            if let Some(value) = value {
+                let kept = if trim { value.trim().to_string() } else { value };
+                *field = Some(kept).filter(|v| !v.trim().is_empty());
+            }
+        };
+        set(&mut profile.notes, self.notes, false);
+        set(&mut profile.environment, self.environment, true);
+        set(&mut profile.owner, self.owner, true);
+        Ok(())
+    }
+}
+
+/// Connection settings given to `update`. Unset fields keep what's stored;
+/// empty strings clear optional ones. Fields for another connection type
+/// are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionChanges {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    /// SSH: "password", "publickey" or "agent". VNC: "auto", "none",
+    /// "password", "vencrypt", "tight", "mslogon" or "ard".
+    pub auth_type: Option<String>,
+    pub private_key_path: Option<String>,
+    pub certificate_path: Option<String>,
+    pub anonymous: Option<bool>,
+    pub domain: Option<String>,
+    pub encoding: Option<VncEncoding>,
+    pub quality: Option<u8>,
+    pub color_depth: Option<VncColorDepth>,
+    pub save_password: Option<bool>,
+    pub rdp_quality: Option<RdpQuality>,
+    pub keyboard: Option<RdpKeyboard>,
+    pub accept_invalid_certs: Option<bool>,
+    /// One with an empty host removes the gateway
+    pub gateway: Option<RdpGateway>,
+    pub auto_reconnect: Option<bool>,
+    pub codec: Option<RdpCodec>,
+    pub shared_folder: Option<String>,
+    pub shared_folder_read_only: Option<bool>,
+    pub custom_performance_flags: Option<RdpPerformanceFlags>,
+}
+
+impl ConnectionChanges {
+    fn apply(self, connection_type: &mut ConnectionType) -> Result<(), StorageError> {
+        fn set<T>(field: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+        fn set_text(field: &mut Option<String>, value: Option<String>) {
+            if let Some(value) = value {
+                *field = Some(value).filter(|v| !v.trim().is_empty());
+            }
+        }
+        let invalid_auth = || StorageError::Invalid("Invalid auth type".to_string());
+
+        match connection_type {
+            ConnectionType::Ssh {
+                host,
+                port,
+                username,
+                auth_method,
+            } => {
+                set(host, self.host);
+                set(port, self.port);
+                set(username, self.username);
+                let (mut key_path, mut cert_path) = match auth_method {
+                    StoredAuthMethod::PublicKey {
+                        private_key_path,
+                        certificate_path,
+                    } => (private_key_path.clone(), certificate_path.clone()),
+                    _ => (String::new(), None),
+                };
+                set(&mut key_path, self.private_key_path);
+                set_text(&mut cert_path, self.certificate_path);
+                let kind = match (self.auth_type.as_deref(), &*auth_method) {
+                    (Some(kind), _) => kind,
+                    (None, StoredAuthMethod::Password) => "password",
+                    (None, StoredAuthMethod::PublicKey { .. }) => "publickey",
+                    (None, StoredAuthMethod::Agent) => "agent",
+                };
+                *auth_method = match kind {
+                    "password" => StoredAuthMethod::Password,
+                    "publickey" => StoredAuthMethod::PublicKey {
+                        private_key_path: key_path,
+                        certificate_path: cert_path,
+                    },
+                    "agent" => StoredAuthMethod::Agent,
+                    _ => return Err(invalid_auth()),
+                };
+            }
+            ConnectionType::Ftp {
+                host,
+                port,
+                username,
+                anonymous,
+            } => {
+                set(host, self.host);
+                set(port, self.port);
+                set_text(username, self.username);
+                set(anonymous, self.anonymous);
+            }
+            ConnectionType::Vnc {
+                host,
+                port,
+                encoding,
+                quality,
+                color_depth,
+                auth_type,
+                username,
+            } => {
+                set(host, self.host);
+                set(port, self.port);
+                set(encoding, self.encoding);
+                set(quality, self.quality.map(Some));
+                set(color_depth, self.color_depth);
+                if let Some(kind) = self.auth_type {
+                    *auth_type = match kind.as_str() {
+                        "auto" => VncAuthType::Auto,
+                        "none" => VncAuthType::None,
+                        "password" => VncAuthType::VncPassword,
+                        "vencrypt" => VncAuthType::VeNCrypt,
+                        "tight" => VncAuthType::Tight,
+                        "mslogon" => VncAuthType::MsLogon,
+                        "ard" => VncAuthType::AppleRemoteDesktop,
+                        _ => return Err(invalid_auth()),
+                    };
+                }
+                set_text(username, self.username);
+            }
+            ConnectionType::Rdp {
+                host,
+                port,
+                username,
+                domain,
+                save_password,
+                quality,
+                keyboard,
+                accept_invalid_certs,
+                gateway,
+                auto_reconnect,
+                codec,
+                shared_folder,
+                custom_performance_flags,
+            } => {
+                set(host, self.host);
+                set(port, self.port);
+                set(username, self.username);
+                set_text(domain, self.domain);
+                set(save_password, self.save_password);
+                set(quality, self.rdp_quality);
+                set(keyboard, self.keyboard);
+                set(accept_invalid_certs, self.accept_invalid_certs);
+                if let Some(new_gateway) = self.gateway {
+                    *gateway = Some(new_gateway).filter(|g| !g.host.trim().is_empty());
+                }
+                set(auto_reconnect, self.auto_reconnect);
+                set(codec, self.codec);
+                if let Some(path) = self.shared_folder {
+                    let read_only = shared_folder.as_ref().is_some_and(|f| f.read_only);
+                    *shared_folder = Some(path).filter(|p| !p.trim().is_empty()).map(|path| RdpSharedFolder {
+                        path,
+                        read_only,
+                    });
+                }
+                if let (Some(folder), Some(read_only)) = (shared_folder.as_mut(), self.shared_folder_read_only) {
+                    folder.read_only = read_only;
+                }
+                set(custom_performance_flags, self.custom_performance_flags.map(Some));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Passwords given to `update_connection`. Unset or empty ones keep what's
+/// saved.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileSecrets {
+    pub password: Option<String>,
+    pub gateway_password: Option<String>,
+    pub passphrase: Option<String>,
+    /// `Some(false)` forgets a saved passphrase; a new one is only saved
+    /// with `Some(true)`
+    pub save_passphrase: Option<bool>,
+}
+
+impl ProfileSecrets {
+    /// Bring the saved passwords in line with a profile that was `before`
+    /// until now. Entries are only deleted when the profile stops using
+    /// them, and only overwritten by new values.
+    pub(crate) fn apply(
+        self,
+        keychain: &impl SecretStore,
+        before: &ConnectionType,
+        profile: &ConnectionProfile,
+    ) -> Result<(), KeychainError> {
+        let keep = |value: Option<String>| value.filter(|v| !v.is_empty());
+        let after = &profile.connection_type;
+
+        let password_entry = &profile.id;
+        if before.saves_password() && !after.saves_password() {
+            keychain.delete(password_entry);
+        } else if let Some(password) = keep(self.password).filter(|_| after.saves_password()) {
+            keychain.store(password_entry, &password)?;
+        }
+
+        let gateway_entry = KeychainManager::gateway_entry(&profile.id);
+        if before.has_gateway() && !after.has_gateway() {
+            keychain.delete(&gateway_entry);
+        } else if let Some(password) = keep(self.gateway_password).filter(|_| after.has_gateway()) {
+            keychain.store(&gateway_entry, &password)?;
+        }
+
+        let passphrase_entry = KeychainManager::passphrase_entry(&profile.id);
+        if (before.uses_key() && !after.uses_key()) || self.save_passphrase == Some(false) {
+            keychain.delete(&passphrase_entry);
+        } else if let (true, Some(true), Some(passphrase)) =
+            (after.uses_key(), self.save_passphrase, keep(self.passphrase))
+        {
+            keychain.store(&passphrase_entry, &passphrase)?;
+        }
+        Ok(())
+    }
+}
+
+/// Narrows `list_connections`; unset fields match everything
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionFilter {
+    pub group: Option<String>,
+    pub tag: Option<String>,
+    /// "ssh", "ftp", "vnc" or "rdp"
+    pub connection_type: Option<String>,
+    /// Text to find in the name, notes or owner, ignoring case
+    pub search: Option<String>,
+}
+
+impl ConnectionFilter {
+    fn matches(&self, profile: &ConnectionProfile) -> bool {
+        let group = self.group.as_deref().is_none_or(|group| {
+            profile.group.as_deref().is_some_and(|g| g.eq_ignore_ascii_case(group))
+        });
+        let tag = self
+            .tag
+            .as_deref()
+            .is_none_or(|tag| profile.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)));
+        let kind = self
+            .connection_type
+            .as_deref()
+            .is_none_or(|kind| profile.connection_type.kind() == kind);
+        let search = self
+            .search
+            .as_deref()
+            .map(|search| search.trim().to_lowercase())
+            .filter(|search| !search.is_empty())
+            .is_none_or(|search| {
+                [Some(&profile.name), profile.notes.as_ref(), profile.owner.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .any(|text| text.to_lowercase().contains(&search))
+            });
+        group && tag && kind && search
+    }
+}
+
+/// Serializes load-modify-save of connections.json. Every command builds
+/// its own `ConnectionStorage`, so this is process-wide; reentrant because
+/// `load` and `save` take it inside the callers' critical sections.
+static STORAGE_LOCK: ReentrantMutex<()> = parking_lot::const_reentrant_mutex(());
+
+/// Key for an encrypted connections.json, set once it's unlocked (or
+/// encryption is enabled) and cleared when encryption is disabled. Set
+/// exactly when the file is encrypted and usable.
+static VAULT_KEY: Mutex<Option<DerivedKey>> = parking_lot::const_mutex(None);
+
+/// Whether passwords go to the encrypted connections.json rather than the
+/// keychain
+pub fn vault_unlocked() -> bool {
+    VAULT_KEY.lock().is_some()
+}
+
+/// Held by tests that read or write connection files, which would see a
+/// key another test set
+#[cfg(test)]
+pub(super) static TEST_LOCK: Mutex<()> = parking_lot::const_mutex(());
+
+/// connections.json as last read or written, with its path and modification
+/// time then; reread once the file changes underneath. Shared like the lock,
+/// and only touched with it held.
+static CACHE: Mutex<Option<(PathBuf, SystemTime, ConnectionsFile)>> = parking_lot::const_mutex(None);
+
+static CHANGE_LISTENER: OnceLock<Box<dyn Fn() + Send + Sync>> = OnceLock::new();
+
+/// Call `listener` whenever connections.json is written. Only the first
+/// listener is kept.
+pub fn on_change(listener: impl Fn() + Send + Sync + 'static) {
+    let _ = CHANGE_LISTENER.set(Box::new(listener));
+}
+
+fn notify_change() {
+    if let Some(listener) = CHANGE_LISTENER.get() {
+        listener();
+    }
+}
+
+type RecoveryListener = Box<dyn Fn(&StorageRecovery) + Send + Sync>;
+
+static RECOVERY_LISTENER: OnceLock<RecoveryListener> = OnceLock::new();
+
+/// connections.json didn't parse and was replaced
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageRecovery {
+    /// Why the file was rejected
+    pub error: String,
+    /// Profiles came back from `connections.json.bak`; otherwise there are
+    /// none
+    pub restored_from_backup: bool,
+    /// Where the unreadable file was moved, to recover by hand
+    pub corrupt_path: String,
+}
+
+/// Call `listener` whenever connections.json is recovered. Only the first
+/// listener is kept.
+pub fn on_recovery(listener: impl Fn(&StorageRecovery) + Send + Sync + 'static) {
+    let _ = RECOVERY_LISTENER.set(Box::new(listener));
+}
+
+/// `path` as stored, or `None` if it can't be read or parsed
+fn read_stored(path: &Path) -> Option<StoredFile> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// Profiles in the contents of connections.json, decrypted with the
+/// unlocked key if encrypted
+fn parse(content: &str) -> Result<ConnectionsFile, StorageError> {
+    match serde_json::from_str(content)? {
+        StoredFile::Plain(data) => migrate(data),
+        StoredFile::Encrypted { encrypted } => {
+            let key = VAULT_KEY.lock().clone().ok_or(StorageError::Locked)?;
+            migrate(serde_json::from_slice(&key.open(&encrypted, ENCRYPTED_AAD)?)?)
+        }
+    }
+}
+
+/// Contents of connections.json for `data`, sealed while a key is set
+fn serialize(data: &ConnectionsFile) -> Result<String, StorageError> {
+    match VAULT_KEY.lock().clone() {
+        Some(key) => {
+            let encrypted = key.seal(&serde_json::to_vec(data)?, ENCRYPTED_AAD)?;
+            Ok(serde_json::to_string_pretty(&EncryptedFile { encrypted: &encrypted })?)
+        }
+        None => Ok(serde_json::to_string_pretty(data)?),
+    }
+}
+
+/// Replace `path` with `contents` by writing a temporary file next to it
+/// and renaming it over, so a crash leaves either the old or the new file.
+/// The file is created readable only by the user on Unix.
+pub(super) fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), StorageError> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    // The mode only applies to new files, so never reuse a leftover one
+    match fs::remove_file(&tmp_path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Rename an unreadable `path` to `<name>.corrupt-<time>`, keeping it for
+/// inspection, and return the new path
+pub(super) fn move_corrupt(path: &Path) -> Result<PathBuf, StorageError> {
+    let mut corrupt_name = path.file_name().unwrap_or_default().to_os_string();
+    corrupt_name.push(format!(".corrupt-{}", Utc::now().format("%Y%m%d%H%M%S")));
+    let corrupt_path = path.with_file_name(corrupt_name);
+    fs::rename(path, &corrupt_path)?;
+    Ok(corrupt_path)
 }
 
 pub struct ConnectionStorage {
@@ -252,51 +1037,187 @@ pub struct ConnectionStorage {
 
 impl ConnectionStorage {
     pub fn new() -> Result<Self, StorageError> {
-        let config_dir = dirs::config_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("openterm");
-
-        fs::create_dir_all(&config_dir)?;
+        let file_path = Self::default_path();
+        if let Some(config_dir) = file_path.parent() {
+            fs::create_dir_all(config_dir)?;
+        }
+        Self::at(file_path)
+    }
 
-        let file_path = config_dir.join("connections.json");
+    fn default_path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("openterm")
+            .join("connections.json")
+    }
 
+    pub(super) fn at(file_path: PathBuf) -> Result<Self, StorageError> {
+        let _guard = STORAGE_LOCK.lock();
         // Create file if it doesn't exist
         if !file_path.exists() {
             let empty = ConnectionsFile::default();
             let json = serde_json::to_string_pretty(&empty)?;
-            fs::write(&file_path, json)?;
+            write_atomic(&file_path, json.as_bytes())?;
         }
 
         Ok(Self { file_path })
     }
 
+    /// The previous version of connections.json, rotated on every save
+    fn backup_path(&self) -> PathBuf {
+        self.file_path.with_extension("json.bak")
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.file_path).and_then(|meta| meta.modified()).ok()
+    }
+
+    /// Keep `data` as the contents of the file as it is now
+    fn remember(&self, data: &ConnectionsFile) {
+        *CACHE.lock() = self
+            .modified()
+            .map(|modified| (self.file_path.clone(), modified, data.clone()));
+    }
+
     fn load(&self) -> Result<ConnectionsFile, StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        if let (Some(modified), Some((path, cached_at, data))) = (self.modified(), CACHE.lock().as_ref()) {
+            if *path == self.file_path && modified == *cached_at {
+                return Ok(data.clone());
+            }
+        }
+        if !self.file_path.exists() {
+            return Ok(ConnectionsFile::default());
+        }
         let content = fs::read_to_string(&self.file_path)?;
-        let data: ConnectionsFile = serde_json::from_str(&content)?;
+        let data = match parse(&content) {
+            Err(StorageError::Locked) => return Err(StorageError::Locked),
+            Err(e @ StorageError::NewerVersion { .. }) => return Err(self.keep_newer(e)),
+            Err(e) => self.recover(e)?,
+            Ok(data) => data,
+        };
+        self.remember(&data);
+        Ok(data)
+    }
+
+    /// Copy a connections.json from a newer build aside, once, so nothing
+    /// this build does can lose it. It's never rewritten: every load fails
+    /// with `error` until the newer build is back.
+    fn keep_newer(&self, error: StorageError) -> StorageError {
+        if let StorageError::NewerVersion { found, .. } = error {
+            let copy = self.file_path.with_extension(format!("json.v{}.bak", found));
+            if !copy.exists() {
+                if let Err(e) = fs::copy(&self.file_path, &copy) {
+                    eprintln!("Connection storage: Failed to back up {}: {}", copy.display(), e);
+                }
+            }
+        }
+        error
+    }
+
+    /// Move an unreadable connections.json aside and continue from the
+    /// backup, or with no profiles if that is unreadable too
+    fn recover(&self, error: StorageError) -> Result<ConnectionsFile, StorageError> {
+        // An encrypted backup can't be checked before unlocking; leave
+        // everything as is until then
+        if !vault_unlocked() && matches!(read_stored(&self.backup_path()), Some(StoredFile::Encrypted { .. })) {
+            return Err(StorageError::Locked);
+        }
+
+        let corrupt_path = move_corrupt(&self.file_path)?;
+
+        let backup = fs::read_to_string(self.backup_path())
+            .ok()
+            .and_then(|content| parse(&content).ok());
+        let restored_from_backup = backup.is_some();
+        let data = backup.unwrap_or_default();
+        write_atomic(&self.file_path, serialize(&data)?.as_bytes())?;
+
+        let recovery = StorageRecovery {
+            error: error.to_string(),
+            restored_from_backup,
+            corrupt_path: corrupt_path.to_string_lossy().into_owned(),
+        };
+        eprintln!(
+            "Connection storage: connections.json was unreadable ({}), moved to {}; restored from backup: {}",
+            recovery.error, recovery.corrupt_path, restored_from_backup
+        );
+        if let Some(listener) = RECOVERY_LISTENER.get() {
+            listener(&recovery);
+        }
+        notify_change();
         Ok(data)
     }
 
     fn save(&self, data: &ConnectionsFile) -> Result<(), StorageError> {
-        let json = serde_json::to_string_pretty(data)?;
-        fs::write(&self.file_path, json)?;
+        let _guard = STORAGE_LOCK.lock();
+        let json = serialize(data)?;
+        if let Some(dir) = self.file_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Callers loaded the current file, so it parses and is worth keeping
+        if let Ok(previous) = fs::read(&self.file_path) {
+            write_atomic(&self.backup_path(), &previous)?;
+        }
+        write_atomic(&self.file_path, json.as_bytes())?;
+        self.remember(data);
+        notify_change();
         Ok(())
     }
 
-    pub fn list(&self) -> Result<Vec<ConnectionProfile>, StorageError> {
+    /// Profiles matching `filter`: favorites first in their manual order,
+    /// then the rest by group (ungrouped last), most recently used and name
+    pub fn list(&self, filter: &ConnectionFilter) -> Result<Vec<ConnectionProfile>, StorageError> {
         let data = self.load()?;
-        let mut connections: Vec<_> = data.connections.into_values().collect();
+        let mut connections: Vec<_> = data
+            .connections
+            .into_values()
+            .filter(|profile| filter.matches(profile))
+            .collect();
         connections.sort_by(|a, b| {
-            // Sort by last_used (most recent first), then by name
-            match (&b.last_used, &a.last_used) {
+            if a.favorite || b.favorite {
+                // Favorites without a position go after those with one
+                let position =
+                    |profile: &ConnectionProfile| (!profile.favorite, profile.sort_order.unwrap_or(u32::MAX));
+                let order = position(a)
+                    .cmp(&position(b))
+                    .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+                if order.is_ne() {
+                    return order;
+                }
+            }
+            let group = |profile: &ConnectionProfile| profile.group.as_ref().map(|g| g.to_lowercase());
+            match (group(a), group(b)) {
+                (Some(a_group), Some(b_group)) => a_group.cmp(&b_group),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            }
+            // Within a group, by last_used (most recent first), then by name
+            .then_with(|| match (&b.last_used, &a.last_used) {
                 (Some(b_time), Some(a_time)) => b_time.cmp(a_time),
                 (Some(_), None) => std::cmp::Ordering::Less,
                 (None, Some(_)) => std::cmp::Ordering::Greater,
                 (None, None) => a.name.cmp(&b.name),
-            }
+            })
+            .then_with(|| a.id.cmp(&b.id))
         });
         Ok(connections)
     }
 
+    /// Names of the groups in use, sorted
+    pub fn groups(&self) -> Result<Vec<String>, StorageError> {
+        let data = self.load()?;
+        let mut groups: Vec<String> = data
+            .connections
+            .into_values()
+            .filter_map(|profile| profile.group)
+            .collect();
+        groups.sort_by_key(|group| group.to_lowercase());
+        groups.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+        Ok(groups)
+    }
+
     pub fn get(&self, id: &str) -> Result<ConnectionProfile, StorageError> {
         let data = self.load()?;
         data.connections
@@ -306,12 +1227,50 @@ impl ConnectionStorage {
     }
 
     pub fn save_connection(&self, profile: ConnectionProfile) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
         let mut data = self.load()?;
         data.connections.insert(profile.id.clone(), profile);
         self.save(&data)
     }
 
+    /// Change the name and settings of an existing profile in place,
+    /// keeping its id, usage history and per-connection preferences. The
+    /// protocol, `kind`, can't be changed. Returns the settings from before
+    /// along with the updated profile.
+    pub fn update(
+        &self,
+        id: &str,
+        kind: &str,
+        name: Option<String>,
+        changes: ConnectionChanges,
+        metadata: ProfileMetadata,
+    ) -> Result<(ConnectionType, ConnectionProfile), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let mut data = self.load()?;
+        let profile = data
+            .connections
+            .get_mut(id)
+            .ok_or_else(|| StorageError::NotFound(id.to_string()))?;
+        let before = profile.connection_type.clone();
+        if before.kind() != kind {
+            let to = ["ssh", "ftp", "vnc", "rdp"]
+                .into_iter()
+                .find(|k| *k == kind)
+                .ok_or_else(|| StorageError::Invalid("Invalid connection type".to_string()))?;
+            return Err(StorageError::TypeChange { from: before.kind(), to });
+        }
+        changes.apply(&mut profile.connection_type)?;
+        metadata.apply(profile)?;
+        if let Some(name) = name {
+            profile.name = name;
+        }
+        let updated = profile.clone();
+        self.save(&data)?;
+        Ok((before, updated))
+    }
+
     pub fn update_last_used(&self, id: &str) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
         let mut data = self.load()?;
         if let Some(profile) = data.connections.get_mut(id) {
             profile.last_used = Some(Utc::now());
@@ -320,15 +1279,622 @@ impl ConnectionStorage {
         Ok(())
     }
 
+    /// Set or clear (`None`) the connection's default download folder
+    pub fn set_default_download_dir(&self, id: &str, dir: Option<String>) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let mut data = self.load()?;
+        let profile = data
+            .connections
+            .get_mut(id)
+            .ok_or_else(|| StorageError::NotFound(id.to_string()))?;
+        profile.default_download_dir = dir;
+        self.save(&data)
+    }
+
+    /// Replace the connection's tags. Blank and repeated tags are dropped.
+    pub fn set_tags(&self, id: &str, tags: Vec<String>) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let mut data = self.load()?;
+        let profile = data
+            .connections
+            .get_mut(id)
+            .ok_or_else(|| StorageError::NotFound(id.to_string()))?;
+        profile.tags.clear();
+        for tag in tags {
+            let tag = tag.trim();
+            if !tag.is_empty() && !profile.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+                profile.tags.push(tag.to_string());
+            }
+        }
+        self.save(&data)
+    }
+
+    /// Move the connection into a group, or out of any (`None`)
+    pub fn set_group(&self, id: &str, group: Option<String>) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let mut data = self.load()?;
+        let profile = data
+            .connections
+            .get_mut(id)
+            .ok_or_else(|| StorageError::NotFound(id.to_string()))?;
+        profile.group = group;
+        self.save(&data)
+    }
+
+    /// Mark or unmark the connection as a favorite. New favorites go after
+    /// the existing ones.
+    pub fn set_favorite(&self, id: &str, favorite: bool) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let mut data = self.load()?;
+        let next = data
+            .connections
+            .values()
+            .filter(|profile| profile.favorite)
+            .filter_map(|profile| profile.sort_order)
+            .max()
+            .map_or(0, |last| last.saturating_add(1));
+        let profile = data
+            .connections
+            .get_mut(id)
+            .ok_or_else(|| StorageError::NotFound(id.to_string()))?;
+        if profile.favorite != favorite {
+            profile.favorite = favorite;
+            profile.sort_order = favorite.then_some(next);
+        }
+        self.save(&data)
+    }
+
+    /// Put the connections in the order of `ordered_ids`; connections not
+    /// listed lose their position and follow the listed ones
+    pub fn reorder(&self, ordered_ids: &[String]) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let mut data = self.load()?;
+        if let Some(missing) = ordered_ids.iter().find(|id| !data.connections.contains_key(*id)) {
+            return Err(StorageError::NotFound(missing.clone()));
+        }
+        for profile in data.connections.values_mut() {
+            profile.sort_order = None;
+        }
+        for (position, id) in ordered_ids.iter().enumerate() {
+            if let Some(profile) = data.connections.get_mut(id) {
+                profile.sort_order = Some(position as u32);
+            }
+        }
+        self.save(&data)
+    }
+
+    /// Set or clear (`None`) the connection's terminal settings
+    pub fn set_terminal_settings(&self, id: &str, settings: Option<TerminalSettings>) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let mut data = self.load()?;
+        let profile = data
+            .connections
+            .get_mut(id)
+            .ok_or_else(|| StorageError::NotFound(id.to_string()))?;
+        profile.terminal_settings = settings;
+        self.save(&data)
+    }
+
+    /// Set or clear (`None`) the folders SFTP/FTP sessions from an SSH or
+    /// FTP connection start in. Both must be absolute.
+    pub fn set_default_paths(
+        &self,
+        id: &str,
+        remote: Option<String>,
+        local: Option<String>,
+    ) -> Result<(), StorageError> {
+        if remote.as_deref().is_some_and(|path| !path.starts_with('/')) {
+            return Err(StorageError::Invalid("The remote folder must be an absolute path".to_string()));
+        }
+        if local.as_deref().is_some_and(|path| !Path::new(path).is_absolute()) {
+            return Err(StorageError::Invalid("The local folder must be an absolute path".to_string()));
+        }
+
+        let _guard = STORAGE_LOCK.lock();
+        let mut data = self.load()?;
+        let profile = data
+            .connections
+            .get_mut(id)
+            .ok_or_else(|| StorageError::NotFound(id.to_string()))?;
+        if !matches!(profile.connection_type, ConnectionType::Ssh { .. } | ConnectionType::Ftp { .. }) {
+            return Err(StorageError::Invalid(format!(
+                "A {} connection has no file browser",
+                profile.connection_type.kind()
+            )));
+        }
+        profile.default_remote_path = remote;
+        profile.default_local_path = local;
+        self.save(&data)
+    }
+
+    /// Open the connection when the app starts. SSH profiles only.
+    pub fn set_auto_connect(&self, id: &str, auto_connect: bool) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let mut data = self.load()?;
+        let profile = data
+            .connections
+            .get_mut(id)
+            .ok_or_else(|| StorageError::NotFound(id.to_string()))?;
+        if auto_connect && !matches!(profile.connection_type, ConnectionType::Ssh { .. }) {
+            return Err(StorageError::Invalid(format!(
+                "A {} connection can't be opened on startup",
+                profile.connection_type.kind()
+            )));
+        }
+        profile.auto_connect = auto_connect;
+        self.save(&data)
+    }
+
+    /// Whether profiles marked `auto_connect` are opened on startup
+    pub fn auto_connect_enabled(&self) -> Result<bool, StorageError> {
+        Ok(!self.load()?.auto_connect_disabled)
+    }
+
+    pub fn set_auto_connect_enabled(&self, enabled: bool) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let mut data = self.load()?;
+        data.auto_connect_disabled = !enabled;
+        self.save(&data)
+    }
+
+    /// Set or clear (`None`) the connection's idle timeout
+    pub fn set_idle_timeout(&self, id: &str, secs: Option<u64>) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let mut data = self.load()?;
+        let profile = data
+            .connections
+            .get_mut(id)
+            .ok_or_else(|| StorageError::NotFound(id.to_string()))?;
+        profile.idle_timeout_secs = secs;
+        self.save(&data)
+    }
+
     pub fn delete(&self, id: &str) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
         let mut data = self.load()?;
         data.connections.remove(id);
         self.save(&data)
     }
+
+    /// Overwrite the backup with the current file, so no copy in the old
+    /// format is left behind
+    fn reset_backup(&self) -> Result<(), StorageError> {
+        write_atomic(&self.backup_path(), &fs::read(&self.file_path)?)
+    }
+
+    pub fn encryption_status(&self) -> Result<StorageEncryptionStatus, StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let enabled = vault_unlocked() || matches!(read_stored(&self.file_path), Some(StoredFile::Encrypted { .. }));
+        Ok(StorageEncryptionStatus {
+            enabled,
+            unlocked: vault_unlocked(),
+        })
+    }
+
+    /// Encrypt connections.json, and from now on the passwords saved with
+    /// connections, under a key derived from `master_password`
+    pub fn enable_encryption(&self, master_password: &str) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        if master_password.is_empty() {
+            return Err(StorageError::Crypto("The master password can't be empty".to_string()));
+        }
+        if self.encryption_status()?.enabled {
+            return Err(StorageError::Crypto("Connection storage is already encrypted".to_string()));
+        }
+
+        let data = self.load()?;
+        *VAULT_KEY.lock() = Some(DerivedKey::new(master_password)?);
+        KeychainManager::forget_cached();
+        if let Err(e) = self.save(&data) {
+            *VAULT_KEY.lock() = None;
+            return Err(e);
+        }
+        self.reset_backup()
+    }
+
+    /// Check `master_password` against the encrypted connections.json and
+    /// keep its key for this run. A wrong password changes nothing; a
+    /// file that isn't encrypted needs no unlocking.
+    pub fn unlock(&self, master_password: &str) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let encrypted = match read_stored(&self.file_path) {
+            Some(StoredFile::Encrypted { encrypted }) => encrypted,
+            Some(StoredFile::Plain(_)) => return Ok(()),
+            // Unreadable; the backup tells whether it was encrypted
+            None => match read_stored(&self.backup_path()) {
+                Some(StoredFile::Encrypted { encrypted }) => encrypted,
+                _ => return Ok(()),
+            },
+        };
+        let key = DerivedKey::for_sealed(master_password, &encrypted)?;
+        key.open(&encrypted, ENCRYPTED_AAD)?;
+        let rekey = !key.is_current();
+        *VAULT_KEY.lock() = Some(key);
+        // Passwords in the file were missing while locked
+        KeychainManager::forget_cached();
+        if rekey {
+            self.rekey(master_password);
+        }
+        Ok(())
+    }
+
+    /// Seal the unlocked file again under a key derived the way new keys
+    /// are. If that fails the file stays as it was, still unlocked.
+    fn rekey(&self, master_password: &str) {
+        let (Ok(data), Ok(key)) = (self.load(), DerivedKey::new(master_password)) else {
+            return;
+        };
+        let previous_key = VAULT_KEY.lock().replace(key);
+        match self.save(&data) {
+            Ok(()) => {
+                let _ = self.reset_backup();
+            }
+            Err(e) => {
+                eprintln!("Failed to re-key connection storage: {}", e);
+                *VAULT_KEY.lock() = previous_key;
+            }
+        }
+    }
+
+    /// Decrypt connections.json back to plain JSON, moving the passwords it
+    /// holds to the keychain. If the keychain refuses any, those already
+    /// moved are taken out again and the file stays encrypted.
+    pub fn disable_encryption(&self, master_password: &str) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        let Some(StoredFile::Encrypted { encrypted }) = read_stored(&self.file_path) else {
+            return Err(StorageError::Crypto("Connection storage isn't encrypted".to_string()));
+        };
+        let key = DerivedKey::for_sealed(master_password, &encrypted)?;
+        let mut data = migrate(serde_json::from_slice(&key.open(&encrypted, ENCRYPTED_AAD)?)?)?;
+
+        // With the key cleared, the keychain manager writes to the keychain
+        let previous_key = VAULT_KEY.lock().take();
+        let secrets = std::mem::take(&mut data.secrets);
+        let mut moved = Vec::new();
+        let mut secret_errors = Vec::new();
+        for (entry, password) in &secrets {
+            match KeychainManager::store_password(entry, password) {
+                Ok(()) => moved.push(entry),
+                Err(e) => {
+                    let id = entry.split(':').next().unwrap_or(entry);
+                    let name = data.connections.get(id).map_or(id, |profile| profile.name.as_str());
+                    secret_errors.push(format!("{}: {}", name, e));
+                }
+            }
+        }
+        let result = if secret_errors.is_empty() {
+            self.save(&data)
+        } else {
+            Err(StorageError::Crypto(format!(
+                "The keychain refused some passwords, so storage stays encrypted: {}",
+                secret_errors.join("; ")
+            )))
+        };
+        if let Err(e) = result {
+            for entry in moved {
+                let _ = KeychainManager::delete_password(entry);
+            }
+            *VAULT_KEY.lock() = previous_key;
+            KeychainManager::forget_cached();
+            return Err(e);
+        }
+        KeychainManager::forget_cached();
+        self.reset_backup()
+    }
+
+    /// Password stored under keychain entry `entry` in the encrypted file
+    pub fn get_secret(&self, entry: &str) -> Result<Option<String>, StorageError> {
+        if !vault_unlocked() {
+            return Err(StorageError::Locked);
+        }
+        Ok(self.load()?.secrets.remove(entry))
+    }
+
+    /// Set or clear (`None`) a password in the encrypted file
+    pub fn set_secret(&self, entry: &str, password: Option<&str>) -> Result<(), StorageError> {
+        let _guard = STORAGE_LOCK.lock();
+        if !vault_unlocked() {
+            return Err(StorageError::Locked);
+        }
+        let mut data = self.load()?;
+        match password {
+            Some(password) => data.secrets.insert(entry.to_string(), password.to_string()),
+            None => data.secrets.remove(entry),
+        };
+        self.save(&data)
+    }
 }
 
+/// Storage at the usual path that creates nothing until the first save, so
+/// it can't fail; a missing file reads as no profiles
 impl Default for ConnectionStorage {
     fn default() -> Self {
-        Self::new().expect("Failed to create connection storage")
+        Self {
+            file_path: Self::default_path(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::keychain::MemoryKeychain;
+    use serde_json::json;
+
+    fn v0_ssh_profile(id: &str) -> Value {
+        json!({
+            "id": id,
+            "name": "web",
+            "host": "web.example.com",
+            "port": 22,
+            "username": "deploy",
+            "auth_method": { "auth_type": "Password" },
+            "created_at": "2024-01-01T00:00:00Z",
+            "last_used": null
+        })
+    }
+
+    #[test]
+    fn migrates_flat_ssh_profile_from_v0() {
+        let file = migrate(json!({ "connections": { "a": v0_ssh_profile("a") } })).unwrap();
+
+        assert_eq!(file.version, SCHEMA_VERSION);
+        let profile = &file.connections["a"];
+        match &profile.connection_type {
+            ConnectionType::Ssh { host, port, username, auth_method } => {
+                assert_eq!(host, "web.example.com");
+                assert_eq!(*port, 22);
+                assert_eq!(username, "deploy");
+                assert!(matches!(auth_method, StoredAuthMethod::Password));
+            }
+            other => panic!("expected an SSH profile, got {}", other.kind()),
+        }
+    }
+
+    #[test]
+    fn migrates_mixed_v0_file() {
+        let mut vnc = v0_ssh_profile("b");
+        vnc["connection_type"] = json!("vnc");
+        vnc["port"] = json!(5901);
+        let file = migrate(json!({ "connections": { "a": v0_ssh_profile("a"), "b": vnc } })).unwrap();
+
+        assert_eq!(file.connections["a"].connection_type.kind(), "ssh");
+        match &file.connections["b"].connection_type {
+            ConnectionType::Vnc { port, .. } => assert_eq!(*port, 5901),
+            other => panic!("expected a VNC profile, got {}", other.kind()),
+        }
+    }
+
+    #[test]
+    fn passes_v1_file_through_unchanged() {
+        let mut profile = v0_ssh_profile("a");
+        profile["connection_type"] = json!("ssh");
+        profile["tags"] = json!(["prod"]);
+        profile["favorite"] = json!(false);
+        profile["auto_connect"] = json!(false);
+        let data = json!({ "version": 1, "connections": { "a": profile }, "auto_connect_disabled": true });
+
+        let file = migrate(data.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&file).unwrap(), data);
+    }
+
+    #[test]
+    fn profile_from_before_tags_has_none() {
+        let mut profile = v0_ssh_profile("a");
+        profile["connection_type"] = json!("ssh");
+        let profile: ConnectionProfile = serde_json::from_value(profile).unwrap();
+        assert!(profile.tags.is_empty());
+        assert_eq!(profile.group, None);
+    }
+
+    #[test]
+    fn rejects_non_integer_version() {
+        for version in [json!("1"), json!(1.5), json!(-1), json!(null)] {
+            let result = migrate(json!({ "version": version, "connections": {} }));
+            assert!(matches!(result, Err(StorageError::Invalid(_))), "accepted version {}", version);
+        }
+    }
+
+    #[test]
+    fn leaves_newer_file_untouched() {
+        let _guard = TEST_LOCK.lock();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("connections.json");
+        let content = r#"{"version": 99, "connections": {}, "something_new": [1, 2, 3]}"#;
+        fs::write(&path, content).unwrap();
+
+        let storage = ConnectionStorage::at(path.clone()).unwrap();
+        let result = storage.list(&ConnectionFilter::default());
+        assert!(matches!(
+            result,
+            Err(StorageError::NewerVersion { found: 99, supported: SCHEMA_VERSION })
+        ));
+        assert!(storage.save_connection(db_profile()).is_err());
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+        assert_eq!(fs::read_to_string(dir.path().join("connections.json.v99.bak")).unwrap(), content);
+    }
+
+    #[test]
+    fn saves_and_lists_concurrently() {
+        let _guard = TEST_LOCK.lock();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("connections.json");
+        ConnectionStorage::at(path.clone()).unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|writer| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let storage = ConnectionStorage::at(path).unwrap();
+                    for i in 0..10 {
+                        let profile = ConnectionProfile::new_ssh(
+                            format!("host-{}-{}", writer, i),
+                            "example.com".to_string(),
+                            22,
+                            "admin".to_string(),
+                            StoredAuthMethod::Password,
+                        );
+                        storage.save_connection(profile).unwrap();
+                        storage.list(&ConnectionFilter::default()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let storage = ConnectionStorage::at(path).unwrap();
+        assert_eq!(storage.list(&ConnectionFilter::default()).unwrap().len(), 80);
+    }
+
+    #[test]
+    fn default_storage_reads_missing_file_as_empty() {
+        let _guard = TEST_LOCK.lock();
+        let dir = tempfile::tempdir().unwrap();
+        let storage = ConnectionStorage {
+            file_path: dir.path().join("openterm").join("connections.json"),
+        };
+        assert!(storage.list(&ConnectionFilter::default()).unwrap().is_empty());
+        storage.save_connection(db_profile()).unwrap();
+        assert_eq!(storage.list(&ConnectionFilter::default()).unwrap().len(), 1);
+    }
+
+    fn db_profile() -> ConnectionProfile {
+        ConnectionProfile::new_ssh(
+            "db".to_string(),
+            "db.example.com".to_string(),
+            22,
+            "admin".to_string(),
+            StoredAuthMethod::Password,
+        )
+    }
+
+    /// Forget the key, as a restart does
+    fn lock_vault() {
+        *VAULT_KEY.lock() = None;
+        *CACHE.lock() = None;
+    }
+
+    #[test]
+    fn encryption_round_trip() {
+        let _guard = TEST_LOCK.lock();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("connections.json");
+        let storage = ConnectionStorage::at(path.clone()).unwrap();
+        storage.save_connection(db_profile()).unwrap();
+
+        storage.enable_encryption("master").unwrap();
+        let sealed = fs::read_to_string(&path).unwrap();
+        assert!(!sealed.contains("db.example.com"));
+        assert!(!fs::read_to_string(storage.backup_path()).unwrap().contains("db.example.com"));
+
+        lock_vault();
+        let status = storage.encryption_status().unwrap();
+        assert!(status.enabled && !status.unlocked);
+        assert!(matches!(storage.list(&ConnectionFilter::default()), Err(StorageError::Locked)));
+
+        storage.unlock("master").unwrap();
+        assert_eq!(storage.list(&ConnectionFilter::default()).unwrap().len(), 1);
+
+        storage.disable_encryption("master").unwrap();
+        assert!(!vault_unlocked());
+        let plain: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(plain["connections"].as_object().unwrap().len(), 1);
+        lock_vault();
+        assert_eq!(storage.list(&ConnectionFilter::default()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn wrong_master_password_changes_nothing() {
+        let _guard = TEST_LOCK.lock();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("connections.json");
+        let storage = ConnectionStorage::at(path.clone()).unwrap();
+        storage.save_connection(db_profile()).unwrap();
+        storage.enable_encryption("master").unwrap();
+        lock_vault();
+        let before = fs::read(&path).unwrap();
+
+        assert!(matches!(storage.unlock("wrong"), Err(StorageError::WrongPassphrase)));
+        assert!(!vault_unlocked());
+        assert!(matches!(
+            storage.disable_encryption("wrong"),
+            Err(StorageError::WrongPassphrase)
+        ));
+        assert_eq!(fs::read(&path).unwrap(), before);
+
+        storage.unlock("master").unwrap();
+        assert_eq!(storage.list(&ConnectionFilter::default()).unwrap().len(), 1);
+        lock_vault();
+    }
+
+    #[test]
+    fn renaming_keeps_rdp_settings_and_gateway_password() {
+        let _guard = TEST_LOCK.lock();
+        let dir = tempfile::tempdir().unwrap();
+        let storage = ConnectionStorage::at(dir.path().join("connections.json")).unwrap();
+        let profile = ConnectionProfile::new_rdp(
+            "desktop".to_string(),
+            "desktop.example.com".to_string(),
+            3389,
+            "alice".to_string(),
+            Some("CORP".to_string()),
+            false,
+            RdpQuality::Ultra,
+            RdpKeyboard {
+                layout: 0x0407,
+                ..RdpKeyboard::default()
+            },
+            true,
+            Some(RdpGateway {
+                host: "gw.example.com".to_string(),
+                port: 443,
+                username: Some("gw-user".to_string()),
+                password: None,
+                domain: None,
+                bypass_local: true,
+            }),
+            false,
+            RdpCodec::Bitmap,
+            Some(RdpSharedFolder {
+                path: "/home/alice/share".to_string(),
+                read_only: true,
+            }),
+            Some(RdpPerformanceFlags {
+                wallpaper: true,
+                themes: false,
+                animations: false,
+                font_smoothing: true,
+                desktop_composition: false,
+                full_window_drag: false,
+            }),
+        );
+        let id = profile.id.clone();
+        storage.save_connection(profile).unwrap();
+        let keychain = MemoryKeychain::default();
+        keychain.store(&KeychainManager::gateway_entry(&id), "gw-secret").unwrap();
+        let settings = |profile: &ConnectionProfile| serde_json::to_value(&profile.connection_type).unwrap();
+        let saved = settings(&storage.get(&id).unwrap());
+
+        let (before, updated) = storage
+            .update(
+                &id,
+                "rdp",
+                Some("renamed".to_string()),
+                ConnectionChanges::default(),
+                ProfileMetadata::default(),
+            )
+            .unwrap();
+        ProfileSecrets::default().apply(&keychain, &before, &updated).unwrap();
+
+        assert_eq!(updated.name, "renamed");
+        assert_eq!(settings(&updated), saved);
+        assert_eq!(settings(&storage.get(&id).unwrap()), saved);
+        assert_eq!(
+            keychain.get(&KeychainManager::gateway_entry(&id)).as_deref(),
+            Some("gw-secret")
+        );
     }
 }
@@ -0,0 +1,308 @@
+use super::connections::StorageError;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::pbkdf2;
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroU32;
+use std::ops::RangeInclusive;
+
+/// Argon2id settings for new keys (OWASP's recommended minimum)
+const ARGON2_MEMORY_KIB: u32 = 19_456;
+const ARGON2_ITERATIONS: u32 = 2;
+const ARGON2_PARALLELISM: u32 = 1;
+/// Settings accepted from sealed data. Less would make a tampered file
+/// cheap to brute-force; more would hang the app deriving its key.
+const ARGON2_MEMORY_KIB_RANGE: RangeInclusive<u32> = 8_192..=1_048_576;
+const ARGON2_ITERATIONS_RANGE: RangeInclusive<u32> = 1..=16;
+const ARGON2_PARALLELISM_RANGE: RangeInclusive<u32> = 1..=16;
+/// PBKDF2-HMAC-SHA256 rounds accepted from data sealed before Argon2id
+const PBKDF2_ITERATIONS_RANGE: RangeInclusive<u32> = 100_000..=10_000_000;
+const SALT_LEN: usize = 16;
+
+/// How the key for sealed data was made
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kdf {
+    Argon2id,
+    /// Data sealed before the tag was written
+    #[default]
+    Pbkdf2Sha256,
+    /// A stored random key, see `DerivedKey::from_bytes`
+    None,
+    /// From a newer version
+    #[serde(other)]
+    Unknown,
+}
+
+/// ChaCha20-Poly1305 under a key from `kdf`, byte fields base64.
+/// `iterations` is the Argon2id time cost or the PBKDF2 round count.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sealed {
+    #[serde(default)]
+    pub kdf: Kdf,
+    pub iterations: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_kib: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallelism: Option<u32>,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// A key's derivation and its settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Derivation {
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+    Pbkdf2 {
+        iterations: u32,
+    },
+    Stored,
+}
+
+impl Derivation {
+    const CURRENT: Self = Self::Argon2id {
+        memory_kib: ARGON2_MEMORY_KIB,
+        iterations: ARGON2_ITERATIONS,
+        parallelism: ARGON2_PARALLELISM,
+    };
+
+    /// What `sealed` records, if a passphrase can open it with those
+    /// settings
+    fn of(sealed: &Sealed) -> Result<Self, StorageError> {
+        let unsupported = || {
+            StorageError::Crypto(format!(
+                "Unsupported key derivation setting: {:?}, {} rounds",
+                sealed.kdf, sealed.iterations
+            ))
+        };
+        match sealed.kdf {
+            Kdf::Argon2id => {
+                let (Some(memory_kib), Some(parallelism)) = (sealed.memory_kib, sealed.parallelism) else {
+                    return Err(damaged());
+                };
+                if !ARGON2_MEMORY_KIB_RANGE.contains(&memory_kib)
+                    || !ARGON2_ITERATIONS_RANGE.contains(&sealed.iterations)
+                    || !ARGON2_PARALLELISM_RANGE.contains(&parallelism)
+                {
+                    return Err(unsupported());
+                }
+                Ok(Self::Argon2id {
+                    memory_kib,
+                    iterations: sealed.iterations,
+                    parallelism,
+                })
+            }
+            Kdf::Pbkdf2Sha256 if PBKDF2_ITERATIONS_RANGE.contains(&sealed.iterations) => Ok(Self::Pbkdf2 {
+                iterations: sealed.iterations,
+            }),
+            Kdf::Pbkdf2Sha256 | Kdf::None | Kdf::Unknown => Err(unsupported()),
+        }
+    }
+}
+
+/// A key derived from a passphrase. Kept around so data can be sealed
+/// again without repeating the (slow) derivation.
+#[derive(Clone)]
+pub struct DerivedKey {
+    key: [u8; 32],
+    salt: [u8; SALT_LEN],
+    derivation: Derivation,
+}
+
+impl DerivedKey {
+    /// Fresh key with a random salt
+    pub fn new(passphrase: &str) -> Result<Self, StorageError> {
+        Self::derive(passphrase, rand::random(), Derivation::CURRENT)
+    }
+
+    /// The key `sealed` was sealed with, if `passphrase` is right
+    pub fn for_sealed(passphrase: &str, sealed: &Sealed) -> Result<Self, StorageError> {
+        let derivation = Derivation::of(sealed)?;
+        let salt = BASE64
+            .decode(&sealed.salt)
+            .ok()
+            .and_then(|salt| salt.try_into().ok())
+            .ok_or_else(damaged)?;
+        Self::derive(passphrase, salt, derivation)
+    }
+
+    /// Random key, for data whose key is stored rather than typed. Sealed
+    /// data records no derivation, so it only opens with `from_bytes`.
+    pub fn random() -> Self {
+        Self::from_bytes(rand::random())
+    }
+
+    pub fn from_bytes(key: [u8; 32]) -> Self {
+        Self {
+            key,
+            salt: [0; SALT_LEN],
+            derivation: Derivation::Stored,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.key
+    }
+
+    /// Whether the key was derived the way new keys are, so data sealed
+    /// with it needn't be re-keyed
+    pub fn is_current(&self) -> bool {
+        matches!(self.derivation, Derivation::Stored) || self.derivation == Derivation::CURRENT
+    }
+
+    fn derive(passphrase: &str, salt: [u8; SALT_LEN], derivation: Derivation) -> Result<Self, StorageError> {
+        let invalid = || StorageError::Crypto("Invalid key derivation settings".to_string());
+        let mut key = [0u8; 32];
+        match derivation {
+            Derivation::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params =
+                    Params::new(memory_kib, iterations, parallelism, Some(key.len())).map_err(|_| invalid())?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+                    .map_err(|_| invalid())?;
+            }
+            Derivation::Pbkdf2 { iterations } => {
+                let rounds = NonZeroU32::new(iterations).ok_or_else(invalid)?;
+                pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, rounds, &salt, passphrase.as_bytes(), &mut key);
+            }
+            Derivation::Stored => return Err(invalid()),
+        }
+        Ok(Self { key, salt, derivation })
+    }
+
+    fn aead_key(&self) -> Result<LessSafeKey, StorageError> {
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &self.key)
+            .map_err(|_| StorageError::Crypto("Failed to set up encryption".to_string()))?;
+        Ok(LessSafeKey::new(key))
+    }
+
+    /// Encrypt `plaintext`, authenticating `aad` along with it
+    pub fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Result<Sealed, StorageError> {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let mut data = plaintext.to_vec();
+        self.aead_key()?
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut data)
+            .map_err(|_| StorageError::Crypto("Encryption failed".to_string()))?;
+        let (kdf, iterations, memory_kib, parallelism) = match self.derivation {
+            Derivation::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => (Kdf::Argon2id, iterations, Some(memory_kib), Some(parallelism)),
+            Derivation::Pbkdf2 { iterations } => (Kdf::Pbkdf2Sha256, iterations, None, None),
+            Derivation::Stored => (Kdf::None, 0, None, None),
+        };
+        Ok(Sealed {
+            kdf,
+            iterations,
+            memory_kib,
+            parallelism,
+            salt: BASE64.encode(self.salt),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(data),
+        })
+    }
+
+    /// Decrypt `sealed`. A wrong key and a tampered file look the same.
+    pub fn open(&self, sealed: &Sealed, aad: &[u8]) -> Result<Vec<u8>, StorageError> {
+        let nonce: [u8; NONCE_LEN] = BASE64
+            .decode(&sealed.nonce)
+            .ok()
+            .and_then(|nonce| nonce.try_into().ok())
+            .ok_or_else(damaged)?;
+        let mut data = BASE64.decode(&sealed.ciphertext).map_err(|_| damaged())?;
+        let plaintext = self
+            .aead_key()?
+            .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut data)
+            .map_err(|_| StorageError::WrongPassphrase)?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+fn damaged() -> StorageError {
+    StorageError::Crypto("The encrypted data is damaged".to_string())
+}
+
+/// Encrypt `plaintext` under a new key from `passphrase`
+pub fn seal(plaintext: &[u8], passphrase: &str, aad: &[u8]) -> Result<Sealed, StorageError> {
+    DerivedKey::new(passphrase)?.seal(plaintext, aad)
+}
+
+/// Decrypt `sealed` with `passphrase`
+pub fn open(sealed: &Sealed, passphrase: &str, aad: &[u8]) -> Result<Vec<u8>, StorageError> {
+    DerivedKey::for_sealed(passphrase, sealed)?.open(sealed, aad)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_data_is_sealed_with_argon2id() {
+        let sealed = seal(b"secret", "passphrase", b"aad").unwrap();
+        assert_eq!(sealed.kdf, Kdf::Argon2id);
+        assert_eq!(sealed.memory_kib, Some(ARGON2_MEMORY_KIB));
+        assert_eq!(open(&sealed, "passphrase", b"aad").unwrap(), b"secret");
+        assert!(matches!(
+            open(&sealed, "wrong", b"aad"),
+            Err(StorageError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn opens_untagged_pbkdf2_data() {
+        let key = DerivedKey::derive("passphrase", [7; SALT_LEN], Derivation::Pbkdf2 { iterations: 100_000 }).unwrap();
+        let mut json = serde_json::to_value(key.seal(b"secret", b"aad").unwrap()).unwrap();
+        json.as_object_mut().unwrap().remove("kdf");
+
+        let sealed: Sealed = serde_json::from_value(json).unwrap();
+        assert_eq!(sealed.kdf, Kdf::Pbkdf2Sha256);
+        let key = DerivedKey::for_sealed("passphrase", &sealed).unwrap();
+        assert!(!key.is_current());
+        assert_eq!(key.open(&sealed, b"aad").unwrap(), b"secret");
+    }
+
+    #[test]
+    fn rejects_settings_out_of_range() {
+        let mut sealed = seal(b"secret", "passphrase", b"aad").unwrap();
+        sealed.kdf = Kdf::Pbkdf2Sha256;
+        for iterations in [1, u32::MAX] {
+            sealed.iterations = iterations;
+            assert!(matches!(
+                DerivedKey::for_sealed("passphrase", &sealed),
+                Err(StorageError::Crypto(_))
+            ));
+        }
+
+        let mut sealed = seal(b"secret", "passphrase", b"aad").unwrap();
+        sealed.memory_kib = Some(u32::MAX);
+        assert!(matches!(
+            DerivedKey::for_sealed("passphrase", &sealed),
+            Err(StorageError::Crypto(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_and_stored_key_derivations() {
+        let mut sealed = seal(b"secret", "passphrase", b"aad").unwrap();
+        let mut json = serde_json::to_value(&sealed).unwrap();
+        json["kdf"] = "scrypt".into();
+        let unknown: Sealed = serde_json::from_value(json).unwrap();
+        assert_eq!(unknown.kdf, Kdf::Unknown);
+        assert!(matches!(open(&unknown, "passphrase", b"aad"), Err(StorageError::Crypto(_))));
+
+        sealed = DerivedKey::random().seal(b"secret", b"aad").unwrap();
+        assert_eq!(sealed.kdf, Kdf::None);
+        assert!(matches!(open(&sealed, "passphrase", b"aad"), Err(StorageError::Crypto(_))));
+    }
+}
@@ -0,0 +1,471 @@
+//! Sessions saved by other clients: PuTTY's registry sessions and
+//! FileZilla's Site Manager. Reading saves nothing; the sessions are a
+//! preview for the user to pick from.
+
+use super::connections::{ConnectionProfile, StoredAuthMethod};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A profile read from another client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForeignSession {
+    pub profile: ConnectionProfile,
+    /// The other client saved a password. The password itself stays out of
+    /// the preview; importing reads it from the source again.
+    #[serde(default)]
+    pub has_password: bool,
+    #[serde(skip)]
+    pub password: Option<String>,
+    /// The private key is a PuTTY `.ppk`, which has to be converted to
+    /// OpenSSH format (e.g. with puttygen) before it can be used
+    #[serde(default)]
+    pub key_needs_conversion: bool,
+    /// Settings that didn't carry over
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// PuTTY's saved SSH sessions. Sessions for other protocols are skipped;
+/// outside Windows, or without PuTTY, there are none.
+#[cfg(windows)]
+pub fn read_putty_sessions() -> Result<Vec<ForeignSession>, String> {
+    use winreg::enums::HKEY_CURRENT_USER;
+    use winreg::RegKey;
+
+    let sessions = match RegKey::predef(HKEY_CURRENT_USER).open_subkey(r"Software\SimonTatham\PuTTY\Sessions") {
+        Ok(sessions) => sessions,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read PuTTY sessions: {}", e)),
+    };
+
+    let mut imported = Vec::new();
+    for key_name in sessions.enum_keys().filter_map(|name| name.ok()) {
+        let Ok(session) = sessions.open_subkey(&key_name) else {
+            continue;
+        };
+        let text = |name: &str| session.get_value::<String, _>(name).ok().filter(|value| !value.is_empty());
+        // "Default Settings" and other templates have no host
+        let Some(host) = text("HostName") else {
+            continue;
+        };
+        if text("Protocol").is_some_and(|protocol| protocol != "ssh") {
+            continue;
+        }
+        let port = session
+            .get_value::<u32, _>("PortNumber")
+            .ok()
+            .and_then(|port| u16::try_from(port).ok())
+            .filter(|port| *port != 0)
+            .unwrap_or(22);
+        imported.push(putty_session(
+            &unescape_putty_name(&key_name),
+            &host,
+            port,
+            text("UserName"),
+            text("PublicKeyFile"),
+        ));
+    }
+    Ok(imported)
+}
+
+#[cfg(not(windows))]
+pub fn read_putty_sessions() -> Result<Vec<ForeignSession>, String> {
+    Ok(Vec::new())
+}
+
+/// Profile for a PuTTY session. A `user@` in the host name wins over the
+/// saved user name, as in PuTTY.
+#[cfg(windows)]
+fn putty_session(name: &str, host: &str, port: u16, user: Option<String>, key_file: Option<String>) -> ForeignSession {
+    let (user, host) = match host.rsplit_once('@') {
+        Some((host_user, host)) => (Some(host_user.to_string()), host),
+        None => (user, host),
+    };
+    let mut warnings = Vec::new();
+    if user.is_none() {
+        warnings.push("No user name saved".to_string());
+    }
+
+    let key_needs_conversion = key_file
+        .as_deref()
+        .is_some_and(|file| Path::new(file).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ppk")));
+    let auth_method = match key_file {
+        Some(private_key_path) => StoredAuthMethod::PublicKey {
+            private_key_path,
+            certificate_path: None,
+        },
+        None => StoredAuthMethod::Password,
+    };
+    ForeignSession {
+        profile: ConnectionProfile::new_ssh(
+            name.to_string(),
+            host.to_string(),
+            port,
+            user.unwrap_or_default(),
+            auth_method,
+        ),
+        has_password: false,
+        password: None,
+        key_needs_conversion,
+        warnings,
+    }
+}
+
+/// PuTTY stores session names with bytes outside a safe set as `%XX`
+#[cfg(any(windows, test))]
+fn unescape_putty_name(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = name.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                unescaped.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        unescaped.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+/// FileZilla's `sitemanager.xml`
+pub fn default_filezilla_path() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let dir = dirs::config_dir().map(|dir| dir.join("FileZilla"));
+    #[cfg(not(windows))]
+    let dir = dirs::home_dir().map(|home| home.join(".config").join("filezilla"));
+    dir.map(|dir| dir.join("sitemanager.xml"))
+}
+
+/// FTP and SFTP sites in a FileZilla `sitemanager.xml`, with Site Manager
+/// folders as groups. Sites for other protocols, FTP over TLS included, are
+/// skipped; a missing file has none.
+pub fn read_filezilla_sites(path: &Path) -> Result<Vec<ForeignSession>, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    let mut reader = Reader::from_str(&content);
+    let mut site_manager = SiteManager::default();
+    loop {
+        match reader.read_event().map_err(xml_error)? {
+            Event::Start(element) => {
+                let encoding = element
+                    .try_get_attribute("encoding")
+                    .ok()
+                    .flatten()
+                    .and_then(|encoding| encoding.unescape_value().ok())
+                    .map(|encoding| encoding.into_owned());
+                site_manager.start(String::from_utf8_lossy(element.local_name().as_ref()).into_owned(), encoding);
+            }
+            Event::End(_) => site_manager.end(),
+            Event::Text(text) => site_manager.text(&text.decode().map_err(xml_error)?),
+            Event::CData(text) => site_manager.text(&text.decode().map_err(xml_error)?),
+            Event::GeneralRef(entity) => {
+                let text = match entity.resolve_char_ref().map_err(xml_error)? {
+                    Some(c) => c.to_string(),
+                    None => {
+                        let name = entity.decode().map_err(xml_error)?;
+                        resolve_predefined_entity(&name).unwrap_or_default().to_string()
+                    }
+                };
+                site_manager.text(&text);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(site_manager.sites)
+}
+
+fn xml_error(e: impl std::fmt::Display) -> String {
+    format!("Invalid sitemanager.xml: {}", e)
+}
+
+/// Walks the elements of a `sitemanager.xml`
+#[derive(Default)]
+struct SiteManager {
+    /// Names of the open elements
+    stack: Vec<String>,
+    /// Names of the open `Folder` elements
+    folders: Vec<String>,
+    /// Text of the open `Server` element's children by element name; its
+    /// own text, the site name in older versions, is under ""
+    server: Option<HashMap<String, String>>,
+    sites: Vec<ForeignSession>,
+}
+
+impl SiteManager {
+    fn start(&mut self, name: String, encoding: Option<String>) {
+        match name.as_str() {
+            "Folder" if self.server.is_none() => self.folders.push(String::new()),
+            "Server" if self.server.is_none() => self.server = Some(HashMap::new()),
+            "Pass" => {
+                if let (Some(fields), Some(encoding)) = (&mut self.server, encoding) {
+                    fields.insert("Pass@encoding".to_string(), encoding);
+                }
+            }
+            _ => {}
+        }
+        self.stack.push(name);
+    }
+
+    fn end(&mut self) {
+        match self.stack.pop().as_deref() {
+            Some("Folder") if self.server.is_none() => {
+                self.folders.pop();
+            }
+            Some("Server") => {
+                if let Some(fields) = self.server.take() {
+                    self.sites.extend(filezilla_site(&fields, &self.folders));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let Some(element) = self.stack.last() else {
+            return;
+        };
+        match &mut self.server {
+            Some(fields) => {
+                let parent = self.stack.len().checked_sub(2).map(|i| self.stack[i].as_str());
+                if element == "Server" {
+                    fields.entry(String::new()).or_default().push_str(text);
+                } else if parent == Some("Server") {
+                    fields.entry(element.clone()).or_default().push_str(text);
+                }
+            }
+            None if element == "Folder" => {
+                if let Some(folder) = self.folders.last_mut() {
+                    folder.push_str(text);
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// Profile for a Site Manager entry
+fn filezilla_site(fields: &HashMap<String, String>, folders: &[String]) -> Option<ForeignSession> {
+    let field = |name: &str| fields.get(name).map(|value| value.trim()).filter(|value| !value.is_empty());
+    let host = field("Host")?.to_string();
+    // 0 FTP, 1 SFTP, 3 implicit FTPS, 4 explicit FTPS, 6 plain FTP only
+    let protocol: u32 = field("Protocol").and_then(|p| p.parse().ok()).unwrap_or(0);
+    // 0 anonymous, 1 normal, 2 ask, 3 interactive, 4 account, 5 key file
+    let logon_type: u32 = field("Logontype").and_then(|t| t.parse().ok()).unwrap_or(1);
+    // FTP over TLS isn't supported, and connecting without it would send
+    // the password in the clear
+    let default_port = match protocol {
+        1 => 22,
+        0 | 6 => 21,
+        _ => return None,
+    };
+    let port = field("Port").and_then(|p| p.parse().ok()).unwrap_or(default_port);
+    let user = field("User").map(|user| user.to_string());
+    let name = field("Name").or_else(|| field("")).unwrap_or(&host).to_string();
+
+    let mut warnings = Vec::new();
+    let password = match (logon_type, fields.get("Pass")) {
+        (0, _) | (_, None) => None,
+        (_, Some(pass)) => match fields.get("Pass@encoding").map(String::as_str) {
+            None | Some("") | Some("plain") => Some(pass.clone()),
+            Some("base64") => {
+                let decoded = BASE64
+                    .decode(pass.trim())
+                    .ok()
+                    .and_then(|bytes| String::from_utf8(bytes).ok());
+                if decoded.is_none() {
+                    warnings.push("The saved password couldn't be decoded".to_string());
+                }
+                decoded
+            }
+            Some(_) => {
+                warnings.push("The password is protected by FileZilla's master password".to_string());
+                None
+            }
+        },
+    };
+
+    let mut key_needs_conversion = false;
+    let mut profile = if protocol == 1 {
+        let auth_method = match field("Keyfile").filter(|_| logon_type == 5) {
+            Some(key_file) => {
+                key_needs_conversion = Path::new(key_file)
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("ppk"));
+                StoredAuthMethod::PublicKey {
+                    private_key_path: key_file.to_string(),
+                    certificate_path: None,
+                }
+            }
+            None => StoredAuthMethod::Password,
+        };
+        if user.is_none() {
+            warnings.push("No user name saved".to_string());
+        }
+        ConnectionProfile::new_ssh(name, host, port, user.unwrap_or_default(), auth_method)
+    } else {
+        ConnectionProfile::new_ftp(name, host, port, user, logon_type == 0)
+    };
+
+    let group = folders
+        .iter()
+        .map(|folder| folder.trim())
+        .filter(|folder| !folder.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    if !group.is_empty() {
+        profile.group = Some(group);
+    }
+
+    let password = password.filter(|password| !password.is_empty());
+    Some(ForeignSession {
+        profile,
+        has_password: password.is_some(),
+        password,
+        key_needs_conversion,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::connections::ConnectionType;
+
+    const SITE_MANAGER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<FileZilla3 version="3.66.0">
+  <Servers>
+    <Server>
+      <Host>ftp.example.com</Host>
+      <Port>2121</Port>
+      <Protocol>0</Protocol>
+      <Logontype>1</Logontype>
+      <User>alice</User>
+      <Pass encoding="base64">c2VjcmV0ICYgbW9yZQ==</Pass>
+      <Name>Tom &amp; Jerry</Name>
+    </Server>
+    <Folder expanded="1">Work
+      <Folder>Prod
+        <Server>
+          <Host>sftp.example.com</Host>
+          <Protocol>1</Protocol>
+          <Logontype>5</Logontype>
+          <User>deploy</User>
+          <Keyfile>C:\keys\deploy.ppk</Keyfile>
+          <Name>Deploy</Name>
+        </Server>
+      </Folder>
+      <Server>
+        <Host>secure.example.com</Host>
+        <Protocol>4</Protocol>
+        <Logontype>1</Logontype>
+        <User>bob</User>
+        <Pass encoding="base64">aHVudGVyMg==</Pass>
+        <Name>Explicit TLS</Name>
+      </Server>
+      <Server>
+        <Host>implicit.example.com</Host>
+        <Protocol>3</Protocol>
+        <Name>Implicit TLS</Name>
+      </Server>
+      <Server>Old name
+        <Host>mirror.example.com</Host>
+        <Logontype>0</Logontype>
+        <Pass>ignored</Pass>
+      </Server>
+    </Folder>
+    <Server>
+      <Host>locked.example.com</Host>
+      <User>carol</User>
+      <Pass encoding="crypt">...</Pass>
+    </Server>
+  </Servers>
+</FileZilla3>
+"#;
+
+    fn read_fixture() -> Vec<ForeignSession> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sitemanager.xml");
+        fs::write(&path, SITE_MANAGER).unwrap();
+        read_filezilla_sites(&path).unwrap()
+    }
+
+    #[test]
+    fn reads_filezilla_sites() {
+        let sites = read_fixture();
+        let names: Vec<_> = sites.iter().map(|site| site.profile.name.as_str()).collect();
+        assert_eq!(names, ["Tom & Jerry", "Deploy", "Old name", "locked.example.com"]);
+
+        let ftp = &sites[0];
+        assert_eq!(ftp.password.as_deref(), Some("secret & more"));
+        assert!(ftp.has_password);
+        assert_eq!(ftp.profile.group, None);
+        match &ftp.profile.connection_type {
+            ConnectionType::Ftp { port, username, anonymous, .. } => {
+                assert_eq!(*port, 2121);
+                assert_eq!(username.as_deref(), Some("alice"));
+                assert!(!anonymous);
+            }
+            other => panic!("expected an FTP profile, got {}", other.kind()),
+        }
+
+        let sftp = &sites[1];
+        assert_eq!(sftp.profile.group.as_deref(), Some("Work/Prod"));
+        assert!(sftp.key_needs_conversion);
+        match &sftp.profile.connection_type {
+            ConnectionType::Ssh { port, auth_method, .. } => {
+                assert_eq!(*port, 22);
+                assert!(matches!(auth_method, StoredAuthMethod::PublicKey { .. }));
+            }
+            other => panic!("expected an SSH profile, got {}", other.kind()),
+        }
+
+        let anonymous = &sites[2];
+        assert_eq!(anonymous.profile.group.as_deref(), Some("Work"));
+        assert!(!anonymous.has_password);
+        assert!(matches!(
+            anonymous.profile.connection_type,
+            ConnectionType::Ftp { anonymous: true, .. }
+        ));
+
+        let locked = &sites[3];
+        assert!(!locked.has_password);
+        assert_eq!(locked.warnings.len(), 1);
+    }
+
+    #[test]
+    fn skips_ftp_over_tls_sites() {
+        assert!(read_fixture()
+            .iter()
+            .all(|site| !site.profile.name.contains("TLS") && site.password.as_deref() != Some("hunter2")));
+    }
+
+    #[test]
+    fn missing_site_manager_has_no_sites() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read_filezilla_sites(&dir.path().join("sitemanager.xml")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unescapes_putty_session_names() {
+        assert_eq!(unescape_putty_name("web%20server"), "web server");
+        assert_eq!(unescape_putty_name("caf%C3%A9"), "caf\u{e9}");
+        assert_eq!(unescape_putty_name("100%"), "100%");
+        assert_eq!(unescape_putty_name("50%zz"), "50%zz");
+    }
+}
@@ -0,0 +1,103 @@
+use super::connections::{move_corrupt, write_atomic, StorageError};
+use parking_lot::{Mutex, MutexGuard};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+/// A JSON file in the config dir holding a `T`, whose read-modify-write is
+/// serialized by a static lock of the owning store
+pub(super) struct JsonFile<T> {
+    file_path: PathBuf,
+    lock: &'static Mutex<()>,
+    data: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned + Default> JsonFile<T> {
+    /// `name` in the config dir, which is created if missing
+    pub(super) fn in_config_dir(name: &str, lock: &'static Mutex<()>) -> Result<Self, StorageError> {
+        let config_dir = dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("openterm");
+
+        fs::create_dir_all(&config_dir)?;
+        Ok(Self::at(config_dir.join(name), lock))
+    }
+
+    fn at(file_path: PathBuf, lock: &'static Mutex<()>) -> Self {
+        Self {
+            file_path,
+            lock,
+            data: PhantomData,
+        }
+    }
+
+    /// Take the lock; hold the returned guard across a load and save
+    pub(super) fn lock(&self) -> LockedJsonFile<'_, T> {
+        LockedJsonFile {
+            file: self,
+            _guard: self.lock.lock(),
+        }
+    }
+}
+
+pub(super) struct LockedJsonFile<'a, T> {
+    file: &'a JsonFile<T>,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl<T: Serialize + DeserializeOwned + Default> LockedJsonFile<'_, T> {
+    /// A file that no longer parses is moved aside and treated as empty
+    pub(super) fn load(&self) -> Result<T, StorageError> {
+        let path = &self.file.file_path;
+        if !path.exists() {
+            return Ok(T::default());
+        }
+        let content = fs::read_to_string(path)?;
+        match serde_json::from_str(&content) {
+            Ok(data) => Ok(data),
+            Err(e) => {
+                let corrupt_path = move_corrupt(path)?;
+                eprintln!(
+                    "{} was unreadable ({}), moved to {}",
+                    path.display(),
+                    e,
+                    corrupt_path.display()
+                );
+                Ok(T::default())
+            }
+        }
+    }
+
+    pub(super) fn save(&self, data: &T) -> Result<(), StorageError> {
+        let json = serde_json::to_string_pretty(data)?;
+        write_atomic(&self.file.file_path, json.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    static TEST_LOCK: Mutex<()> = parking_lot::const_mutex(());
+
+    #[test]
+    fn unreadable_file_is_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.json");
+        fs::write(&path, "{ not json").unwrap();
+        let file = JsonFile::<HashMap<String, u32>>::at(path, &TEST_LOCK);
+
+        let locked = file.lock();
+        let mut data = locked.load().unwrap();
+        assert!(data.is_empty());
+        let moved: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(moved.len(), 1);
+        assert!(moved[0].to_string_lossy().starts_with("data.json.corrupt-"));
+
+        data.insert("a".to_string(), 1);
+        locked.save(&data).unwrap();
+        assert_eq!(locked.load().unwrap(), data);
+    }
+}
@@ -1,47 +1,376 @@
+use super::connections::{vault_unlocked, ConnectionStorage, StorageError};
+use super::secret_file::SecretFile;
 use keyring::Entry;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use thiserror::Error;
 
 const SERVICE_NAME: &str = "openterm";
 
+/// Looked up to see whether the OS keychain answers at all
+const PROBE_ENTRY: &str = "openterm-probe";
+
+/// Set once the OS keychain has failed for lack of a backend; passwords go
+/// to the secret file until a status check finds it working again
+static OS_UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+/// Whether each entry has a password, as last found by `has_password`.
+/// Asking the keychain is slow and can prompt on macOS.
+static PRESENCE: Mutex<BTreeMap<String, bool>> = parking_lot::const_mutex(BTreeMap::new());
+
 #[derive(Error, Debug)]
 pub enum KeychainError {
     #[error("Keychain error: {0}")]
     Keyring(String),
     #[error("Entry not found")]
     NotFound,
+    #[error("Encrypted storage error: {0}")]
+    Storage(String),
+    #[error("No usable keychain: {0}")]
+    Unavailable(String),
+}
+
+impl From<StorageError> for KeychainError {
+    fn from(e: StorageError) -> Self {
+        KeychainError::Storage(e.to_string())
+    }
 }
 
 impl From<keyring::Error> for KeychainError {
     fn from(e: keyring::Error) -> Self {
         match e {
             keyring::Error::NoEntry => KeychainError::NotFound,
+            keyring::Error::PlatformFailure(e) => KeychainError::Unavailable(e.to_string()),
             _ => KeychainError::Keyring(e.to_string()),
         }
     }
 }
 
+/// Where new passwords are saved
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeychainBackend {
+    /// The OS keychain or secret service
+    Os,
+    /// secrets.json, for systems without a usable keychain
+    File,
+    /// The encrypted connections file, while unlocked
+    EncryptedStorage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KeychainBackendStatus {
+    pub backend: KeychainBackend,
+    pub os_keychain_available: bool,
+    pub store_secrets_in_file: bool,
+}
+
+/// The OS keychain or secret service, under `SERVICE_NAME`
+trait OsKeychain {
+    fn set(&self, entry: &str, password: &str) -> keyring::Result<()>;
+    fn get(&self, entry: &str) -> keyring::Result<String>;
+}
+
+struct SystemKeychain;
+
+impl OsKeychain for SystemKeychain {
+    fn set(&self, entry: &str, password: &str) -> keyring::Result<()> {
+        Entry::new(SERVICE_NAME, entry)?.set_password(password)
+    }
+
+    fn get(&self, entry: &str) -> keyring::Result<String> {
+        Entry::new(SERVICE_NAME, entry)?.get_password()
+    }
+}
+
+/// OS keychain access. While connection storage is encrypted and unlocked,
+/// passwords are kept in the encrypted connections file instead; when the
+/// OS has no keychain, or the user prefers it, in the secret file. Those
+/// saved earlier elsewhere are still read from where they are.
 pub struct KeychainManager;
 
 impl KeychainManager {
     pub fn store_password(connection_id: &str, password: &str) -> Result<(), KeychainError> {
-        let entry = Entry::new(SERVICE_NAME, connection_id)?;
-        entry.set_password(password)?;
-        Ok(())
+        let result = Self::store(connection_id, password);
+        PRESENCE.lock().remove(connection_id);
+        result
+    }
+
+    fn store(connection_id: &str, password: &str) -> Result<(), KeychainError> {
+        if vault_unlocked() {
+            return Ok(ConnectionStorage::new()?.set_secret(connection_id, Some(password))?);
+        }
+        Self::store_in(&SystemKeychain, &SecretFile::new()?, connection_id, password)
+    }
+
+    /// Save to the OS keychain or the secret file. A copy left in the secret
+    /// file from before is removed once the keychain has the password, since
+    /// the file is read first.
+    fn store_in(
+        os: &impl OsKeychain,
+        file: &SecretFile,
+        connection_id: &str,
+        password: &str,
+    ) -> Result<(), KeychainError> {
+        if !Self::use_file(file) {
+            match os.set(connection_id, password).map_err(KeychainError::from) {
+                Ok(()) => {
+                    Self::logged(file.set(connection_id, None));
+                    return Ok(());
+                }
+                Err(KeychainError::Unavailable(e)) => Self::mark_unavailable(&e),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(file.set(connection_id, Some(password))?)
     }
 
     pub fn get_password(connection_id: &str) -> Result<String, KeychainError> {
-        let entry = Entry::new(SERVICE_NAME, connection_id)?;
-        let password = entry.get_password()?;
-        Ok(password)
+        if vault_unlocked() {
+            if let Some(password) = ConnectionStorage::new()?.get_secret(connection_id)? {
+                return Ok(password);
+            }
+        }
+        match SecretFile::new() {
+            Ok(file) => Self::get_from(&SystemKeychain, &file, connection_id),
+            Err(e) => {
+                eprintln!("Can't read secrets.json: {}", e);
+                Self::get_from_os(&SystemKeychain, connection_id)
+            }
+        }
+    }
+
+    /// The password in the secret file, or else in the OS keychain
+    fn get_from(os: &impl OsKeychain, file: &SecretFile, connection_id: &str) -> Result<String, KeychainError> {
+        match file.get(connection_id) {
+            Ok(Some(password)) => return Ok(password),
+            Ok(None) => {}
+            // Don't let a damaged file hide passwords in the keychain
+            Err(e) => eprintln!("Can't read secrets.json: {}", e),
+        }
+        Self::get_from_os(os, connection_id)
+    }
+
+    fn get_from_os(os: &impl OsKeychain, connection_id: &str) -> Result<String, KeychainError> {
+        if OS_UNAVAILABLE.load(Ordering::Relaxed) {
+            return Err(KeychainError::NotFound);
+        }
+        match os.get(connection_id).map_err(KeychainError::from) {
+            Err(KeychainError::Unavailable(e)) => {
+                Self::mark_unavailable(&e);
+                Err(KeychainError::NotFound)
+            }
+            result => result,
+        }
     }
 
     pub fn delete_password(connection_id: &str) -> Result<(), KeychainError> {
-        let entry = Entry::new(SERVICE_NAME, connection_id)?;
-        entry.delete_password()?;
-        Ok(())
+        let result = Self::delete(connection_id);
+        PRESENCE.lock().remove(connection_id);
+        result
+    }
+
+    /// Delete from everywhere the password may be. A damaged secret file
+    /// only fails this when it's the only place passwords can go.
+    fn delete(connection_id: &str) -> Result<(), KeychainError> {
+        let file_result = SecretFile::new().and_then(|file| file.set(connection_id, None));
+        if vault_unlocked() {
+            ConnectionStorage::new()?.set_secret(connection_id, None)?;
+            // It may also be in the keychain from before encryption
+            let _ = Entry::new(SERVICE_NAME, connection_id).and_then(|entry| entry.delete_password());
+            Self::logged(file_result);
+            return Ok(());
+        }
+        if OS_UNAVAILABLE.load(Ordering::Relaxed) {
+            return Ok(file_result?);
+        }
+        let result = Entry::new(SERVICE_NAME, connection_id).and_then(|entry| entry.delete_password());
+        match result.map_err(KeychainError::from) {
+            Err(KeychainError::Unavailable(e)) => {
+                Self::mark_unavailable(&e);
+                Ok(file_result?)
+            }
+            result => {
+                Self::logged(file_result);
+                result
+            }
+        }
+    }
+
+    /// Whether new passwords go to the secret file rather than the OS
+    /// keychain. An unreadable secret file leaves them in the keychain.
+    fn use_file(file: &SecretFile) -> bool {
+        OS_UNAVAILABLE.load(Ordering::Relaxed) || Self::logged(file.preferred()).unwrap_or(false)
+    }
+
+    /// Log a failure of the secret file while another store is usable
+    fn logged<T>(result: Result<T, StorageError>) -> Option<T> {
+        result.inspect_err(|e| eprintln!("Can't use secrets.json: {}", e)).ok()
+    }
+
+    fn mark_unavailable(error: &str) {
+        if !OS_UNAVAILABLE.swap(true, Ordering::Relaxed) {
+            eprintln!("OS keychain unavailable ({}), keeping passwords in secrets.json", error);
+        }
+    }
+
+    /// Which backend new passwords go to. Checks the OS keychain again, so
+    /// one that has come up since is used from now on.
+    pub fn backend_status() -> Result<KeychainBackendStatus, KeychainError> {
+        let probe = Entry::new(SERVICE_NAME, PROBE_ENTRY).and_then(|entry| entry.get_password());
+        let os_keychain_available = !matches!(probe, Err(keyring::Error::PlatformFailure(_)));
+        if OS_UNAVAILABLE.swap(!os_keychain_available, Ordering::Relaxed) == os_keychain_available {
+            Self::forget_cached();
+        }
+
+        let store_secrets_in_file = SecretFile::new()?.preferred()?;
+        let backend = if vault_unlocked() {
+            KeychainBackend::EncryptedStorage
+        } else if store_secrets_in_file || !os_keychain_available {
+            KeychainBackend::File
+        } else {
+            KeychainBackend::Os
+        };
+        Ok(KeychainBackendStatus {
+            backend,
+            os_keychain_available,
+            store_secrets_in_file,
+        })
+    }
+
+    /// Save new passwords to the secret file even while the OS keychain
+    /// works. Passwords already saved stay where they are.
+    pub fn set_store_secrets_in_file(enabled: bool) -> Result<(), KeychainError> {
+        Ok(SecretFile::new()?.set_preferred(enabled)?)
     }
 
     pub fn has_password(connection_id: &str) -> bool {
-        Self::get_password(connection_id).is_ok()
+        if let Some(&found) = PRESENCE.lock().get(connection_id) {
+            return found;
+        }
+        let found = match Self::get_password(connection_id) {
+            Ok(_) => true,
+            Err(KeychainError::NotFound) => false,
+            // Denied or unavailable; ask again next time
+            Err(_) => return false,
+        };
+        PRESENCE.lock().insert(connection_id.to_string(), found);
+        found
+    }
+
+    /// Drop what `has_password` remembers, after passwords moved between
+    /// the keychain and the encrypted file
+    pub fn forget_cached() {
+        PRESENCE.lock().clear();
+    }
+
+    /// Keychain entry for a connection's RD Gateway password
+    pub fn gateway_entry(connection_id: &str) -> String {
+        format!("{}:gateway", connection_id)
+    }
+
+    /// Keychain entry for the passphrase of a connection's private key
+    pub fn passphrase_entry(connection_id: &str) -> String {
+        format!("{}:passphrase", connection_id)
+    }
+
+    pub fn store_passphrase(connection_id: &str, passphrase: &str) -> Result<(), KeychainError> {
+        Self::store_password(&Self::passphrase_entry(connection_id), passphrase)
+    }
+
+    pub fn get_passphrase(connection_id: &str) -> Result<String, KeychainError> {
+        Self::get_password(&Self::passphrase_entry(connection_id))
+    }
+
+    pub fn delete_passphrase(connection_id: &str) -> Result<(), KeychainError> {
+        Self::delete_password(&Self::passphrase_entry(connection_id))
+    }
+}
+
+/// Saved passwords by keychain entry, for code that reads and writes them
+/// in bulk
+pub(crate) trait SecretStore {
+    fn get(&self, entry: &str) -> Option<String>;
+    fn store(&self, entry: &str, password: &str) -> Result<(), KeychainError>;
+    fn delete(&self, entry: &str);
+}
+
+/// `KeychainManager` as a `SecretStore`
+pub(crate) struct Keychain;
+
+impl SecretStore for Keychain {
+    fn get(&self, entry: &str) -> Option<String> {
+        KeychainManager::get_password(entry).ok()
+    }
+
+    fn store(&self, entry: &str, password: &str) -> Result<(), KeychainError> {
+        KeychainManager::store_password(entry, password)
+    }
+
+    fn delete(&self, entry: &str) {
+        let _ = KeychainManager::delete_password(entry);
+    }
+}
+
+/// A `SecretStore` kept in memory, for tests
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MemoryKeychain(pub Mutex<std::collections::HashMap<String, String>>);
+
+#[cfg(test)]
+impl SecretStore for MemoryKeychain {
+    fn get(&self, entry: &str) -> Option<String> {
+        self.0.lock().get(entry).cloned()
+    }
+
+    fn store(&self, entry: &str, password: &str) -> Result<(), KeychainError> {
+        self.0.lock().insert(entry.to_string(), password.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, entry: &str) {
+        self.0.lock().remove(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct FakeKeychain(Mutex<HashMap<String, String>>);
+
+    impl OsKeychain for FakeKeychain {
+        fn set(&self, entry: &str, password: &str) -> keyring::Result<()> {
+            self.0.lock().insert(entry.to_string(), password.to_string());
+            Ok(())
+        }
+
+        fn get(&self, entry: &str) -> keyring::Result<String> {
+            self.0.lock().get(entry).cloned().ok_or(keyring::Error::NoEntry)
+        }
+    }
+
+    #[test]
+    fn keychain_store_removes_stale_file_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = SecretFile::in_dir(dir.path());
+        let os = FakeKeychain::default();
+
+        // Saved to the file while the keychain was away or not preferred
+        file.set_preferred(true).unwrap();
+        KeychainManager::store_in(&os, &file, "conn", "old").unwrap();
+        assert_eq!(file.get("conn").unwrap().as_deref(), Some("old"));
+        assert!(os.get("conn").is_err());
+
+        // Back to the keychain: the update must not be hidden by the file
+        file.set_preferred(false).unwrap();
+        KeychainManager::store_in(&os, &file, "conn", "new").unwrap();
+        assert_eq!(os.get("conn").unwrap(), "new");
+        assert_eq!(file.get("conn").unwrap(), None);
+        assert_eq!(KeychainManager::get_from(&os, &file, "conn").unwrap(), "new");
     }
 }
@@ -1,5 +1,23 @@
+pub mod certificates;
 pub mod connections;
+mod crypto;
+pub mod foreign;
+mod json_file;
 pub mod keychain;
+pub mod portable;
+pub mod rdp_licenses;
+pub mod secret_file;
+pub mod transfer_journal;
+pub mod usage;
 
-pub use connections::{ConnectionProfile, ConnectionStorage, ConnectionType, StoredAuthMethod};
-pub use keychain::KeychainManager;
+pub use certificates::CertificatePins;
+pub use connections::{
+    ConnectionChanges, ConnectionFilter, ConnectionProfile, ConnectionStorage, ConnectionType, ProfileMetadata,
+    ProfileSecrets, StorageEncryptionStatus, StoredAuthMethod, TerminalSettings,
+};
+pub use foreign::ForeignSession;
+pub use keychain::{KeychainBackendStatus, KeychainManager};
+pub use portable::ImportSummary;
+pub use rdp_licenses::{RdpLicenses, StoredLicense};
+pub use transfer_journal::{JournalTracker, ResumableTransfer, TransferJournal};
+pub use usage::{ConnectionStats, UsageLog, UsageTracker};
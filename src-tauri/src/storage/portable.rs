@@ -0,0 +1,342 @@
+use super::connections::{ConnectionProfile, ConnectionStorage, StorageError};
+use super::crypto::{open, seal, Sealed};
+use super::keychain::{Keychain, KeychainManager, SecretStore};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Marks a file as written by `export_connections`
+const FORMAT: &str = "openterm-connections";
+const VERSION: u32 = 1;
+
+/// An export file. The profiles are either in `connections` or, when
+/// a passphrase was given, sealed in `encrypted` together with the secrets.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportFile {
+    format: String,
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    connections: Option<Vec<ConnectionProfile>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted: Option<Sealed>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Payload {
+    connections: Vec<ConnectionProfile>,
+    /// Keychain passwords by profile id
+    #[serde(default)]
+    secrets: HashMap<String, Secrets>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Secrets {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gateway_password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    passphrase: Option<String>,
+}
+
+/// Outcome of `import_connections`
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportSummary {
+    pub imported: Vec<ConnectionProfile>,
+    /// Imported under a new id because one with theirs already existed
+    pub renamed_ids: usize,
+    /// Passwords that couldn't be put in the keychain
+    pub secret_errors: Vec<String>,
+}
+
+/// Write every profile to `path`. With `include_secrets` their keychain
+/// passwords go along, which needs a `passphrase`; a passphrase always
+/// encrypts the file. Returns how many profiles were written.
+pub fn export_connections(
+    storage: &ConnectionStorage,
+    path: &Path,
+    include_secrets: bool,
+    passphrase: Option<&str>,
+) -> Result<usize, StorageError> {
+    export_with(storage, &Keychain, path, include_secrets, passphrase)
+}
+
+fn export_with(
+    storage: &ConnectionStorage,
+    keychain: &impl SecretStore,
+    path: &Path,
+    include_secrets: bool,
+    passphrase: Option<&str>,
+) -> Result<usize, StorageError> {
+    let passphrase = passphrase.filter(|p| !p.is_empty());
+    if include_secrets && passphrase.is_none() {
+        return Err(StorageError::Export(
+            "A passphrase is required to export passwords".to_string(),
+        ));
+    }
+
+    let connections = storage.list(&Default::default())?;
+    let count = connections.len();
+    let file = match passphrase {
+        None => ExportFile {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            connections: Some(connections),
+            encrypted: None,
+        },
+        Some(passphrase) => {
+            let secrets = if include_secrets {
+                connections
+                    .iter()
+                    .map(|profile| {
+                        let secrets = Secrets {
+                            password: keychain.get(&profile.id),
+                            gateway_password: keychain.get(&KeychainManager::gateway_entry(&profile.id)),
+                            passphrase: keychain.get(&KeychainManager::passphrase_entry(&profile.id)),
+                        };
+                        (profile.id.clone(), secrets)
+                    })
+                    .filter(|(_, s)| s.password.is_some() || s.gateway_password.is_some() || s.passphrase.is_some())
+                    .collect()
+            } else {
+                HashMap::new()
+            };
+            let payload = serde_json::to_vec(&Payload { connections, secrets })?;
+            ExportFile {
+                format: FORMAT.to_string(),
+                version: VERSION,
+                connections: None,
+                encrypted: Some(seal(&payload, passphrase, FORMAT.as_bytes())?),
+            }
+        }
+    };
+
+    fs::write(path, serde_json::to_string_pretty(&file)?)?;
+    Ok(count)
+}
+
+/// Add the profiles in an export file to `storage`. Profiles whose id is
+/// taken get a new one; exported passwords are restored to the keychain.
+pub fn import_connections(
+    storage: &ConnectionStorage,
+    path: &Path,
+    passphrase: Option<&str>,
+) -> Result<ImportSummary, StorageError> {
+    import_with(storage, &Keychain, path, passphrase)
+}
+
+fn import_with(
+    storage: &ConnectionStorage,
+    keychain: &impl SecretStore,
+    path: &Path,
+    passphrase: Option<&str>,
+) -> Result<ImportSummary, StorageError> {
+    let content = fs::read_to_string(path)?;
+    let file: ExportFile = serde_json::from_str(&content)
+        .map_err(|e| StorageError::Export(format!("Not a connections export: {}", e)))?;
+    if file.format != FORMAT {
+        return Err(StorageError::Export("Not a connections export".to_string()));
+    }
+    if file.version > VERSION {
+        return Err(StorageError::Export(format!(
+            "Export version {} is newer than this app supports",
+            file.version
+        )));
+    }
+
+    let payload = match (file.encrypted, file.connections) {
+        (Some(sealed), _) => {
+            let passphrase = passphrase.filter(|p| !p.is_empty()).ok_or_else(|| {
+                StorageError::Export("This export is encrypted; enter its passphrase".to_string())
+            })?;
+            let plaintext = open(&sealed, passphrase, FORMAT.as_bytes())?;
+            serde_json::from_slice(&plaintext)?
+        }
+        (None, Some(connections)) => Payload {
+            connections,
+            secrets: HashMap::new(),
+        },
+        (None, None) => Payload::default(),
+    };
+
+    let mut taken: HashSet<String> = storage
+        .list(&Default::default())?
+        .into_iter()
+        .map(|profile| profile.id)
+        .collect();
+    let mut summary = ImportSummary {
+        imported: Vec::new(),
+        renamed_ids: 0,
+        secret_errors: Vec::new(),
+    };
+
+    let mut secrets = payload.secrets;
+    for mut profile in payload.connections {
+        let exported_id = profile.id.clone();
+        if !taken.insert(profile.id.clone()) {
+            profile.id = Uuid::new_v4().to_string();
+            taken.insert(profile.id.clone());
+            summary.renamed_ids += 1;
+        }
+        storage.save_connection(profile.clone())?;
+
+        let secret = secrets.remove(&exported_id).unwrap_or_default();
+        if let Some(password) = secret.password {
+            if let Err(e) = keychain.store(&profile.id, &password) {
+                summary.secret_errors.push(format!("{}: {}", profile.name, e));
+            }
+        }
+        if let Some(password) = secret.gateway_password {
+            if let Err(e) = keychain.store(&KeychainManager::gateway_entry(&profile.id), &password) {
+                summary.secret_errors.push(format!("{} (gateway): {}", profile.name, e));
+            }
+        }
+        if let Some(passphrase) = secret.passphrase {
+            if let Err(e) = keychain.store(&KeychainManager::passphrase_entry(&profile.id), &passphrase) {
+                summary.secret_errors.push(format!("{} (key passphrase): {}", profile.name, e));
+            }
+        }
+        summary.imported.push(profile);
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::connections::{StoredAuthMethod, TEST_LOCK};
+    use crate::storage::keychain::MemoryKeychain;
+
+    /// A storage holding one SSH profile, and an empty one to import into
+    fn storages(dir: &Path) -> (ConnectionStorage, ConnectionStorage) {
+        let source = ConnectionStorage::at(dir.join("source.json")).unwrap();
+        source
+            .save_connection(ConnectionProfile::new_ssh(
+                "db".to_string(),
+                "db.example.com".to_string(),
+                2222,
+                "admin".to_string(),
+                StoredAuthMethod::Password,
+            ))
+            .unwrap();
+        let target = ConnectionStorage::at(dir.join("target.json")).unwrap();
+        (source, target)
+    }
+
+    fn assert_same_profiles(source: &ConnectionStorage, target: &ConnectionStorage) {
+        let json = |storage: &ConnectionStorage| {
+            serde_json::to_value(storage.list(&Default::default()).unwrap()).unwrap()
+        };
+        assert_eq!(json(source), json(target));
+    }
+
+    #[test]
+    fn plain_export_round_trips() {
+        let _guard = TEST_LOCK.lock();
+        let dir = tempfile::tempdir().unwrap();
+        let (source, target) = storages(dir.path());
+        let path = dir.path().join("export.json");
+
+        assert_eq!(export_connections(&source, &path, false, None).unwrap(), 1);
+        assert!(fs::read_to_string(&path).unwrap().contains("db.example.com"));
+
+        let summary = import_connections(&target, &path, None).unwrap();
+        assert_eq!(summary.imported.len(), 1);
+        assert_eq!(summary.renamed_ids, 0);
+        assert_same_profiles(&source, &target);
+    }
+
+    #[test]
+    fn encrypted_export_round_trips() {
+        let _guard = TEST_LOCK.lock();
+        let dir = tempfile::tempdir().unwrap();
+        let (source, target) = storages(dir.path());
+        let path = dir.path().join("export.json");
+
+        export_connections(&source, &path, false, Some("correct horse")).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("db.example.com"));
+        assert!(matches!(
+            import_connections(&target, &path, None),
+            Err(StorageError::Export(_))
+        ));
+
+        let summary = import_connections(&target, &path, Some("correct horse")).unwrap();
+        assert_eq!(summary.imported.len(), 1);
+        assert_same_profiles(&source, &target);
+    }
+
+    #[test]
+    fn wrong_passphrase_imports_nothing() {
+        let _guard = TEST_LOCK.lock();
+        let dir = tempfile::tempdir().unwrap();
+        let (source, target) = storages(dir.path());
+        let path = dir.path().join("export.json");
+
+        export_connections(&source, &path, false, Some("correct horse")).unwrap();
+        assert!(matches!(
+            import_connections(&target, &path, Some("battery staple")),
+            Err(StorageError::WrongPassphrase)
+        ));
+        assert!(target.list(&Default::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn export_with_secrets_restores_passwords() {
+        let _guard = TEST_LOCK.lock();
+        let dir = tempfile::tempdir().unwrap();
+        let (source, target) = storages(dir.path());
+        let path = dir.path().join("export.json");
+        let id = source.list(&Default::default()).unwrap()[0].id.clone();
+        let source_keychain = MemoryKeychain::default();
+        source_keychain.store(&id, "hunter2").unwrap();
+        source_keychain.store(&KeychainManager::passphrase_entry(&id), "key pass").unwrap();
+
+        export_with(&source, &source_keychain, &path, true, Some("correct horse")).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("hunter2"));
+
+        let target_keychain = MemoryKeychain::default();
+        let summary = import_with(&target, &target_keychain, &path, Some("correct horse")).unwrap();
+        assert!(summary.secret_errors.is_empty());
+        assert_same_profiles(&source, &target);
+        assert_eq!(*target_keychain.0.lock(), *source_keychain.0.lock());
+    }
+
+    #[test]
+    fn secrets_need_a_passphrase() {
+        let _guard = TEST_LOCK.lock();
+        let dir = tempfile::tempdir().unwrap();
+        let (source, _) = storages(dir.path());
+        let path = dir.path().join("export.json");
+        let keychain = MemoryKeychain::default();
+
+        for passphrase in [None, Some("")] {
+            assert!(matches!(
+                export_with(&source, &keychain, &path, true, passphrase),
+                Err(StorageError::Export(_))
+            ));
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn secrets_in_a_plain_file_are_ignored() {
+        let _guard = TEST_LOCK.lock();
+        let dir = tempfile::tempdir().unwrap();
+        let (source, target) = storages(dir.path());
+        let path = dir.path().join("export.json");
+        let id = source.list(&Default::default()).unwrap()[0].id.clone();
+        export_connections(&source, &path, false, None).unwrap();
+
+        // A plain export has nowhere for passwords; one added by hand is not read
+        let mut file: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        file["secrets"] = serde_json::json!({ id.as_str(): { "password": "hunter2" } });
+        fs::write(&path, file.to_string()).unwrap();
+
+        let keychain = MemoryKeychain::default();
+        let summary = import_with(&target, &keychain, &path, None).unwrap();
+        assert_eq!(summary.imported.len(), 1);
+        assert!(keychain.0.lock().is_empty());
+    }
+}
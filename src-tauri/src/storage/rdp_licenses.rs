@@ -0,0 +1,131 @@
+use super::connections::StorageError;
+use super::json_file::JsonFile;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Serializes read-modify-write of rdp_licenses.json between sessions
+static LICENSES_LOCK: Mutex<()> = parking_lot::const_mutex(());
+
+/// A client access license issued by an RDS license server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredLicense {
+    pub version: u32,
+    pub scope: String,
+    pub company_name: String,
+    pub product_id: String,
+    /// License blob, base64
+    pub license: String,
+}
+
+impl StoredLicense {
+    fn is_for(&self, version: u32, scope: &str, company_name: &str, product_id: &str) -> bool {
+        self.version == version
+            && self.scope == scope
+            && self.company_name == company_name
+            && self.product_id == product_id
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct LicensesFile {
+    /// Sent with every license request; licenses are bound to it, so it
+    /// must stay the same between connects
+    #[serde(default)]
+    hardware_id: Option<[u32; 4]>,
+    /// Licenses by server name
+    #[serde(default)]
+    servers: HashMap<String, Vec<StoredLicense>>,
+}
+
+/// RDP client licenses, so licensed RDS farms don't negotiate a new CAL on
+/// every connect
+pub struct RdpLicenses {
+    file: JsonFile<LicensesFile>,
+}
+
+impl RdpLicenses {
+    pub fn new() -> Result<Self, StorageError> {
+        Ok(Self {
+            file: JsonFile::in_config_dir("rdp_licenses.json", &LICENSES_LOCK)?,
+        })
+    }
+
+    /// This client's hardware ID, created on first use
+    pub fn hardware_id(&self) -> Result<[u32; 4], StorageError> {
+        let file = self.file.lock();
+        let mut data = file.load()?;
+        if let Some(hardware_id) = data.hardware_id {
+            return Ok(hardware_id);
+        }
+        let hardware_id = rand::random();
+        data.hardware_id = Some(hardware_id);
+        file.save(&data)?;
+        Ok(hardware_id)
+    }
+
+    /// License blob cached for `server` and the given product. An entry
+    /// that doesn't decode is dropped.
+    pub fn get(
+        &self,
+        server: &str,
+        version: u32,
+        scope: &str,
+        company_name: &str,
+        product_id: &str,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        let file = self.file.lock();
+        let mut data = file.load()?;
+        let Some(licenses) = data.servers.get_mut(&server.to_lowercase()) else {
+            return Ok(None);
+        };
+        let Some(index) = licenses
+            .iter()
+            .position(|license| license.is_for(version, scope, company_name, product_id))
+        else {
+            return Ok(None);
+        };
+
+        match BASE64.decode(&licenses[index].license) {
+            Ok(blob) if !blob.is_empty() => Ok(Some(blob)),
+            _ => {
+                eprintln!("Dropping corrupt RDP license for {}", server);
+                licenses.remove(index);
+                file.save(&data)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Cache a license for `server`, replacing any earlier one for the
+    /// same product
+    pub fn store(&self, server: &str, license: StoredLicense) -> Result<(), StorageError> {
+        let file = self.file.lock();
+        let mut data = file.load()?;
+        let licenses = data.servers.entry(server.to_lowercase()).or_default();
+        licenses.retain(|existing| {
+            !existing.is_for(license.version, &license.scope, &license.company_name, &license.product_id)
+        });
+        licenses.push(license);
+        file.save(&data)
+    }
+
+    /// Drop the licenses cached for `server`
+    pub fn forget(&self, server: &str) -> Result<(), StorageError> {
+        let file = self.file.lock();
+        let mut data = file.load()?;
+        if data.servers.remove(&server.to_lowercase()).is_some() {
+            file.save(&data)?;
+        }
+        Ok(())
+    }
+
+    /// Drop every cached license. The hardware ID is kept.
+    pub fn clear(&self) -> Result<(), StorageError> {
+        let file = self.file.lock();
+        let mut data = file.load()?;
+        data.servers.clear();
+        file.save(&data)
+    }
+}
@@ -0,0 +1,148 @@
+use super::connections::{write_atomic, StorageError};
+use super::crypto::{DerivedKey, Sealed};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use parking_lot::{const_mutex, Mutex};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const AAD: &[u8] = b"openterm-secrets";
+
+/// Serializes load-modify-save of secrets.json across instances
+static LOCK: Mutex<()> = const_mutex(());
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SecretsFile {
+    /// Keep passwords here even when the OS keychain works
+    #[serde(default)]
+    store_secrets_in_file: bool,
+    /// Passwords by keychain entry name, sealed with the key in secrets.key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secrets: Option<Sealed>,
+}
+
+/// Passwords for systems without a usable OS keychain, such as headless
+/// Linux with no secret service. They're encrypted with a random key in
+/// secrets.key, readable only by the user, so a copy of secrets.json alone
+/// reveals nothing; anyone who can read both files can read them.
+pub struct SecretFile {
+    file_path: PathBuf,
+    key_path: PathBuf,
+}
+
+impl SecretFile {
+    pub fn new() -> Result<Self, StorageError> {
+        let config_dir = dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("openterm");
+
+        fs::create_dir_all(&config_dir)?;
+        Ok(Self::in_dir(&config_dir))
+    }
+
+    /// secrets.json and secrets.key in `dir`
+    pub(super) fn in_dir(dir: &Path) -> Self {
+        Self {
+            file_path: dir.join("secrets.json"),
+            key_path: dir.join("secrets.key"),
+        }
+    }
+
+    fn load(&self) -> Result<SecretsFile, StorageError> {
+        if !self.file_path.exists() {
+            return Ok(SecretsFile::default());
+        }
+        let content = fs::read_to_string(&self.file_path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn save(&self, data: &SecretsFile) -> Result<(), StorageError> {
+        write_atomic(&self.file_path, serde_json::to_string_pretty(data)?.as_bytes())
+    }
+
+    /// The key in secrets.key, created on first use
+    fn key(&self) -> Result<DerivedKey, StorageError> {
+        if self.key_path.exists() {
+            let key = BASE64
+                .decode(fs::read_to_string(&self.key_path)?.trim())
+                .ok()
+                .and_then(|key| key.try_into().ok())
+                .ok_or_else(|| StorageError::Crypto("secrets.key is damaged".to_string()))?;
+            return Ok(DerivedKey::from_bytes(key));
+        }
+        let key = DerivedKey::random();
+        write_atomic(&self.key_path, BASE64.encode(key.to_bytes()).as_bytes())?;
+        Ok(key)
+    }
+
+    fn secrets(&self, data: &SecretsFile) -> Result<HashMap<String, String>, StorageError> {
+        match &data.secrets {
+            Some(sealed) => Ok(serde_json::from_slice(&self.key()?.open(sealed, AAD)?)?),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    /// Whether the user asked for passwords to go here rather than to the
+    /// keychain
+    pub fn preferred(&self) -> Result<bool, StorageError> {
+        Ok(self.load()?.store_secrets_in_file)
+    }
+
+    pub fn set_preferred(&self, preferred: bool) -> Result<(), StorageError> {
+        let _guard = LOCK.lock();
+        let mut data = self.load()?;
+        data.store_secrets_in_file = preferred;
+        self.save(&data)
+    }
+
+    /// Password stored under keychain entry `entry`
+    pub fn get(&self, entry: &str) -> Result<Option<String>, StorageError> {
+        let data = self.load()?;
+        Ok(self.secrets(&data)?.remove(entry))
+    }
+
+    /// Set or clear (`None`) a password
+    pub fn set(&self, entry: &str, password: Option<&str>) -> Result<(), StorageError> {
+        let _guard = LOCK.lock();
+        let mut data = self.load()?;
+        let mut secrets = self.secrets(&data)?;
+        let changed = match password {
+            Some(password) => secrets.insert(entry.to_string(), password.to_string()).as_deref() != Some(password),
+            None => secrets.remove(entry).is_some(),
+        };
+        if !changed {
+            return Ok(());
+        }
+        data.secrets = if secrets.is_empty() {
+            None
+        } else {
+            Some(self.key()?.seal(&serde_json::to_vec(&secrets)?, AAD)?)
+        };
+        self.save(&data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn writes_private_files_atomically() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.key");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        // Left over from a crash, with the wrong mode
+        fs::write(dir.path().join("secrets.key.tmp"), "stale").unwrap();
+
+        write_atomic(&path, b"new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(!dir.path().join("secrets.key.tmp").exists());
+    }
+}
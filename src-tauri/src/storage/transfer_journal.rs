@@ -0,0 +1,140 @@
+use super::connections::StorageError;
+use super::json_file::JsonFile;
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Time between journal writes while a transfer runs; at most this much
+/// progress is lost to a crash
+const JOURNAL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Serializes read-modify-write of the journal between transfer threads
+static JOURNAL_LOCK: Mutex<()> = parking_lot::const_mutex(());
+
+/// An SFTP transfer that was interrupted, or is still running
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumableTransfer {
+    /// Id of the transfer that wrote the entry
+    pub id: String,
+    pub is_upload: bool,
+    pub local_path: String,
+    pub remote_path: String,
+    /// `user@host:port` the transfer went to, to pick a session to resume on
+    #[serde(default)]
+    pub server: Option<String>,
+    pub total_bytes: u64,
+    /// Bytes done as of the last journal write
+    pub transferred_bytes: u64,
+    #[serde(default)]
+    pub preserve_metadata: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct JournalFile {
+    #[serde(default)]
+    transfers: Vec<ResumableTransfer>,
+}
+
+/// Transfers in flight, kept on disk so they can be resumed after the app
+/// is closed or crashes mid-transfer
+pub struct TransferJournal {
+    file: JsonFile<JournalFile>,
+}
+
+impl TransferJournal {
+    pub fn new() -> Result<Self, StorageError> {
+        Ok(Self {
+            file: JsonFile::in_config_dir("transfers.json", &JOURNAL_LOCK)?,
+        })
+    }
+
+    /// Journaled transfers, most recently active first
+    pub fn list(&self) -> Result<Vec<ResumableTransfer>, StorageError> {
+        let mut transfers = self.file.lock().load()?.transfers;
+        transfers.sort_by_key(|transfer| std::cmp::Reverse(transfer.updated_at));
+        Ok(transfers)
+    }
+
+    pub fn get(&self, id: &str) -> Result<ResumableTransfer, StorageError> {
+        self.file
+            .lock()
+            .load()?
+            .transfers
+            .into_iter()
+            .find(|transfer| transfer.id == id)
+            .ok_or_else(|| StorageError::NotFound(id.to_string()))
+    }
+
+    /// Add `transfer`, or update the entry with its id
+    pub fn record(&self, transfer: &ResumableTransfer) -> Result<(), StorageError> {
+        let file = self.file.lock();
+        let mut data = file.load()?;
+        match data.transfers.iter_mut().find(|t| t.id == transfer.id) {
+            Some(existing) => *existing = transfer.clone(),
+            None => data.transfers.push(transfer.clone()),
+        }
+        file.save(&data)
+    }
+
+    pub fn remove(&self, id: &str) -> Result<(), StorageError> {
+        let file = self.file.lock();
+        let mut data = file.load()?;
+        let before = data.transfers.len();
+        data.transfers.retain(|transfer| transfer.id != id);
+        if data.transfers.len() != before {
+            file.save(&data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps a running transfer's journal entry current. Journal failures are
+/// logged rather than failing the transfer, which then just can't be
+/// resumed.
+pub struct JournalTracker {
+    entry: ResumableTransfer,
+    last_write: Instant,
+}
+
+impl JournalTracker {
+    /// Journal `entry` as started
+    pub fn start(mut entry: ResumableTransfer) -> Self {
+        entry.updated_at = Utc::now();
+        write_entry(&entry);
+        Self {
+            entry,
+            last_write: Instant::now(),
+        }
+    }
+
+    pub fn update(&mut self, transferred: u64, total: u64) {
+        self.entry.transferred_bytes = transferred;
+        self.entry.total_bytes = total;
+        if self.last_write.elapsed() >= JOURNAL_INTERVAL {
+            self.entry.updated_at = Utc::now();
+            write_entry(&self.entry);
+            self.last_write = Instant::now();
+        }
+    }
+
+    /// Drop the entry once the transfer completed; otherwise (failed or
+    /// cancelled, including by the app closing) record where it stopped
+    pub fn finish(mut self, completed: bool) {
+        if completed {
+            if let Err(e) = TransferJournal::new().and_then(|journal| journal.remove(&self.entry.id)) {
+                eprintln!("Transfer journal: Failed to remove {}: {}", self.entry.id, e);
+            }
+        } else {
+            self.entry.updated_at = Utc::now();
+            write_entry(&self.entry);
+        }
+    }
+}
+
+fn write_entry(entry: &ResumableTransfer) {
+    if let Err(e) = TransferJournal::new().and_then(|journal| journal.record(entry)) {
+        eprintln!("Transfer journal: Failed to record {}: {}", entry.id, e);
+    }
+}
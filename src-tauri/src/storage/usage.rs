@@ -0,0 +1,167 @@
+use super::connections::StorageError;
+use super::json_file::JsonFile;
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Serializes read-modify-write of usage.json between sessions
+static USAGE_LOCK: Mutex<()> = parking_lot::const_mutex(());
+
+/// The most recent failed connect to a profile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectFailure {
+    pub at: DateTime<Utc>,
+    pub error: String,
+}
+
+/// How a saved connection has been used
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    #[serde(default)]
+    pub connection_id: String,
+    /// Successful connects
+    #[serde(default)]
+    pub connect_count: u64,
+    #[serde(default)]
+    pub failure_count: u64,
+    /// Time sessions from this profile were open, counted when they close
+    #[serde(default)]
+    pub total_connected_secs: u64,
+    #[serde(default)]
+    pub last_connected_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_failure: Option<ConnectFailure>,
+}
+
+impl ConnectionStats {
+    /// Latest connect attempt, successful or not
+    fn last_attempt(&self) -> Option<DateTime<Utc>> {
+        self.last_connected_at.max(self.last_failure.as_ref().map(|failure| failure.at))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct UsageFile {
+    /// Stats by profile id
+    #[serde(default)]
+    connections: HashMap<String, ConnectionStats>,
+}
+
+/// Connect history of saved connections, kept apart from connections.json
+/// since it changes on every connect
+pub struct UsageLog {
+    file: JsonFile<UsageFile>,
+}
+
+impl UsageLog {
+    pub fn new() -> Result<Self, StorageError> {
+        Ok(Self {
+            file: JsonFile::in_config_dir("usage.json", &USAGE_LOCK)?,
+        })
+    }
+
+    fn update(&self, connection_id: &str, f: impl FnOnce(&mut ConnectionStats)) -> Result<(), StorageError> {
+        let file = self.file.lock();
+        let mut data = file.load()?;
+        let stats = data
+            .connections
+            .entry(connection_id.to_string())
+            .or_insert_with(|| ConnectionStats {
+                connection_id: connection_id.to_string(),
+                ..Default::default()
+            });
+        f(stats);
+        file.save(&data)
+    }
+
+    pub fn record_connected(&self, connection_id: &str) -> Result<(), StorageError> {
+        self.update(connection_id, |stats| {
+            stats.connect_count += 1;
+            stats.last_connected_at = Some(Utc::now());
+        })
+    }
+
+    pub fn record_failure(&self, connection_id: &str, error: &str) -> Result<(), StorageError> {
+        self.update(connection_id, |stats| {
+            stats.failure_count += 1;
+            stats.last_failure = Some(ConnectFailure {
+                at: Utc::now(),
+                error: error.to_string(),
+            });
+        })
+    }
+
+    /// Add a closed session's duration
+    pub fn record_session(&self, connection_id: &str, duration: Duration) -> Result<(), StorageError> {
+        self.update(connection_id, |stats| {
+            stats.total_connected_secs += duration.as_secs();
+        })
+    }
+
+    /// Zeroes for a profile that was never connected
+    pub fn stats(&self, connection_id: &str) -> Result<ConnectionStats, StorageError> {
+        Ok(self
+            .file
+            .lock()
+            .load()?
+            .connections
+            .remove(connection_id)
+            .unwrap_or_else(|| ConnectionStats {
+                connection_id: connection_id.to_string(),
+                ..Default::default()
+            }))
+    }
+
+    /// Profiles by their latest connect attempt, most recent first
+    pub fn recent(&self, limit: usize) -> Result<Vec<ConnectionStats>, StorageError> {
+        let mut connections: Vec<_> = self.file.lock().load()?.connections.into_values().collect();
+        connections.sort_by_key(|stats| std::cmp::Reverse(stats.last_attempt()));
+        connections.truncate(limit);
+        Ok(connections)
+    }
+
+    pub fn remove(&self, connection_id: &str) -> Result<(), StorageError> {
+        let file = self.file.lock();
+        let mut data = file.load()?;
+        if data.connections.remove(connection_id).is_some() {
+            file.save(&data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts a session opened from a saved profile and adds its duration when
+/// it closes. Usage log failures are logged rather than failing the session.
+pub struct UsageTracker {
+    connection_id: String,
+    started: Instant,
+}
+
+impl UsageTracker {
+    /// Record a successful connect to `connection_id`
+    pub fn start(connection_id: &str) -> Self {
+        if let Err(e) = UsageLog::new().and_then(|log| log.record_connected(connection_id)) {
+            eprintln!("Usage log: Failed to record connect to {}: {}", connection_id, e);
+        }
+        Self {
+            connection_id: connection_id.to_string(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn finish(self) {
+        let duration = self.started.elapsed();
+        if let Err(e) = UsageLog::new().and_then(|log| log.record_session(&self.connection_id, duration)) {
+            eprintln!("Usage log: Failed to record session of {}: {}", self.connection_id, e);
+        }
+    }
+}
+
+/// Record a failed connect to `connection_id`
+pub fn record_failure(connection_id: &str, error: &str) {
+    if let Err(e) = UsageLog::new().and_then(|log| log.record_failure(connection_id, error)) {
+        eprintln!("Usage log: Failed to record failure of {}: {}", connection_id, e);
+    }
+}
@@ -1,14 +1,54 @@
-use super::session::{SessionInfo, SshConnectionInfo, TerminalSession};
+use super::session::{SessionCounters, SessionInfo, SessionStats, SshConnectionInfo, TerminalSession};
+use crate::idle::IdleWatch;
+use crate::proxy::ProxyConfig;
 use crate::ssh::AuthMethod;
-use crate::ssh::SshClient;
+use crate::ssh::client::SshError;
+use crate::ssh::{SshBanner, SshClient, SshConnectStage};
+use crate::storage::UsageTracker;
 use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tauri::{AppHandle, Emitter};
 use uuid::Uuid;
 
+/// How terminal output is read and batched into `terminal-output-*` events
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct OutputConfig {
+    /// Size of a single read from the PTY or SSH channel
+    pub read_buffer_size: usize,
+    /// Emit as soon as this many bytes are pending
+    pub max_batch_bytes: usize,
+    /// Emit pending output at least this often while data keeps arriving
+    pub flush_interval_ms: u64,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            read_buffer_size: 16 * 1024,
+            max_batch_bytes: 64 * 1024,
+            flush_interval_ms: 8,
+        }
+    }
+}
+
+enum OutputChunk {
+    Data(Vec<u8>),
+    /// The reader found nothing to read right now
+    Idle,
+    /// Reading failed or the server hung up; shown to the user
+    Error(String),
+}
+
+/// Terminal type requested when the caller doesn't pick one
+pub const DEFAULT_TERM: &str = "xterm-256color";
+
 pub struct TerminalManager {
     sessions: RwLock<HashMap<String, TerminalSession>>,
+    output_config: RwLock<OutputConfig>,
 }
 
 impl Default for TerminalManager {
@@ -21,12 +61,23 @@ impl TerminalManager {
     pub fn new() -> Self {
         Self {
             sessions: RwLock::new(HashMap::new()),
+            output_config: RwLock::new(OutputConfig::default()),
         }
     }
 
-    pub fn create_local_session(&self) -> Result<SessionInfo, String> {
+    pub fn output_config(&self) -> OutputConfig {
+        *self.output_config.read()
+    }
+
+    /// Applies to output readers started after the change
+    pub fn set_output_config(&self, config: OutputConfig) {
+        *self.output_config.write() = config;
+    }
+
+    /// `term` is the `TERM` to advertise, `DEFAULT_TERM` if not given
+    pub fn create_local_session(&self, term: Option<&str>) -> Result<SessionInfo, String> {
         let id = Uuid::new_v4().to_string();
-        let session = TerminalSession::new_local(id.clone())
+        let session = TerminalSession::new_local(id.clone(), term.unwrap_or(DEFAULT_TERM))
             .map_err(|e| format!("Failed to create terminal session: {}", e))?;
 
         let info = SessionInfo::from(&session);
@@ -40,9 +91,13 @@ impl TerminalManager {
         port: u16,
         username: &str,
         auth: &AuthMethod,
+        term: Option<&str>,
+        proxy: Option<&ProxyConfig>,
+        progress: Option<&dyn Fn(SshConnectStage)>,
     ) -> Result<SessionInfo, String> {
         let id = Uuid::new_v4().to_string();
-        let session = TerminalSession::new_ssh(id.clone(), host, port, username, auth)
+        let term = term.unwrap_or(DEFAULT_TERM);
+        let session = TerminalSession::new_ssh(id.clone(), host, port, username, auth, term, proxy, progress)
             .map_err(|e| format!("Failed to create SSH session: {}", e))?;
 
         let info = SessionInfo::from(&session);
@@ -66,6 +121,39 @@ impl TerminalManager {
         session.resize(cols, rows).map_err(|e| e.to_string())
     }
 
+    /// Close the session once nothing has been typed or printed for `timeout`
+    pub fn set_idle_timeout(
+        self: &Arc<Self>,
+        session_id: &str,
+        timeout: Duration,
+        app_handle: AppHandle,
+    ) -> Result<(), String> {
+        let sessions = self.sessions.read();
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| format!("Session not found: {}", session_id))?;
+
+        let manager = Arc::downgrade(self);
+        let id = session_id.to_string();
+        let watch = IdleWatch::start(session_id, session.activity.clone(), timeout, app_handle, move || {
+            manager
+                .upgrade()
+                .is_some_and(|manager| manager.close_session(&id).is_ok())
+        });
+        *session.idle_watch.lock() = Some(watch);
+        Ok(())
+    }
+
+    /// Count the session's time toward its profile's usage once it closes
+    pub fn track_usage(&self, session_id: &str, tracker: UsageTracker) -> Result<(), String> {
+        let sessions = self.sessions.read();
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| format!("Session not found: {}", session_id))?;
+        *session.usage.lock() = Some(tracker);
+        Ok(())
+    }
+
     pub fn close_session(&self, session_id: &str) -> Result<(), String> {
         let mut sessions = self.sessions.write();
         if let Some(session) = sessions.remove(session_id) {
@@ -76,6 +164,14 @@ impl TerminalManager {
         }
     }
 
+    /// Stop every session, e.g. on application exit
+    pub fn close_all(&self) {
+        let sessions: Vec<_> = self.sessions.write().drain().collect();
+        for (_, session) in sessions {
+            session.stop();
+        }
+    }
+
     pub fn get_session_info(&self, session_id: &str) -> Option<SessionInfo> {
         let sessions = self.sessions.read();
         sessions.get(session_id).map(SessionInfo::from)
@@ -86,11 +182,35 @@ impl TerminalManager {
         sessions.values().map(SessionInfo::from).collect()
     }
 
+    /// `(alive, ssh_channel_eof)` for a session, `None` if it doesn't exist
+    pub fn session_health(&self, session_id: &str) -> Option<(bool, Option<bool>)> {
+        let sessions = self.sessions.read();
+        sessions
+            .get(session_id)
+            .map(|s| (s.is_alive(), s.channel_eof()))
+    }
+
+    pub fn session_stats(&self, session_id: &str) -> Option<SessionStats> {
+        let sessions = self.sessions.read();
+        sessions.get(session_id).map(TerminalSession::stats)
+    }
+
+    /// Shared byte counters of a session, for transfers made on its behalf
+    pub fn session_counters(&self, session_id: &str) -> Option<SessionCounters> {
+        let sessions = self.sessions.read();
+        sessions.get(session_id).map(|s| s.counters.clone())
+    }
+
     pub fn get_ssh_client(&self, session_id: &str) -> Option<Arc<SshClient>> {
         let sessions = self.sessions.read();
         sessions.get(session_id).and_then(|s| s.get_ssh_client())
     }
 
+    pub fn get_ssh_banner(&self, session_id: &str) -> Option<SshBanner> {
+        let sessions = self.sessions.read();
+        sessions.get(session_id).and_then(|s| s.get_ssh_banner())
+    }
+
     pub fn get_ssh_connection_info(&self, session_id: &str) -> Option<SshConnectionInfo> {
         let sessions = self.sessions.read();
         sessions.get(session_id).and_then(|s| s.get_ssh_connection_info())
@@ -107,49 +227,95 @@ impl TerminalManager {
             .ok_or_else(|| "No reader available".to_string())?;
 
         let id = session_id.to_string();
+        let activity = session.activity.clone();
+        let counters = session.counters.clone();
+        let config = *self.output_config.read();
+        let (tx, rx) = mpsc::channel::<OutputChunk>();
 
+        // Reader: pull bytes off the PTY/channel as fast as they come
         std::thread::spawn(move || {
-            let mut buf = [0u8; 4096];
-            let mut accum = Vec::with_capacity(32 * 1024);
-            let mut last_emit = std::time::Instant::now();
-            let event_name = format!("terminal-output-{}", id);
-            let flush_interval = std::time::Duration::from_millis(16);
-            let max_accum = 32 * 1024;
-
+            let mut buf = vec![0u8; config.read_buffer_size.max(1)];
             loop {
                 match reader.read(&mut buf) {
                     Ok(0) => {
-                        // EOF - flush remaining
-                        if !accum.is_empty() {
-                            let _ = app_handle.emit(&event_name, accum.clone());
+                        if let Some(reason) = reader.take_disconnect() {
+                            let _ = tx.send(OutputChunk::Error(SshError::Disconnected(reason).to_string()));
                         }
                         break;
                     }
                     Ok(n) => {
-                        accum.extend_from_slice(&buf[..n]);
-                        let elapsed = last_emit.elapsed();
-                        if accum.len() >= max_accum || elapsed >= flush_interval {
-                            if app_handle.emit(&event_name, std::mem::take(&mut accum)).is_err() {
-                                break;
-                            }
-                            accum.reserve(max_accum);
-                            last_emit = std::time::Instant::now();
+                        activity.touch();
+                        counters.record_received(n);
+                        if tx.send(OutputChunk::Data(buf[..n].to_vec())).is_err() {
+                            break;
                         }
                     }
                     Err(e) => {
                         if e.kind() == std::io::ErrorKind::WouldBlock {
-                            // Natural pause - flush if we have data (good for interactive latency)
-                            if !accum.is_empty() {
-                                if app_handle.emit(&event_name, std::mem::take(&mut accum)).is_err() {
-                                    break;
-                                }
-                                accum.reserve(max_accum);
-                                last_emit = std::time::Instant::now();
+                            // Natural pause - flush what we have (good for interactive latency)
+                            if tx.send(OutputChunk::Idle).is_err() {
+                                break;
                             }
-                            std::thread::sleep(std::time::Duration::from_millis(5));
+                            std::thread::sleep(Duration::from_millis(5));
                             continue;
                         }
                         eprintln!("Error reading from session: {}", e);
+                        let message = match reader.take_disconnect() {
+                            Some(reason) => SshError::Disconnected(reason).to_string(),
+                            None => e.to_string(),
+                        };
+                        let _ = tx.send(OutputChunk::Error(message));
+                        break;
+                    }
+                }
+            }
+        });
+
+        // Emitter: coalesce chunks so bursty output doesn't flood the IPC bridge
+        std::thread::spawn(move || {
+            let event_name = format!("terminal-output-{}", id);
+            let error_event = format!("terminal-error-{}", id);
+            let flush_interval = Duration::from_millis(config.flush_interval_ms);
+            let mut accum: Vec<u8> = Vec::with_capacity(config.max_batch_bytes);
+            let mut batch_started = Instant::now();
+
+            loop {
+                let received = if accum.is_empty() {
+                    rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+                } else {
+                    rx.recv_timeout(flush_interval.saturating_sub(batch_started.elapsed()))
+                };
+
+                let flush = match received {
+                    Ok(OutputChunk::Data(data)) => {
+                        if accum.is_empty() {
+                            batch_started = Instant::now();
+                        }
+                        accum.extend_from_slice(&data);
+                        accum.len() >= config.max_batch_bytes
+                            || batch_started.elapsed() >= flush_interval
+                    }
+                    Ok(OutputChunk::Idle) | Err(RecvTimeoutError::Timeout) => true,
+                    Ok(OutputChunk::Error(message)) => {
+                        // Output that arrived before the error goes out first
+                        if !accum.is_empty() {
+                            let _ = app_handle.emit(&event_name, std::mem::take(&mut accum));
+                        }
+                        let _ = app_handle.emit(&error_event, message);
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        // EOF - flush remaining
+                        if !accum.is_empty() {
+                            let _ = app_handle.emit(&event_name, accum);
+                        }
+                        break;
+                    }
+                };
+
+                if flush && !accum.is_empty() {
+                    let batch = std::mem::replace(&mut accum, Vec::with_capacity(config.max_batch_bytes));
+                    if app_handle.emit(&event_name, batch).is_err() {
                         break;
                     }
                 }
@@ -2,4 +2,4 @@ pub mod manager;
 pub mod pty;
 pub mod session;
 
-pub use manager::TerminalManager;
+pub use manager::{OutputConfig, TerminalManager};
@@ -37,7 +37,7 @@ impl PtyHandle {
         })
     }
 
-    pub fn spawn_shell(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    pub fn spawn_shell(&self, term: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let shell = if cfg!(target_os = "windows") {
             "powershell.exe".to_string()
         } else {
@@ -49,6 +49,8 @@ impl PtyHandle {
         if !cfg!(target_os = "windows") {
             cmd.arg("-l"); // Login shell on Unix
         }
+        cmd.env("TERM", term);
+        cmd.env("COLORTERM", "truecolor");
 
         let slave = self.slave.lock();
         let child = slave.spawn_command(cmd)?;
@@ -80,6 +82,14 @@ impl PtyHandle {
         Ok(())
     }
 
+    /// Whether the shell process is still running
+    pub fn is_alive(&self) -> bool {
+        match self.child.lock().as_mut() {
+            Some(child) => matches!(child.try_wait(), Ok(None)),
+            None => false,
+        }
+    }
+
     pub fn get_reader(&self) -> Arc<Mutex<Box<dyn Read + Send>>> {
         self.reader.clone()
     }
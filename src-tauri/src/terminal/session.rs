@@ -1,17 +1,31 @@
 use super::pty::PtyHandle;
-use crate::ssh::{AuthMethod, SshClient};
+use crate::idle::{Activity, IdleWatch};
+use crate::proxy::ProxyConfig;
+use crate::ssh::{AuthMethod, SshBanner, SshClient, SshConnectStage};
 use crate::ssh::client::SshChannel;
+use crate::ssh::disconnect::SshDisconnect;
+use crate::storage::{TerminalSettings, UsageTracker};
+use crate::util;
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use ssh2::Channel;
 use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum SessionType {
     Local,
-    Ssh { host: String, port: u16, username: String },
+    Ssh {
+        host: String,
+        port: u16,
+        username: String,
+        /// Proxy or jump host the connection goes through, as `host:port`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        via: Option<String>,
+    },
 }
 
 enum SessionBackend {
@@ -20,6 +34,7 @@ enum SessionBackend {
         client: Arc<SshClient>,
         channel: SshChannel,
         auth: AuthMethod,
+        proxy: Option<ProxyConfig>,
     },
 }
 
@@ -30,6 +45,56 @@ pub struct SshConnectionInfo {
     pub port: u16,
     pub username: String,
     pub auth: AuthMethod,
+    pub proxy: Option<ProxyConfig>,
+}
+
+/// Bytes a session has moved. Clones share the counts.
+#[derive(Clone, Default)]
+pub struct SessionCounters(Arc<Counts>);
+
+#[derive(Default)]
+struct Counts {
+    sent: AtomicU64,
+    received: AtomicU64,
+    uploaded: AtomicU64,
+    downloaded: AtomicU64,
+}
+
+impl SessionCounters {
+    pub fn record_sent(&self, bytes: usize) {
+        self.0.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_received(&self, bytes: usize) {
+        self.0.received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Bytes of files uploaded over SFTP opened from the session
+    pub fn record_uploaded(&self, bytes: usize) {
+        self.0.uploaded.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_downloaded(&self, bytes: usize) {
+        self.0.downloaded.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// Counters of a terminal session, for diagnostics and the status bar
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionStats {
+    /// Input written to the shell
+    pub bytes_sent: u64,
+    /// Terminal output read from the shell
+    pub bytes_received: u64,
+    /// File transfers through SFTP browsers opened from the session
+    pub bytes_uploaded: u64,
+    pub bytes_downloaded: u64,
+    pub uptime_secs: u64,
+    /// Time since the last input or output
+    pub idle_secs: u64,
+    pub alive: bool,
+    /// Exit status of the remote shell, once the SSH channel has closed
+    pub exit_status: Option<i32>,
 }
 
 pub struct TerminalSession {
@@ -38,15 +103,24 @@ pub struct TerminalSession {
     pub title: String,
     backend: Option<SessionBackend>,
     running: Arc<Mutex<bool>>,
+    /// Touched on every write and by the output reader
+    pub activity: Activity,
+    /// Set when the session has an idle timeout
+    pub idle_watch: Mutex<Option<IdleWatch>>,
+    /// Set for sessions opened from a saved profile; finished on `stop`
+    pub usage: Mutex<Option<UsageTracker>>,
+    /// Updated on every write and by the output reader
+    pub counters: SessionCounters,
+    opened_at: Instant,
 }
 
 // Safety: All internal types are wrapped in thread-safe primitives
 unsafe impl Sync for TerminalSession {}
 
 impl TerminalSession {
-    pub fn new_local(id: String) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+    pub fn new_local(id: String, term: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         let pty = PtyHandle::new(80, 24)?;
-        pty.spawn_shell()?;
+        pty.spawn_shell(term)?;
 
         Ok(Self {
             id,
@@ -54,6 +128,11 @@ impl TerminalSession {
             title: "Local Terminal".to_string(),
             backend: Some(SessionBackend::Local(pty)),
             running: Arc::new(Mutex::new(true)),
+            activity: Activity::new(),
+            idle_watch: Mutex::new(None),
+            usage: Mutex::new(None),
+            counters: SessionCounters::default(),
+            opened_at: Instant::now(),
         })
     }
 
@@ -63,11 +142,14 @@ impl TerminalSession {
         port: u16,
         username: &str,
         auth: &AuthMethod,
+        term: &str,
+        proxy: Option<&ProxyConfig>,
+        progress: Option<&dyn Fn(SshConnectStage)>,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let client = SshClient::connect(host, port, username, auth)?;
-        let channel = client.open_channel()?;
+        let client = SshClient::connect(host, port, username, auth, proxy, progress)?;
+        let channel = client.open_channel(term)?;
 
-        let title = format!("{}@{}:{}", username, host, port);
+        let title = format!("{}@{}", username, util::host_port(host, port));
 
         Ok(Self {
             id,
@@ -75,19 +157,27 @@ impl TerminalSession {
                 host: host.to_string(),
                 port,
                 username: username.to_string(),
+                via: proxy.map(|proxy| util::host_port(&proxy.host, proxy.port)),
             },
             title,
             backend: Some(SessionBackend::Ssh {
                 client: Arc::new(client),
                 channel,
                 auth: auth.clone(),
+                proxy: proxy.cloned(),
             }),
             running: Arc::new(Mutex::new(true)),
+            activity: Activity::new(),
+            idle_watch: Mutex::new(None),
+            usage: Mutex::new(None),
+            counters: SessionCounters::default(),
+            opened_at: Instant::now(),
         })
     }
 
     pub fn write(&self, data: &[u8]) -> Result<usize, std::io::Error> {
-        match &self.backend {
+        self.activity.touch();
+        let written = match &self.backend {
             Some(SessionBackend::Local(pty)) => pty.write(data),
             Some(SessionBackend::Ssh { channel, .. }) => {
                 channel.write(data).map_err(|e: crate::ssh::client::SshError| {
@@ -98,7 +188,9 @@ impl TerminalSession {
                 std::io::ErrorKind::NotConnected,
                 "No backend available",
             )),
-        }
+        }?;
+        self.counters.record_sent(written);
+        Ok(written)
     }
 
     pub fn resize(&self, cols: u16, rows: u16) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -116,8 +208,46 @@ impl TerminalSession {
         *self.running.lock()
     }
 
+    /// Running and the shell (or SSH channel) hasn't ended
+    pub fn is_alive(&self) -> bool {
+        self.is_running()
+            && match &self.backend {
+                Some(SessionBackend::Local(pty)) => pty.is_alive(),
+                Some(SessionBackend::Ssh { channel, .. }) => !channel.eof(),
+                None => false,
+            }
+    }
+
+    /// For SSH sessions, whether the server closed the channel
+    pub fn channel_eof(&self) -> Option<bool> {
+        match &self.backend {
+            Some(SessionBackend::Ssh { channel, .. }) => Some(channel.eof()),
+            _ => None,
+        }
+    }
+
+    pub fn stats(&self) -> SessionStats {
+        let counts = &self.counters.0;
+        SessionStats {
+            bytes_sent: counts.sent.load(Ordering::Relaxed),
+            bytes_received: counts.received.load(Ordering::Relaxed),
+            bytes_uploaded: counts.uploaded.load(Ordering::Relaxed),
+            bytes_downloaded: counts.downloaded.load(Ordering::Relaxed),
+            uptime_secs: self.opened_at.elapsed().as_secs(),
+            idle_secs: self.activity.idle_for().as_secs(),
+            alive: self.is_alive(),
+            exit_status: match &self.backend {
+                Some(SessionBackend::Ssh { channel, .. }) => channel.exit_status(),
+                _ => None,
+            },
+        }
+    }
+
     pub fn stop(&self) {
         *self.running.lock() = false;
+        if let Some(usage) = self.usage.lock().take() {
+            usage.finish();
+        }
         if let Some(SessionBackend::Ssh { channel, .. }) = &self.backend {
             let _ = channel.close();
         }
@@ -126,9 +256,10 @@ impl TerminalSession {
     pub fn get_reader(&self) -> Option<SessionReader> {
         match &self.backend {
             Some(SessionBackend::Local(pty)) => Some(SessionReader::Local(pty.get_reader())),
-            Some(SessionBackend::Ssh { channel, .. }) => {
-                Some(SessionReader::Ssh(channel.get_reader()))
-            }
+            Some(SessionBackend::Ssh { client, channel, .. }) => Some(SessionReader::Ssh {
+                channel: channel.get_reader(),
+                client: client.clone(),
+            }),
             None => None,
         }
     }
@@ -140,17 +271,26 @@ impl TerminalSession {
         }
     }
 
+    /// Get the banners the SSH server presented while connecting
+    pub fn get_ssh_banner(&self) -> Option<SshBanner> {
+        match &self.backend {
+            Some(SessionBackend::Ssh { client, .. }) => Some(client.banner().clone()),
+            _ => None,
+        }
+    }
+
     /// Get SSH connection info for creating a separate SFTP connection
     pub fn get_ssh_connection_info(&self) -> Option<SshConnectionInfo> {
         match (&self.session_type, &self.backend) {
             (
-                SessionType::Ssh { host, port, username },
-                Some(SessionBackend::Ssh { auth, .. }),
+                SessionType::Ssh { host, port, username, .. },
+                Some(SessionBackend::Ssh { auth, proxy, .. }),
             ) => Some(SshConnectionInfo {
                 host: host.clone(),
                 port: *port,
                 username: username.clone(),
                 auth: auth.clone(),
+                proxy: proxy.clone(),
             }),
             _ => None,
         }
@@ -159,7 +299,10 @@ impl TerminalSession {
 
 pub enum SessionReader {
     Local(Arc<Mutex<Box<dyn Read + Send>>>),
-    Ssh(Arc<Mutex<Channel>>),
+    Ssh {
+        channel: Arc<Mutex<Channel>>,
+        client: Arc<SshClient>,
+    },
 }
 
 impl SessionReader {
@@ -169,12 +312,21 @@ impl SessionReader {
                 let mut guard = reader.lock();
                 guard.read(buf)
             }
-            SessionReader::Ssh(channel) => {
+            SessionReader::Ssh { channel, client } => {
+                let _session = client.lock_nonblocking();
                 let mut guard = channel.lock();
                 guard.read(buf)
             }
         }
     }
+
+    /// Why the SSH server closed the connection, if it said
+    pub fn take_disconnect(&self) -> Option<SshDisconnect> {
+        match self {
+            SessionReader::Local(_) => None,
+            SessionReader::Ssh { client, .. } => client.take_disconnect(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -182,6 +334,14 @@ pub struct SessionInfo {
     pub id: String,
     pub session_type: SessionType,
     pub title: String,
+    /// Pre-authentication banner shown by the SSH server, if any
+    pub banner: Option<String>,
+    /// Tab settings of the saved connection the session was opened from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terminal_settings: Option<TerminalSettings>,
+    /// The connection goes through a proxy or jump host
+    #[serde(default)]
+    pub tunneled: bool,
 }
 
 impl From<&TerminalSession> for SessionInfo {
@@ -190,6 +350,9 @@ impl From<&TerminalSession> for SessionInfo {
             id: session.id.clone(),
             session_type: session.session_type.clone(),
             title: session.title.clone(),
+            banner: session.get_ssh_banner().and_then(|b| b.message),
+            terminal_settings: None,
+            tunneled: matches!(session.session_type, SessionType::Ssh { via: Some(_), .. }),
         }
     }
 }
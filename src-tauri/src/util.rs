@@ -0,0 +1,141 @@
+use globset::{GlobBuilder, GlobMatcher};
+
+/// Quote `s` for a POSIX shell
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Whether `name` can be an environment variable name in any shell
+pub fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `host` without the brackets around an IPv6 literal, e.g. "::1" for "[::1]"
+pub fn unbracket_host(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// "host:port", with IPv6 literals bracketed as in "[::1]:22"
+pub fn host_port(host: &str, port: u16) -> String {
+    let host = unbracket_host(host);
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// `path` as it can be pasted into a POSIX shell: unchanged when it has
+/// nothing the shell would interpret, single-quoted otherwise
+pub fn shell_path(path: &str) -> String {
+    let plain = !path.is_empty()
+        && path
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-+,:@%=".contains(c));
+    if plain {
+        path.to_string()
+    } else {
+        shell_quote(path)
+    }
+}
+
+/// A local path for pasting into the local terminal's shell, which on
+/// Windows is PowerShell (see `terminal::pty`)
+pub fn local_shell_path(path: &str) -> String {
+    if cfg!(windows) {
+        powershell_path(path)
+    } else {
+        shell_path(path)
+    }
+}
+
+/// Single-quote `s` for PowerShell. Single-quoted strings expand neither
+/// `$` nor backticks; a quote inside one is doubled, and PowerShell counts
+/// the typographic single quotes as quotes too.
+pub fn powershell_quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('\'');
+    for c in s.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// `path` for PowerShell: unchanged when it has nothing PowerShell would
+/// interpret, quoted with `powershell_quote` otherwise
+fn powershell_path(path: &str) -> String {
+    let plain = !path.is_empty()
+        && path
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "\\/._-:".contains(c));
+    if plain {
+        path.to_string()
+    } else {
+        powershell_quote(path)
+    }
+}
+
+/// Glob on entry names for directory listings, e.g. "*.log". Matching
+/// ignores case.
+pub struct NameFilter {
+    matcher: GlobMatcher,
+    /// Keep every directory so the user can still navigate
+    keep_dirs: bool,
+}
+
+impl NameFilter {
+    /// None when `pattern` is missing or blank
+    pub fn new(pattern: Option<&str>, keep_dirs: bool) -> Result<Option<Self>, String> {
+        let Some(pattern) = pattern.map(str::trim).filter(|p| !p.is_empty()) else {
+            return Ok(None);
+        };
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| format!("Invalid filter: {}", e))?;
+        Ok(Some(Self {
+            matcher: glob.compile_matcher(),
+            keep_dirs,
+        }))
+    }
+
+    pub fn keeps(&self, name: &str, is_dir: bool) -> bool {
+        (is_dir && self.keep_dirs) || self.matcher.is_match(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn powershell_quote_doubles_every_single_quote() {
+        assert_eq!(powershell_quote("plain"), "'plain'");
+        assert_eq!(powershell_quote("$HOME `n"), "'$HOME `n'");
+        assert_eq!(powershell_quote("it's"), "'it''s'");
+        for quote in ['\u{2018}', '\u{2019}', '\u{201a}', '\u{201b}'] {
+            assert_eq!(
+                powershell_quote(&format!("a{}; rm x", quote)),
+                format!("'a{0}{0}; rm x'", quote)
+            );
+        }
+        assert_eq!(powershell_quote(""), "''");
+    }
+
+    #[test]
+    fn powershell_path_quotes_what_powershell_would_expand() {
+        assert_eq!(powershell_path(r"C:\Users\me\file.txt"), r"C:\Users\me\file.txt");
+        assert_eq!(powershell_path(r"C:\My Files\$HOME %TEMP%.txt"), r"'C:\My Files\$HOME %TEMP%.txt'");
+        assert_eq!(powershell_path(r"C:\it's `here`"), r"'C:\it''s `here`'");
+        assert_eq!(powershell_path("C:\\a\u{2019}b"), "'C:\\a\u{2019}\u{2019}b'");
+        assert_eq!(powershell_path(""), "''");
+    }
+}
@@ -0,0 +1,979 @@
+//! RFB security handshake.
+//!
+//! The vnc crate only understands None and classic VNC authentication (with
+//! the password cut to 8 bytes), so the handshake is done here and the
+//! authenticated stream is handed to the crate through the local relay.
+
+use crate::certificate::{CertificatePolicy, ServerCertificate, UntrustedCertificate};
+use crate::ssh::SshTunnel;
+use aes::Aes128;
+use des::cipher::generic_array::GenericArray;
+use des::cipher::{BlockEncrypt, KeyInit};
+use des::Des;
+use md5::{Digest, Md5};
+use num_bigint::BigUint;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum VncAuthError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("TLS error: {0}")]
+    Tls(String),
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+    #[error("Server refused connection: {0}")]
+    Refused(String),
+    #[error("Authentication failed: {0}")]
+    Failed(String),
+    #[error("Unsupported VNC security type: {0}")]
+    Unsupported(String),
+    #[error("{0} authentication requires a {1}")]
+    MissingCredential(&'static str, &'static str),
+    /// TLS failed, most likely because the certificate didn't validate
+    #[error("TLS certificate rejected: {0}")]
+    CertificateRejected(String),
+    #[error("{0}")]
+    UntrustedCertificate(Box<UntrustedCertificate>),
+}
+
+/// Which security type to negotiate with the server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VncAuthType {
+    /// Pick the best type the server offers for the given credentials
+    #[default]
+    Auto,
+    None,
+    /// Classic VNC authentication (password limited to 8 characters; longer
+    /// ones are cut, which Auto refuses to do)
+    VncPassword,
+    /// VeNCrypt with X.509 TLS, or plain credentials inside an SSH tunnel
+    VeNCrypt,
+    /// TightVNC security
+    Tight,
+    /// UltraVNC MS-Logon II (username and password)
+    MsLogon,
+    /// Apple Remote Desktop, used by macOS Screen Sharing (macOS account
+    /// username and password)
+    AppleRemoteDesktop,
+}
+
+impl VncAuthType {
+    /// Whether connecting can't succeed without a password
+    pub fn requires_password(&self) -> bool {
+        matches!(
+            self,
+            Self::VncPassword | Self::VeNCrypt | Self::MsLogon | Self::AppleRemoteDesktop
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VncCredentials {
+    pub auth_type: VncAuthType,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+// RFB security types
+const SEC_NONE: u8 = 1;
+const SEC_VNC: u8 = 2;
+const SEC_TIGHT: u8 = 16;
+const SEC_VENCRYPT: u8 = 19;
+const SEC_ARD: u8 = 30;
+const SEC_MS_LOGON_II: u8 = 113;
+
+// VeNCrypt subtypes
+const VENCRYPT_PLAIN: u32 = 256;
+const VENCRYPT_TLS_NONE: u32 = 257;
+const VENCRYPT_TLS_VNC: u32 = 258;
+const VENCRYPT_TLS_PLAIN: u32 = 259;
+const VENCRYPT_X509_NONE: u32 = 260;
+const VENCRYPT_X509_VNC: u32 = 261;
+const VENCRYPT_X509_PLAIN: u32 = 262;
+
+/// Classic VNC authentication only uses the first 8 bytes of the password
+const VNC_PASSWORD_LEN: usize = 8;
+
+/// Apple Remote Desktop sends the username and password in 64-byte,
+/// NUL-terminated fields
+const ARD_FIELD_LEN: usize = 64;
+
+/// MS-Logon II sends the username and password in NUL-terminated fields of
+/// these sizes
+const MS_LOGON_USERNAME_LEN: usize = 256;
+const MS_LOGON_PASSWORD_LEN: usize = 64;
+
+/// Largest Diffie-Hellman key accepted from an ARD server, in bytes
+const ARD_MAX_KEY_LEN: usize = 1024;
+
+/// Most capabilities accepted in a Tight server's tunnel or authentication
+/// list. The protocol defines only a handful of each.
+const TIGHT_MAX_CAPABILITIES: u32 = 64;
+
+fn security_type_name(sec: u8) -> String {
+    match sec {
+        1 => "None".to_string(),
+        2 => "VNC".to_string(),
+        5 => "RA2".to_string(),
+        6 => "RA2ne".to_string(),
+        16 => "Tight".to_string(),
+        17 => "Ultra".to_string(),
+        18 => "TLS".to_string(),
+        19 => "VeNCrypt".to_string(),
+        20 => "SASL".to_string(),
+        21 => "MD5".to_string(),
+        22 => "xvp".to_string(),
+        30 => "Apple Remote Desktop".to_string(),
+        113 => "MS-Logon II".to_string(),
+        n => format!("type {}", n),
+    }
+}
+
+/// Stream to the server, upgraded to TLS when VeNCrypt asks for it
+pub enum RfbStream {
+    Plain(TcpStream),
+    Tls(Box<native_tls::TlsStream<TcpStream>>),
+    /// Forwarded through an SSH connection
+    Ssh(Box<SshTunnel>),
+}
+
+impl Read for RfbStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            RfbStream::Plain(s) => s.read(buf),
+            RfbStream::Tls(s) => s.read(buf),
+            RfbStream::Ssh(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for RfbStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            RfbStream::Plain(s) => s.write(buf),
+            RfbStream::Tls(s) => s.write(buf),
+            RfbStream::Ssh(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RfbStream::Plain(s) => s.flush(),
+            RfbStream::Tls(s) => s.flush(),
+            RfbStream::Ssh(s) => s.flush(),
+        }
+    }
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn read_reason<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = read_u32(r)? as usize;
+    let mut buf = vec![0u8; len.min(64 * 1024)];
+    r.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+/// Negotiate version and security with the server.
+/// On success the stream is positioned right before ClientInit.
+///
+/// VeNCrypt's TLS certificate is checked against `certificates`. Without a
+/// policy the handshake is only probing: it stops with `UntrustedCertificate`
+/// as soon as TLS is up, before any credentials are sent.
+pub fn handshake(
+    mut stream: RfbStream,
+    host: &str,
+    port: u16,
+    credentials: &VncCredentials,
+    certificates: Option<&CertificatePolicy>,
+) -> Result<RfbStream, VncAuthError> {
+    // ProtocolVersion: "RFB xxx.yyy\n"
+    let mut version = [0u8; 12];
+    stream.read_exact(&mut version)?;
+    let minor = parse_version(&version)?;
+    stream.write_all(format!("RFB 003.{:03}\n", minor).as_bytes())?;
+
+    let offered = if minor == 3 {
+        // 3.3: the server decides
+        match read_u32(&mut stream)? {
+            0 => return Err(VncAuthError::Refused(read_reason(&mut stream)?)),
+            sec => vec![sec as u8],
+        }
+    } else {
+        let count = read_u8(&mut stream)?;
+        if count == 0 {
+            return Err(VncAuthError::Refused(read_reason(&mut stream)?));
+        }
+        let mut types = vec![0u8; count as usize];
+        stream.read_exact(&mut types)?;
+        types
+    };
+
+    let sec = choose_security_type(&offered, credentials)?;
+    if minor != 3 {
+        stream.write_all(&[sec])?;
+    }
+
+    // RFB 3.8 always sends a SecurityResult; older versions skip it for None
+    let mut expect_result = minor == 8;
+    match sec {
+        SEC_NONE => {}
+        SEC_VNC => {
+            vnc_auth(&mut stream, credentials)?;
+            expect_result = true;
+        }
+        SEC_TIGHT => {
+            if tight_auth(&mut stream, credentials)? {
+                expect_result = true;
+            }
+        }
+        SEC_VENCRYPT => {
+            stream = vencrypt(stream, host, port, credentials, certificates)?;
+            expect_result = true;
+        }
+        SEC_MS_LOGON_II => {
+            ms_logon(&mut stream, credentials)?;
+            expect_result = true;
+        }
+        SEC_ARD => {
+            ard_auth(&mut stream, credentials)?;
+            expect_result = true;
+        }
+        other => return Err(VncAuthError::Unsupported(security_type_name(other))),
+    }
+
+    if expect_result {
+        match read_u32(&mut stream)? {
+            0 => {}
+            _ => {
+                let reason = if minor == 8 {
+                    read_reason(&mut stream).unwrap_or_default()
+                } else {
+                    String::new()
+                };
+                let reason = if reason.is_empty() {
+                    "invalid credentials".to_string()
+                } else {
+                    reason
+                };
+                return Err(VncAuthError::Failed(reason));
+            }
+        }
+    }
+
+    Ok(stream)
+}
+
+/// Run the handshake only as far as VeNCrypt's TLS upgrade and return the
+/// server's certificate, to show the user after it failed validation
+pub fn probe_certificate(
+    stream: RfbStream,
+    host: &str,
+    port: u16,
+    credentials: &VncCredentials,
+) -> Result<ServerCertificate, VncAuthError> {
+    match handshake(stream, host, port, credentials, None) {
+        Err(VncAuthError::UntrustedCertificate(untrusted)) => Ok(untrusted.certificate),
+        Err(e) => Err(e),
+        Ok(_) => Err(VncAuthError::Tls("server didn't negotiate TLS".to_string())),
+    }
+}
+
+/// Returns the minor version to speak (3, 7 or 8)
+fn parse_version(version: &[u8; 12]) -> Result<u32, VncAuthError> {
+    let text = String::from_utf8_lossy(version);
+    if !text.starts_with("RFB ") || version[7] != b'.' || version[11] != b'\n' {
+        return Err(VncAuthError::Protocol("not a VNC server".to_string()));
+    }
+    let major: u32 = text[4..7]
+        .parse()
+        .map_err(|_| VncAuthError::Protocol(format!("bad version {}", text.trim())))?;
+    let minor: u32 = text[8..11]
+        .parse()
+        .map_err(|_| VncAuthError::Protocol(format!("bad version {}", text.trim())))?;
+
+    Ok(match (major, minor) {
+        (3, 7) => 7,
+        (3, m) if m < 7 => 3,
+        _ => 8,
+    })
+}
+
+fn choose_security_type(offered: &[u8], credentials: &VncCredentials) -> Result<u8, VncAuthError> {
+    let has_password = credentials.password.as_deref().is_some_and(|p| !p.is_empty());
+    let has_username = credentials.username.as_deref().is_some_and(|u| !u.is_empty());
+    let long_password = credentials
+        .password
+        .as_deref()
+        .is_some_and(|p| p.len() > VNC_PASSWORD_LEN);
+
+    let preference: Vec<u8> = match credentials.auth_type {
+        VncAuthType::Auto if !has_password => vec![SEC_NONE, SEC_TIGHT, SEC_VENCRYPT],
+        // Only types that carry the whole password; classic VNC auth (also
+        // used by Tight) would cut it
+        VncAuthType::Auto if long_password => {
+            let password_len = credentials.password.as_deref().map_or(0, str::len);
+            let mut types = vec![SEC_VENCRYPT];
+            if has_username && password_len < ARD_FIELD_LEN {
+                types.push(SEC_ARD);
+            }
+            if has_username && password_len < MS_LOGON_PASSWORD_LEN {
+                types.push(SEC_MS_LOGON_II);
+            }
+            types
+        }
+        // A Mac offering both wants the account login for a username; its
+        // VNC password is a separate, optional setting
+        VncAuthType::Auto if has_username && offered.contains(&SEC_ARD) => {
+            vec![SEC_ARD, SEC_VNC, SEC_VENCRYPT, SEC_TIGHT]
+        }
+        VncAuthType::Auto => {
+            let mut types = vec![SEC_VNC, SEC_VENCRYPT, SEC_TIGHT];
+            if has_username {
+                types.push(SEC_MS_LOGON_II);
+            }
+            types
+        }
+        VncAuthType::None => vec![SEC_NONE],
+        VncAuthType::VncPassword => vec![SEC_VNC],
+        VncAuthType::VeNCrypt => vec![SEC_VENCRYPT],
+        VncAuthType::Tight => vec![SEC_TIGHT],
+        VncAuthType::MsLogon => vec![SEC_MS_LOGON_II],
+        VncAuthType::AppleRemoteDesktop => vec![SEC_ARD],
+    };
+
+    if let Some(sec) = preference.iter().find(|t| offered.contains(t)) {
+        return Ok(*sec);
+    }
+
+    let names: Vec<String> = offered.iter().map(|t| security_type_name(*t)).collect();
+    let wanted = match credentials.auth_type {
+        VncAuthType::Auto if long_password => {
+            return Err(VncAuthError::Unsupported(format!(
+                "{} (the password is longer than the {} characters classic VNC authentication can carry; choose VNC password authentication to send only the first {})",
+                names.join(", "),
+                VNC_PASSWORD_LEN,
+                VNC_PASSWORD_LEN
+            )))
+        }
+        VncAuthType::Auto => return Err(VncAuthError::Unsupported(names.join(", "))),
+        VncAuthType::None => "None",
+        VncAuthType::VncPassword => "VNC",
+        VncAuthType::VeNCrypt => "VeNCrypt",
+        VncAuthType::Tight => "Tight",
+        VncAuthType::MsLogon => "MS-Logon II",
+        VncAuthType::AppleRemoteDesktop => "Apple Remote Desktop",
+    };
+    Err(VncAuthError::Unsupported(format!(
+        "{} is not offered by the server (offers: {})",
+        wanted,
+        names.join(", ")
+    )))
+}
+
+/// VNC's DES key: the password bytes with each byte's bits reversed
+fn vnc_des(key: &[u8]) -> Des {
+    let mut reversed = [0u8; 8];
+    for (dst, src) in reversed.iter_mut().zip(key) {
+        *dst = src.reverse_bits();
+    }
+    Des::new(GenericArray::from_slice(&reversed))
+}
+
+/// Classic VNC challenge-response. A longer password is only cut to 8
+/// bytes when VNC password authentication was chosen explicitly.
+fn vnc_auth<S: Read + Write>(stream: &mut S, credentials: &VncCredentials) -> Result<(), VncAuthError> {
+    let password = credentials
+        .password
+        .as_deref()
+        .ok_or(VncAuthError::MissingCredential("VNC", "password"))?;
+    if password.len() > VNC_PASSWORD_LEN {
+        if credentials.auth_type != VncAuthType::VncPassword {
+            return Err(VncAuthError::Unsupported(format!(
+                "classic VNC authentication with a password longer than {} characters; choose VNC password authentication to send only the first {}",
+                VNC_PASSWORD_LEN, VNC_PASSWORD_LEN
+            )));
+        }
+        eprintln!(
+            "VNC: server only supports classic VNC authentication, using the first {} characters of the password",
+            VNC_PASSWORD_LEN
+        );
+    }
+
+    let mut challenge = [0u8; 16];
+    stream.read_exact(&mut challenge)?;
+
+    let cipher = vnc_des(password.as_bytes());
+    for block in challenge.chunks_mut(8) {
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+    }
+    stream.write_all(&challenge)?;
+    Ok(())
+}
+
+/// The number of Tight capabilities of `kind` the server is about to send
+fn read_tight_count<S: Read>(stream: &mut S, kind: &str) -> Result<u32, VncAuthError> {
+    let count = read_u32(stream)?;
+    if count > TIGHT_MAX_CAPABILITIES {
+        return Err(VncAuthError::Protocol(format!(
+            "server lists {} Tight {} types",
+            count, kind
+        )));
+    }
+    Ok(count)
+}
+
+/// TightVNC security. Returns true if an authentication scheme was run.
+fn tight_auth<S: Read + Write>(stream: &mut S, credentials: &VncCredentials) -> Result<bool, VncAuthError> {
+    // Tunnel capabilities - always pick "no tunneling"
+    let tunnels = read_tight_count(stream, "tunnel")?;
+    if tunnels > 0 {
+        for _ in 0..tunnels {
+            let mut cap = [0u8; 16];
+            stream.read_exact(&mut cap)?;
+        }
+        stream.write_all(&0u32.to_be_bytes())?;
+    }
+
+    let auth_count = read_tight_count(stream, "authentication")?;
+    if auth_count == 0 {
+        return Ok(false);
+    }
+
+    let mut codes = Vec::new();
+    for _ in 0..auth_count {
+        let mut cap = [0u8; 16];
+        stream.read_exact(&mut cap)?;
+        codes.push(u32::from_be_bytes([cap[0], cap[1], cap[2], cap[3]]));
+    }
+
+    let has_password = credentials.password.as_deref().is_some_and(|p| !p.is_empty());
+    if codes.contains(&(SEC_VNC as u32)) && (has_password || !codes.contains(&(SEC_NONE as u32))) {
+        stream.write_all(&(SEC_VNC as u32).to_be_bytes())?;
+        vnc_auth(stream, credentials)?;
+        Ok(true)
+    } else if codes.contains(&(SEC_NONE as u32)) {
+        stream.write_all(&(SEC_NONE as u32).to_be_bytes())?;
+        Ok(false)
+    } else {
+        let names: Vec<String> = codes.iter().map(|c| format!("code {}", c)).collect();
+        Err(VncAuthError::Unsupported(format!(
+            "Tight authentication schemes: {}",
+            names.join(", ")
+        )))
+    }
+}
+
+/// VeNCrypt: optional X.509 TLS followed by None, VNC or plain credentials.
+/// Plain credentials are only sent inside TLS or an SSH tunnel.
+fn vencrypt(
+    mut stream: RfbStream,
+    host: &str,
+    port: u16,
+    credentials: &VncCredentials,
+    certificates: Option<&CertificatePolicy>,
+) -> Result<RfbStream, VncAuthError> {
+    let major = read_u8(&mut stream)?;
+    let minor = read_u8(&mut stream)?;
+    if (major, minor) < (0, 2) {
+        return Err(VncAuthError::Unsupported(format!("VeNCrypt version {}.{}", major, minor)));
+    }
+    stream.write_all(&[0, 2])?;
+    if read_u8(&mut stream)? != 0 {
+        return Err(VncAuthError::Unsupported("VeNCrypt version 0.2 rejected by server".to_string()));
+    }
+
+    let count = read_u8(&mut stream)?;
+    let mut subtypes = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        subtypes.push(read_u32(&mut stream)?);
+    }
+
+    let has_password = credentials.password.as_deref().is_some_and(|p| !p.is_empty());
+    // An SSH tunnel is already encrypted, and TLS can only be layered over TCP here
+    let tls_possible = matches!(stream, RfbStream::Plain(_));
+    let subtype = choose_vencrypt_subtype(&subtypes, has_password, tls_possible)?;
+    let x509 = matches!(subtype, VENCRYPT_X509_NONE | VENCRYPT_X509_VNC | VENCRYPT_X509_PLAIN);
+    if certificates.is_none() && !x509 {
+        return Err(VncAuthError::Tls("server didn't negotiate TLS".to_string()));
+    }
+    stream.write_all(&subtype.to_be_bytes())?;
+
+    if x509 {
+        if read_u8(&mut stream)? != 1 {
+            return Err(VncAuthError::Tls("server refused to start TLS".to_string()));
+        }
+        let tcp = match stream {
+            RfbStream::Plain(tcp) => tcp,
+            _ => unreachable!("TLS is only negotiated over a plain TCP stream"),
+        };
+        stream = RfbStream::Tls(Box::new(start_tls(tcp, host, port, certificates)?));
+    }
+
+    match subtype {
+        VENCRYPT_X509_VNC => vnc_auth(&mut stream, credentials)?,
+        VENCRYPT_X509_PLAIN | VENCRYPT_PLAIN => {
+            let username = credentials.username.as_deref().unwrap_or_default();
+            let password = credentials.password.as_deref().unwrap_or_default();
+            let mut msg = Vec::with_capacity(8 + username.len() + password.len());
+            msg.extend_from_slice(&(username.len() as u32).to_be_bytes());
+            msg.extend_from_slice(&(password.len() as u32).to_be_bytes());
+            msg.extend_from_slice(username.as_bytes());
+            msg.extend_from_slice(password.as_bytes());
+            stream.write_all(&msg)?;
+        }
+        s if s == SEC_VNC as u32 => vnc_auth(&mut stream, credentials)?,
+        _ => {}
+    }
+    stream.flush()?;
+
+    Ok(stream)
+}
+
+/// The VeNCrypt subtype to use out of those the server offers. Credentials
+/// only go inside X.509 TLS, or in plain text when `tls_possible` is false
+/// because the stream is already an SSH tunnel.
+fn choose_vencrypt_subtype(subtypes: &[u32], has_password: bool, tls_possible: bool) -> Result<u32, VncAuthError> {
+    let preference: &[u32] = match (has_password, tls_possible) {
+        (true, true) => &[VENCRYPT_X509_PLAIN, VENCRYPT_X509_VNC, SEC_VNC as u32],
+        // The tunnel already encrypts the credentials
+        (true, false) => &[VENCRYPT_PLAIN, SEC_VNC as u32],
+        (false, true) => &[VENCRYPT_X509_NONE, SEC_NONE as u32],
+        (false, false) => &[SEC_NONE as u32],
+    };
+
+    if let Some(subtype) = preference.iter().find(|s| subtypes.contains(s)) {
+        return Ok(*subtype);
+    }
+    let anonymous_tls = subtypes
+        .iter()
+        .any(|s| matches!(*s, VENCRYPT_TLS_NONE | VENCRYPT_TLS_VNC | VENCRYPT_TLS_PLAIN));
+    let reason = if anonymous_tls {
+        "VeNCrypt anonymous TLS subtypes (TLSNone/TLSVnc/TLSPlain) are not supported; enable X509 subtypes on the server".to_string()
+    } else if has_password && subtypes.contains(&VENCRYPT_PLAIN) {
+        "VeNCrypt Plain would send the password unencrypted; enable X509 subtypes on the server or connect through an SSH tunnel".to_string()
+    } else {
+        let names: Vec<String> = subtypes.iter().map(|s| s.to_string()).collect();
+        format!("VeNCrypt subtypes {}", names.join(", "))
+    };
+    Err(VncAuthError::Unsupported(reason))
+}
+
+/// Upgrade to TLS, validating the certificate against the system roots
+/// unless one is pinned for this server. Without a policy, fail with the
+/// certificate as soon as TLS is up.
+fn start_tls(
+    tcp: TcpStream,
+    host: &str,
+    port: u16,
+    certificates: Option<&CertificatePolicy>,
+) -> Result<native_tls::TlsStream<TcpStream>, VncAuthError> {
+    let pinned = certificates
+        .filter(|c| !c.accept_any)
+        .and_then(|c| c.pinned.as_deref());
+    let verify = certificates.is_some_and(|c| !c.accept_any && c.pinned.is_none());
+
+    let mut builder = native_tls::TlsConnector::builder();
+    if !verify {
+        // Compared by fingerprint below instead
+        builder
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    }
+    let connector = builder.build().map_err(|e| VncAuthError::Tls(e.to_string()))?;
+    let tls = match connector.connect(host, tcp) {
+        Ok(tls) => tls,
+        Err(native_tls::HandshakeError::Failure(e)) if verify => {
+            return Err(VncAuthError::CertificateRejected(e.to_string()))
+        }
+        Err(e) => return Err(VncAuthError::Tls(e.to_string())),
+    };
+
+    if certificates.is_none() || pinned.is_some() {
+        let der = tls
+            .peer_certificate()
+            .and_then(|cert| cert.map(|c| c.to_der()).transpose())
+            .map_err(|e| VncAuthError::Tls(e.to_string()))?
+            .ok_or_else(|| VncAuthError::Tls("server sent no certificate".to_string()))?;
+        let certificate = ServerCertificate::from_der(host, port, &der).map_err(VncAuthError::Tls)?;
+        let reason = match pinned {
+            Some(pinned) if certificate.matches(pinned) => return Ok(tls),
+            Some(_) => "The certificate has changed since it was trusted".to_string(),
+            // Probing; the caller knows why validation failed
+            None => String::new(),
+        };
+        return Err(VncAuthError::UntrustedCertificate(Box::new(UntrustedCertificate {
+            certificate,
+            reason,
+        })));
+    }
+
+    Ok(tls)
+}
+
+/// UltraVNC MS-Logon II: Diffie-Hellman key exchange, then DES-encrypted credentials
+fn ms_logon<S: Read + Write>(stream: &mut S, credentials: &VncCredentials) -> Result<(), VncAuthError> {
+    let username = credentials
+        .username
+        .as_deref()
+        .ok_or(VncAuthError::MissingCredential("MS-Logon", "username"))?;
+    let password = credentials
+        .password
+        .as_deref()
+        .ok_or(VncAuthError::MissingCredential("MS-Logon", "password"))?;
+    // As with ARD, cutting them would only fail authentication with no hint why
+    for (what, value, field_len) in [
+        ("username", username, MS_LOGON_USERNAME_LEN),
+        ("password", password, MS_LOGON_PASSWORD_LEN),
+    ] {
+        if value.len() >= field_len {
+            return Err(VncAuthError::Unsupported(format!(
+                "MS-Logon authentication with a {} longer than {} bytes",
+                what,
+                field_len - 1
+            )));
+        }
+    }
+
+    let generator = read_u64(stream)?;
+    let modulus = read_u64(stream)?;
+    let server_key = read_u64(stream)?;
+    if modulus < 2 {
+        return Err(VncAuthError::Protocol("invalid MS-Logon parameters".to_string()));
+    }
+
+    let private = rand::random::<u64>() % (modulus - 1) + 1;
+    let public = pow_mod(generator, private, modulus);
+    stream.write_all(&public.to_be_bytes())?;
+    let key = pow_mod(server_key, private, modulus).to_be_bytes();
+
+    let mut user = [0u8; MS_LOGON_USERNAME_LEN];
+    let mut pass = [0u8; MS_LOGON_PASSWORD_LEN];
+    user[..username.len()].copy_from_slice(username.as_bytes());
+    pass[..password.len()].copy_from_slice(password.as_bytes());
+
+    ms_logon_encrypt(&mut user, &key);
+    ms_logon_encrypt(&mut pass, &key);
+    stream.write_all(&user)?;
+    stream.write_all(&pass)?;
+    Ok(())
+}
+
+/// Apple Remote Desktop: Diffie-Hellman key agreement, then the username and
+/// password AES-128-encrypted with the MD5 of the shared secret
+fn ard_auth<S: Read + Write>(stream: &mut S, credentials: &VncCredentials) -> Result<(), VncAuthError> {
+    let username = credentials
+        .username
+        .as_deref()
+        .ok_or(VncAuthError::MissingCredential("Apple Remote Desktop", "username"))?;
+    let password = credentials
+        .password
+        .as_deref()
+        .ok_or(VncAuthError::MissingCredential("Apple Remote Desktop", "password"))?;
+    // Each goes in a NUL-terminated field; cutting them would just fail
+    // authentication with no hint why
+    for (what, value) in [("username", username), ("password", password)] {
+        if value.len() >= ARD_FIELD_LEN {
+            return Err(VncAuthError::Unsupported(format!(
+                "Apple Remote Desktop authentication with a {} longer than {} bytes",
+                what,
+                ARD_FIELD_LEN - 1
+            )));
+        }
+    }
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header)?;
+    let generator = u16::from_be_bytes([header[0], header[1]]);
+    let key_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    if key_len == 0 || key_len > ARD_MAX_KEY_LEN {
+        return Err(VncAuthError::Protocol(format!("invalid ARD key length {}", key_len)));
+    }
+    let mut prime = vec![0u8; key_len];
+    stream.read_exact(&mut prime)?;
+    let mut server_key = vec![0u8; key_len];
+    stream.read_exact(&mut server_key)?;
+
+    let prime = BigUint::from_bytes_be(&prime);
+    if prime < BigUint::from(3u8) {
+        return Err(VncAuthError::Protocol("invalid ARD parameters".to_string()));
+    }
+    let mut private = vec![0u8; key_len];
+    rand::thread_rng().fill_bytes(&mut private);
+    let private = BigUint::from_bytes_be(&private) % &prime;
+    // Unused bytes are random rather than zero
+    let mut padding = [0u8; 2 * ARD_FIELD_LEN];
+    rand::thread_rng().fill_bytes(&mut padding);
+
+    stream.write_all(&ard_response(
+        generator,
+        &prime,
+        &server_key,
+        &private,
+        padding,
+        username,
+        password,
+    ))?;
+    Ok(())
+}
+
+/// The client's ARD reply: the username and password fields, encrypted with
+/// the MD5 of the shared secret, then the client's public key. `private` and
+/// `fields` (random bytes the strings are written over) are passed in so the
+/// output is reproducible. Strings must already fit their fields.
+fn ard_response(
+    generator: u16,
+    prime: &BigUint,
+    server_key: &[u8],
+    private: &BigUint,
+    mut fields: [u8; 2 * ARD_FIELD_LEN],
+    username: &str,
+    password: &str,
+) -> Vec<u8> {
+    let key_len = server_key.len();
+    let public = BigUint::from(generator).modpow(private, prime);
+    let shared = BigUint::from_bytes_be(server_key).modpow(private, prime);
+    let key = Md5::digest(fixed_be(&shared, key_len));
+
+    for (field, value) in fields.chunks_mut(ARD_FIELD_LEN).zip([username, password]) {
+        field[..value.len()].copy_from_slice(value.as_bytes());
+        field[value.len()] = 0;
+    }
+    let cipher = Aes128::new(GenericArray::from_slice(&key));
+    for block in fields.chunks_mut(16) {
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+    }
+
+    let mut out = fields.to_vec();
+    out.extend_from_slice(&fixed_be(&public, key_len));
+    out
+}
+
+/// `n` as big-endian bytes, zero-padded on the left to `len`
+fn fixed_be(n: &BigUint, len: usize) -> Vec<u8> {
+    let bytes = n.to_bytes_be();
+    let mut out = vec![0u8; len.saturating_sub(bytes.len())];
+    out.extend_from_slice(&bytes);
+    out
+}
+
+/// UltraVNC's vncEncryptBytes2: DES in CBC mode with the key as IV
+fn ms_logon_encrypt(data: &mut [u8], key: &[u8; 8]) {
+    let cipher = vnc_des(key);
+    let mut previous = *key;
+    for block in data.chunks_mut(8) {
+        for (b, p) in block.iter_mut().zip(previous.iter()) {
+            *b ^= p;
+        }
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+        previous.copy_from_slice(block);
+    }
+}
+
+fn pow_mod(base: u64, exp: u64, modulus: u64) -> u64 {
+    let m = modulus as u128;
+    let mut result: u128 = 1;
+    let mut base = base as u128 % m;
+    let mut exp = exp;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result * base % m;
+        }
+        base = base * base % m;
+        exp >>= 1;
+    }
+    result as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Worked out independently (Python: pow, hashlib.md5, AES-128-ECB from
+    /// the cryptography package) for a 16-byte key with p = 2^127 - 1
+    #[test]
+    fn ard_response_known_answer() {
+        let prime = (BigUint::from(1u8) << 127u32) - 1u8;
+        let server_key: Vec<u8> = (1..=16).collect();
+        let private = BigUint::from(0x1234_5678_9abc_def0_u64);
+        let mut fields = [0u8; 2 * ARD_FIELD_LEN];
+        for (i, b) in fields.iter_mut().enumerate() {
+            *b = (i * 7 + 3) as u8;
+        }
+
+        let response = ard_response(5, &prime, &server_key, &private, fields, "alice", "s3cret");
+        let hex: String = response.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(
+            hex,
+            concat!(
+                "3346de105549a970b28b119b7166f68b24960d22e41644889663b13f46147b5a",
+                "c1c38d728989608981879e506cfe5d7d7d23a87fc37ca12d24a2ab32024c594c",
+                "6c226fcb6e167c9b540848d35a3a28bd139614fd7c314d3fdb09fe32e6cf432d",
+                "2db73426f3da819e760e4ba0c0772e101a09cea75a6715472bd54d41dfe9d745",
+                "0c1680872c3150e7c119f4f6f0f6c6d8",
+            )
+        );
+    }
+
+    #[test]
+    fn ard_refuses_credentials_that_do_not_fit() {
+        let credentials = |username: &str, password: &str| VncCredentials {
+            username: Some(username.to_string()),
+            password: Some(password.to_string()),
+            ..Default::default()
+        };
+        let fits = "x".repeat(ARD_FIELD_LEN - 1);
+        let too_long = "x".repeat(ARD_FIELD_LEN);
+
+        for (username, password) in [(&too_long, &fits), (&fits, &too_long)] {
+            // Refused before anything is read from the server
+            let mut stream = io::Cursor::new(Vec::new());
+            let err = ard_auth(&mut stream, &credentials(username, password)).unwrap_err();
+            assert!(matches!(err, VncAuthError::Unsupported(_)), "{}", err);
+        }
+
+        // One that fits gets as far as reading the server's parameters
+        let mut stream = io::Cursor::new(Vec::new());
+        let err = ard_auth(&mut stream, &credentials(&fits, &fits)).unwrap_err();
+        assert!(matches!(err, VncAuthError::Io(_)), "{}", err);
+    }
+
+    fn credentials(auth_type: VncAuthType, username: Option<&str>, password: Option<&str>) -> VncCredentials {
+        VncCredentials {
+            auth_type,
+            username: username.map(str::to_string),
+            password: password.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn ms_logon_refuses_credentials_that_do_not_fit() {
+        let user_fits = "u".repeat(MS_LOGON_USERNAME_LEN - 1);
+        let user_too_long = "u".repeat(MS_LOGON_USERNAME_LEN);
+        let pass_fits = "p".repeat(MS_LOGON_PASSWORD_LEN - 1);
+        let pass_too_long = "p".repeat(MS_LOGON_PASSWORD_LEN);
+
+        for (username, password) in [(&user_too_long, &pass_fits), (&user_fits, &pass_too_long)] {
+            let mut stream = io::Cursor::new(Vec::new());
+            let creds = credentials(VncAuthType::MsLogon, Some(username), Some(password));
+            let err = ms_logon(&mut stream, &creds).unwrap_err();
+            assert!(matches!(err, VncAuthError::Unsupported(_)), "{}", err);
+        }
+
+        // Ones that fit get as far as reading the server's parameters
+        let mut stream = io::Cursor::new(Vec::new());
+        let creds = credentials(VncAuthType::MsLogon, Some(&user_fits), Some(&pass_fits));
+        let err = ms_logon(&mut stream, &creds).unwrap_err();
+        assert!(matches!(err, VncAuthError::Io(_)), "{}", err);
+    }
+
+    #[test]
+    fn tight_refuses_oversized_capability_lists() {
+        let creds = credentials(VncAuthType::Tight, None, None);
+        let huge = u32::MAX.to_be_bytes().to_vec();
+        let mut after_tunnels = 0u32.to_be_bytes().to_vec();
+        after_tunnels.extend_from_slice(&huge);
+
+        for input in [huge.clone(), after_tunnels] {
+            let mut stream = io::Cursor::new(input);
+            let err = tight_auth(&mut stream, &creds).unwrap_err();
+            assert!(matches!(err, VncAuthError::Protocol(_)), "{}", err);
+        }
+    }
+
+    #[test]
+    fn security_type_preference() {
+        let all = [SEC_NONE, SEC_VNC, SEC_TIGHT, SEC_VENCRYPT, SEC_ARD, SEC_MS_LOGON_II];
+        let long = "p".repeat(VNC_PASSWORD_LEN + 1);
+        let too_long_for_ard = "p".repeat(ARD_FIELD_LEN);
+        let cases: [(&[u8], VncCredentials, u8); 11] = [
+            (&all, credentials(VncAuthType::Auto, None, None), SEC_NONE),
+            (&[SEC_VNC, SEC_TIGHT], credentials(VncAuthType::Auto, None, None), SEC_TIGHT),
+            (&all, credentials(VncAuthType::Auto, None, Some("secret")), SEC_VNC),
+            (&[SEC_NONE, SEC_TIGHT], credentials(VncAuthType::Auto, None, Some("secret")), SEC_TIGHT),
+            // A username on a Mac means the account login
+            (&all, credentials(VncAuthType::Auto, Some("me"), Some("secret")), SEC_ARD),
+            (&[SEC_NONE, SEC_MS_LOGON_II], credentials(VncAuthType::Auto, Some("me"), Some("secret")), SEC_MS_LOGON_II),
+            // Classic VNC auth would cut a long password
+            (&all, credentials(VncAuthType::Auto, Some("me"), Some(&long)), SEC_VENCRYPT),
+            (&[SEC_VNC, SEC_ARD], credentials(VncAuthType::Auto, Some("me"), Some(&long)), SEC_ARD),
+            (&[SEC_VNC, SEC_MS_LOGON_II], credentials(VncAuthType::Auto, Some("me"), Some(&long)), SEC_MS_LOGON_II),
+            // Explicit choices are used even when something else is offered first
+            (&all, credentials(VncAuthType::Tight, None, Some("secret")), SEC_TIGHT),
+            (&all, credentials(VncAuthType::MsLogon, Some("me"), Some("secret")), SEC_MS_LOGON_II),
+        ];
+        for (offered, creds, expected) in cases {
+            assert_eq!(choose_security_type(offered, &creds).unwrap(), expected, "{:?} {:?}", offered, creds);
+        }
+
+        let refused = [
+            (&[SEC_VNC, SEC_TIGHT][..], credentials(VncAuthType::Auto, None, Some(&long))),
+            (&[SEC_VNC, SEC_ARD][..], credentials(VncAuthType::Auto, Some("me"), Some(&too_long_for_ard))),
+            (&[SEC_VNC][..], credentials(VncAuthType::VeNCrypt, None, Some("secret"))),
+        ];
+        for (offered, creds) in refused {
+            assert!(choose_security_type(offered, &creds).is_err(), "{:?} {:?}", offered, creds);
+        }
+    }
+
+    #[test]
+    fn vencrypt_subtype_choice() {
+        let vnc = SEC_VNC as u32;
+        let none = SEC_NONE as u32;
+        let all = [
+            none,
+            vnc,
+            VENCRYPT_PLAIN,
+            VENCRYPT_TLS_NONE,
+            VENCRYPT_X509_NONE,
+            VENCRYPT_X509_VNC,
+            VENCRYPT_X509_PLAIN,
+        ];
+        // (offered, has_password, tls_possible, expected)
+        let cases: [(&[u32], bool, bool, u32); 7] = [
+            (&all, true, true, VENCRYPT_X509_PLAIN),
+            (&[vnc, VENCRYPT_X509_VNC], true, true, VENCRYPT_X509_VNC),
+            (&[VENCRYPT_PLAIN, vnc], true, true, vnc),
+            // Inside an SSH tunnel plain credentials are fine
+            (&all, true, false, VENCRYPT_PLAIN),
+            (&all, false, true, VENCRYPT_X509_NONE),
+            (&[none, VENCRYPT_X509_VNC], false, true, none),
+            (&all, false, false, none),
+        ];
+        for (offered, has_password, tls_possible, expected) in cases {
+            assert_eq!(
+                choose_vencrypt_subtype(offered, has_password, tls_possible).unwrap(),
+                expected,
+                "{:?} password={} tls={}",
+                offered,
+                has_password,
+                tls_possible
+            );
+        }
+
+        // Plain over the open network, and anonymous TLS, are refused
+        assert!(choose_vencrypt_subtype(&[VENCRYPT_PLAIN], true, true).is_err());
+        assert!(choose_vencrypt_subtype(&[VENCRYPT_TLS_NONE, VENCRYPT_TLS_VNC], false, true).is_err());
+    }
+}
@@ -1,63 +1,86 @@
-use std::io::{Read, Write};
-use std::net::TcpStream;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use parking_lot::Mutex;
-use vnc::{Client, PixelFormat, Rect};
+use vnc::client::Event;
+use vnc::{Client, Rect};
+
+use super::auth::{self, RfbStream};
+use super::{relay, FrameBuffer, InputBatcher, InputEvent, VncColorDepth, VncCredentials, VncCursor, VncEncoding};
+use crate::certificate::CertificatePolicy;
+use crate::frame::{coalesce_regions, DirtyRect, FrameUpdate, Region};
+
+/// Probe an idle server this often so a dead connection is noticed
+const VNC_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Give up on a server that hasn't sent anything for this long
+const VNC_IDLE_TIMEOUT: Duration = Duration::from_secs(45);
 
 pub struct VncClient {
     client: Arc<Mutex<Client>>,
-    width: u16,
-    height: u16,
+    /// Local copy of the remote screen (RGBA)
+    framebuffer: Arc<Mutex<FrameBuffer>>,
+    /// Regions changed since the last emitted update
+    pending_regions: Mutex<Vec<Region>>,
+    /// Whether the initial full frame has been produced
+    full_frame_sent: Mutex<bool>,
+    /// Coalesces pointer moves before they hit the network
+    input_batcher: Mutex<InputBatcher>,
+    encoding: VncEncoding,
+    /// Throughput of the initial full update in bytes/sec, used by Auto encoding
+    bandwidth: Option<f64>,
+    /// Our end of the relay socket; shutting it down tears down the transport
+    relay_socket: TcpStream,
+    /// Set by `close()` when we end the session ourselves
+    closed: AtomicBool,
+    /// Cleared once the server goes away or stops responding
+    connected: AtomicBool,
+    /// When the server last sent anything
+    last_activity: Mutex<Instant>,
+    /// When we last asked the server for a reply while idle
+    last_probe: Mutex<Instant>,
+    /// Current desktop size; changes when the server sends DesktopSize
+    size: Mutex<(u16, u16)>,
+    /// New size not yet reported to the frontend
+    pending_resize: Mutex<Option<(u16, u16)>>,
+    /// Cursor shape not yet reported to the frontend
+    pending_cursor: Mutex<Option<VncCursor>>,
     connection_info: super::VncConnectionInfo,
 }
 
 impl VncClient {
+    /// Open a session over `stream`, which is a direct TCP connection or an SSH tunnel
     pub fn connect(
+        stream: RfbStream,
         host: &str,
         port: u16,
-        password: Option<&str>,
+        credentials: &VncCredentials,
+        certificates: &CertificatePolicy,
+        encoding: VncEncoding,
+        quality: u8,
+        color_depth: VncColorDepth,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let tcp = TcpStream::connect(format!("{}:{}", host, port))?;
-        tcp.set_nonblocking(false)?;
-
-        let mut client = Client::from_tcp_stream(tcp, false, |_auth_methods| {
-            if let Some(pwd) = password {
-                // VNC password is DES-encrypted 8 bytes
-                let mut key = [0u8; 8];
-                let bytes = pwd.as_bytes();
-                let len = bytes.len().min(8);
-                key[..len].copy_from_slice(&bytes[..len]);
-                Some(vnc::client::AuthChoice::Password(key))
-            } else {
-                Some(vnc::client::AuthChoice::None)
-            }
-        })?;
+        // Authenticate ourselves, then let the vnc crate take over the
+        // session through a relay that needs no further authentication
+        let stream = auth::handshake(stream, host, port, credentials, Some(certificates))?;
+        let local = relay::spawn(stream)?;
+        let relay_socket = local.try_clone()?;
+        let mut client =
+            Client::from_tcp_stream(local, false, |_auth_methods| Some(vnc::client::AuthChoice::None))?;
 
         // Get framebuffer info
         let width = client.size().0;
         let height = client.size().1;
 
-        // Request preferred pixel format (RGBA)
-        let pixel_format = PixelFormat {
-            bits_per_pixel: 32,
-            depth: 24,
-            big_endian: false,
-            true_colour: true,
-            red_max: 255,
-            green_max: 255,
-            blue_max: 255,
-            red_shift: 16,
-            green_shift: 8,
-            blue_shift: 0,
-        };
-        client.set_format(pixel_format)?;
-
-        // Set encodings (prefer efficient ones)
-        client.set_encodings(&[
-            vnc::Encoding::Zrle,
-            vnc::Encoding::CopyRect,
-            vnc::Encoding::Raw,
-        ])?;
+        // set_format pulls one full Raw update in the server's format before
+        // switching to the configured one, which doubles as a bandwidth probe
+        let raw_bytes = width as f64 * height as f64 * (client.format().bits_per_pixel / 8) as f64;
+        let started = Instant::now();
+        client.set_format(color_depth.pixel_format())?;
+        let elapsed = started.elapsed().as_secs_f64();
+        let bandwidth = if elapsed > 0.0 { Some(raw_bytes / elapsed) } else { None };
+
+        client.set_encodings(&encoding.encoding_list(quality, bandwidth))?;
 
         // Request initial screen update
         client.request_update(
@@ -72,8 +95,20 @@ impl VncClient {
 
         Ok(Self {
             client: Arc::new(Mutex::new(client)),
-            width,
-            height,
+            framebuffer: Arc::new(Mutex::new(FrameBuffer::new(width, height))),
+            pending_regions: Mutex::new(Vec::new()),
+            full_frame_sent: Mutex::new(false),
+            input_batcher: Mutex::new(InputBatcher::new()),
+            encoding,
+            bandwidth,
+            relay_socket,
+            closed: AtomicBool::new(false),
+            connected: AtomicBool::new(true),
+            last_activity: Mutex::new(Instant::now()),
+            last_probe: Mutex::new(Instant::now()),
+            size: Mutex::new((width, height)),
+            pending_resize: Mutex::new(None),
+            pending_cursor: Mutex::new(None),
             connection_info: super::VncConnectionInfo {
                 host: host.to_string(),
                 port,
@@ -87,9 +122,11 @@ impl VncClient {
         y: u16,
         button_mask: u8,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.ensure_connected()?;
         let mut client = self.client.lock();
-        client.send_pointer_event(button_mask, x, y)?;
-        Ok(())
+        client
+            .send_pointer_event(button_mask, x, y)
+            .map_err(|e| self.connection_lost(e))
     }
 
     pub fn send_key_event(
@@ -97,8 +134,42 @@ impl VncClient {
         key: u32,
         down: bool,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.ensure_connected()?;
         let mut client = self.client.lock();
-        client.send_key_event(down, key)?;
+        client
+            .send_key_event(down, key)
+            .map_err(|e| self.connection_lost(e))
+    }
+
+    /// Renegotiate compression quality (0-9) by re-sending SetEncodings
+    pub fn set_quality(&self, quality: u8) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let encodings = self.encoding.encoding_list(quality, self.bandwidth);
+        self.client.lock().set_encodings(&encodings)?;
+        Ok(())
+    }
+
+    /// Queue an input event, sending whatever the batcher releases
+    pub fn queue_input(&self, event: InputEvent) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.ensure_connected()?;
+        let events = self.input_batcher.lock().add_event(event);
+        self.send_events(events)
+    }
+
+    /// Send a pointer move held back by the batcher once its window has passed
+    pub fn flush_input(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let events = self.input_batcher.lock().maybe_flush();
+        self.send_events(events)
+    }
+
+    fn send_events(&self, events: Vec<InputEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for event in events {
+            match event {
+                InputEvent::Pointer { x, y, button_mask } => {
+                    self.send_pointer_event(x, y, button_mask)?
+                }
+                InputEvent::Key { key, down } => self.send_key_event(key, down)?,
+            }
+        }
         Ok(())
     }
 
@@ -106,39 +177,213 @@ impl VncClient {
         &self,
         incremental: bool,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (width, height) = *self.size.lock();
         let mut client = self.client.lock();
         client.request_update(
             Rect {
                 left: 0,
                 top: 0,
-                width: self.width,
-                height: self.height,
+                width,
+                height,
             },
             incremental,
-        )?;
-        Ok(())
+        )
+        .map_err(|e| self.connection_lost(e))
     }
 
-    pub fn read_event(&self) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    /// Drain pending server events into the framebuffer.
+    /// Returns a frame update once the server has finished sending a frame.
+    pub fn poll_frame(&self) -> Result<Option<FrameUpdate>, Box<dyn std::error::Error + Send + Sync>> {
         let mut client = self.client.lock();
-        
-        match client.poll_event() {
-            Some(event) => {
-                // Process event and return framebuffer data if available
-                // For now, return None as placeholder - actual implementation would handle
-                // framebuffer updates from the VNC server
-                Ok(None)
+        let format = client.format();
+        let mut frame_complete = false;
+        let mut resized = false;
+
+        let mut received = false;
+
+        while let Some(event) = client.poll_event() {
+            received = true;
+            match event {
+                Event::PutPixels(rect, pixels) => {
+                    self.framebuffer.lock().put_pixels(&rect, &pixels, &format);
+                    self.mark_dirty(&rect);
+                }
+                Event::CopyPixels { src, dst } => {
+                    self.framebuffer.lock().copy_rect(&src, &dst);
+                    self.mark_dirty(&dst);
+                }
+                Event::SetColourMap { first_colour, colours } => {
+                    self.framebuffer.lock().set_colour_map(first_colour, &colours);
+                }
+                Event::SetCursor { size, hotspot, pixels, mask_bits } => {
+                    let cursor = VncCursor::decode(
+                        &self.framebuffer.lock(),
+                        size,
+                        hotspot,
+                        &pixels,
+                        &mask_bits,
+                        &format,
+                    );
+                    *self.pending_cursor.lock() = Some(cursor);
+                }
+                Event::Resize(width, height) => {
+                    self.apply_resize(width, height);
+                    resized = true;
+                }
+                Event::EndOfFrame => {
+                    frame_complete = true;
+                }
+                Event::Disconnected(error) => {
+                    self.connected.store(false, Ordering::SeqCst);
+                    return Err(match error {
+                        Some(e) => format!("VNC server disconnected: {}", e).into(),
+                        None => "VNC server disconnected".into(),
+                    });
+                }
+                _ => {}
             }
-            None => Ok(None),
         }
+
+        drop(client);
+
+        if received {
+            *self.last_activity.lock() = Instant::now();
+        } else {
+            self.check_idle()?;
+        }
+
+        if !frame_complete {
+            return Ok(None);
+        }
+
+        // Ask for the next frame now that this one has arrived; after a
+        // resize the whole new desktop is needed
+        self.request_update(!resized)?;
+
+        let regions = std::mem::take(&mut *self.pending_regions.lock());
+        if regions.is_empty() {
+            return Ok(None);
+        }
+
+        let framebuffer = self.framebuffer.lock();
+        let mut full_frame_sent = self.full_frame_sent.lock();
+        if !*full_frame_sent {
+            *full_frame_sent = true;
+            return Ok(Some(FrameUpdate::full(
+                framebuffer.width,
+                framebuffer.height,
+                framebuffer.as_bytes().to_vec(),
+            )));
+        }
+
+        let rects = coalesce_regions(regions)
+            .iter()
+            .filter_map(|r| r.clamp(framebuffer.width, framebuffer.height))
+            .map(|r| DirtyRect::from_framebuffer(&r, framebuffer.as_bytes(), framebuffer.width))
+            .collect();
+
+        Ok(Some(FrameUpdate::partial(rects)))
+    }
+
+    /// Servers only answer incremental requests when something changes, so a
+    /// quiet server is asked for a single pixel now and then. No reply at all
+    /// within `VNC_IDLE_TIMEOUT` means the connection is dead.
+    fn check_idle(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let idle = self.last_activity.lock().elapsed();
+        if idle >= VNC_IDLE_TIMEOUT {
+            self.connected.store(false, Ordering::SeqCst);
+            return Err(format!("VNC server not responding for {}s", idle.as_secs()).into());
+        }
+
+        let mut last_probe = self.last_probe.lock();
+        if idle >= VNC_KEEPALIVE_INTERVAL && last_probe.elapsed() >= VNC_KEEPALIVE_INTERVAL {
+            *last_probe = Instant::now();
+            self.client
+                .lock()
+                .request_update(
+                    Rect {
+                        left: 0,
+                        top: 0,
+                        width: 1,
+                        height: 1,
+                    },
+                    false,
+                )
+                .map_err(|e| self.connection_lost(e))?;
+        }
+        Ok(())
+    }
+
+    fn ensure_connected(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.is_connected() {
+            Ok(())
+        } else {
+            Err("VNC session closed".into())
+        }
+    }
+
+    /// Record that the transport failed and describe why
+    fn connection_lost(&self, error: vnc::Error) -> Box<dyn std::error::Error + Send + Sync> {
+        self.connected.store(false, Ordering::SeqCst);
+        format!("VNC connection lost: {}", error).into()
+    }
+
+    /// Switch to a new desktop size. The next emitted update is a full frame
+    /// at the new size.
+    fn apply_resize(&self, width: u16, height: u16) {
+        self.framebuffer.lock().resize(width, height);
+        *self.size.lock() = (width, height);
+        *self.full_frame_sent.lock() = false;
+        *self.pending_resize.lock() = Some((width, height));
+
+        let mut pending = self.pending_regions.lock();
+        pending.clear();
+        pending.push(Region::new(0, 0, width, height));
+    }
+
+    /// Size the server switched to since the last call, if any
+    pub fn take_resize(&self) -> Option<(u16, u16)> {
+        self.pending_resize.lock().take()
+    }
+
+    /// Cursor shape the server sent since the last call, if any
+    pub fn take_cursor(&self) -> Option<VncCursor> {
+        self.pending_cursor.lock().take()
+    }
+
+    fn mark_dirty(&self, rect: &Rect) {
+        self.pending_regions
+            .lock()
+            .push(Region::new(rect.left, rect.top, rect.width, rect.height));
+    }
+
+    /// Copy of the current screen as (width, height, RGBA)
+    pub fn snapshot(&self) -> (u16, u16, Vec<u8>) {
+        let fb = self.framebuffer.lock();
+        (fb.width, fb.height, fb.as_bytes().to_vec())
+    }
+
+    /// Disconnect; also closes any SSH tunnel underneath
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.connected.store(false, Ordering::SeqCst);
+        let _ = self.relay_socket.shutdown(Shutdown::Both);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
     }
 
     pub fn width(&self) -> u16 {
-        self.width
+        self.size.lock().0
     }
 
     pub fn height(&self) -> u16 {
-        self.height
+        self.size.lock().1
     }
 
     pub fn connection_info(&self) -> &super::VncConnectionInfo {
@@ -0,0 +1,53 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::Serialize;
+use vnc::PixelFormat;
+
+use super::FrameBuffer;
+
+/// Cursor shape sent by the server (Cursor pseudo-encoding), emitted as
+/// `vnc-cursor-{session_id}` so the frontend can draw it locally.
+/// A 0x0 cursor means the server wants the cursor hidden.
+#[derive(Debug, Clone, Serialize)]
+pub struct VncCursor {
+    pub width: u16,
+    pub height: u16,
+    pub hotspot_x: u16,
+    pub hotspot_y: u16,
+    pub data: String, // Base64-encoded RGBA pixels
+}
+
+impl VncCursor {
+    /// Combine the cursor pixels with its 1-bit transparency mask
+    /// (rows padded to whole bytes, most significant bit first)
+    pub fn decode(
+        framebuffer: &FrameBuffer,
+        size: (u16, u16),
+        hotspot: (u16, u16),
+        pixels: &[u8],
+        mask_bits: &[u8],
+        format: &PixelFormat,
+    ) -> Self {
+        let (width, height) = size;
+        let mut rgba = framebuffer.to_rgba(pixels, format);
+        let mask_stride = (width as usize).div_ceil(8);
+
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let visible = mask_bits
+                    .get(y * mask_stride + x / 8)
+                    .is_some_and(|byte| byte & (0x80 >> (x % 8)) != 0);
+                if let Some(alpha) = rgba.get_mut((y * width as usize + x) * 4 + 3) {
+                    *alpha = if visible { 255 } else { 0 };
+                }
+            }
+        }
+
+        Self {
+            width,
+            height,
+            hotspot_x: hotspot.0,
+            hotspot_y: hotspot.1,
+            data: BASE64.encode(&rgba),
+        }
+    }
+}
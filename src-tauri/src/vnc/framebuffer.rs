@@ -1,7 +1,10 @@
+use vnc::{Colour, PixelFormat, Rect};
+
 pub struct FrameBuffer {
     pub width: u16,
     pub height: u16,
     pub data: Vec<u8>, // RGBA format
+    palette: Vec<[u8; 4]>,
 }
 
 impl FrameBuffer {
@@ -11,9 +14,17 @@ impl FrameBuffer {
             width,
             height,
             data: vec![0; size],
+            palette: Vec::new(),
         }
     }
 
+    /// Change the desktop size; contents are cleared but the colour map is kept
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+        self.data = vec![0; (width as usize) * (height as usize) * 4];
+    }
+
     pub fn update_rect(&mut self, x: u16, y: u16, width: u16, height: u16, data: &[u8]) {
         for row in 0..height {
             let src_offset = (row as usize) * (width as usize) * 4;
@@ -27,6 +38,84 @@ impl FrameBuffer {
         }
     }
 
+    /// Write server pixels (in the negotiated pixel format) into the RGBA buffer
+    pub fn put_pixels(&mut self, rect: &Rect, pixels: &[u8], format: &PixelFormat) {
+        let rgba = self.to_rgba(pixels, format);
+        self.update_rect(rect.left, rect.top, rect.width, rect.height, &rgba);
+    }
+
+    /// Convert server pixels to opaque RGBA using the current colour map
+    pub fn to_rgba(&self, pixels: &[u8], format: &PixelFormat) -> Vec<u8> {
+        let bytes_per_pixel = (format.bits_per_pixel as usize / 8).max(1);
+        let mut rgba = Vec::with_capacity(pixels.len() / bytes_per_pixel * 4);
+        for pixel in pixels.chunks_exact(bytes_per_pixel) {
+            rgba.extend_from_slice(&self.pixel_to_rgba(pixel, format));
+        }
+        rgba
+    }
+
+    /// Copy a rectangle within the framebuffer (CopyRect encoding)
+    pub fn copy_rect(&mut self, src: &Rect, dst: &Rect) {
+        let row_len = (src.width as usize) * 4;
+        let mut rows = Vec::with_capacity(row_len * src.height as usize);
+        for row in 0..src.height {
+            let offset = ((src.top + row) as usize * self.width as usize + src.left as usize) * 4;
+            if offset + row_len <= self.data.len() {
+                rows.extend_from_slice(&self.data[offset..offset + row_len]);
+            }
+        }
+        self.update_rect(dst.left, dst.top, dst.width, dst.height, &rows);
+    }
+
+    /// Update palette entries used by non-true-colour pixel formats
+    pub fn set_colour_map(&mut self, first_colour: u16, colours: &[Colour]) {
+        let first = first_colour as usize;
+        if self.palette.len() < first + colours.len() {
+            self.palette.resize(first + colours.len(), [0, 0, 0, 255]);
+        }
+        for (i, colour) in colours.iter().enumerate() {
+            self.palette[first + i] = [
+                (colour.red >> 8) as u8,
+                (colour.green >> 8) as u8,
+                (colour.blue >> 8) as u8,
+                255,
+            ];
+        }
+    }
+
+    fn pixel_to_rgba(&self, pixel: &[u8], format: &PixelFormat) -> [u8; 4] {
+        let value = match (pixel.len(), format.big_endian) {
+            (1, _) => pixel[0] as u32,
+            (2, true) => u16::from_be_bytes([pixel[0], pixel[1]]) as u32,
+            (2, false) => u16::from_le_bytes([pixel[0], pixel[1]]) as u32,
+            (4, true) => u32::from_be_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]),
+            (4, false) => u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]),
+            _ => 0,
+        };
+
+        if !format.true_colour {
+            return self
+                .palette
+                .get(value as usize)
+                .copied()
+                .unwrap_or([0, 0, 0, 255]);
+        }
+
+        let channel = |shift: u8, max: u16| -> u8 {
+            if max == 0 {
+                return 0;
+            }
+            (((value >> shift) & max as u32) * 255 / max as u32) as u8
+        };
+
+        [
+            channel(format.red_shift, format.red_max),
+            channel(format.green_shift, format.green_max),
+            channel(format.blue_shift, format.blue_max),
+            255,
+        ]
+    }
+
     pub fn as_bytes(&self) -> &[u8] {
         &self.data
     }
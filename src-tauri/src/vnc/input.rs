@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum InputEvent {
+    #[serde(rename = "pointer")]
+    Pointer {
+        x: u16,
+        y: u16,
+        button_mask: u8,
+    },
+    #[serde(rename = "key")]
+    Key {
+        key: u32,
+        down: bool,
+    },
+}
+
+/// Pointer event batcher to avoid flooding the server during drags.
+/// Consecutive moves with the same button state are collapsed to the latest
+/// position; button state changes and key events are passed through immediately.
+pub struct InputBatcher {
+    pending_move: Option<InputEvent>,
+    last_button_mask: u8,
+    last_flush: Instant,
+    max_batch_time: Duration,
+}
+
+impl InputBatcher {
+    pub fn new() -> Self {
+        Self {
+            pending_move: None,
+            last_button_mask: 0,
+            last_flush: Instant::now(),
+            max_batch_time: Duration::from_millis(16), // ~60 FPS max pointer rate
+        }
+    }
+
+    /// Add an event, returning the events that should be sent right away (in order)
+    pub fn add_event(&mut self, event: InputEvent) -> Vec<InputEvent> {
+        match event {
+            InputEvent::Pointer { button_mask, .. } if button_mask == self.last_button_mask => {
+                // Plain move - keep only the latest position
+                self.pending_move = Some(event);
+                self.maybe_flush()
+            }
+            InputEvent::Pointer { button_mask, .. } => {
+                // Button state changed - send now; it carries the latest position anyway
+                self.last_button_mask = button_mask;
+                self.pending_move = None;
+                self.last_flush = Instant::now();
+                vec![event]
+            }
+            InputEvent::Key { .. } => {
+                // Keep ordering: any pending move goes out before the key
+                let mut events = self.flush();
+                events.push(event);
+                events
+            }
+        }
+    }
+
+    /// Check if the pending move should be sent
+    pub fn should_flush(&self) -> bool {
+        self.pending_move.is_some() && self.last_flush.elapsed() >= self.max_batch_time
+    }
+
+    /// Get and clear the pending move
+    pub fn flush(&mut self) -> Vec<InputEvent> {
+        self.last_flush = Instant::now();
+        self.pending_move.take().into_iter().collect()
+    }
+
+    /// Flush the pending move if enough time has passed
+    pub fn maybe_flush(&mut self) -> Vec<InputEvent> {
+        if self.should_flush() {
+            self.flush()
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Default for InputBatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const XK_SHIFT_L: u32 = 0xffe1;
+
+/// X11 keysym for `c`, and whether a US layout needs Shift to type it
+fn keysym_for_char(c: char) -> (u32, bool) {
+    match c {
+        '\n' | '\r' => (0xff0d, false), // Return
+        '\t' => (0xff09, false),        // Tab
+        '\u{8}' => (0xff08, false),     // BackSpace
+        '\u{1b}' => (0xff1b, false),    // Escape
+        'A'..='Z' => (c as u32, true),
+        '~' | '!' | '@' | '#' | '$' | '%' | '^' | '&' | '*' | '(' | ')' | '_' | '+' | '{' | '}'
+        | '|' | ':' | '"' | '<' | '>' | '?' => (c as u32, true),
+        // ASCII and Latin-1 keysyms equal their code points
+        '\u{20}'..='\u{7e}' | '\u{a0}'..='\u{ff}' => (c as u32, false),
+        _ => (0x0100_0000 | c as u32, false),
+    }
+}
+
+/// Press/release sequence that types `c`, wrapped in Shift when needed
+pub fn char_key_events(c: char) -> Vec<InputEvent> {
+    let (key, shift) = keysym_for_char(c);
+    let mut events = Vec::with_capacity(4);
+    if shift {
+        events.push(InputEvent::Key { key: XK_SHIFT_L, down: true });
+    }
+    events.push(InputEvent::Key { key, down: true });
+    events.push(InputEvent::Key { key, down: false });
+    if shift {
+        events.push(InputEvent::Key { key: XK_SHIFT_L, down: false });
+    }
+    events
+}
+
+/// Characters of `text` to type, with each "\r\n" typed as one Return
+pub fn text_chars(text: &str) -> impl Iterator<Item = char> + '_ {
+    let mut after_cr = false;
+    text.chars().filter(move |&c| {
+        let skip = after_cr && c == '\n';
+        after_cr = c == '\r';
+        !skip
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crlf_is_typed_as_one_return() {
+        let returns = |text: &str| {
+            text_chars(text)
+                .flat_map(char_key_events)
+                .filter(|event| matches!(event, InputEvent::Key { key: 0xff0d, down: true }))
+                .count()
+        };
+        assert_eq!(returns("a\r\nb"), 1);
+        assert_eq!(returns("a\nb\rc"), 2);
+        assert_eq!(returns("\r\r\n\n"), 3);
+        assert_eq!(text_chars("a\r\nb").collect::<String>(), "a\rb");
+    }
+}
@@ -1,31 +1,119 @@
-use super::{InputEvent, VncClient};
+use super::auth::{self, RfbStream, VncAuthError};
+use super::{char_key_events, text_chars, InputEvent, VncAuthType, VncClient, VncColorDepth, VncCredentials, VncEncoding};
+use crate::certificate::{CertificatePolicy, ConnectError, UntrustedCertificate};
+use crate::frame::{save_screenshot, FrameChannel, ScreenshotFormat, ScreenshotInfo};
+use crate::ssh::SshClient;
+use crate::idle::{Activity, IdleWatch};
+use crate::storage::UsageTracker;
 use parking_lot::Mutex;
 use std::collections::HashMap;
+use serde::Serialize;
+use std::net::{IpAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
+use tauri::ipc::{Channel, Response};
 use tauri::{AppHandle, Emitter};
 
+/// Pause between characters typed by `send_text`
+const TEXT_KEY_DELAY: Duration = Duration::from_millis(5);
+
+/// Payload of `vnc-incoming-{listener_id}` when a server connects back to us
+#[derive(Debug, Clone, Serialize)]
+pub struct VncIncomingSession {
+    pub session_id: String,
+    pub host: String,
+    pub port: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Payload of `vnc-resized-{session_id}` when the server changes the desktop size
+#[derive(Debug, Clone, Serialize)]
+pub struct VncResize {
+    pub width: u16,
+    pub height: u16,
+}
+
+impl From<(u16, u16)> for VncResize {
+    fn from((width, height): (u16, u16)) -> Self {
+        Self { width, height }
+    }
+}
+
 pub struct VncManager {
     sessions: Arc<Mutex<HashMap<String, Arc<VncClient>>>>,
+    /// Reverse-connection listeners and their stop flags
+    listeners: Mutex<HashMap<String, Arc<AtomicBool>>>,
+    /// Binary frame channels attached by viewers
+    frame_channels: Arc<Mutex<HashMap<String, FrameChannel>>>,
+    /// Last input and idle watcher of sessions with an idle timeout. Screen
+    /// updates don't count as activity; a clock on the desktop would keep
+    /// the session alive forever.
+    idle: Arc<Mutex<HashMap<String, (Activity, IdleWatch)>>>,
+    /// Sessions opened from a saved profile, timed until closed
+    usage: Mutex<HashMap<String, UsageTracker>>,
 }
 
 impl VncManager {
     pub fn new() -> Self {
         Self {
             sessions: Arc::new(Mutex::new(HashMap::new())),
+            listeners: Mutex::new(HashMap::new()),
+            frame_channels: Arc::new(Mutex::new(HashMap::new())),
+            idle: Arc::new(Mutex::new(HashMap::new())),
+            usage: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Connect to `host:port` directly, or as seen from `via_ssh` when tunnelling.
+    /// A VeNCrypt certificate that fails `certificates` comes back as
+    /// `ConnectError::UntrustedCertificate` for the user to review.
     pub fn create_session(
         &self,
         session_id: String,
         host: &str,
         port: u16,
-        password: Option<&str>,
-    ) -> Result<(u16, u16), String> {
-        let client = VncClient::connect(host, port, password)
-            .map_err(|e| format!("VNC connection failed: {}", e))?;
+        credentials: &VncCredentials,
+        certificates: &CertificatePolicy,
+        encoding: VncEncoding,
+        quality: u8,
+        color_depth: VncColorDepth,
+        via_ssh: Option<SshClient>,
+    ) -> Result<(u16, u16), ConnectError> {
+        let stream = match via_ssh {
+            Some(ssh) => {
+                let tunnel = ssh
+                    .open_direct_tcpip(host, port)
+                    .map_err(|e| format!("SSH tunnel failed: {}", e))?;
+                RfbStream::Ssh(Box::new(tunnel))
+            }
+            None => RfbStream::Plain(Self::connect_tcp(host, port)?),
+        };
+
+        let client = match VncClient::connect(stream, host, port, credentials, certificates, encoding, quality, color_depth) {
+            Ok(client) => client,
+            Err(e) => {
+                return Err(match e.downcast::<VncAuthError>() {
+                    Ok(e) => match *e {
+                        VncAuthError::UntrustedCertificate(untrusted) => ConnectError::UntrustedCertificate(untrusted),
+                        VncAuthError::CertificateRejected(reason) => {
+                            // Reconnect just to show the user the certificate
+                            let certificate = Self::connect_tcp(host, port)
+                                .and_then(|tcp| {
+                                    auth::probe_certificate(RfbStream::Plain(tcp), host, port, credentials)
+                                        .map_err(|e| e.to_string())
+                                })
+                                .map_err(|_| format!("VNC connection failed: TLS certificate rejected: {}", reason))?;
+                            ConnectError::UntrustedCertificate(Box::new(UntrustedCertificate { certificate, reason }))
+                        }
+                        e => format!("VNC connection failed: {}", e).into(),
+                    },
+                    Err(e) => format!("VNC connection failed: {}", e).into(),
+                });
+            }
+        };
 
         let width = client.width();
         let height = client.height();
@@ -36,78 +124,313 @@ impl VncManager {
         Ok((width, height))
     }
 
-    pub fn start_frame_reader(&self, session_id: &str, app_handle: AppHandle) -> Result<(), String> {
-        let sessions = self.sessions.lock();
-        let client = sessions
-            .get(session_id)
-            .ok_or_else(|| "VNC session not found".to_string())?
-            .clone();
+    fn connect_tcp(host: &str, port: u16) -> Result<std::net::TcpStream, String> {
+        let tcp = crate::proxy::connect(host, port, None).map_err(|e| format!("VNC connection failed: {}", e))?;
+        tcp.set_nonblocking(false)
+            .map_err(|e| format!("VNC connection failed: {}", e))?;
+        Ok(tcp)
+    }
+
+    /// Listen for servers connecting to us (reverse VNC). Every accepted
+    /// connection becomes a regular session announced via `vnc-incoming-{listener_id}`.
+    ///
+    /// Any host that can reach `bind_address:port` gets a handshake with
+    /// `credentials`, so nothing that would hand them to a rogue peer is
+    /// used: ARD and MS-Logon encrypt them with a Diffie-Hellman group the
+    /// peer picks, so they are refused, and Auto never chooses them.
+    pub fn start_listener(
+        &self,
+        listener_id: String,
+        bind_address: IpAddr,
+        port: u16,
+        mut credentials: VncCredentials,
+        encoding: VncEncoding,
+        quality: u8,
+        color_depth: VncColorDepth,
+        app_handle: AppHandle,
+    ) -> Result<u16, String> {
+        match credentials.auth_type {
+            VncAuthType::MsLogon | VncAuthType::AppleRemoteDesktop => {
+                return Err(
+                    "ARD and MS-Logon authentication can't be used for incoming connections".to_string(),
+                );
+            }
+            // Auto only picks ARD or MS-Logon given a username
+            VncAuthType::Auto => credentials.username = None,
+            _ => {}
+        }
 
-        let session_id = session_id.to_string();
+        let listener = TcpListener::bind((bind_address, port))
+            .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+        let local_port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        // Non-blocking so the stop flag is noticed
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
 
-        thread::spawn(move || {
-            loop {
-                // Request incremental update
-                if let Err(e) = client.request_update(true) {
-                    eprintln!("Failed to request VNC update: {}", e);
-                    let _ = app_handle.emit(&format!("vnc-error-{}", session_id), format!("{}", e));
-                    break;
-                }
+        let stop = Arc::new(AtomicBool::new(false));
+        self.listeners.lock().insert(listener_id.clone(), stop.clone());
+        let sessions = self.sessions.clone();
+        let frame_channels = self.frame_channels.clone();
 
-                // Read and emit frame data
-                match client.read_event() {
-                    Ok(Some(frame_data)) => {
-                        let _ = app_handle.emit(&format!("vnc-frame-{}", session_id), frame_data);
-                    }
-                    Ok(None) => {
-                        // No update, continue
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                let (tcp, peer) = match listener.accept() {
+                    Ok(conn) => conn,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(100));
+                        continue;
                     }
                     Err(e) => {
-                        eprintln!("VNC read error: {}", e);
-                        let _ = app_handle.emit(&format!("vnc-error-{}", session_id), format!("{}", e));
+                        let _ = app_handle.emit(&format!("vnc-listen-error-{}", listener_id), e.to_string());
                         break;
                     }
-                }
+                };
+
+                // Each handshake runs on its own thread so a slow peer doesn't block others
+                let sessions = sessions.clone();
+                let frame_channels = frame_channels.clone();
+                let credentials = credentials.clone();
+                let app_handle = app_handle.clone();
+                let listener_id = listener_id.clone();
+                thread::spawn(move || {
+                    let host = peer.ip().to_string();
+                    let result = tcp
+                        .set_nonblocking(false)
+                        .map_err(|e| e.into())
+                        .and_then(|_| {
+                            VncClient::connect(
+                                RfbStream::Plain(tcp),
+                                &host,
+                                peer.port(),
+                                &credentials,
+                                // No pin can be keyed to a peer's ephemeral
+                                // port, so VeNCrypt certificates must validate
+                                &CertificatePolicy::default(),
+                                encoding,
+                                quality,
+                                color_depth,
+                            )
+                        });
 
-                // Small delay to avoid busy loop
-                thread::sleep(Duration::from_millis(16)); // ~60 FPS
+                    match result {
+                        Ok(client) => {
+                            let session_id = uuid::Uuid::new_v4().to_string();
+                            let incoming = VncIncomingSession {
+                                session_id: session_id.clone(),
+                                host,
+                                port: peer.port(),
+                                width: client.width(),
+                                height: client.height(),
+                            };
+                            let client = Arc::new(client);
+                            sessions.lock().insert(session_id.clone(), client.clone());
+                            let frames = FrameChannel::default();
+                            frame_channels.lock().insert(session_id.clone(), frames.clone());
+                            spawn_frame_reader(client, sessions, session_id, frames, app_handle.clone());
+                            let _ = app_handle.emit(&format!("vnc-incoming-{}", listener_id), incoming);
+                        }
+                        Err(e) => {
+                            let _ = app_handle.emit(
+                                &format!("vnc-listen-error-{}", listener_id),
+                                format!("Incoming connection from {} failed: {}", peer, e),
+                            );
+                        }
+                    }
+                });
             }
         });
 
+        Ok(local_port)
+    }
+
+    /// Stop accepting reverse connections; sessions already established stay open
+    pub fn stop_listener(&self, listener_id: &str) -> Result<(), String> {
+        let stop = self
+            .listeners
+            .lock()
+            .remove(listener_id)
+            .ok_or_else(|| "VNC listener not found".to_string())?;
+        stop.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn start_frame_reader(&self, session_id: &str, app_handle: AppHandle) -> Result<(), String> {
+        let sessions = self.sessions.lock();
+        let client = sessions
+            .get(session_id)
+            .ok_or_else(|| "VNC session not found".to_string())?
+            .clone();
+        let frames = self
+            .frame_channels
+            .lock()
+            .entry(session_id.to_string())
+            .or_default()
+            .clone();
+
+        spawn_frame_reader(client, self.sessions.clone(), session_id.to_string(), frames, app_handle);
+        Ok(())
+    }
+
+    /// Send the session's frames over `channel` as binary messages instead of
+    /// `vnc-frame-{session_id}` events
+    pub fn attach_frame_channel(&self, session_id: &str, channel: Channel<Response>) -> Result<(), String> {
+        if !self.sessions.lock().contains_key(session_id) {
+            return Err("VNC session not found".to_string());
+        }
+        self.frame_channels
+            .lock()
+            .entry(session_id.to_string())
+            .or_default()
+            .attach(channel);
+        Ok(())
+    }
+
+    /// Send the session's frames as `vnc-frame-{session_id}` events again
+    pub fn detach_frame_channel(&self, session_id: &str) -> Result<(), String> {
+        if !self.sessions.lock().contains_key(session_id) {
+            return Err("VNC session not found".to_string());
+        }
+        if let Some(frames) = self.frame_channels.lock().get(session_id) {
+            frames.detach();
+        }
         Ok(())
     }
 
+    /// Count the session's time toward its profile's usage once it closes
+    pub fn track_usage(&self, session_id: &str, tracker: UsageTracker) {
+        self.usage.lock().insert(session_id.to_string(), tracker);
+    }
+
+    /// Close the session once no input has been sent for `timeout`
+    pub fn set_idle_timeout(
+        self: &Arc<Self>,
+        session_id: &str,
+        timeout: Duration,
+        app_handle: AppHandle,
+    ) -> Result<(), String> {
+        if !self.sessions.lock().contains_key(session_id) {
+            return Err("VNC session not found".to_string());
+        }
+
+        let activity = Activity::new();
+        let manager = Arc::downgrade(self);
+        let id = session_id.to_string();
+        let watch = IdleWatch::start(session_id, activity.clone(), timeout, app_handle, move || {
+            let Some(manager) = manager.upgrade() else {
+                return false;
+            };
+            let open = manager.sessions.lock().contains_key(&id);
+            let _ = manager.close_session(&id);
+            open
+        });
+        self.idle.lock().insert(session_id.to_string(), (activity, watch));
+        Ok(())
+    }
+
+    fn touch(&self, session_id: &str) {
+        if let Some((activity, _)) = self.idle.lock().get(session_id) {
+            activity.touch();
+        }
+    }
+
     pub fn send_input(
         &self,
         session_id: &str,
         event: InputEvent,
     ) -> Result<(), String> {
+        self.touch(session_id);
         let sessions = self.sessions.lock();
+        // Sessions whose connection dropped are removed by the frame reader
         let client = sessions
             .get(session_id)
-            .ok_or_else(|| "VNC session not found".to_string())?;
+            .ok_or_else(|| "VNC session closed".to_string())?;
 
-        match event {
-            InputEvent::Pointer { x, y, button_mask } => {
-                client
-                    .send_pointer_event(x, y, button_mask)
-                    .map_err(|e| e.to_string())?;
-            }
-            InputEvent::Key { key, down } => {
-                client
-                    .send_key_event(key, down)
-                    .map_err(|e| e.to_string())?;
+        client.queue_input(event).map_err(|e| e.to_string())
+    }
+
+    /// Type `text` as key presses, pausing between characters so slow
+    /// servers don't drop any
+    pub fn send_text(&self, session_id: &str, text: &str) -> Result<(), String> {
+        self.touch(session_id);
+        let client = self
+            .sessions
+            .lock()
+            .get(session_id)
+            .ok_or_else(|| "VNC session closed".to_string())?
+            .clone();
+
+        for c in text_chars(text) {
+            for event in char_key_events(c) {
+                client.queue_input(event).map_err(|e| e.to_string())?;
             }
+            thread::sleep(TEXT_KEY_DELAY);
         }
-
         Ok(())
     }
 
+    pub fn set_quality(&self, session_id: &str, quality: u8) -> Result<(), String> {
+        let sessions = self.sessions.lock();
+        let client = sessions
+            .get(session_id)
+            .ok_or_else(|| "VNC session not found".to_string())?;
+
+        client.set_quality(quality).map_err(|e| e.to_string())
+    }
+
+    /// `None` if the session doesn't exist (dropped sessions are removed)
+    pub fn is_connected(&self, session_id: &str) -> Option<bool> {
+        self.sessions.lock().get(session_id).map(|c| c.is_connected())
+    }
+
+    pub fn has_session(&self, session_id: &str) -> bool {
+        self.sessions.lock().contains_key(session_id)
+    }
+
+    /// Save the current screen to `path`
+    pub fn capture_screenshot(
+        &self,
+        session_id: &str,
+        path: &str,
+        format: ScreenshotFormat,
+    ) -> Result<ScreenshotInfo, String> {
+        let sessions = self.sessions.lock();
+        let client = sessions
+            .get(session_id)
+            .ok_or_else(|| "VNC session not found".to_string())?
+            .clone();
+        let (width, height, rgba) = client.snapshot();
+        drop(sessions);
+
+        save_screenshot(rgba, width as u32, height as u32, path, format)
+    }
+
     pub fn close_session(&self, session_id: &str) -> Result<(), String> {
-        self.sessions.lock().remove(session_id);
+        self.idle.lock().remove(session_id);
+        if let Some(usage) = self.usage.lock().remove(session_id) {
+            usage.finish();
+        }
+        self.frame_channels.lock().remove(session_id);
+        if let Some(client) = self.sessions.lock().remove(session_id) {
+            client.close();
+        }
         Ok(())
     }
 
+    /// Disconnect every session and stop all listeners, e.g. on application exit
+    pub fn close_all(&self) {
+        self.idle.lock().clear();
+        for (_, usage) in self.usage.lock().drain() {
+            usage.finish();
+        }
+        for (_, stop) in self.listeners.lock().drain() {
+            stop.store(true, Ordering::SeqCst);
+        }
+        self.frame_channels.lock().clear();
+        let sessions: Vec<_> = self.sessions.lock().drain().collect();
+        for (_, client) in sessions {
+            client.close();
+        }
+    }
+
     pub fn get_dimensions(&self, session_id: &str) -> Result<(u16, u16), String> {
         let sessions = self.sessions.lock();
         let client = sessions
@@ -117,6 +440,84 @@ impl VncManager {
     }
 }
 
+/// Pump server updates for `client` until it is closed or the connection drops.
+/// A dropped session is removed from `sessions` and reported via
+/// `vnc-disconnected-{session_id}`.
+fn spawn_frame_reader(
+    client: Arc<VncClient>,
+    sessions: Arc<Mutex<HashMap<String, Arc<VncClient>>>>,
+    session_id: String,
+    frames: FrameChannel,
+    app_handle: AppHandle,
+) {
+    thread::spawn(move || {
+        let event_name = format!("vnc-frame-{}", session_id);
+
+        loop {
+            if client.is_closed() {
+                break;
+            }
+
+            // Send any pointer move the input batcher is still holding
+            if let Err(e) = client.flush_input() {
+                eprintln!("VNC: Failed to flush input: {}", e);
+            }
+
+            // Apply server updates and emit completed frames
+            let result = client.poll_frame();
+
+            // Cursor shapes arrive independently of frames
+            if let Some(cursor) = client.take_cursor() {
+                let _ = app_handle.emit(&format!("vnc-cursor-{}", session_id), cursor);
+            }
+
+            match result {
+                Ok(Some(update)) => {
+                    // Announce a desktop resize before the frame drawn at the new size
+                    if let Some(size) = client.take_resize() {
+                        let _ = app_handle.emit(
+                            &format!("vnc-resized-{}", session_id),
+                            VncResize::from(size),
+                        );
+                    }
+
+                    if let Err(e) = frames.send(&app_handle, &event_name, &update) {
+                        eprintln!("VNC: Failed to emit frame update: {}", e);
+                        break;
+                    }
+                }
+                Ok(None) => {
+                    // No complete frame yet, continue
+                }
+                Err(e) => {
+                    if !client.is_closed() {
+                        eprintln!("VNC session {} disconnected: {}", session_id, e);
+                        client.close();
+                        {
+                            let mut sessions = sessions.lock();
+                            // Only drop our own entry, not a newer session reusing the ID
+                            if sessions
+                                .get(&session_id)
+                                .is_some_and(|c| Arc::ptr_eq(c, &client))
+                            {
+                                sessions.remove(&session_id);
+                            }
+                        }
+                        let _ = app_handle.emit(
+                            &format!("vnc-disconnected-{}", session_id),
+                            e.to_string(),
+                        );
+                    }
+                    break;
+                }
+            }
+
+            // Small delay to avoid busy loop
+            thread::sleep(Duration::from_millis(16)); // ~60 FPS
+        }
+    });
+}
+
 impl Default for VncManager {
     fn default() -> Self {
         Self::new()
@@ -1,31 +1,175 @@
+mod auth;
 mod client;
+mod cursor;
 mod framebuffer;
+mod input;
 mod manager;
+mod relay;
 
+pub use auth::{VncAuthType, VncCredentials};
 pub use client::VncClient;
+pub use cursor::VncCursor;
 pub use framebuffer::FrameBuffer;
+pub use input::{char_key_events, text_chars, InputBatcher, InputEvent};
 pub use manager::VncManager;
 
 use serde::{Deserialize, Serialize};
 
+/// SSH server to reach the VNC server through, either given inline or as a
+/// saved SSH connection profile. Host and port of the VNC server are then
+/// resolved on the SSH server (usually `localhost`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct SshTunnelConfig {
+    pub connection_id: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub auth: Option<crate::ssh::AuthMethod>,
+    /// Password or key passphrase for a saved profile, if not in the keychain
+    pub password: Option<String>,
+    pub passphrase: Option<String>,
+    /// HTTP proxy to reach the SSH server through
+    pub proxy: Option<crate::proxy::ProxyConfig>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VncConnectionInfo {
     pub host: String,
     pub port: u16,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-pub enum InputEvent {
-    #[serde(rename = "pointer")]
-    Pointer {
-        x: u16,
-        y: u16,
-        button_mask: u8,
-    },
-    #[serde(rename = "key")]
-    Key {
-        key: u32,
-        down: bool,
-    },
+/// Preferred encoding for framebuffer updates.
+///
+/// Tight isn't offered: the vnc crate has no Tight decoder, so ZRLE is the
+/// best it can do on slow links. The quality level is still sent as the
+/// JPEG quality pseudo-encoding, but without Tight only its inverse, the
+/// compression level, has an effect.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VncEncoding {
+    /// Ordered by the bandwidth measured while connecting
+    #[default]
+    Auto,
+    /// Zlib-compressed tiles - best for slow links. Profiles saved with the
+    /// Hextile option, which the vnc crate can't decode, load as this.
+    #[serde(alias = "Hextile")]
+    Zrle,
+    /// Uncompressed
+    Raw,
+}
+
+/// Pixel format requested from the server. Fewer bits per pixel cut the
+/// bandwidth of every encoding, at the cost of banding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VncColorDepth {
+    /// 24-bit colour in 32-bit pixels
+    #[default]
+    Full,
+    /// 16-bit RGB565
+    High,
+    /// 8-bit BGR233
+    Low,
+    /// 8-bit indices into a 256-colour map chosen by the server
+    Palette,
+}
+
+impl VncColorDepth {
+    pub fn pixel_format(&self) -> vnc::PixelFormat {
+        let (bits_per_pixel, depth, (red_max, green_max, blue_max), (red_shift, green_shift, blue_shift)) = match self {
+            VncColorDepth::Full => (32, 24, (255, 255, 255), (16, 8, 0)),
+            VncColorDepth::High => (16, 16, (31, 63, 31), (11, 5, 0)),
+            VncColorDepth::Low => (8, 8, (7, 7, 3), (0, 3, 6)),
+            VncColorDepth::Palette => (8, 8, (0, 0, 0), (0, 0, 0)),
+        };
+        vnc::PixelFormat {
+            bits_per_pixel,
+            depth,
+            big_endian: false,
+            true_colour: *self != VncColorDepth::Palette,
+            red_max,
+            green_max,
+            blue_max,
+            red_shift,
+            green_shift,
+            blue_shift,
+        }
+    }
+}
+
+/// Highest quality/compression level understood by servers (0-9)
+pub const VNC_MAX_QUALITY: u8 = 9;
+/// Quality level used when none is configured
+pub const VNC_DEFAULT_QUALITY: u8 = 6;
+
+/// Links faster than this (bytes/sec) prefer Raw over Zrle in Auto mode,
+/// saving the zlib work on both ends
+const AUTO_FAST_LINK_BPS: f64 = 64.0 * 1024.0 * 1024.0;
+
+impl VncEncoding {
+    /// Build the SetEncodings list for this preference. Only encodings the
+    /// vnc crate decodes may be listed (anything else ends the session), plus
+    /// pseudo-encodings the server never sends rectangles in. The quality level (0-9) is sent as the JPEG quality
+    /// pseudo-encoding and its inverse as the compression level
+    /// pseudo-encoding.
+    pub fn encoding_list(&self, quality: u8, bandwidth: Option<f64>) -> Vec<vnc::Encoding> {
+        let quality = quality.min(VNC_MAX_QUALITY);
+        let preferred = match self {
+            VncEncoding::Auto => match bandwidth {
+                Some(bps) if bps >= AUTO_FAST_LINK_BPS => vec![vnc::Encoding::Raw, vnc::Encoding::Zrle],
+                _ => vec![vnc::Encoding::Zrle, vnc::Encoding::Raw],
+            },
+            VncEncoding::Zrle => vec![vnc::Encoding::Zrle, vnc::Encoding::Raw],
+            VncEncoding::Raw => vec![vnc::Encoding::Raw],
+        };
+
+        let mut encodings = vec![vnc::Encoding::CopyRect];
+        encodings.extend(preferred);
+        // Let the server resize the desktop mid-session
+        encodings.push(vnc::Encoding::DesktopSize);
+        // Have the server send the cursor shape instead of drawing it
+        encodings.push(vnc::Encoding::Cursor);
+        // Pseudo-encodings: JPEG quality level (-32..-23), compression level (-256..-247)
+        encodings.push(vnc::Encoding::Unknown(-32 + quality as i32));
+        encodings.push(vnc::Encoding::Unknown(-256 + (VNC_MAX_QUALITY - quality) as i32));
+        encodings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What the vnc crate's client decodes
+    const DECODABLE_ENCODINGS: [vnc::Encoding; 5] = [
+        vnc::Encoding::Raw,
+        vnc::Encoding::CopyRect,
+        vnc::Encoding::Zrle,
+        vnc::Encoding::Cursor,
+        vnc::Encoding::DesktopSize,
+    ];
+
+    #[test]
+    fn only_decodable_encodings_are_advertised() {
+        let fast = AUTO_FAST_LINK_BPS * 2.0;
+        for encoding in [VncEncoding::Auto, VncEncoding::Zrle, VncEncoding::Raw] {
+            for bandwidth in [None, Some(1024.0), Some(fast)] {
+                for quality in [0, VNC_DEFAULT_QUALITY, VNC_MAX_QUALITY] {
+                    for advertised in encoding.encoding_list(quality, bandwidth) {
+                        let ok = match advertised {
+                            // JPEG quality and compression level hints
+                            vnc::Encoding::Unknown(n) => (-32..=-23).contains(&n) || (-256..=-247).contains(&n),
+                            other => DECODABLE_ENCODINGS.contains(&other),
+                        };
+                        assert!(ok, "{:?} advertises {:?}", encoding, advertised);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hextile_profiles_load_as_zrle() {
+        let encoding: VncEncoding = serde_json::from_str("\"Hextile\"").unwrap();
+        assert_eq!(encoding, VncEncoding::Zrle);
+    }
 }
@@ -0,0 +1,183 @@
+use super::auth::RfbStream;
+use crate::ssh::SshTunnel;
+use parking_lot::Mutex;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Bytes the vnc crate sends during its (fake) handshake: version + security type
+const CLIENT_HANDSHAKE_LEN: usize = 12 + 1;
+
+/// Expose an already authenticated server stream on a loopback socket.
+/// The returned socket behaves like an RFB 3.8 server asking for no
+/// authentication, so it can be passed to `vnc::Client::from_tcp_stream`.
+pub fn spawn(remote: RfbStream) -> io::Result<TcpStream> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))?;
+    let client_side = TcpStream::connect(listener.local_addr()?)?;
+    let (mut local, _) = listener.accept()?;
+
+    // Version, one security type (None), SecurityResult OK
+    local.write_all(b"RFB 003.008\n")?;
+    local.write_all(&[1, 1])?;
+    local.write_all(&0u32.to_be_bytes())?;
+
+    thread::spawn(move || {
+        let mut handshake = [0u8; CLIENT_HANDSHAKE_LEN];
+        if local.read_exact(&mut handshake).is_err() {
+            return;
+        }
+
+        match remote {
+            RfbStream::Plain(tcp) => pump_plain(local, tcp),
+            RfbStream::Tls(tls) => pump_tls(local, *tls),
+            RfbStream::Ssh(tunnel) => pump_ssh(local, *tunnel),
+        }
+    });
+
+    Ok(client_side)
+}
+
+fn pump_plain(local: TcpStream, remote: TcpStream) {
+    let (mut local_read, mut remote_write) = match (local.try_clone(), remote.try_clone()) {
+        (Ok(l), Ok(r)) => (l, r),
+        _ => return,
+    };
+
+    let upstream = thread::spawn(move || {
+        let _ = io::copy(&mut local_read, &mut remote_write);
+        let _ = remote_write.shutdown(Shutdown::Both);
+    });
+
+    let (mut remote_read, mut local_write) = (remote, local);
+    let _ = io::copy(&mut remote_read, &mut local_write);
+    let _ = local_write.shutdown(Shutdown::Both);
+    let _ = upstream.join();
+}
+
+/// A TLS stream can't be split, so both directions share it behind a lock and
+/// reads use a short timeout to let writes through.
+fn pump_tls(local: TcpStream, remote: native_tls::TlsStream<TcpStream>) {
+    if remote
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_millis(10)))
+        .is_err()
+    {
+        return;
+    }
+    let mut local_read = match local.try_clone() {
+        Ok(l) => l,
+        Err(_) => return,
+    };
+
+    let remote = Arc::new(Mutex::new(remote));
+    let remote_write = remote.clone();
+
+    let upstream = thread::spawn(move || {
+        let mut buf = vec![0u8; 16 * 1024];
+        loop {
+            match local_read.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let mut tls = remote_write.lock();
+                    if tls.write_all(&buf[..n]).and_then(|_| tls.flush()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        let mut tls = remote_write.lock();
+        let _ = tls.shutdown();
+        let _ = tls.get_ref().shutdown(Shutdown::Both);
+    });
+
+    let mut local_write = local;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let mut tls = remote.lock();
+        let result = tls.read(&mut buf);
+        // Hand the lock to a waiting writer before the next read
+        parking_lot::MutexGuard::unlock_fair(tls);
+
+        match result {
+            Ok(0) => break,
+            Ok(n) => {
+                if local_write.write_all(&buf[..n]).is_err() {
+                    break;
+                }
+            }
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+    }
+    let _ = local_write.shutdown(Shutdown::Both);
+    let _ = upstream.join();
+}
+
+/// An SSH channel shares the session with everything else on it, so it is
+/// driven non-blocking from a single thread.
+fn pump_ssh(mut local: TcpStream, mut tunnel: SshTunnel) {
+    if local.set_nonblocking(true).is_err() {
+        return;
+    }
+    tunnel.set_blocking(false);
+
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let mut idle = true;
+
+        match tunnel.read(&mut buf) {
+            Ok(0) if tunnel.eof() => break,
+            Ok(0) => {}
+            Ok(n) => {
+                idle = false;
+                if write_all_retry(&mut local, &buf[..n]).is_err() {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(_) => break,
+        }
+
+        match local.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                idle = false;
+                if write_all_retry(&mut tunnel, &buf[..n]).is_err() {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(_) => break,
+        }
+
+        if idle {
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+    let _ = local.shutdown(Shutdown::Both);
+}
+
+/// `write_all` for a non-blocking writer
+fn write_all_retry<W: Write>(writer: &mut W, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        match writer.write(data) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    loop {
+        match writer.flush() {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
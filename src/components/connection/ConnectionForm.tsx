@@ -392,7 +392,7 @@ export function ConnectionForm({
           undefined,
           rdpPassword || undefined,
           undefined,
-          rdpDomain || undefined
+          rdpDomain // Empty clears the domain
         );
         toast.success(`Connection "${rdpName}" updated`);
         resetForm();
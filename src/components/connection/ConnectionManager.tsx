@@ -8,6 +8,7 @@ import { Button, Input, Modal, TextField} from "@heroui/react";
 import { cn } from "@/lib/utils";
 import { toast } from "sonner";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import type { ConnectionProfile } from "../../types";
 import { VscAdd, VscEdit, VscSync, VscTrash, VscTerminal, VscCloud, VscRemote, VscDesktopDownload } from "react-icons/vsc";
 
@@ -28,7 +29,7 @@ export function ConnectionManager({ onNewConnection, onEditConnection, onOpenSft
     connectToSaved,
     hasStoredPassword,
   } = useConnectionStore();
-  const { connect: ftpConnect } = useFtpStore();
+  const { connectSaved: ftpConnectSaved } = useFtpStore();
   const { connect: vncConnect } = useVncStore();
   const { connect: rdpConnect } = useRdpStore();
   const [connectingId, setConnectingId] = useState<string | null>(null);
@@ -50,6 +51,11 @@ export function ConnectionManager({ onNewConnection, onEditConnection, onOpenSft
 
   useEffect(() => {
     loadConnections();
+    // Saved from anywhere, including other windows and imports
+    const unlisten = listen("connections-changed", () => loadConnections());
+    return () => {
+      unlisten.then((fn) => fn());
+    };
   }, []);
 
   // Close context menu on outside click or scroll
@@ -75,29 +81,7 @@ export function ConnectionManager({ onNewConnection, onEditConnection, onOpenSft
     if (connection.connection_type === "ftp") {
       setConnectingId(connection.id);
       try {
-        const password = await invoke<string | null>("keychain_get_password", { 
-          connectionId: connection.id 
-        }).catch(() => null);
-
-        if (connection.anonymous) {
-          await ftpConnect(
-            connection.host!,
-            connection.port!,
-            undefined,
-            undefined,
-            connection.name,
-            connection.id
-          );
-        } else {
-          await ftpConnect(
-            connection.host!,
-            connection.port!,
-            connection.username!,
-            password || "",
-            connection.name,
-            connection.id
-          );
-        }
+        await ftpConnectSaved(connection.id, connection.name, connection.host!);
 
         // Add FTP tab
         const ftpTabId = `ftp-${Date.now()}`;
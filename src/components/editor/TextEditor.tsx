@@ -5,7 +5,7 @@ import { invoke } from "@tauri-apps/api/core";
 import { useTheme } from "next-themes";
 import { useTerminalStore } from "../../stores/terminalStore";
 import { toast } from "sonner";
-import type { EditorTab } from "../../types";
+import type { EditorFile, EditorTab, FileFormat } from "../../types";
 import { VscLoading } from "react-icons/vsc";
 
 interface TextEditorProps {
@@ -62,6 +62,8 @@ export function TextEditor({ tab, isActive }: TextEditorProps) {
   const [error, setError] = useState<string | null>(null);
   const [cursorPosition, setCursorPosition] = useState({ line: 1, col: 1 });
   const savedContentRef = useRef<string>("");
+  // Encoding and line endings of an SFTP file, re-applied on save
+  const formatRef = useRef<FileFormat | null>(null);
   const updateEditorTab = useTerminalStore((s) => s.updateEditorTab);
 
   const loadFile = useCallback(async () => {
@@ -73,12 +75,19 @@ export function TextEditor({ tab, isActive }: TextEditorProps) {
         case "local":
           fileContent = await invoke<string>("read_local_file", { path: tab.filePath });
           break;
-        case "sftp":
-          fileContent = await invoke<string>("sftp_read_file", {
+        case "sftp": {
+          const file = await invoke<EditorFile>("sftp_edit_open", {
             sftpId: tab.sessionId,
             remotePath: tab.filePath,
           });
+          if (file.content === null) {
+            setError("This looks like a binary file and can't be edited.");
+            return;
+          }
+          formatRef.current = file.format;
+          fileContent = file.content;
           break;
+        }
         case "ftp":
           fileContent = await invoke<string>("ftp_read_file", {
             ftpId: tab.sessionId,
@@ -121,6 +130,7 @@ export function TextEditor({ tab, isActive }: TextEditorProps) {
             sftpId: tab.sessionId,
             remotePath: tab.filePath,
             content: currentContent,
+            format: formatRef.current,
           });
           break;
         case "ftp":
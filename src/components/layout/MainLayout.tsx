@@ -1,8 +1,11 @@
-import { useRef, useState } from "react";
+import { useEffect, useRef, useState } from "react";
 import { TerminalTabs } from "../terminal/TerminalTabs";
 import { Sidebar } from "./Sidebar";
 import { ThemeToggle } from "../theme/ThemeToggle";
 import { useFtpStore } from "../../stores/ftpStore";
+import { useConnectionStore } from "../../stores/connectionStore";
+import { useTerminalStore } from "../../stores/terminalStore";
+import { useSettingsStore } from "../../stores/settingsStore";
 import { SftpBrowser } from "../sftp/SftpBrowser";
 import { FtpBrowser } from "../ftp";
 import {
@@ -11,14 +14,42 @@ import {
   Separator as PanelResizeHandle,
   PanelImperativeHandle,
 } from "react-resizable-panels";
-import { VscLayoutSidebarLeft } from "react-icons/vsc";
+import { VscFileBinary, VscLayoutSidebarLeft } from "react-icons/vsc";
 import { Button } from "@heroui/react";
+import { toast } from "sonner";
 
 export function MainLayout() {
   const sidebarRef = useRef<PanelImperativeHandle>(null);
   const [activeSftpSession, setActiveSftpSession] = useState<string | null>(null);
   const [showFtpBrowser, setShowFtpBrowser] = useState(false);
   const ftpId = useFtpStore((state) => state.ftpId);
+  const binaryFrames = useSettingsStore((state) => state.binaryFrames);
+  const setBinaryFrames = useSettingsStore((state) => state.setBinaryFrames);
+
+  // Open profiles marked to connect on startup, a tab for each as it comes up
+  useEffect(() => {
+    useConnectionStore
+      .getState()
+      .runStartupConnections((result) => {
+        if (result.status !== "connected") return;
+        const sessionType = result.session.session_type;
+        useTerminalStore.getState().addSftpTab({
+          id: `sftp-${result.session.id}`,
+          title: `SFTP: ${result.name}`,
+          sessionId: result.session.id,
+          host: sessionType.type === "Ssh" ? sessionType.host : result.name,
+          connectionName: result.name,
+          connectionId: result.connection_id,
+        });
+      })
+      .then((results) => {
+        const failed = results.filter((result) => result.status === "failed");
+        if (failed.length > 0) {
+          toast.error(`Startup connections failed: ${failed.map((result) => result.name).join(", ")}`);
+        }
+      })
+      .catch((error) => toast.error(`Startup connections: ${error}`));
+  }, []);
 
   const handleOpenSftp = (sessionId: string) => {
     setActiveSftpSession(sessionId);
@@ -65,6 +96,17 @@ export function MainLayout() {
             <VscLayoutSidebarLeft size={15} />
           </Button>
           <ThemeToggle />
+          <Button
+            isIconOnly
+            size="sm"
+            variant="ghost"
+            onPress={() => setBinaryFrames(!binaryFrames)}
+            className={`${binaryFrames ? "text-neutral-500 dark:text-neutral-400" : "text-neutral-300 dark:text-neutral-600"} hover:text-neutral-900 dark:hover:text-white data-[hover=true]:bg-black/10 dark:data-[hover=true]:bg-white/10 w-6 h-6 min-w-6 rounded-sm`}
+            aria-label="Toggle binary frames"
+            title={binaryFrames ? "Remote desktop frames: binary channel" : "Remote desktop frames: events"}
+          >
+            <VscFileBinary size={15} />
+          </Button>
         </div>
         <span className="absolute left-1/2 -translate-x-1/2 text-xs font-medium text-neutral-500 dark:text-neutral-400 pointer-events-none">OpenTerm</span>
       </header>
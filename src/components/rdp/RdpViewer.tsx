@@ -1,8 +1,10 @@
 import { useEffect, useRef, useCallback, useState, useMemo } from "react";
 import { listen, UnlistenFn } from "@tauri-apps/api/event";
-import { invoke } from "@tauri-apps/api/core";
-import type { RdpInputEvent, FrameUpdate } from "../../types";
+import { invoke, Channel } from "@tauri-apps/api/core";
+import type { RdpInputEvent, FrameUpdate, RdpReconnecting, RdpDisconnect } from "../../types";
 import { toast } from "sonner";
+import { drawBinaryFrame, drawFrameEvent, FrameStats } from "../../lib/frames";
+import { useSettingsStore } from "../../stores/settingsStore";
 
 interface RdpViewerProps {
   sessionId: string;
@@ -29,6 +31,7 @@ function throttle<T extends (...args: Parameters<T>) => void>(
 export function RdpViewer({ sessionId, width, height, isActive }: RdpViewerProps) {
   const canvasRef = useRef<HTMLCanvasElement>(null);
   const [error, setError] = useState<string | null>(null);
+  const binaryFrames = useSettingsStore((state) => state.binaryFrames);
 
   const sendInput = useCallback(
     async (event: RdpInputEvent) => {
@@ -57,46 +60,92 @@ export function RdpViewer({ sessionId, width, height, isActive }: RdpViewerProps
 
     let unlistenFrame: UnlistenFn | null = null;
     let unlistenError: UnlistenFn | null = null;
-
-    // Helper to decode Base64 to Uint8ClampedArray
-    const decodeBase64 = (base64: string): Uint8ClampedArray => {
-      const binaryString = atob(base64);
-      const bytes = new Uint8ClampedArray(binaryString.length);
-      for (let i = 0; i < binaryString.length; i++) {
-        bytes[i] = binaryString.charCodeAt(i);
-      }
-      return bytes;
+    let unlistenClipboard: UnlistenFn | null = null;
+    let unlistenReconnecting: UnlistenFn | null = null;
+    let unlistenReconnected: UnlistenFn | null = null;
+    let unlistenLost: UnlistenFn | null = null;
+    let unlistenDisconnected: UnlistenFn | null = null;
+    const reconnectToast = `rdp-reconnect-${sessionId}`;
+    const eventStats = new FrameStats("RDP frames (events)");
+    const binaryStats = new FrameStats("RDP frames (binary)");
+    // Tell the backend each frame is painted, so it holds back updates
+    // instead of queueing them while we're behind
+    const ack = () => {
+      invoke("rdp_frame_ack", { sessionId }).catch(() => {});
     };
 
     const setupListeners = async () => {
-      // Frame updates - handles both full frames and dirty rectangles
+      // Frame updates - handles both full frames and dirty rectangles.
+      // Also used until the binary channel is attached.
       unlistenFrame = await listen<FrameUpdate>(
         `rdp-frame-${sessionId}`,
         (event) => {
-          const update = event.payload;
-          
-          if (update.type === "Full") {
-            // Full frame update - decode Base64
-            const data = decodeBase64(update.data);
-            const imageData = new ImageData(data, update.width, update.height);
-            ctx.putImageData(imageData, 0, 0);
-          } else if (update.type === "Partial") {
-            // Dirty rectangle updates - decode Base64
-            for (const rect of update.rects) {
-              if (rect.width > 0 && rect.height > 0 && rect.data.length > 0) {
-                const data = decodeBase64(rect.data);
-                const imageData = new ImageData(data, rect.width, rect.height);
-                ctx.putImageData(imageData, rect.x, rect.y);
-              }
-            }
-          }
+          const start = performance.now();
+          eventStats.record(drawFrameEvent(ctx, event.payload), start);
+          ack();
         }
       );
 
+      if (binaryFrames) {
+        const channel = new Channel<ArrayBuffer>();
+        channel.onmessage = (buffer) => {
+          const start = performance.now();
+          binaryStats.record(drawBinaryFrame(ctx, buffer), start);
+          ack();
+        };
+        invoke("rdp_attach_frame_channel", { sessionId, channel }).catch((err) => {
+          console.error("Failed to attach RDP frame channel, using events:", err);
+        });
+      } else {
+        // Drop a channel attached before the setting was turned off
+        invoke("rdp_detach_frame_channel", { sessionId }).catch(() => {});
+      }
+
       // Error events
       unlistenError = await listen<string>(`rdp-error-${sessionId}`, (event) => {
         setError(event.payload);
-        toast.error(`RDP error: ${event.payload}`);
+        toast.error(`RDP error: ${event.payload}`, { id: reconnectToast });
+      });
+
+      // Connection dropped and is being re-established
+      unlistenReconnecting = await listen<RdpReconnecting>(
+        `rdp-reconnecting-${sessionId}`,
+        (event) => {
+          const { attempt, max_attempts } = event.payload;
+          toast.loading(`Connection lost, reconnecting (${attempt}/${max_attempts})...`, {
+            id: reconnectToast,
+          });
+        }
+      );
+
+      unlistenReconnected = await listen(`rdp-reconnected-${sessionId}`, () => {
+        setError(null);
+        toast.success("Reconnected", { id: reconnectToast });
+      });
+
+      // Profiles that ask before reconnecting
+      unlistenLost = await listen<string>(`rdp-connection-lost-${sessionId}`, (event) => {
+        setError(event.payload);
+        if (window.confirm(`RDP connection lost: ${event.payload}\n\nReconnect?`)) {
+          invoke("rdp_reconnect", { sessionId }).catch((err) => {
+            setError(String(err));
+            toast.error(`Reconnect failed: ${err}`, { id: reconnectToast });
+          });
+        }
+      });
+
+      // The server ended the session, or reconnecting gave up
+      unlistenDisconnected = await listen<RdpDisconnect>(`rdp-disconnected-${sessionId}`, (event) => {
+        const message = event.payload.detail && event.payload.code === "other"
+          ? `${event.payload.message} (${event.payload.detail})`
+          : event.payload.message;
+        setError(message);
+        toast.error(`RDP session ended: ${message}`, { id: reconnectToast });
+      });
+
+      // Text copied on the remote side
+      unlistenClipboard = await listen<string>(`rdp-clipboard-${sessionId}`, (event) => {
+        navigator.clipboard.writeText(event.payload).catch(() => {});
       });
     };
 
@@ -105,8 +154,13 @@ export function RdpViewer({ sessionId, width, height, isActive }: RdpViewerProps
     return () => {
       if (unlistenFrame) unlistenFrame();
       if (unlistenError) unlistenError();
+      if (unlistenClipboard) unlistenClipboard();
+      if (unlistenReconnecting) unlistenReconnecting();
+      if (unlistenReconnected) unlistenReconnected();
+      if (unlistenLost) unlistenLost();
+      if (unlistenDisconnected) unlistenDisconnected();
     };
-  }, [sessionId, width, height]);
+  }, [sessionId, width, height, binaryFrames]);
 
   // Mouse event handlers - throttled to 60 FPS to reduce lag
   const handleMouseMoveRaw = useCallback(
@@ -106,8 +106,8 @@ export function FileTree({
       }
     }
 
-    // Navigate to directory
-    if (file.file_type === "Directory") {
+    // Navigate to directory (or a symlink to one)
+    if (file.file_type === "Directory" || file.target_type === "Directory") {
       if (onClearSelection) {
         onClearSelection();
       }
@@ -116,7 +116,7 @@ export function FileTree({
   };
 
   const handleDoubleClick = (file: FileEntry) => {
-    if (file.file_type !== "Directory" && onOpenFile) {
+    if (file.file_type !== "Directory" && file.target_type !== "Directory" && onOpenFile) {
       onOpenFile(file);
     }
   };
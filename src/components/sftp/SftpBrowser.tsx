@@ -27,10 +27,11 @@ import { cn } from "@/lib/utils";
 
 interface SftpBrowserProps {
   sessionId: string;
+  connectionId?: string; // Saved profile the session came from, for its default folders
   onClose: () => void;
 }
 
-export function SftpBrowser({ sessionId, onClose: _onClose }: SftpBrowserProps) {
+export function SftpBrowser({ sessionId, connectionId, onClose: _onClose }: SftpBrowserProps) {
   const {
     currentPath,
     files,
@@ -71,7 +72,7 @@ export function SftpBrowser({ sessionId, onClose: _onClose }: SftpBrowserProps)
   const [renameName, setRenameName] = useState("");
 
   useEffect(() => {
-    openSftp(sessionId);
+    openSftp(sessionId, connectionId);
     return () => {
       closeSftp();
     };
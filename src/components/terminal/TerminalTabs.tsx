@@ -11,7 +11,7 @@ import { VncViewer } from "../vnc/VncViewer";
 import { RdpViewer } from "../rdp/RdpViewer";
 import { cn } from "@/lib/utils";
 import { TextEditor } from "../editor/TextEditor";
-import { VscTerminal, VscCloud, VscRemote, VscDesktopDownload, VscFolder, VscEdit } from "react-icons/vsc";
+import { VscTerminal, VscCloud, VscRemote, VscDesktopDownload, VscFolder, VscEdit, VscArrowSwap } from "react-icons/vsc";
 import { toast } from "sonner";
 import type { TerminalTab } from "../../types";
 
@@ -122,6 +122,17 @@ export function TerminalTabs() {
               <span className="flex-1 truncate text-xs">
                 {isDirty ? "● " : ""}{tab.title}
               </span>
+              {'sessionInfo' in tab && tab.sessionInfo.tunneled && (
+                <span
+                  title={
+                    tab.sessionInfo.session_type.type === 'Ssh' && tab.sessionInfo.session_type.via
+                      ? `Via ${tab.sessionInfo.session_type.via}`
+                      : "Tunneled"
+                  }
+                >
+                  <VscArrowSwap className="h-3 w-3 shrink-0 text-amber-500" />
+                </span>
+              )}
               {!isFtp && !isSftp && !isVnc && !isRdp && 'sessionInfo' in tab && tab.sessionInfo.session_type.type === 'Ssh' && (
                 <button
                   className="flex items-center justify-center w-5 h-5 rounded hover:bg-blue-500/20 text-neutral-500 dark:text-neutral-400 hover:text-blue-500 transition-colors opacity-0 group-hover:opacity-100"
@@ -182,7 +193,7 @@ export function TerminalTabs() {
               activeTabId === tab.id ? "block" : "hidden"
             )}
           >
-            <SftpBrowser sessionId={tab.sessionId} onClose={() => {}} />
+            <SftpBrowser sessionId={tab.sessionId} connectionId={tab.connectionId} onClose={() => {}} />
           </div>
         ))}
         {vncTabs.map((tab) => (
@@ -1,8 +1,10 @@
 import { useEffect, useRef, useCallback, useState } from "react";
 import { listen, UnlistenFn } from "@tauri-apps/api/event";
-import { invoke } from "@tauri-apps/api/core";
-import type { VncInputEvent } from "../../types";
+import { invoke, Channel } from "@tauri-apps/api/core";
+import type { VncInputEvent, FrameUpdate } from "../../types";
 import { toast } from "sonner";
+import { drawBinaryFrame, drawFrameEvent, FrameStats } from "../../lib/frames";
+import { useSettingsStore } from "../../stores/settingsStore";
 
 interface VncViewerProps {
   sessionId: string;
@@ -15,6 +17,7 @@ export function VncViewer({ sessionId, width, height, isActive }: VncViewerProps
   const canvasRef = useRef<HTMLCanvasElement>(null);
   const [error, setError] = useState<string | null>(null);
   const mouseButtonMask = useRef<number>(0);
+  const binaryFrames = useSettingsStore((state) => state.binaryFrames);
 
   const sendInput = useCallback(
     async (event: VncInputEvent) => {
@@ -39,23 +42,48 @@ export function VncViewer({ sessionId, width, height, isActive }: VncViewerProps
 
     let unlistenFrame: UnlistenFn | null = null;
     let unlistenError: UnlistenFn | null = null;
+    let unlistenDisconnected: UnlistenFn | null = null;
+    const eventStats = new FrameStats("VNC frames (events)");
+    const binaryStats = new FrameStats("VNC frames (binary)");
 
     const setupListeners = async () => {
-      // Frame updates
-      unlistenFrame = await listen<number[]>(
+      // Frame updates, also used until the binary channel is attached
+      unlistenFrame = await listen<FrameUpdate>(
         `vnc-frame-${sessionId}`,
         (event) => {
-          const data = new Uint8ClampedArray(event.payload);
-          const imageData = new ImageData(data, width, height);
-          ctx.putImageData(imageData, 0, 0);
+          const start = performance.now();
+          eventStats.record(drawFrameEvent(ctx, event.payload), start);
         }
       );
 
+      if (binaryFrames) {
+        const channel = new Channel<ArrayBuffer>();
+        channel.onmessage = (buffer) => {
+          const start = performance.now();
+          binaryStats.record(drawBinaryFrame(ctx, buffer), start);
+        };
+        invoke("vnc_attach_frame_channel", { sessionId, channel }).catch((err) => {
+          console.error("Failed to attach VNC frame channel, using events:", err);
+        });
+      } else {
+        // Drop a channel attached before the setting was turned off
+        invoke("vnc_detach_frame_channel", { sessionId }).catch(() => {});
+      }
+
       // Error events
       unlistenError = await listen<string>(`vnc-error-${sessionId}`, (event) => {
         setError(event.payload);
         toast.error(`VNC error: ${event.payload}`);
       });
+
+      // Server closed the connection or stopped responding
+      unlistenDisconnected = await listen<string>(
+        `vnc-disconnected-${sessionId}`,
+        (event) => {
+          setError(event.payload);
+          toast.error(`VNC disconnected: ${event.payload}`);
+        }
+      );
     };
 
     setupListeners();
@@ -63,8 +91,9 @@ export function VncViewer({ sessionId, width, height, isActive }: VncViewerProps
     return () => {
       if (unlistenFrame) unlistenFrame();
       if (unlistenError) unlistenError();
+      if (unlistenDisconnected) unlistenDisconnected();
     };
-  }, [sessionId, width, height]);
+  }, [sessionId, width, height, binaryFrames]);
 
   // Mouse event handlers
   const handleMouseMove = useCallback(
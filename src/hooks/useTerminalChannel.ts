@@ -133,5 +133,18 @@ export function useTerminalChannel({
     };
   }, [sessionId, terminal, flushBuffer]);
 
+  // Show why the session ended (read failure or server disconnect message)
+  useEffect(() => {
+    if (!sessionId || !terminal) return;
+
+    const unlisten = listen<string>(`terminal-error-${sessionId}`, (event) => {
+      terminal.write(`\r\n\x1b[31m${event.payload}\x1b[0m\r\n`);
+    });
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, [sessionId, terminal]);
+
   return { writeToBackend, resize };
 }
@@ -0,0 +1,18 @@
+import type { UntrustedCertificate } from "../types";
+
+export function isUntrustedCertificate(error: unknown): error is UntrustedCertificate {
+  return typeof error === "object" && error !== null && "certificate" in error;
+}
+
+// Ask the user whether to trust a certificate that failed validation
+export function confirmCertificate({ certificate, reason }: UntrustedCertificate): boolean {
+  return window.confirm(
+    `The certificate presented by ${certificate.host}:${certificate.port} is not trusted.\n` +
+      `${reason}\n\n` +
+      `Subject: ${certificate.subject}\n` +
+      `Issuer: ${certificate.issuer}\n` +
+      `Valid: ${certificate.not_before ?? "?"} to ${certificate.not_after ?? "?"}\n` +
+      `SHA-256: ${certificate.fingerprint}\n\n` +
+      `Trust this certificate and connect?`
+  );
+}
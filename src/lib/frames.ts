@@ -0,0 +1,84 @@
+import type { FrameUpdate } from "../types";
+
+// Frame messages come either as JSON events with Base64 pixels or as binary
+// channel messages; see FrameUpdate::encode_binary in src-tauri/src/frame/mod.rs
+// for the binary layout. Both return the backend's send time.
+
+const BINARY_HEADER_LEN = 12;
+const RECT_HEADER_LEN = 8;
+
+const decodeBase64 = (base64: string): Uint8ClampedArray => {
+  const binaryString = atob(base64);
+  const bytes = new Uint8ClampedArray(binaryString.length);
+  for (let i = 0; i < binaryString.length; i++) {
+    bytes[i] = binaryString.charCodeAt(i);
+  }
+  return bytes;
+};
+
+export function drawFrameEvent(ctx: CanvasRenderingContext2D, update: FrameUpdate): number {
+  if (update.type === "Full") {
+    const data = decodeBase64(update.data);
+    ctx.putImageData(new ImageData(data, update.width, update.height), 0, 0);
+  } else {
+    for (const rect of update.rects) {
+      if (rect.width > 0 && rect.height > 0 && rect.data.length > 0) {
+        const data = decodeBase64(rect.data);
+        ctx.putImageData(new ImageData(data, rect.width, rect.height), rect.x, rect.y);
+      }
+    }
+  }
+  return update.sent_at;
+}
+
+export function drawBinaryFrame(ctx: CanvasRenderingContext2D, buffer: ArrayBuffer): number {
+  const view = new DataView(buffer);
+  const count = view.getUint16(2, true);
+  const sentAt = view.getFloat64(4, true);
+
+  let offset = BINARY_HEADER_LEN;
+  for (let i = 0; i < count; i++) {
+    const x = view.getUint16(offset, true);
+    const y = view.getUint16(offset + 2, true);
+    const width = view.getUint16(offset + 4, true);
+    const height = view.getUint16(offset + 6, true);
+    offset += RECT_HEADER_LEN;
+
+    const length = width * height * 4;
+    if (length > 0) {
+      // Pixels are used in place, without copying
+      const data = new Uint8ClampedArray(buffer, offset, length);
+      ctx.putImageData(new ImageData(data, width, height), x, y);
+    }
+    offset += length;
+  }
+  return sentAt;
+}
+
+const STATS_INTERVAL = 100; // frames
+
+// Latency (backend send to painted) and time spent decoding and drawing,
+// logged every STATS_INTERVAL frames to compare the two transports
+export class FrameStats {
+  private frames = 0;
+  private latency = 0;
+  private drawTime = 0;
+
+  constructor(private label: string) {}
+
+  record(sentAt: number, drawStart: number) {
+    this.frames++;
+    this.latency += Date.now() - sentAt;
+    this.drawTime += performance.now() - drawStart;
+
+    if (this.frames === STATS_INTERVAL) {
+      console.debug(
+        `${this.label}: avg latency ${(this.latency / this.frames).toFixed(1)}ms, ` +
+          `decode+draw ${(this.drawTime / this.frames).toFixed(2)}ms over ${this.frames} frames`
+      );
+      this.frames = 0;
+      this.latency = 0;
+      this.drawTime = 0;
+    }
+  }
+}
@@ -1,6 +1,40 @@
 import { create } from "zustand";
 import { invoke } from "@tauri-apps/api/core";
-import type { ConnectionProfile, SessionInfo, AuthMethod } from "../types";
+import { listen } from "@tauri-apps/api/event";
+import type {
+  ConnectionProfile,
+  ConnectionImportSummary,
+  ForeignSession,
+  SessionInfo,
+  StorageEncryptionStatus,
+  KeychainBackendStatus,
+  ProfileMetadata,
+  ConnectionStats,
+  GroupConnectOptions,
+  GroupConnectResult,
+  SshConnectStage,
+  AuthMethod,
+  TerminalSettings,
+} from "../types";
+
+// Run an SSH connect command, passing stages to onStage as
+// ssh-connect-progress-{connectId} arrives
+async function withConnectProgress<T>(
+  command: string,
+  args: Record<string, unknown>,
+  onStage?: (stage: SshConnectStage) => void
+): Promise<T> {
+  if (!onStage) return invoke<T>(command, args);
+  const connectId = crypto.randomUUID();
+  const unlisten = await listen<SshConnectStage>(`ssh-connect-progress-${connectId}`, (event) =>
+    onStage(event.payload)
+  );
+  try {
+    return await invoke<T>(command, { ...args, connectId });
+  } finally {
+    unlisten();
+  }
+}
 
 interface ConnectionState {
   connections: ConnectionProfile[];
@@ -16,7 +50,9 @@ interface ConnectionState {
     username: string,
     authType: "password" | "publickey" | "agent",
     privateKeyPath?: string,
-    password?: string
+    password?: string,
+    passphrase?: string,
+    savePassphrase?: boolean // Keep the key passphrase in the keychain
   ) => Promise<ConnectionProfile>;
   saveFtpConnection: (
     name: string,
@@ -40,6 +76,8 @@ interface ConnectionState {
     password: string | null,
     domain: string | null
   ) => Promise<ConnectionProfile>;
+  // Settings left undefined keep what's saved, as do the other settings of
+  // the profile
   updateConnection: (
     id: string,
     name: string,
@@ -51,24 +89,76 @@ interface ConnectionState {
     privateKeyPath?: string,
     password?: string,
     anonymous?: boolean,
-    domain?: string
+    domain?: string,
+    passphrase?: string,
+    savePassphrase?: boolean, // false forgets a saved passphrase
+    metadata?: ProfileMetadata
   ) => Promise<ConnectionProfile>;
   deleteConnection: (id: string) => Promise<void>;
+  // Matches the name, notes or owner, ignoring case; doesn't touch connections
+  searchConnections: (search: string) => Promise<ConnectionProfile[]>;
+  setConnectionTags: (id: string, tags: string[]) => Promise<void>;
+  setConnectionGroup: (id: string, group: string | null) => Promise<void>;
+  setConnectionFavorite: (id: string, favorite: boolean) => Promise<void>;
+  // Absolute paths; null or "" clears. SSH and FTP profiles only.
+  setConnectionDefaultPaths: (id: string, remotePath: string | null, localPath: string | null) => Promise<void>;
+  // null clears them; resolves to what was stored
+  setConnectionTerminalSettings: (
+    id: string,
+    settings: TerminalSettings | null
+  ) => Promise<TerminalSettings | null>;
+  // Favorites are listed in the order of orderedIds
+  reorderConnections: (orderedIds: string[]) => Promise<void>;
+  // Preview of hosts in an OpenSSH config; nothing is saved until
+  // saveImportedConnections is called with the ones the user keeps
+  importSshConfig: (path?: string) => Promise<ConnectionProfile[]>;
+  saveImportedConnections: (profiles: ConnectionProfile[]) => Promise<ConnectionProfile[]>;
+  // Previews of PuTTY sessions (Windows only) and FileZilla sites;
+  // saveForeignSessions saves the ones the user keeps, given the same
+  // FileZilla path so saved passwords can be read again
+  importPuttySessions: () => Promise<ForeignSession[]>;
+  importFilezillaSites: (path?: string) => Promise<ForeignSession[]>;
+  saveForeignSessions: (sessions: ForeignSession[], filezillaPath?: string) => Promise<ConnectionImportSummary>;
+  // Passwords are only exported with a passphrase, which encrypts the file
+  exportConnections: (path: string, includeSecrets: boolean, passphrase?: string) => Promise<number>;
+  importConnections: (path: string, passphrase?: string) => Promise<ConnectionImportSummary>;
+  // Storage commands fail while encrypted storage is locked
+  getStorageEncryptionStatus: () => Promise<StorageEncryptionStatus>;
+  enableStorageEncryption: (masterPassword: string) => Promise<void>;
+  unlockStorage: (masterPassword: string) => Promise<void>;
+  // Fails, leaving storage encrypted, if the keychain won't take the passwords
+  disableStorageEncryption: (masterPassword: string) => Promise<void>;
   connectToSaved: (
     connectionId: string,
     password?: string,
-    passphrase?: string
+    passphrase?: string,
+    onStage?: (stage: SshConnectStage) => void
   ) => Promise<SessionInfo>;
+  // Every SSH profile in the group or with the tag; failures don't stop the rest
+  connectGroup: (groupOrTag: string, options?: GroupConnectOptions) => Promise<GroupConnectResult[]>;
+  // Opens auto_connect profiles once per app run, passing each result to
+  // onResult as it arrives; does nothing when turned off globally
+  runStartupConnections: (onResult?: (result: GroupConnectResult) => void) => Promise<GroupConnectResult[]>;
+  setConnectionAutoConnect: (id: string, autoConnect: boolean) => Promise<void>;
+  getAutoConnectEnabled: () => Promise<boolean>;
+  setAutoConnectEnabled: (enabled: boolean) => Promise<void>;
   connectDirect: (
     host: string,
     port: number,
     username: string,
-    auth: AuthMethod
+    auth: AuthMethod,
+    onStage?: (stage: SshConnectStage) => void
   ) => Promise<SessionInfo>;
   hasStoredPassword: (connectionId: string) => Promise<boolean>;
+  getConnectionStats: (connectionId: string) => Promise<ConnectionStats>;
+  // Most recently connected or attempted first; limit defaults to 10
+  getRecentConnections: (limit?: number) => Promise<ConnectionStats[]>;
+  getKeychainBackendStatus: () => Promise<KeychainBackendStatus>;
+  // Passwords already saved stay where they are
+  setStoreSecretsInFile: (enabled: boolean) => Promise<KeychainBackendStatus>;
 }
 
-export const useConnectionStore = create<ConnectionState>((set) => ({
+export const useConnectionStore = create<ConnectionState>((set, get) => ({
   connections: [],
   loading: false,
   error: null,
@@ -90,7 +180,9 @@ export const useConnectionStore = create<ConnectionState>((set) => ({
     username,
     authType,
     privateKeyPath,
-    password
+    password,
+    passphrase,
+    savePassphrase
   ) => {
     const profile = await invoke<ConnectionProfile>("save_connection", {
       name,
@@ -100,6 +192,8 @@ export const useConnectionStore = create<ConnectionState>((set) => ({
       authType,
       privateKeyPath,
       password,
+      passphrase,
+      savePassphrase,
     });
 
     set((state) => ({
@@ -158,7 +252,22 @@ export const useConnectionStore = create<ConnectionState>((set) => ({
     return profile;
   },
 
-  updateConnection: async (id, name, connectionType, host, port, username, authType, privateKeyPath, password, anonymous, domain) => {
+  updateConnection: async (
+    id,
+    name,
+    connectionType,
+    host,
+    port,
+    username,
+    authType,
+    privateKeyPath,
+    password,
+    anonymous,
+    domain,
+    passphrase,
+    savePassphrase,
+    metadata
+  ) => {
     const profile = await invoke<ConnectionProfile>("update_connection", {
       id,
       name,
@@ -171,6 +280,9 @@ export const useConnectionStore = create<ConnectionState>((set) => ({
       password,
       anonymous,
       domain,
+      passphrase,
+      savePassphrase,
+      ...metadata,
     });
 
     set((state) => ({
@@ -187,24 +299,185 @@ export const useConnectionStore = create<ConnectionState>((set) => ({
     }));
   },
 
-  connectToSaved: async (connectionId, password, passphrase) => {
-    return invoke<SessionInfo>("connect_saved", {
-      connectionId,
-      password,
-      passphrase,
-    });
+  searchConnections: async (search) => {
+    return invoke<ConnectionProfile[]>("list_connections", { search });
   },
 
-  connectDirect: async (host, port, username, auth) => {
-    return invoke<SessionInfo>("create_ssh_terminal", {
-      host,
-      port,
-      username,
-      auth,
-    });
+  setConnectionTags: async (id, tags) => {
+    await invoke("set_connection_tags", { id, tags });
+    // Reload to pick up the normalized tags
+    const profile = await invoke<ConnectionProfile>("get_connection", { id });
+    set((state) => ({
+      connections: state.connections.map((c) => (c.id === id ? profile : c)),
+    }));
+  },
+
+  setConnectionGroup: async (id, group) => {
+    await invoke("set_connection_group", { id, group });
+    // Group changes reorder the list
+    const connections = await invoke<ConnectionProfile[]>("list_connections");
+    set({ connections });
+  },
+
+  setConnectionFavorite: async (id, favorite) => {
+    await invoke("set_connection_favorite", { id, favorite });
+    const connections = await invoke<ConnectionProfile[]>("list_connections");
+    set({ connections });
+  },
+
+  setConnectionDefaultPaths: async (id, remotePath, localPath) => {
+    await invoke("set_connection_default_paths", { id, remotePath, localPath });
+    const profile = await invoke<ConnectionProfile>("get_connection", { id });
+    set((state) => ({
+      connections: state.connections.map((c) => (c.id === id ? profile : c)),
+    }));
+  },
+
+  setConnectionTerminalSettings: async (id, settings) => {
+    const stored = await invoke<TerminalSettings | null>("set_connection_terminal_settings", { id, settings });
+    set((state) => ({
+      connections: state.connections.map((c) =>
+        c.id === id ? { ...c, terminal_settings: stored } : c
+      ),
+    }));
+    return stored;
+  },
+
+  reorderConnections: async (orderedIds) => {
+    await invoke("reorder_connections", { orderedIds });
+    const connections = await invoke<ConnectionProfile[]>("list_connections");
+    set({ connections });
+  },
+
+  importSshConfig: async (path) => {
+    return invoke<ConnectionProfile[]>("import_ssh_config", { path });
+  },
+
+  saveImportedConnections: async (profiles) => {
+    const saved = await invoke<ConnectionProfile[]>("save_imported_connections", { profiles });
+    set((state) => ({
+      connections: [...state.connections, ...saved],
+    }));
+    return saved;
+  },
+
+  importPuttySessions: async () => {
+    return invoke<ForeignSession[]>("import_putty_sessions");
+  },
+
+  importFilezillaSites: async (path) => {
+    return invoke<ForeignSession[]>("import_filezilla_sites", { path });
+  },
+
+  saveForeignSessions: async (sessions, filezillaPath) => {
+    const summary = await invoke<ConnectionImportSummary>("save_foreign_sessions", { sessions, filezillaPath });
+    set((state) => ({
+      connections: [...state.connections, ...summary.imported],
+    }));
+    return summary;
+  },
+
+  exportConnections: async (path, includeSecrets, passphrase) => {
+    return invoke<number>("export_connections", { path, includeSecrets, passphrase });
+  },
+
+  importConnections: async (path, passphrase) => {
+    const summary = await invoke<ConnectionImportSummary>("import_connections", { path, passphrase });
+    set((state) => ({
+      connections: [...state.connections, ...summary.imported],
+    }));
+    return summary;
+  },
+
+  getStorageEncryptionStatus: async () => {
+    return invoke<StorageEncryptionStatus>("storage_encryption_status");
+  },
+
+  enableStorageEncryption: async (masterPassword) => {
+    await invoke("enable_storage_encryption", { masterPassword });
+  },
+
+  unlockStorage: async (masterPassword) => {
+    await invoke("unlock_storage", { masterPassword });
+    await get().loadConnections();
+  },
+
+  disableStorageEncryption: async (masterPassword) => {
+    await invoke("disable_storage_encryption", { masterPassword });
+  },
+
+  connectToSaved: async (connectionId, password, passphrase, onStage) => {
+    return withConnectProgress<SessionInfo>(
+      "connect_saved",
+      {
+        connectionId,
+        password,
+        passphrase,
+      },
+      onStage
+    );
+  },
+
+  connectGroup: async (groupOrTag, options) => {
+    return invoke<GroupConnectResult[]>("connect_group", { groupOrTag, options });
+  },
+
+  runStartupConnections: async (onResult) => {
+    const unlisten = onResult
+      ? await listen<GroupConnectResult>("startup-connection", (event) => onResult(event.payload))
+      : null;
+    try {
+      return await invoke<GroupConnectResult[]>("run_startup_connections");
+    } finally {
+      unlisten?.();
+    }
+  },
+
+  setConnectionAutoConnect: async (id, autoConnect) => {
+    await invoke("set_connection_auto_connect", { id, autoConnect });
+    set((state) => ({
+      connections: state.connections.map((c) => (c.id === id ? { ...c, auto_connect: autoConnect } : c)),
+    }));
+  },
+
+  getAutoConnectEnabled: async () => {
+    return invoke<boolean>("get_auto_connect_enabled");
+  },
+
+  setAutoConnectEnabled: async (enabled) => {
+    await invoke("set_auto_connect_enabled", { enabled });
+  },
+
+  connectDirect: async (host, port, username, auth, onStage) => {
+    return withConnectProgress<SessionInfo>(
+      "create_ssh_terminal",
+      {
+        host,
+        port,
+        username,
+        auth,
+      },
+      onStage
+    );
   },
 
   hasStoredPassword: async (connectionId) => {
     return invoke<boolean>("has_stored_password", { connectionId });
   },
+
+  getConnectionStats: async (connectionId) => {
+    return invoke<ConnectionStats>("get_connection_stats", { id: connectionId });
+  },
+
+  getRecentConnections: async (limit) => {
+    return invoke<ConnectionStats[]>("get_recent_connections", { limit });
+  },
+
+  getKeychainBackendStatus: async () => {
+    return invoke<KeychainBackendStatus>("keychain_backend_status");
+  },
+
+  setStoreSecretsInFile: async (enabled) => {
+    return invoke<KeychainBackendStatus>("set_store_secrets_in_file", { enabled });
+  },
 }));
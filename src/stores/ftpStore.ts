@@ -1,7 +1,22 @@
 import { create } from "zustand";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
-import type { FileEntry, TransferProgress } from "../types";
+import type { FileBrowserOpened, FileEntry, FtpTransferMode, TransferProgress } from "../types";
+import { useLocalStore } from "./localStore";
+
+// Extensions transferred in ASCII mode unless the caller says otherwise
+const TEXT_EXTENSIONS = new Set([
+  "txt", "csv", "log", "md", "ini", "cfg", "conf", "xml", "json", "yml", "yaml",
+  "html", "htm", "css", "js", "ts", "php", "py", "pl", "rb", "sh", "bat", "cmd", "sql",
+]);
+
+// ASCII for well-known text files, binary for everything else
+export function ftpTransferModeFor(path: string): FtpTransferMode {
+  const name = path.split(/[\\/]/).pop() ?? "";
+  const dot = name.lastIndexOf(".");
+  const ext = dot > 0 ? name.slice(dot + 1).toLowerCase() : "";
+  return TEXT_EXTENSIONS.has(ext) ? "Ascii" : "Binary";
+}
 
 interface FtpState {
   ftpId: string | null;
@@ -10,6 +25,7 @@ interface FtpState {
   connectionId: string | null;
   currentPath: string;
   files: FileEntry[];
+  nameFilter: string; // Glob such as "*.log"; directories always show
   loading: boolean;
   error: string | null;
   transfers: TransferProgress[];
@@ -23,14 +39,21 @@ interface FtpState {
     connectionName?: string,
     connectionId?: string
   ) => Promise<void>;
+  // Saved profile: password from the keychain unless given, starting in its default folders
+  connectSaved: (connectionId: string, connectionName: string, host: string, password?: string) => Promise<void>;
   disconnect: () => Promise<void>;
   navigateTo: (path: string) => Promise<void>;
   refresh: () => Promise<void>;
+  setNameFilter: (filter: string) => Promise<void>;
   createDirectory: (name: string) => Promise<void>;
   deleteItem: (path: string, isDir: boolean) => Promise<void>;
   rename: (oldPath: string, newPath: string) => Promise<void>;
-  download: (remotePath: string, localPath: string) => Promise<void>;
-  upload: (localPath: string, remotePath: string) => Promise<void>;
+  copyPath: (entry: FileEntry) => Promise<void>; // shell-quoted, to the clipboard
+  // mode defaults to ftpTransferModeFor the file name
+  download: (remotePath: string, localPath: string, mode?: FtpTransferMode) => Promise<void>;
+  upload: (localPath: string, remotePath: string, mode?: FtpTransferMode) => Promise<void>;
+  // Always binary; deletes the local file only after the upload verifies
+  moveFromLocal: (localPath: string, remotePath: string) => Promise<void>;
   uploadFolder: (localPath: string, remotePath: string) => Promise<void>;
   updateTransferProgress: (
     id: string,
@@ -48,6 +71,7 @@ export const useFtpStore = create<FtpState>((set, get) => ({
   connectionId: null,
   currentPath: "/",
   files: [],
+  nameFilter: "",
   loading: false,
   error: null,
   transfers: [],
@@ -84,6 +108,34 @@ export const useFtpStore = create<FtpState>((set, get) => ({
     }
   },
 
+  connectSaved: async (connectionId, connectionName, host, password) => {
+    set({ loading: true, error: null });
+    try {
+      const opened = await invoke<FileBrowserOpened>("connect_saved_ftp", { connectionId, password });
+      const ftpId = opened.browser_id;
+      const files = await invoke<FileEntry[]>("ftp_list_dir", {
+        ftpId,
+        path: opened.current_path,
+      });
+
+      set({
+        ftpId,
+        host,
+        connectionName,
+        connectionId,
+        currentPath: opened.current_path,
+        files,
+        loading: false,
+      });
+      if (opened.default_local_path) {
+        await useLocalStore.getState().navigateTo(opened.default_local_path);
+      }
+    } catch (error) {
+      set({ error: String(error), loading: false });
+      throw error;
+    }
+  },
+
   disconnect: async () => {
     const { ftpId } = get();
     if (ftpId) {
@@ -96,20 +148,25 @@ export const useFtpStore = create<FtpState>((set, get) => ({
       connectionId: null,
       currentPath: "/",
       files: [],
+      nameFilter: "",
       error: null,
       transfers: [],
     });
   },
 
   navigateTo: async (path) => {
-    const { ftpId } = get();
+    const { ftpId, nameFilter } = get();
     if (!ftpId) return;
 
     set({ loading: true, error: null });
     try {
-      const files = await invoke<FileEntry[]>("ftp_list_dir", { ftpId, path });
-      // Get the actual current path after navigation
-      const currentPath = await invoke<string>("ftp_pwd", { ftpId });
+      // Change directory first so the server resolves "..", then list it
+      const currentPath = await invoke<string>("ftp_chdir", { ftpId, path });
+      const files = await invoke<FileEntry[]>("ftp_list_dir", {
+        ftpId,
+        path: currentPath,
+        filter: nameFilter || undefined,
+      });
       set({ currentPath, files, loading: false });
     } catch (error) {
       set({ error: String(error), loading: false });
@@ -117,7 +174,7 @@ export const useFtpStore = create<FtpState>((set, get) => ({
   },
 
   refresh: async () => {
-    const { ftpId, currentPath } = get();
+    const { ftpId, currentPath, nameFilter } = get();
     if (!ftpId) return;
 
     set({ loading: true, error: null });
@@ -125,6 +182,7 @@ export const useFtpStore = create<FtpState>((set, get) => ({
       const files = await invoke<FileEntry[]>("ftp_list_dir", {
         ftpId,
         path: currentPath,
+        filter: nameFilter || undefined,
       });
       set({ files, loading: false });
     } catch (error) {
@@ -132,6 +190,11 @@ export const useFtpStore = create<FtpState>((set, get) => ({
     }
   },
 
+  setNameFilter: async (filter) => {
+    set({ nameFilter: filter });
+    await get().refresh();
+  },
+
   createDirectory: async (name) => {
     const { ftpId, currentPath } = get();
     if (!ftpId) return;
@@ -157,7 +220,12 @@ export const useFtpStore = create<FtpState>((set, get) => ({
     await get().refresh();
   },
 
-  download: async (remotePath, localPath) => {
+  copyPath: async (entry) => {
+    const quoted = await invoke<string>("shell_quoted_path", { entry });
+    await navigator.clipboard.writeText(quoted);
+  },
+
+  download: async (remotePath, localPath, mode) => {
     const { ftpId } = get();
     if (!ftpId) return;
 
@@ -165,6 +233,7 @@ export const useFtpStore = create<FtpState>((set, get) => ({
       ftpId,
       remotePath,
       localPath,
+      mode: mode ?? ftpTransferModeFor(remotePath),
     });
 
     set((state) => ({
@@ -204,7 +273,7 @@ export const useFtpStore = create<FtpState>((set, get) => ({
     );
   },
 
-  upload: async (localPath, remotePath) => {
+  upload: async (localPath, remotePath, mode) => {
     const { ftpId } = get();
     if (!ftpId) return;
 
@@ -217,6 +286,7 @@ export const useFtpStore = create<FtpState>((set, get) => ({
       ftpId,
       localPath,
       remotePath,
+      mode: mode ?? ftpTransferModeFor(localPath),
     });
 
     set((state) => ({
@@ -257,6 +327,58 @@ export const useFtpStore = create<FtpState>((set, get) => ({
     );
   },
 
+  moveFromLocal: async (localPath, remotePath) => {
+    const { ftpId } = get();
+    if (!ftpId) return;
+
+    let progressUnsub: (() => void) | null = null;
+    let completeUnsub: (() => void) | null = null;
+    let errorUnsub: (() => void) | null = null;
+
+    const progress = await invoke<TransferProgress>("ftp_move_from_local", {
+      ftpId,
+      localPath,
+      remotePath,
+    });
+
+    set((state) => ({
+      transfers: [...state.transfers, progress],
+    }));
+
+    progressUnsub = await listen<[number, number]>(
+      `ftp-transfer-progress-${progress.id}`,
+      (event) => {
+        get().updateTransferProgress(
+          progress.id,
+          event.payload[0],
+          event.payload[1]
+        );
+      }
+    );
+
+    completeUnsub = await listen<boolean>(
+      `ftp-transfer-complete-${progress.id}`,
+      () => {
+        get().completeTransfer(progress.id);
+        get().refresh();
+        useLocalStore.getState().refresh();
+        if (progressUnsub) progressUnsub();
+        if (completeUnsub) completeUnsub();
+        if (errorUnsub) errorUnsub();
+      }
+    );
+
+    errorUnsub = await listen<string>(
+      `ftp-transfer-error-${progress.id}`,
+      (event) => {
+        get().failTransfer(progress.id, event.payload);
+        if (progressUnsub) progressUnsub();
+        if (completeUnsub) completeUnsub();
+        if (errorUnsub) errorUnsub();
+      }
+    );
+  },
+
   uploadFolder: async (localPath, remotePath) => {
     const { ftpId } = get();
     if (!ftpId) return;
@@ -5,6 +5,7 @@ import type { FileEntry } from "../types";
 interface LocalState {
   currentPath: string;
   files: FileEntry[];
+  nameFilter: string; // Glob such as "*.log"; directories always show
   loading: boolean;
   error: string | null;
   selectedFiles: Set<string>;
@@ -14,6 +15,7 @@ interface LocalState {
   navigateTo: (path: string) => Promise<void>;
   navigateUp: () => Promise<void>;
   refresh: () => Promise<void>;
+  setNameFilter: (filter: string) => Promise<void>;
   goHome: () => Promise<void>;
   goDownloads: () => Promise<void>;
   selectFile: (path: string) => void;
@@ -21,11 +23,13 @@ interface LocalState {
   clearSelection: () => void;
   selectAll: () => void;
   getSelectedFiles: () => FileEntry[];
+  copyPath: (entry: FileEntry) => Promise<void>; // shell-quoted, to the clipboard
 }
 
 export const useLocalStore = create<LocalState>((set, get) => ({
   currentPath: "",
   files: [],
+  nameFilter: "",
   loading: false,
   error: null,
   selectedFiles: new Set(),
@@ -48,7 +52,10 @@ export const useLocalStore = create<LocalState>((set, get) => ({
   navigateTo: async (path: string) => {
     set({ loading: true, error: null, selectedFiles: new Set() });
     try {
-      const files = await invoke<FileEntry[]>("local_list_dir", { path });
+      const files = await invoke<FileEntry[]>("local_list_dir", {
+        path,
+        filter: get().nameFilter || undefined,
+      });
       set({
         currentPath: path,
         files,
@@ -73,6 +80,11 @@ export const useLocalStore = create<LocalState>((set, get) => ({
     await get().navigateTo(currentPath);
   },
 
+  setNameFilter: async (filter) => {
+    set({ nameFilter: filter });
+    await get().refresh();
+  },
+
   goHome: async () => {
     try {
       const homeDir = await invoke<string>("local_get_home_dir");
@@ -120,4 +132,9 @@ export const useLocalStore = create<LocalState>((set, get) => ({
     const { files, selectedFiles } = get();
     return files.filter((f) => selectedFiles.has(f.path));
   },
+
+  copyPath: async (entry) => {
+    const quoted = await invoke<string>("shell_quoted_path", { entry, local: true });
+    await navigator.clipboard.writeText(quoted);
+  },
 }));
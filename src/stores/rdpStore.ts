@@ -1,8 +1,39 @@
 import { create } from "zustand";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import { confirmCertificate, isUntrustedCertificate } from "../lib/certificates";
+import type { RdpConnectError, RdpConnectStage } from "../types";
+
+// rdp_connect returns at once and reports the outcome as
+// rdp-connected-{id} / rdp-connect-failed-{id}, so listen before invoking
+async function connectSession(
+  sessionId: string,
+  args: Record<string, unknown>,
+  onStage: (stage: RdpConnectStage) => void
+): Promise<string> {
+  let settle!: { resolve: () => void; reject: (error: RdpConnectError) => void };
+  const outcome = new Promise<void>((resolve, reject) => {
+    settle = { resolve, reject };
+  });
+  const unlisteners = await Promise.all([
+    listen(`rdp-connected-${sessionId}`, () => settle.resolve()),
+    listen<RdpConnectError>(`rdp-connect-failed-${sessionId}`, (event) => settle.reject(event.payload)),
+    listen<RdpConnectStage>(`rdp-connect-progress-${sessionId}`, (event) => onStage(event.payload)),
+  ]);
+  try {
+    await invoke<string>("rdp_connect", { ...args, sessionId });
+    await outcome;
+    return sessionId;
+  } finally {
+    unlisteners.forEach((unlisten) => unlisten());
+  }
+}
 
 interface RdpState {
   sessionId: string | null;
+  // Session still connecting, so disconnect can cancel it
+  pendingSessionId: string | null;
+  connectStage: RdpConnectStage | null;
   host: string;
   port: number;
   username: string;
@@ -28,10 +59,13 @@ interface RdpState {
   ) => Promise<string>;
   disconnect: () => Promise<void>;
   sendInput: (event: import("../types").RdpInputEvent) => Promise<void>;
+  sendSpecialKey: (combo: import("../types").RdpSpecialKey) => Promise<void>;
 }
 
 export const useRdpStore = create<RdpState>((set, get) => ({
   sessionId: null,
+  pendingSessionId: null,
+  connectStage: null,
   host: "",
   port: 3389,
   username: "",
@@ -55,20 +89,38 @@ export const useRdpStore = create<RdpState>((set, get) => ({
     connectionId
   ) => {
     set({ error: null });
+    const args = {
+      host,
+      port,
+      username,
+      password,
+      domain: domain || null,
+      width,
+      height,
+      quality,
+    };
+    const onStage = (connectStage: RdpConnectStage) => set({ connectStage });
     try {
-      const sessionId = await invoke<string>("rdp_connect", {
-        host,
-        port,
-        username,
-        password,
-        domain: domain || null,
-        width,
-        height,
-        quality,
-      });
+      let sessionId = crypto.randomUUID();
+      set({ pendingSessionId: sessionId });
+      try {
+        await connectSession(sessionId, args, onStage);
+      } catch (error) {
+        if (!isUntrustedCertificate(error) || !confirmCertificate(error)) throw error;
+        await invoke("rdp_trust_certificate", {
+          host,
+          port,
+          fingerprint: error.certificate.fingerprint,
+        });
+        sessionId = crypto.randomUUID();
+        set({ pendingSessionId: sessionId });
+        await connectSession(sessionId, args, onStage);
+      }
 
       set({
         sessionId,
+        pendingSessionId: null,
+        connectStage: null,
         host,
         port,
         username,
@@ -81,13 +133,16 @@ export const useRdpStore = create<RdpState>((set, get) => ({
 
       return sessionId;
     } catch (error) {
-      set({ error: String(error), connected: false });
+      const message = isUntrustedCertificate(error)
+        ? `Untrusted certificate: ${error.reason}`
+        : String(error);
+      set({ error: message, connected: false, pendingSessionId: null, connectStage: null });
       throw error;
     }
   },
 
   disconnect: async () => {
-    const { sessionId } = get();
+    const sessionId = get().sessionId ?? get().pendingSessionId;
     if (sessionId) {
       try {
         await invoke("rdp_disconnect", { sessionId });
@@ -97,6 +152,8 @@ export const useRdpStore = create<RdpState>((set, get) => ({
     }
     set({
       sessionId: null,
+      pendingSessionId: null,
+      connectStage: null,
       host: "",
       port: 3389,
       username: "",
@@ -117,4 +174,15 @@ export const useRdpStore = create<RdpState>((set, get) => ({
       console.error("Failed to send RDP input:", error);
     }
   },
+
+  sendSpecialKey: async (combo) => {
+    const { sessionId } = get();
+    if (!sessionId) return;
+
+    try {
+      await invoke("rdp_send_special_key", { sessionId, combo });
+    } catch (error) {
+      console.error("Failed to send RDP special key:", error);
+    }
+  },
 }));
@@ -0,0 +1,21 @@
+import { create } from "zustand";
+import { persist } from "zustand/middleware";
+
+interface SettingsState {
+  // Stream remote desktop frames as binary channel messages; when off they
+  // arrive as JSON events with Base64 pixels
+  binaryFrames: boolean;
+
+  setBinaryFrames: (enabled: boolean) => void;
+}
+
+export const useSettingsStore = create<SettingsState>()(
+  persist(
+    (set) => ({
+      binaryFrames: true,
+
+      setBinaryFrames: (enabled) => set({ binaryFrames: enabled }),
+    }),
+    { name: "openterm-settings" }
+  )
+);
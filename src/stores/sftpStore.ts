@@ -1,7 +1,15 @@
 import { create } from "zustand";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
-import type { FileEntry, TransferProgress } from "../types";
+import type {
+  FileBrowserOpened,
+  FileEntry,
+  ResumableTransfer,
+  SftpDeleteComplete,
+  SftpDeleteSummary,
+  TransferProgress,
+} from "../types";
+import { useLocalStore } from "./localStore";
 
 export interface TransferMeta {
   startTime: number;
@@ -16,22 +24,34 @@ interface SftpState {
   sessionId: string | null;
   currentPath: string;
   files: FileEntry[];
+  nameFilter: string; // Glob such as "*.log"; directories always show
   loading: boolean;
   error: string | null;
   transfers: TransferProgress[];
   transferMeta: Record<string, TransferMeta>;
 
   // Actions
-  openSftp: (sessionId: string) => Promise<void>;
+  // With a connectionId, starts in the profile's default folders
+  openSftp: (sessionId: string, connectionId?: string) => Promise<void>;
   closeSftp: () => Promise<void>;
   navigateTo: (path: string) => Promise<void>;
   refresh: () => Promise<void>;
+  setNameFilter: (filter: string) => Promise<void>;
   createDirectory: (name: string) => Promise<void>;
   deleteItem: (path: string, isDir: boolean) => Promise<void>;
+  deleteItems: (items: { path: string; isDir: boolean }[]) => Promise<SftpDeleteSummary | undefined>;
   rename: (oldPath: string, newPath: string) => Promise<void>;
+  copyPath: (entry: FileEntry) => Promise<void>; // shell-quoted, to the clipboard
   download: (remotePath: string, localPath: string) => Promise<void>;
   upload: (localPath: string, remotePath: string) => Promise<void>;
+  // Deletes the local file only after the upload verifies
+  moveFromLocal: (localPath: string, remotePath: string) => Promise<void>;
   uploadFolder: (localPath: string, remotePath: string) => Promise<void>;
+  // Interrupted transfers survive restarts; resume them on a session to
+  // the same server
+  listResumableTransfers: () => Promise<ResumableTransfer[]>;
+  resumeTransfer: (transferId: string) => Promise<void>;
+  discardResumableTransfer: (transferId: string) => Promise<void>;
   updateTransferProgress: (id: string, transferred: number, total: number) => void;
   completeTransfer: (id: string) => void;
   failTransfer: (id: string, error: string) => void;
@@ -44,25 +64,33 @@ export const useSftpStore = create<SftpState>((set, get) => ({
   sessionId: null,
   currentPath: "/",
   files: [],
+  nameFilter: "",
   loading: false,
   error: null,
   transfers: [],
   transferMeta: {},
 
-  openSftp: async (sessionId) => {
+  openSftp: async (sessionId, connectionId) => {
     set({ loading: true, error: null });
     try {
-      const sftpId = await invoke<string>("sftp_open", { sessionId });
-      const homePath = await invoke<string>("sftp_realpath", { sftpId, path: "." });
-      const files = await invoke<FileEntry[]>("sftp_list_dir", { sftpId, path: homePath });
+      const opened = await invoke<FileBrowserOpened>("sftp_open", { sessionId, connectionId });
+      const sftpId = opened.browser_id;
+      const files = await invoke<FileEntry[]>("sftp_list_dir", {
+        sftpId,
+        path: opened.current_path,
+        followLinks: true,
+      });
 
       set({
         sftpId,
         sessionId,
-        currentPath: homePath,
+        currentPath: opened.current_path,
         files,
         loading: false,
       });
+      if (opened.default_local_path) {
+        await useLocalStore.getState().navigateTo(opened.default_local_path);
+      }
     } catch (error) {
       set({ error: String(error), loading: false });
     }
@@ -78,17 +106,23 @@ export const useSftpStore = create<SftpState>((set, get) => ({
       sessionId: null,
       currentPath: "/",
       files: [],
+      nameFilter: "",
     });
   },
 
   navigateTo: async (path) => {
-    const { sftpId } = get();
+    const { sftpId, nameFilter } = get();
     if (!sftpId) return;
 
     set({ loading: true, error: null });
     try {
       const realPath = await invoke<string>("sftp_realpath", { sftpId, path });
-      const files = await invoke<FileEntry[]>("sftp_list_dir", { sftpId, path: realPath });
+      const files = await invoke<FileEntry[]>("sftp_list_dir", {
+        sftpId,
+        path: realPath,
+        followLinks: true,
+        filter: nameFilter || undefined,
+      });
       set({ currentPath: realPath, files, loading: false });
     } catch (error) {
       set({ error: String(error), loading: false });
@@ -96,18 +130,28 @@ export const useSftpStore = create<SftpState>((set, get) => ({
   },
 
   refresh: async () => {
-    const { sftpId, currentPath } = get();
+    const { sftpId, currentPath, nameFilter } = get();
     if (!sftpId) return;
 
     set({ loading: true, error: null });
     try {
-      const files = await invoke<FileEntry[]>("sftp_list_dir", { sftpId, path: currentPath });
+      const files = await invoke<FileEntry[]>("sftp_list_dir", {
+        sftpId,
+        path: currentPath,
+        followLinks: true,
+        filter: nameFilter || undefined,
+      });
       set({ files, loading: false });
     } catch (error) {
       set({ error: String(error), loading: false });
     }
   },
 
+  setNameFilter: async (filter) => {
+    set({ nameFilter: filter });
+    await get().refresh();
+  },
+
   createDirectory: async (name) => {
     const { sftpId, currentPath } = get();
     if (!sftpId) return;
@@ -125,6 +169,38 @@ export const useSftpStore = create<SftpState>((set, get) => ({
     await get().refresh();
   },
 
+  deleteItems: async (items) => {
+    const { sftpId } = get();
+    if (!sftpId) return;
+
+    // The delete runs in the background and may finish before its ID comes
+    // back, so listen first and keep completions until the ID is known
+    const completed = new Map<string, SftpDeleteSummary>();
+    let deleteId: string | null = null;
+    let settle!: (summary: SftpDeleteSummary) => void;
+    const outcome = new Promise<SftpDeleteSummary>((resolve) => {
+      settle = resolve;
+    });
+    const unlisten = await listen<SftpDeleteComplete>(`sftp-delete-complete-${sftpId}`, (event) => {
+      const { delete_id, deleted, failed } = event.payload;
+      if (delete_id === deleteId) settle({ deleted, failed });
+      else if (deleteId === null) completed.set(delete_id, { deleted, failed });
+    });
+    try {
+      deleteId = await invoke<string>("sftp_delete_many", {
+        sftpId,
+        items: items.map(({ path, isDir }) => [path, isDir]),
+      });
+      const early = completed.get(deleteId);
+      if (early) settle(early);
+      const summary = await outcome;
+      await get().refresh();
+      return summary;
+    } finally {
+      unlisten();
+    }
+  },
+
   rename: async (oldPath, newPath) => {
     const { sftpId } = get();
     if (!sftpId) return;
@@ -133,6 +209,11 @@ export const useSftpStore = create<SftpState>((set, get) => ({
     await get().refresh();
   },
 
+  copyPath: async (entry) => {
+    const quoted = await invoke<string>("shell_quoted_path", { entry });
+    await navigator.clipboard.writeText(quoted);
+  },
+
   download: async (remotePath, localPath) => {
     const { sftpId } = get();
     if (!sftpId) return;
@@ -220,6 +301,102 @@ export const useSftpStore = create<SftpState>((set, get) => ({
     );
   },
 
+  moveFromLocal: async (localPath, remotePath) => {
+    const { sftpId } = get();
+    if (!sftpId) return;
+
+    const progress = await invoke<TransferProgress>("sftp_move_from_local", {
+      sftpId,
+      localPath,
+      remotePath,
+    });
+
+    set((state) => ({
+      transfers: [...state.transfers, progress],
+    }));
+
+    const progressUnsub = await listen<[number, number]>(
+      `transfer-progress-${progress.id}`,
+      (event) => {
+        get().updateTransferProgress(progress.id, event.payload[0], event.payload[1]);
+      }
+    );
+
+    const completeUnsub = await listen<boolean>(
+      `transfer-complete-${progress.id}`,
+      () => {
+        get().completeTransfer(progress.id);
+        get().refresh();
+        useLocalStore.getState().refresh();
+        progressUnsub();
+        completeUnsub();
+        errorUnsub();
+      }
+    );
+
+    const errorUnsub = await listen<string>(
+      `transfer-error-${progress.id}`,
+      (event) => {
+        get().failTransfer(progress.id, event.payload);
+        progressUnsub();
+        completeUnsub();
+        errorUnsub();
+      }
+    );
+  },
+
+  listResumableTransfers: async () => {
+    return invoke<ResumableTransfer[]>("list_resumable_transfers");
+  },
+
+  resumeTransfer: async (transferId) => {
+    const { sftpId } = get();
+    if (!sftpId) return;
+
+    const progress = await invoke<TransferProgress>("resume_transfer", {
+      sftpId,
+      transferId,
+    });
+
+    set((state) => ({
+      transfers: [...state.transfers, progress],
+    }));
+
+    const progressUnsub = await listen<[number, number]>(
+      `transfer-progress-${progress.id}`,
+      (event) => {
+        get().updateTransferProgress(progress.id, event.payload[0], event.payload[1]);
+      }
+    );
+
+    const completeUnsub = await listen<boolean>(
+      `transfer-complete-${progress.id}`,
+      () => {
+        get().completeTransfer(progress.id);
+        if (progress.is_upload) {
+          get().refresh();
+        }
+        progressUnsub();
+        completeUnsub();
+        errorUnsub();
+      }
+    );
+
+    const errorUnsub = await listen<string>(
+      `transfer-error-${progress.id}`,
+      (event) => {
+        get().failTransfer(progress.id, event.payload);
+        progressUnsub();
+        completeUnsub();
+        errorUnsub();
+      }
+    );
+  },
+
+  discardResumableTransfer: async (transferId) => {
+    await invoke("discard_resumable_transfer", { transferId });
+  },
+
   uploadFolder: async (localPath, remotePath) => {
     const { sftpId } = get();
     if (!sftpId) return;
@@ -27,6 +27,10 @@ interface TerminalState {
   updateEditorTab: (tabId: string, updates: Partial<EditorTab>) => void;
   setActiveTab: (tabId: string) => void;
   updateTabTitle: (tabId: string, title: string) => void;
+  // Types an export into the session's shell; best effort, that shell only
+  setSessionEnv: (sessionId: string, key: string, value: string) => Promise<void>;
+  // SSH only: env of a fresh shell on the server, not the interactive one
+  getSessionEnv: (sessionId: string) => Promise<Record<string, string>>;
 }
 
 export const useTerminalStore = create<TerminalState>((set, _get) => ({
@@ -282,4 +286,12 @@ export const useTerminalStore = create<TerminalState>((set, _get) => ({
       ),
     }));
   },
+
+  setSessionEnv: async (sessionId, key, value) => {
+    await invoke("set_session_env", { sessionId, key, value });
+  },
+
+  getSessionEnv: async (sessionId) => {
+    return invoke<Record<string, string>>("get_session_env", { sessionId });
+  },
 }));
@@ -1,5 +1,6 @@
 import { create } from "zustand";
 import { invoke } from "@tauri-apps/api/core";
+import { confirmCertificate, isUntrustedCertificate } from "../lib/certificates";
 
 interface VncState {
   sessionId: string | null;
@@ -37,15 +38,21 @@ export const useVncStore = create<VncState>((set, get) => ({
 
   connect: async (host, port, password, connectionName, connectionId) => {
     set({ error: null });
+    const args = { host, port, password: password || null };
     try {
-      const [sessionId, width, height] = await invoke<[string, number, number]>(
-        "vnc_connect",
-        {
+      let result: [string, number, number];
+      try {
+        result = await invoke<[string, number, number]>("vnc_connect", args);
+      } catch (error) {
+        if (!isUntrustedCertificate(error) || !confirmCertificate(error)) throw error;
+        await invoke("vnc_trust_certificate", {
           host,
           port,
-          password: password || null,
-        }
-      );
+          fingerprint: error.certificate.fingerprint,
+        });
+        result = await invoke<[string, number, number]>("vnc_connect", args);
+      }
+      const [sessionId, width, height] = result;
 
       set({
         sessionId,
@@ -60,7 +67,10 @@ export const useVncStore = create<VncState>((set, get) => ({
 
       return sessionId;
     } catch (error) {
-      set({ error: String(error), connected: false });
+      const message = isUntrustedCertificate(error)
+        ? `Untrusted certificate: ${error.reason}`
+        : String(error);
+      set({ error: message, connected: false });
       throw error;
     }
   },
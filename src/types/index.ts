@@ -1,6 +1,6 @@
 export type SessionType =
   | { type: "Local" }
-  | { type: "Ssh"; host: string; port: number; username: string }
+  | { type: "Ssh"; host: string; port: number; username: string; via?: string } // via: proxy host:port
   | { type: "Ftp"; host: string; port: number; username?: string }
   | { type: "Vnc"; host: string; port: number }
   | { type: "Rdp"; host: string; port: number; username: string };
@@ -9,6 +9,58 @@ export interface SessionInfo {
   id: string;
   session_type: SessionType;
   title: string;
+  banner: string | null;
+  terminal_settings?: TerminalSettings; // From connect_saved, to apply to the tab
+  tunneled: boolean; // Routed through a proxy or jump host
+}
+
+// Per-connection terminal look and behavior; unset fields use the app
+// settings. Set with set_connection_terminal_settings.
+export interface TerminalSettings {
+  color_scheme?: string;
+  font_family?: string;
+  font_size?: number; // 4-96
+  cursor_style?: "block" | "underline" | "bar";
+  scrollback?: number; // Lines, at most 1,000,000
+  startup_command?: string; // Typed into the shell once the session opens
+  tab_color?: string; // CSS color
+}
+
+export interface SshBanner {
+  server_id: string | null;
+  message: string | null;
+}
+
+// Result of ssh_probe_auth: methods the server accepts, before logging in
+export interface SshAuthMethods {
+  methods: string[]; // e.g. "publickey", "password", "keyboard-interactive"
+  none_accepted: boolean; // No authentication needed
+}
+
+// Result of session_status
+export interface SessionStatus {
+  kind: "terminal" | "sftp" | "ftp" | "vnc" | "rdp" | null;
+  connected: boolean;
+  channel_eof: boolean | null;
+  active_transfers: number;
+}
+
+// Result of session_stats, for terminal sessions
+export interface SessionStats {
+  bytes_sent: number;
+  bytes_received: number;
+  bytes_uploaded: number; // SFTP transfers from browsers opened on the session
+  bytes_downloaded: number;
+  uptime_secs: number;
+  idle_secs: number;
+  alive: boolean;
+  exit_status: number | null; // Remote shell's exit code once the SSH channel closed
+}
+
+export interface TerminalOutputConfig {
+  read_buffer_size: number;
+  max_batch_bytes: number;
+  flush_interval_ms: number;
 }
 
 export interface TerminalTab {
@@ -66,12 +118,17 @@ export interface EditorTab {
 // Auth types
 export type AuthMethod =
   | { type: "Password"; password: string }
-  | { type: "PublicKey"; private_key_path: string; passphrase?: string }
+  | {
+      type: "PublicKey";
+      private_key_path: string;
+      passphrase?: string;
+      certificate_path?: string;
+    }
   | { type: "Agent" };
 
 export type StoredAuthMethod =
   | { auth_type: "Password" }
-  | { auth_type: "PublicKey"; private_key_path: string }
+  | { auth_type: "PublicKey"; private_key_path: string; certificate_path?: string }
   | { auth_type: "Agent" };
 
 // Connection types
@@ -94,6 +151,11 @@ export type ConnectionType =
       connection_type: "vnc";
       host: string;
       port: number;
+      encoding: VncEncoding;
+      quality: number | null;
+      color_depth?: VncColorDepth;
+      auth_type: VncAuthType;
+      username: string | null;
     }
   | {
       connection_type: "rdp";
@@ -101,8 +163,54 @@ export type ConnectionType =
       port: number;
       username: string;
       domain: string | null;
+      save_password: boolean;
+      quality: RdpQuality;
+      keyboard: RdpKeyboard;
+      accept_invalid_certs: boolean; // Skip certificate validation (explicit opt-in)
+      gateway: RdpGateway | null;
+      auto_reconnect: boolean; // Otherwise ask before reconnecting a dropped session
+      codec: RdpCodec;
+      shared_folder: RdpSharedFolder | null;
+      custom_performance_flags: RdpPerformanceFlags | null; // Overrides the preset's
     };
 
+// Result of import_connections
+export interface ConnectionImportSummary {
+  imported: ConnectionProfile[];
+  renamed_ids: number; // Imported under a new id to avoid a clash
+  secret_errors: string[]; // Passwords the keychain wouldn't take
+}
+
+// Master-password encryption of the saved connections
+export interface StorageEncryptionStatus {
+  enabled: boolean;
+  unlocked: boolean; // Other storage commands fail until unlock_storage
+}
+
+// Where saved passwords go; "file" when there is no OS keychain or the user prefers it
+export type KeychainBackend = "os" | "file" | "encrypted_storage";
+
+export interface KeychainBackendStatus {
+  backend: KeychainBackend;
+  os_keychain_available: boolean;
+  store_secrets_in_file: boolean;
+}
+
+// Payload of connections-recovered: connections.json was unreadable and replaced
+export interface StorageRecovery {
+  error: string;
+  restored_from_backup: boolean; // Otherwise the connection list starts empty
+  corrupt_path: string; // Where the unreadable file was moved
+}
+
+// A PuTTY session or FileZilla site, previewed before it's saved
+export interface ForeignSession {
+  profile: ConnectionProfile;
+  has_password: boolean; // Read again from the source and put in the keychain on save
+  key_needs_conversion: boolean; // A .ppk key, to convert with puttygen
+  warnings: string[];
+}
+
 export interface ConnectionProfile {
   id: string;
   name: string;
@@ -113,13 +221,64 @@ export interface ConnectionProfile {
   auth_method?: StoredAuthMethod;
   anonymous?: boolean;
   domain?: string | null;
+  save_password?: boolean;
+  encoding?: VncEncoding;
+  quality?: number | RdpQuality | null; // VNC level 0-9, or RDP preset
+  color_depth?: VncColorDepth; // VNC only
+  auth_type?: VncAuthType;
+  keyboard?: RdpKeyboard;
+  accept_invalid_certs?: boolean;
+  gateway?: RdpGateway | null;
+  auto_reconnect?: boolean;
+  codec?: RdpCodec;
+  shared_folder?: RdpSharedFolder | null;
+  custom_performance_flags?: RdpPerformanceFlags | null;
   created_at: string;
   last_used: string | null;
+  default_download_dir?: string | null; // Set with set_connection_download_dir
+  // SSH/FTP: absolute folders file browsing starts in, remote and local.
+  // Set with set_connection_default_paths.
+  default_remote_path?: string | null;
+  default_local_path?: string | null;
+  // SSH/RDP/VNC sessions from this profile close after this long without
+  // activity, then session-idle-timeout-{id} fires. Set with
+  // set_connection_idle_timeout.
+  idle_timeout_secs?: number | null;
+  tags?: string[]; // Set with set_connection_tags
+  group?: string | null; // Set with set_connection_group
+  favorite?: boolean; // Listed first; set with set_connection_favorite
+  sort_order?: number | null; // Position among favorites, set with reorder_connections
+  auto_connect?: boolean; // SSH only; set with set_connection_auto_connect
+  terminal_settings?: TerminalSettings | null;
+  notes?: string | null; // Up to 16 KB of free text
+  environment?: string | null; // e.g. "prod", "staging" or "dev"
+  owner?: string | null;
+}
+
+// Usage of a saved connection, kept in usage.json
+export interface ConnectionStats {
+  connection_id: string;
+  connect_count: number;
+  failure_count: number;
+  total_connected_secs: number; // Added as sessions close
+  last_connected_at: string | null;
+  last_failure: { at: string; error: string } | null;
+}
+
+// Passed to update_connection: unset fields stay as they are, empty
+// strings clear them
+export interface ProfileMetadata {
+  notes?: string;
+  environment?: string;
+  owner?: string;
 }
 
 // SFTP types
 export type FileType = "File" | "Directory" | "Symlink" | "Other";
 
+// Coarse kind from the file name, for icons and sorting by type
+export type FileCategory = "directory" | "archive" | "image" | "text" | "code" | "binary";
+
 export interface FileEntry {
   name: string;
   path: string;
@@ -127,6 +286,67 @@ export interface FileEntry {
   size: number;
   modified: number | null;
   permissions: number | null;
+  size_human: string; // e.g. "4.2 MB"; empty for directories
+  category: FileCategory;
+  target_type?: FileType | null; // What a symlink points to, when listed with followLinks
+}
+
+// Streamed listing events: sftp-listing-{id}, sftp-listing-complete-{id}
+export interface SftpListingBatch {
+  listing_id: string;
+  path: string;
+  entries: FileEntry[];
+}
+
+export interface SftpListingComplete {
+  listing_id: string;
+  path: string;
+  total: number;
+}
+
+// Recursive chmod events: sftp-chmod-progress-{id}, sftp-chmod-complete-{id}
+export interface SftpChmodProgress {
+  chmod_id: string;
+  changed: number;
+  failed: number;
+}
+
+export interface SftpChmodComplete {
+  chmod_id: string;
+  path: string;
+  changed: number;
+  failed: { path: string; error: string }[]; // Entries that couldn't be changed
+}
+
+// Payload of sftp-delete-item-{sftpId}; error is null if the item was deleted
+export interface SftpDeleteResult {
+  delete_id: string;
+  path: string;
+  error: string | null;
+}
+
+// Outcome of sftp_delete_many, which returns a delete_id at once and
+// finishes with sftp-delete-complete-{sftpId}
+export interface SftpDeleteSummary {
+  deleted: string[];
+  failed: { path: string; error: string }[];
+}
+
+export interface SftpDeleteComplete extends SftpDeleteSummary {
+  delete_id: string;
+}
+
+// Result of sftp_edit_open; pass format back to sftp_write_file when saving
+export interface FileFormat {
+  encoding: "Utf8" | "Utf16Le" | "Utf16Be" | "Latin1";
+  bom: boolean;
+  line_ending: "Lf" | "Crlf";
+  binary: boolean;
+}
+
+export interface EditorFile {
+  content: string | null; // LF line endings; null for binary files
+  format: FileFormat;
 }
 
 export type TransferStatus =
@@ -137,6 +357,92 @@ export type TransferStatus =
   | "Cancelled";
 
 // VNC types
+export type VncEncoding = "Auto" | "Zrle" | "Raw";
+
+// Pixel format requested from the server: 24-bit, 16-bit RGB565, 8-bit BGR233
+// or an 8-bit server palette. Lower depths save bandwidth on slow links.
+export type VncColorDepth = "full" | "high" | "low" | "palette";
+
+// Payload of vnc-incoming-{listener_id} for reverse connections
+export interface VncIncomingSession {
+  session_id: string;
+  host: string;
+  port: number;
+  width: number;
+  height: number;
+}
+
+// Payload of vnc-resized-{session_id} when the server changes desktop size
+export interface VncResize {
+  width: number;
+  height: number;
+}
+
+// Payload of vnc-cursor-{session_id}; 0x0 means hide the cursor
+export interface VncCursor {
+  width: number;
+  height: number;
+  hotspot_x: number;
+  hotspot_y: number;
+  data: string; // Base64-encoded RGBA pixels
+}
+
+// SSH server to tunnel a VNC connection through (inline or a saved profile)
+export interface SshTunnelConfig {
+  connection_id?: string;
+  host?: string;
+  port?: number;
+  username?: string;
+  auth?: AuthMethod;
+  password?: string;
+  passphrase?: string;
+  proxy?: ProxyConfig;
+}
+
+// HTTP proxy (CONNECT) for SSH, FTP and RDP connections on restricted networks
+export interface ProxyConfig {
+  host: string;
+  port: number;
+  username?: string;
+  password?: string;
+}
+
+// Result of sftp_open and connect_saved_ftp
+export interface FileBrowserOpened {
+  browser_id: string; // sftp_id or ftp_id
+  current_path: string; // The profile's default remote folder, else home
+  default_local_path: string | null; // Where the local pane should open
+}
+
+// Payload of ssh-connect-progress-{connect_id}, sent as each connect stage starts
+export type SshConnectStage = "tcp_connected" | "ssh_handshake" | "authenticating";
+
+export interface GroupConnectOptions {
+  term?: string;
+  proxy?: ProxyConfig;
+  max_parallel?: number; // Defaults to 4
+}
+
+// One profile's outcome in connect_group
+export type GroupConnectResult =
+  | { status: "connected"; connection_id: string; name: string; session: SessionInfo }
+  | { status: "failed"; connection_id: string; name: string; error: string };
+
+// Payload of startup-connections-complete
+export interface StartupConnectionsSummary {
+  connected: number;
+  failed: GroupConnectResult[];
+}
+
+export type VncAuthType =
+  | "Auto"
+  | "None"
+  | "VncPassword"
+  | "VeNCrypt"
+  | "Tight"
+  | "MsLogon"
+  | "AppleRemoteDesktop";
+
 export type VncInputEvent =
   | { type: "pointer"; x: number; y: number; button_mask: number }
   | { type: "key"; key: number; down: boolean };
@@ -149,13 +455,213 @@ export type RdpQuality =
   | "Performance" // 16-bit, aggressive compression
   | "LowBandwidth"; // 8-bit, maximum compression
 
+// Bitmap codec; Auto follows the quality preset (RemoteFX for High/Balanced).
+// Bitmap is for servers that misbehave with RemoteFX.
+export type RdpCodec = "Auto" | "RemoteFx" | "Bitmap";
+
+// Local folder redirected into RDP sessions as a drive. Passed to
+// rdp_connect / save_rdp_connection as shared_folder and
+// shared_folder_read_only.
+export interface RdpSharedFolder {
+  path: string;
+  read_only: boolean;
+}
+
+// Visual features to keep, overriding those the quality preset picks.
+// Passed to rdp_connect / save_rdp_connection as custom_performance_flags
+// and to update_connection as rdp_custom_performance_flags.
+export interface RdpPerformanceFlags {
+  wallpaper: boolean;
+  themes: boolean;
+  animations: boolean; // Menu and window animations
+  font_smoothing: boolean;
+  desktop_composition: boolean;
+  full_window_drag: boolean; // Window contents shown while dragging
+}
+
+export type RdpKeyboardType =
+  | "IbmPcXt"
+  | "OlivettiIco"
+  | "IbmPcAt"
+  | "IbmEnhanced"
+  | "Nokia1050"
+  | "Nokia9140"
+  | "Japanese";
+
+export interface RdpKeyboard {
+  layout: number | string; // Layout ID (0x409), locale ("de-DE") or short name ("uk"); saved as the ID
+  keyboard_type: RdpKeyboardType;
+  functional_keys_count: number;
+}
+
+// Result of rdp_keyboard_layouts
+export interface KeyboardLayout {
+  locale: string; // e.g. "de-DE"
+  label: string;
+  id: number;
+}
+
+export interface KeyboardLayouts {
+  system_layout: number; // Used when no layout is given
+  layouts: KeyboardLayout[];
+}
+
+// RD Gateway (MS-TSGU over HTTPS) for rdp_connect and RDP profiles.
+// Credentials default to the RDP ones; saved passwords go to the keychain.
+export interface RdpGateway {
+  host: string;
+  port?: number; // Default 443
+  username?: string;
+  password?: string;
+  domain?: string;
+  bypass_local?: boolean; // Connect directly to hosts on the local network
+}
+
+// Smart card logon for rdp_connect only; saved profiles always use a
+// password. The certificate and its RSA private key (PKCS#1) are exported
+// from the card, as DER or PEM files.
+export interface RdpSmartCard {
+  pin: string;
+  reader_name: string;
+  container_name?: string;
+  csp_name?: string; // Default "Microsoft Base Smart Card Crypto Provider"
+  certificate_path: string;
+  private_key_path: string;
+}
+
+// Payload of rdp-resized-{session_id} after rdp_resize takes effect
+export interface RdpResize {
+  width: number;
+  height: number;
+}
+
+// Payload of rdp-reconnecting-{session_id}, before each reconnect attempt.
+// Success sends rdp-reconnected-{session_id} (RdpResize) and a full frame;
+// profiles without auto_reconnect get rdp-connection-lost-{session_id} and
+// call rdp_reconnect instead.
+export interface RdpReconnecting {
+  attempt: number;
+  max_attempts: number;
+  reason: string;
+}
+
+// Payload of rdp-disconnected-{session_id} when the server ends the session
+// or reconnecting gives up. Later calls on the session fail with
+// "Session ended: ..." until rdp_disconnect.
+export interface RdpDisconnect {
+  code:
+    | "logoff"
+    | "admin_logoff"
+    | "admin_disconnect"
+    | "idle_timeout"
+    | "session_time_limit"
+    | "replaced_by_other_connection"
+    | "licensing"
+    | "access_denied"
+    | "server_disconnect"
+    | "connection_lost"
+    | "other";
+  message: string;
+  detail: string | null; // What the server or socket reported
+}
+
+// Result of rdp_set_quality and payload of rdp-quality-{session_id}, also
+// sent after each reconnect. Color depth and codec are what the current
+// connection negotiated; pending_reconnect means they change on reconnect.
+export interface RdpQualityInfo {
+  quality: RdpQuality;
+  color_depth: number;
+  codec: RdpCodec;
+  pending_reconnect: boolean;
+}
+
+// Payload of rdp-pointer-{session_id}; not sent when softwareCursor is on
+export type RdpPointer =
+  | {
+      type: "bitmap";
+      width: number;
+      height: number;
+      hotspot_x: number;
+      hotspot_y: number;
+      data: string; // Base64-encoded RGBA pixels
+    }
+  | { type: "position"; x: number; y: number }
+  | { type: "hidden" }
+  | { type: "default" };
+
+export interface ServerCertificate {
+  host: string;
+  port: number;
+  subject: string;
+  issuer: string;
+  not_before: string | null;
+  not_after: string | null;
+  fingerprint: string; // SHA-256, colon-separated hex
+}
+
+// Error from rdp_connect / connect_saved_rdp, or vnc_connect / connect_saved_vnc
+// for VeNCrypt, when the certificate isn't trusted; other errors are plain
+// strings. Accept with rdp_trust_certificate or vnc_trust_certificate.
+export interface UntrustedCertificate {
+  certificate: ServerCertificate;
+  reason: string;
+}
+
+// Payload of rdp-connect-failed-{id}; rdp-connected-{id} carries the desktop
+// { width, height }. rdp_cancel_connect aborts a pending connect.
+export type RdpConnectError = UntrustedCertificate | string;
+
+// Result of rdp_frame_stats
+export interface FrameFlowStats {
+  frames_sent: number;
+  // Updates merged into a later frame because the viewer was behind
+  frames_skipped: number;
+  frames_in_flight: number;
+  max_in_flight: number;
+  fps: number;
+}
+
+// Result of rdp_codec_stats, totals since the connection was made
+export interface RdpCodecStats {
+  codec: RdpCodec;
+  seconds: number;
+  bytes_received: number;
+  kbps: number;
+  decode_ms: number;
+  decode_cpu_percent: number; // of one core
+}
+
+// Payload of rdp-connect-progress-{id}, sent as each connect phase starts
+export type RdpConnectStage = "connecting" | "tls_handshake" | "authenticating" | "negotiating_display";
+
+// Result of connect_saved_rdp; prompt-only profiles come back as needs_password
+export type SavedRdpConnect =
+  | { status: "connected"; session_id: string; width: number; height: number }
+  | { status: "needs_password"; connection_id: string; host: string; username: string };
+
 export type RdpInputEvent =
   | { type: "mouse_move"; x: number; y: number }
   | { type: "mouse_button"; button: number; down: boolean; x: number; y: number }
   | { type: "mouse_wheel"; delta: number; x: number; y: number }
-  | { type: "keyboard"; scancode: number; down: boolean };
+  | { type: "keyboard"; scancode: number; down: boolean }
+  | { type: "text"; text: string }; // Unicode key events, for non-US layouts and IME
+
+// Key combinations the local OS would intercept, for rdp_send_special_key
+export type RdpSpecialKey =
+  | "CtrlAltDel"
+  | "CtrlShiftEsc"
+  | "Windows"
+  | "AltTab"
+  | "AltShiftTab"
+  | "AltF4"
+  | "PrintScreen"
+  | "AltPrintScreen"
+  | "WinL"
+  | "WinR"
+  | "WinD"
+  | "WinE";
 
-// RDP frame update types - for efficient dirty rectangle updates
+// RDP/VNC frame update types - for efficient dirty rectangle updates
 export interface DirtyRect {
   x: number;
   y: number;
@@ -164,9 +670,23 @@ export interface DirtyRect {
   data: string; // Base64-encoded RGBA pixels
 }
 
+// sent_at: milliseconds since the Unix epoch, for measuring latency
 export type FrameUpdate =
-  | { type: "Full"; width: number; height: number; data: string } // Base64-encoded
-  | { type: "Partial"; rects: DirtyRect[] };
+  | { type: "Full"; width: number; height: number; data: string; sent_at: number } // Base64-encoded
+  | { type: "Partial"; rects: DirtyRect[]; sent_at: number };
+
+export type ScreenshotFormat = "png" | "jpeg";
+
+export interface ScreenshotInfo {
+  path: string;
+  width: number;
+  height: number;
+  file_size: number;
+}
+
+// FTP representation type for single-file transfers; Ascii translates
+// line endings
+export type FtpTransferMode = "Binary" | "Ascii";
 
 export interface TransferProgress {
   id: string;
@@ -178,3 +698,28 @@ export interface TransferProgress {
   transferred_bytes: number;
   status: TransferStatus;
 }
+
+// An SFTP transfer that stopped before finishing (app closed or crashed),
+// from list_resumable_transfers
+export interface ResumableTransfer {
+  id: string;
+  is_upload: boolean;
+  local_path: string;
+  remote_path: string;
+  server: string | null; // user@host:port it went to
+  total_bytes: number;
+  transferred_bytes: number; // As of the last journal write
+  preserve_metadata: boolean;
+  updated_at: string;
+}
+
+// Folder uploads also send transfer-group-{id} (ftp-transfer-group-{id}
+// for FTP), with the transfer's id, alongside their progress events.
+export interface TransferGroup {
+  group_id: string;
+  total_files: number;
+  completed_files: number;
+  current_file: string | null; // Path within the folder
+  transferred_bytes: number;
+  total_bytes: number;
+}